@@ -1,10 +1,15 @@
-use std::time::Instant;
+use std::{convert::Infallible, net::SocketAddr, sync::Arc, time::Instant};
 
 use blueboat::{
-  api::testutil::ApiTester,
+  api::{fetch::build_http_client, testutil::ApiTester},
+  egress::EgressPolicy,
   v8util::{create_uint8array_from_bytes, ObjectExt},
 };
 use criterion::{criterion_group, criterion_main, Bencher, Criterion};
+use hyper::{
+  service::{make_service_fn, service_fn},
+  Body, Response, Server,
+};
 use rand::RngCore;
 
 pub fn run(c: &mut Criterion) {
@@ -14,6 +19,18 @@ pub fn run(c: &mut Criterion) {
   c.bench_function("b64 encode 4096", |b| {
     run_b64_encode(b, 4096);
   });
+  c.bench_function("tera render source", |b| {
+    run_tera_render(b, false);
+  });
+  c.bench_function("tera render compiled", |b| {
+    run_tera_render(b, true);
+  });
+  c.bench_function("fetch same origin pooled", |b| {
+    run_fetch_same_origin(b, true);
+  });
+  c.bench_function("fetch same origin fresh client", |b| {
+    run_fetch_same_origin(b, false);
+  });
 }
 
 criterion_group!(api_benchmark, run);
@@ -42,3 +59,79 @@ fn run_b64_encode(b: &mut Bencher, buffer_size: usize) {
     start.elapsed()
   })
 }
+
+const TERA_BENCH_TEMPLATE: &str = r#"
+<ul>
+{% for item in items %}
+  <li class="{% if loop.index is odd %}odd{% else %}even{% endif %}">{{ item.name | title }}: {{ item.price }}</li>
+{% endfor %}
+</ul>
+"#;
+
+fn run_tera_render(b: &mut Bencher, compiled: bool) {
+  b.iter_custom(|n| {
+    let mut api = ApiTester::new();
+    api.run(|scope| {
+      let g = scope.get_current_context().global(scope);
+      let n = v8::Number::new(scope, n as f64);
+      g.set_ext(scope, "n", n.into());
+      let src = v8::String::new(scope, TERA_BENCH_TEMPLATE).unwrap();
+      g.set_ext(scope, "src", src.into());
+      let compiled = v8::Boolean::new(scope, compiled);
+      g.set_ext(scope, "compiled", compiled.into());
+    });
+    let start = Instant::now();
+    let _: () = api.run_script(
+      r#"
+  const items = [1, 2, 3, 4, 5, 6, 7, 8].map((i) => ({ name: "item " + i, price: i * 10 }));
+  const t = compiled ? Template.compile(src) : src;
+  for(let i = 0; i < n; i++) {
+    Template.render(t, { items });
+  }
+  "#,
+    );
+    start.elapsed()
+  })
+}
+
+fn run_fetch_same_origin(b: &mut Bencher, pooled: bool) {
+  let rt = tokio::runtime::Runtime::new().unwrap();
+  let addr: SocketAddr = rt.block_on(async {
+    let make_svc = make_service_fn(|_| async {
+      Ok::<_, Infallible>(service_fn(|_| async {
+        Ok::<_, Infallible>(Response::new(Body::from("hello")))
+      }))
+    });
+    let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    addr
+  });
+  let url = format!("http://{}/", addr);
+  let mk_client = || {
+    build_http_client(
+      None,
+      &Default::default(),
+      Arc::new(EgressPolicy::unrestricted()),
+    )
+    .unwrap()
+  };
+  b.iter_custom(|n| {
+    rt.block_on(async {
+      let client = mk_client();
+      let start = Instant::now();
+      for _ in 0..n {
+        let client = if pooled { client.clone() } else { mk_client() };
+        client
+          .get(&url)
+          .send()
+          .await
+          .unwrap()
+          .bytes()
+          .await
+          .unwrap();
+      }
+      start.elapsed()
+    })
+  })
+}
@@ -2,10 +2,14 @@ import { BlueboatBootstrapData } from "./native_schema";
 
 export { mysql } from "./mysql";
 export { apns } from "./apns";
+export { fcm } from "./fcm";
+export { webpush } from "./webpush";
 export { pubsub } from "./pubsub";
 
 import { init as mysqlInit } from "./mysql";
 import { init as apnsInit } from "./apns";
+import { init as fcmInit } from "./fcm";
+import { init as webpushInit } from "./webpush";
 import { init as pubsubInit } from "./pubsub/index";
 
 export { serveStaticFiles } from "./serve_static";
@@ -21,9 +25,33 @@ export function mustGetEnv(key: string): string {
   }
 }
 
+export interface MemoryPressureInfo {
+  usedHeapSize: number;
+  heapSizeLimit: number;
+}
+
+let memoryPressureHandler: ((info: MemoryPressureInfo) => void) | null = null;
+
+/**
+ * Registers a handler called when the heap is close to its limit, so that the app
+ * can drop caches. The handler must be synchronous and return quickly: it is
+ * terminated after a short deadline. It runs outside of any request.
+ */
+export function onMemoryPressure(
+  handler: ((info: MemoryPressureInfo) => void) | null
+) {
+  memoryPressureHandler = handler;
+}
+
+export function appMemoryPressure(info: MemoryPressureInfo) {
+  if (memoryPressureHandler) memoryPressureHandler(info);
+}
+
 export function init(bs: BlueboatBootstrapData) {
   Object.assign(env, bs.env);
   mysqlInit(bs);
   apnsInit(bs);
+  fcmInit(bs);
+  webpushInit(bs);
   pubsubInit(bs);
 }
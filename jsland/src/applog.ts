@@ -0,0 +1,74 @@
+import { setTimeout } from "./timeout";
+import { wrapNativeAsync } from "./util";
+
+export interface TailOpts {
+  /** The cursor of the previous page. Omit to read the latest lines. */
+  after?: number;
+
+  /** Defaults to 100, at most 1000. */
+  limit?: number;
+
+  /** Skips lines below this level. */
+  minLevel?: LogLevel;
+}
+
+export type LogLevel = "debug" | "info" | "warn" | "error";
+
+export interface TailEntry {
+  seq: number;
+  tsMs: number;
+  version: string;
+  requestId: string;
+  logseq: number;
+  level: LogLevel;
+  message: string;
+
+  /** JSON object of a structured entry. */
+  fields: string | null;
+}
+
+export interface TailPage {
+  entries: TailEntry[];
+
+  /** Pass as `after` to read the lines following this page. */
+  cursor: number;
+
+  /** Some lines were evicted from the tail before they were read. */
+  missed: boolean;
+}
+
+/**
+ * Writes a log entry with key/value fields, which log processors receive as a
+ * JSON object instead of a flattened string. Without a message, the fields are
+ * also used as the message.
+ */
+export function structured(
+  level: LogLevel,
+  fields: Record<string, unknown>,
+  message?: string
+): void {
+  __blueboat_host_invoke("log_structured", level, fields, message);
+}
+
+/**
+ * Reads recent log lines of this app from the server's in-memory tail.
+ */
+export function tail(opts: TailOpts = {}): Promise<TailPage> {
+  return wrapNativeAsync((callback) =>
+    __blueboat_host_invoke("applog_tail", opts, callback)
+  );
+}
+
+/**
+ * Yields new log lines as they arrive, polling every `intervalMs`.
+ */
+export async function* follow(
+  intervalMs = 1000
+): AsyncGenerator<TailEntry, void, undefined> {
+  let page = await tail({ limit: 0 });
+  while (true) {
+    await new Promise<void>((resolve) => setTimeout(() => resolve(), intervalMs));
+    page = await tail({ after: page.cursor });
+    yield* page.entries;
+  }
+}
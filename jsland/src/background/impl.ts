@@ -3,11 +3,54 @@ import { wrapNativeAsync } from "../util";
 
 let registration: BackgroundEntryBase | null = null;
 
-export interface AtLeastOnceOpts {
+/**
+ * Whether a deferred run is pinned to the version of the app that scheduled it.
+ *
+ * Defaults to `true`. A pinned run fails if a new version has been deployed in
+ * the meantime (and an at-least-once task is retried, then dead-lettered). Set
+ * it to `false` to always run on the latest deployed version, e.g. for jobs that
+ * migrate data to a new schema.
+ */
+export interface VersionOpts {
   sameVersion?: boolean;
 }
 
+export interface AtMostOnceOpts extends VersionOpts {}
+
+export interface AtLeastOnceOpts extends VersionOpts {
+
+  /** Retries after the first failed run. Defaults to 5, at most 20. */
+  maxRetries?: number;
+
+  /**
+   * Delay before the first retry, doubled on each further retry. Defaults to
+   * 1000, at most `maxDelayMs`.
+   */
+  baseDelayMs?: number;
+
+  /** Upper bound of the retry delay. Defaults to 60000, at most 3600000. */
+  maxDelayMs?: number;
+
+  /** Fraction of each delay, between 0 and 1, that is randomized away. Defaults to 0.2. */
+  jitter?: number;
+
+  /**
+   * Suppresses tasks scheduled by this app with the same key while the window
+   * started by the first accepted one is open, across all instances. Retries
+   * are not affected. Up to 10000 keys are kept per app; beyond that, expired
+   * keys are dropped first, then the key closest to expiring.
+   */
+  dedupKey?: string;
+
+  /** Length of the dedup window. Defaults to 3600, at most 86400. */
+  dedupWindowSecs?: number;
+}
+
 export interface DelayedTaskOpts {
+  /**
+   * Unix time to run the task at, at most 30 days ahead. A time in the past
+   * runs the task as soon as possible.
+   */
   tsSecs: number;
   sameVersion?: boolean;
 }
@@ -16,6 +59,21 @@ export interface DelayedTaskInfo {
   id: string;
 }
 
+export interface CronOpts {
+  /** Registering the same name again replaces the job. An app has at most 32 jobs. */
+  name: string;
+
+  /**
+   * Cron expression with a seconds field, like `0 30 9 * * Mon-Fri`. Jobs fire
+   * at most once a minute.
+   */
+  schedule: string;
+
+  /** IANA timezone name. Defaults to UTC. */
+  timezone?: string;
+  sameVersion?: boolean;
+}
+
 export class BackgroundEntryBase {
   constructor() {
     if (registration)
@@ -33,14 +91,18 @@ export function atMostOnce<
   A,
   T extends BackgroundEntryBase & { [P in K]: (arg: A) => unknown },
   K extends keyof T & string
->(base: T, key: K, arg: A) {
+>(base: T, key: K, arg: A, opts: AtMostOnceOpts = {}) {
   const inv: BackgroundInvocation = {
     entry: key,
     arg,
   };
-  __blueboat_host_invoke("schedule_at_most_once", inv);
+  __blueboat_host_invoke("schedule_at_most_once", inv, opts);
 }
 
+/**
+ * Resolves once the task is stored. It is then run, and retried on failure, by
+ * an instance that accepts background tasks, even if this one goes away.
+ */
 export function atLeastOnce<
   A,
   T extends BackgroundEntryBase & { [P in K]: (arg: A) => unknown },
@@ -60,6 +122,10 @@ export function atLeastOnce<
   );
 }
 
+/**
+ * Resolves once the task is stored. It is then run at `tsSecs` by an instance
+ * that accepts background tasks. A failed run is not retried.
+ */
 export function delayed<
   A,
   T extends BackgroundEntryBase & { [P in K]: (arg: A) => unknown },
@@ -79,15 +145,92 @@ export function delayed<
   );
 }
 
+/**
+ * Registers a job that runs on one instance at each fire time of its schedule,
+ * until it is removed with `cronRemove`. Resolves once the job is stored.
+ */
+export function cron<
+  A,
+  T extends BackgroundEntryBase & { [P in K]: (arg: A) => unknown },
+  K extends keyof T & string
+>(base: T, key: K, arg: A, opts: CronOpts): Promise<void> {
+  const inv: BackgroundInvocation = {
+    entry: key,
+    arg,
+  };
+  return wrapNativeAsync((callback) =>
+    __blueboat_host_invoke("schedule_cron", inv, opts, callback)
+  );
+}
+
+/**
+ * Removes a cron job registered by this app. Resolves to whether the job
+ * existed. A run in progress is not interrupted.
+ */
+export function cronRemove(name: string): Promise<boolean> {
+  return wrapNativeAsync((callback) =>
+    __blueboat_host_invoke("task_cron_remove", name, callback)
+  );
+}
+
+/**
+ * Cancels a delayed task that hasn't fired yet. Resolves to whether a pending
+ * task was removed.
+ */
+export function cancel(id: string): Promise<boolean> {
+  return wrapNativeAsync((callback) =>
+    __blueboat_host_invoke("task_cancel", id, callback)
+  );
+}
+
+export interface DlqListOpts {
+  /** Only list entries scheduled by this version of the app. */
+  version?: string;
+
+  /** At most 1000. */
+  limit?: number;
+}
+
+export interface DlqEntry {
+  id: string;
+  version: string;
+  attempts: number;
+  error: string;
+  deadLetteredAtMs: number;
+}
+
+/**
+ * Lists at-least-once tasks of this app whose retries were exhausted, oldest
+ * first. Up to 1000 are kept per app; beyond that the oldest are dropped.
+ */
+export function dlqList(opts: DlqListOpts = {}): Promise<DlqEntry[]> {
+  return wrapNativeAsync((callback) =>
+    __blueboat_host_invoke("task_dlq_list", opts, callback)
+  );
+}
+
+/**
+ * Schedules dead-lettered tasks again with a fresh retry budget. Returns the ids
+ * that were redriven by this call; ids redriven before are skipped.
+ */
+export function dlqRedrive(ids: string[]): Promise<string[]> {
+  return wrapNativeAsync((callback) =>
+    __blueboat_host_invoke("task_dlq_redrive", ids, callback)
+  );
+}
+
 export async function appBackgroundEntry(message: BackgroundInvocation) {
+  // A failed run is reported with a 5xx status so that at-least-once tasks are retried.
+  let status = 200;
   try {
     let f: (arg: unknown) => unknown = (<any>registration)[message.entry];
     await f(message.arg);
   } catch (e) {
     console.log("background entry error: " + e);
+    status = 500;
   }
   let res: BlueboatResponse = {
-    status: 200,
+    status,
     headers: {},
   };
   __blueboat_host_invoke("complete", res, new Uint8Array());
@@ -1 +1 @@
-export { BackgroundEntryBase, atMostOnce, atLeastOnce, DelayedTaskOpts, delayed } from "./impl";
+export { BackgroundEntryBase, VersionOpts, AtMostOnceOpts, AtLeastOnceOpts, atMostOnce, atLeastOnce, DelayedTaskOpts, DelayedTaskInfo, delayed, cancel, CronOpts, cron, cronRemove, DlqListOpts, DlqEntry, dlqList, dlqRedrive } from "./impl";
@@ -0,0 +1,7 @@
+export function encode(value: unknown): Uint8Array {
+  return <Uint8Array>__blueboat_host_invoke("codec_cbor_encode", value);
+}
+
+export function decode(data: Uint8Array): unknown {
+  return __blueboat_host_invoke("codec_cbor_decode", data);
+}
@@ -1,5 +1,12 @@
-import { CodecBase64Mode } from "../native_schema";
+import {
+  CodecBase64Mode,
+  CodecCompressAlgorithm,
+  CodecUrlEncodeMode,
+} from "../native_schema";
+export * as CBOR from "./cbor";
+export * as MessagePack from "./msgpack";
 export * as Multipart from "./multipart";
+export * as StructuredClone from "./structured_clone";
 
 export function hexencode(x: string | Uint8Array): string {
   return <string>__blueboat_host_invoke("codec_hexencode", x);
@@ -33,3 +40,34 @@ export function b64decode(
 ): Uint8Array {
   return <Uint8Array>__blueboat_host_invoke("codec_b64decode", x, mode);
 }
+
+export function compress(
+  algorithm: CodecCompressAlgorithm,
+  x: string | Uint8Array,
+  level?: number | null | undefined
+): Uint8Array {
+  return <Uint8Array>__blueboat_host_invoke("codec_compress", algorithm, level, x);
+}
+
+export interface DecompressOptions {
+  maxOutputSize?: number;
+}
+
+export function decompress(
+  algorithm: CodecCompressAlgorithm,
+  x: Uint8Array,
+  opts: DecompressOptions = {}
+): Uint8Array {
+  return <Uint8Array>__blueboat_host_invoke("codec_decompress", algorithm, x, opts.maxOutputSize);
+}
+
+export function urlEncode(
+  x: string | Uint8Array,
+  mode: CodecUrlEncodeMode = "component"
+): string {
+  return <string>__blueboat_host_invoke("codec_url_encode", x, mode);
+}
+
+export function urlDecode(x: string | Uint8Array): string {
+  return <string>__blueboat_host_invoke("codec_url_decode", x);
+}
@@ -10,3 +10,24 @@ export function decode(data: Uint8Array, boundary: string): MultipartField[] {
     __blueboat_host_invoke("codec_multipart_decode", data, boundary)
   );
 }
+
+export interface MultipartPart {
+  name: string;
+  file_name?: string | null | undefined;
+  content_type?: string | null | undefined;
+  body: string | Uint8Array;
+}
+
+export interface MultipartEncodeOutput {
+  content_type: string;
+  body: Uint8Array;
+}
+
+export function encode(
+  parts: MultipartPart[],
+  boundary?: string | null | undefined
+): MultipartEncodeOutput {
+  return <MultipartEncodeOutput>(
+    __blueboat_host_invoke("codec_multipart_encode", parts, boundary)
+  );
+}
@@ -0,0 +1,12 @@
+/**
+ * Serializes a value with V8's structured clone algorithm, the same format
+ * used for background task payloads. Maps, Sets, Dates and typed arrays
+ * survive the round trip; functions and symbols throw.
+ */
+export function encode(value: unknown): Uint8Array {
+  return <Uint8Array>__blueboat_host_invoke("codec_structured_clone_encode", value);
+}
+
+export function decode(data: Uint8Array): unknown {
+  return __blueboat_host_invoke("codec_structured_clone_decode", data);
+}
@@ -1,5 +1,7 @@
 export interface Console {
   log(...args: unknown[]): void;
+  debug(...args: unknown[]): void;
+  info(...args: unknown[]): void;
   warn(...args: unknown[]): void;
   error(...args: unknown[]): void;
 }
@@ -8,10 +10,16 @@ export const console: Console = {
   log(...args: unknown[]): void {
     __blueboat_host_invoke("log", ...args);
   },
+  debug(...args: unknown[]): void {
+    __blueboat_host_invoke("log_debug", ...args);
+  },
+  info(...args: unknown[]): void {
+    __blueboat_host_invoke("log_info", ...args);
+  },
   warn(...args: unknown[]): void {
-    __blueboat_host_invoke("log", ...args);
+    __blueboat_host_invoke("log_warn", ...args);
   },
   error(...args: unknown[]): void {
-    __blueboat_host_invoke("log", ...args);
+    __blueboat_host_invoke("log_error", ...args);
   },
 };
@@ -1,5 +1,19 @@
+import { DatasetMimeSniffResult } from "../native_schema";
+
 export function guessByExt(ext: string): string | undefined {
   return <string | undefined>(
     __blueboat_host_invoke("dataset_mime_guess_by_ext", ext)
   );
 }
+
+/**
+ * Detects the type of `data` from its leading bytes, which is safer than the
+ * extension for untrusted uploads. A few kilobytes of the start are enough.
+ */
+export function guessByContent(
+  data: Uint8Array
+): DatasetMimeSniffResult | undefined {
+  return <DatasetMimeSniffResult | undefined>(
+    __blueboat_host_invoke("dataset_mime_guess_by_content", data)
+  );
+}
@@ -1,16 +1,28 @@
 import {
+  AwsSigV4SignRequest,
+  S3AbortMultipartUploadRequest,
+  S3CompletedPart,
+  S3CompleteMultipartUploadOutput,
+  S3CompleteMultipartUploadRequest,
+  S3CreateMultipartUploadRequest,
   S3Credentials,
   S3ListObjectsV2Output,
   S3ListObjectsV2Request,
+  S3ObjectRequest,
   S3PresignInfo,
   S3PresignOptions,
+  S3PresignUrlRequest,
   S3Region,
+  S3UploadPartRequest,
 } from "../../native_schema";
+import { parse as parseXml, XmlElement } from "../../text/xml";
 import { wrapNativeAsync } from "../../util";
+import { signedFetch } from "../signed_fetch";
 
 export interface JsAwsCredentials {
   key: string;
   secret: string;
+  session_token?: string | null | undefined;
 }
 
 export interface AwsSignaturePayloadBase {
@@ -56,9 +68,26 @@ export function sign<T extends AwsSignaturePayload | AwsSignaturePayloadPresigne
   );
 }
 
+// Returns the headers that sign the request with SigV4, for any AWS service. Send them along with
+// `req.headers` and the same body.
+export function sigv4Sign(
+  creds: JsAwsCredentials,
+  req: AwsSigV4SignRequest,
+  body?: string | Uint8Array | null
+): Record<string, string> {
+  return <Record<string, string>>(
+    __blueboat_host_invoke(
+      "external_aws_sigv4_sign",
+      creds,
+      req,
+      typeof body === "string" ? new TextEncoder().encode(body) : body
+    )
+  );
+}
+
 export function getPresignedUrl(
   region: S3Region,
-  credentials: S3Credentials,
+  credentials: S3Credentials | null,
   info: S3PresignInfo,
   options: S3PresignOptions
 ): string {
@@ -73,6 +102,18 @@ export function getPresignedUrl(
   );
 }
 
+// Returns a GET or PUT URL that is valid for at most 7 days, signed with SigV4 query parameters.
+// Without credentials the URL is left unsigned.
+export function presignUrl(
+  region: S3Region,
+  credentials: S3Credentials | null,
+  req: S3PresignUrlRequest
+): string {
+  return <string>(
+    __blueboat_host_invoke("external_s3_presign", region, credentials, req)
+  );
+}
+
 export function listObjectsV2(
   region: S3Region,
   credentials: S3Credentials,
@@ -88,3 +129,187 @@ export function listObjectsV2(
     )
   );
 }
+
+// Throws on an S3 error. `CompleteMultipartUpload` may also report an error in the body of a 200
+// response, so the parsed body is checked too.
+async function s3Result(res: Response): Promise<Record<string, XmlElement>> {
+  const text = await res.text();
+  const doc = text ? parseXml(text) : {};
+  if (!res.ok || doc.Error) {
+    throw new Error(`s3 request failed with status ${res.status}: ${text}`);
+  }
+  return doc;
+}
+
+function xmlField(doc: Record<string, XmlElement>, root: string, name: string): string | undefined {
+  const element = doc[root];
+  if (element === undefined || typeof element === "string") return undefined;
+  const value = element[name];
+  return typeof value === "string" ? value : undefined;
+}
+
+export async function createMultipartUpload(
+  region: S3Region,
+  credentials: S3Credentials | null,
+  req: S3CreateMultipartUploadRequest
+): Promise<string> {
+  const doc = await s3Result(
+    await signedFetch((callback) =>
+      __blueboat_host_invoke(
+        "external_s3_create_multipart_upload",
+        region,
+        credentials,
+        req,
+        callback
+      )
+    )
+  );
+  const uploadId = xmlField(doc, "InitiateMultipartUploadResult", "UploadId");
+  if (uploadId === undefined) {
+    throw new Error("s3 response is missing upload id");
+  }
+  return uploadId;
+}
+
+// Resolves to the ETag of the part.
+export async function uploadPart(
+  region: S3Region,
+  credentials: S3Credentials | null,
+  req: S3UploadPartRequest,
+  body: Uint8Array
+): Promise<string> {
+  const res = await signedFetch((callback) =>
+    __blueboat_host_invoke(
+      "external_s3_upload_part",
+      region,
+      credentials,
+      req,
+      body,
+      callback
+    )
+  );
+  await s3Result(res);
+  const eTag = res.headers.get("etag");
+  if (eTag === null) {
+    throw new Error("s3 response is missing ETag");
+  }
+  return eTag;
+}
+
+export async function completeMultipartUpload(
+  region: S3Region,
+  credentials: S3Credentials | null,
+  req: S3CompleteMultipartUploadRequest
+): Promise<S3CompleteMultipartUploadOutput> {
+  const res = await signedFetch((callback) =>
+    __blueboat_host_invoke(
+      "external_s3_complete_multipart_upload",
+      region,
+      credentials,
+      req,
+      callback
+    )
+  );
+  const doc = await s3Result(res);
+  const root = "CompleteMultipartUploadResult";
+  return {
+    bucket: xmlField(doc, root, "Bucket"),
+    e_tag: xmlField(doc, root, "ETag"),
+    key: xmlField(doc, root, "Key"),
+    location: xmlField(doc, root, "Location"),
+    version_id: res.headers.get("x-amz-version-id") ?? undefined,
+  };
+}
+
+export async function abortMultipartUpload(
+  region: S3Region,
+  credentials: S3Credentials | null,
+  req: S3AbortMultipartUploadRequest
+): Promise<void> {
+  await s3Result(
+    await signedFetch((callback) =>
+      __blueboat_host_invoke(
+        "external_s3_abort_multipart_upload",
+        region,
+        credentials,
+        req,
+        callback
+      )
+    )
+  );
+}
+
+function objectRequest(
+  api: string,
+  region: S3Region,
+  credentials: S3Credentials | null,
+  req: S3ObjectRequest,
+  body: Uint8Array | null
+): Promise<Response> {
+  return signedFetch((callback) =>
+    body
+      ? __blueboat_host_invoke(api, region, credentials, req, body, callback)
+      : __blueboat_host_invoke(api, region, credentials, req, callback)
+  );
+}
+
+// Signs and sends the request through `fetch`. Check `status` of the response: S3 errors are
+// returned as responses, not rejections.
+export function getObject(
+  region: S3Region,
+  credentials: S3Credentials | null,
+  req: S3ObjectRequest
+): Promise<Response> {
+  return objectRequest("external_s3_get_object", region, credentials, req, null);
+}
+
+export function putObject(
+  region: S3Region,
+  credentials: S3Credentials | null,
+  req: S3ObjectRequest,
+  body: Uint8Array
+): Promise<Response> {
+  return objectRequest("external_s3_put_object", region, credentials, req, body);
+}
+
+export function deleteObject(
+  region: S3Region,
+  credentials: S3Credentials | null,
+  req: S3ObjectRequest
+): Promise<Response> {
+  return objectRequest("external_s3_delete_object", region, credentials, req, null);
+}
+
+// Uploads `parts` in order as one object. Every part except the last must be at least 5 MiB.
+// The upload is aborted if any step fails.
+export async function uploadMultipart(
+  region: S3Region,
+  credentials: S3Credentials,
+  req: S3CreateMultipartUploadRequest,
+  parts: AsyncIterable<Uint8Array> | Iterable<Uint8Array>
+): Promise<S3CompleteMultipartUploadOutput> {
+  const uploadId = await createMultipartUpload(region, credentials, req);
+  const target = { bucket: req.bucket, key: req.key, upload_id: uploadId };
+  try {
+    const completed: S3CompletedPart[] = [];
+    for await (const body of parts) {
+      const partNumber = completed.length + 1;
+      const eTag = await uploadPart(
+        region,
+        credentials,
+        <S3UploadPartRequest>{ ...target, part_number: partNumber },
+        body
+      );
+      completed.push({ e_tag: eTag, part_number: partNumber });
+    }
+    return await completeMultipartUpload(region, credentials, <S3CompleteMultipartUploadRequest>{
+      ...target,
+      parts: completed,
+    });
+  } catch (e) {
+    await abortMultipartUpload(region, credentials, <S3AbortMultipartUploadRequest>target).catch(
+      () => {}
+    );
+    throw e;
+  }
+}
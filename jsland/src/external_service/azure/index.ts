@@ -0,0 +1,7 @@
+import { AzureSharedKeyRequest } from "../../native_schema";
+
+// Returns the `Authorization` header for a request to Azure Storage. `req.headers` must be the
+// headers sent with the request, including `x-ms-date` and `x-ms-version`.
+export function signSharedKey(req: AzureSharedKeyRequest): string {
+  return <string>__blueboat_host_invoke("external_azure_sign", req);
+}
@@ -0,0 +1,41 @@
+import { HostObject } from "../../host_object";
+import { GcsObjectRequest, GcsSignedUrlRequest } from "../../native_schema";
+import { signedFetch } from "../signed_fetch";
+
+// A parsed service account key. Parsing is slow, so keep the instance around.
+export class ServiceAccount extends HostObject {
+  // `key` is the JSON key file of the service account.
+  constructor(key: string | object) {
+    const sym = <symbol>(
+      __blueboat_host_invoke(
+        "external_gcs_load_key",
+        typeof key === "string" ? key : JSON.stringify(key)
+      )
+    );
+    super(sym);
+  }
+
+  signUrl(req: GcsSignedUrlRequest): string {
+    return <string>__blueboat_host_invoke("external_gcs_sign", this.hostSymbol, req);
+  }
+
+  // Signs and sends the request through `fetch`. Check `status` of the response: GCS errors are
+  // returned as responses, not rejections.
+  getObject(req: GcsObjectRequest): Promise<Response> {
+    return signedFetch((callback) =>
+      __blueboat_host_invoke("external_gcs_get_object", this.hostSymbol, req, callback)
+    );
+  }
+
+  putObject(req: GcsObjectRequest, body: Uint8Array): Promise<Response> {
+    return signedFetch((callback) =>
+      __blueboat_host_invoke("external_gcs_put_object", this.hostSymbol, req, body, callback)
+    );
+  }
+
+  deleteObject(req: GcsObjectRequest): Promise<Response> {
+    return signedFetch((callback) =>
+      __blueboat_host_invoke("external_gcs_delete_object", this.hostSymbol, req, callback)
+    );
+  }
+}
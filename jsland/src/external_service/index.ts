@@ -1,2 +1,4 @@
 export * as GitHub from "octokit";
 export * as AWS from "./aws/index";
+export * as GCS from "./gcs/index";
+export * as Azure from "./azure/index";
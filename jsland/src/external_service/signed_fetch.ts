@@ -0,0 +1,17 @@
+// Calls a native handler that sends a signed request through `fetch`. The native callback
+// receives the response info and body like the one of `fetch`.
+export function signedFetch(invoke: (callback: Function) => void): Promise<Response> {
+  return new Promise((resolve, reject) => {
+    invoke((err: unknown, info: any, body: ArrayBuffer) => {
+      if (err) {
+        reject(err);
+        return;
+      }
+      const headers: Record<string, string> = {};
+      for (const k in info.headers) {
+        headers[k] = info.headers[k].join(", ");
+      }
+      resolve(new Response(body, { status: info.status, headers }));
+    });
+  });
+}
@@ -0,0 +1,26 @@
+import { BlueboatBootstrapData, FcmRequest, FcmResponse } from "./native_schema";
+import { wrapNativeAsync } from "./util";
+
+export interface Fcm {
+  // Resolves with `error` set when FCM rejects the message. Remove the device token when
+  // `error.token_invalid` is true.
+  send(data: FcmRequest): Promise<FcmResponse>;
+}
+
+class FcmImpl implements Fcm {
+  constructor(private key: string) {}
+
+  send(data: FcmRequest): Promise<FcmResponse> {
+    return wrapNativeAsync((callback) =>
+      __blueboat_host_invoke("fcm_send", this.key, data, callback)
+    );
+  }
+}
+
+export const fcm: Record<string, Fcm> = {};
+
+export function init(bs: BlueboatBootstrapData) {
+  for (const x of bs.fcm) {
+    fcm[x] = new FcmImpl(x);
+  }
+}
@@ -537,6 +537,28 @@
     DOMException.prototype.constructor = DOMException;
   }
 
+  // Pull-based reader for streaming response bodies (`fetch(url, { stream: true })`).
+  function FetchBodyReader(handle) {
+    this._handle = handle;
+  }
+
+  FetchBodyReader.prototype.read = function () {
+    var handle = this._handle;
+    return new Promise(function (resolve, reject) {
+      __blueboat_host_invoke("fetch_read_chunk", handle, function (err, chunk) {
+        if (err) {
+          reject(err);
+        } else {
+          resolve(chunk);
+        }
+      });
+    });
+  };
+
+  FetchBodyReader.prototype.close = function () {
+    __blueboat_host_invoke("fetch_close_stream", this._handle);
+  };
+
   function fetch(input, init) {
     return new Promise(function (resolve, reject) {
       var request = new Request(input, init);
@@ -561,6 +583,17 @@
       for (const k in request.headers.map)
         headers[k] = [request.headers.map[k]];
 
+      const nativeOptions = {
+        ifNoneMatch: init && init.ifNoneMatch,
+        ifModifiedSince: init && init.ifModifiedSince,
+        timeoutMs: init && init.timeout,
+        stream: !!(init && init.stream),
+        redirect: (init && init.redirect) || "follow",
+        maxRedirects: init && init.maxRedirects,
+        proxy: init && init.proxy,
+        raw: !!(init && init.raw),
+      };
+
       __blueboat_host_invoke(
         "fetch",
         {
@@ -582,12 +615,21 @@
               status: res.status,
               statusText: "SomeStatus",
               headers,
-              url: "",
+              url: res.url,
             };
-            const targetResponse = new Response(body, options);
+            const targetResponse = new Response(
+              res.notModified ? null : body,
+              options
+            );
+            targetResponse.notModified = res.notModified;
+            targetResponse.redirected = res.redirected;
+            if (res.bodyHandle !== null && res.bodyHandle !== undefined) {
+              targetResponse.bodyReader = new FetchBodyReader(res.bodyHandle);
+            }
             resolve(targetResponse);
           }
-        }
+        },
+        nativeOptions
       );
     });
   }
@@ -0,0 +1,37 @@
+import { GraphicsAnimationEncodeConfig } from "../native_schema";
+import { CanvasImpl } from "./canvas";
+
+export interface AnimationFrame {
+  canvas: CanvasImpl;
+  delayMs: number;
+}
+
+export interface AnimationOpts {
+  // Number of times the animation is played. Defaults to 0, which loops forever.
+  loopCount?: number;
+}
+
+function encode(format: "gif" | "apng", frames: AnimationFrame[], opts: AnimationOpts): Uint8Array {
+  const snapshots = frames.map((x) => x.canvas.snapshot());
+  const config: GraphicsAnimationEncodeConfig = {
+    format,
+    loop_count: opts.loopCount || 0,
+    frames: frames.map((x, i) => ({
+      canvas: snapshots[i].config,
+      delay_ms: x.delayMs,
+    })),
+  };
+  return <Uint8Array>__blueboat_host_invoke(
+    "graphics_animation_encode",
+    config,
+    snapshots.map((x) => x.raster)
+  );
+}
+
+export function toGif(frames: AnimationFrame[], opts: AnimationOpts = {}): Uint8Array {
+  return encode("gif", frames, opts);
+}
+
+export function toApng(frames: AnimationFrame[], opts: AnimationOpts = {}): Uint8Array {
+  return encode("apng", frames, opts);
+}
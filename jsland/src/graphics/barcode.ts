@@ -0,0 +1,27 @@
+import { GraphicsBarcodeEncodeConfig, GraphicsBarcodeSymbology } from "../native_schema";
+
+export interface BarcodeOpts {
+  moduleWidth?: number;
+  height?: number;
+  humanReadable?: boolean;
+  fontSize?: number;
+}
+
+function buildConfig(symbology: GraphicsBarcodeSymbology, opts: BarcodeOpts, format: "svg" | "png"): GraphicsBarcodeEncodeConfig {
+  return {
+    symbology,
+    format,
+    module_width: opts.moduleWidth || 2,
+    height: opts.height || 80,
+    human_readable: opts.humanReadable === undefined ? true : opts.humanReadable,
+    font_size: opts.fontSize,
+  };
+}
+
+export function toSvg(symbology: GraphicsBarcodeSymbology, content: string, opts: BarcodeOpts = {}): string {
+  return <string>__blueboat_host_invoke("graphics_barcode_encode", content, buildConfig(symbology, opts, "svg"));
+}
+
+export function toPng(symbology: GraphicsBarcodeSymbology, content: string, opts: BarcodeOpts = {}): Uint8Array {
+  return <Uint8Array>__blueboat_host_invoke("graphics_barcode_encode", content, buildConfig(symbology, opts, "png"));
+}
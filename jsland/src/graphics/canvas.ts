@@ -2,10 +2,15 @@ import {
   CanvasConfig,
   CanvasDrawConfig,
   CanvasEncodeConfig,
+  CanvasEncodedImageFormat,
+  CanvasGradient as NativeCanvasGradient,
+  CanvasGradientShape,
   CanvasOp,
+  CanvasPngCompression,
   CanvasPathOp,
   CanvasRenderSvgConfig,
   CanvasRenderSvgFitTo,
+  GraphicsImageFilter,
 } from "../native_schema";
 
 function createRasterFromConfig(config: CanvasConfig): Uint8Array {
@@ -41,18 +46,50 @@ function createRasterFromConfig(config: CanvasConfig): Uint8Array {
   );
 }
 
+export class CanvasGradientImpl {
+  private shape: CanvasGradientShape;
+  private stops: { offset: number; color: string }[] = [];
+
+  constructor(shape: CanvasGradientShape) {
+    this.shape = shape;
+  }
+
+  // Offsets outside [0, 1] are clamped and stops are sorted by offset when the gradient is used.
+  addColorStop(offset: number, color: string) {
+    this.stops.push({ offset, color });
+  }
+
+  toNative(): NativeCanvasGradient {
+    return { shape: this.shape, stops: this.stops.slice() };
+  }
+}
+
+function fillStyleOp(style: string | CanvasGradientImpl): CanvasOp {
+  if (typeof style === "string") {
+    return { op: "SetFillStyleColor", color: style };
+  }
+  return { op: "SetFillStyleGradient", gradient: style.toNative() };
+}
+
+function strokeStyleOp(style: string | CanvasGradientImpl): CanvasOp {
+  if (typeof style === "string") {
+    return { op: "SetStrokeStyleColor", color: style };
+  }
+  return { op: "SetStrokeStyleGradient", gradient: style.toNative() };
+}
+
 class SkiaCanvas {
   config: CanvasConfig;
   log: CanvasOp[] = [];
   raster: Uint8Array;
-  strokeStyle: string | undefined = undefined;
-  fillStyle: string | undefined = undefined;
+  strokeStyle: string | CanvasGradientImpl | undefined = undefined;
+  fillStyle: string | CanvasGradientImpl | undefined = undefined;
   lineWidth: number | undefined = undefined;
   font: string = "10px sans-serif";
 
-  constructor(config: CanvasConfig) {
+  constructor(config: CanvasConfig, raster?: Uint8Array) {
     this.config = config;
-    this.raster = createRasterFromConfig(config);
+    this.raster = raster || createRasterFromConfig(config);
   }
 
   commit(): void {
@@ -61,16 +98,10 @@ class SkiaCanvas {
 
     // Start point for the next `commit()`
     if (this.strokeStyle !== undefined) {
-      this.log.push({
-        op: "SetStrokeStyleColor",
-        color: this.strokeStyle,
-      });
+      this.log.push(strokeStyleOp(this.strokeStyle));
     }
     if (this.fillStyle !== undefined) {
-      this.log.push({
-        op: "SetFillStyleColor",
-        color: this.fillStyle,
-      });
+      this.log.push(fillStyleOp(this.fillStyle));
     }
     if (this.lineWidth !== undefined) {
       this.log.push({
@@ -118,6 +149,16 @@ class SkiaCanvas {
     );
   }
 
+  filter(filter: GraphicsImageFilter) {
+    this.commit();
+    __blueboat_host_invoke(
+      "graphics_image_filter",
+      this.config,
+      filter,
+      this.raster
+    );
+  }
+
   renderSvg(svg: string, fit: CanvasRenderSvgFitTo) {
     this.commit();
     let config: CanvasRenderSvgConfig = {
@@ -281,6 +322,24 @@ export class CanvasImpl {
     });
   }
 
+  // Wraps RGBA8888 unpremultiplied pixels, as returned by the image functions.
+  static fromRgbaPixels(width: number, height: number, pixels: Uint8Array): CanvasImpl {
+    if (pixels.length !== width * height * 4) {
+      throw new Error("pixel buffer size mismatch");
+    }
+    const cvs: CanvasImpl = Object.create(CanvasImpl.prototype);
+    cvs.impl = new SkiaCanvas({
+      alpha_type: "Unpremul",
+      color_type: "RGBA8888",
+      dimensions: {
+        width,
+        height,
+      },
+      pixel_geometry: "RGBH",
+    }, pixels);
+    return cvs;
+  }
+
   get width() {
     return this.impl.config.dimensions.width;
   }
@@ -297,13 +356,45 @@ export class CanvasImpl {
     this.impl.commit();
   }
 
-  encode(options?: { type?: string; quality?: number }): Uint8Array {
+  // `quality` applies to JPEG and lossy WebP. `pngCompression` and `lossless` are only accepted
+  // for PNG and WebP respectively.
+  encode(options?: {
+    type?: string;
+    quality?: number;
+    pngCompression?: CanvasPngCompression;
+    lossless?: boolean;
+  }): Uint8Array {
+    let format: CanvasEncodedImageFormat;
+    switch (options?.type) {
+      case "jpeg":
+        format = "JPEG";
+        break;
+      case "webp":
+        format = "WEBP";
+        break;
+      default:
+        format = "PNG";
+    }
     return this.impl.encode({
-      format: options?.type == "jpeg" ? "JPEG" : "PNG",
+      format,
       quality: options?.quality || 90,
+      png_compression: options?.pngCompression,
+      webp_lossless: options?.lossless,
     });
   }
 
+  // Applies a blur, sharpen, grayscale, brightness or contrast filter in place. Runs synchronously
+  // and is billed as busy time; large blur radii on large canvases are expensive.
+  filter(filter: GraphicsImageFilter) {
+    this.impl.filter(filter);
+  }
+
+  // Flushes pending drawing and returns the canvas config with its framebuffer.
+  snapshot(): { config: CanvasConfig; raster: Uint8Array } {
+    this.impl.commit();
+    return { config: this.impl.config, raster: this.impl.raster };
+  }
+
   getContext(contextId: string, options?: any): CanvasRenderingContext2DImpl {
     if (contextId === "2d") {
       return new CanvasRenderingContext2DImpl(this.impl);
@@ -359,34 +450,47 @@ export class CanvasRenderingContext2DImpl {
     }
   }
 
-  get fillStyle(): string | CanvasGradient | CanvasPattern {
+  get fillStyle(): string | CanvasGradientImpl {
     return this.impl.fillStyle || "";
   }
 
-  set fillStyle(value: string | CanvasGradient | CanvasPattern) {
-    if (typeof value !== "string") {
+  set fillStyle(value: string | CanvasGradientImpl) {
+    if (typeof value !== "string" && !(value instanceof CanvasGradientImpl)) {
       throw new TypeError("fillStyle: unsupported type");
     }
     this.impl.fillStyle = value;
-    this.impl.log.push({
-      op: "SetFillStyleColor",
-      color: value,
-    });
+    this.impl.log.push(fillStyleOp(value));
   }
 
-  get strokeStyle(): string | CanvasGradient | CanvasPattern {
+  get strokeStyle(): string | CanvasGradientImpl {
     return this.impl.strokeStyle || "";
   }
 
-  set strokeStyle(value: string | CanvasGradient | CanvasPattern) {
-    if (typeof value !== "string") {
+  set strokeStyle(value: string | CanvasGradientImpl) {
+    if (typeof value !== "string" && !(value instanceof CanvasGradientImpl)) {
       throw new TypeError("strokeStyle: unsupported type");
     }
     this.impl.strokeStyle = value;
-    this.impl.log.push({
-      op: "SetStrokeStyleColor",
-      color: value,
-    });
+    this.impl.log.push(strokeStyleOp(value));
+  }
+
+  createLinearGradient(x0: number, y0: number, x1: number, y1: number): CanvasGradientImpl {
+    return new CanvasGradientImpl({ type: "Linear", x0, y0, x1, y1 });
+  }
+
+  createRadialGradient(
+    x0: number,
+    y0: number,
+    r0: number,
+    x1: number,
+    y1: number,
+    r1: number
+  ): CanvasGradientImpl {
+    return new CanvasGradientImpl({ type: "Radial", x0, y0, r0, x1, y1, r1 });
+  }
+
+  createConicGradient(startAngle: number, x: number, y: number): CanvasGradientImpl {
+    return new CanvasGradientImpl({ type: "Conic", x, y, start_angle: startAngle });
   }
 
   get lineWidth(): number {
@@ -0,0 +1,77 @@
+import {
+  CanvasEncodeConfig,
+  GraphicsDecodedImageInfo,
+  GraphicsImageCropConfig,
+  GraphicsImageResizeConfig,
+  GraphicsResizeFilter,
+} from "../native_schema";
+import { CanvasImpl } from "./canvas";
+
+export interface ResizeOpts {
+  width: number;
+  height: number;
+  filter?: GraphicsResizeFilter;
+}
+
+export interface CropOpts {
+  x: number;
+  y: number;
+  width: number;
+  height: number;
+}
+
+export interface DecodedImage extends GraphicsDecodedImageInfo {
+  canvas: CanvasImpl;
+}
+
+// Decodes a PNG, JPEG, WebP or GIF (first frame) image onto a new canvas.
+export function decode(image: Uint8Array): DecodedImage {
+  const out = <GraphicsDecodedImageInfo & { pixels: Uint8Array }>(
+    __blueboat_host_invoke("graphics_canvas_decode_image", image)
+  );
+  return {
+    format: out.format,
+    width: out.width,
+    height: out.height,
+    canvas: CanvasImpl.fromRgbaPixels(out.width, out.height, out.pixels),
+  };
+}
+
+export function resize(image: Uint8Array, opts: ResizeOpts): CanvasImpl {
+  const pixels = nativeResize(image, opts, null);
+  return CanvasImpl.fromRgbaPixels(opts.width, opts.height, pixels);
+}
+
+export function resizeEncoded(image: Uint8Array, opts: ResizeOpts, output: CanvasEncodeConfig): Uint8Array {
+  return nativeResize(image, opts, output);
+}
+
+export function crop(image: Uint8Array, opts: CropOpts): CanvasImpl {
+  const pixels = nativeCrop(image, opts, null);
+  return CanvasImpl.fromRgbaPixels(opts.width, opts.height, pixels);
+}
+
+export function cropEncoded(image: Uint8Array, opts: CropOpts, output: CanvasEncodeConfig): Uint8Array {
+  return nativeCrop(image, opts, output);
+}
+
+function nativeResize(image: Uint8Array, opts: ResizeOpts, output: CanvasEncodeConfig | null): Uint8Array {
+  const config: GraphicsImageResizeConfig = {
+    width: opts.width,
+    height: opts.height,
+    filter: opts.filter || "lanczos3",
+    output,
+  };
+  return <Uint8Array>__blueboat_host_invoke("graphics_image_resize", image, config);
+}
+
+function nativeCrop(image: Uint8Array, opts: CropOpts, output: CanvasEncodeConfig | null): Uint8Array {
+  const config: GraphicsImageCropConfig = {
+    x: opts.x,
+    y: opts.y,
+    width: opts.width,
+    height: opts.height,
+    output,
+  };
+  return <Uint8Array>__blueboat_host_invoke("graphics_image_crop", image, config);
+}
@@ -1,3 +1,8 @@
 export { CanvasImpl as Canvas, Path2DImpl as Path2D } from "./canvas";
 export * as Layout from "./layout";
 export * as Text from "./text";
+export * as Image from "./image";
+export * as QR from "./qr";
+export * as Barcode from "./barcode";
+export * as Animation from "./animation";
+export * as PDF from "./pdf";
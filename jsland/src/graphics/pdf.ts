@@ -0,0 +1,37 @@
+import { GraphicsPdfMargins, GraphicsPdfRenderConfig, GraphicsPdfText } from "../native_schema";
+import { CanvasImpl } from "./canvas";
+
+export interface PdfPage {
+  canvas: CanvasImpl;
+
+  // Page size in points (1/72 inch). Defaults to A4.
+  width?: number;
+  height?: number;
+  margins?: GraphicsPdfMargins;
+
+  // Vector text in Helvetica, positioned in points relative to the content box.
+  texts?: GraphicsPdfText[];
+}
+
+export interface PdfOpts {
+  title?: string;
+}
+
+export function render(pages: PdfPage[], opts: PdfOpts = {}): Uint8Array {
+  const snapshots = pages.map((x) => x.canvas.snapshot());
+  const config: GraphicsPdfRenderConfig = {
+    title: opts.title,
+    pages: pages.map((x, i) => ({
+      canvas: snapshots[i].config,
+      width: x.width || 595,
+      height: x.height || 842,
+      margins: x.margins || { top: 0, right: 0, bottom: 0, left: 0 },
+      texts: x.texts || [],
+    })),
+  };
+  return <Uint8Array>__blueboat_host_invoke(
+    "graphics_canvas_render_pdf",
+    config,
+    snapshots.map((x) => x.raster)
+  );
+}
@@ -0,0 +1,24 @@
+import { GraphicsQrEcLevel, GraphicsQrEncodeConfig } from "../native_schema";
+
+export interface QrOpts {
+  ecLevel?: GraphicsQrEcLevel;
+  moduleSize?: number;
+  quietZone?: number;
+}
+
+function buildConfig(opts: QrOpts, format: "svg" | "png"): GraphicsQrEncodeConfig {
+  return {
+    ec_level: opts.ecLevel || "M",
+    module_size: opts.moduleSize || 4,
+    quiet_zone: opts.quietZone === undefined ? 4 : opts.quietZone,
+    format,
+  };
+}
+
+export function toSvg(payload: string, opts: QrOpts = {}): string {
+  return <string>__blueboat_host_invoke("graphics_qr_encode", payload, buildConfig(opts, "svg"));
+}
+
+export function toPng(payload: string, opts: QrOpts = {}): Uint8Array {
+  return <Uint8Array>__blueboat_host_invoke("graphics_qr_encode", payload, buildConfig(opts, "png"));
+}
@@ -1,4 +1,11 @@
-import { GraphicsTextMeasureOutput, GraphicsTextMeasureSettings } from "../native_schema";
+import {
+  GraphicsTextAlign,
+  GraphicsTextLayoutOutput,
+  GraphicsTextLayoutSettings,
+  GraphicsTextMeasureOutput,
+  GraphicsTextMeasureSettings,
+} from "../native_schema";
+import { CanvasRenderingContext2DImpl } from "./canvas";
 
 export interface MeasureOpts {
   font: string;
@@ -13,4 +20,37 @@ export function measureSimple(text: string, opts: MeasureOpts): GraphicsTextMeas
   };
   const out = <GraphicsTextMeasureOutput>__blueboat_host_invoke("graphics_text_measure", settings);
   return out;
-}
\ No newline at end of file
+}
+
+export interface LayoutOpts {
+  font: string;
+  maxWidth: number;
+  align?: GraphicsTextAlign;
+}
+
+// Breaks text into lines of at most `maxWidth` and positions each word. Words that don't fit on
+// a line of their own are broken between characters.
+export function layout(text: string, opts: LayoutOpts): GraphicsTextLayoutOutput {
+  const settings: GraphicsTextLayoutSettings = {
+    text,
+    font: opts.font,
+    max_width: opts.maxWidth,
+    align: opts.align || "left",
+  };
+  return <GraphicsTextLayoutOutput>__blueboat_host_invoke("graphics_text_layout", settings);
+}
+
+// Draws a layout computed with the same font as `ctx.font`, with its top-left corner at (x, y).
+export function fillLayout(ctx: CanvasRenderingContext2DImpl, out: GraphicsTextLayoutOutput, x: number, y: number) {
+  for (const line of out.lines) {
+    for (const run of line.runs) {
+      ctx.fillText(run.text, x + run.x, y + line.y);
+    }
+  }
+}
+
+// Registers a TrueType or OpenType font and returns a family name for use in font strings, e.g.
+// `ctx.font = "16px " + loadFont(data)`. Loaded fonts are cached for the lifetime of the worker.
+export function loadFont(data: Uint8Array): string {
+  return <string>__blueboat_host_invoke("graphics_font_load", data);
+}
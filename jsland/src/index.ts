@@ -22,8 +22,12 @@ import * as backgroundMod from "./background/index";
 import { appBackgroundEntry } from "./background/impl";
 import * as textMod from "./text/index";
 import * as kvMod from "./kv";
+import * as appLogMod from "./applog";
+import * as metricMod from "./metric";
 import { HostObject as HostObject_ } from "./host_object"
 import * as compressMod from "./compress/index";
+import * as wasmMod from "./wasm";
+import { WebSocketClient } from "./websocket";
 import { generateStdRequest } from "./util";
 import { appSseAuthEntry } from "./pubsub";
 
@@ -42,8 +46,12 @@ const lateRoot = {
   Background: backgroundMod,
   TextUtil: textMod,
   KV: kvMod,
+  AppLog: appLogMod,
+  Metric: metricMod,
   Compress: compressMod,
+  Wasm: wasmMod,
   HostObject: HostObject_,
+  WebSocketClient,
   setTimeout,
   clearTimeout,
   setInterval,
@@ -53,6 +61,7 @@ const lateRoot = {
   __blueboat_app_sse_auth_entry: appSseAuthEntry,
   __blueboat_app_bootstrap: appBootstrap,
   __blueboat_app_warmup: appWarmup,
+  __blueboat_app_memory_pressure: appMod.appMemoryPressure,
 };
 
 const methodMap: Record<
@@ -202,6 +211,9 @@ declare global {
   const Background: typeof backgroundMod;
   const TextUtil: typeof textMod;
   const KV: typeof kvMod;
+  const AppLog: typeof appLogMod;
+  const Metric: typeof metricMod;
   const HostObject: typeof HostObject_;
   const Compress: typeof compressMod;
+  const Wasm: typeof wasmMod;
 }
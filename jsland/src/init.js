@@ -14,3 +14,7 @@ globalThis.URLSearchParams = URLSearchParams;
 
 require("./fetch");
 require("./index");
+
+// Apps reach WebAssembly through `Wasm` only, which checks imports and counts
+// linear memory against the isolate memory limit (see wasm.ts).
+delete globalThis.WebAssembly;
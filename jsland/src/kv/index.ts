@@ -4,11 +4,19 @@ export interface CompareAndSetManyRequestKey {
   key: string;
   check: TriStateCheck;
   set: TriStateSet;
+
+  // Seconds until the key expires. Only valid when setting a value.
+  ttl?: number;
 }
 
 export interface PrefixListOptions {
   reverse?: boolean;
+
+  // Return values alongside keys. Defaults to `true`; set to `false` to only list keys.
   wantValue?: boolean;
+
+  // At most 1000 when listing values. Listing values without a limit returns
+  // the first 1000 keys and a cursor to the rest.
   limit?: number;
   cursor?: string;
 }
@@ -20,10 +28,41 @@ export type TriStateSet = "delete" | "preserve" | { value: Uint8Array } | {
   },
 }
 
+export interface SetOptions {
+  ttl?: number;
+}
+
+export interface PrefixListPage {
+  keyValuePairs: [string, Uint8Array][];
+
+  // Pass as `opts.cursor` to fetch the next page. `null` once the listing is exhausted.
+  cursor: string | null;
+}
+
 export interface CommitResult {
   versionstamp: string | null;
 }
 
+export interface KvChange {
+  // Key relative to the watched prefix.
+  key: string;
+
+  // `null` if the key was deleted.
+  value: Uint8Array | null;
+}
+
+export class KvWatch {
+  private handle: number;
+
+  constructor(handle: number) {
+    this.handle = handle;
+  }
+
+  stop() {
+    __blueboat_host_invoke("kv_unwatch", this.handle);
+  }
+}
+
 export class Namespace {
   private name: string;
 
@@ -56,7 +95,7 @@ export class Namespace {
   async prefixList(prefix: string, opts: PrefixListOptions = {}, primary: boolean = false): Promise<[string, Uint8Array][]> {
     opts = Object.assign(<PrefixListOptions>{
       reverse: false,
-      wantValue: false,
+      wantValue: true,
       limit: 100,
     }, opts);
     return await wrapNativeAsync(callback => __blueboat_host_invoke("kv_prefix_list", {
@@ -67,6 +106,22 @@ export class Namespace {
     }, callback));
   }
 
+  async prefixListPage(prefix: string, opts: PrefixListOptions = {}, primary: boolean = false): Promise<PrefixListPage> {
+    opts = Object.assign(<PrefixListOptions>{
+      reverse: false,
+      wantValue: true,
+      limit: 100,
+    }, opts);
+    return await wrapNativeAsync(callback => __blueboat_host_invoke("kv_prefix_list_1", {
+      namespace: this.name,
+      prefix,
+      opts,
+      primary,
+    }, callback));
+  }
+
+  // Deletes `prefix` and every key under it, expired or not. Watches are notified of a single
+  // deletion of `prefix`; watches of longer prefixes are not notified.
   async prefixDelete(prefix: string): Promise<void> {
     await wrapNativeAsync(callback => __blueboat_host_invoke("kv_prefix_delete", {
       namespace: this.name,
@@ -78,7 +133,7 @@ export class Namespace {
     return (await this.getMany([path], primary))[0];
   }
 
-  async set(path: string, value: Uint8Array | string): Promise<void> {
+  async set(path: string, value: Uint8Array | string, opts: SetOptions = {}): Promise<void> {
     if (typeof value === "string") {
       value = new TextEncoder().encode(value);
     }
@@ -87,6 +142,7 @@ export class Namespace {
       key: path,
       check: "any",
       set: { value: value },
+      ttl: opts.ttl,
     }]);
   }
 
@@ -98,6 +154,32 @@ export class Namespace {
     }]);
   }
 
+  // Polls for changes under `prefix` until stopped or until the request completes. Every change is
+  // reported in commit order, with the value it was set to (`null` for a delete).
+  watch(prefix: string, onChange: (change: KvChange) => void, onError?: (e: Error) => void): KvWatch {
+    const handle = <number>__blueboat_host_invoke("kv_watch", {
+      namespace: this.name,
+      prefix,
+    }, (err: Error | undefined, change: KvChange) => {
+      if (err) {
+        if (onError) onError(err);
+      } else {
+        onChange(change);
+      }
+    });
+    return new KvWatch(handle);
+  }
+
+  // Counters are stored as decimal strings and returned as bigints, which keep their precision
+  // beyond `Number.MAX_SAFE_INTEGER`.
+  async increment(path: string, delta: number = 1): Promise<bigint> {
+    return await wrapNativeAsync(callback => __blueboat_host_invoke("kv_increment", {
+      namespace: this.name,
+      key: path,
+      delta,
+    }, callback));
+  }
+
   rawRun(script: string, data: string): Promise<string> {
     return wrapNativeAsync((callback) =>
       (<any>globalThis).__blueboat_host_invoke("kv_run", {
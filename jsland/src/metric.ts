@@ -0,0 +1,16 @@
+export type Labels = Record<string, string>;
+
+/**
+ * Adds `value` (default 1) to a counter. Series are labelled with the app, and
+ * samples beyond the per-app series limit are dropped.
+ */
+export function incr(name: string, value = 1, labels?: Labels): void {
+  __blueboat_host_invoke("metric_incr", name, value, labels);
+}
+
+/**
+ * Records a value into a histogram, such as a latency in seconds.
+ */
+export function observe(name: string, value: number, labels?: Labels): void {
+  __blueboat_host_invoke("metric_observe", name, value, labels);
+}
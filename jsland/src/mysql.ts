@@ -1,17 +1,110 @@
-import { BlueboatBootstrapData } from "./native_schema";
+import {
+  BlueboatBootstrapData,
+  MysqlBatchOptions,
+  MysqlBatchRowResult,
+  MysqlExecOptions,
+  MysqlStreamOptions,
+} from "./native_schema";
 import { wrapNativeAsync } from "./util";
 
 export interface Mysql {
   exec<Spec extends string>(
     stmt: string,
     args: Record<string, MysqlInputType>,
-    outSpec: Spec
+    outSpec: Spec,
+    opts?: MysqlExecOpts
   ): Promise<Row<Spec>[]>;
+  execBatch(
+    stmt: string,
+    rows: Record<string, MysqlInputType>[],
+    opts?: MysqlBatchOpts
+  ): Promise<MysqlBatchRowResult[]>;
+  stream<Spec extends string>(
+    stmt: string,
+    args: Record<string, MysqlInputType>,
+    outSpec: Spec,
+    opts?: MysqlStreamOpts
+  ): Promise<MysqlCursor<Row<Spec>>>;
   startTransaction(): Promise<void>;
   commit(): Promise<void>;
   rollback(): Promise<void>;
 }
 
+export interface MysqlExecOpts {
+  // Close the prepared statement right after the query instead of keeping it in
+  // the connection's statement cache.
+  bypassStmtCache?: boolean;
+
+  // Abort the query after this many milliseconds. A timed-out query inside a transaction rolls the
+  // transaction back.
+  timeoutMs?: number;
+
+  // Run the query on the read replica, if the app has one. Replication is asynchronous, so the
+  // result may not include recent writes, including this request's own. Ignored inside
+  // transactions, which always use the primary.
+  readonly?: boolean;
+}
+
+export interface MysqlStreamOpts {
+  // Run the query on the read replica, if the app has one.
+  readonly?: boolean;
+}
+
+// A streaming query. Not available inside transactions. The cursor is closed once it is
+// exhausted, and at the end of the request.
+export interface MysqlCursor<T> extends AsyncIterable<T> {
+  // Returns up to `maxRows` rows. Fewer rows mean that the result set is exhausted.
+  fetch(maxRows: number): Promise<T[]>;
+  close(): void;
+}
+
+const DEFAULT_FETCH_SIZE = 100;
+
+class MysqlCursorImpl<T> implements MysqlCursor<T> {
+  private handle: number;
+  private done = false;
+
+  constructor(handle: number) {
+    this.handle = handle;
+  }
+
+  async fetch(maxRows: number): Promise<T[]> {
+    if (this.done) return [];
+    const rows: T[] = await wrapNativeAsync((callback) =>
+      __blueboat_host_invoke(
+        "mysql_query_fetch",
+        this.handle,
+        maxRows,
+        callback
+      )
+    );
+    if (rows.length < maxRows) this.done = true;
+    return rows;
+  }
+
+  close() {
+    if (this.done) return;
+    this.done = true;
+    __blueboat_host_invoke("mysql_query_close", this.handle);
+  }
+
+  async *[Symbol.asyncIterator](): AsyncIterator<T> {
+    try {
+      while (!this.done) {
+        yield* await this.fetch(DEFAULT_FETCH_SIZE);
+      }
+    } finally {
+      this.close();
+    }
+  }
+}
+
+export interface MysqlBatchOpts {
+  // Keep going after a failed row and commit the rows that succeeded. By default the whole batch
+  // is rolled back on the first failure.
+  continueOnError?: boolean;
+}
+
 type ValueSpec = "i" | "I" | "f" | "s" | "b" | "d";
 type RowSpec<TThis extends ValueSpec, TRem extends string> = `${TThis}${TRem}`;
 
@@ -60,8 +153,14 @@ class MysqlImpl implements Mysql {
   exec<Spec extends string>(
     stmt: string,
     args: Record<string, MysqlInputType>,
-    outSpec: Spec
+    outSpec: Spec,
+    opts: MysqlExecOpts = {}
   ): Promise<Row<Spec>[]> {
+    const nativeOpts: MysqlExecOptions = {
+      bypass_stmt_cache: !!opts.bypassStmtCache,
+      timeout_ms: opts.timeoutMs,
+      readonly: !!opts.readonly,
+    };
     return wrapNativeAsync((callback) =>
       __blueboat_host_invoke(
         "mysql_exec",
@@ -69,9 +168,53 @@ class MysqlImpl implements Mysql {
         stmt,
         args,
         outSpec,
-        callback
+        callback,
+        nativeOpts
+      )
+    );
+  }
+
+  execBatch(
+    stmt: string,
+    rows: Record<string, MysqlInputType>[],
+    opts: MysqlBatchOpts = {}
+  ): Promise<MysqlBatchRowResult[]> {
+    const nativeOpts: MysqlBatchOptions = {
+      continue_on_error: !!opts.continueOnError,
+    };
+    return wrapNativeAsync((callback) =>
+      __blueboat_host_invoke(
+        "mysql_exec_batch",
+        this.key,
+        stmt,
+        rows,
+        callback,
+        nativeOpts
+      )
+    );
+  }
+
+  async stream<Spec extends string>(
+    stmt: string,
+    args: Record<string, MysqlInputType>,
+    outSpec: Spec,
+    opts: MysqlStreamOpts = {}
+  ): Promise<MysqlCursor<Row<Spec>>> {
+    const nativeOpts: MysqlStreamOptions = {
+      readonly: !!opts.readonly,
+    };
+    const handle: number = await wrapNativeAsync((callback) =>
+      __blueboat_host_invoke(
+        "mysql_query_stream",
+        this.key,
+        stmt,
+        args,
+        outSpec,
+        callback,
+        nativeOpts
       )
     );
+    return new MysqlCursorImpl(handle);
   }
 
   startTransaction(): Promise<void> {
@@ -12,3 +12,28 @@ export function aes128GcmSivEncrypt(params: AesGcmSivParams): Uint8Array {
 export function aes128GcmSivDecrypt(params: AesGcmSivParams): Uint8Array {
   return <Uint8Array>__blueboat_host_invoke("crypto_aead_aes128_gcm_siv_decrypt", params.key, params.nonce, params.data, params.associatedData);
 }
+
+export interface AesGcmParams {
+  key: Uint8Array;
+  nonce: Uint8Array;
+  data: Uint8Array;
+  associatedData?: Uint8Array | null | undefined,
+}
+
+export function aesGcmEncrypt(params: AesGcmParams): Uint8Array {
+  return <Uint8Array>__blueboat_host_invoke("crypto_aes_gcm_encrypt", params.key, params.nonce, params.data, params.associatedData);
+}
+
+export function aesGcmDecrypt(params: AesGcmParams): Uint8Array {
+  return <Uint8Array>__blueboat_host_invoke("crypto_aes_gcm_decrypt", params.key, params.nonce, params.data, params.associatedData);
+}
+
+export type ChaCha20Poly1305Params = AesGcmParams;
+
+export function chacha20Poly1305Encrypt(params: ChaCha20Poly1305Params): Uint8Array {
+  return <Uint8Array>__blueboat_host_invoke("crypto_chacha20poly1305_encrypt", params.key, params.nonce, params.data, params.associatedData);
+}
+
+export function chacha20Poly1305Decrypt(params: ChaCha20Poly1305Params): Uint8Array {
+  return <Uint8Array>__blueboat_host_invoke("crypto_chacha20poly1305_decrypt", params.key, params.nonce, params.data, params.associatedData);
+}
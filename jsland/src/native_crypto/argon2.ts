@@ -0,0 +1,15 @@
+export interface Argon2HashOptions {
+  // Memory cost in KiB.
+  memoryCost?: number;
+  timeCost?: number;
+  // Lanes; at most 1, since workers are single-threaded.
+  parallelism?: number;
+}
+
+export function hash(password: string | Uint8Array, opts?: Argon2HashOptions): string {
+  return <string>__blueboat_host_invoke("crypto_argon2_hash", password, opts);
+}
+
+export function verify(encoded: string, password: string | Uint8Array): boolean {
+  return <boolean>__blueboat_host_invoke("crypto_argon2_verify", encoded, password);
+}
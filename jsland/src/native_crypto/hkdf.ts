@@ -0,0 +1,14 @@
+// `sha1` is for interoperability with legacy protocols only.
+export type HkdfHash = "sha1" | "sha256" | "sha384" | "sha512";
+
+export interface HkdfParams {
+  hash: HkdfHash;
+  ikm: Uint8Array;
+  salt?: Uint8Array | null | undefined;
+  info?: Uint8Array | null | undefined;
+  length: number;
+}
+
+export function derive(params: HkdfParams): Uint8Array {
+  return <Uint8Array>__blueboat_host_invoke("crypto_hkdf", params.hash, params.ikm, params.salt, params.info, params.length);
+}
@@ -4,3 +4,13 @@ export function hmacSha256({ key, data }: { key: Uint8Array, data: Uint8Array })
     data,
   });
 }
+
+export type HmacAlgorithm = "sha256" | "sha384" | "sha512";
+
+export function sign(algorithm: HmacAlgorithm, key: Uint8Array, data: Uint8Array): Uint8Array {
+  return <Uint8Array>__blueboat_host_invoke("crypto_hmac_sign", algorithm, key, data);
+}
+
+export function verify(algorithm: HmacAlgorithm, key: Uint8Array, data: Uint8Array, mac: Uint8Array): boolean {
+  return <boolean>__blueboat_host_invoke("crypto_hmac_verify", algorithm, key, data, mac);
+}
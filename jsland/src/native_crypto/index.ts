@@ -1,11 +1,20 @@
 export * as Ed25519 from "./ed25519";
 export * as X25519 from "./x25519";
+export * as P256 from "./p256";
+export * as Secp256k1 from "./secp256k1";
+export * as RSA from "./rsa";
 export * as JWT from "./jwt";
+export * as JWE from "./jwe";
 export * as AEAD from "./aead";
 export * as HMAC from "./hmac";
+export * as PBKDF2 from "./pbkdf2";
+export * as HKDF from "./hkdf";
+export * as Argon2 from "./argon2";
 
 export type DigestAlgorithm = "sha1" | "sha256" | "sha384" | "sha512" | "blake3";
 
+export type StreamingDigestAlgorithm = DigestAlgorithm | "md5";
+
 export function digest(
   algorithm: DigestAlgorithm,
   data: Uint8Array
@@ -16,3 +25,21 @@ export function digest(
 export function constantTimeEq(a: Uint8Array, b: Uint8Array): boolean {
   return <boolean>__blueboat_host_invoke("crypto_constant_time_eq", a, b);
 }
+
+// Only valid within the request that created it.
+export class DigestStream {
+  private handle: number;
+
+  constructor(algorithm: StreamingDigestAlgorithm) {
+    this.handle = <number>__blueboat_host_invoke("crypto_digest_init", algorithm);
+  }
+
+  update(data: string | Uint8Array): this {
+    __blueboat_host_invoke("crypto_digest_update", this.handle, data);
+    return this;
+  }
+
+  final(): Uint8Array {
+    return <Uint8Array>__blueboat_host_invoke("crypto_digest_final", this.handle);
+  }
+}
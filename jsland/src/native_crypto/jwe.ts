@@ -0,0 +1,33 @@
+export type Algorithm = "RSA-OAEP" | "RSA-OAEP-256" | "ECDH-ES";
+
+export type Encryption = "A128GCM" | "A256GCM";
+
+export interface KeyInfo {
+  type: KeyType;
+  data: string;
+}
+
+// `der` keys are passed as standard base64 in `KeyInfo.data`.
+export type KeyType = "pem" | "der";
+
+export function encrypt(
+  alg: Algorithm,
+  enc: Encryption,
+  publicKey: KeyInfo,
+  payload: string | Uint8Array,
+  header?: Record<string, unknown>
+): string {
+  return <string>(
+    __blueboat_host_invoke("crypto_jwe_encrypt", alg, enc, publicKey, payload, header)
+  );
+}
+
+export function decrypt(
+  alg: Algorithm,
+  privateKey: KeyInfo,
+  token: string
+): { header: Record<string, unknown>; plaintext: Uint8Array } {
+  return <any>(
+    __blueboat_host_invoke("crypto_jwe_decrypt", alg, privateKey, token)
+  );
+}
@@ -3,10 +3,12 @@ export interface KeyInfo {
   data: string;
 }
 
-export type KeyType = "base64Secret" | "rsaPem" | "ecPem";
+// `rsaDer` and `ecDer` keys are passed as standard base64 in `KeyInfo.data`.
+export type KeyType = "base64Secret" | "rsaPem" | "rsaDer" | "ecPem" | "ecDer";
 
 export interface Validation {
-  leeway: number;
+  // Allowed clock skew in seconds. Defaults to 0.
+  leeway?: number;
   validate_exp?: boolean;
   validate_nbf?: boolean;
   aud?: string[];
@@ -44,12 +46,36 @@ export function encode(header: Header, claims: unknown, key: KeyInfo): string {
   );
 }
 
+export type JwtErrorKind =
+  | "expired"
+  | "notYetValid"
+  | "invalidAudience"
+  | "invalidIssuer"
+  | "invalidSubject"
+  | "invalidSignature"
+  | "invalidAlgorithm"
+  | "malformed"
+  | "other";
+
+export class JwtError extends Error {
+  kind: JwtErrorKind;
+
+  constructor(kind: JwtErrorKind, message: string) {
+    super(message);
+    this.kind = kind;
+  }
+}
+
 export function decode(
   token: string,
   key: KeyInfo,
   validation: Validation
 ): { header: Header; claims: unknown } {
-  return <any>(
+  const out = <any>(
     __blueboat_host_invoke("crypto_jwt_decode", token, key, validation)
   );
+  if (out.error) {
+    throw new JwtError(out.error.kind, out.error.message);
+  }
+  return out.token;
 }
@@ -0,0 +1,89 @@
+const secretKeySym = Symbol("p256-secret-key");
+const publicKeySym = Symbol("p256-public-key");
+
+export type SignatureFormat = "der" | "raw";
+
+export class Keypair {
+  secret: SecretKey;
+  public: PublicKey;
+
+  constructor(secretKey: SecretKey, publicKey: PublicKey) {
+    if (!secretKey[secretKeySym] || !publicKey[publicKeySym])
+      throw new TypeError("invalid keypair parameters");
+    this.secret = secretKey;
+    this.public = publicKey;
+  }
+
+  sign(message: Uint8Array, format: SignatureFormat = "der"): Uint8Array {
+    return <Uint8Array>(
+      __blueboat_host_invoke(
+        "crypto_p256_sign",
+        this.secret[secretKeySym],
+        message,
+        format
+      )
+    );
+  }
+}
+
+export class SecretKey {
+  [secretKeySym]: Uint8Array;
+
+  constructor(bytes?: Uint8Array) {
+    if (!bytes) {
+      bytes = crypto.getRandomValues(new Uint8Array(32));
+    } else {
+      bytes = Uint8Array.from(bytes);
+    }
+
+    if (bytes.byteLength != 32) {
+      throw new Error("invalid secret key");
+    }
+
+    this[secretKeySym] = bytes;
+  }
+
+  exportSecret(): Uint8Array {
+    return Uint8Array.from(this[secretKeySym]);
+  }
+}
+
+export class PublicKey {
+  [publicKeySym]: Uint8Array;
+
+  // Accepts SEC1-encoded (compressed or uncompressed) points.
+  constructor(source: Uint8Array | SecretKey) {
+    if (source instanceof Uint8Array) {
+      this[publicKeySym] = Uint8Array.from(source);
+    } else if (source instanceof SecretKey) {
+      this[publicKeySym] = <Uint8Array>(
+        __blueboat_host_invoke(
+          "crypto_p256_derive_public",
+          source[secretKeySym]
+        )
+      );
+    } else {
+      throw new Error("invalid public key");
+    }
+  }
+
+  exportPublic(): Uint8Array {
+    return Uint8Array.from(this[publicKeySym]);
+  }
+
+  verify(
+    signature: Uint8Array,
+    message: Uint8Array,
+    format: SignatureFormat = "der"
+  ): boolean {
+    return <boolean>(
+      __blueboat_host_invoke(
+        "crypto_p256_verify",
+        this[publicKeySym],
+        signature,
+        message,
+        format
+      )
+    );
+  }
+}
@@ -0,0 +1,13 @@
+export type Pbkdf2Hash = "sha1" | "sha256" | "sha384" | "sha512";
+
+export interface Pbkdf2Params {
+  password: Uint8Array;
+  salt: Uint8Array;
+  iterations: number;
+  length: number;
+  hash: Pbkdf2Hash;
+}
+
+export function derive(params: Pbkdf2Params): Uint8Array {
+  return <Uint8Array>__blueboat_host_invoke("crypto_pbkdf2", params.password, params.salt, params.iterations, params.length, params.hash);
+}
@@ -0,0 +1,28 @@
+export interface KeyInfo {
+  type: KeyType;
+  data: string;
+}
+
+// `der` keys are passed as standard base64 in `KeyInfo.data`. Both PKCS#8/SPKI and PKCS#1 are
+// accepted.
+export type KeyType = "pem" | "der";
+
+export type Hash = "sha256" | "sha384" | "sha512";
+
+export type SignatureScheme = "pkcs1v15" | "pss";
+
+export function oaepEncrypt(publicKey: KeyInfo, hash: Hash, data: Uint8Array): Uint8Array {
+  return <Uint8Array>__blueboat_host_invoke("crypto_rsa_oaep_encrypt", publicKey, hash, data);
+}
+
+export function oaepDecrypt(privateKey: KeyInfo, hash: Hash, data: Uint8Array): Uint8Array {
+  return <Uint8Array>__blueboat_host_invoke("crypto_rsa_oaep_decrypt", privateKey, hash, data);
+}
+
+export function sign(privateKey: KeyInfo, scheme: SignatureScheme, hash: Hash, message: Uint8Array): Uint8Array {
+  return <Uint8Array>__blueboat_host_invoke("crypto_rsa_sign", privateKey, scheme, hash, message);
+}
+
+export function verify(publicKey: KeyInfo, scheme: SignatureScheme, hash: Hash, signature: Uint8Array, message: Uint8Array): boolean {
+  return <boolean>__blueboat_host_invoke("crypto_rsa_verify", publicKey, scheme, hash, signature, message);
+}
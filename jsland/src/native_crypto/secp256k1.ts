@@ -0,0 +1,23 @@
+// `hash` is always a 32-byte message digest, e.g. keccak256 of the payload for Ethereum.
+
+export function sign(secretKey: Uint8Array, hash: Uint8Array): Uint8Array {
+  return <Uint8Array>(
+    __blueboat_host_invoke("crypto_secp256k1_sign", secretKey, hash)
+  );
+}
+
+export function verify(
+  publicKey: Uint8Array,
+  signature: Uint8Array,
+  hash: Uint8Array
+): boolean {
+  return <boolean>(
+    __blueboat_host_invoke("crypto_secp256k1_verify", publicKey, signature, hash)
+  );
+}
+
+export function recover(signature: Uint8Array, hash: Uint8Array): Uint8Array {
+  return <Uint8Array>(
+    __blueboat_host_invoke("crypto_secp256k1_recover", signature, hash)
+  );
+}
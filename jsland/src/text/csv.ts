@@ -0,0 +1,25 @@
+import { TextCsvOptions } from "../native_schema";
+
+export type CsvOpts = Partial<TextCsvOptions>;
+
+export function parse(x: string | Uint8Array, opts?: { header?: false } & CsvOpts): string[][];
+export function parse(
+  x: string | Uint8Array,
+  opts: { header: true } & CsvOpts
+): Record<string, string>[];
+export function parse(
+  x: string | Uint8Array,
+  opts: CsvOpts = {}
+): string[][] | Record<string, string>[] {
+  return <string[][] | Record<string, string>[]>(
+    __blueboat_host_invoke("text_csv_parse", x, opts)
+  );
+}
+
+// Lines end with CRLF as in RFC 4180. `header` writes the keys of object rows as the first line.
+export function stringify(
+  rows: (unknown[] | Record<string, unknown>)[],
+  opts: CsvOpts = {}
+): string {
+  return <string>__blueboat_host_invoke("text_csv_stringify", rows, opts);
+}
@@ -0,0 +1,8 @@
+import { TextHtmlSanitizeOptions } from "../native_schema";
+
+export type HtmlSanitizeOpts = Partial<TextHtmlSanitizeOptions>;
+
+// Removes scripts, event handlers, unsafe URLs and everything outside of the allowlist.
+export function sanitize(html: string, opts: HtmlSanitizeOpts = {}): string {
+  return <string>__blueboat_host_invoke("text_html_sanitize", html, opts);
+}
@@ -1,4 +1,8 @@
 export * as Markdown from "./markdown";
 export * as Yaml from "./yaml";
+export * as Toml from "./toml";
+export * as Csv from "./csv";
+export * as Html from "./html";
+export * as Xml from "./xml";
 export * as Json from "./json";
 export * as DOM from "./dom";
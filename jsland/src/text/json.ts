@@ -2,8 +2,27 @@ export function parse(x: string | Uint8Array): unknown {
   return __blueboat_host_invoke("text_json_parse", x);
 }
 
+// Accepts JSON5: comments, trailing commas, unquoted keys, single-quoted strings, hex numbers,
+// `Infinity` and `NaN`. Errors include the line and column.
+export function parse5(x: string | Uint8Array): unknown {
+  return __blueboat_host_invoke("text_json5_parse", x);
+}
+
 type ToUint8ArrayOutput<T> = T extends undefined ? undefined : Uint8Array;
 
-export function toUint8Array<T>(x: T): ToUint8ArrayOutput<T> {
+export interface StringifyOptions {
+  // Encode BigInts as decimal strings. Without this, BigInts are rejected like `JSON.stringify`
+  // does, since JSON numbers beyond 2^53 lose precision in most parsers.
+  bigintAsString?: boolean;
+}
+
+function bigintToString(_key: string, value: unknown): unknown {
+  return typeof value === "bigint" ? value.toString() : value;
+}
+
+export function toUint8Array<T>(x: T, opts: StringifyOptions = {}): ToUint8ArrayOutput<T> {
+  if (opts.bigintAsString && x !== undefined) {
+    return <ToUint8ArrayOutput<T>>new TextEncoder().encode(JSON.stringify(x, bigintToString));
+  }
   return <ToUint8ArrayOutput<T>>__blueboat_host_invoke("text_json_to_uint8array", x);
 }
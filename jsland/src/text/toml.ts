@@ -0,0 +1,11 @@
+// Tables become objects with their keys in document order. Datetimes become ISO 8601 strings.
+export function parse(x: string | Uint8Array): Record<string, unknown> {
+  return <Record<string, unknown>>(
+    __blueboat_host_invoke("text_toml_parse", x)
+  );
+}
+
+// Throws on values that TOML can't represent, like `null` and `undefined`.
+export function stringify(x: Record<string, unknown>): string {
+  return <string>__blueboat_host_invoke("text_toml_stringify", x);
+}
@@ -0,0 +1,31 @@
+import {
+  TextXmlParseOptions,
+  TextXmlStringifyOptions,
+} from "../native_schema";
+
+// An element with only text is a string. Otherwise attributes are under `@`, text is under
+// `#text`, and child elements are under their names, as arrays when a name repeats.
+export type XmlElement =
+  | string
+  | {
+      "@"?: Record<string, string>;
+      "#text"?: string;
+      [child: string]: unknown;
+    };
+
+// Returns `{ rootName: element }`. Documents with a DOCTYPE are rejected.
+export function parse(
+  x: string | Uint8Array,
+  opts: Partial<TextXmlParseOptions> = {}
+): Record<string, XmlElement> {
+  return <Record<string, XmlElement>>(
+    __blueboat_host_invoke("text_xml_parse", x, opts)
+  );
+}
+
+export function stringify(
+  x: Record<string, unknown>,
+  opts: Partial<TextXmlStringifyOptions> = {}
+): string {
+  return <string>__blueboat_host_invoke("text_xml_stringify", x, opts);
+}
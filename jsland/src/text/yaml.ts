@@ -1,5 +1,16 @@
-export function parse(x: string | Uint8Array): unknown {
-  return __blueboat_host_invoke("text_yaml_parse", x);
+import { TextYamlParseOptions } from "../native_schema";
+
+// Aliases are resolved into copies of the anchored value.
+export function parse(
+  x: string | Uint8Array,
+  opts: Partial<TextYamlParseOptions> = {}
+): unknown {
+  return __blueboat_host_invoke("text_yaml_parse", x, opts);
+}
+
+// Parses a stream of `---` separated documents.
+export function parseAll(x: string | Uint8Array): unknown[] {
+  return <unknown[]>parse(x, { multi_document: true });
 }
 
 type StringifyOutput<T> = T extends undefined ? undefined : string;
@@ -1 +1,2 @@
 export * as JTD from "./jtd";
+export * as JSONSchema from "./jsonschema";
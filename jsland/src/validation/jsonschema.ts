@@ -0,0 +1,37 @@
+import { HostObject } from "../host_object";
+import {
+  JsonSchemaLoadOptions,
+  ValidationError,
+  ValidationOptions,
+} from "../native_schema";
+
+export type FormatPredicate = (value: string) => boolean;
+
+export interface JSONSchemaOptions extends Partial<JsonSchemaLoadOptions> {
+  // Custom formats by name. Builtin formats are email, uri, uuid, date-time, ipv4 and ipv6.
+  formats?: Record<string, FormatPredicate>;
+}
+
+export class JSONSchema<T = unknown> extends HostObject {
+  errors: ValidationError[] = [];
+
+  // Schemas without `$schema` are draft 2020-12. `$ref` can only point into the same document.
+  // Unknown formats are an error unless `lenient_formats` is set.
+  constructor(
+    schema: Record<string, unknown> | boolean,
+    opts: JSONSchemaOptions = {}
+  ) {
+    const { formats, ...loadOpts } = opts;
+    const sym = <symbol>(
+      __blueboat_host_invoke("jsonschema_load", schema, formats, loadOpts)
+    );
+    super(sym);
+  }
+
+  validate(input: unknown, opts: Partial<ValidationOptions> = {}): input is T {
+    this.errors = <ValidationError[]>(
+      __blueboat_host_invoke("jsonschema_validate", this.hostSymbol, input, opts)
+    );
+    return this.errors.length === 0;
+  }
+}
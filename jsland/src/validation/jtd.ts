@@ -1,8 +1,10 @@
 import { JTDSchemaType } from "ajv/dist/core";
 import { HostObject } from "../host_object";
+import { ValidationError, ValidationOptions } from "../native_schema";
 export { JTDSchemaType } from "ajv/dist/core";
 
 export class JTDStaticSchema<T> extends HostObject {
+  errors: ValidationError[] = [];
   lastError: string | undefined;
 
   constructor(schema: JTDSchemaType<T>) {
@@ -10,10 +12,12 @@ export class JTDStaticSchema<T> extends HostObject {
     super(sym);
   }
 
-  validate(input: unknown): input is T {
-    this.lastError = <string>(
-      __blueboat_host_invoke("jtd_validate", this.hostSymbol, input)
+  // Collects up to 100 errors, or only the first with `first_error_only`.
+  validate(input: unknown, opts: Partial<ValidationOptions> = {}): input is T {
+    this.errors = <ValidationError[]>(
+      __blueboat_host_invoke("jtd_validate", this.hostSymbol, input, opts)
     );
-    return this.lastError === undefined;
+    this.lastError = this.errors.length ? this.errors[0].message : undefined;
+    return this.errors.length === 0;
   }
 }
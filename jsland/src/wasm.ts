@@ -0,0 +1,92 @@
+// Captured before `init.js` removes the global, so that apps can only reach
+// the engine through the checks below.
+const WA = WebAssembly;
+
+// Exported memories would otherwise lead back to the constructor, which makes
+// memories that are not counted against the isolate memory limit.
+delete (<any>WA.Memory.prototype).constructor;
+
+// Must match `MEMORY_EXPORT_PREFIX` in wasm.rs.
+const MEMORY_EXPORT_PREFIX = "__blueboat_memory_";
+
+export type ImportValue = Function | number | bigint | WebAssembly.Global;
+export type Imports = Record<string, Record<string, ImportValue>>;
+
+/**
+ * A compiled module. The underlying `WebAssembly.Module` is not exposed, since
+ * its constructor would compile arbitrary bytes without the checks here.
+ */
+export class Module {
+  #module: WebAssembly.Module;
+  #memoryPages: number[];
+
+  constructor(bytes: Uint8Array) {
+    const [prepared, memoryPages] = <[Uint8Array, number[]]>(
+      __blueboat_host_invoke("wasm_prepare", bytes)
+    );
+    this.#module = new WA.Module(prepared);
+    this.#memoryPages = memoryPages;
+  }
+
+  /** @internal */
+  static unwrap(module: Module): WebAssembly.Module {
+    return module.#module;
+  }
+
+  /** @internal */
+  static memoryPages(module: Module): number[] {
+    return module.#memoryPages;
+  }
+}
+
+export interface Instance {
+  exports: WebAssembly.Exports;
+}
+
+/**
+ * Compiles a WebAssembly module. Modules may only import functions and
+ * globals, and each linear memory is capped at the host's wasm memory limit.
+ * The maximum sizes of all live memories count against the isolate memory
+ * limit, and execution is terminated when they no longer fit.
+ */
+export function compile(bytes: Uint8Array): Module {
+  return new Module(bytes);
+}
+
+/**
+ * Compiles (if needed) and instantiates a module. Only the values in
+ * `imports` are visible to it; nothing from the host is linked implicitly.
+ */
+export function instantiate(
+  module: Uint8Array | Module,
+  imports: Imports = {},
+): Instance {
+  const wrapped = module instanceof Module ? module : compile(module);
+  const compiled = Module.unwrap(wrapped);
+  const linked: Imports = {};
+  for (const desc of WA.Module.imports(compiled)) {
+    const value = Object.prototype.hasOwnProperty.call(imports, desc.module)
+      ? imports[desc.module][desc.name]
+      : undefined;
+    if (value === undefined) {
+      throw new TypeError(`missing import: ${desc.module}.${desc.name}`);
+    }
+    const ok = desc.kind === "function"
+      ? typeof value === "function"
+      : typeof value === "number" || typeof value === "bigint" || value instanceof WA.Global;
+    if (!ok) {
+      throw new TypeError(`import ${desc.module}.${desc.name} must be a ${desc.kind}`);
+    }
+    (linked[desc.module] ||= {})[desc.name] = value;
+  }
+  const raw = new WA.Instance(compiled, linked).exports;
+  __blueboat_host_invoke("wasm_track_memories", raw, Module.memoryPages(wrapped));
+
+  const exports: WebAssembly.Exports = Object.create(null);
+  for (const name of Object.keys(raw)) {
+    if (!name.startsWith(MEMORY_EXPORT_PREFIX)) {
+      exports[name] = raw[name];
+    }
+  }
+  return { exports: Object.freeze(exports) };
+}
@@ -0,0 +1,40 @@
+import {
+  BlueboatBootstrapData,
+  WebPushOptions,
+  WebPushResponse,
+  WebPushSubscription,
+} from "./native_schema";
+import { wrapNativeAsync } from "./util";
+
+export interface WebPush {
+  // Encrypts and sends `payload` to a browser subscription. At most 3993 bytes. Remove the
+  // subscription when the response has `expired` set.
+  send(
+    subscription: WebPushSubscription,
+    payload: string | Uint8Array,
+    opts?: Partial<WebPushOptions>
+  ): Promise<WebPushResponse>;
+}
+
+class WebPushImpl implements WebPush {
+  constructor(private key: string) {}
+
+  send(
+    subscription: WebPushSubscription,
+    payload: string | Uint8Array,
+    opts: Partial<WebPushOptions> = {}
+  ): Promise<WebPushResponse> {
+    const body = typeof payload === "string" ? new TextEncoder().encode(payload) : payload;
+    return wrapNativeAsync((callback) =>
+      __blueboat_host_invoke("webpush_send", this.key, subscription, body, opts, callback)
+    );
+  }
+}
+
+export const webpush: Record<string, WebPush> = {};
+
+export function init(bs: BlueboatBootstrapData) {
+  for (const x of bs.webpush) {
+    webpush[x] = new WebPushImpl(x);
+  }
+}
@@ -0,0 +1,49 @@
+import { wrapNativeAsync } from "./util";
+
+export interface ConnectOptions {
+  headers?: Record<string, string>;
+  protocols?: string[];
+  timeout?: number;
+}
+
+// A WebSocket client connection. The connection is closed when the request completes.
+export class WebSocketClient {
+  private handle: number;
+  readonly protocol: string | null;
+
+  private constructor(handle: number, protocol: string | null) {
+    this.handle = handle;
+    this.protocol = protocol;
+  }
+
+  static async connect(url: string, opts: ConnectOptions = {}): Promise<WebSocketClient> {
+    const res: { handle: number, protocol: string | null } = await wrapNativeAsync(callback =>
+      __blueboat_host_invoke("websocket_connect", url, {
+        headers: opts.headers,
+        protocols: opts.protocols,
+        timeoutMs: opts.timeout,
+      }, callback)
+    );
+    return new WebSocketClient(res.handle, res.protocol);
+  }
+
+  send(data: string | Uint8Array) {
+    __blueboat_host_invoke("websocket_send", this.handle, data);
+  }
+
+  // `onMessage` receives `null` once the connection is closed. Can only be set once.
+  onMessage(onMessage: (data: string | Uint8Array | null) => void, onError?: (e: Error) => void) {
+    __blueboat_host_invoke("websocket_on_message", this.handle,
+      (err: Error | undefined, data: string | Uint8Array | null) => {
+        if (err) {
+          if (onError) onError(err);
+        } else {
+          onMessage(data);
+        }
+      });
+  }
+
+  close(code?: number, reason?: string) {
+    __blueboat_host_invoke("websocket_close", this.handle, code, reason);
+  }
+}
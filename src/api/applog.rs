@@ -0,0 +1,80 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use v8;
+
+use crate::{
+  api::util::{v8_deserialize, v8_invoke_callback, v8_serialize},
+  exec::Executor,
+  logsvc::{AppLogTailPage, APPLOG_TAIL},
+  lpch::AppLogLevel,
+  metadata::Metadata,
+  reliable_channel::RchReqBody,
+  v8util::FunctionCallbackArgumentsExt,
+};
+
+const DEFAULT_TAIL_LIMIT: usize = 100;
+const MAX_TAIL_LIMIT: usize = 1000;
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct AppLogTailOpts {
+  /// The cursor of the previous page. Omit to read the latest lines.
+  #[serde(default)]
+  after: Option<u64>,
+  #[serde(default)]
+  limit: Option<usize>,
+  #[serde(default)]
+  min_level: Option<AppLogLevel>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AppLogTailRequest {
+  after: Option<u64>,
+  limit: usize,
+  min_level: AppLogLevel,
+}
+
+#[async_trait::async_trait]
+#[typetag::serde]
+impl RchReqBody for AppLogTailRequest {
+  async fn handle(self: Box<Self>, md: Arc<Metadata>) -> Result<Box<dyn erased_serde::Serialize>> {
+    Ok(Box::new(APPLOG_TAIL.read(
+      &md.path,
+      self.after,
+      self.limit,
+      self.min_level,
+    )))
+  }
+}
+
+/// Reads recent log lines of the calling app, across all of its workers on this
+/// server.
+pub fn api_applog_tail(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  _retval: v8::ReturnValue,
+) -> Result<()> {
+  let opts: AppLogTailOpts = if args.get(1).is_null_or_undefined() {
+    Default::default()
+  } else {
+    v8_deserialize(scope, args.get(1))?
+  };
+  let callback = v8::Global::new(scope, args.load_function_at(2)?);
+  let exec = Executor::try_current_result()?;
+  let ctx = exec.upgrade().unwrap().ctx;
+  let req = AppLogTailRequest {
+    after: opts.after,
+    limit: opts.limit.unwrap_or(DEFAULT_TAIL_LIMIT).min(MAX_TAIL_LIMIT),
+    min_level: opts.min_level.unwrap_or(AppLogLevel::Debug),
+  };
+  Executor::spawn(&exec.clone(), async move {
+    let out: Result<AppLogTailPage> = ctx.rch.call(req).await;
+    Executor::enter(&exec, |scope| {
+      let res = out.and_then(|x| v8_serialize(scope, &x));
+      v8_invoke_callback("applog_tail", scope, res, &callback);
+    });
+  });
+  Ok(())
+}
@@ -0,0 +1,246 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use serde_cbor::Value;
+use thiserror::Error;
+use v8;
+
+use super::value::{be_bytes_to_words, words_to_be_bytes, CodecValue, MaxDepthExceeded, MAX_DEPTH};
+use crate::v8util::{create_uint8array_from_bytes, LocalValueExt};
+
+#[derive(Error, Debug)]
+#[error("unsupported cbor map key")]
+struct UnsupportedMapKey;
+
+const TAG_POSITIVE_BIGNUM: u64 = 2;
+const TAG_NEGATIVE_BIGNUM: u64 = 3;
+
+fn increment_words(words: &mut Vec<u64>) {
+  for w in words.iter_mut() {
+    let (x, carry) = w.overflowing_add(1);
+    *w = x;
+    if !carry {
+      return;
+    }
+  }
+  words.push(1);
+}
+
+/// `words` must be non-zero.
+fn decrement_words(words: &mut [u64]) {
+  for w in words.iter_mut() {
+    let (x, borrow) = w.overflowing_sub(1);
+    *w = x;
+    if !borrow {
+      return;
+    }
+  }
+}
+
+fn codec_value_to_cbor(v: CodecValue) -> Value {
+  match v {
+    CodecValue::Null => Value::Null,
+    CodecValue::Bool(x) => Value::Bool(x),
+    CodecValue::Integer(x) => Value::Integer(x),
+    // RFC 8949 bignums: tag 2 holds n, tag 3 holds -1 - n.
+    CodecValue::BigInt {
+      negative: false,
+      words,
+    } => Value::Tag(
+      TAG_POSITIVE_BIGNUM,
+      Box::new(Value::Bytes(words_to_be_bytes(&words))),
+    ),
+    CodecValue::BigInt {
+      negative: true,
+      mut words,
+    } => {
+      decrement_words(&mut words);
+      Value::Tag(
+        TAG_NEGATIVE_BIGNUM,
+        Box::new(Value::Bytes(words_to_be_bytes(&words))),
+      )
+    }
+    CodecValue::Float(x) => Value::Float(x),
+    CodecValue::String(x) => Value::Text(x),
+    CodecValue::Bytes(x) => Value::Bytes(x),
+    CodecValue::Array(x) => Value::Array(x.into_iter().map(codec_value_to_cbor).collect()),
+    CodecValue::Map(x) => Value::Map(
+      x.into_iter()
+        .map(|(k, v)| (Value::Text(k), codec_value_to_cbor(v)))
+        .collect::<BTreeMap<_, _>>(),
+    ),
+  }
+}
+
+fn cbor_to_codec_value(v: Value, depth: usize) -> Result<CodecValue> {
+  if depth > MAX_DEPTH {
+    return Err(MaxDepthExceeded(MAX_DEPTH).into());
+  }
+
+  Ok(match v {
+    Value::Null => CodecValue::Null,
+    Value::Bool(x) => CodecValue::Bool(x),
+    Value::Integer(x) => CodecValue::Integer(x),
+    Value::Float(x) => CodecValue::Float(x),
+    Value::Text(x) => CodecValue::String(x),
+    Value::Bytes(x) => CodecValue::Bytes(x),
+    Value::Array(x) => CodecValue::Array(
+      x.into_iter()
+        .map(|x| cbor_to_codec_value(x, depth + 1))
+        .collect::<Result<_>>()?,
+    ),
+    Value::Map(x) => CodecValue::Map(
+      x.into_iter()
+        .map(|(k, v)| {
+          let k = match k {
+            Value::Text(x) => x,
+            Value::Integer(x) => x.to_string(),
+            _ => return Err(UnsupportedMapKey.into()),
+          };
+          Ok((k, cbor_to_codec_value(v, depth + 1)?))
+        })
+        .collect::<Result<_>>()?,
+    ),
+    Value::Tag(tag, x) => match (tag, *x) {
+      (TAG_POSITIVE_BIGNUM, Value::Bytes(x)) => {
+        CodecValue::from_sign_and_words(false, be_bytes_to_words(&x))
+      }
+      (TAG_NEGATIVE_BIGNUM, Value::Bytes(x)) => {
+        let mut words = be_bytes_to_words(&x);
+        increment_words(&mut words);
+        CodecValue::from_sign_and_words(true, words)
+      }
+      // Other tags carry no meaning for JS consumers - keep the tagged value.
+      (_, x) => cbor_to_codec_value(x, depth + 1)?,
+    },
+    _ => CodecValue::Null,
+  })
+}
+
+pub fn api_codec_cbor_encode(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let value = CodecValue::from_v8(scope, args.get(1))?;
+  let out = serde_cbor::to_vec(&codec_value_to_cbor(value))?;
+  retval.set(create_uint8array_from_bytes(scope, &out).into());
+  Ok(())
+}
+
+pub fn api_codec_cbor_decode(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let data = unsafe { args.get(1).read_bytes_assume_noalias(scope)? };
+  // serde_cbor enforces its own recursion limit while parsing, so the parser cannot overflow the
+  // stack before we get to check the depth here.
+  let value: Value = serde_cbor::from_slice(&data)?;
+  let value = cbor_to_codec_value(value, 0)?;
+  retval.set(value.to_v8(scope)?);
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::api::testutil::ApiTester;
+
+  #[test]
+  fn test_cbor_roundtrip() {
+    let mut tester = ApiTester::new();
+    let out: String = tester.run_script(
+      r#"
+      const v = Codec.CBOR.decode(Codec.CBOR.encode({ a: [1, 2.5, "x", null, true], b: new Uint8Array([1, 2]) }));
+      JSON.stringify([v.a, v.b instanceof Uint8Array, Array.from(v.b)]);
+      "#,
+    );
+    assert_eq!(out, r#"[[1,2.5,"x",null,true],true,[1,2]]"#);
+  }
+
+  #[test]
+  fn test_cbor_bigint_roundtrip() {
+    let mut tester = ApiTester::new();
+    let out: String = tester.run_script(
+      r#"
+      const values = [2n ** 63n, -(2n ** 64n), 2n ** 64n, -(2n ** 64n) - 1n, 3n ** 100n, -(2n ** 200n)];
+      const decoded = Codec.CBOR.decode(Codec.CBOR.encode(values));
+      JSON.stringify([
+        decoded.map((x, i) => typeof x === "bigint" && x === values[i]),
+        Array.from(Codec.CBOR.encode(2n ** 64n)),
+        Array.from(Codec.CBOR.encode(-(2n ** 64n) - 1n)),
+      ]);
+      "#,
+    );
+    assert_eq!(
+      out,
+      r#"[[true,true,true,true,true,true],[194,73,1,0,0,0,0,0,0,0,0],[195,73,1,0,0,0,0,0,0,0,0]]"#
+    );
+  }
+
+  #[test]
+  fn test_cbor_decode_vectors() {
+    // Examples from RFC 8949 Appendix A.
+    let mut tester = ApiTester::new();
+    let out: String = tester.run_script(
+      r#"
+      const decode = (bytes) => Codec.CBOR.decode(new Uint8Array(bytes));
+      JSON.stringify([
+        decode([0x1b, 0, 0, 0, 0xe8, 0xd4, 0xa5, 0x10, 0]),
+        decode([0x39, 0x03, 0xe7]),
+        decode([0xf9, 0x3c, 0x00]),
+        decode([0xfb, 0x3f, 0xf1, 0x99, 0x99, 0x99, 0x99, 0x99, 0x9a]),
+        decode([0x62, 0xc3, 0xbc]),
+        decode([0xa2, 0x01, 0x02, 0x03, 0x04]),
+        decode([0xa2, 0x61, 0x61, 0x01, 0x61, 0x62, 0x82, 0x02, 0x03]),
+        decode([0xc1, 0x1a, 0x51, 0x4b, 0x67, 0xb0]),
+        decode([0xc2, 0x49, 1, 0, 0, 0, 0, 0, 0, 0, 0]) === 2n ** 64n,
+        decode([0xc3, 0x49, 1, 0, 0, 0, 0, 0, 0, 0, 0]) === -(2n ** 64n) - 1n,
+        Array.from(decode([0x44, 1, 2, 3, 4])),
+      ]);
+      "#,
+    );
+    assert_eq!(
+      out,
+      r#"[1000000000000,-1000,1,1.1,"ü",{"1":2,"3":4},{"a":1,"b":[2,3]},1363896240,true,true,[1,2,3,4]]"#
+    );
+  }
+
+  #[test]
+  fn test_cbor_rejects_deep_nesting() {
+    let mut tester = ApiTester::new();
+    let out: bool = tester.run_script(
+      r#"
+      let v = [];
+      for (let i = 0; i < 100; i++) v = [v];
+      let failed = false;
+      try { Codec.CBOR.encode(v); } catch(e) { failed = true; }
+      failed;
+      "#,
+    );
+    assert!(out);
+  }
+
+  #[test]
+  fn test_cbor_decode_rejects_deep_nesting() {
+    // Past our depth cap but within serde_cbor's recursion limit, and far past both.
+    let mut tester = ApiTester::new();
+    let out: Vec<String> = tester.run_script(
+      r#"
+      [100, 1000000].map((depth) => {
+        const bytes = new Uint8Array(depth + 1).fill(0x81);
+        bytes[depth] = 0x80;
+        try {
+          Codec.CBOR.decode(bytes);
+          return "decoded";
+        } catch (e) {
+          return e.message;
+        }
+      });
+      "#,
+    );
+    assert_eq!(out.len(), 2);
+    assert!(out[0].contains("value nesting exceeds maximum depth of 64"));
+    assert!(out[1] != "decoded");
+  }
+}
@@ -0,0 +1,190 @@
+use std::io::{Read, Write};
+
+use anyhow::Result;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use thiserror::Error;
+use v8;
+
+use crate::{
+  api::util::v8_deserialize,
+  v8util::{create_uint8array_from_bytes, LocalValueExt},
+};
+
+/// Upper bound on the output of `codec_decompress` and of decoded `fetch` responses. Callers may
+/// request a lower limit.
+pub const MAX_DECOMPRESSED_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(Error, Debug)]
+#[error("decompressed size exceeds limit of {0} bytes")]
+struct DecompressedSizeLimitExceeded(usize);
+
+#[derive(Error, Debug)]
+#[error("invalid compression level {level} for {alg}")]
+struct InvalidCompressionLevel {
+  alg: &'static str,
+  level: i32,
+}
+
+/// `deflate` follows the HTTP `Content-Encoding` semantics, i.e. zlib-wrapped deflate.
+#[derive(Deserialize, JsonSchema, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum CodecCompressAlgorithm {
+  Gzip,
+  Deflate,
+  Brotli,
+  Zstd,
+}
+
+impl CodecCompressAlgorithm {
+  fn name(&self) -> &'static str {
+    match self {
+      Self::Gzip => "gzip",
+      Self::Deflate => "deflate",
+      Self::Brotli => "brotli",
+      Self::Zstd => "zstd",
+    }
+  }
+
+  /// Maps an HTTP content coding to the algorithm decoding it.
+  pub fn from_content_coding(coding: &str) -> Option<Self> {
+    match coding.to_ascii_lowercase().as_str() {
+      "gzip" | "x-gzip" => Some(Self::Gzip),
+      "deflate" => Some(Self::Deflate),
+      "br" => Some(Self::Brotli),
+      "zstd" => Some(Self::Zstd),
+      _ => None,
+    }
+  }
+
+  fn resolve_level(&self, level: Option<i32>) -> Result<i32> {
+    let (default, range) = match self {
+      Self::Gzip | Self::Deflate => (6, 0..=9),
+      Self::Brotli => (5, 0..=11),
+      Self::Zstd => (3, 1..=22),
+    };
+    let level = level.unwrap_or(default);
+    if !range.contains(&level) {
+      return Err(
+        InvalidCompressionLevel {
+          alg: self.name(),
+          level,
+        }
+        .into(),
+      );
+    }
+    Ok(level)
+  }
+
+  fn compress(&self, level: i32, data: &[u8]) -> Result<Vec<u8>> {
+    Ok(match self {
+      Self::Gzip => {
+        let mut enc =
+          flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::new(level as u32));
+        enc.write_all(data)?;
+        enc.finish()?
+      }
+      Self::Deflate => {
+        let mut enc =
+          flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::new(level as u32));
+        enc.write_all(data)?;
+        enc.finish()?
+      }
+      Self::Brotli => {
+        let mut out = Vec::new();
+        {
+          let mut enc = brotli::CompressorWriter::new(&mut out, 4096, level as u32, 22);
+          enc.write_all(data)?;
+        }
+        out
+      }
+      Self::Zstd => zstd::stream::encode_all(data, level)?,
+    })
+  }
+
+  pub fn decompress(&self, data: &[u8], limit: usize) -> Result<Vec<u8>> {
+    let reader: Box<dyn Read + '_> = match self {
+      Self::Gzip => Box::new(flate2::read::MultiGzDecoder::new(data)),
+      Self::Deflate => Box::new(flate2::read::ZlibDecoder::new(data)),
+      Self::Brotli => Box::new(brotli::Decompressor::new(data, 4096)),
+      Self::Zstd => Box::new(zstd::stream::read::Decoder::new(data)?),
+    };
+
+    // Read one byte past the limit so that we can tell "exactly at limit" from "too large".
+    let mut out = Vec::new();
+    reader.take(limit as u64 + 1).read_to_end(&mut out)?;
+    if out.len() > limit {
+      return Err(DecompressedSizeLimitExceeded(limit).into());
+    }
+    Ok(out)
+  }
+}
+
+pub fn api_codec_compress(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let alg: CodecCompressAlgorithm = v8_deserialize(scope, args.get(1))?;
+  let level: Option<i32> = v8_deserialize(scope, args.get(2))?;
+  let level = alg.resolve_level(level)?;
+  let data = unsafe { args.get(3).read_bytes_assume_noalias(scope)? };
+  let out = alg.compress(level, &data)?;
+  retval.set(create_uint8array_from_bytes(scope, &out).into());
+  Ok(())
+}
+
+pub fn api_codec_decompress(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let alg: CodecCompressAlgorithm = v8_deserialize(scope, args.get(1))?;
+  let data = unsafe { args.get(2).read_bytes_assume_noalias(scope)? };
+  let limit: Option<usize> = v8_deserialize(scope, args.get(3))?;
+  let limit = limit
+    .unwrap_or(MAX_DECOMPRESSED_SIZE)
+    .min(MAX_DECOMPRESSED_SIZE);
+  let out = alg.decompress(&data, limit)?;
+  retval.set(create_uint8array_from_bytes(scope, &out).into());
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::api::testutil::ApiTester;
+
+  #[test]
+  fn test_compress_roundtrip() {
+    for alg in ["gzip", "deflate", "brotli", "zstd"] {
+      let mut tester = ApiTester::new();
+      let out: String = tester.run_script(&format!(
+        r#"
+        const input = "hello ".repeat(1000);
+        const compressed = Codec.compress("{alg}", input);
+        new TextDecoder().decode(Codec.decompress("{alg}", compressed));
+        "#,
+        alg = alg
+      ));
+      assert_eq!(out, "hello ".repeat(1000));
+    }
+  }
+
+  #[test]
+  fn test_decompress_limit() {
+    let mut tester = ApiTester::new();
+    let out: bool = tester.run_script(
+      r#"
+      const compressed = Codec.compress("gzip", new Uint8Array(4096));
+      let failed = false;
+      try {
+        Codec.decompress("gzip", compressed, { maxOutputSize: 4095 });
+      } catch(e) {
+        failed = true;
+      }
+      failed && Codec.decompress("gzip", compressed, { maxOutputSize: 4096 }).length === 4096;
+      "#,
+    );
+    assert!(out);
+  }
+}
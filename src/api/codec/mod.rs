@@ -1,10 +1,17 @@
+pub mod cbor;
+pub mod compression;
+pub mod msgpack;
 pub mod multipart;
+pub mod percent;
+pub mod structured_clone;
+pub mod value;
 
 use super::util::v8_deserialize;
 use crate::{api::util::ArrayBufferBuilder, v8util::LocalValueExt};
 use anyhow::Result;
 use schemars::JsonSchema;
 use serde::Deserialize;
+use thiserror::Error;
 use v8;
 
 #[derive(Deserialize, JsonSchema)]
@@ -28,6 +35,31 @@ impl CodecBase64Mode {
       Self::UrlsafeNopad => base64::URL_SAFE_NO_PAD,
     }
   }
+
+  /// Decoding only cares about the alphabet - padding is optional in every mode.
+  fn build_decode_config(&self) -> base64::Config {
+    match self {
+      Self::Standard | Self::StandardNopad => base64::STANDARD_NO_PAD,
+      Self::Urlsafe | Self::UrlsafeNopad => base64::URL_SAFE_NO_PAD,
+    }
+  }
+}
+
+#[derive(Error, Debug)]
+#[error("invalid base64 padding")]
+struct InvalidBase64Padding;
+
+/// Strips the padding from `data`. Padded input must have one or two `=` and a length that is a
+/// multiple of 4.
+fn strip_b64_padding(data: &[u8]) -> Result<&[u8]> {
+  let pad = data.iter().rev().take_while(|&&x| x == b'=').count();
+  if pad == 0 {
+    return Ok(data);
+  }
+  if pad > 2 || data.len() % 4 != 0 {
+    return Err(InvalidBase64Padding.into());
+  }
+  Ok(&data[..data.len() - pad])
 }
 
 pub fn api_codec_hexencode(
@@ -99,11 +131,61 @@ pub fn api_codec_b64decode(
   mut retval: v8::ReturnValue,
 ) -> Result<()> {
   let data = unsafe { args.get(1).read_bytes_assume_noalias(scope)? };
-  let data = &data[..];
+  let data = strip_b64_padding(&data[..])?;
   let mode: CodecBase64Mode = v8_deserialize(scope, args.get(2))?;
   let mut out = ArrayBufferBuilder::new(scope, (data.len() + 3) / 4 * 3);
 
-  let n = base64::decode_config_slice(data, mode.build_config(), &mut out)?;
+  let n = base64::decode_config_slice(data, mode.build_decode_config(), &mut out)?;
   retval.set(out.build_uint8array(scope, Some(n)).into());
   Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+  use crate::api::testutil::ApiTester;
+
+  #[test]
+  fn test_b64_urlsafe() {
+    let mut tester = ApiTester::new();
+
+    let out: String =
+      tester.run_script(r#"Codec.b64encode(new Uint8Array([251, 255]), "urlsafe");"#);
+    assert_eq!(out.as_str(), "-_8=");
+
+    let out: String =
+      tester.run_script(r#"Codec.b64encode(new Uint8Array([251, 255]), "urlsafe-nopad");"#);
+    assert_eq!(out.as_str(), "-_8");
+
+    // Both padded and unpadded input decode in either url-safe mode
+    for mode in ["urlsafe", "urlsafe-nopad"] {
+      for input in ["-_8=", "-_8"] {
+        let out: Vec<u8> = tester.run_script(&format!(
+          r#"Array.from(Codec.b64decode("{}", "{}"));"#,
+          input, mode
+        ));
+        assert_eq!(out, vec![251, 255]);
+      }
+    }
+  }
+
+  #[test]
+  fn test_b64_padding() {
+    let mut tester = ApiTester::new();
+    let out: String = tester.run_script(
+      r#"
+      const decode = (x) => {
+        try {
+          return Array.from(Codec.b64decode(x, "standard")).join(",");
+        } catch (e) {
+          return "error";
+        }
+      };
+      JSON.stringify(["+w==", "+w", "+/8=", "AAAA", "", "+/8==", "+w=", "+w===", "===="].map(decode));
+      "#,
+    );
+    assert_eq!(
+      out,
+      r#"["251","251","251,255","0,0,0","","error","error","error","error"]"#
+    );
+  }
+}
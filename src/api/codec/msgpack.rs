@@ -0,0 +1,292 @@
+use std::convert::TryFrom;
+
+use anyhow::Result;
+use byteorder::{BigEndian, ReadBytesExt};
+use rmp::Marker;
+use thiserror::Error;
+use v8;
+
+use super::value::{CodecValue, MaxDepthExceeded, MAX_DEPTH};
+use crate::v8util::{create_uint8array_from_bytes, LocalValueExt};
+
+#[derive(Error, Debug)]
+#[error("unsupported msgpack type: {0}")]
+struct UnsupportedType(&'static str);
+
+#[derive(Error, Debug)]
+#[error("unexpected end of msgpack input")]
+struct UnexpectedEof;
+
+#[derive(Error, Debug)]
+#[error("trailing bytes after msgpack value")]
+struct TrailingBytes;
+
+#[derive(Error, Debug)]
+#[error("value too large for msgpack")]
+struct ValueTooLarge;
+
+#[derive(Error, Debug)]
+#[error("integer out of msgpack range")]
+struct IntegerOutOfRange;
+
+#[derive(Error, Debug)]
+#[error("unsupported msgpack map key")]
+struct UnsupportedMapKey;
+
+fn encode_len(len: usize) -> Result<u32> {
+  u32::try_from(len).map_err(|_| ValueTooLarge.into())
+}
+
+fn encode_value(out: &mut Vec<u8>, v: &CodecValue) -> Result<()> {
+  match v {
+    CodecValue::Null => rmp::encode::write_nil(out)?,
+    CodecValue::Bool(x) => rmp::encode::write_bool(out, *x)?,
+    CodecValue::Integer(x) => {
+      if let Ok(x) = i64::try_from(*x) {
+        rmp::encode::write_sint(out, x)?;
+      } else if let Ok(x) = u64::try_from(*x) {
+        rmp::encode::write_uint(out, x)?;
+      } else {
+        return Err(IntegerOutOfRange.into());
+      }
+    }
+    // MessagePack has no integer type wider than 64 bits.
+    CodecValue::BigInt { .. } => return Err(IntegerOutOfRange.into()),
+    CodecValue::Float(x) => rmp::encode::write_f64(out, *x)?,
+    CodecValue::String(x) => rmp::encode::write_str(out, x)?,
+    CodecValue::Bytes(x) => rmp::encode::write_bin(out, x)?,
+    CodecValue::Array(x) => {
+      rmp::encode::write_array_len(out, encode_len(x.len())?)?;
+      for x in x {
+        encode_value(out, x)?;
+      }
+    }
+    CodecValue::Map(x) => {
+      rmp::encode::write_map_len(out, encode_len(x.len())?)?;
+      for (k, v) in x {
+        rmp::encode::write_str(out, k)?;
+        encode_value(out, v)?;
+      }
+    }
+  }
+  Ok(())
+}
+
+fn take<'a>(rd: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+  if rd.len() < len {
+    return Err(UnexpectedEof.into());
+  }
+  let (head, tail) = rd.split_at(len);
+  *rd = tail;
+  Ok(head)
+}
+
+fn decode_string(rd: &mut &[u8], len: usize) -> Result<String> {
+  Ok(std::str::from_utf8(take(rd, len)?)?.to_string())
+}
+
+fn decode_array(rd: &mut &[u8], len: usize, depth: usize) -> Result<CodecValue> {
+  // Every element takes at least one byte - don't trust `len` for preallocation beyond that.
+  let mut out = Vec::with_capacity(len.min(rd.len()));
+  for _ in 0..len {
+    out.push(decode_value(rd, depth + 1)?);
+  }
+  Ok(CodecValue::Array(out))
+}
+
+fn decode_map(rd: &mut &[u8], len: usize, depth: usize) -> Result<CodecValue> {
+  let mut out = Vec::with_capacity(len.min(rd.len() / 2));
+  for _ in 0..len {
+    let k = match decode_value(rd, depth + 1)? {
+      CodecValue::String(x) => x,
+      CodecValue::Integer(x) => x.to_string(),
+      _ => return Err(UnsupportedMapKey.into()),
+    };
+    let v = decode_value(rd, depth + 1)?;
+    out.push((k, v));
+  }
+  Ok(CodecValue::Map(out))
+}
+
+fn decode_value(rd: &mut &[u8], depth: usize) -> Result<CodecValue> {
+  if depth > MAX_DEPTH {
+    return Err(MaxDepthExceeded(MAX_DEPTH).into());
+  }
+
+  let marker = Marker::from_u8(rd.read_u8().map_err(|_| UnexpectedEof)?);
+  Ok(match marker {
+    Marker::Null => CodecValue::Null,
+    Marker::True => CodecValue::Bool(true),
+    Marker::False => CodecValue::Bool(false),
+    Marker::FixPos(x) => CodecValue::Integer(x as i128),
+    Marker::FixNeg(x) => CodecValue::Integer(x as i128),
+    Marker::U8 => CodecValue::Integer(rd.read_u8()? as i128),
+    Marker::U16 => CodecValue::Integer(rd.read_u16::<BigEndian>()? as i128),
+    Marker::U32 => CodecValue::Integer(rd.read_u32::<BigEndian>()? as i128),
+    Marker::U64 => CodecValue::Integer(rd.read_u64::<BigEndian>()? as i128),
+    Marker::I8 => CodecValue::Integer(rd.read_i8()? as i128),
+    Marker::I16 => CodecValue::Integer(rd.read_i16::<BigEndian>()? as i128),
+    Marker::I32 => CodecValue::Integer(rd.read_i32::<BigEndian>()? as i128),
+    Marker::I64 => CodecValue::Integer(rd.read_i64::<BigEndian>()? as i128),
+    Marker::F32 => CodecValue::Float(rd.read_f32::<BigEndian>()? as f64),
+    Marker::F64 => CodecValue::Float(rd.read_f64::<BigEndian>()?),
+    Marker::FixStr(len) => CodecValue::String(decode_string(rd, len as usize)?),
+    Marker::Str8 => {
+      let len = rd.read_u8()? as usize;
+      CodecValue::String(decode_string(rd, len)?)
+    }
+    Marker::Str16 => {
+      let len = rd.read_u16::<BigEndian>()? as usize;
+      CodecValue::String(decode_string(rd, len)?)
+    }
+    Marker::Str32 => {
+      let len = rd.read_u32::<BigEndian>()? as usize;
+      CodecValue::String(decode_string(rd, len)?)
+    }
+    Marker::Bin8 => {
+      let len = rd.read_u8()? as usize;
+      CodecValue::Bytes(take(rd, len)?.to_vec())
+    }
+    Marker::Bin16 => {
+      let len = rd.read_u16::<BigEndian>()? as usize;
+      CodecValue::Bytes(take(rd, len)?.to_vec())
+    }
+    Marker::Bin32 => {
+      let len = rd.read_u32::<BigEndian>()? as usize;
+      CodecValue::Bytes(take(rd, len)?.to_vec())
+    }
+    Marker::FixArray(len) => decode_array(rd, len as usize, depth)?,
+    Marker::Array16 => {
+      let len = rd.read_u16::<BigEndian>()? as usize;
+      decode_array(rd, len, depth)?
+    }
+    Marker::Array32 => {
+      let len = rd.read_u32::<BigEndian>()? as usize;
+      decode_array(rd, len, depth)?
+    }
+    Marker::FixMap(len) => decode_map(rd, len as usize, depth)?,
+    Marker::Map16 => {
+      let len = rd.read_u16::<BigEndian>()? as usize;
+      decode_map(rd, len, depth)?
+    }
+    Marker::Map32 => {
+      let len = rd.read_u32::<BigEndian>()? as usize;
+      decode_map(rd, len, depth)?
+    }
+    Marker::FixExt1
+    | Marker::FixExt2
+    | Marker::FixExt4
+    | Marker::FixExt8
+    | Marker::FixExt16
+    | Marker::Ext8
+    | Marker::Ext16
+    | Marker::Ext32 => return Err(UnsupportedType("ext").into()),
+    Marker::Reserved => return Err(UnsupportedType("reserved").into()),
+  })
+}
+
+pub fn api_codec_msgpack_encode(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let value = CodecValue::from_v8(scope, args.get(1))?;
+  let mut out = Vec::new();
+  encode_value(&mut out, &value)?;
+  retval.set(create_uint8array_from_bytes(scope, &out).into());
+  Ok(())
+}
+
+pub fn api_codec_msgpack_decode(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let data = unsafe { args.get(1).read_bytes_assume_noalias(scope)? };
+  let mut rd = &data[..];
+  let value = decode_value(&mut rd, 0)?;
+  if !rd.is_empty() {
+    return Err(TrailingBytes.into());
+  }
+  retval.set(value.to_v8(scope)?);
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{decode_value, encode_value};
+  use crate::api::{codec::value::CodecValue, testutil::ApiTester};
+
+  #[test]
+  fn test_msgpack_value_roundtrip() {
+    let value = CodecValue::Map(vec![
+      (
+        "list".into(),
+        CodecValue::Array(vec![
+          CodecValue::Integer(1),
+          CodecValue::Integer(-300),
+          CodecValue::Integer(u64::MAX as i128),
+          CodecValue::Float(1.5),
+          CodecValue::Float(2.0),
+          CodecValue::Null,
+        ]),
+      ),
+      (
+        "nested".into(),
+        CodecValue::Map(vec![
+          ("s".into(), CodecValue::String("hello".into())),
+          ("b".into(), CodecValue::Bytes(vec![0, 1, 2])),
+          ("t".into(), CodecValue::Bool(true)),
+        ]),
+      ),
+    ]);
+    let mut buf = Vec::new();
+    encode_value(&mut buf, &value).unwrap();
+    let mut rd = &buf[..];
+    // Integer/float distinction is preserved: 2.0 stays a float.
+    assert_eq!(decode_value(&mut rd, 0).unwrap(), value);
+    assert!(rd.is_empty());
+  }
+
+  #[test]
+  fn test_msgpack_js_roundtrip() {
+    let mut tester = ApiTester::new();
+    let out: String = tester.run_script(
+      r#"
+      const input = { a: [1, [2, [3, { b: "c" }]]], d: { e: { f: -1.25 } }, g: new Uint8Array([7, 8]) };
+      const encoded = Codec.MessagePack.encode(input);
+      // 0xc4 is the bin8 marker, 0x02 the length
+      const hasBin = encoded.some((x, i) => x === 0xc4 && encoded[i + 1] === 2 && encoded[i + 2] === 7);
+      const v = Codec.MessagePack.decode(encoded);
+      JSON.stringify([v.a, v.d, Array.from(v.g), v.g instanceof Uint8Array, hasBin]);
+      "#,
+    );
+    assert_eq!(
+      out,
+      r#"[[1,[2,[3,{"b":"c"}]]],{"e":{"f":-1.25}},[7,8],true,true]"#
+    );
+  }
+
+  #[test]
+  fn test_msgpack_bigint() {
+    let mut tester = ApiTester::new();
+    let out: String = tester.run_script(
+      r#"
+      const values = [2n ** 53n + 1n, 2n ** 64n - 1n, -(2n ** 63n)];
+      const decoded = Codec.MessagePack.decode(Codec.MessagePack.encode(values));
+      let msg = "";
+      try { Codec.MessagePack.encode(2n ** 64n); } catch(e) { msg = String(e); }
+      JSON.stringify([decoded.map((x, i) => x === values[i]), msg.includes("out of msgpack range")]);
+      "#,
+    );
+    assert_eq!(out, "[[true,true,true],true]");
+  }
+
+  #[test]
+  fn test_msgpack_rejects_deep_nesting() {
+    // 100 nested fixarrays of length 1
+    let mut buf = vec![0x91u8; 100];
+    buf.push(0xc0);
+    assert!(decode_value(&mut &buf[..], 0).is_err());
+  }
+}
@@ -4,14 +4,47 @@ use anyhow::Result;
 use bytes::Bytes;
 use futures::stream::once;
 use multer::Multipart;
+use rand::Rng;
 use serde::Serialize;
+use thiserror::Error;
 use v8;
 
 use crate::{
   api::util::{v8_deref_typed_array_assuming_noalias, v8_serialize},
-  v8util::create_uint8array_from_bytes,
+  v8util::{create_uint8array_from_bytes, LocalValueExt, ObjectExt},
 };
 
+const MAX_BOUNDARY_LENGTH: usize = 70;
+
+#[derive(Error, Debug)]
+#[error("invalid multipart boundary")]
+struct InvalidBoundary;
+
+#[derive(Error, Debug)]
+#[error("multipart boundary occurs in the content of part {0}")]
+struct BoundaryCollision(usize);
+
+#[derive(Error, Debug)]
+#[error("invalid content type for part {0}")]
+struct InvalidContentType(usize);
+
+#[derive(Error, Debug)]
+#[error("invalid multipart part at index {0}")]
+struct InvalidPart(usize);
+
+struct CodecMultipartPart {
+  name: String,
+  file_name: Option<String>,
+  content_type: Option<String>,
+  body: Vec<u8>,
+}
+
+#[derive(Serialize)]
+pub struct CodecMultipartEncodeOutput<'s> {
+  content_type: String,
+  body: serde_v8::Value<'s>,
+}
+
 #[derive(Serialize)]
 pub struct CodecMultipartData<'s> {
   name: Option<String>,
@@ -53,3 +86,196 @@ pub fn api_codec_multipart_decode(
   retval.set(res);
   Ok(())
 }
+
+/// RFC 2046 `bchars`, without the trailing-space special case.
+fn is_valid_boundary(boundary: &str) -> bool {
+  !boundary.is_empty()
+    && boundary.len() <= MAX_BOUNDARY_LENGTH
+    && boundary.bytes().all(|x| {
+      x.is_ascii_alphanumeric()
+        || matches!(
+          x,
+          b'\'' | b'(' | b')' | b'+' | b'_' | b',' | b'-' | b'.' | b'/' | b':' | b'=' | b'?'
+        )
+    })
+}
+
+fn generate_boundary() -> String {
+  let mut rand_bytes = [0u8; 16];
+  rand::thread_rng().fill(&mut rand_bytes[..]);
+  format!("----BlueboatFormBoundary{}", hex::encode(&rand_bytes))
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+  haystack.windows(needle.len()).any(|x| x == needle)
+}
+
+/// Escapes a `Content-Disposition` parameter value the way browsers do.
+fn escape_disposition_param(value: &str) -> String {
+  value
+    .replace('"', "%22")
+    .replace('\r', "%0D")
+    .replace('\n', "%0A")
+}
+
+fn read_parts(
+  scope: &mut v8::HandleScope,
+  parts: v8::Local<v8::Value>,
+) -> Result<Vec<CodecMultipartPart>> {
+  let parts = v8::Local::<v8::Array>::try_from(parts)?;
+  let mut out = Vec::with_capacity(parts.length() as usize);
+  for i in 0..parts.length() {
+    let part = parts.get_index(scope, i).ok_or(InvalidPart(i as usize))?;
+    let part = v8::Local::<v8::Object>::try_from(part).map_err(|_| InvalidPart(i as usize))?;
+    let name = part.get_ext(scope, "name");
+    if !name.is_string() {
+      return Err(InvalidPart(i as usize).into());
+    }
+    let name = name.to_rust_string_lossy(scope);
+    let file_name = part.get_ext(scope, "file_name");
+    let file_name = if file_name.is_null_or_undefined() {
+      None
+    } else {
+      Some(file_name.to_rust_string_lossy(scope))
+    };
+    let content_type = part.get_ext(scope, "content_type");
+    let content_type = if content_type.is_null_or_undefined() {
+      None
+    } else {
+      Some(content_type.to_rust_string_lossy(scope))
+    };
+    let body = part.get_ext(scope, "body");
+    let body = unsafe { body.read_bytes_assume_noalias(scope)? }.to_vec();
+    out.push(CodecMultipartPart {
+      name,
+      file_name,
+      content_type,
+      body,
+    });
+  }
+  Ok(out)
+}
+
+fn encode_parts(parts: &[CodecMultipartPart], boundary: &str) -> Result<Vec<u8>> {
+  let delimiter = format!("--{}", boundary);
+  let mut out: Vec<u8> = Vec::new();
+  for (i, part) in parts.iter().enumerate() {
+    if contains(&part.body, delimiter.as_bytes()) {
+      return Err(BoundaryCollision(i).into());
+    }
+
+    out.extend_from_slice(delimiter.as_bytes());
+    out.extend_from_slice(b"\r\n");
+    out.extend_from_slice(
+      format!(
+        "Content-Disposition: form-data; name=\"{}\"",
+        escape_disposition_param(&part.name)
+      )
+      .as_bytes(),
+    );
+    if let Some(file_name) = &part.file_name {
+      out.extend_from_slice(
+        format!("; filename=\"{}\"", escape_disposition_param(file_name)).as_bytes(),
+      );
+    }
+    out.extend_from_slice(b"\r\n");
+
+    let content_type = match (&part.content_type, &part.file_name) {
+      (Some(x), _) => Some(x.as_str()),
+      (None, Some(_)) => Some("application/octet-stream"),
+      (None, None) => None,
+    };
+    if let Some(content_type) = content_type {
+      if content_type.contains(|x| x == '\r' || x == '\n') {
+        return Err(InvalidContentType(i).into());
+      }
+      out.extend_from_slice(format!("Content-Type: {}\r\n", content_type).as_bytes());
+    }
+    out.extend_from_slice(b"\r\n");
+    out.extend_from_slice(&part.body);
+    out.extend_from_slice(b"\r\n");
+  }
+  out.extend_from_slice(format!("{}--\r\n", delimiter).as_bytes());
+  Ok(out)
+}
+
+pub fn api_codec_multipart_encode(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let parts = read_parts(scope, args.get(1))?;
+  let boundary = args.get(2);
+
+  let (boundary, body) = if boundary.is_null_or_undefined() {
+    // A collision with 128 random bits is practically impossible, but retry anyway to be safe.
+    let mut attempt = 0;
+    loop {
+      let boundary = generate_boundary();
+      match encode_parts(&parts, &boundary) {
+        Ok(x) => break (boundary, x),
+        Err(e) if e.is::<BoundaryCollision>() && attempt < 3 => {
+          attempt += 1;
+        }
+        Err(e) => return Err(e),
+      }
+    }
+  } else {
+    let boundary = boundary.to_rust_string_lossy(scope);
+    if !is_valid_boundary(&boundary) {
+      return Err(InvalidBoundary.into());
+    }
+    let body = encode_parts(&parts, &boundary)?;
+    (boundary, body)
+  };
+
+  let body = create_uint8array_from_bytes(scope, &body);
+  let out = CodecMultipartEncodeOutput {
+    content_type: format!("multipart/form-data; boundary={}", boundary),
+    body: v8::Local::<v8::Value>::from(body).into(),
+  };
+  retval.set(v8_serialize(scope, &out)?);
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::api::testutil::ApiTester;
+
+  #[test]
+  fn test_multipart_roundtrip() {
+    let mut tester = ApiTester::new();
+    let out: String = tester.run_script(
+      r#"
+      const { body, content_type } = Codec.Multipart.encode([
+        { name: "text", body: "hello" },
+        { name: "file", file_name: "a\"b.bin", body: new Uint8Array([1, 2, 3]) },
+      ]);
+      const boundary = content_type.split("boundary=")[1];
+      const fields = Codec.Multipart.decode(body, boundary);
+      JSON.stringify(fields.map(x => [x.name, x.file_name, x.content_type, Array.from(x.body)]));
+      "#,
+    );
+    assert_eq!(
+      out,
+      r#"[["text",null,null,[104,101,108,108,111]],["file","a%22b.bin","application/octet-stream",[1,2,3]]]"#
+    );
+  }
+
+  #[test]
+  fn test_multipart_boundary_collision() {
+    let mut tester = ApiTester::new();
+    let out: bool = tester.run_script(
+      r#"
+      let failed = false;
+      try {
+        Codec.Multipart.encode([{ name: "x", body: "--abc" }], "abc");
+      } catch(e) {
+        failed = true;
+      }
+      failed;
+      "#,
+    );
+    assert!(out);
+  }
+}
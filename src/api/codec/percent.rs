@@ -0,0 +1,145 @@
+use anyhow::Result;
+use percent_encoding::{percent_decode, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use thiserror::Error;
+use v8;
+
+use crate::{
+  api::util::{mk_v8_string, v8_deserialize},
+  v8util::LocalValueExt,
+};
+
+/// RFC 3986 `unreserved`: everything except these is encoded.
+const COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+  .remove(b'-')
+  .remove(b'.')
+  .remove(b'_')
+  .remove(b'~');
+
+/// RFC 3986 `pchar` = unreserved / sub-delims / ":" / "@".
+const PATH_SEGMENT: &AsciiSet = &COMPONENT
+  .remove(b'!')
+  .remove(b'$')
+  .remove(b'&')
+  .remove(b'\'')
+  .remove(b'(')
+  .remove(b')')
+  .remove(b'*')
+  .remove(b'+')
+  .remove(b',')
+  .remove(b';')
+  .remove(b'=')
+  .remove(b':')
+  .remove(b'@');
+
+/// RFC 3986 `query` = *( pchar / "/" / "?" ).
+const QUERY: &AsciiSet = &PATH_SEGMENT.remove(b'/').remove(b'?');
+
+/// Everything allowed in a URI: unreserved and reserved characters.
+const URI: &AsciiSet = &QUERY.remove(b'#').remove(b'[').remove(b']');
+
+#[derive(Error, Debug)]
+#[error("malformed percent-encoding at offset {0}")]
+struct MalformedPercentEncoding(usize);
+
+#[derive(Deserialize, JsonSchema, Copy, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum CodecUrlEncodeMode {
+  Component,
+  PathSegment,
+  Query,
+  Uri,
+}
+
+impl CodecUrlEncodeMode {
+  fn ascii_set(&self) -> &'static AsciiSet {
+    match self {
+      Self::Component => COMPONENT,
+      Self::PathSegment => PATH_SEGMENT,
+      Self::Query => QUERY,
+      Self::Uri => URI,
+    }
+  }
+}
+
+fn validate_percent_encoding(data: &[u8]) -> Result<()> {
+  let mut i = 0;
+  while i < data.len() {
+    if data[i] == b'%' {
+      let valid =
+        data.len() >= i + 3 && data[i + 1].is_ascii_hexdigit() && data[i + 2].is_ascii_hexdigit();
+      if !valid {
+        return Err(MalformedPercentEncoding(i).into());
+      }
+      i += 3;
+    } else {
+      i += 1;
+    }
+  }
+  Ok(())
+}
+
+pub fn api_codec_url_encode(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let data = unsafe { args.get(1).read_string_assume_noalias(scope)? };
+  let mode: CodecUrlEncodeMode = v8_deserialize(scope, args.get(2))?;
+  let out = utf8_percent_encode(&data, mode.ascii_set()).to_string();
+  retval.set(mk_v8_string(scope, &out)?.into());
+  Ok(())
+}
+
+pub fn api_codec_url_decode(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let data = unsafe { args.get(1).read_bytes_assume_noalias(scope)? };
+  validate_percent_encoding(&data)?;
+  let out = percent_decode(&data).decode_utf8()?;
+  retval.set(mk_v8_string(scope, &out)?.into());
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::api::testutil::ApiTester;
+
+  #[test]
+  fn test_url_encode_modes() {
+    let mut tester = ApiTester::new();
+    let out: Vec<String> = tester.run_script(
+      r#"
+      ["component", "pathSegment", "query", "uri"].map(m => Codec.urlEncode("a b/c?d=e&f~#é", m));
+      "#,
+    );
+    assert_eq!(
+      out,
+      vec![
+        "a%20b%2Fc%3Fd%3De%26f~%23%C3%A9",
+        "a%20b%2Fc%3Fd=e&f~%23%C3%A9",
+        "a%20b/c?d=e&f~%23%C3%A9",
+        "a%20b/c?d=e&f~#%C3%A9",
+      ]
+    );
+  }
+
+  #[test]
+  fn test_url_decode_malformed() {
+    let mut tester = ApiTester::new();
+    let out: String = tester.run_script(r#"Codec.urlDecode("a%20b%2Fc%C3%A9");"#);
+    assert_eq!(out, "a b/cé");
+
+    for input in ["%", "%2", "%zz", "%C3"] {
+      let mut tester = ApiTester::new();
+      let out: bool = tester.run_script(&format!(
+        r#"let failed = false; try {{ Codec.urlDecode("{}"); }} catch(e) {{ failed = true; }} failed;"#,
+        input
+      ));
+      assert!(out, "{} should fail to decode", input);
+    }
+  }
+}
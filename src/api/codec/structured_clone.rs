@@ -0,0 +1,68 @@
+use anyhow::Result;
+use v8;
+
+use crate::{
+  objserde::{deserialize_v8_value, serialize_v8_value},
+  v8util::{create_uint8array_from_bytes, LocalValueExt},
+};
+
+/// Encodes a value with V8's structured clone format - the same encoding used for scheduled
+/// task payloads. Functions and other uncloneable values fail with a data clone error.
+pub fn api_codec_structured_clone_encode(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let out = serialize_v8_value(scope, args.get(1))?;
+  retval.set(create_uint8array_from_bytes(scope, &out).into());
+  Ok(())
+}
+
+pub fn api_codec_structured_clone_decode(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let data = unsafe { args.get(1).read_bytes_assume_noalias(scope)? };
+  let value = deserialize_v8_value(scope, &data)?;
+  retval.set(value);
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::api::testutil::ApiTester;
+
+  #[test]
+  fn test_structured_clone_roundtrip() {
+    let mut tester = ApiTester::new();
+    let out: String = tester.run_script(
+      r#"
+      const v = Codec.StructuredClone.decode(Codec.StructuredClone.encode({
+        m: new Map([["k", new Set([1, 2])]]),
+        b: new Uint8Array([1, 2, 3]),
+      }));
+      JSON.stringify([
+        v.m instanceof Map,
+        Array.from(v.m.get("k")),
+        v.b instanceof Uint8Array,
+        Array.from(v.b),
+      ]);
+      "#,
+    );
+    assert_eq!(out, "[true,[1,2],true,[1,2,3]]");
+  }
+
+  #[test]
+  fn test_structured_clone_rejects_functions() {
+    let mut tester = ApiTester::new();
+    let out: String = tester.run_script(
+      r#"
+      let msg = "";
+      try { Codec.StructuredClone.encode({ f() {} }); } catch(e) { msg = String(e); }
+      msg;
+      "#,
+    );
+    assert!(out.contains("could not be cloned"), "{}", out);
+  }
+}
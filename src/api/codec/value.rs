@@ -0,0 +1,192 @@
+use std::convert::TryFrom;
+
+use anyhow::Result;
+use thiserror::Error;
+use v8;
+
+use crate::{
+  api::util::{v8_deref_arraybuffer_assuming_noalias, v8_deref_typed_array_assuming_noalias},
+  v8util::create_uint8array_from_bytes,
+};
+
+/// Maximum nesting depth of arrays and maps accepted by the binary codecs, in both directions.
+pub const MAX_DEPTH: usize = 64;
+
+const MAX_SAFE_INTEGER: i128 = (1i128 << 53) - 1;
+
+#[derive(Error, Debug)]
+#[error("value nesting exceeds maximum depth of {0}")]
+pub struct MaxDepthExceeded(pub usize);
+
+#[derive(Error, Debug)]
+#[error("value cannot be encoded")]
+struct UnsupportedValue;
+
+#[derive(Error, Debug)]
+#[error("integer out of range")]
+struct IntegerOutOfRange;
+
+#[derive(Error, Debug)]
+#[error("cannot create string")]
+struct StringCreationFailed;
+
+/// Format-independent intermediate representation shared by the binary codecs (CBOR, MessagePack).
+///
+/// JS numbers that are integral and within the safe integer range become `Integer`; everything
+/// else becomes `Float`. BigInts become `Integer` if they fit the 64-bit range native to the
+/// formats (-2^64 to 2^64 - 1) and `BigInt` otherwise. Typed arrays and ArrayBuffers become
+/// `Bytes`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CodecValue {
+  Null,
+  Bool(bool),
+  Integer(i128),
+  /// Sign and magnitude as little-endian 64-bit words, the layout V8 uses for BigInts.
+  BigInt {
+    negative: bool,
+    words: Vec<u64>,
+  },
+  Float(f64),
+  String(String),
+  Bytes(Vec<u8>),
+  Array(Vec<CodecValue>),
+  Map(Vec<(String, CodecValue)>),
+}
+
+impl CodecValue {
+  /// Builds an integer from its sign and magnitude, preferring `Integer` where possible.
+  pub fn from_sign_and_words(negative: bool, mut words: Vec<u64>) -> Self {
+    while words.last() == Some(&0) {
+      words.pop();
+    }
+    match words.len() {
+      0 => Self::Integer(0),
+      1 if negative => Self::Integer(-(words[0] as i128)),
+      1 => Self::Integer(words[0] as i128),
+      2 if negative && words == [0, 1] => Self::Integer(-(1i128 << 64)),
+      _ => Self::BigInt { negative, words },
+    }
+  }
+
+  pub fn from_v8(scope: &mut v8::HandleScope, value: v8::Local<v8::Value>) -> Result<Self> {
+    Self::from_v8_at_depth(scope, value, 0)
+  }
+
+  fn from_v8_at_depth(
+    scope: &mut v8::HandleScope,
+    value: v8::Local<v8::Value>,
+    depth: usize,
+  ) -> Result<Self> {
+    if depth > MAX_DEPTH {
+      return Err(MaxDepthExceeded(MAX_DEPTH).into());
+    }
+
+    if value.is_null_or_undefined() {
+      Ok(Self::Null)
+    } else if value.is_boolean() {
+      Ok(Self::Bool(value.is_true()))
+    } else if let Ok(x) = v8::Local::<v8::BigInt>::try_from(value) {
+      let mut words = vec![0u64; x.word_count()];
+      let (negative, words) = x.to_words_array(&mut words);
+      Ok(Self::from_sign_and_words(negative, words.to_vec()))
+    } else if value.is_number() {
+      let x = value.number_value(scope).unwrap_or(f64::NAN);
+      if x.fract() == 0.0 && x.abs() <= MAX_SAFE_INTEGER as f64 {
+        Ok(Self::Integer(x as i128))
+      } else {
+        Ok(Self::Float(x))
+      }
+    } else if value.is_string() {
+      Ok(Self::String(value.to_rust_string_lossy(scope)))
+    } else if let Ok(x) = v8::Local::<v8::TypedArray>::try_from(value) {
+      let view = unsafe { v8_deref_typed_array_assuming_noalias(scope, x) };
+      Ok(Self::Bytes(view.to_vec()))
+    } else if let Ok(x) = v8::Local::<v8::ArrayBuffer>::try_from(value) {
+      let view = unsafe { v8_deref_arraybuffer_assuming_noalias(x) };
+      Ok(Self::Bytes(view.to_vec()))
+    } else if let Ok(x) = v8::Local::<v8::Array>::try_from(value) {
+      let len = x.length();
+      let mut out = Vec::with_capacity(len as usize);
+      for i in 0..len {
+        let elem = x.get_index(scope, i).ok_or(UnsupportedValue)?;
+        out.push(Self::from_v8_at_depth(scope, elem, depth + 1)?);
+      }
+      Ok(Self::Array(out))
+    } else if value.is_function() || value.is_symbol() {
+      Err(UnsupportedValue.into())
+    } else if let Ok(x) = v8::Local::<v8::Object>::try_from(value) {
+      let names = x.get_own_property_names(scope).ok_or(UnsupportedValue)?;
+      let len = names.length();
+      let mut out = Vec::with_capacity(len as usize);
+      for i in 0..len {
+        let k = names.get_index(scope, i).ok_or(UnsupportedValue)?;
+        let v = x.get(scope, k).ok_or(UnsupportedValue)?;
+        let k = k.to_rust_string_lossy(scope);
+        out.push((k, Self::from_v8_at_depth(scope, v, depth + 1)?));
+      }
+      Ok(Self::Map(out))
+    } else {
+      Err(UnsupportedValue.into())
+    }
+  }
+
+  pub fn to_v8<'s>(&self, scope: &mut v8::HandleScope<'s>) -> Result<v8::Local<'s, v8::Value>> {
+    Ok(match self {
+      Self::Null => v8::null(scope).into(),
+      Self::Bool(x) => v8::Boolean::new(scope, *x).into(),
+      Self::Integer(x) => {
+        let x = *x;
+        if x.abs() <= MAX_SAFE_INTEGER {
+          v8::Number::new(scope, x as f64).into()
+        } else {
+          let m = x.unsigned_abs();
+          v8::BigInt::new_from_words(scope, x < 0, &[m as u64, (m >> 64) as u64])
+            .ok_or(IntegerOutOfRange)?
+            .into()
+        }
+      }
+      Self::BigInt { negative, words } => v8::BigInt::new_from_words(scope, *negative, words)
+        .ok_or(IntegerOutOfRange)?
+        .into(),
+      Self::Float(x) => v8::Number::new(scope, *x).into(),
+      Self::String(x) => v8::String::new(scope, x)
+        .ok_or(StringCreationFailed)?
+        .into(),
+      Self::Bytes(x) => create_uint8array_from_bytes(scope, x).into(),
+      Self::Array(x) => {
+        let elements = x
+          .iter()
+          .map(|x| x.to_v8(scope))
+          .collect::<Result<Vec<_>>>()?;
+        v8::Array::new_with_elements(scope, &elements).into()
+      }
+      Self::Map(x) => {
+        let obj = v8::Object::new(scope);
+        for (k, v) in x {
+          let k = v8::String::new(scope, k).ok_or(StringCreationFailed)?;
+          let v = v.to_v8(scope)?;
+          // `create_data_property` instead of `set` so that keys like `__proto__` are plain data.
+          obj.create_data_property(scope, k.into(), v);
+        }
+        obj.into()
+      }
+    })
+  }
+}
+
+/// Converts little-endian words to minimal big-endian bytes, as used by CBOR bignums.
+pub fn words_to_be_bytes(words: &[u64]) -> Vec<u8> {
+  words
+    .iter()
+    .rev()
+    .flat_map(|x| x.to_be_bytes())
+    .skip_while(|x| *x == 0)
+    .collect()
+}
+
+pub fn be_bytes_to_words(bytes: &[u8]) -> Vec<u64> {
+  bytes
+    .rchunks(8)
+    .map(|chunk| chunk.iter().fold(0u64, |acc, x| (acc << 8) | *x as u64))
+    .collect()
+}
@@ -4,10 +4,12 @@ use aes_gcm_siv::aead::generic_array::{ArrayLength, GenericArray};
 use aes_gcm_siv::aead::{AeadCore, AeadMutInPlace, Buffer, NewAead};
 use aes_gcm_siv::{Aes128GcmSiv, Key, Nonce};
 use anyhow::Result;
+use ring::aead as ring_aead;
+use thiserror::Error;
 use v8;
 
 use crate::api::util::ArrayBufferBuilder;
-use crate::v8util::{GenericBytesView, LocalValueExt};
+use crate::v8util::{create_uint8array_from_bytes, GenericBytesView, LocalValueExt};
 
 pub struct AesGcmSivParams<K, N, Buf> {
   key: K,
@@ -166,3 +168,281 @@ pub fn api_crypto_aead_aes128_gcm_siv_decrypt(
   );
   Ok(())
 }
+
+#[derive(Error, Debug)]
+#[error("invalid aes-gcm key length: expected 16 or 32 bytes, got {0}")]
+struct InvalidAesGcmKeyLength(usize);
+
+#[derive(Error, Debug)]
+#[error("invalid chacha20-poly1305 key length: expected 32 bytes, got {0}")]
+struct InvalidChaCha20Poly1305KeyLength(usize);
+
+#[derive(Error, Debug)]
+#[error("invalid nonce length: expected {expected} bytes, got {actual}")]
+struct InvalidNonceLength {
+  expected: usize,
+  actual: usize,
+}
+
+#[derive(Error, Debug)]
+#[error("{0} failed: authentication tag mismatch")]
+struct AeadOpenFailed(&'static str);
+
+struct RingAeadParams {
+  key: GenericBytesView,
+  nonce: GenericBytesView,
+  data: GenericBytesView,
+  associated_data: Option<GenericBytesView>,
+}
+
+impl RingAeadParams {
+  fn from_args<'a, 'b>(
+    scope: &mut v8::HandleScope<'a>,
+    args: &v8::FunctionCallbackArguments<'b>,
+  ) -> Result<Self> {
+    let key = unsafe { args.get(1).read_bytes_assume_noalias(scope) }?;
+    let nonce = unsafe { args.get(2).read_bytes_assume_noalias(scope) }?;
+    let data = unsafe { args.get(3).read_bytes_assume_noalias(scope) }?;
+    let associated_data = args.get(4);
+    let associated_data = if associated_data.is_null_or_undefined() {
+      None
+    } else {
+      Some(unsafe { associated_data.read_bytes_assume_noalias(scope) }?)
+    };
+    Ok(Self {
+      key,
+      nonce,
+      data,
+      associated_data,
+    })
+  }
+
+  fn build_key(&self, alg: &'static ring_aead::Algorithm) -> Result<ring_aead::LessSafeKey> {
+    let key = ring_aead::UnboundKey::new(alg, &self.key[..])
+      .map_err(|_| anyhow::anyhow!("invalid key length: {}", self.key.len()))?;
+    Ok(ring_aead::LessSafeKey::new(key))
+  }
+
+  fn build_nonce(&self) -> Result<ring_aead::Nonce> {
+    ring_aead::Nonce::try_assume_unique_for_key(&self.nonce[..]).map_err(|_| {
+      InvalidNonceLength {
+        expected: ring_aead::NONCE_LEN,
+        actual: self.nonce.len(),
+      }
+      .into()
+    })
+  }
+
+  fn aad(&self) -> ring_aead::Aad<&[u8]> {
+    ring_aead::Aad::from(self.associated_data.as_ref().map(|x| &x[..]).unwrap_or(&[]))
+  }
+
+  fn seal(&self, alg: &'static ring_aead::Algorithm, op: &'static str) -> Result<Vec<u8>> {
+    let key = self.build_key(alg)?;
+    let nonce = self.build_nonce()?;
+    let mut buf = self.data.to_vec();
+    key
+      .seal_in_place_append_tag(nonce, self.aad(), &mut buf)
+      .map_err(|_| anyhow::anyhow!("{} failed", op))?;
+    Ok(buf)
+  }
+
+  fn open(&self, alg: &'static ring_aead::Algorithm, op: &'static str) -> Result<Vec<u8>> {
+    let key = self.build_key(alg)?;
+    let nonce = self.build_nonce()?;
+    let mut buf = self.data.to_vec();
+    let plaintext_len = key
+      .open_in_place(nonce, self.aad(), &mut buf)
+      .map_err(|_| AeadOpenFailed(op))?
+      .len();
+    buf.truncate(plaintext_len);
+    Ok(buf)
+  }
+}
+
+fn aes_gcm_algorithm(key_len: usize) -> Result<&'static ring_aead::Algorithm> {
+  match key_len {
+    16 => Ok(&ring_aead::AES_128_GCM),
+    32 => Ok(&ring_aead::AES_256_GCM),
+    _ => Err(InvalidAesGcmKeyLength(key_len).into()),
+  }
+}
+
+pub fn api_crypto_aes_gcm_encrypt(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let params = RingAeadParams::from_args(scope, &args)?;
+  let alg = aes_gcm_algorithm(params.key.len())?;
+  let output = params.seal(alg, "aes_gcm_encrypt")?;
+  retval.set(create_uint8array_from_bytes(scope, &output).into());
+  Ok(())
+}
+
+pub fn api_crypto_aes_gcm_decrypt(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let params = RingAeadParams::from_args(scope, &args)?;
+  let alg = aes_gcm_algorithm(params.key.len())?;
+  let output = params.open(alg, "aes_gcm_decrypt")?;
+  retval.set(create_uint8array_from_bytes(scope, &output).into());
+  Ok(())
+}
+
+pub fn api_crypto_chacha20poly1305_encrypt(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let params = RingAeadParams::from_args(scope, &args)?;
+  if params.key.len() != 32 {
+    return Err(InvalidChaCha20Poly1305KeyLength(params.key.len()).into());
+  }
+  let output = params.seal(&ring_aead::CHACHA20_POLY1305, "chacha20poly1305_encrypt")?;
+  retval.set(create_uint8array_from_bytes(scope, &output).into());
+  Ok(())
+}
+
+pub fn api_crypto_chacha20poly1305_decrypt(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let params = RingAeadParams::from_args(scope, &args)?;
+  if params.key.len() != 32 {
+    return Err(InvalidChaCha20Poly1305KeyLength(params.key.len()).into());
+  }
+  let output = params.open(&ring_aead::CHACHA20_POLY1305, "chacha20poly1305_decrypt")?;
+  retval.set(create_uint8array_from_bytes(scope, &output).into());
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::api::testutil::ApiTester;
+
+  const RFC8439_PLAINTEXT: &str = "Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+  const RFC8439_SEALED: &str = "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d63dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b3692ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc3ff4def08e4b7a9de576d26586cec64b61161ae10b594f09e26a7e902ecbd0600691";
+
+  fn rfc8439_params(data: &str) -> String {
+    format!(
+      r#"{{
+  key: Codec.hexdecode("808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f"),
+  nonce: Codec.hexdecode("070000004041424344454647"),
+  associatedData: Codec.hexdecode("50515253c0c1c2c3c4c5c6c7"),
+  data: {},
+}}"#,
+      data
+    )
+  }
+
+  #[test]
+  fn test_chacha20poly1305_rfc8439_vector() {
+    let mut tester = ApiTester::new();
+    let sealed: String = tester.run_script(&format!(
+      "Codec.hexencode(NativeCrypto.AEAD.chacha20Poly1305Encrypt({}));",
+      rfc8439_params(&format!(
+        "new TextEncoder().encode({:?})",
+        RFC8439_PLAINTEXT
+      ))
+    ));
+    assert_eq!(sealed, RFC8439_SEALED);
+
+    let opened: String = tester.run_script(&format!(
+      "new TextDecoder().decode(NativeCrypto.AEAD.chacha20Poly1305Decrypt({}));",
+      rfc8439_params(&format!("Codec.hexdecode({:?})", RFC8439_SEALED))
+    ));
+    assert_eq!(opened, RFC8439_PLAINTEXT);
+  }
+
+  #[test]
+  fn test_chacha20poly1305_rejects_tampered_ciphertext() {
+    let mut tester = ApiTester::new();
+    let rejected: bool = tester.run_script(&format!(
+      r#"
+{{
+  const params = {};
+  params.data[0] ^= 1;
+  let rejected = false;
+  try {{
+    NativeCrypto.AEAD.chacha20Poly1305Decrypt(params);
+  }} catch (e) {{
+    rejected = true;
+  }}
+  rejected;
+}}
+    "#,
+      rfc8439_params(&format!("Codec.hexdecode({:?})", RFC8439_SEALED))
+    ));
+    assert!(rejected);
+  }
+
+  // The Galois/Counter Mode of Operation (GCM), McGrew & Viega, test cases 4 and 16.
+  const GCM_PLAINTEXT: &str = "d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a721c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b39";
+  const GCM_VECTORS: [(&str, &str); 2] = [
+    (
+      "feffe9928665731c6d6a8f9467308308",
+      "42831ec2217774244b7221b784d0d49ce3aa212f2c02a4e035c17e2329aca12e21d514b25466931c7d8f6a5aac84aa051ba30b396a0aac973d58e0915bc94fbc3221a5db94fae95ae7121a47",
+    ),
+    (
+      "feffe9928665731c6d6a8f9467308308feffe9928665731c6d6a8f9467308308",
+      "522dc1f099567d07f47f37a32a84427d643a8cdcbfe5c0c97598a2bd2555d1aa8cb08e48590dbb3da7b08b1056828838c5f61e6393ba7a0abcc9f66276fc6ece0f4e1768cddf8853bb2d551b",
+    ),
+  ];
+
+  fn gcm_params(key: &str, data: &str) -> String {
+    format!(
+      r#"{{
+  key: Codec.hexdecode({:?}),
+  nonce: Codec.hexdecode("cafebabefacedbaddecaf888"),
+  associatedData: Codec.hexdecode("feedfacedeadbeeffeedfacedeadbeefabaddad2"),
+  data: Codec.hexdecode({:?}),
+}}"#,
+      key, data
+    )
+  }
+
+  #[test]
+  fn test_aes_gcm_vectors() {
+    for (key, sealed) in GCM_VECTORS {
+      let mut tester = ApiTester::new();
+      let out: String = tester.run_script(&format!(
+        "Codec.hexencode(NativeCrypto.AEAD.aesGcmEncrypt({}));",
+        gcm_params(key, GCM_PLAINTEXT)
+      ));
+      assert_eq!(out, sealed);
+
+      let out: String = tester.run_script(&format!(
+        "Codec.hexencode(NativeCrypto.AEAD.aesGcmDecrypt({}));",
+        gcm_params(key, sealed)
+      ));
+      assert_eq!(out, GCM_PLAINTEXT);
+    }
+  }
+
+  #[test]
+  fn test_aes_gcm_rejects_tag_mismatch() {
+    let (key, sealed) = GCM_VECTORS[0];
+    let mut tester = ApiTester::new();
+    let rejected: bool = tester.run_script(&format!(
+      r#"
+{{
+  const params = {};
+  params.data[params.data.length - 1] ^= 1;
+  let rejected = false;
+  try {{
+    NativeCrypto.AEAD.aesGcmDecrypt(params);
+  }} catch (e) {{
+    rejected = true;
+  }}
+  rejected;
+}}
+    "#,
+      gcm_params(key, sealed)
+    ));
+    assert!(rejected);
+  }
+}
@@ -0,0 +1,234 @@
+use anyhow::Result;
+use argon2::{
+  password_hash::SaltString, Algorithm, Argon2, Params, PasswordHash, PasswordHasher,
+  PasswordVerifier, Version,
+};
+use rand::Rng;
+use serde::Deserialize;
+use thiserror::Error;
+use v8;
+
+use crate::{
+  api::util::{mk_v8_string, v8_deserialize},
+  v8util::LocalValueExt,
+};
+
+/// OWASP-recommended baseline for argon2id: 19 MiB of memory and 2 passes.
+const DEFAULT_MEMORY_COST_KIB: u32 = 19 * 1024;
+const DEFAULT_TIME_COST: u32 = 2;
+
+/// A worker shares its process memory with everything else running in the isolate, so one hash
+/// must not be able to allocate an unbounded amount of memory.
+const MAX_MEMORY_COST_KIB: u32 = 64 * 1024;
+const MAX_TIME_COST: u32 = 10;
+
+/// Workers are single-threaded - there is no point in parallel lanes.
+const MAX_PARALLELISM: u32 = 1;
+
+#[derive(Error, Debug)]
+#[error("argon2 memory cost must not exceed {} KiB", MAX_MEMORY_COST_KIB)]
+struct MemoryCostTooHigh;
+
+#[derive(Error, Debug)]
+#[error("argon2 time cost must not exceed {}", MAX_TIME_COST)]
+struct TimeCostTooHigh;
+
+#[derive(Error, Debug)]
+#[error("argon2 parallelism must not exceed {}", MAX_PARALLELISM)]
+struct ParallelismTooHigh;
+
+#[derive(Error, Debug)]
+#[error("argon2 memory cost of {0} KiB does not fit in the remaining isolate heap")]
+struct MemoryCostExceedsHeapLimit(u32);
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct Argon2HashOptions {
+  memory_cost: Option<u32>,
+  time_cost: Option<u32>,
+  parallelism: Option<u32>,
+}
+
+impl Argon2HashOptions {
+  fn build_params(&self) -> Result<Params> {
+    let m_cost = self.memory_cost.unwrap_or(DEFAULT_MEMORY_COST_KIB);
+    let t_cost = self.time_cost.unwrap_or(DEFAULT_TIME_COST);
+    let p_cost = self.parallelism.unwrap_or(MAX_PARALLELISM);
+    check_costs(m_cost, t_cost, p_cost)?;
+    Params::new(m_cost, t_cost, p_cost, None)
+      .map_err(|e| anyhow::anyhow!("invalid argon2 params: {}", e))
+  }
+}
+
+fn check_costs(m_cost: u32, t_cost: u32, p_cost: u32) -> Result<()> {
+  if m_cost > MAX_MEMORY_COST_KIB {
+    return Err(MemoryCostTooHigh.into());
+  }
+  if t_cost > MAX_TIME_COST {
+    return Err(TimeCostTooHigh.into());
+  }
+  if p_cost > MAX_PARALLELISM {
+    return Err(ParallelismTooHigh.into());
+  }
+  Ok(())
+}
+
+/// The hash memory is allocated outside of the V8 heap, but counts against the same process
+/// memory, so it must fit in what is left of the heap limit.
+fn check_heap_limit(scope: &mut v8::HandleScope, m_cost: u32) -> Result<()> {
+  let mut stats = v8::HeapStatistics::default();
+  scope.get_heap_statistics(&mut stats);
+  let available = stats
+    .heap_size_limit()
+    .saturating_sub(stats.used_heap_size()) as u64;
+  if m_cost as u64 * 1024 > available {
+    return Err(MemoryCostExceedsHeapLimit(m_cost).into());
+  }
+  Ok(())
+}
+
+pub fn api_crypto_argon2_hash(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let password = unsafe { args.get(1).read_bytes_assume_noalias(scope)? };
+  let opts = args.get(2);
+  let opts: Argon2HashOptions = if opts.is_null_or_undefined() {
+    Default::default()
+  } else {
+    v8_deserialize(scope, opts)?
+  };
+  let params = opts.build_params()?;
+  check_heap_limit(scope, params.m_cost())?;
+
+  let mut salt = [0u8; 16];
+  rand::thread_rng().fill(&mut salt[..]);
+  let salt = SaltString::b64_encode(&salt).map_err(|e| anyhow::anyhow!("salt error: {}", e))?;
+
+  let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+  let hash = argon2
+    .hash_password(&password, &salt)
+    .map_err(|e| anyhow::anyhow!("argon2 hash failed: {}", e))?
+    .to_string();
+  retval.set(mk_v8_string(scope, &hash)?.into());
+  Ok(())
+}
+
+pub fn api_crypto_argon2_verify(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let encoded = unsafe { args.get(1).read_string_assume_noalias(scope)? };
+  let password = unsafe { args.get(2).read_bytes_assume_noalias(scope)? };
+  let hash =
+    PasswordHash::new(&encoded).map_err(|e| anyhow::anyhow!("invalid argon2 hash: {}", e))?;
+
+  // The cost parameters come from the encoded string - apply the same limits as hashing.
+  let params =
+    Params::try_from(&hash).map_err(|e| anyhow::anyhow!("invalid argon2 hash: {}", e))?;
+  check_costs(params.m_cost(), params.t_cost(), params.p_cost())?;
+  check_heap_limit(scope, params.m_cost())?;
+
+  let ok = Argon2::default().verify_password(&password, &hash).is_ok();
+  retval.set(v8::Boolean::new(scope, ok).into());
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::api::testutil::ApiTester;
+
+  // Small costs keep the tests fast in debug builds.
+  const FAST: &str = "{ memoryCost: 64, timeCost: 1 }";
+
+  fn attempt(tester: &mut ApiTester, expr: &str) -> String {
+    tester.run_script(&format!(
+      r#"
+{{
+  let out;
+  try {{
+    out = String({});
+  }} catch (e) {{
+    out = e.message;
+  }}
+  out;
+}}
+    "#,
+      expr
+    ))
+  }
+
+  #[test]
+  fn test_hash_verify_roundtrip() {
+    let mut tester = ApiTester::new();
+    let out: Vec<String> = tester.run_script(&format!(
+      r#"
+{{
+  const encoded = NativeCrypto.Argon2.hash("hunter2", {});
+  [
+    encoded.split("$").slice(0, 4).join("$"),
+    String(NativeCrypto.Argon2.verify(encoded, "hunter2")),
+    String(NativeCrypto.Argon2.verify(encoded, "hunter3")),
+  ];
+}}
+    "#,
+      FAST
+    ));
+    assert_eq!(out, vec!["$argon2id$v=19$m=64,t=1,p=1", "true", "false"]);
+  }
+
+  #[test]
+  fn test_malformed_hash_is_rejected() {
+    let mut tester = ApiTester::new();
+    let out = attempt(
+      &mut tester,
+      r#"NativeCrypto.Argon2.verify("not a phc string", "hunter2")"#,
+    );
+    assert!(out.starts_with("invalid argon2 hash"), "{}", out);
+  }
+
+  #[test]
+  fn test_costs_over_caps_are_rejected() {
+    let mut tester = ApiTester::new();
+    let hash = |opts: &str| format!(r#"NativeCrypto.Argon2.hash("hunter2", {})"#, opts);
+    assert_eq!(
+      attempt(&mut tester, &hash("{ memoryCost: 65537, timeCost: 1 }")),
+      "argon2 memory cost must not exceed 65536 KiB"
+    );
+    assert_eq!(
+      attempt(&mut tester, &hash("{ memoryCost: 64, timeCost: 11 }")),
+      "argon2 time cost must not exceed 10"
+    );
+    assert_eq!(
+      attempt(
+        &mut tester,
+        &hash("{ memoryCost: 64, timeCost: 1, parallelism: 2 }")
+      ),
+      "argon2 parallelism must not exceed 1"
+    );
+
+    // The same caps apply to the parameters stored in an encoded hash.
+    let verify = |from: &str, to: &str| {
+      format!(
+        r#"NativeCrypto.Argon2.verify({}.replace({:?}, {:?}), "hunter2")"#,
+        hash(FAST),
+        from,
+        to
+      )
+    };
+    assert_eq!(
+      attempt(&mut tester, &verify("m=64,", "m=65537,")),
+      "argon2 memory cost must not exceed 65536 KiB"
+    );
+    assert_eq!(
+      attempt(&mut tester, &verify("t=1,", "t=11,")),
+      "argon2 time cost must not exceed 10"
+    );
+    assert_eq!(
+      attempt(&mut tester, &verify("p=1$", "p=2$")),
+      "argon2 parallelism must not exceed 1"
+    );
+  }
+}
@@ -0,0 +1,179 @@
+use anyhow::Result;
+use serde::Deserialize;
+use thiserror::Error;
+use v8;
+
+use crate::{
+  api::util::{v8_deserialize, ArrayBufferBuilder},
+  v8util::LocalValueExt,
+};
+
+#[derive(Error, Debug)]
+#[error("hkdf output length must be between 1 and {0} bytes")]
+struct InvalidOutputLength(usize);
+
+#[derive(Deserialize, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum HkdfHash {
+  Sha1,
+  Sha256,
+  Sha384,
+  Sha512,
+}
+
+impl HkdfHash {
+  fn algorithm(self) -> ring::hkdf::Algorithm {
+    match self {
+      Self::Sha1 => ring::hkdf::HKDF_SHA1_FOR_LEGACY_USE_ONLY,
+      Self::Sha256 => ring::hkdf::HKDF_SHA256,
+      Self::Sha384 => ring::hkdf::HKDF_SHA384,
+      Self::Sha512 => ring::hkdf::HKDF_SHA512,
+    }
+  }
+}
+
+struct OutputLength(usize);
+
+impl ring::hkdf::KeyType for OutputLength {
+  fn len(&self) -> usize {
+    self.0
+  }
+}
+
+pub fn api_crypto_hkdf(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let hash: HkdfHash = v8_deserialize(scope, args.get(1))?;
+  let ikm = unsafe { args.get(2).read_bytes_assume_noalias(scope)? };
+  let salt = args.get(3);
+  let salt = if salt.is_null_or_undefined() {
+    None
+  } else {
+    Some(unsafe { salt.read_bytes_assume_noalias(scope)? })
+  };
+  let info = args.get(4);
+  let info = if info.is_null_or_undefined() {
+    None
+  } else {
+    Some(unsafe { info.read_bytes_assume_noalias(scope)? })
+  };
+  let length: usize = v8_deserialize(scope, args.get(5))?;
+
+  let alg = hash.algorithm();
+
+  // RFC 5869, section 2.3: L <= 255 * HashLen
+  let max_length = 255 * alg.hmac_algorithm().digest_algorithm().output_len;
+  if length == 0 || length > max_length {
+    return Err(InvalidOutputLength(max_length).into());
+  }
+
+  let prk = ring::hkdf::Salt::new(alg, salt.as_ref().map(|x| &x[..]).unwrap_or(&[])).extract(&ikm);
+  let info = info.as_ref().map(|x| &x[..]).unwrap_or(&[]);
+  let info = [info];
+  let okm = prk
+    .expand(&info, OutputLength(length))
+    .map_err(|_| InvalidOutputLength(max_length))?;
+  let mut out = ArrayBufferBuilder::new(scope, length);
+  okm
+    .fill(&mut out)
+    .map_err(|_| anyhow::anyhow!("hkdf expand failed"))?;
+  retval.set(out.build_uint8array(scope, None).into());
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::api::testutil::ApiTester;
+
+  fn derive_expr(hash: &str, ikm: &str, salt: &str, info: &str, length: usize) -> String {
+    format!(
+      r#"NativeCrypto.HKDF.derive({{ hash: {:?}, ikm: Codec.hexdecode({:?}), salt: Codec.hexdecode({:?}), info: Codec.hexdecode({:?}), length: {} }})"#,
+      hash, ikm, salt, info, length
+    )
+  }
+
+  fn rejection(tester: &mut ApiTester, expr: &str) -> String {
+    tester.run_script(&format!(
+      r#"
+{{
+  let out = "ok";
+  try {{
+    {};
+  }} catch (e) {{
+    out = e.message;
+  }}
+  out;
+}}
+    "#,
+      expr
+    ))
+  }
+
+  #[test]
+  fn test_rfc5869_vectors() {
+    // RFC 5869, appendix A: test cases 1 and 3 (SHA-256) and 4 (SHA-1).
+    let vectors = [
+      (
+        "sha256",
+        "0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b",
+        "000102030405060708090a0b0c",
+        "f0f1f2f3f4f5f6f7f8f9",
+        "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf34007208d5b887185865",
+      ),
+      (
+        "sha256",
+        "0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b",
+        "",
+        "",
+        "8da4e775a563c18f715f802a063c5a31b8a11f5c5ee1879ec3454e5f3c738d2d9d201395faa4b61a96c8",
+      ),
+      (
+        "sha1",
+        "0b0b0b0b0b0b0b0b0b0b0b",
+        "000102030405060708090a0b0c",
+        "f0f1f2f3f4f5f6f7f8f9",
+        "085a01ea1b10f36933068b56efa5ad81a4f14b822f5b091568a9cdd4f155fda2c22e422478d305f3f896",
+      ),
+    ];
+    let mut tester = ApiTester::new();
+    for (hash, ikm, salt, info, okm) in vectors {
+      let out: String = tester.run_script(&format!(
+        "Codec.hexencode({});",
+        derive_expr(hash, ikm, salt, info, okm.len() / 2)
+      ));
+      assert_eq!(out, okm);
+    }
+  }
+
+  #[test]
+  fn test_output_length_limit() {
+    let mut tester = ApiTester::new();
+    assert_eq!(
+      rejection(&mut tester, &derive_expr("sha256", "00", "", "", 255 * 32)),
+      "ok"
+    );
+    assert_eq!(
+      rejection(
+        &mut tester,
+        &derive_expr("sha256", "00", "", "", 255 * 32 + 1)
+      ),
+      "hkdf output length must be between 1 and 8160 bytes"
+    );
+    assert_eq!(
+      rejection(
+        &mut tester,
+        &derive_expr("sha1", "00", "", "", 255 * 20 + 1)
+      ),
+      "hkdf output length must be between 1 and 5100 bytes"
+    );
+  }
+
+  #[test]
+  fn test_unsupported_hash() {
+    let mut tester = ApiTester::new();
+    let out = rejection(&mut tester, &derive_expr("md5", "00", "", "", 16));
+    assert_ne!(out, "ok");
+  }
+}
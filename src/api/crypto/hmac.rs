@@ -1,9 +1,9 @@
-use sha2::Sha256;
+use sha2::{Sha256, Sha384, Sha512};
 use v8;
 
 use crate::{
   api::util::{v8_deref_typed_array_assuming_noalias, v8_deserialize},
-  v8util::create_uint8array_from_bytes,
+  v8util::{create_uint8array_from_bytes, LocalValueExt},
 };
 use anyhow::Result;
 use hmac::{Hmac, Mac};
@@ -17,6 +17,50 @@ pub struct HmacSha256Params<'s> {
   data: serde_v8::Value<'s>,
 }
 
+#[derive(Deserialize, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum HmacAlgorithm {
+  Sha256,
+  Sha384,
+  Sha512,
+}
+
+impl HmacAlgorithm {
+  fn sign(self, key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    match self {
+      Self::Sha256 => hmac_sign::<Hmac<Sha256>>(key, data),
+      Self::Sha384 => hmac_sign::<Hmac<Sha384>>(key, data),
+      Self::Sha512 => hmac_sign::<Hmac<Sha512>>(key, data),
+    }
+  }
+
+  fn verify(self, key: &[u8], data: &[u8], expected: &[u8]) -> Result<bool> {
+    match self {
+      Self::Sha256 => hmac_verify::<Hmac<Sha256>>(key, data, expected),
+      Self::Sha384 => hmac_verify::<Hmac<Sha384>>(key, data, expected),
+      Self::Sha512 => hmac_verify::<Hmac<Sha512>>(key, data, expected),
+    }
+  }
+}
+
+fn hmac_sign<M: Mac + hmac::digest::KeyInit>(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+  let mut mac = <M as Mac>::new_from_slice(key)?;
+  mac.update(data);
+  Ok(mac.finalize().into_bytes().to_vec())
+}
+
+fn hmac_verify<M: Mac + hmac::digest::KeyInit>(
+  key: &[u8],
+  data: &[u8],
+  expected: &[u8],
+) -> Result<bool> {
+  let mut mac = <M as Mac>::new_from_slice(key)?;
+  mac.update(data);
+
+  // `verify_slice` compares in constant time.
+  Ok(mac.verify_slice(expected).is_ok())
+}
+
 pub fn api_crypto_hmac_sha256(
   scope: &mut v8::HandleScope,
   args: v8::FunctionCallbackArguments,
@@ -33,3 +77,94 @@ pub fn api_crypto_hmac_sha256(
   retval.set(create_uint8array_from_bytes(scope, &result.into_bytes()[..]).into());
   Ok(())
 }
+
+pub fn api_crypto_hmac_sign(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let alg: HmacAlgorithm = v8_deserialize(scope, args.get(1))?;
+  let key = unsafe { args.get(2).read_bytes_assume_noalias(scope)? };
+  let data = unsafe { args.get(3).read_bytes_assume_noalias(scope)? };
+  let out = alg.sign(&key, &data)?;
+  retval.set(create_uint8array_from_bytes(scope, &out).into());
+  Ok(())
+}
+
+pub fn api_crypto_hmac_verify(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let alg: HmacAlgorithm = v8_deserialize(scope, args.get(1))?;
+  let key = unsafe { args.get(2).read_bytes_assume_noalias(scope)? };
+  let data = unsafe { args.get(3).read_bytes_assume_noalias(scope)? };
+  let expected = unsafe { args.get(4).read_bytes_assume_noalias(scope)? };
+  let ok = alg.verify(&key, &data, &expected)?;
+  retval.set(v8::Boolean::new(scope, ok).into());
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::api::testutil::ApiTester;
+
+  // RFC 4231, test cases 1 and 2: (key, data, [sha256, sha384, sha512]).
+  const RFC4231_VECTORS: [(&str, &str, [&str; 3]); 2] = [
+    (
+      "0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b",
+      "4869205468657265",
+      [
+        "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7",
+        "afd03944d84895626b0825f4ab46907f15f9dadbe4101ec682aa034c7cebc59cfaea9ea9076ede7f4af152e8b2fa9cb6",
+        "87aa7cdea5ef619d4ff0b4241a1d6cb02379f4e2ce4ec2787ad0b30545e17cdedaa833b7d6b8a702038b274eaea3f4e4be9d914eeb61f1702e696c203a126854",
+      ],
+    ),
+    (
+      "4a656665",
+      "7768617420646f2079612077616e7420666f72206e6f7468696e673f",
+      [
+        "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+        "af45d2e376484031617f78d2b58a6b1b9c7ef464f5a01b47e42ec3736322445e8e2240ca5e69e2c78b3239ecfab21649",
+        "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea2505549758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737",
+      ],
+    ),
+  ];
+
+  #[test]
+  fn test_rfc4231_vectors() {
+    let mut tester = ApiTester::new();
+    for (key, data, macs) in RFC4231_VECTORS {
+      let out: Vec<String> = tester.run_script(&format!(
+        r#"["sha256", "sha384", "sha512"].map(alg => Codec.hexencode(NativeCrypto.HMAC.sign(alg, Codec.hexdecode("{}"), Codec.hexdecode("{}"))));"#,
+        key, data
+      ));
+      assert_eq!(out, macs);
+    }
+  }
+
+  #[test]
+  fn test_verify_rejects_modified_mac() {
+    let (key, data, macs) = RFC4231_VECTORS[0];
+    let mut tester = ApiTester::new();
+    let out: Vec<bool> = tester.run_script(&format!(
+      r#"
+{{
+  const key = Codec.hexdecode("{}");
+  const data = Codec.hexdecode("{}");
+  const mac = Codec.hexdecode("{}");
+  const flipped = mac.slice();
+  flipped[flipped.length - 1] ^= 1;
+  [
+    NativeCrypto.HMAC.verify("sha256", key, data, mac),
+    NativeCrypto.HMAC.verify("sha256", key, data, flipped),
+    NativeCrypto.HMAC.verify("sha256", key, data, mac.subarray(0, 16)),
+    NativeCrypto.HMAC.verify("sha512", key, data, mac),
+  ];
+}}
+    "#,
+      key, data, macs[0]
+    ));
+    assert_eq!(out, vec![true, false, false, false]);
+  }
+}
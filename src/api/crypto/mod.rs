@@ -1,12 +1,21 @@
 pub mod aead;
+pub mod argon2;
 pub mod curve25519;
+pub mod hkdf;
 pub mod hmac;
+pub mod jwe;
 pub mod jwt;
+pub mod p256;
+pub mod pbkdf2;
+pub mod rsa;
+pub mod secp256k1;
+pub mod stream;
 
 use anyhow::Result;
 use md5::{Digest, Md5};
-use rand::Rng;
-use std::convert::TryFrom;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Deserialize;
+use std::{cell::RefCell, convert::TryFrom};
 use thiserror::Error;
 use v8;
 
@@ -14,6 +23,30 @@ use crate::{
   api::util::v8_deref_typed_array_assuming_noalias, v8util::create_uint8array_from_bytes,
 };
 
+/// An asymmetric key in PEM or DER form. DER keys are passed as standard base64 in `data`.
+#[derive(Deserialize)]
+pub struct PemOrDerKey {
+  #[serde(rename = "type")]
+  pub _type: PemOrDerKeyType,
+  pub data: String,
+}
+
+#[derive(Deserialize, Copy, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum PemOrDerKeyType {
+  Pem,
+  Der,
+}
+
+impl PemOrDerKey {
+  pub fn load(&self) -> Result<Vec<u8>> {
+    Ok(match self._type {
+      PemOrDerKeyType::Pem => self.data.as_bytes().to_vec(),
+      PemOrDerKeyType::Der => base64::decode(&self.data)?,
+    })
+  }
+}
+
 pub fn api_crypto_digest(
   scope: &mut v8::HandleScope,
   args: v8::FunctionCallbackArguments,
@@ -55,6 +88,37 @@ pub fn api_crypto_digest(
   Ok(())
 }
 
+/// Seeded generator that replaces the OS source for `crypto.getRandomValues` and
+/// `crypto.randomUUID` when the worker runs in deterministic test mode. It lives in an isolate
+/// slot, so each app gets its own sequence, and is only installed by `BlueboatCtx::init` - see
+/// `pm::deterministic_rng_seed` for the conditions. The sequence restarts at every request, so
+/// that a request's output doesn't depend on the requests served before it.
+pub struct DeterministicRng {
+  seed: u64,
+  rng: RefCell<StdRng>,
+}
+
+impl DeterministicRng {
+  pub fn new(seed: u64) -> Self {
+    Self {
+      seed,
+      rng: RefCell::new(StdRng::seed_from_u64(seed)),
+    }
+  }
+
+  /// Restarts the sequence from the seed.
+  pub fn reset(&self) {
+    *self.rng.borrow_mut() = StdRng::seed_from_u64(self.seed);
+  }
+}
+
+fn fill_random(isolate: &mut v8::Isolate, out: &mut [u8]) {
+  match isolate.get_slot::<DeterministicRng>() {
+    Some(rng) => rng.rng.borrow_mut().fill(out),
+    None => rand::thread_rng().fill(out),
+  }
+}
+
 pub fn api_crypto_getrandom(
   scope: &mut v8::HandleScope,
   args: v8::FunctionCallbackArguments,
@@ -62,7 +126,7 @@ pub fn api_crypto_getrandom(
 ) -> Result<()> {
   let out = v8::Local::<v8::TypedArray>::try_from(args.get(1))?;
   let mut view = unsafe { v8_deref_typed_array_assuming_noalias(scope, out) };
-  rand::thread_rng().fill(&mut view[..]);
+  fill_random(scope, &mut view[..]);
   retval.set(out.into());
   Ok(())
 }
@@ -72,7 +136,12 @@ pub fn api_crypto_random_uuid(
   _args: v8::FunctionCallbackArguments,
   mut retval: v8::ReturnValue,
 ) -> Result<()> {
-  let uuid = uuid::Uuid::new_v4();
+  let mut bytes = [0u8; 16];
+  fill_random(scope, &mut bytes);
+  let uuid = uuid::Builder::from_bytes(bytes)
+    .set_variant(uuid::Variant::RFC4122)
+    .set_version(uuid::Version::Random)
+    .build();
   let uuid = v8::String::new(scope, &uuid.to_string()).unwrap();
   retval.set(uuid.into());
   Ok(())
@@ -91,3 +160,46 @@ pub fn api_crypto_constant_time_eq(
   retval.set(v8::Boolean::new(scope, eq).into());
   Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+  use super::DeterministicRng;
+  use crate::api::testutil::ApiTester;
+
+  fn seeded_values(seed: u64) -> (String, Vec<u8>) {
+    let mut tester = ApiTester::new();
+    tester.run(|scope| {
+      scope.set_slot(DeterministicRng::new(seed));
+    });
+    tester
+      .run_script("[crypto.randomUUID(), Array.from(crypto.getRandomValues(new Uint8Array(8)))]")
+  }
+
+  #[test]
+  fn seeded_rng_is_reproducible() {
+    let (uuid, bytes) = seeded_values(42);
+    assert_eq!(seeded_values(42), (uuid.clone(), bytes.clone()));
+    assert_ne!(seeded_values(43).0, uuid);
+    assert_eq!(uuid.as_bytes()[14], b'4');
+
+    let mut tester = ApiTester::new();
+    let a: String = tester.run_script("crypto.randomUUID()");
+    let b: String = tester.run_script("crypto.randomUUID()");
+    assert_ne!(a, b);
+  }
+
+  #[test]
+  fn reset_restarts_the_sequence() {
+    let mut tester = ApiTester::new();
+    tester.run(|scope| {
+      scope.set_slot(DeterministicRng::new(42));
+    });
+    let first: String = tester.run_script("crypto.randomUUID()");
+    let second: String = tester.run_script("crypto.randomUUID()");
+    assert_ne!(first, second);
+
+    tester.run(|scope| scope.get_slot::<DeterministicRng>().unwrap().reset());
+    let again: String = tester.run_script("crypto.randomUUID()");
+    assert_eq!(again, first);
+  }
+}
@@ -0,0 +1,148 @@
+use std::num::NonZeroU32;
+
+use anyhow::Result;
+use serde::Deserialize;
+use thiserror::Error;
+use v8;
+
+use crate::{
+  api::util::{v8_deserialize, ArrayBufferBuilder},
+  v8util::LocalValueExt,
+};
+
+/// Upper bound on the iteration count so that a single derivation cannot eat up the request's
+/// time budget.
+const MAX_ITERATIONS: u32 = 1_000_000;
+const MAX_OUTPUT_LENGTH: usize = 1024;
+
+#[derive(Error, Debug)]
+#[error("pbkdf2 iteration count must be between 1 and {}", MAX_ITERATIONS)]
+struct InvalidIterations;
+
+#[derive(Error, Debug)]
+#[error("pbkdf2 output length must be between 1 and {}", MAX_OUTPUT_LENGTH)]
+struct InvalidOutputLength;
+
+#[derive(Deserialize, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum Pbkdf2Hash {
+  Sha1,
+  Sha256,
+  Sha384,
+  Sha512,
+}
+
+impl Pbkdf2Hash {
+  fn algorithm(self) -> ring::pbkdf2::Algorithm {
+    match self {
+      Self::Sha1 => ring::pbkdf2::PBKDF2_HMAC_SHA1,
+      Self::Sha256 => ring::pbkdf2::PBKDF2_HMAC_SHA256,
+      Self::Sha384 => ring::pbkdf2::PBKDF2_HMAC_SHA384,
+      Self::Sha512 => ring::pbkdf2::PBKDF2_HMAC_SHA512,
+    }
+  }
+}
+
+pub fn api_crypto_pbkdf2(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let password = unsafe { args.get(1).read_bytes_assume_noalias(scope)? };
+  let salt = unsafe { args.get(2).read_bytes_assume_noalias(scope)? };
+  let iterations: u32 = v8_deserialize(scope, args.get(3))?;
+  let length: usize = v8_deserialize(scope, args.get(4))?;
+  let hash: Pbkdf2Hash = v8_deserialize(scope, args.get(5))?;
+
+  if iterations > MAX_ITERATIONS {
+    return Err(InvalidIterations.into());
+  }
+  let iterations = NonZeroU32::new(iterations).ok_or(InvalidIterations)?;
+  if length == 0 || length > MAX_OUTPUT_LENGTH {
+    return Err(InvalidOutputLength.into());
+  }
+
+  let mut out = ArrayBufferBuilder::new(scope, length);
+  ring::pbkdf2::derive(hash.algorithm(), iterations, &salt, &password, &mut out);
+  retval.set(out.build_uint8array(scope, None).into());
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::api::testutil::ApiTester;
+
+  fn derive_hex(tester: &mut ApiTester, password: &str, salt: &str, c: u32, len: u32) -> String {
+    tester.run_script(&format!(
+      r#"
+{{
+  const enc = new TextEncoder();
+  Codec.hexencode(NativeCrypto.PBKDF2.derive({{
+    password: enc.encode({:?}),
+    salt: enc.encode({:?}),
+    iterations: {},
+    length: {},
+    hash: "sha1",
+  }}));
+}}
+    "#,
+      password, salt, c, len
+    ))
+  }
+
+  #[test]
+  fn test_rfc6070_vectors() {
+    let mut tester = ApiTester::new();
+    assert_eq!(
+      derive_hex(&mut tester, "password", "salt", 1, 20),
+      "0c60c80f961f0e71f3a9b524af6012062fe037a6"
+    );
+    assert_eq!(
+      derive_hex(&mut tester, "password", "salt", 2, 20),
+      "ea6c014dc72d6f8ccd1ed92ace1d41f0d8de8957"
+    );
+    assert_eq!(
+      derive_hex(&mut tester, "password", "salt", 4096, 20),
+      "4b007901b765489abead49d926f721d065a429c1"
+    );
+    assert_eq!(
+      derive_hex(
+        &mut tester,
+        "passwordPASSWORDpassword",
+        "saltSALTsaltSALTsaltSALTsaltSALTsalt",
+        4096,
+        25
+      ),
+      "3d2eec4fe41c849b80c8d83662c0e44a8b291a964cf2f07038"
+    );
+    assert_eq!(
+      derive_hex(&mut tester, "pass\0word", "sa\0lt", 4096, 16),
+      "56fa6aa75548099dcc37d7f03425e0c3"
+    );
+  }
+
+  #[test]
+  fn test_iteration_cap() {
+    let mut tester = ApiTester::new();
+    let rejected: bool = tester.run_script(
+      r#"
+{
+  let rejected = false;
+  try {
+    NativeCrypto.PBKDF2.derive({
+      password: new Uint8Array(8),
+      salt: new Uint8Array(8),
+      iterations: 1000000000,
+      length: 32,
+      hash: "sha256",
+    });
+  } catch (e) {
+    rejected = true;
+  }
+  rejected;
+}
+    "#,
+    );
+    assert!(rejected);
+  }
+}
@@ -0,0 +1,179 @@
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use secp256k1::{
+  ecdsa::{RecoverableSignature, RecoveryId, Signature},
+  All, Message, PublicKey, Secp256k1, SecretKey,
+};
+use thiserror::Error;
+use v8;
+
+use crate::v8util::{create_uint8array_from_bytes, LocalValueExt};
+
+static SECP: Lazy<Secp256k1<All>> = Lazy::new(Secp256k1::new);
+
+#[derive(Error, Debug)]
+#[error("message hash must be exactly 32 bytes, got {0}")]
+struct InvalidMessageHashLength(usize);
+
+#[derive(Error, Debug)]
+#[error("recoverable signature must be exactly 65 bytes, got {0}")]
+struct InvalidRecoverableSignatureLength(usize);
+
+fn load_message_hash(hash: &[u8]) -> Result<Message> {
+  if hash.len() != 32 {
+    return Err(InvalidMessageHashLength(hash.len()).into());
+  }
+  Ok(Message::from_slice(hash)?)
+}
+
+fn load_recoverable_signature(sig: &[u8]) -> Result<RecoverableSignature> {
+  if sig.len() != 65 {
+    return Err(InvalidRecoverableSignatureLength(sig.len()).into());
+  }
+  let recid = RecoveryId::from_i32(sig[64] as i32)?;
+  Ok(RecoverableSignature::from_compact(&sig[..64], recid)?)
+}
+
+/// Signs a 32-byte message hash. The output is `r || s || recovery_id` (65 bytes).
+pub fn api_crypto_secp256k1_sign(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let secret = unsafe { args.get(1).read_bytes_assume_noalias(scope)? };
+  let hash = unsafe { args.get(2).read_bytes_assume_noalias(scope)? };
+  let secret = SecretKey::from_slice(&secret)?;
+  let hash = load_message_hash(&hash)?;
+  let (recid, sig) = SECP
+    .sign_ecdsa_recoverable(&hash, &secret)
+    .serialize_compact();
+  let mut out = [0u8; 65];
+  out[..64].copy_from_slice(&sig);
+  out[64] = recid.to_i32() as u8;
+  retval.set(create_uint8array_from_bytes(scope, &out).into());
+  Ok(())
+}
+
+/// Verifies a 64-byte (`r || s`) or 65-byte recoverable signature. Only low-S signatures are
+/// accepted.
+pub fn api_crypto_secp256k1_verify(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let public = unsafe { args.get(1).read_bytes_assume_noalias(scope)? };
+  let sig = unsafe { args.get(2).read_bytes_assume_noalias(scope)? };
+  let hash = unsafe { args.get(3).read_bytes_assume_noalias(scope)? };
+  let public = PublicKey::from_slice(&public)?;
+  let hash = load_message_hash(&hash)?;
+  let sig = if sig.len() == 65 {
+    &sig[..64]
+  } else {
+    &sig[..]
+  };
+  let ok = match Signature::from_compact(sig) {
+    Ok(sig) => SECP.verify_ecdsa(&hash, &sig, &public).is_ok(),
+    Err(_) => false,
+  };
+  retval.set(v8::Boolean::new(scope, ok).into());
+  Ok(())
+}
+
+/// Recovers the uncompressed (65-byte) public key from a recoverable signature.
+pub fn api_crypto_secp256k1_recover(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let sig = unsafe { args.get(1).read_bytes_assume_noalias(scope)? };
+  let hash = unsafe { args.get(2).read_bytes_assume_noalias(scope)? };
+  let sig = load_recoverable_signature(&sig)?;
+  let hash = load_message_hash(&hash)?;
+  let public = SECP.recover_ecdsa(&hash, &sig)?;
+  retval.set(create_uint8array_from_bytes(scope, &public.serialize_uncompressed()).into());
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::api::testutil::ApiTester;
+
+  // Secret key 1 signing SHA-256("Satoshi Nakamoto") with an RFC 6979 nonce, a common secp256k1
+  // test vector. The last byte of the signature is the recovery id.
+  const SECRET: &str = "0000000000000000000000000000000000000000000000000000000000000001";
+  const PUBLIC: &str = "0479be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798483ada7726a3c4655da4fbfc0e1108a8fd17b448a68554199c47d08ffb10d4b8";
+  const HASH: &str = "a0dc65ffca799873cbea0ac274015b9526505daaaed385155425f7337704883e";
+  const SIGNATURE: &str = "934b1ea10a4b3c1757e2b0c017d0b6143ce3c9a7e6a4a49860d7a6ab210ee3d82442ce9d2b916064108014783e923ec36b49743e2ffa1c4496f01a512aafd9e501";
+
+  #[test]
+  fn test_sign_verify_vector() {
+    let mut tester = ApiTester::new();
+    let sig: String = tester.run_script(&format!(
+      r#"Codec.hexencode(NativeCrypto.Secp256k1.sign(Codec.hexdecode("{}"), Codec.hexdecode("{}")));"#,
+      SECRET, HASH
+    ));
+    assert_eq!(sig, SIGNATURE);
+
+    let out: Vec<bool> = tester.run_script(&format!(
+      r#"
+{{
+  const pub = Codec.hexdecode("{}");
+  const sig = Codec.hexdecode("{}");
+  const hash = Codec.hexdecode("{}");
+  const otherHash = hash.slice();
+  otherHash[0] ^= 1;
+  [
+    NativeCrypto.Secp256k1.verify(pub, sig, hash),
+    NativeCrypto.Secp256k1.verify(pub, sig.subarray(0, 64), hash),
+    NativeCrypto.Secp256k1.verify(pub, sig, otherHash),
+  ];
+}}
+    "#,
+      PUBLIC, SIGNATURE, HASH
+    ));
+    assert_eq!(out, vec![true, true, false]);
+  }
+
+  #[test]
+  fn test_recover_public_key() {
+    let mut tester = ApiTester::new();
+    let public: String = tester.run_script(&format!(
+      r#"Codec.hexencode(NativeCrypto.Secp256k1.recover(Codec.hexdecode("{}"), Codec.hexdecode("{}")));"#,
+      SIGNATURE, HASH
+    ));
+    assert_eq!(public, PUBLIC);
+
+    // The other recovery id yields a different key, if any.
+    let out: String = tester.run_script(&format!(
+      r#"
+{{
+  const sig = Codec.hexdecode("{}");
+  sig[64] ^= 1;
+  try {{
+    Codec.hexencode(NativeCrypto.Secp256k1.recover(sig, Codec.hexdecode("{}")));
+  }} catch (e) {{
+    "error";
+  }}
+}}
+    "#,
+      SIGNATURE, HASH
+    ));
+    assert_ne!(out, PUBLIC);
+
+    let rejected: bool = tester.run_script(&format!(
+      r#"
+{{
+  let rejected = false;
+  try {{
+    NativeCrypto.Secp256k1.recover(Codec.hexdecode("{}").subarray(0, 64), Codec.hexdecode("{}"));
+  }} catch (e) {{
+    rejected = true;
+  }}
+  rejected;
+}}
+    "#,
+      SIGNATURE, HASH
+    ));
+    assert!(rejected);
+  }
+}
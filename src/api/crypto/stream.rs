@@ -0,0 +1,212 @@
+use anyhow::Result;
+use md5::{Digest, Md5};
+use serde::Deserialize;
+use thiserror::Error;
+use v8;
+
+use crate::{
+  api::util::v8_deserialize,
+  exec::Executor,
+  v8util::{create_uint8array_from_bytes, LocalValueExt},
+};
+
+/// Maximum number of in-flight streaming digests per request.
+const MAX_STREAMING_DIGESTS: usize = 256;
+
+#[derive(Error, Debug)]
+#[error("too many streaming digests")]
+struct TooManyDigests;
+
+#[derive(Error, Debug)]
+#[error("invalid digest handle")]
+struct InvalidHandle;
+
+#[derive(Deserialize, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamingDigestAlgorithm {
+  Sha1,
+  Sha256,
+  Sha384,
+  Sha512,
+  Blake3,
+  Md5,
+}
+
+pub enum StreamingDigest {
+  Ring(ring::digest::Context),
+  Blake3(Box<blake3::Hasher>),
+  Md5(Md5),
+}
+
+impl StreamingDigest {
+  fn new(alg: StreamingDigestAlgorithm) -> Self {
+    use StreamingDigestAlgorithm as A;
+    match alg {
+      A::Sha1 => Self::Ring(ring::digest::Context::new(
+        &ring::digest::SHA1_FOR_LEGACY_USE_ONLY,
+      )),
+      A::Sha256 => Self::Ring(ring::digest::Context::new(&ring::digest::SHA256)),
+      A::Sha384 => Self::Ring(ring::digest::Context::new(&ring::digest::SHA384)),
+      A::Sha512 => Self::Ring(ring::digest::Context::new(&ring::digest::SHA512)),
+      A::Blake3 => Self::Blake3(Box::new(blake3::Hasher::new())),
+      A::Md5 => Self::Md5(Md5::new()),
+    }
+  }
+
+  fn update(&mut self, data: &[u8]) {
+    match self {
+      Self::Ring(x) => x.update(data),
+      Self::Blake3(x) => {
+        x.update(data);
+      }
+      Self::Md5(x) => x.update(data),
+    }
+  }
+
+  fn finish(self) -> Vec<u8> {
+    match self {
+      Self::Ring(x) => x.finish().as_ref().to_vec(),
+      Self::Blake3(x) => x.finalize().as_bytes().to_vec(),
+      Self::Md5(x) => x.finalize().to_vec(),
+    }
+  }
+}
+
+pub fn api_crypto_digest_init(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let alg: StreamingDigestAlgorithm = v8_deserialize(scope, args.get(1))?;
+  let e = Executor::try_current_result()?.upgrade().unwrap();
+  let mut digests = e.digests.borrow_mut();
+  if digests.len() >= MAX_STREAMING_DIGESTS {
+    return Err(TooManyDigests.into());
+  }
+  let handle = e.allocate_handle();
+  digests.insert(handle, StreamingDigest::new(alg));
+  retval.set(v8::Integer::new_from_unsigned(scope, handle).into());
+  Ok(())
+}
+
+pub fn api_crypto_digest_update(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  _retval: v8::ReturnValue,
+) -> Result<()> {
+  let handle: u32 = v8_deserialize(scope, args.get(1))?;
+  let data = unsafe { args.get(2).read_bytes_assume_noalias(scope)? };
+  let e = Executor::try_current_result()?.upgrade().unwrap();
+  let mut digests = e.digests.borrow_mut();
+  digests.get_mut(&handle).ok_or(InvalidHandle)?.update(&data);
+  Ok(())
+}
+
+pub fn api_crypto_digest_final(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let handle: u32 = v8_deserialize(scope, args.get(1))?;
+  let e = Executor::try_current_result()?.upgrade().unwrap();
+  let digest = e
+    .digests
+    .borrow_mut()
+    .remove(&handle)
+    .ok_or(InvalidHandle)?;
+  let output = digest.finish();
+  retval.set(create_uint8array_from_bytes(scope, &output).into());
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use serde::de::DeserializeOwned;
+  use tokio::sync::watch;
+
+  use crate::{
+    api::testutil::{eval, ApiTester},
+    exec::Executor,
+    metadata::Metadata,
+  };
+
+  /// Digest handles belong to the executor, so scripts run in one.
+  fn run_in_executor<T: DeserializeOwned>(script: &str) -> T {
+    let metadata: Metadata = serde_json::from_value(serde_json::json!({
+      "version": "1",
+      "package": "",
+      "env": {},
+    }))
+    .unwrap();
+    let ctx = ApiTester::new().into_ctx(metadata);
+    let rt = tokio::runtime::Builder::new_current_thread()
+      .enable_all()
+      .build()
+      .unwrap();
+    tokio::task::LocalSet::new().block_on(&rt, async {
+      let (_cancel_tx, cancel) = watch::channel(());
+      let (exec, _spawn_activity) = Executor::new(ctx, "test".into(), cancel).unwrap();
+      Executor::enter(&exec.downgrade(), |scope| eval(scope, script)).unwrap()
+    })
+  }
+
+  #[test]
+  fn test_incremental_matches_one_shot() {
+    let out: Vec<bool> = run_in_executor(
+      r#"
+{
+  const data = new TextEncoder().encode("The quick brown fox jumps over the lazy dog");
+  ["sha1", "sha256", "sha384", "sha512", "blake3"].map(alg => {
+    const stream = new NativeCrypto.DigestStream(alg);
+    for (let i = 0; i < data.length; i += 7) {
+      stream.update(data.subarray(i, i + 7));
+    }
+    return Codec.hexencode(stream.final()) === Codec.hexencode(NativeCrypto.digest(alg, data));
+  });
+}
+    "#,
+    );
+    assert_eq!(out, vec![true; 5]);
+
+    let md5: String = run_in_executor(
+      r#"Codec.hexencode(new NativeCrypto.DigestStream("md5").update("hello ").update("world").final());"#,
+    );
+    assert_eq!(md5, "5eb63bbbe01eeed093cb22bb8f5acdc3");
+  }
+
+  #[test]
+  fn test_finalized_and_unknown_handles_are_rejected() {
+    let out: Vec<String> = run_in_executor(
+      r#"
+{
+  const attempt = f => {
+    try {
+      f();
+      return "ok";
+    } catch (e) {
+      return e.message;
+    }
+  };
+  const stream = new NativeCrypto.DigestStream("sha256");
+  [
+    attempt(() => stream.final()),
+    attempt(() => stream.final()),
+    attempt(() => stream.update("more")),
+    attempt(() => __blueboat_host_invoke("crypto_digest_update", 123456, new Uint8Array(1))),
+    attempt(() => __blueboat_host_invoke("crypto_digest_final", 123456)),
+  ];
+}
+    "#,
+    );
+    assert_eq!(
+      out,
+      vec![
+        "ok",
+        "invalid digest handle",
+        "invalid digest handle",
+        "invalid digest handle",
+        "invalid digest handle",
+      ]
+    );
+  }
+}
@@ -1,8 +1,13 @@
 use anyhow::Result;
 use mime_guess::MimeGuess;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use v8;
 
-use crate::api::util::mk_v8_string;
+use crate::{
+  api::util::{mk_v8_string, v8_serialize},
+  v8util::LocalValueExt,
+};
 
 pub fn api_dataset_mime_guess_by_ext(
   scope: &mut v8::HandleScope,
@@ -19,3 +24,236 @@ pub fn api_dataset_mime_guess_by_ext(
   }
   Ok(())
 }
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct DatasetMimeSniffResult {
+  mime: String,
+  confidence: DatasetMimeSniffConfidence,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum DatasetMimeSniffConfidence {
+  /// A signature unique to the format.
+  High,
+
+  /// A short signature, or a container shared by several formats (e.g. ZIP for
+  /// `.docx` and `.jar`).
+  Medium,
+
+  /// Guessed from the content looking like text.
+  Low,
+}
+
+use DatasetMimeSniffConfidence::{High, Low, Medium};
+
+/// Leading byte signatures. `None` matches any byte.
+const SIGNATURES: &[(&[Option<u8>], &str, DatasetMimeSniffConfidence)] = &[
+  (&sig(b"\x89PNG\r\n\x1a\n"), "image/png", High),
+  (&sig(b"\xff\xd8\xff"), "image/jpeg", High),
+  (&sig(b"GIF87a"), "image/gif", High),
+  (&sig(b"GIF89a"), "image/gif", High),
+  (&riff(b"WEBP"), "image/webp", High),
+  (&riff(b"WAVE"), "audio/wav", High),
+  (&riff(b"AVI "), "video/x-msvideo", High),
+  (&sig(b"II*\x00"), "image/tiff", High),
+  (&sig(b"MM\x00*"), "image/tiff", High),
+  (&sig(b"\x00\x00\x01\x00"), "image/x-icon", Medium),
+  (&sig(b"BM"), "image/bmp", Medium),
+  (&sig(b"%PDF-"), "application/pdf", High),
+  (&sig(b"PK\x03\x04"), "application/zip", Medium),
+  (&sig(b"PK\x05\x06"), "application/zip", Medium),
+  (&sig(b"\x1f\x8b\x08"), "application/gzip", High),
+  (&sig(b"BZh"), "application/x-bzip2", Medium),
+  (&sig(b"\xfd7zXZ\x00"), "application/x-xz", High),
+  (
+    &sig(b"7z\xbc\xaf\x27\x1c"),
+    "application/x-7z-compressed",
+    High,
+  ),
+  (&sig(b"\x28\xb5\x2f\xfd"), "application/zstd", High),
+  (&sig(b"OggS"), "application/ogg", High),
+  (&sig(b"fLaC"), "audio/flac", High),
+  (&sig(b"ID3"), "audio/mpeg", Medium),
+  (&sig(b"\x1a\x45\xdf\xa3"), "video/x-matroska", Medium),
+  (&sig(b"\x00asm"), "application/wasm", High),
+  (&sig(b"\x7fELF"), "application/x-executable", High),
+  (&sig(b"wOFF"), "font/woff", High),
+  (&sig(b"wOF2"), "font/woff2", High),
+  (
+    &sig(b"SQLite format 3\x00"),
+    "application/vnd.sqlite3",
+    High,
+  ),
+];
+
+const fn sig<const N: usize>(x: &[u8; N]) -> [Option<u8>; N] {
+  let mut out = [None; N];
+  let mut i = 0;
+  while i < N {
+    out[i] = Some(x[i]);
+    i += 1;
+  }
+  out
+}
+
+/// `RIFF`, a 4-byte size, then the form type.
+const fn riff(form: &[u8; 4]) -> [Option<u8>; 12] {
+  let mut out = [None; 12];
+  let mut i = 0;
+  while i < 4 {
+    out[i] = Some(b"RIFF"[i]);
+    out[i + 8] = Some(form[i]);
+    i += 1;
+  }
+  out
+}
+
+/// Detects the type of `data` from its leading bytes.
+fn sniff(data: &[u8]) -> Option<DatasetMimeSniffResult> {
+  let result = |mime: &str, confidence| {
+    Some(DatasetMimeSniffResult {
+      mime: mime.to_string(),
+      confidence,
+    })
+  };
+
+  for (signature, mime, confidence) in SIGNATURES {
+    if data.len() >= signature.len()
+      && signature
+        .iter()
+        .zip(data)
+        .all(|(s, d)| s.map(|s| s == *d).unwrap_or(true))
+    {
+      return result(mime, *confidence);
+    }
+  }
+
+  // ISO base media files: a box size, then `ftyp` and the major brand.
+  if data.len() >= 12 && &data[4..8] == b"ftyp" {
+    return match &data[8..12] {
+      b"avif" | b"avis" => result("image/avif", High),
+      b"heic" | b"heix" | b"mif1" => result("image/heic", High),
+      b"qt  " => result("video/quicktime", High),
+      b"M4A " => result("audio/mp4", High),
+      _ => result("video/mp4", High),
+    };
+  }
+
+  let text = data.strip_prefix(b"\xef\xbb\xbf").unwrap_or(data);
+  // The input may be a truncated prefix, which can cut a multi-byte character.
+  let text = match std::str::from_utf8(text) {
+    Ok(x) => x,
+    Err(e) if e.error_len().is_none() => std::str::from_utf8(&text[..e.valid_up_to()]).ok()?,
+    Err(_) => return None,
+  };
+  if text
+    .chars()
+    .any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c'))
+  {
+    return None;
+  }
+  let start = text.trim_start().get(..14).unwrap_or(text.trim_start());
+  let start = start.to_ascii_lowercase();
+  if start.starts_with("<?xml") {
+    result("application/xml", Medium)
+  } else if start.starts_with("<!doctype html") || start.starts_with("<html") {
+    result("text/html", Medium)
+  } else if start.starts_with("<svg") {
+    result("image/svg+xml", Medium)
+  } else if start.starts_with("%!ps") {
+    result("application/postscript", Medium)
+  } else if text.is_empty() {
+    None
+  } else {
+    result("text/plain", Low)
+  }
+}
+
+pub fn api_dataset_mime_guess_by_content(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let data = unsafe { args.get(1).read_bytes_assume_noalias(scope)? };
+  if let Some(x) = sniff(&data) {
+    retval.set(v8_serialize(scope, &x)?);
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{sniff, DatasetMimeSniffConfidence};
+
+  fn guess(data: &[u8]) -> Option<(String, DatasetMimeSniffConfidence)> {
+    sniff(data).map(|x| (x.mime, x.confidence))
+  }
+
+  fn assert_guess(data: &[u8], mime: &str, confidence: DatasetMimeSniffConfidence) {
+    assert_eq!(guess(data), Some((mime.to_string(), confidence)));
+  }
+
+  #[test]
+  fn binary_formats() {
+    use DatasetMimeSniffConfidence::*;
+    assert_guess(
+      b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR\x00\x00\x00\x10",
+      "image/png",
+      High,
+    );
+    assert_guess(b"\xff\xd8\xff\xe0\x00\x10JFIF\x00\x01", "image/jpeg", High);
+    assert_guess(b"GIF89a\x01\x00\x01\x00\x80\x00", "image/gif", High);
+    assert_guess(b"RIFF\x24\x08\x00\x00WEBPVP8 ", "image/webp", High);
+    assert_guess(b"%PDF-1.7\n%\xe2\xe3\xcf\xd3\n", "application/pdf", High);
+    assert_guess(
+      b"PK\x03\x04\x14\x00\x06\x00\x08\x00\x00\x00!\x00",
+      "application/zip",
+      Medium,
+    );
+    assert_guess(
+      b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x00\x03",
+      "application/gzip",
+      High,
+    );
+    assert_guess(b"\x00asm\x01\x00\x00\x00", "application/wasm", High);
+    assert_guess(
+      b"\x00\x00\x00\x20ftypisom\x00\x00\x02\x00isomiso2",
+      "video/mp4",
+      High,
+    );
+    assert_guess(
+      b"\x00\x00\x00\x1cftypavif\x00\x00\x00\x00avifmif1",
+      "image/avif",
+      High,
+    );
+  }
+
+  #[test]
+  fn text_formats() {
+    use DatasetMimeSniffConfidence::*;
+    assert_guess(
+      b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<root/>",
+      "application/xml",
+      Medium,
+    );
+    assert_guess(b"\n  <!DOCTYPE html>\n<html>", "text/html", Medium);
+    assert_guess(
+      b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>",
+      "image/svg+xml",
+      Medium,
+    );
+    assert_guess(b"\xef\xbb\xbfhello, world\n", "text/plain", Low);
+
+    // Truncated in the middle of a multi-byte character.
+    assert_guess(b"caf\xc3", "text/plain", Low);
+  }
+
+  #[test]
+  fn unknown() {
+    assert_eq!(guess(b""), None);
+    assert_eq!(guess(b"\x89PN"), None);
+    assert_eq!(guess(b"\x00\x01\x02\x03\x04"), None);
+    assert_eq!(guess(b"abc\xff\xfedef"), None);
+  }
+}
@@ -1,2 +1,5 @@
 pub mod aws;
+pub mod azure;
+pub mod gcs;
 pub mod s3;
+pub mod sigv4;
@@ -0,0 +1,439 @@
+//! AWS Signature Version 4.
+//!
+//! https://docs.aws.amazon.com/general/latest/gr/sigv4_signing.html
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use percent_encoding::percent_decode_str;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use url::Url;
+
+pub const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+
+/// Payload hash for requests whose body is not signed, like presigned URLs.
+pub const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+/// Longest validity of a presigned URL.
+pub const MAX_PRESIGN_EXPIRY_SECS: u64 = 7 * 24 * 3600;
+
+#[derive(Error, Debug)]
+#[error("presigned url expiry must be between 1 and 604800 seconds (7 days), got {0}")]
+pub struct InvalidPresignExpiry(pub u64);
+
+pub struct SigV4Credentials<'a> {
+  pub access_key: &'a str,
+  pub secret_key: &'a str,
+  pub session_token: Option<&'a str>,
+}
+
+/// Where and when a request is signed.
+pub struct SigV4Scope<'a> {
+  pub region: &'a str,
+  pub service: &'a str,
+  pub time: DateTime<Utc>,
+}
+
+impl<'a> SigV4Scope<'a> {
+  fn date(&self) -> String {
+    self.time.format("%Y%m%d").to_string()
+  }
+
+  fn timestamp(&self) -> String {
+    self.time.format("%Y%m%dT%H%M%SZ").to_string()
+  }
+
+  fn credential_scope(&self) -> String {
+    format!(
+      "{}/{}/{}/aws4_request",
+      self.date(),
+      self.region,
+      self.service
+    )
+  }
+}
+
+/// Percent-encodes everything except unreserved characters, and `/` unless `encode_slash`.
+pub fn uri_encode(s: &str, encode_slash: bool) -> String {
+  let mut out = String::with_capacity(s.len());
+  for b in s.bytes() {
+    match b {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => out.push(b as char),
+      b'/' if !encode_slash => out.push('/'),
+      _ => out.push_str(&format!("%{:02X}", b)),
+    }
+  }
+  out
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+  hex::encode(Sha256::digest(data))
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+  let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac accepts any key length");
+  mac.update(data.as_bytes());
+  mac.finalize().into_bytes().to_vec()
+}
+
+/// Query parameters, encoded and sorted as in the canonical request.
+pub fn canonical_query_string(params: &[(String, String)]) -> String {
+  let mut encoded = params
+    .iter()
+    .map(|(k, v)| (uri_encode(k, true), uri_encode(v, true)))
+    .collect::<Vec<_>>();
+  encoded.sort();
+  encoded
+    .into_iter()
+    .map(|(k, v)| format!("{}={}", k, v))
+    .collect::<Vec<_>>()
+    .join("&")
+}
+
+/// Lowercases header names and trims values, joining repeated headers with `,`.
+pub fn canonical_headers<'b>(
+  headers: impl IntoIterator<Item = (&'b str, &'b str)>,
+) -> BTreeMap<String, String> {
+  let mut out: BTreeMap<String, String> = BTreeMap::new();
+  for (k, v) in headers {
+    let v = v.split_whitespace().collect::<Vec<_>>().join(" ");
+    out
+      .entry(k.to_ascii_lowercase())
+      .and_modify(|x| {
+        x.push(',');
+        x.push_str(&v);
+      })
+      .or_insert(v);
+  }
+  out
+}
+
+pub fn signed_headers(headers: &BTreeMap<String, String>) -> String {
+  headers.keys().cloned().collect::<Vec<_>>().join(";")
+}
+
+/// `canonical_uri` is the already encoded path. `headers` come from `canonical_headers`.
+pub fn canonical_request(
+  method: &str,
+  canonical_uri: &str,
+  canonical_query: &str,
+  headers: &BTreeMap<String, String>,
+  payload_hash: &str,
+) -> String {
+  let mut out = format!("{}\n{}\n{}\n", method, canonical_uri, canonical_query);
+  for (k, v) in headers {
+    out.push_str(k);
+    out.push(':');
+    out.push_str(v);
+    out.push('\n');
+  }
+  out.push('\n');
+  out.push_str(&signed_headers(headers));
+  out.push('\n');
+  out.push_str(payload_hash);
+  out
+}
+
+/// Signs a canonical request and returns the hex signature.
+pub fn signature(creds: &SigV4Credentials, scope: &SigV4Scope, canonical_request: &str) -> String {
+  let string_to_sign = format!(
+    "{}\n{}\n{}\n{}",
+    ALGORITHM,
+    scope.timestamp(),
+    scope.credential_scope(),
+    sha256_hex(canonical_request.as_bytes())
+  );
+  let key = hmac_sha256(
+    format!("AWS4{}", creds.secret_key).as_bytes(),
+    &scope.date(),
+  );
+  let key = hmac_sha256(&key, scope.region);
+  let key = hmac_sha256(&key, scope.service);
+  let key = hmac_sha256(&key, "aws4_request");
+  hex::encode(hmac_sha256(&key, &string_to_sign))
+}
+
+/// A request to presign. `headers` must include `host`, and the client must send all of them with
+/// the same values.
+pub struct PresignRequest<'a> {
+  pub method: &'a str,
+  pub base_url: &'a str,
+
+  /// Already encoded path.
+  pub canonical_uri: &'a str,
+
+  /// The request's own query parameters, unencoded.
+  pub query: &'a [(String, String)],
+  pub headers: &'a [(&'a str, &'a str)],
+  pub expires_in_secs: u64,
+}
+
+/// Builds a presigned URL with the signature in the query string.
+pub fn presign_url(
+  creds: &SigV4Credentials,
+  scope: &SigV4Scope,
+  req: &PresignRequest,
+) -> Result<String, InvalidPresignExpiry> {
+  if req.expires_in_secs == 0 || req.expires_in_secs > MAX_PRESIGN_EXPIRY_SECS {
+    return Err(InvalidPresignExpiry(req.expires_in_secs));
+  }
+  let headers = canonical_headers(req.headers.iter().copied());
+  let mut params = req.query.to_vec();
+  params.extend([
+    ("X-Amz-Algorithm".to_string(), ALGORITHM.to_string()),
+    (
+      "X-Amz-Credential".to_string(),
+      format!("{}/{}", creds.access_key, scope.credential_scope()),
+    ),
+    ("X-Amz-Date".to_string(), scope.timestamp()),
+    ("X-Amz-Expires".to_string(), req.expires_in_secs.to_string()),
+    ("X-Amz-SignedHeaders".to_string(), signed_headers(&headers)),
+  ]);
+  if let Some(token) = creds.session_token {
+    params.push(("X-Amz-Security-Token".to_string(), token.to_string()));
+  }
+  let query = canonical_query_string(&params);
+  let request = canonical_request(
+    req.method,
+    req.canonical_uri,
+    &query,
+    &headers,
+    UNSIGNED_PAYLOAD,
+  );
+  let sig = signature(creds, scope, &request);
+  Ok(format!(
+    "{}{}?{}&X-Amz-Signature={}",
+    req.base_url, req.canonical_uri, query, sig
+  ))
+}
+
+/// Signs a request with the `Authorization` header. `headers` must include `host` and everything
+/// else that should be signed. Returns the headers to add to the request: `x-amz-date`,
+/// `x-amz-security-token` with temporary credentials, and `authorization`.
+pub fn sign_headers(
+  creds: &SigV4Credentials,
+  scope: &SigV4Scope,
+  method: &str,
+  canonical_uri: &str,
+  canonical_query: &str,
+  headers: &[(&str, &str)],
+  payload_hash: &str,
+) -> Vec<(String, String)> {
+  let timestamp = scope.timestamp();
+  let mut added = vec![("x-amz-date".to_string(), timestamp)];
+  if let Some(token) = creds.session_token {
+    added.push(("x-amz-security-token".to_string(), token.to_string()));
+  }
+  let all = canonical_headers(
+    headers
+      .iter()
+      .copied()
+      .chain(added.iter().map(|(k, v)| (k.as_str(), v.as_str()))),
+  );
+  let request = canonical_request(method, canonical_uri, canonical_query, &all, payload_hash);
+  let sig = signature(creds, scope, &request);
+  added.push((
+    "authorization".to_string(),
+    format!(
+      "{} Credential={}/{},SignedHeaders={},Signature={}",
+      ALGORITHM,
+      creds.access_key,
+      scope.credential_scope(),
+      signed_headers(&all),
+      sig
+    ),
+  ));
+  added
+}
+
+/// The canonical URI of `url`. Each path segment is decoded and encoded again, so
+/// `/example%20space/` and `/ሴ` are both handled.
+pub fn canonical_uri(url: &Url) -> String {
+  url
+    .path()
+    .split('/')
+    .map(|x| uri_encode(&percent_decode_str(x).decode_utf8_lossy(), true))
+    .collect::<Vec<_>>()
+    .join("/")
+}
+
+/// The `host` header of a request to `url`.
+pub fn host_header(url: &Url) -> String {
+  match url.port() {
+    Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+    None => url.host_str().unwrap_or_default().to_string(),
+  }
+}
+
+/// Signs a request to any AWS service with the `Authorization` header. The host, path and query
+/// are taken from `url`, and `headers` are the other headers to sign. Returns the headers to add
+/// like `sign_headers`.
+pub fn sign_request(
+  creds: &SigV4Credentials,
+  scope: &SigV4Scope,
+  method: &str,
+  url: &Url,
+  headers: &[(&str, &str)],
+  payload_hash: &str,
+) -> Vec<(String, String)> {
+  let host = host_header(url);
+  let query = url
+    .query_pairs()
+    .map(|(k, v)| (k.into_owned(), v.into_owned()))
+    .collect::<Vec<_>>();
+  let mut all = vec![("host", host.as_str())];
+  all.extend(
+    headers
+      .iter()
+      .copied()
+      .filter(|(k, _)| !k.eq_ignore_ascii_case("host")),
+  );
+  sign_headers(
+    creds,
+    scope,
+    method,
+    &canonical_uri(url),
+    &canonical_query_string(&query),
+    &all,
+    payload_hash,
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::{TimeZone, Utc};
+  use url::Url;
+
+  use super::{sha256_hex, sign_request, SigV4Credentials, SigV4Scope};
+
+  /// Signs a request from the AWS SigV4 test suite and returns its `Authorization` header.
+  fn suite_authorization(
+    method: &str,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+    session_token: Option<&str>,
+  ) -> String {
+    let creds = SigV4Credentials {
+      access_key: "AKIDEXAMPLE",
+      secret_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+      session_token,
+    };
+    let scope = SigV4Scope {
+      region: "us-east-1",
+      service: "service",
+      time: Utc.ymd(2015, 8, 30).and_hms(12, 36, 0),
+    };
+    let added = sign_request(
+      &creds,
+      &scope,
+      method,
+      &Url::parse(url).unwrap(),
+      headers,
+      &sha256_hex(body),
+    );
+    assert_eq!(added[0], ("x-amz-date".into(), "20150830T123600Z".into()));
+    added
+      .into_iter()
+      .find(|(k, _)| k == "authorization")
+      .unwrap()
+      .1
+  }
+
+  fn suite_signature(method: &str, url: &str) -> String {
+    let auth = suite_authorization(method, url, &[], b"", None);
+    assert!(auth.contains(",SignedHeaders=host;x-amz-date,"), "{}", auth);
+    auth.rsplit_once("Signature=").unwrap().1.to_string()
+  }
+
+  #[test]
+  fn aws_test_suite() {
+    // https://docs.aws.amazon.com/general/latest/gr/signature-v4-test-suite.html
+    let cases = [
+      (
+        "GET",
+        "https://example.amazonaws.com/",
+        "5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31",
+      ),
+      (
+        "POST",
+        "https://example.amazonaws.com/",
+        "5da7c1a2acd57cee7505fc6676e4e544621c30862966e37dddb68e92efbe5d6b",
+      ),
+      (
+        "GET",
+        "https://example.amazonaws.com/?Param2=value2&Param1=value1",
+        "b97d918cfa904a5beff61c982a1b6f458b799221646efd99d3219ec94cdf2500",
+      ),
+      (
+        "GET",
+        "https://example.amazonaws.com/?Param1=value1",
+        "a67d582fa61cc504c4bae71f336f98b97f1ea3c7a6bfe1b6e45aec72011b9aeb",
+      ),
+      (
+        "GET",
+        "https://example.amazonaws.com/-._~0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz",
+        "07ef7494c76fa4850883e2b006601f940f8a34d404d0cfa977f52a65bbf5f24f",
+      ),
+      (
+        "GET",
+        "https://example.amazonaws.com/ሴ",
+        "8318018e0b0f223aa2bbf98705b62bb787dc9c0e678f255a891fd03141be5d85",
+      ),
+      (
+        "GET",
+        "https://example.amazonaws.com/example space/",
+        "652487583200325589f1fba4c7e578f72c47cb61beeca81406b39ddec1366741",
+      ),
+    ];
+    for (method, url, signature) in cases {
+      assert_eq!(
+        suite_signature(method, url),
+        signature,
+        "{} {}",
+        method,
+        url
+      );
+    }
+  }
+
+  #[test]
+  fn aws_test_suite_with_headers_and_body() {
+    let auth = suite_authorization(
+      "POST",
+      "https://example.amazonaws.com/",
+      &[("Content-Type", "application/x-www-form-urlencoded")],
+      b"Param1=value1",
+      None,
+    );
+    assert_eq!(
+      auth,
+      "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request,\
+       SignedHeaders=content-type;host;x-amz-date,\
+       Signature=ff11897932ad3f4e8b18135d722051e5ac45fc38421b1da7b9d196a0fe09473a"
+    );
+
+    let auth = suite_authorization(
+      "POST",
+      "https://example.amazonaws.com/",
+      &[],
+      b"",
+      Some(
+        "AQoDYXdzEPT//////////wEXAMPLEtc764bNrC9SAPBSM22wDOk4x4HIZ8j4FZTwdQWLWsKWHGBuFqwAeMicRX\
+            mxfpSPfIeoIYRqTflfKD8YUuwthAx7mSEI/qkPpKPi/kMcGdQrmGdeehM4IC1NtBmUpp2wUE8phUZampKsburED\
+            y0KPkyQDYwT7WZ0wq5VSXDvp75YU9HFvlRd8Tx6q6fE8YQcHNVXAkiY9q6d+xo0rKwT38xVqr7ZD0u0iPPkUL6\
+            4lIZbqBAz+scqKmlzm8FDrypNC9Yjc8fPOLn9FX9KSYvKTr4rvx3iSIlTJabIQwj2ICCR/oLxBA==",
+      ),
+    );
+    assert!(
+      auth.ends_with(
+        "SignedHeaders=host;x-amz-date;x-amz-security-token,\
+         Signature=85d96828115b5dc0cfc3bd16ad9e210dd772bbebba041836c64533a82be05ead"
+      ),
+      "{}",
+      auth
+    );
+  }
+}
@@ -0,0 +1,415 @@
+use std::{
+  collections::HashMap,
+  time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use thiserror::Error;
+use v8;
+
+use crate::{
+  api::util::{v8_invoke_callback, v8_serialize},
+  exec::Executor,
+  metadata::FcmMetadata,
+  v8util::FunctionCallbackArgumentsExt,
+};
+
+use super::util::v8_deserialize;
+
+const FCM_SCOPE: &str = "https://www.googleapis.com/auth/firebase.messaging";
+
+const DEFAULT_TOKEN_URI: &str = "https://oauth2.googleapis.com/token";
+
+/// Lifetime of the JWT assertion exchanged for an access token. Google allows at most one hour.
+const ASSERTION_LIFETIME_SECS: u64 = 3600;
+
+/// Access tokens are refreshed this long before they expire.
+const TOKEN_EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+/// FCM error codes meaning that the device token will never work again.
+const INVALID_TOKEN_ERROR_CODES: &[&str] = &["UNREGISTERED", "SENDER_ID_MISMATCH"];
+
+#[derive(Error, Debug)]
+#[error("invalid fcm service account: {0}")]
+struct InvalidFcmServiceAccount(String);
+
+#[derive(Error, Debug)]
+#[error("fcm access token request failed with status {status}: {body}")]
+struct FcmTokenRequestFailed {
+  status: u16,
+  body: String,
+}
+
+#[derive(Deserialize)]
+struct ServiceAccountKeyJson {
+  project_id: String,
+  client_email: String,
+  private_key: String,
+  #[serde(default)]
+  token_uri: Option<String>,
+}
+
+struct FcmAccessToken {
+  token: String,
+  expires_at: Instant,
+}
+
+/// Sends messages with the FCM HTTP v1 API, authenticating as a service account.
+pub struct FcmClient {
+  project_id: String,
+  client_email: String,
+  token_uri: String,
+  key: EncodingKey,
+
+  /// Held while refreshing, so that concurrent sends wait for one token request.
+  token: tokio::sync::Mutex<Option<FcmAccessToken>>,
+}
+
+#[derive(Serialize)]
+struct FcmAssertionClaims<'a> {
+  iss: &'a str,
+  scope: &'a str,
+  aud: &'a str,
+  iat: u64,
+  exp: u64,
+}
+
+#[derive(Deserialize)]
+struct FcmTokenResponse {
+  access_token: String,
+  expires_in: u64,
+}
+
+impl FcmClient {
+  pub fn from_metadata(md: &FcmMetadata) -> Result<Self> {
+    let key: ServiceAccountKeyJson = serde_json::from_str(&md.service_account)
+      .map_err(|e| InvalidFcmServiceAccount(e.to_string()))?;
+    Ok(Self {
+      project_id: md.project_id.clone().unwrap_or(key.project_id),
+      key: EncodingKey::from_rsa_pem(key.private_key.as_bytes())
+        .map_err(|e| InvalidFcmServiceAccount(e.to_string()))?,
+      client_email: key.client_email,
+      token_uri: key
+        .token_uri
+        .unwrap_or_else(|| DEFAULT_TOKEN_URI.to_string()),
+      token: tokio::sync::Mutex::new(None),
+    })
+  }
+
+  fn assertion(&self, now: u64) -> Result<String> {
+    let claims = FcmAssertionClaims {
+      iss: &self.client_email,
+      scope: FCM_SCOPE,
+      aud: &self.token_uri,
+      iat: now,
+      exp: now + ASSERTION_LIFETIME_SECS,
+    };
+    Ok(jsonwebtoken::encode(
+      &Header::new(Algorithm::RS256),
+      &claims,
+      &self.key,
+    )?)
+  }
+
+  /// Returns a cached access token, or exchanges a new assertion for one.
+  async fn access_token(&self, http: &reqwest::Client) -> Result<String> {
+    let mut token = self.token.lock().await;
+    if let Some(x) = &*token {
+      if x.expires_at > Instant::now() + TOKEN_EXPIRY_MARGIN {
+        return Ok(x.token.clone());
+      }
+    }
+    let assertion = self.assertion(chrono::Utc::now().timestamp() as u64)?;
+    let res = http
+      .post(&self.token_uri)
+      .form(&[
+        ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+        ("assertion", assertion.as_str()),
+      ])
+      .send()
+      .await?;
+    let status = res.status();
+    if !status.is_success() {
+      return Err(
+        FcmTokenRequestFailed {
+          status: status.as_u16(),
+          body: res.text().await.unwrap_or_default(),
+        }
+        .into(),
+      );
+    }
+    let res: FcmTokenResponse = res.json().await?;
+    *token = Some(FcmAccessToken {
+      token: res.access_token.clone(),
+      expires_at: Instant::now() + Duration::from_secs(res.expires_in),
+    });
+    Ok(res.access_token)
+  }
+
+  async fn send(&self, http: &reqwest::Client, req: &FcmRequest) -> Result<FcmResponse> {
+    let token = self.access_token(http).await?;
+    let res = http
+      .post(format!(
+        "https://fcm.googleapis.com/v1/projects/{}/messages:send",
+        self.project_id
+      ))
+      .bearer_auth(token)
+      .json(&fcm_message(req))
+      .send()
+      .await?;
+    let code = res.status().as_u16();
+    let body = res.bytes().await?;
+    Ok(parse_fcm_response(code, &body))
+  }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct FcmRequest {
+  device_token: String,
+  #[serde(default)]
+  notification: Option<FcmNotification>,
+
+  /// Values must be strings, as required by FCM.
+  #[serde(default)]
+  data: HashMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+struct FcmNotification {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  title: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  body: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  image: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct FcmResponse {
+  error: Option<FcmError>,
+
+  /// Like `projects/myproject/messages/0:1500415314455276%31bd1c9631bd1c96`.
+  message_id: Option<String>,
+  code: u16,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+struct FcmError {
+  /// The gRPC status, like `NOT_FOUND` or `INVALID_ARGUMENT`.
+  status: String,
+
+  /// The FCM specific error code, like `UNREGISTERED` or `QUOTA_EXCEEDED`.
+  error_code: Option<String>,
+  message: String,
+
+  /// The device token will never work again and should be removed.
+  token_invalid: bool,
+}
+
+fn fcm_message(req: &FcmRequest) -> serde_json::Value {
+  let mut message = serde_json::json!({ "token": req.device_token });
+  if let Some(x) = &req.notification {
+    message["notification"] = serde_json::to_value(x).unwrap_or_default();
+  }
+  if !req.data.is_empty() {
+    message["data"] = serde_json::to_value(&req.data).unwrap_or_default();
+  }
+  serde_json::json!({ "message": message })
+}
+
+fn parse_fcm_response(code: u16, body: &[u8]) -> FcmResponse {
+  #[derive(Deserialize)]
+  struct SendResponse {
+    name: String,
+  }
+
+  #[derive(Deserialize)]
+  struct ErrorResponse {
+    error: ErrorBody,
+  }
+
+  #[derive(Deserialize)]
+  struct ErrorBody {
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    details: Vec<ErrorDetail>,
+  }
+
+  #[derive(Deserialize)]
+  #[serde(rename_all = "camelCase")]
+  struct ErrorDetail {
+    #[serde(default, rename = "@type")]
+    _type: String,
+    error_code: Option<String>,
+  }
+
+  if (200..300).contains(&code) {
+    if let Ok(x) = serde_json::from_slice::<SendResponse>(body) {
+      return FcmResponse {
+        error: None,
+        message_id: Some(x.name),
+        code,
+      };
+    }
+  }
+
+  let error = match serde_json::from_slice::<ErrorResponse>(body) {
+    Ok(x) => {
+      let error_code = x
+        .error
+        .details
+        .into_iter()
+        .filter(|x| x._type.ends_with("google.firebase.fcm.v1.FcmError"))
+        .find_map(|x| x.error_code);
+      FcmError {
+        token_invalid: error_code
+          .as_deref()
+          .map(|x| INVALID_TOKEN_ERROR_CODES.contains(&x))
+          .unwrap_or(false),
+        status: x.error.status,
+        error_code,
+        message: x.error.message,
+      }
+    }
+    Err(_) => FcmError {
+      status: "UNKNOWN".into(),
+      error_code: None,
+      message: String::from_utf8_lossy(body).into_owned(),
+      token_invalid: false,
+    },
+  };
+  FcmResponse {
+    error: Some(error),
+    message_id: None,
+    code,
+  }
+}
+
+pub fn api_fcm_send(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  _retval: v8::ReturnValue,
+) -> Result<()> {
+  #[derive(Error, Debug)]
+  #[error("fcm key not found: {0}")]
+  struct NotFound(String);
+
+  let key = v8::Local::<v8::String>::try_from(args.get(1))?.to_rust_string_lossy(scope);
+  let exec = Executor::try_current_result()?;
+  let ctx = exec.upgrade().unwrap().ctx;
+  let client = match ctx.fcm.get(&key) {
+    Some(x) => x,
+    None => return Err(NotFound(key).into()),
+  };
+
+  let req: FcmRequest = v8_deserialize(scope, args.get(2))?;
+  let callback = v8::Global::new(scope, args.load_function_at(3)?);
+  let exec_2 = exec.clone();
+  Executor::spawn(&exec_2, async move {
+    let res = client.send(&ctx.http_client, &req).await;
+    Executor::enter(&exec, |scope| {
+      let res = res.and_then(|x| v8_serialize(scope, &x));
+      v8_invoke_callback("fcm_send", scope, res, &callback);
+    });
+  });
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{fcm_message, parse_fcm_response, FcmNotification, FcmRequest};
+
+  #[test]
+  fn builds_message() {
+    let req = FcmRequest {
+      device_token: "tok".into(),
+      notification: Some(FcmNotification {
+        title: Some("Hi".into()),
+        body: None,
+        image: None,
+      }),
+      data: [("k".to_string(), "v".to_string())].into_iter().collect(),
+    };
+    assert_eq!(
+      fcm_message(&req),
+      serde_json::json!({
+        "message": {
+          "token": "tok",
+          "notification": { "title": "Hi" },
+          "data": { "k": "v" },
+        }
+      })
+    );
+  }
+
+  #[test]
+  fn parses_success() {
+    let res = parse_fcm_response(
+      200,
+      br#"{"name": "projects/myproject/messages/0:1500415314455276%31bd1c9631bd1c96"}"#,
+    );
+    assert!(res.error.is_none());
+    assert_eq!(
+      res.message_id.as_deref(),
+      Some("projects/myproject/messages/0:1500415314455276%31bd1c9631bd1c96")
+    );
+  }
+
+  #[test]
+  fn parses_unregistered_token() {
+    let res = parse_fcm_response(
+      404,
+      br#"{
+        "error": {
+          "code": 404,
+          "message": "Requested entity was not found.",
+          "status": "NOT_FOUND",
+          "details": [
+            {
+              "@type": "type.googleapis.com/google.firebase.fcm.v1.FcmError",
+              "errorCode": "UNREGISTERED"
+            }
+          ]
+        }
+      }"#,
+    );
+    let error = res.error.unwrap();
+    assert_eq!(res.code, 404);
+    assert_eq!(error.status, "NOT_FOUND");
+    assert_eq!(error.error_code.as_deref(), Some("UNREGISTERED"));
+    assert!(error.token_invalid);
+  }
+
+  #[test]
+  fn parses_other_errors() {
+    let res = parse_fcm_response(
+      400,
+      br#"{
+        "error": {
+          "code": 400,
+          "message": "Invalid value at 'message.data[0].value'",
+          "status": "INVALID_ARGUMENT",
+          "details": [
+            {
+              "@type": "type.googleapis.com/google.firebase.fcm.v1.FcmError",
+              "errorCode": "INVALID_ARGUMENT"
+            }
+          ]
+        }
+      }"#,
+    );
+    assert!(!res.error.unwrap().token_invalid);
+
+    let res = parse_fcm_response(502, b"Bad Gateway");
+    let error = res.error.unwrap();
+    assert_eq!(error.message, "Bad Gateway");
+    assert!(!error.token_invalid);
+  }
+}
@@ -0,0 +1,257 @@
+use std::convert::TryFrom;
+
+use anyhow::Result;
+use image::{
+  codecs::gif::{GifEncoder, Repeat},
+  Delay, Frame, RgbaImage,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use v8;
+
+use crate::{
+  api::util::{v8_deref_typed_array_assuming_noalias, v8_deserialize},
+  v8util::create_uint8array_from_bytes,
+};
+
+use super::{
+  codec::read_rgba,
+  raster::{check_dimensions, MAX_IMAGE_PIXELS},
+  CanvasConfig,
+};
+
+/// Maximum number of frames in one animation.
+pub const MAX_ANIMATION_FRAMES: usize = 512;
+
+/// Maximum number of pixels summed over all frames of one animation.
+pub const MAX_ANIMATION_PIXELS: u64 = 4 * MAX_IMAGE_PIXELS;
+
+#[derive(Serialize, Deserialize, JsonSchema, Copy, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum GraphicsAnimationFormat {
+  Gif,
+  Apng,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct GraphicsAnimationFrame {
+  pub canvas: CanvasConfig,
+  pub delay_ms: u16,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct GraphicsAnimationEncodeConfig {
+  pub format: GraphicsAnimationFormat,
+
+  /// Number of times the animation is played. Zero loops forever.
+  pub loop_count: u16,
+
+  pub frames: Vec<GraphicsAnimationFrame>,
+}
+
+#[derive(Error, Debug)]
+#[error("animation frame count out of range")]
+pub struct InvalidFrameCount;
+
+#[derive(Error, Debug)]
+#[error("animation exceeds the pixel budget")]
+pub struct AnimationTooLarge;
+
+#[derive(Error, Debug)]
+#[error("all animation frames must have the same dimensions")]
+struct FrameDimensionMismatch;
+
+#[derive(Error, Debug)]
+#[error("expected {0} frame rasters, got {1}")]
+struct FrameRasterCountMismatch(usize, u32);
+
+/// A frame converted to RGBA8888 (unpremultiplied).
+pub struct RgbaFrame {
+  pub pixels: Vec<u8>,
+  pub delay_ms: u16,
+}
+
+/// Checks frame count, frame dimensions and the total pixel budget before any pixel is read.
+fn check_frames(frames: &[GraphicsAnimationFrame]) -> Result<(u32, u32)> {
+  if frames.is_empty() || frames.len() > MAX_ANIMATION_FRAMES {
+    return Err(InvalidFrameCount.into());
+  }
+  let dims = &frames[0].canvas.dimensions;
+  if frames
+    .iter()
+    .any(|x| x.canvas.dimensions.width != dims.width || x.canvas.dimensions.height != dims.height)
+  {
+    return Err(FrameDimensionMismatch.into());
+  }
+  let (width, height) = (
+    u32::try_from(dims.width).unwrap_or(0),
+    u32::try_from(dims.height).unwrap_or(0),
+  );
+  check_dimensions(width, height)?;
+  if width as u64 * height as u64 * frames.len() as u64 > MAX_ANIMATION_PIXELS {
+    return Err(AnimationTooLarge.into());
+  }
+  Ok((width, height))
+}
+
+pub fn encode_animation(
+  format: GraphicsAnimationFormat,
+  loop_count: u16,
+  width: u32,
+  height: u32,
+  frames: Vec<RgbaFrame>,
+) -> Result<Vec<u8>> {
+  let mut out = vec![];
+  match format {
+    GraphicsAnimationFormat::Gif => {
+      let mut enc = GifEncoder::new_with_speed(&mut out, 10);
+      enc.set_repeat(match loop_count {
+        0 => Repeat::Infinite,
+        n => Repeat::Finite(n),
+      })?;
+      for frame in frames {
+        let image =
+          RgbaImage::from_raw(width, height, frame.pixels).ok_or(FrameDimensionMismatch)?;
+        enc.encode_frame(Frame::from_parts(
+          image,
+          0,
+          0,
+          Delay::from_numer_denom_ms(frame.delay_ms as u32, 1),
+        ))?;
+      }
+    }
+    GraphicsAnimationFormat::Apng => {
+      let mut enc = png::Encoder::new(&mut out, width, height);
+      enc.set_color(png::ColorType::Rgba);
+      enc.set_depth(png::BitDepth::Eight);
+      enc.set_animated(frames.len() as u32, loop_count as u32)?;
+      let mut writer = enc.write_header()?;
+      for frame in frames {
+        writer.set_frame_delay(frame.delay_ms, 1000)?;
+        writer.write_image_data(&frame.pixels)?;
+      }
+      writer.finish()?;
+    }
+  }
+  Ok(out)
+}
+
+/// Encodes canvases into an animated GIF or APNG. Takes the encode config and an array with the
+/// framebuffer of each frame, in the same order as `frames`.
+pub fn api_graphics_animation_encode(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let cfg: GraphicsAnimationEncodeConfig = v8_deserialize(scope, args.get(1))?;
+  let (width, height) = check_frames(&cfg.frames)?;
+  let rasters = v8::Local::<v8::Array>::try_from(args.get(2))?;
+  if rasters.length() as usize != cfg.frames.len() {
+    return Err(FrameRasterCountMismatch(cfg.frames.len(), rasters.length()).into());
+  }
+
+  let mut frames = Vec::with_capacity(cfg.frames.len());
+  for (i, frame) in cfg.frames.iter().enumerate() {
+    let fb = rasters
+      .get_index(scope, i as u32)
+      .ok_or(FrameRasterCountMismatch(cfg.frames.len(), rasters.length()))?;
+    let fb = v8::Local::<v8::TypedArray>::try_from(fb)?;
+    let mut fb = unsafe { v8_deref_typed_array_assuming_noalias(scope, fb) };
+    frames.push(RgbaFrame {
+      pixels: read_rgba(&frame.canvas, &mut fb)?,
+      delay_ms: frame.delay_ms,
+    });
+  }
+
+  let out = encode_animation(cfg.format, cfg.loop_count, width, height, frames)?;
+  retval.set(create_uint8array_from_bytes(scope, &out).into());
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Cursor;
+
+  use image::{
+    codecs::{gif::GifDecoder, png::PngDecoder},
+    AnimationDecoder,
+  };
+
+  use super::{
+    check_frames, encode_animation, AnimationTooLarge, GraphicsAnimationFormat,
+    GraphicsAnimationFrame, InvalidFrameCount, RgbaFrame, MAX_ANIMATION_FRAMES,
+  };
+  use crate::api::graphics::{
+    CanvasAlphaType, CanvasColorType, CanvasConfig, CanvasDimensions, CanvasPixelGeometry,
+  };
+
+  const COLORS: [[u8; 4]; 3] = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]];
+
+  fn mk_frames() -> Vec<RgbaFrame> {
+    COLORS
+      .iter()
+      .enumerate()
+      .map(|(i, color)| RgbaFrame {
+        pixels: color.repeat(8 * 8),
+        delay_ms: 100 * (i as u16 + 1),
+      })
+      .collect()
+  }
+
+  fn mk_frame_config(width: i32, height: i32) -> GraphicsAnimationFrame {
+    GraphicsAnimationFrame {
+      canvas: CanvasConfig {
+        dimensions: CanvasDimensions { width, height },
+        color_type: CanvasColorType::RGBA8888,
+        alpha_type: CanvasAlphaType::Unpremul,
+        pixel_geometry: CanvasPixelGeometry::RGBH,
+      },
+      delay_ms: 100,
+    }
+  }
+
+  fn check_decoded(frames: Vec<image::Frame>) {
+    assert_eq!(frames.len(), COLORS.len());
+    for (i, frame) in frames.iter().enumerate() {
+      let (numer, denom) = frame.delay().numer_denom_ms();
+      assert_eq!(numer / denom, 100 * (i as u32 + 1));
+      let buffer = frame.buffer();
+      assert_eq!(buffer.dimensions(), (8, 8));
+      assert!(buffer.pixels().all(|x| x.0 == COLORS[i]));
+    }
+  }
+
+  #[test]
+  fn gif_round_trip() {
+    let out = encode_animation(GraphicsAnimationFormat::Gif, 0, 8, 8, mk_frames()).unwrap();
+    let decoder = GifDecoder::new(Cursor::new(out)).unwrap();
+    check_decoded(decoder.into_frames().collect_frames().unwrap());
+  }
+
+  #[test]
+  fn apng_round_trip() {
+    let out = encode_animation(GraphicsAnimationFormat::Apng, 3, 8, 8, mk_frames()).unwrap();
+    let decoder = PngDecoder::new(Cursor::new(out)).unwrap();
+    assert!(decoder.is_apng());
+    check_decoded(decoder.apng().into_frames().collect_frames().unwrap());
+  }
+
+  #[test]
+  fn limits() {
+    assert!(check_frames(&[]).unwrap_err().is::<InvalidFrameCount>());
+
+    let frames = (0..MAX_ANIMATION_FRAMES + 1)
+      .map(|_| mk_frame_config(1, 1))
+      .collect::<Vec<_>>();
+    assert!(check_frames(&frames).unwrap_err().is::<InvalidFrameCount>());
+
+    let frames = (0..8)
+      .map(|_| mk_frame_config(4096, 4096))
+      .collect::<Vec<_>>();
+    assert!(check_frames(&frames).unwrap_err().is::<AnimationTooLarge>());
+
+    check_frames(&[mk_frame_config(16, 16), mk_frame_config(16, 16)]).unwrap();
+    check_frames(&[mk_frame_config(16, 16), mk_frame_config(8, 8)]).unwrap_err();
+  }
+}
@@ -0,0 +1,474 @@
+use std::fmt::Write;
+
+use anyhow::Result;
+use fontdue::layout::HorizontalAlign;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use v8;
+
+use crate::{
+  api::util::{mk_v8_string, v8_deserialize},
+  v8util::{create_uint8array_from_bytes, LocalValueExt},
+};
+
+use super::{
+  codec::{encode_canvas, CanvasEncodeConfig},
+  raster::check_dimensions,
+  CanvasAlphaType, CanvasColorType, CanvasConfig, CanvasDimensions, CanvasFillRule, CanvasOp,
+  CanvasPathOp, CanvasPixelGeometry, CommitApplier,
+};
+
+/// Bar/space widths of Code 128 symbols 0-105. Each symbol is 11 modules wide.
+const CODE128_PATTERNS: [&str; 106] = [
+  "212222", "222122", "222221", "121223", "121322", "131222", "122213", "122312", "132212",
+  "221213", "221312", "231212", "112232", "122132", "122231", "113222", "123122", "123221",
+  "223211", "221132", "221231", "213212", "223112", "312131", "311222", "321122", "321221",
+  "312212", "322112", "322211", "212123", "212321", "232121", "111323", "131123", "131321",
+  "112313", "132113", "132311", "211313", "231113", "231311", "112133", "112331", "132131",
+  "113123", "113321", "133121", "313121", "211331", "231131", "213113", "213311", "213131",
+  "311123", "311321", "331121", "312113", "312311", "332111", "314111", "221411", "431111",
+  "111224", "111422", "121124", "121421", "141122", "141221", "112214", "112412", "122114",
+  "122411", "142112", "142211", "241211", "221114", "413111", "241112", "134111", "111242",
+  "121142", "121241", "114212", "124112", "124211", "411212", "421112", "421211", "212141",
+  "214121", "412121", "111143", "111341", "131141", "114113", "114311", "411113", "411311",
+  "113141", "114131", "311141", "411131", "211412", "211214", "211232",
+];
+const CODE128_STOP: &str = "2331112";
+const CODE128_START_B: usize = 104;
+const CODE128_START_C: usize = 105;
+
+/// EAN-13 "L" (odd parity) digit encodings. "R" encodings are their complements, and "G"
+/// encodings are the reversed "R" encodings.
+const EAN_L_PATTERNS: [&str; 10] = [
+  "0001101", "0011001", "0010011", "0111101", "0100011", "0110001", "0101111", "0111011",
+  "0110111", "0001011",
+];
+
+/// Parity of the left-half digits, selected by the first digit.
+const EAN_PARITY: [&str; 10] = [
+  "LLLLLL", "LLGLGG", "LLGGLG", "LLGGGL", "LGLLGG", "LGGLLG", "LGGGLL", "LGLGLG", "LGLGGL",
+  "LGGLGL",
+];
+
+#[derive(Serialize, Deserialize, JsonSchema, Copy, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum GraphicsBarcodeSymbology {
+  Code128,
+  Ean13,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Copy, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum GraphicsBarcodeOutputFormat {
+  Svg,
+  Png,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct GraphicsBarcodeEncodeConfig {
+  pub symbology: GraphicsBarcodeSymbology,
+  pub format: GraphicsBarcodeOutputFormat,
+
+  /// Width of the narrowest bar in pixels (PNG) or user units (SVG).
+  pub module_width: u32,
+
+  /// Height of the bars, excluding the human-readable text.
+  pub height: u32,
+
+  /// Print the content below the bars.
+  pub human_readable: bool,
+
+  /// Font size of the human-readable text. Defaults to 12.
+  pub font_size: Option<f32>,
+}
+
+#[derive(Error, Debug)]
+#[error("invalid barcode content: {0}")]
+pub struct InvalidBarcodeContent(&'static str);
+
+#[derive(Error, Debug)]
+#[error("invalid ean-13 check digit")]
+pub struct InvalidCheckDigit;
+
+/// An encoded barcode: bar/space modules plus the text to print below them.
+pub struct BarcodeModules {
+  pub modules: Vec<bool>,
+  pub text: String,
+  pub quiet_left: u32,
+  pub quiet_right: u32,
+}
+
+fn push_widths(out: &mut Vec<bool>, widths: &str) {
+  for (i, w) in widths.bytes().enumerate() {
+    for _ in 0..(w - b'0') {
+      out.push(i % 2 == 0);
+    }
+  }
+}
+
+pub fn encode_code128(content: &str) -> Result<BarcodeModules> {
+  if content.is_empty() || content.len() > 80 {
+    return Err(InvalidBarcodeContent("code128 content must be 1-80 characters").into());
+  }
+  let bytes = content.as_bytes();
+
+  // Code set C packs digit pairs; everything else uses code set B.
+  let symbols: Vec<usize> = if bytes.len() % 2 == 0 && bytes.iter().all(|x| x.is_ascii_digit()) {
+    std::iter::once(CODE128_START_C)
+      .chain(
+        bytes
+          .chunks(2)
+          .map(|x| ((x[0] - b'0') * 10 + (x[1] - b'0')) as usize),
+      )
+      .collect()
+  } else {
+    if !bytes.iter().all(|x| (32..127).contains(x)) {
+      return Err(InvalidBarcodeContent("code128 content must be printable ascii").into());
+    }
+    std::iter::once(CODE128_START_B)
+      .chain(bytes.iter().map(|x| (x - 32) as usize))
+      .collect()
+  };
+
+  let checksum = symbols
+    .iter()
+    .enumerate()
+    .map(|(i, x)| i.max(1) * x)
+    .sum::<usize>()
+    % 103;
+
+  let mut modules = vec![];
+  for &x in symbols.iter().chain(std::iter::once(&checksum)) {
+    push_widths(&mut modules, CODE128_PATTERNS[x]);
+  }
+  push_widths(&mut modules, CODE128_STOP);
+  Ok(BarcodeModules {
+    modules,
+    text: content.to_string(),
+    quiet_left: 10,
+    quiet_right: 10,
+  })
+}
+
+fn ean13_check_digit(digits: &[u8]) -> u8 {
+  let sum: u32 = digits
+    .iter()
+    .enumerate()
+    .map(|(i, &x)| x as u32 * if i % 2 == 0 { 1 } else { 3 })
+    .sum();
+  ((10 - sum % 10) % 10) as u8
+}
+
+/// Accepts 12 digits (the check digit is appended) or 13 digits (the check digit is validated).
+pub fn encode_ean13(content: &str) -> Result<BarcodeModules> {
+  if !content.bytes().all(|x| x.is_ascii_digit()) {
+    return Err(InvalidBarcodeContent("ean-13 content must be digits").into());
+  }
+  let mut digits: Vec<u8> = content.bytes().map(|x| x - b'0').collect();
+  let check = match digits.len() {
+    12 | 13 => ean13_check_digit(&digits[..12]),
+    _ => return Err(InvalidBarcodeContent("ean-13 content must be 12 or 13 digits").into()),
+  };
+  if digits.len() == 13 && digits[12] != check {
+    return Err(InvalidCheckDigit.into());
+  }
+  digits.truncate(12);
+  digits.push(check);
+
+  let mut modules = vec![];
+  push_bits(&mut modules, "101");
+  let parity = EAN_PARITY[digits[0] as usize].as_bytes();
+  for (i, &d) in digits[1..7].iter().enumerate() {
+    let l = EAN_L_PATTERNS[d as usize];
+    if parity[i] == b'G' {
+      // G = reversed R = reversed complement of L
+      for b in l.bytes().rev() {
+        modules.push(b == b'0');
+      }
+    } else {
+      push_bits(&mut modules, l);
+    }
+  }
+  push_bits(&mut modules, "01010");
+  for &d in &digits[7..] {
+    for b in EAN_L_PATTERNS[d as usize].bytes() {
+      modules.push(b == b'0');
+    }
+  }
+  push_bits(&mut modules, "101");
+  Ok(BarcodeModules {
+    modules,
+    text: digits.iter().map(|x| (x + b'0') as char).collect(),
+    quiet_left: 11,
+    quiet_right: 7,
+  })
+}
+
+fn push_bits(out: &mut Vec<bool>, bits: &str) {
+  out.extend(bits.bytes().map(|x| x == b'1'));
+}
+
+/// Layout of the rendered barcode, in pixels/user units.
+struct BarcodeLayout {
+  width: u32,
+  height: u32,
+  font_size: f32,
+  bars: Vec<(u32, u32)>,
+}
+
+impl BarcodeLayout {
+  fn new(modules: &BarcodeModules, cfg: &GraphicsBarcodeEncodeConfig) -> Result<Self> {
+    let font_size = cfg.font_size.unwrap_or(12.0).max(1.0);
+    let total = modules.quiet_left as usize + modules.modules.len() + modules.quiet_right as usize;
+    let width = (total as u32).saturating_mul(cfg.module_width);
+    let text_height = if cfg.human_readable {
+      (font_size * 1.5).ceil() as u32
+    } else {
+      0
+    };
+    let height = cfg.height.saturating_add(text_height);
+    check_dimensions(width, height)?;
+
+    // (x, width) of each run of bars
+    let mut bars = vec![];
+    let mut i = 0;
+    while i < modules.modules.len() {
+      if !modules.modules[i] {
+        i += 1;
+        continue;
+      }
+      let start = i;
+      while i < modules.modules.len() && modules.modules[i] {
+        i += 1;
+      }
+      bars.push((
+        (modules.quiet_left + start as u32) * cfg.module_width,
+        (i - start) as u32 * cfg.module_width,
+      ));
+    }
+    Ok(Self {
+      width,
+      height,
+      font_size,
+      bars,
+    })
+  }
+}
+
+fn escape_xml(s: &str) -> String {
+  let mut out = String::with_capacity(s.len());
+  for c in s.chars() {
+    match c {
+      '&' => out.push_str("&amp;"),
+      '<' => out.push_str("&lt;"),
+      '>' => out.push_str("&gt;"),
+      '"' => out.push_str("&quot;"),
+      _ => out.push(c),
+    }
+  }
+  out
+}
+
+pub fn render_svg(modules: &BarcodeModules, cfg: &GraphicsBarcodeEncodeConfig) -> Result<String> {
+  let layout = BarcodeLayout::new(modules, cfg)?;
+  let mut out = String::new();
+  write!(
+    out,
+    r##"<?xml version="1.0" standalone="yes"?><svg xmlns="http://www.w3.org/2000/svg" version="1.1" width="{w}" height="{h}" viewBox="0 0 {w} {h}" shape-rendering="crispEdges"><rect x="0" y="0" width="{w}" height="{h}" fill="#fff"/><path fill="#000" d=""##,
+    w = layout.width,
+    h = layout.height,
+  )?;
+  for (x, w) in &layout.bars {
+    write!(out, "M{} 0h{}v{}h-{}z", x, w, cfg.height, w)?;
+  }
+  out.push_str(r#""/>"#);
+  if cfg.human_readable {
+    write!(
+      out,
+      r##"<text x="{}" y="{}" font-family="monospace" font-size="{}" text-anchor="middle" fill="#000">{}</text>"##,
+      layout.width as f32 / 2.0,
+      cfg.height as f32 + layout.font_size * 1.2,
+      layout.font_size,
+      escape_xml(&modules.text),
+    )?;
+  }
+  out.push_str("</svg>");
+  Ok(out)
+}
+
+/// Rasterizes through the canvas op pipeline, so that text uses the same fonts as `fillText`.
+pub fn render_png(modules: &BarcodeModules, cfg: &GraphicsBarcodeEncodeConfig) -> Result<Vec<u8>> {
+  let layout = BarcodeLayout::new(modules, cfg)?;
+  let config = CanvasConfig {
+    dimensions: CanvasDimensions {
+      width: layout.width as i32,
+      height: layout.height as i32,
+    },
+    color_type: CanvasColorType::RGBA8888,
+    alpha_type: CanvasAlphaType::Opaque,
+    pixel_geometry: CanvasPixelGeometry::RGBH,
+  };
+  let rect = |x: u32, y: u32, width: u32, height: u32| CanvasPathOp::Rect {
+    x: x as f32,
+    y: y as f32,
+    width: width as f32,
+    height: height as f32,
+  };
+  let mut ops = vec![
+    CanvasOp::SetFillStyleColor {
+      color: "#fff".into(),
+    },
+    CanvasOp::Fill {
+      path: vec![rect(0, 0, layout.width, layout.height)],
+      fill_rule: CanvasFillRule::NonZero,
+    },
+    CanvasOp::SetFillStyleColor {
+      color: "#000".into(),
+    },
+    CanvasOp::Fill {
+      path: layout
+        .bars
+        .iter()
+        .map(|&(x, w)| rect(x, 0, w, cfg.height))
+        .collect(),
+      fill_rule: CanvasFillRule::NonZero,
+    },
+  ];
+  if cfg.human_readable {
+    ops.push(CanvasOp::SetFont {
+      font: format!("{}px monospace, sans-serif", layout.font_size),
+    });
+    ops.push(CanvasOp::FillText {
+      text: modules.text.clone(),
+      x: 0.0,
+      y: cfg.height as f32 + layout.font_size * 0.25,
+      max_width: Some(layout.width as f32),
+    });
+  }
+
+  let mut fb = vec![0u8; layout.width as usize * layout.height as usize * 4];
+  {
+    let mut cvs = config.build_canvas(&mut fb)?;
+    let mut applier = CommitApplier::new(&mut cvs);
+    applier.text_align = HorizontalAlign::Center;
+    for op in &ops {
+      applier.apply(op)?;
+    }
+  }
+  encode_canvas(&config, &CanvasEncodeConfig::png(), &mut fb)
+}
+
+pub fn encode(content: &str, symbology: GraphicsBarcodeSymbology) -> Result<BarcodeModules> {
+  match symbology {
+    GraphicsBarcodeSymbology::Code128 => encode_code128(content),
+    GraphicsBarcodeSymbology::Ean13 => encode_ean13(content),
+  }
+}
+
+/// Returns an SVG string or PNG bytes depending on `format`.
+pub fn api_graphics_barcode_encode(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let content = unsafe { args.get(1).read_string_assume_noalias(scope)? };
+  let cfg: GraphicsBarcodeEncodeConfig = v8_deserialize(scope, args.get(2))?;
+  let modules = encode(&content, cfg.symbology)?;
+  match cfg.format {
+    GraphicsBarcodeOutputFormat::Svg => {
+      let svg = render_svg(&modules, &cfg)?;
+      retval.set(mk_v8_string(scope, &svg)?.into());
+    }
+    GraphicsBarcodeOutputFormat::Png => {
+      let png = render_png(&modules, &cfg)?;
+      retval.set(create_uint8array_from_bytes(scope, &png).into());
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    encode_code128, encode_ean13, render_png, render_svg, GraphicsBarcodeEncodeConfig,
+    GraphicsBarcodeOutputFormat, GraphicsBarcodeSymbology, InvalidBarcodeContent,
+    InvalidCheckDigit, CODE128_PATTERNS,
+  };
+  use crate::api::graphics::raster::decode_rgba;
+
+  fn to_bits(x: &[bool]) -> String {
+    x.iter().map(|&x| if x { '1' } else { '0' }).collect()
+  }
+
+  #[test]
+  fn code128() {
+    for p in CODE128_PATTERNS {
+      assert_eq!(p.bytes().map(|x| (x - b'0') as u32).sum::<u32>(), 11);
+    }
+
+    // Start B, "H", "I", checksum, stop
+    let x = encode_code128("HI").unwrap();
+    assert_eq!(x.modules.len(), 11 * 4 + 13);
+    // Start B is 11010010000.
+    assert_eq!(&to_bits(&x.modules)[..11], "11010010000");
+
+    // Digit pairs use code set C.
+    let x = encode_code128("123456").unwrap();
+    assert_eq!(x.modules.len(), 11 * 5 + 13);
+
+    assert!(encode_code128("")
+      .unwrap_err()
+      .is::<InvalidBarcodeContent>());
+    assert!(encode_code128("caf\u{e9}")
+      .unwrap_err()
+      .is::<InvalidBarcodeContent>());
+  }
+
+  #[test]
+  fn ean13() {
+    let x = encode_ean13("400638133393").unwrap();
+    assert_eq!(x.text, "4006381333931");
+    assert_eq!(x.modules.len(), 95);
+
+    let x = encode_ean13("5901234123457").unwrap();
+    let bits = to_bits(&x.modules);
+    assert!(bits.starts_with("101"));
+    assert!(bits.ends_with("101"));
+    assert_eq!(&bits[45..50], "01010");
+    // First left digit "9" with parity L.
+    assert_eq!(&bits[3..10], "0001011");
+
+    assert!(encode_ean13("5901234123458")
+      .unwrap_err()
+      .is::<InvalidCheckDigit>());
+    assert!(encode_ean13("12345")
+      .unwrap_err()
+      .is::<InvalidBarcodeContent>());
+    assert!(encode_ean13("59012341234a")
+      .unwrap_err()
+      .is::<InvalidBarcodeContent>());
+  }
+
+  #[test]
+  fn render() {
+    let modules = encode_ean13("5901234123457").unwrap();
+    let mut cfg = GraphicsBarcodeEncodeConfig {
+      symbology: GraphicsBarcodeSymbology::Ean13,
+      format: GraphicsBarcodeOutputFormat::Svg,
+      module_width: 2,
+      height: 50,
+      human_readable: true,
+      font_size: None,
+    };
+    let svg = render_svg(&modules, &cfg).unwrap();
+    assert!(svg.contains(">5901234123457</text>"));
+
+    cfg.human_readable = false;
+    let png = render_png(&modules, &cfg).unwrap();
+    let (_, image) = decode_rgba(&png).unwrap();
+    assert_eq!(image.dimensions(), ((11 + 95 + 7) * 2, 50));
+    assert_eq!(image.get_pixel(0, 0).0, [0xff, 0xff, 0xff, 0xff]);
+    assert_eq!(image.get_pixel(22, 0).0, [0, 0, 0, 0xff]);
+  }
+}
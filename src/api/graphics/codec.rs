@@ -2,9 +2,13 @@ use crate::api::util::v8_deserialize;
 use crate::v8util::create_uint8array_from_bytes;
 use crate::{api::util::v8_deref_typed_array_assuming_noalias, impl_idenum};
 use anyhow::Result;
+use image::{
+  codecs::png::{CompressionType, FilterType as PngFilterType, PngEncoder},
+  ImageEncoder,
+};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use skia_safe::EncodedImageFormat;
+use skia_safe::{AlphaType, ColorType, EncodedImageFormat, ISize, ImageInfo};
 use std::convert::TryFrom;
 use thiserror::Error;
 use v8;
@@ -13,8 +17,69 @@ use super::CanvasConfig;
 
 #[derive(Serialize, Deserialize, JsonSchema)]
 pub struct CanvasEncodeConfig {
+  /// One of `PNG`, `JPEG` and `WEBP`. Other formats (including AVIF) have no encoder.
   pub format: CanvasEncodedImageFormat,
+
+  /// 0-100. Used by JPEG and lossy WebP.
   pub quality: u32,
+
+  /// PNG only.
+  #[serde(default)]
+  pub png_compression: Option<CanvasPngCompression>,
+
+  /// WebP only. Defaults to lossy.
+  #[serde(default)]
+  pub webp_lossless: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Copy, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum CanvasPngCompression {
+  Fast,
+  Default,
+  Best,
+}
+
+impl From<CanvasPngCompression> for CompressionType {
+  fn from(that: CanvasPngCompression) -> Self {
+    match that {
+      CanvasPngCompression::Fast => Self::Fast,
+      CanvasPngCompression::Default => Self::Default,
+      CanvasPngCompression::Best => Self::Best,
+    }
+  }
+}
+
+#[derive(Error, Debug)]
+#[error("invalid encode options: {0}")]
+pub struct InvalidEncodeOptions(&'static str);
+
+impl CanvasEncodeConfig {
+  pub fn png() -> Self {
+    Self {
+      format: CanvasEncodedImageFormat::PNG,
+      quality: 100,
+      png_compression: None,
+      webp_lossless: None,
+    }
+  }
+
+  fn validate(&self) -> Result<(), InvalidEncodeOptions> {
+    use CanvasEncodedImageFormat as F;
+    if self.quality > 100 {
+      return Err(InvalidEncodeOptions("quality must be between 0 and 100"));
+    }
+    if !matches!(self.format, F::PNG | F::JPEG | F::WEBP) {
+      return Err(InvalidEncodeOptions("unsupported format"));
+    }
+    if self.png_compression.is_some() && !matches!(self.format, F::PNG) {
+      return Err(InvalidEncodeOptions("png_compression requires PNG"));
+    }
+    if self.webp_lossless.is_some() && !matches!(self.format, F::WEBP) {
+      return Err(InvalidEncodeOptions("webp_lossless requires WEBP"));
+    }
+    Ok(())
+  }
 }
 
 impl_idenum!(
@@ -34,30 +99,188 @@ impl_idenum!(
   HEIF,
 );
 
+#[derive(Error, Debug)]
+#[error("canvas encode failed")]
+struct CanvasEncodeError;
+
+/// Reads the pixels of a canvas framebuffer as RGBA8888 (unpremultiplied), whatever the canvas
+/// color type is.
+pub fn read_rgba(config: &CanvasConfig, fb: &mut [u8]) -> Result<Vec<u8>> {
+  let mut cvs = config.build_canvas(fb)?;
+  let (width, height) = (
+    config.dimensions.width as usize,
+    config.dimensions.height as usize,
+  );
+  let info = ImageInfo::new(
+    ISize {
+      width: width as i32,
+      height: height as i32,
+    },
+    ColorType::RGBA8888,
+    AlphaType::Unpremul,
+    None,
+  );
+  let mut rgba = vec![0u8; width * height * 4];
+  if !cvs.read_pixels(&info, &mut rgba, width * 4, (0, 0)) {
+    return Err(CanvasEncodeError.into());
+  }
+  Ok(rgba)
+}
+
+/// Writes RGBA8888 (unpremultiplied) pixels into a canvas framebuffer, converting them to the
+/// canvas color type.
+pub fn write_rgba(config: &CanvasConfig, fb: &mut [u8], rgba: &[u8]) -> Result<()> {
+  let mut cvs = config.build_canvas(fb)?;
+  let width = config.dimensions.width as usize;
+  let info = ImageInfo::new(
+    ISize {
+      width: config.dimensions.width,
+      height: config.dimensions.height,
+    },
+    ColorType::RGBA8888,
+    AlphaType::Unpremul,
+    None,
+  );
+  if !cvs.write_pixels(&info, rgba, width * 4, (0, 0)) {
+    return Err(CanvasEncodeError.into());
+  }
+  Ok(())
+}
+
+/// Encodes the pixels of a canvas framebuffer.
+pub fn encode_canvas(
+  config: &CanvasConfig,
+  encode_config: &CanvasEncodeConfig,
+  fb: &mut [u8],
+) -> Result<Vec<u8>> {
+  use CanvasEncodedImageFormat as F;
+  encode_config.validate()?;
+
+  // Skia's PNG encoder doesn't take a compression level.
+  if let (F::PNG, Some(compression)) = (encode_config.format, encode_config.png_compression) {
+    let rgba = read_rgba(config, fb)?;
+    let mut out = vec![];
+    PngEncoder::new_with_quality(&mut out, compression.into(), PngFilterType::Adaptive)
+      .write_image(
+        &rgba,
+        config.dimensions.width as u32,
+        config.dimensions.height as u32,
+        image::ColorType::Rgba8,
+      )?;
+    return Ok(out);
+  }
+
+  let mut cvs = config.build_canvas(fb)?;
+  // Skia encodes WebP losslessly iff quality is 100.
+  let quality = match (encode_config.format, encode_config.webp_lossless) {
+    (F::WEBP, Some(true)) => 100,
+    (F::WEBP, _) => encode_config.quality.min(99),
+    _ => encode_config.quality,
+  };
+  let pixels = cvs.peek_pixels().ok_or(CanvasEncodeError)?;
+  let data = skia_safe::encode::pixmap(&pixels, encode_config.format.into(), quality as usize)
+    .ok_or(CanvasEncodeError)?;
+  Ok(data.as_bytes().to_vec())
+}
+
 pub fn api_graphics_canvas_encode(
   scope: &mut v8::HandleScope,
   args: v8::FunctionCallbackArguments,
   mut retval: v8::ReturnValue,
 ) -> Result<()> {
-  #[derive(Error, Debug)]
-  #[error("canvas encode failed")]
-  struct CanvasEncodeError;
-
   let config: CanvasConfig = v8_deserialize(scope, args.get(1))?;
   let encode_config: CanvasEncodeConfig = v8_deserialize(scope, args.get(2))?;
   let fb = v8::Local::<v8::TypedArray>::try_from(args.get(3))?;
   let mut fb = unsafe { v8_deref_typed_array_assuming_noalias(scope, fb) };
-  let mut cvs = config.build_canvas(&mut fb)?;
-
-  let pixels = cvs.peek_pixels().unwrap();
-  let data = skia_safe::encode::pixmap(
-    &pixels,
-    encode_config.format.into(),
-    encode_config.quality as usize,
-  )
-  .ok_or(CanvasEncodeError)?;
+  let data = encode_canvas(&config, &encode_config, &mut fb)?;
   let data = create_uint8array_from_bytes(scope, &data);
   retval.set(data.into());
 
   Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    encode_canvas, CanvasEncodeConfig, CanvasEncodedImageFormat, CanvasPngCompression,
+    InvalidEncodeOptions,
+  };
+  use crate::api::graphics::{
+    raster::decode_rgba, CanvasAlphaType, CanvasColorType, CanvasConfig, CanvasDimensions,
+    CanvasPixelGeometry,
+  };
+
+  fn mk_config() -> CanvasConfig {
+    CanvasConfig {
+      dimensions: CanvasDimensions {
+        width: 8,
+        height: 8,
+      },
+      color_type: CanvasColorType::RGBA8888,
+      alpha_type: CanvasAlphaType::Unpremul,
+      pixel_geometry: CanvasPixelGeometry::RGBH,
+    }
+  }
+
+  fn mk_fb() -> Vec<u8> {
+    (0..8 * 8)
+      .flat_map(|i| [i as u8 * 4, 0, 255 - i as u8, 255])
+      .collect()
+  }
+
+  #[test]
+  fn png_compression_levels() {
+    for compression in [
+      CanvasPngCompression::Fast,
+      CanvasPngCompression::Default,
+      CanvasPngCompression::Best,
+    ] {
+      let mut cfg = CanvasEncodeConfig::png();
+      cfg.png_compression = Some(compression);
+      let out = encode_canvas(&mk_config(), &cfg, &mut mk_fb()).unwrap();
+      let (_, image) = decode_rgba(&out).unwrap();
+      assert_eq!(image.into_raw(), mk_fb());
+    }
+  }
+
+  #[test]
+  fn webp_lossless() {
+    let cfg = CanvasEncodeConfig {
+      format: CanvasEncodedImageFormat::WEBP,
+      quality: 0,
+      png_compression: None,
+      webp_lossless: Some(true),
+    };
+    let out = encode_canvas(&mk_config(), &cfg, &mut mk_fb()).unwrap();
+    let (_, image) = decode_rgba(&out).unwrap();
+    assert_eq!(image.into_raw(), mk_fb());
+  }
+
+  #[test]
+  fn rejects_invalid_options() {
+    let mut cfg = CanvasEncodeConfig::png();
+    cfg.format = CanvasEncodedImageFormat::JPEG;
+    cfg.quality = 101;
+    assert!(encode_canvas(&mk_config(), &cfg, &mut mk_fb())
+      .unwrap_err()
+      .is::<InvalidEncodeOptions>());
+
+    cfg.quality = 80;
+    cfg.png_compression = Some(CanvasPngCompression::Best);
+    assert!(encode_canvas(&mk_config(), &cfg, &mut mk_fb())
+      .unwrap_err()
+      .is::<InvalidEncodeOptions>());
+
+    let mut cfg = CanvasEncodeConfig::png();
+    cfg.webp_lossless = Some(false);
+    assert!(encode_canvas(&mk_config(), &cfg, &mut mk_fb())
+      .unwrap_err()
+      .is::<InvalidEncodeOptions>());
+
+    let mut cfg = CanvasEncodeConfig::png();
+    cfg.format = CanvasEncodedImageFormat::HEIF;
+    assert!(encode_canvas(&mk_config(), &cfg, &mut mk_fb())
+      .unwrap_err()
+      .is::<InvalidEncodeOptions>());
+  }
+}
@@ -1,13 +1,116 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
-use fontdue::Font;
+use anyhow::Result;
+use fontdue::{Font, FontSettings};
 use itertools::Itertools;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use ttf_parser::Face;
+use v8;
 
-use crate::{api::graphics::font_util::FontStyle, gres::FONTS};
+use crate::{
+  api::{graphics::font_util::FontStyle, util::mk_v8_string},
+  gres::FONTS,
+  v8util::LocalValueExt,
+};
+
+/// Maximum number of fonts an app can load at runtime.
+pub const MAX_LOADED_FONTS: usize = 32;
+
+/// Maximum total size of the font files an app can load at runtime.
+pub const MAX_LOADED_FONT_BYTES: usize = 32 * 1024 * 1024;
+
+const LOADED_FONT_PREFIX: &str = "blueboat-font-";
+
+/// Fonts loaded with `graphics_font_load`, keyed by handle.
+///
+/// A worker process hosts a single isolate, so this cache is per isolate and survives across
+/// requests. Loaded fonts are leaked to get the same `'static` lifetime as the global fonts; the
+/// limits above bound the leak.
+static LOADED_FONTS: Lazy<Mutex<LoadedFonts>> = Lazy::new(Default::default);
+
+#[derive(Default)]
+struct LoadedFonts {
+  fonts: HashMap<String, &'static Font>,
+  total_bytes: usize,
+}
+
+#[derive(Error, Debug)]
+#[error("unsupported font format, expected TrueType or OpenType")]
+pub struct UnsupportedFontFormat;
+
+#[derive(Error, Debug)]
+#[error("invalid font: {0}")]
+pub struct InvalidFont(String);
+
+#[derive(Error, Debug)]
+#[error("too many fonts loaded")]
+pub struct TooManyFonts;
+
+fn check_font_format(data: &[u8]) -> Result<()> {
+  match data.get(0..4) {
+    Some(b"\x00\x01\x00\x00") | Some(b"true") | Some(b"OTTO") | Some(b"ttcf") => Ok(()),
+    _ => Err(UnsupportedFontFormat.into()),
+  }
+}
+
+/// Parses and registers a TTF/OTF font. Returns a handle that can be used as a font family name.
+/// Loading the same file again returns the cached handle without parsing it again.
+pub fn load_font(data: &[u8]) -> Result<String> {
+  let handle = format!(
+    "{}{}",
+    LOADED_FONT_PREFIX,
+    hex::encode(&Sha256::digest(data)[..12])
+  );
+  let mut loaded = LOADED_FONTS.lock();
+  if loaded.fonts.contains_key(&handle) {
+    return Ok(handle);
+  }
+  if loaded.fonts.len() >= MAX_LOADED_FONTS
+    || loaded.total_bytes + data.len() > MAX_LOADED_FONT_BYTES
+  {
+    return Err(TooManyFonts.into());
+  }
+
+  check_font_format(data)?;
+  Face::from_slice(data, 0).map_err(|e| InvalidFont(e.to_string()))?;
+  let font =
+    Font::from_bytes(data, FontSettings::default()).map_err(|e| InvalidFont(e.to_string()))?;
+
+  loaded.total_bytes += data.len();
+  loaded
+    .fonts
+    .insert(handle.clone(), Box::leak(Box::new(font)));
+  Ok(handle)
+}
+
+fn lookup_loaded_font(name: &str) -> Option<&'static Font> {
+  if !name.starts_with(LOADED_FONT_PREFIX) {
+    return None;
+  }
+  LOADED_FONTS.lock().fonts.get(name).copied()
+}
+
+pub fn api_graphics_font_load(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let data = unsafe { args.get(1).read_bytes_assume_noalias(scope)? };
+  let handle = load_font(&data)?;
+  retval.set(mk_v8_string(scope, &handle)?.into());
+  Ok(())
+}
 
 pub fn search_font(spec: &super::font_util::Font) -> Vec<&'static Font> {
   let mut ret = vec![];
   for candidate in spec.family.split(",").map(|x| x.trim()) {
+    if let Some(m) = lookup_loaded_font(candidate) {
+      ret.push(m);
+      continue;
+    }
     if let Some(m) = match_font_family(candidate, spec) {
       ret.push(m);
     }
@@ -78,3 +181,23 @@ fn match_font_family(candidate: &str, spec: &super::font_util::Font) -> Option<&
   }
   out.map(|x| x.1)
 }
+
+#[cfg(test)]
+mod tests {
+  use super::{load_font, InvalidFont, UnsupportedFontFormat};
+
+  #[test]
+  fn rejects_unsupported_formats() {
+    let cases: [&[u8]; 4] = [b"wOFF\x00\x01\x00\x00", b"wOF2", b"", b"\x89PNG\r\n\x1a\n"];
+    for data in cases {
+      assert!(load_font(data).unwrap_err().is::<UnsupportedFontFormat>());
+    }
+  }
+
+  #[test]
+  fn rejects_truncated_fonts() {
+    let mut data = b"\x00\x01\x00\x00".to_vec();
+    data.extend_from_slice(&[0u8; 60]);
+    assert!(load_font(&data).unwrap_err().is::<InvalidFont>());
+  }
+}
@@ -0,0 +1,288 @@
+use anyhow::Result;
+use css_color_parser::Color as CssColor;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use skia_safe::{gradient_shader, Color, Matrix, Point, Shader, TileMode};
+use thiserror::Error;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(tag = "type")]
+pub enum CanvasGradientShape {
+  /// Along the line from (x0, y0) to (x1, y1).
+  Linear { x0: f32, y0: f32, x1: f32, y1: f32 },
+
+  /// Between the circle at (x0, y0) with radius r0 and the circle at (x1, y1) with radius r1,
+  /// like `createRadialGradient`.
+  Radial {
+    x0: f32,
+    y0: f32,
+    r0: f32,
+    x1: f32,
+    y1: f32,
+    r1: f32,
+  },
+
+  /// Around (x, y), clockwise from `start_angle` radians, where zero points to the right, like
+  /// `createConicGradient`.
+  Conic { x: f32, y: f32, start_angle: f32 },
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct CanvasGradientStop {
+  pub offset: f32,
+  pub color: String,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct CanvasGradient {
+  pub shape: CanvasGradientShape,
+  pub stops: Vec<CanvasGradientStop>,
+}
+
+#[derive(Error, Debug)]
+#[error("invalid gradient: {0}")]
+pub struct InvalidGradient(&'static str);
+
+impl CanvasGradientShape {
+  fn validate(&self) -> Result<()> {
+    let (numbers, radii) = match *self {
+      Self::Linear { x0, y0, x1, y1 } => (vec![x0, y0, x1, y1], vec![]),
+      Self::Radial {
+        x0,
+        y0,
+        r0,
+        x1,
+        y1,
+        r1,
+      } => (vec![x0, y0, x1, y1], vec![r0, r1]),
+      Self::Conic { x, y, start_angle } => (vec![x, y, start_angle], vec![]),
+    };
+    if !numbers.iter().chain(&radii).all(|x| x.is_finite()) {
+      return Err(InvalidGradient("non-finite coordinate").into());
+    }
+    if radii.iter().any(|x| *x < 0.0) {
+      return Err(InvalidGradient("negative radius").into());
+    }
+    Ok(())
+  }
+}
+
+impl CanvasGradient {
+  /// Parses the color stops, clamping offsets into [0, 1] and sorting them. Stops with equal
+  /// offsets keep their order, which gives a hard transition.
+  fn normalized_stops(&self) -> Result<(Vec<Color>, Vec<f32>)> {
+    if self.stops.is_empty() {
+      return Err(InvalidGradient("no color stops").into());
+    }
+    let mut stops = Vec::with_capacity(self.stops.len());
+    for stop in &self.stops {
+      if stop.offset.is_nan() {
+        return Err(InvalidGradient("stop offset is NaN").into());
+      }
+      let c: CssColor = stop.color.parse()?;
+      let color = Color::from_argb((c.a * 255.0).round() as u8, c.r, c.g, c.b);
+      stops.push((stop.offset.clamp(0.0, 1.0), color));
+    }
+    stops.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    Ok(stops.into_iter().map(|(o, c)| (c, o)).unzip())
+  }
+
+  pub fn build_shader(&self) -> Result<Shader> {
+    #[derive(Error, Debug)]
+    #[error("gradient shader creation failed")]
+    struct ShaderCreationError;
+
+    self.shape.validate()?;
+    let (colors, offsets) = self.normalized_stops()?;
+    let colors = &colors[..];
+    let offsets = Some(&offsets[..]);
+    let shader = match self.shape {
+      CanvasGradientShape::Linear { x0, y0, x1, y1 } => gradient_shader::linear(
+        (Point::new(x0, y0), Point::new(x1, y1)),
+        colors,
+        offsets,
+        TileMode::Clamp,
+        None,
+        None,
+      ),
+      CanvasGradientShape::Radial {
+        x0,
+        y0,
+        r0,
+        x1,
+        y1,
+        r1,
+      } => gradient_shader::two_point_conical(
+        Point::new(x0, y0),
+        r0,
+        Point::new(x1, y1),
+        r1,
+        colors,
+        offsets,
+        TileMode::Clamp,
+        None,
+        None,
+      ),
+      CanvasGradientShape::Conic { x, y, start_angle } => {
+        // Skia sweeps clockwise from the positive x axis, so only the start angle needs a
+        // rotation.
+        let center = Point::new(x, y);
+        let rotation = Matrix::rotate_deg_pivot(start_angle.to_degrees(), center);
+        gradient_shader::sweep(
+          center,
+          colors,
+          offsets,
+          TileMode::Clamp,
+          None,
+          None,
+          &rotation,
+        )
+      }
+    };
+    Ok(shader.ok_or(ShaderCreationError)?)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use skia_safe::{Color, Paint, Rect};
+
+  use super::{CanvasGradient, CanvasGradientShape, CanvasGradientStop, InvalidGradient};
+  use crate::api::graphics::{
+    codec::read_rgba, CanvasAlphaType, CanvasColorType, CanvasConfig, CanvasDimensions,
+    CanvasPixelGeometry,
+  };
+
+  fn mk_gradient(shape: CanvasGradientShape, stops: &[(f32, &str)]) -> CanvasGradient {
+    CanvasGradient {
+      shape,
+      stops: stops
+        .iter()
+        .map(|(offset, color)| CanvasGradientStop {
+          offset: *offset,
+          color: color.to_string(),
+        })
+        .collect(),
+    }
+  }
+
+  /// Fills a 16x16 canvas with the gradient and returns its RGBA pixels.
+  fn render(gradient: &CanvasGradient) -> Vec<u8> {
+    let config = CanvasConfig {
+      dimensions: CanvasDimensions {
+        width: 16,
+        height: 16,
+      },
+      color_type: CanvasColorType::RGBA8888,
+      alpha_type: CanvasAlphaType::Unpremul,
+      pixel_geometry: CanvasPixelGeometry::RGBH,
+    };
+    let mut fb = vec![0u8; 16 * 16 * 4];
+    {
+      let mut cvs = config.build_canvas(&mut fb).unwrap();
+      let mut paint = Paint::default();
+      paint.set_shader(gradient.build_shader().unwrap());
+      cvs.draw_rect(Rect::from_wh(16.0, 16.0), &paint);
+    }
+    read_rgba(&config, &mut fb).unwrap()
+  }
+
+  fn pixel(pixels: &[u8], x: usize, y: usize) -> [u8; 4] {
+    let i = (y * 16 + x) * 4;
+    [pixels[i], pixels[i + 1], pixels[i + 2], pixels[i + 3]]
+  }
+
+  #[test]
+  fn normalizes_stops() {
+    let g = mk_gradient(
+      CanvasGradientShape::Linear {
+        x0: 0.0,
+        y0: 0.0,
+        x1: 1.0,
+        y1: 0.0,
+      },
+      &[(1.5, "blue"), (0.5, "lime"), (-1.0, "red"), (0.5, "white")],
+    );
+    let (colors, offsets) = g.normalized_stops().unwrap();
+    assert_eq!(offsets, [0.0, 0.5, 0.5, 1.0]);
+    assert_eq!(
+      colors,
+      [Color::RED, Color::GREEN, Color::WHITE, Color::BLUE]
+    );
+  }
+
+  #[test]
+  fn rejects_invalid_gradients() {
+    let radial = CanvasGradientShape::Radial {
+      x0: 0.0,
+      y0: 0.0,
+      r0: -1.0,
+      x1: 0.0,
+      y1: 0.0,
+      r1: 1.0,
+    };
+    let conic = CanvasGradientShape::Conic {
+      x: 0.0,
+      y: 0.0,
+      start_angle: 0.0,
+    };
+    for g in [
+      mk_gradient(radial, &[(0.0, "red")]),
+      mk_gradient(conic.clone(), &[]),
+      mk_gradient(conic, &[(f32::NAN, "red")]),
+    ] {
+      assert!(g.build_shader().unwrap_err().is::<InvalidGradient>());
+    }
+  }
+
+  #[test]
+  fn radial() {
+    let g = mk_gradient(
+      CanvasGradientShape::Radial {
+        x0: 8.0,
+        y0: 8.0,
+        r0: 0.0,
+        x1: 8.0,
+        y1: 8.0,
+        r1: 8.0,
+      },
+      &[(0.0, "red"), (1.0, "blue")],
+    );
+    let pixels = render(&g);
+    let center = pixel(&pixels, 8, 8);
+    assert!(center[0] > 200 && center[2] < 50, "{:?}", center);
+    // Outside the end circle the last color is extended.
+    assert_eq!(pixel(&pixels, 0, 0), [0, 0, 255, 255]);
+  }
+
+  #[test]
+  fn conic() {
+    // Red to the right of the center, blue just past a quarter turn clockwise.
+    let g = mk_gradient(
+      CanvasGradientShape::Conic {
+        x: 8.0,
+        y: 8.0,
+        start_angle: 0.0,
+      },
+      &[(0.0, "red"), (0.25, "blue"), (1.0, "blue")],
+    );
+    let pixels = render(&g);
+    let right = pixel(&pixels, 15, 8);
+    assert!(right[0] > 200 && right[2] < 50, "{:?}", right);
+    let below = pixel(&pixels, 7, 15);
+    assert!(below[2] > 200 && below[0] < 50, "{:?}", below);
+
+    // Starting a quarter turn later moves red below the center.
+    let g = mk_gradient(
+      CanvasGradientShape::Conic {
+        x: 8.0,
+        y: 8.0,
+        start_angle: std::f32::consts::FRAC_PI_2,
+      },
+      &[(0.0, "red"), (0.25, "blue"), (1.0, "blue")],
+    );
+    let pixels = render(&g);
+    let below = pixel(&pixels, 7, 15);
+    assert!(below[0] > 200 && below[2] < 50, "{:?}", below);
+  }
+}
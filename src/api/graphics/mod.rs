@@ -1,8 +1,15 @@
+pub mod animation;
+pub mod barcode;
 pub mod codec;
 pub mod draw;
+pub mod filter;
 mod font_util;
 pub mod fonts;
+pub mod gradient;
 pub mod layout;
+pub mod pdf;
+pub mod qr;
+pub mod raster;
 pub mod svg;
 pub mod text;
 
@@ -22,9 +29,12 @@ use std::convert::TryFrom;
 use thiserror::Error;
 use v8;
 
-use crate::api::{
-  graphics::fonts::search_font,
-  util::{v8_deref_typed_array_assuming_noalias, write_applog},
+use crate::{
+  api::{
+    graphics::{fonts::search_font, gradient::CanvasGradient},
+    util::{v8_deref_typed_array_assuming_noalias, write_applog},
+  },
+  lpch::AppLogLevel,
 };
 
 use super::util::v8_deserialize;
@@ -235,6 +245,12 @@ pub enum CanvasOp {
   SetFillStyleColor {
     color: String,
   },
+  SetStrokeStyleGradient {
+    gradient: CanvasGradient,
+  },
+  SetFillStyleGradient {
+    gradient: CanvasGradient,
+  },
   SetFont {
     font: String,
   },
@@ -281,31 +297,13 @@ pub fn api_graphics_canvas_commit(
   let mut cvs = config.build_canvas(&mut fb)?;
 
   {
-    let mut applier = CommitApplier {
-      cvs: &mut cvs,
-      stroke_paint: Paint::default(),
-      fill_paint: Paint::default(),
-      clear_paint: Paint::default(),
-      font: vec![],
-      font_size: 10.0,
-      text_align: HorizontalAlign::Left,
-      layout: Layout::new(CoordinateSystem::PositiveYDown),
-    };
-    applier.stroke_paint.set_style(PaintStyle::Stroke);
-    applier.fill_paint.set_style(PaintStyle::Fill);
-    applier
-      .clear_paint
-      .set_style(PaintStyle::Fill)
-      .set_color(Color::from_argb(0, 0, 0, 0))
-      .set_stroke_miter(10.0)
-      .set_blend_mode(BlendMode::Clear);
-
+    let mut applier = CommitApplier::new(&mut cvs);
     for (i, op) in ops.iter().enumerate() {
       // Log application should not throw
       match applier.apply(op).map_err(|e| CommitError(i, e)) {
         Ok(()) => {}
         Err(e) => {
-          write_applog(scope, format!("{}", e));
+          write_applog(scope, AppLogLevel::Error, format!("{}", e));
         }
       }
     }
@@ -326,6 +324,28 @@ struct CommitApplier<'p: 'q, 'q> {
 }
 
 impl<'p, 'q> CommitApplier<'p, 'q> {
+  fn new(cvs: &'q mut OwnedCanvas<'p>) -> Self {
+    let mut applier = CommitApplier {
+      cvs,
+      stroke_paint: Paint::default(),
+      fill_paint: Paint::default(),
+      clear_paint: Paint::default(),
+      font: vec![],
+      font_size: 10.0,
+      text_align: HorizontalAlign::Left,
+      layout: Layout::new(CoordinateSystem::PositiveYDown),
+    };
+    applier.stroke_paint.set_style(PaintStyle::Stroke);
+    applier.fill_paint.set_style(PaintStyle::Fill);
+    applier
+      .clear_paint
+      .set_style(PaintStyle::Fill)
+      .set_color(Color::from_argb(0, 0, 0, 0))
+      .set_stroke_miter(10.0)
+      .set_blend_mode(BlendMode::Clear);
+    applier
+  }
+
   fn build_path(&mut self, p: &[CanvasPathOp]) -> Path {
     use CanvasPathOp as V;
     let mut out = Path::new();
@@ -481,6 +501,7 @@ impl<'p, 'q> CommitApplier<'p, 'q> {
         let css_color: CssColor = color.parse()?;
         let mut color = Color4f::from(Color::from_argb(1, css_color.r, css_color.g, css_color.b));
         color.a = css_color.a;
+        self.stroke_paint.set_shader(None);
         self.stroke_paint.set_color4f(color, None);
       }
       V::SetStrokeLineWidth { width } => {
@@ -490,8 +511,19 @@ impl<'p, 'q> CommitApplier<'p, 'q> {
         let css_color: CssColor = color.parse()?;
         let mut color = Color4f::from(Color::from_argb(1, css_color.r, css_color.g, css_color.b));
         color.a = css_color.a;
+        self.fill_paint.set_shader(None);
         self.fill_paint.set_color4f(color, None);
       }
+      V::SetStrokeStyleGradient { gradient } => {
+        let shader = gradient.build_shader()?;
+        self.stroke_paint.set_color(Color::BLACK);
+        self.stroke_paint.set_shader(shader);
+      }
+      V::SetFillStyleGradient { gradient } => {
+        let shader = gradient.build_shader()?;
+        self.fill_paint.set_color(Color::BLACK);
+        self.fill_paint.set_shader(shader);
+      }
       V::Stroke { path } => {
         let p = self.build_path(path);
         self.cvs.draw_path(&p, &self.stroke_paint);
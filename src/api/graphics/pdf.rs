@@ -0,0 +1,488 @@
+use std::{convert::TryFrom, fmt::Write as _, io::Write as _};
+
+use anyhow::Result;
+use flate2::{write::ZlibEncoder, Compression};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use v8;
+
+use crate::{
+  api::util::{v8_deref_typed_array_assuming_noalias, v8_deserialize},
+  v8util::create_uint8array_from_bytes,
+};
+
+use super::{
+  codec::read_rgba,
+  raster::{check_dimensions, MAX_IMAGE_PIXELS},
+  CanvasConfig,
+};
+
+/// Maximum number of pages in one document.
+pub const MAX_PDF_PAGES: usize = 256;
+
+/// Maximum number of canvas pixels summed over all pages of one document.
+pub const MAX_PDF_PIXELS: u64 = 4 * MAX_IMAGE_PIXELS;
+
+/// Maximum page width or height in points. This is the limit recommended by the PDF
+/// specification for compatibility with older readers.
+pub const MAX_PDF_PAGE_SIZE: f32 = 14400.0;
+
+#[derive(Serialize, Deserialize, JsonSchema, Copy, Clone, Default)]
+pub struct GraphicsPdfMargins {
+  pub top: f32,
+  pub right: f32,
+  pub bottom: f32,
+  pub left: f32,
+}
+
+/// Text drawn over the page raster with the standard Helvetica font. Characters outside Latin-1
+/// are replaced with `?`.
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct GraphicsPdfText {
+  pub text: String,
+
+  /// Position of the baseline origin in points, relative to the top-left corner of the content
+  /// box.
+  pub x: f32,
+  pub y: f32,
+  pub size: f32,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct GraphicsPdfPage {
+  pub canvas: CanvasConfig,
+
+  /// Page size in points (1/72 inch). A4 is 595x842, US Letter is 612x792.
+  pub width: f32,
+  pub height: f32,
+
+  /// The canvas is scaled to fill the page minus the margins.
+  #[serde(default)]
+  pub margins: GraphicsPdfMargins,
+
+  #[serde(default)]
+  pub texts: Vec<GraphicsPdfText>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct GraphicsPdfRenderConfig {
+  pub title: Option<String>,
+  pub pages: Vec<GraphicsPdfPage>,
+}
+
+#[derive(Error, Debug)]
+#[error("pdf page count out of range")]
+pub struct InvalidPageCount;
+
+#[derive(Error, Debug)]
+#[error("invalid pdf page geometry")]
+pub struct InvalidPageGeometry;
+
+#[derive(Error, Debug)]
+#[error("pdf exceeds the pixel budget")]
+pub struct PdfTooLarge;
+
+#[derive(Error, Debug)]
+#[error("expected {0} page rasters, got {1}")]
+struct PageRasterCountMismatch(usize, u32);
+
+impl GraphicsPdfPage {
+  fn validate(&self) -> Result<()> {
+    let m = &self.margins;
+    let numbers = [self.width, self.height, m.top, m.right, m.bottom, m.left];
+    let ok = numbers.iter().all(|x| x.is_finite() && *x >= 0.0)
+      && self.width <= MAX_PDF_PAGE_SIZE
+      && self.height <= MAX_PDF_PAGE_SIZE
+      && m.left + m.right < self.width
+      && m.top + m.bottom < self.height
+      && self
+        .texts
+        .iter()
+        .all(|t| t.x.is_finite() && t.y.is_finite() && t.size.is_finite() && t.size > 0.0);
+    if !ok {
+      return Err(InvalidPageGeometry.into());
+    }
+    check_dimensions(
+      u32::try_from(self.canvas.dimensions.width).unwrap_or(0),
+      u32::try_from(self.canvas.dimensions.height).unwrap_or(0),
+    )
+  }
+}
+
+fn check_pages(pages: &[GraphicsPdfPage]) -> Result<()> {
+  if pages.is_empty() || pages.len() > MAX_PDF_PAGES {
+    return Err(InvalidPageCount.into());
+  }
+  let mut pixels = 0u64;
+  for page in pages {
+    page.validate()?;
+    pixels += page.canvas.dimensions.width as u64 * page.canvas.dimensions.height as u64;
+  }
+  if pixels > MAX_PDF_PIXELS {
+    return Err(PdfTooLarge.into());
+  }
+  Ok(())
+}
+
+/// Encodes a string as a PDF literal string in WinAnsiEncoding, which matches Latin-1 for
+/// printable characters.
+fn pdf_string(s: &str) -> String {
+  let mut out = String::with_capacity(s.len() + 2);
+  out.push('(');
+  for ch in s.chars() {
+    match ch {
+      '(' | ')' | '\\' => {
+        out.push('\\');
+        out.push(ch);
+      }
+      ' '..='~' => out.push(ch),
+      '\u{a0}'..='\u{ff}' => write!(out, "\\{:03o}", ch as u32).unwrap(),
+      _ => out.push('?'),
+    }
+  }
+  out.push(')');
+  out
+}
+
+fn deflate(data: &[u8]) -> Result<Vec<u8>> {
+  let mut enc = ZlibEncoder::new(Vec::new(), Compression::default());
+  enc.write_all(data)?;
+  Ok(enc.finish()?)
+}
+
+/// Minimal PDF writer that tracks object offsets for the cross-reference table.
+struct PdfWriter {
+  out: Vec<u8>,
+  offsets: Vec<usize>,
+}
+
+impl PdfWriter {
+  fn new() -> Self {
+    Self {
+      // The binary comment marks the file as binary for transfer tools.
+      out: b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec(),
+      offsets: vec![],
+    }
+  }
+
+  fn reserve(&mut self) -> usize {
+    self.offsets.push(0);
+    self.offsets.len()
+  }
+
+  fn begin(&mut self, id: usize) {
+    self.offsets[id - 1] = self.out.len();
+    self
+      .out
+      .extend_from_slice(format!("{} 0 obj\n", id).as_bytes());
+  }
+
+  fn object(&mut self, id: usize, body: &str) {
+    self.begin(id);
+    self.out.extend_from_slice(body.as_bytes());
+    self.out.extend_from_slice(b"\nendobj\n");
+  }
+
+  fn stream(&mut self, id: usize, dict: &str, data: &[u8]) {
+    self.begin(id);
+    self
+      .out
+      .extend_from_slice(format!("<<{} /Length {}>>\nstream\n", dict, data.len()).as_bytes());
+    self.out.extend_from_slice(data);
+    self.out.extend_from_slice(b"\nendstream\nendobj\n");
+  }
+
+  fn finish(mut self, root: usize, info: Option<usize>) -> Vec<u8> {
+    let xref = self.out.len();
+    let mut table = format!("xref\n0 {}\n0000000000 65535 f \n", self.offsets.len() + 1);
+    for offset in &self.offsets {
+      writeln!(table, "{:010} 00000 n ", offset).unwrap();
+    }
+    write!(
+      table,
+      "trailer\n<< /Size {} /Root {} 0 R",
+      self.offsets.len() + 1,
+      root
+    )
+    .unwrap();
+    if let Some(info) = info {
+      write!(table, " /Info {} 0 R", info).unwrap();
+    }
+    write!(table, " >>\nstartxref\n{}\n%%EOF\n", xref).unwrap();
+    self.out.extend_from_slice(table.as_bytes());
+    self.out
+  }
+}
+
+/// Writes one page. `rgba` holds the RGBA8888 (unpremultiplied) pixels of the page canvas.
+fn write_page(
+  w: &mut PdfWriter,
+  pages_id: usize,
+  font_id: usize,
+  page: &GraphicsPdfPage,
+  rgba: &[u8],
+) -> Result<usize> {
+  let (width, height) = (page.canvas.dimensions.width, page.canvas.dimensions.height);
+  let image_dict = format!(
+    " /Type /XObject /Subtype /Image /Width {} /Height {} /BitsPerComponent 8 /Filter /FlateDecode",
+    width, height
+  );
+
+  let rgb = rgba
+    .chunks_exact(4)
+    .flat_map(|px| px[..3].iter().copied())
+    .collect::<Vec<u8>>();
+  let smask = if rgba.chunks_exact(4).any(|px| px[3] != 0xff) {
+    let alpha = rgba.chunks_exact(4).map(|px| px[3]).collect::<Vec<u8>>();
+    let id = w.reserve();
+    w.stream(
+      id,
+      &format!("{} /ColorSpace /DeviceGray", image_dict),
+      &deflate(&alpha)?,
+    );
+    format!(" /SMask {} 0 R", id)
+  } else {
+    String::new()
+  };
+  let image_id = w.reserve();
+  w.stream(
+    image_id,
+    &format!("{} /ColorSpace /DeviceRGB{}", image_dict, smask),
+    &deflate(&rgb)?,
+  );
+
+  let m = &page.margins;
+  let content_width = page.width - m.left - m.right;
+  let content_height = page.height - m.top - m.bottom;
+  let mut content = format!(
+    "q {} 0 0 {} {} {} cm /Im0 Do Q\n",
+    content_width, content_height, m.left, m.bottom
+  );
+  for text in &page.texts {
+    // PDF user space has its origin at the bottom-left corner of the page.
+    writeln!(
+      content,
+      "BT /F1 {} Tf {} {} Td {} Tj ET",
+      text.size,
+      m.left + text.x,
+      page.height - m.top - text.y,
+      pdf_string(&text.text)
+    )
+    .unwrap();
+  }
+  let content_id = w.reserve();
+  w.stream(
+    content_id,
+    " /Filter /FlateDecode",
+    &deflate(content.as_bytes())?,
+  );
+
+  let page_id = w.reserve();
+  w.object(
+    page_id,
+    &format!(
+      "<< /Type /Page /Parent {} 0 R /MediaBox [0 0 {} {}] /Resources << /XObject << /Im0 {} 0 R >> /Font << /F1 {} 0 R >> >> /Contents {} 0 R >>",
+      pages_id, page.width, page.height, image_id, font_id, content_id
+    ),
+  );
+  Ok(page_id)
+}
+
+/// Builds a PDF document with one page per canvas. `rasters` holds the RGBA8888
+/// (unpremultiplied) pixels of each page canvas.
+pub fn render_pdf(cfg: &GraphicsPdfRenderConfig, rasters: &[Vec<u8>]) -> Result<Vec<u8>> {
+  check_pages(&cfg.pages)?;
+  let mut w = PdfWriter::new();
+  let catalog_id = w.reserve();
+  let pages_id = w.reserve();
+  let font_id = w.reserve();
+
+  w.object(
+    catalog_id,
+    &format!("<< /Type /Catalog /Pages {} 0 R >>", pages_id),
+  );
+  w.object(
+    font_id,
+    "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>",
+  );
+
+  let mut kids = vec![];
+  for (page, rgba) in cfg.pages.iter().zip(rasters) {
+    kids.push(format!(
+      "{} 0 R",
+      write_page(&mut w, pages_id, font_id, page, rgba)?
+    ));
+  }
+  w.object(
+    pages_id,
+    &format!(
+      "<< /Type /Pages /Kids [{}] /Count {} >>",
+      kids.join(" "),
+      kids.len()
+    ),
+  );
+
+  let info_id = match &cfg.title {
+    Some(title) => {
+      let id = w.reserve();
+      w.object(
+        id,
+        &format!("<< /Title {} /Producer (blueboat) >>", pdf_string(title)),
+      );
+      Some(id)
+    }
+    None => None,
+  };
+  Ok(w.finish(catalog_id, info_id))
+}
+
+/// Renders canvases into a PDF document. Takes the render config and an array with the
+/// framebuffer of each page, in the same order as `pages`.
+pub fn api_graphics_canvas_render_pdf(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let cfg: GraphicsPdfRenderConfig = v8_deserialize(scope, args.get(1))?;
+  check_pages(&cfg.pages)?;
+  let rasters = v8::Local::<v8::Array>::try_from(args.get(2))?;
+  if rasters.length() as usize != cfg.pages.len() {
+    return Err(PageRasterCountMismatch(cfg.pages.len(), rasters.length()).into());
+  }
+
+  let mut pixels = Vec::with_capacity(cfg.pages.len());
+  for (i, page) in cfg.pages.iter().enumerate() {
+    let fb = rasters
+      .get_index(scope, i as u32)
+      .ok_or(PageRasterCountMismatch(cfg.pages.len(), rasters.length()))?;
+    let fb = v8::Local::<v8::TypedArray>::try_from(fb)?;
+    let mut fb = unsafe { v8_deref_typed_array_assuming_noalias(scope, fb) };
+    pixels.push(read_rgba(&page.canvas, &mut fb)?);
+  }
+
+  let out = render_pdf(&cfg, &pixels)?;
+  retval.set(create_uint8array_from_bytes(scope, &out).into());
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    pdf_string, render_pdf, GraphicsPdfMargins, GraphicsPdfPage, GraphicsPdfRenderConfig,
+    GraphicsPdfText, InvalidPageCount, InvalidPageGeometry,
+  };
+  use crate::api::graphics::{
+    CanvasAlphaType, CanvasColorType, CanvasConfig, CanvasDimensions, CanvasPixelGeometry,
+  };
+
+  fn mk_page(width: i32, height: i32) -> GraphicsPdfPage {
+    GraphicsPdfPage {
+      canvas: CanvasConfig {
+        dimensions: CanvasDimensions { width, height },
+        color_type: CanvasColorType::RGBA8888,
+        alpha_type: CanvasAlphaType::Unpremul,
+        pixel_geometry: CanvasPixelGeometry::RGBH,
+      },
+      width: 595.0,
+      height: 842.0,
+      margins: GraphicsPdfMargins {
+        top: 36.0,
+        right: 36.0,
+        bottom: 36.0,
+        left: 36.0,
+      },
+      texts: vec![],
+    }
+  }
+
+  fn contains(haystack: &[u8], needle: &str) -> bool {
+    haystack
+      .windows(needle.len())
+      .any(|x| x == needle.as_bytes())
+  }
+
+  /// Checks that every cross-reference entry points at the object it names.
+  fn check_xref(pdf: &[u8]) {
+    let pos = pdf
+      .windows(b"startxref\n".len())
+      .rposition(|x| x == b"startxref\n")
+      .unwrap();
+    let tail = std::str::from_utf8(&pdf[pos..]).unwrap();
+    let startxref: usize = tail.lines().nth(1).unwrap().parse().unwrap();
+    let table = std::str::from_utf8(&pdf[startxref..pos]).unwrap();
+    let mut lines = table.lines();
+    assert_eq!(lines.next(), Some("xref"));
+    let count: usize = lines
+      .next()
+      .unwrap()
+      .split(' ')
+      .nth(1)
+      .unwrap()
+      .parse()
+      .unwrap();
+    for (id, line) in lines.take(count).enumerate().skip(1) {
+      assert_eq!(line.len(), 19);
+      let offset: usize = line[..10].parse().unwrap();
+      assert!(pdf[offset..].starts_with(format!("{} 0 obj\n", id).as_bytes()));
+    }
+  }
+
+  #[test]
+  fn multi_page_document() {
+    let mut first = mk_page(4, 4);
+    first.texts.push(GraphicsPdfText {
+      text: "Invoice (draft)".into(),
+      x: 0.0,
+      y: 12.0,
+      size: 12.0,
+    });
+    let cfg = GraphicsPdfRenderConfig {
+      title: Some("Report".into()),
+      pages: vec![first, mk_page(2, 2)],
+    };
+    let opaque = [10u8, 20, 30, 255].repeat(16);
+    let transparent = [0u8, 0, 0, 0].repeat(4);
+    let pdf = render_pdf(&cfg, &[opaque, transparent]).unwrap();
+
+    assert!(pdf.starts_with(b"%PDF-1.4\n"));
+    assert!(pdf.ends_with(b"%%EOF\n"));
+    assert!(contains(&pdf, "/Count 2"));
+    assert!(contains(&pdf, "/MediaBox [0 0 595 842]"));
+    assert!(contains(&pdf, "/Title (Report)"));
+    // Only the page with transparency gets a soft mask.
+    assert_eq!(
+      pdf
+        .windows(b"/SMask".len())
+        .filter(|x| *x == b"/SMask")
+        .count(),
+      1
+    );
+    check_xref(&pdf);
+  }
+
+  #[test]
+  fn string_escaping() {
+    assert_eq!(pdf_string("a(b)\\c"), "(a\\(b\\)\\\\c)");
+    assert_eq!(pdf_string("caf\u{e9} \u{4e2d}"), "(caf\\351 ?)");
+  }
+
+  #[test]
+  fn validation() {
+    let cfg = GraphicsPdfRenderConfig {
+      title: None,
+      pages: vec![],
+    };
+    assert!(render_pdf(&cfg, &[]).unwrap_err().is::<InvalidPageCount>());
+
+    let mut page = mk_page(1, 1);
+    page.margins.left = 600.0;
+    let cfg = GraphicsPdfRenderConfig {
+      title: None,
+      pages: vec![page],
+    };
+    assert!(render_pdf(&cfg, &[vec![0; 4]])
+      .unwrap_err()
+      .is::<InvalidPageGeometry>());
+  }
+}
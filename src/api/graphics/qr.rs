@@ -0,0 +1,240 @@
+use std::fmt::Write;
+
+use anyhow::Result;
+use qrcode::{types::QrError, Color, EcLevel, QrCode};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use v8;
+
+use crate::{
+  api::util::{mk_v8_string, v8_deserialize},
+  v8util::{create_uint8array_from_bytes, LocalValueExt},
+};
+
+use super::{
+  codec::{encode_canvas, CanvasEncodeConfig},
+  raster::check_dimensions,
+  CanvasAlphaType, CanvasColorType, CanvasConfig, CanvasDimensions, CanvasPixelGeometry,
+};
+
+#[derive(Serialize, Deserialize, JsonSchema, Copy, Clone)]
+pub enum GraphicsQrEcLevel {
+  L,
+  M,
+  Q,
+  H,
+}
+
+impl From<GraphicsQrEcLevel> for EcLevel {
+  fn from(that: GraphicsQrEcLevel) -> Self {
+    match that {
+      GraphicsQrEcLevel::L => Self::L,
+      GraphicsQrEcLevel::M => Self::M,
+      GraphicsQrEcLevel::Q => Self::Q,
+      GraphicsQrEcLevel::H => Self::H,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Copy, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum GraphicsQrOutputFormat {
+  Svg,
+  Png,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct GraphicsQrEncodeConfig {
+  pub ec_level: GraphicsQrEcLevel,
+
+  /// Size of one module in pixels (PNG) or user units (SVG).
+  pub module_size: u32,
+
+  /// Width of the light border around the symbol, in modules. The QR specification asks for 4.
+  pub quiet_zone: u32,
+
+  pub format: GraphicsQrOutputFormat,
+}
+
+#[derive(Error, Debug)]
+#[error("qr payload too long for error correction level")]
+pub struct QrPayloadTooLong;
+
+#[derive(Error, Debug)]
+#[error("qr encode failed: {0}")]
+struct QrEncodeError(QrError);
+
+#[derive(Error, Debug)]
+#[error("qr module size must be positive")]
+struct InvalidModuleSize;
+
+/// The encoded symbol, with the side length in modules including the quiet zone on both sides.
+struct QrModules {
+  code: QrCode,
+  quiet_zone: u32,
+  size: u32,
+}
+
+impl QrModules {
+  fn new(payload: &str, cfg: &GraphicsQrEncodeConfig) -> Result<Self> {
+    if cfg.module_size == 0 {
+      return Err(InvalidModuleSize.into());
+    }
+    let code = QrCode::with_error_correction_level(payload, cfg.ec_level.into()).map_err(
+      |e| -> anyhow::Error {
+        match e {
+          QrError::DataTooLong => QrPayloadTooLong.into(),
+          e => QrEncodeError(e).into(),
+        }
+      },
+    )?;
+    let size = (code.width() as u32)
+      .checked_add(cfg.quiet_zone.saturating_mul(2))
+      .unwrap_or(u32::MAX);
+    let pixels = size.saturating_mul(cfg.module_size);
+    check_dimensions(pixels, pixels)?;
+    Ok(Self {
+      code,
+      quiet_zone: cfg.quiet_zone,
+      size,
+    })
+  }
+
+  fn dark_modules(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+    let width = self.code.width();
+    (0..width).flat_map(move |y| {
+      (0..width).filter_map(move |x| {
+        if self.code[(x, y)] == Color::Dark {
+          Some((x as u32 + self.quiet_zone, y as u32 + self.quiet_zone))
+        } else {
+          None
+        }
+      })
+    })
+  }
+}
+
+pub fn render_svg(payload: &str, cfg: &GraphicsQrEncodeConfig) -> Result<String> {
+  let modules = QrModules::new(payload, cfg)?;
+  let px = modules.size * cfg.module_size;
+  let s = cfg.module_size;
+  let mut out = String::new();
+  write!(
+    out,
+    r##"<?xml version="1.0" standalone="yes"?><svg xmlns="http://www.w3.org/2000/svg" version="1.1" width="{px}" height="{px}" viewBox="0 0 {px} {px}" shape-rendering="crispEdges"><rect x="0" y="0" width="{px}" height="{px}" fill="#fff"/><path fill="#000" d=""##,
+    px = px
+  )?;
+  for (x, y) in modules.dark_modules() {
+    write!(out, "M{} {}h{}v{}h-{}z", x * s, y * s, s, s, s)?;
+  }
+  out.push_str(r#""/></svg>"#);
+  Ok(out)
+}
+
+pub fn render_png(payload: &str, cfg: &GraphicsQrEncodeConfig) -> Result<Vec<u8>> {
+  let modules = QrModules::new(payload, cfg)?;
+  let px = modules.size * cfg.module_size;
+  let s = cfg.module_size as usize;
+  let row_bytes = px as usize * 4;
+  let mut fb = vec![0xffu8; row_bytes * px as usize];
+  for (x, y) in modules.dark_modules() {
+    let (x, y) = (x as usize * s, y as usize * s);
+    for row in y..y + s {
+      let start = row * row_bytes + x * 4;
+      for pixel in fb[start..start + s * 4].chunks_exact_mut(4) {
+        pixel.copy_from_slice(&[0, 0, 0, 0xff]);
+      }
+    }
+  }
+  let config = CanvasConfig {
+    dimensions: CanvasDimensions {
+      width: px as i32,
+      height: px as i32,
+    },
+    color_type: CanvasColorType::RGBA8888,
+    alpha_type: CanvasAlphaType::Opaque,
+    pixel_geometry: CanvasPixelGeometry::RGBH,
+  };
+  encode_canvas(&config, &CanvasEncodeConfig::png(), &mut fb)
+}
+
+/// Returns an SVG string or PNG bytes depending on `format`.
+pub fn api_graphics_qr_encode(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let payload = unsafe { args.get(1).read_string_assume_noalias(scope)? };
+  let cfg: GraphicsQrEncodeConfig = v8_deserialize(scope, args.get(2))?;
+  match cfg.format {
+    GraphicsQrOutputFormat::Svg => {
+      let svg = render_svg(&payload, &cfg)?;
+      retval.set(mk_v8_string(scope, &svg)?.into());
+    }
+    GraphicsQrOutputFormat::Png => {
+      let png = render_png(&payload, &cfg)?;
+      retval.set(create_uint8array_from_bytes(scope, &png).into());
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    render_png, render_svg, GraphicsQrEcLevel, GraphicsQrEncodeConfig, GraphicsQrOutputFormat,
+    QrPayloadTooLong,
+  };
+  use crate::api::graphics::raster::decode_rgba;
+
+  fn mk_config(
+    ec_level: GraphicsQrEcLevel,
+    format: GraphicsQrOutputFormat,
+  ) -> GraphicsQrEncodeConfig {
+    GraphicsQrEncodeConfig {
+      ec_level,
+      module_size: 4,
+      quiet_zone: 4,
+      format,
+    }
+  }
+
+  #[test]
+  fn svg() {
+    let cfg = mk_config(GraphicsQrEcLevel::M, GraphicsQrOutputFormat::Svg);
+    let svg = render_svg("otpauth://totp/test?secret=JBSWY3DPEHPK3PXP", &cfg).unwrap();
+    assert!(svg.starts_with("<?xml"));
+    assert!(svg.ends_with("</svg>"));
+  }
+
+  #[test]
+  fn png_with_quiet_zone() {
+    let mut cfg = mk_config(GraphicsQrEcLevel::L, GraphicsQrOutputFormat::Png);
+    let png = render_png("hello", &cfg).unwrap();
+    let (_, image) = decode_rgba(&png).unwrap();
+    // Version 1 is 21 modules wide.
+    assert_eq!(image.width(), (21 + 8) * 4);
+    assert_eq!(image.get_pixel(0, 0).0, [0xff, 0xff, 0xff, 0xff]);
+    // Top-left finder pattern starts right after the quiet zone.
+    assert_eq!(image.get_pixel(16, 16).0, [0, 0, 0, 0xff]);
+
+    cfg.quiet_zone = 0;
+    let png = render_png("hello", &cfg).unwrap();
+    let (_, image) = decode_rgba(&png).unwrap();
+    assert_eq!(image.width(), 21 * 4);
+    assert_eq!(image.get_pixel(0, 0).0, [0, 0, 0, 0xff]);
+  }
+
+  #[test]
+  fn capacity() {
+    // Version 40-H holds at most 1273 bytes.
+    let payload = "x".repeat(1500);
+    let cfg = mk_config(GraphicsQrEcLevel::H, GraphicsQrOutputFormat::Svg);
+    assert!(render_svg(&payload, &cfg)
+      .unwrap_err()
+      .is::<QrPayloadTooLong>());
+    let cfg = mk_config(GraphicsQrEcLevel::L, GraphicsQrOutputFormat::Svg);
+    render_svg(&payload, &cfg).unwrap();
+  }
+}
@@ -0,0 +1,349 @@
+use std::{convert::TryFrom, io::Cursor};
+
+use anyhow::Result;
+use image::{imageops::FilterType, io::Limits, io::Reader as ImageReader, ImageFormat, RgbaImage};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use v8;
+
+use crate::{
+  api::util::{v8_deserialize, v8_serialize},
+  v8util::{create_uint8array_from_bytes, LocalValueExt, ObjectExt},
+};
+
+use super::{
+  codec::{encode_canvas, CanvasEncodeConfig},
+  CanvasAlphaType, CanvasColorType, CanvasConfig, CanvasDimensions, CanvasPixelGeometry,
+};
+
+/// Maximum width or height of decoded and resampled images.
+pub const MAX_IMAGE_DIMENSION: u32 = 16384;
+
+/// Maximum number of pixels of decoded and resampled images. An RGBA buffer of this size takes
+/// 64 MiB.
+pub const MAX_IMAGE_PIXELS: u64 = 16 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize, JsonSchema, Copy, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum GraphicsResizeFilter {
+  Nearest,
+  Triangle,
+  Lanczos3,
+}
+
+impl From<GraphicsResizeFilter> for FilterType {
+  fn from(that: GraphicsResizeFilter) -> Self {
+    match that {
+      GraphicsResizeFilter::Nearest => Self::Nearest,
+      GraphicsResizeFilter::Triangle => Self::Triangle,
+      GraphicsResizeFilter::Lanczos3 => Self::Lanczos3,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Copy, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum GraphicsImageFormat {
+  Png,
+  Jpeg,
+  Webp,
+  Gif,
+}
+
+impl GraphicsImageFormat {
+  fn from_image_format(x: ImageFormat) -> Option<Self> {
+    match x {
+      ImageFormat::Png => Some(Self::Png),
+      ImageFormat::Jpeg => Some(Self::Jpeg),
+      ImageFormat::WebP => Some(Self::Webp),
+      ImageFormat::Gif => Some(Self::Gif),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct GraphicsDecodedImageInfo {
+  pub format: GraphicsImageFormat,
+  pub width: u32,
+  pub height: u32,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct GraphicsImageResizeConfig {
+  pub width: u32,
+  pub height: u32,
+  pub filter: GraphicsResizeFilter,
+
+  /// Re-encode the result. Without this, the raw RGBA8888 (unpremultiplied) pixels are returned.
+  pub output: Option<CanvasEncodeConfig>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct GraphicsImageCropConfig {
+  pub x: u32,
+  pub y: u32,
+  pub width: u32,
+  pub height: u32,
+
+  /// Re-encode the result. Without this, the raw RGBA8888 (unpremultiplied) pixels are returned.
+  pub output: Option<CanvasEncodeConfig>,
+}
+
+#[derive(Error, Debug)]
+#[error("image dimensions {0}x{1} out of range")]
+pub struct ImageDimensionsOutOfRange(pub u32, pub u32);
+
+#[derive(Error, Debug)]
+#[error("crop rectangle exceeds image bounds")]
+struct CropOutOfBounds;
+
+#[derive(Error, Debug)]
+#[error("unsupported image format")]
+pub struct UnsupportedImageFormat;
+
+pub fn check_dimensions(width: u32, height: u32) -> Result<()> {
+  if width == 0
+    || height == 0
+    || width > MAX_IMAGE_DIMENSION
+    || height > MAX_IMAGE_DIMENSION
+    || width as u64 * height as u64 > MAX_IMAGE_PIXELS
+  {
+    return Err(ImageDimensionsOutOfRange(width, height).into());
+  }
+  Ok(())
+}
+
+/// Decodes a PNG, JPEG, WebP or GIF (first frame) image into RGBA8888 pixels, applying the same
+/// size limits as the outputs.
+///
+/// Processes are built with `panic = "abort"`, so a panic in a decoder can't be caught. Inputs are
+/// sniffed and size-checked before any decoder runs, and truncated or corrupt data is reported by
+/// the decoders as errors.
+pub fn decode_rgba(data: &[u8]) -> Result<(GraphicsImageFormat, RgbaImage)> {
+  let mut reader = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+  let format = reader
+    .format()
+    .and_then(GraphicsImageFormat::from_image_format)
+    .ok_or(UnsupportedImageFormat)?;
+  let mut limits = Limits::default();
+  limits.max_image_width = Some(MAX_IMAGE_DIMENSION);
+  limits.max_image_height = Some(MAX_IMAGE_DIMENSION);
+  limits.max_alloc = Some(MAX_IMAGE_PIXELS * 4);
+  reader.limits(limits);
+  let image = reader.decode()?;
+  check_dimensions(image.width(), image.height())?;
+  Ok((format, image.into_rgba8()))
+}
+
+/// Converts pixels into the output requested by the worker: encoded bytes, or a raw framebuffer
+/// compatible with an RGBA8888/Unpremul canvas.
+fn output_image(image: RgbaImage, output: Option<&CanvasEncodeConfig>) -> Result<Vec<u8>> {
+  let output = match output {
+    Some(x) => x,
+    None => return Ok(image.into_raw()),
+  };
+  let config = CanvasConfig {
+    dimensions: CanvasDimensions {
+      width: image.width() as i32,
+      height: image.height() as i32,
+    },
+    color_type: CanvasColorType::RGBA8888,
+    alpha_type: CanvasAlphaType::Unpremul,
+    pixel_geometry: CanvasPixelGeometry::RGBH,
+  };
+  let mut fb = image.into_raw();
+  encode_canvas(&config, output, &mut fb)
+}
+
+pub fn resize(data: &[u8], cfg: &GraphicsImageResizeConfig) -> Result<Vec<u8>> {
+  check_dimensions(cfg.width, cfg.height)?;
+  let (_, image) = decode_rgba(data)?;
+  let image = image::imageops::resize(&image, cfg.width, cfg.height, cfg.filter.into());
+  output_image(image, cfg.output.as_ref())
+}
+
+pub fn crop(data: &[u8], cfg: &GraphicsImageCropConfig) -> Result<Vec<u8>> {
+  check_dimensions(cfg.width, cfg.height)?;
+  let (_, image) = decode_rgba(data)?;
+  let right = cfg.x.checked_add(cfg.width).ok_or(CropOutOfBounds)?;
+  let bottom = cfg.y.checked_add(cfg.height).ok_or(CropOutOfBounds)?;
+  if right > image.width() || bottom > image.height() {
+    return Err(CropOutOfBounds.into());
+  }
+  let image = image::imageops::crop_imm(&image, cfg.x, cfg.y, cfg.width, cfg.height).to_image();
+  output_image(image, cfg.output.as_ref())
+}
+
+pub fn api_graphics_image_resize(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let data = unsafe { args.get(1).read_bytes_assume_noalias(scope)? };
+  let cfg: GraphicsImageResizeConfig = v8_deserialize(scope, args.get(2))?;
+  let out = resize(&data, &cfg)?;
+  retval.set(create_uint8array_from_bytes(scope, &out).into());
+  Ok(())
+}
+
+pub fn api_graphics_image_crop(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let data = unsafe { args.get(1).read_bytes_assume_noalias(scope)? };
+  let cfg: GraphicsImageCropConfig = v8_deserialize(scope, args.get(2))?;
+  let out = crop(&data, &cfg)?;
+  retval.set(create_uint8array_from_bytes(scope, &out).into());
+  Ok(())
+}
+
+/// Decodes an image into the framebuffer of a new RGBA8888/Unpremul canvas. Returns the image
+/// info with the framebuffer in `pixels`.
+pub fn api_graphics_canvas_decode_image(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let data = unsafe { args.get(1).read_bytes_assume_noalias(scope)? };
+  let (format, image) = decode_rgba(&data)?;
+  let info = GraphicsDecodedImageInfo {
+    format,
+    width: image.width(),
+    height: image.height(),
+  };
+  let out = v8_serialize(scope, &info)?;
+  let pixels = create_uint8array_from_bytes(scope, image.as_raw());
+  v8::Local::<v8::Object>::try_from(out)?.set_ext(scope, "pixels", pixels.into());
+  retval.set(out);
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Cursor;
+
+  use image::{DynamicImage, ImageOutputFormat, Rgba, RgbaImage};
+
+  use super::{
+    crop, decode_rgba, resize, GraphicsImageCropConfig, GraphicsImageFormat,
+    GraphicsImageResizeConfig, GraphicsResizeFilter, ImageDimensionsOutOfRange,
+    UnsupportedImageFormat,
+  };
+
+  fn mk_image(width: u32, height: u32, format: ImageOutputFormat) -> Vec<u8> {
+    let image = RgbaImage::from_fn(width, height, |x, y| Rgba([x as u8, y as u8, 0, 255]));
+    let image = match format {
+      // JPEG has no alpha channel.
+      ImageOutputFormat::Jpeg(_) => {
+        DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(image).into_rgb8())
+      }
+      _ => DynamicImage::ImageRgba8(image),
+    };
+    let mut out = Cursor::new(vec![]);
+    image.write_to(&mut out, format).unwrap();
+    out.into_inner()
+  }
+
+  fn mk_png(width: u32, height: u32) -> Vec<u8> {
+    mk_image(width, height, ImageOutputFormat::Png)
+  }
+
+  #[test]
+  fn resize_and_crop() {
+    let png = mk_png(64, 32);
+    for filter in [
+      GraphicsResizeFilter::Nearest,
+      GraphicsResizeFilter::Triangle,
+      GraphicsResizeFilter::Lanczos3,
+    ] {
+      let out = resize(
+        &png,
+        &GraphicsImageResizeConfig {
+          width: 16,
+          height: 8,
+          filter,
+          output: None,
+        },
+      )
+      .unwrap();
+      assert_eq!(out.len(), 16 * 8 * 4);
+    }
+
+    let out = crop(
+      &png,
+      &GraphicsImageCropConfig {
+        x: 10,
+        y: 5,
+        width: 4,
+        height: 2,
+        output: None,
+      },
+    )
+    .unwrap();
+    assert_eq!(out.len(), 4 * 2 * 4);
+    assert_eq!(&out[0..4], &[10, 5, 0, 255]);
+
+    let mut cfg = GraphicsImageCropConfig {
+      x: 60,
+      y: 0,
+      width: 8,
+      height: 8,
+      output: None,
+    };
+    assert!(crop(&png, &cfg).is_err());
+    cfg.x = u32::MAX;
+    assert!(crop(&png, &cfg).is_err());
+  }
+
+  #[test]
+  fn rejects_absurd_sizes() {
+    let png = mk_png(4, 4);
+    for (width, height) in [(0, 10), (100000, 1), (16384, 16384)] {
+      let err = resize(
+        &png,
+        &GraphicsImageResizeConfig {
+          width,
+          height,
+          filter: GraphicsResizeFilter::Nearest,
+          output: None,
+        },
+      )
+      .unwrap_err();
+      assert!(err.is::<ImageDimensionsOutOfRange>());
+    }
+  }
+
+  #[test]
+  fn decode_formats() {
+    for (format, expected) in [
+      (ImageOutputFormat::Png, GraphicsImageFormat::Png),
+      (ImageOutputFormat::Jpeg(90), GraphicsImageFormat::Jpeg),
+      (ImageOutputFormat::Gif, GraphicsImageFormat::Gif),
+    ] {
+      let data = mk_image(20, 10, format);
+      let (format, image) = decode_rgba(&data).unwrap();
+      assert_eq!(format, expected);
+      assert_eq!(image.dimensions(), (20, 10));
+
+      // Truncated headers must fail cleanly.
+      for len in [16, 4] {
+        assert!(decode_rgba(&data[..len]).is_err());
+      }
+    }
+  }
+
+  #[test]
+  fn rejects_garbage() {
+    assert!(decode_rgba(b"not an image")
+      .unwrap_err()
+      .is::<UnsupportedImageFormat>());
+    let png = mk_png(32, 32);
+    assert!(decode_rgba(&png[..png.len() / 2]).is_err());
+    assert!(decode_rgba(b"BM\0\0\0\0\0\0\0\0\0\0\0\0")
+      .unwrap_err()
+      .is::<UnsupportedImageFormat>());
+  }
+}
@@ -1,7 +1,11 @@
 use anyhow::Result;
-use fontdue::layout::{CoordinateSystem, Layout, LayoutSettings, TextStyle};
+use fontdue::{
+  layout::{CoordinateSystem, Layout, LayoutSettings, TextStyle},
+  Font,
+};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 use v8;
 
 use crate::api::util::{v8_deserialize, v8_serialize};
@@ -30,6 +34,236 @@ pub struct GraphicsTextMeasureGlyph {
   height: usize,
 }
 
+#[derive(Deserialize, JsonSchema, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum GraphicsTextAlign {
+  Left,
+  Center,
+  Right,
+  Justify,
+}
+
+#[derive(Deserialize, JsonSchema, Clone)]
+pub struct GraphicsTextLayoutSettings {
+  text: String,
+  font: String,
+  max_width: f32,
+  align: GraphicsTextAlign,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Debug)]
+pub struct GraphicsTextLayoutOutput {
+  height: f32,
+  lines: Vec<GraphicsTextLayoutLine>,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Debug)]
+pub struct GraphicsTextLayoutLine {
+  /// Top of the line box.
+  y: f32,
+  baseline: f32,
+
+  /// Width of the line content before justification, excluding trailing whitespace.
+  width: f32,
+  runs: Vec<GraphicsTextLayoutRun>,
+}
+
+/// A word positioned on its line. `x` is relative to the left edge of the layout box.
+#[derive(Serialize, JsonSchema, Clone, Debug)]
+pub struct GraphicsTextLayoutRun {
+  text: String,
+  x: f32,
+  width: f32,
+}
+
+#[derive(Error, Debug)]
+#[error("max_width must be a positive number")]
+pub struct InvalidMaxWidth;
+
+/// Picks the first font in the fallback list that has a glyph for `ch`.
+fn font_index_for(fonts: &[&Font], ch: char) -> usize {
+  fonts
+    .iter()
+    .position(|x| x.lookup_glyph_index(ch) != 0)
+    .unwrap_or(0)
+}
+
+/// A word with the whitespace that follows it. A word followed by a newline ends its paragraph.
+#[derive(Default)]
+struct Word {
+  text: String,
+  width: f32,
+  space: f32,
+  hard_break: bool,
+}
+
+impl Word {
+  /// Splits off the longest prefix that fits in `max_width`, keeping at least one character.
+  fn split_to_fit(&mut self, max_width: f32, advance: &impl Fn(char) -> f32) -> Word {
+    let mut head = Word::default();
+    for ch in self.text.chars() {
+      let w = advance(ch);
+      if !head.text.is_empty() && head.width + w > max_width {
+        break;
+      }
+      head.text.push(ch);
+      head.width += w;
+    }
+    self.text.drain(..head.text.len());
+    self.width -= head.width;
+    head
+  }
+}
+
+fn split_words(text: &str, advance: &impl Fn(char) -> f32) -> Vec<Word> {
+  let mut words = vec![];
+  let mut current = Word::default();
+  for ch in text.chars() {
+    if ch == '\n' {
+      current.hard_break = true;
+      words.push(std::mem::take(&mut current));
+    } else if ch.is_whitespace() {
+      current.space += advance(ch);
+    } else {
+      if current.space > 0.0 {
+        words.push(std::mem::take(&mut current));
+      }
+      current.text.push(ch);
+      current.width += advance(ch);
+    }
+  }
+  words.push(current);
+  words
+}
+
+fn finish_line(
+  words: Vec<Word>,
+  paragraph_end: bool,
+  max_width: f32,
+  align: GraphicsTextAlign,
+  y: f32,
+  ascent: f32,
+) -> GraphicsTextLayoutLine {
+  let last_space = words.last().map(|x| x.space).unwrap_or(0.0);
+  let width = words.iter().map(|x| x.width + x.space).sum::<f32>() - last_space;
+  let free = (max_width - width).max(0.0);
+  let (mut x, gap) = match align {
+    GraphicsTextAlign::Left => (0.0, 0.0),
+    GraphicsTextAlign::Center => (free / 2.0, 0.0),
+    GraphicsTextAlign::Right => (free, 0.0),
+    GraphicsTextAlign::Justify if !paragraph_end && words.len() > 1 => {
+      (0.0, free / (words.len() - 1) as f32)
+    }
+    GraphicsTextAlign::Justify => (0.0, 0.0),
+  };
+  let mut runs = vec![];
+  for word in words {
+    if !word.text.is_empty() {
+      runs.push(GraphicsTextLayoutRun {
+        text: word.text,
+        x,
+        width: word.width,
+      });
+    }
+    x += word.width + word.space + gap;
+  }
+  GraphicsTextLayoutLine {
+    y,
+    baseline: y + ascent,
+    width,
+    runs,
+  }
+}
+
+/// Greedy line breaking at whitespace. A word wider than `max_width` on its own is broken at
+/// character boundaries.
+fn layout_text(
+  text: &str,
+  max_width: f32,
+  align: GraphicsTextAlign,
+  line_height: f32,
+  ascent: f32,
+  advance: impl Fn(char) -> f32,
+) -> Result<GraphicsTextLayoutOutput> {
+  if !max_width.is_finite() || max_width <= 0.0 {
+    return Err(InvalidMaxWidth.into());
+  }
+
+  let mut lines = vec![];
+  let mut current: Vec<Word> = vec![];
+  let mut current_width = 0.0f32;
+  let push_line =
+    |words: Vec<Word>, paragraph_end: bool, lines: &mut Vec<GraphicsTextLayoutLine>| {
+      let y = lines.len() as f32 * line_height;
+      lines.push(finish_line(
+        words,
+        paragraph_end,
+        max_width,
+        align,
+        y,
+        ascent,
+      ));
+    };
+
+  for mut word in split_words(text, &advance) {
+    if !current.is_empty() && current_width + word.width > max_width {
+      push_line(std::mem::take(&mut current), false, &mut lines);
+      current_width = 0.0;
+    }
+    while current.is_empty() && word.width > max_width && word.text.chars().nth(1).is_some() {
+      let head = word.split_to_fit(max_width, &advance);
+      push_line(vec![head], false, &mut lines);
+    }
+    current_width += word.width + word.space;
+    let hard_break = word.hard_break;
+    current.push(word);
+    if hard_break {
+      push_line(std::mem::take(&mut current), true, &mut lines);
+      current_width = 0.0;
+    }
+  }
+  if !current.is_empty() {
+    push_line(current, true, &mut lines);
+  }
+
+  Ok(GraphicsTextLayoutOutput {
+    height: lines.len() as f32 * line_height,
+    lines,
+  })
+}
+
+/// Breaks text into lines that fit `max_width` and positions each word according to `align`.
+pub fn api_graphics_text_layout(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let settings: GraphicsTextLayoutSettings = v8_deserialize(scope, args.get(1))?;
+  let font = font_util::Font::new(&settings.font)?;
+  let fonts = search_font(&font);
+  if fonts.is_empty() {
+    anyhow::bail!("No available fonts");
+  }
+  let (line_height, ascent) = match fonts[0].horizontal_line_metrics(font.size) {
+    Some(m) => (m.new_line_size, m.ascent),
+    None => (font.size * 1.2, font.size),
+  };
+  let out = layout_text(
+    &settings.text,
+    settings.max_width,
+    settings.align,
+    line_height,
+    ascent,
+    |ch| {
+      fonts[font_index_for(&fonts, ch)]
+        .metrics(ch, font.size)
+        .advance_width
+    },
+  )?;
+  retval.set(v8_serialize(scope, &out)?);
+  Ok(())
+}
+
 pub fn api_graphics_text_measure(
   scope: &mut v8::HandleScope,
   args: v8::FunctionCallbackArguments,
@@ -51,13 +285,7 @@ pub fn api_graphics_text_measure(
   for ch in settings.text.chars() {
     sbuf.clear();
     sbuf.push(ch);
-    let font_index = fonts
-      .iter()
-      .copied()
-      .enumerate()
-      .find(|(_, x)| x.lookup_glyph_index(ch) != 0)
-      .map(|(i, _)| i)
-      .unwrap_or_else(|| 0);
+    let font_index = font_index_for(&fonts, ch);
     layout.append(&fonts, &TextStyle::new(&sbuf, font.size, font_index));
   }
 
@@ -79,3 +307,86 @@ pub fn api_graphics_text_measure(
 
   Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+  use super::{layout_text, GraphicsTextAlign, GraphicsTextLayoutOutput, InvalidMaxWidth};
+
+  /// Monospace layout: every character is 10 units wide and lines are 20 units tall.
+  fn layout(text: &str, max_width: f32, align: GraphicsTextAlign) -> GraphicsTextLayoutOutput {
+    layout_text(text, max_width, align, 20.0, 15.0, |_| 10.0).unwrap()
+  }
+
+  fn line_texts(out: &GraphicsTextLayoutOutput) -> Vec<String> {
+    out
+      .lines
+      .iter()
+      .map(|l| {
+        l.runs
+          .iter()
+          .map(|r| r.text.as_str())
+          .collect::<Vec<_>>()
+          .join(" ")
+      })
+      .collect()
+  }
+
+  #[test]
+  fn wraps_at_words() {
+    let out = layout("the quick brown fox", 100.0, GraphicsTextAlign::Left);
+    assert_eq!(line_texts(&out), ["the quick", "brown fox"]);
+    assert_eq!(out.height, 40.0);
+    assert_eq!(out.lines[1].y, 20.0);
+    assert_eq!(out.lines[1].baseline, 35.0);
+    assert_eq!(out.lines[0].width, 90.0);
+    assert_eq!(out.lines[0].runs[1].x, 40.0);
+  }
+
+  #[test]
+  fn hard_breaks() {
+    let out = layout("a\n\nb", 100.0, GraphicsTextAlign::Left);
+    assert_eq!(line_texts(&out), ["a", "", "b"]);
+  }
+
+  #[test]
+  fn breaks_long_words() {
+    let out = layout("abcdefghij xy", 40.0, GraphicsTextAlign::Left);
+    assert_eq!(line_texts(&out), ["abcd", "efgh", "ij", "xy"]);
+
+    // A single character wider than the box still goes on its own line.
+    let out = layout("ab", 5.0, GraphicsTextAlign::Left);
+    assert_eq!(line_texts(&out), ["a", "b"]);
+  }
+
+  #[test]
+  fn alignment() {
+    let out = layout("ab cd", 100.0, GraphicsTextAlign::Center);
+    assert_eq!(out.lines[0].runs[0].x, 25.0);
+    let out = layout("ab cd", 100.0, GraphicsTextAlign::Right);
+    assert_eq!(out.lines[0].runs[0].x, 50.0);
+    assert_eq!(out.lines[0].runs[1].x, 80.0);
+  }
+
+  #[test]
+  fn justify() {
+    let out = layout("aa bb cc dd", 100.0, GraphicsTextAlign::Justify);
+    assert_eq!(line_texts(&out), ["aa bb cc", "dd"]);
+    let runs = &out.lines[0].runs;
+    assert_eq!(runs[0].x, 0.0);
+    assert_eq!(runs[1].x, 40.0);
+    assert_eq!(runs[2].x + runs[2].width, 100.0);
+    // The last line of a paragraph is left aligned.
+    assert_eq!(out.lines[1].runs[0].x, 0.0);
+  }
+
+  #[test]
+  fn invalid_width() {
+    for w in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+      assert!(
+        layout_text("a", w, GraphicsTextAlign::Left, 20.0, 15.0, |_| 10.0)
+          .unwrap_err()
+          .is::<InvalidMaxWidth>()
+      );
+    }
+  }
+}
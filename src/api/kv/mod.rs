@@ -1,26 +1,201 @@
-use std::sync::Arc;
+use std::{
+  sync::Arc,
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use crate::{
   exec::Executor,
-  mds::get_mds,
+  lpch::AppLogLevel,
+  mds::{get_mds, MdsCluster},
   metadata::Metadata,
   reliable_channel::RchReqBody,
-  v8util::{create_uint8array_from_bytes, FunctionCallbackArgumentsExt},
+  v8util::{create_uint8array_from_bytes, FunctionCallbackArgumentsExt, ObjectExt},
 };
 use anyhow::Result;
 use foundationdb::{
   options::{MutationType, StreamingMode},
-  RangeOption,
+  RangeOption, Transaction,
 };
 use futures::{stream::FuturesOrdered, TryStreamExt};
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::oneshot;
 use v8;
 
-use super::util::{v8_deref_typed_array_assuming_noalias, v8_deserialize, v8_error, v8_serialize};
+use super::util::{
+  mk_v8_string, v8_deref_typed_array_assuming_noalias, v8_deserialize, v8_error,
+  v8_invoke_callback, v8_serialize, write_applog,
+};
 
 const MAX_KEYS_PER_OP: usize = 1000;
 const MAX_KEY_SIZE: usize = 4096;
 const MAX_VALUE_SIZE: usize = 80000;
+const MAX_PREFIX_LIST_TOTAL_VALUE_SIZE: usize = 8 * 1024 * 1024;
+const MAX_KV_WATCHES: usize = 16;
+const KV_WATCH_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const KV_WATCH_LEASE_MS: u64 = 30_000;
+const KV_WATCH_SWEEP_LIMIT: usize = 16;
+const KV_WATCH_BATCH_SIZE: usize = 100;
+
+// Key expiry
+//
+// FoundationDB has no native TTL, so expiry is implemented on top of it: setting a key with a TTL
+// additionally writes the absolute expiry time (milliseconds since the Unix epoch, big-endian u64)
+// to `MdsCluster::pack_expiry_key`. Every read path treats a key whose expiry time has passed as
+// absent. Expired entries are not reclaimed in the background - they stay in storage until the
+// key is overwritten or deleted, directly or by a prefix delete. A prefix delete clears the expiry
+// records under the prefix together with the keys, so it never leaves a stale record behind for
+// a key that is written again later.
+
+fn now_millis() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|x| x.as_millis() as u64)
+    .unwrap_or(0)
+}
+
+fn is_expired(expiry: Option<&[u8]>, now: u64) -> bool {
+  match expiry {
+    Some(x) if x.len() == 8 => {
+      let mut buf = [0u8; 8];
+      buf.copy_from_slice(x);
+      u64::from_be_bytes(buf) <= now
+    }
+    _ => false,
+  }
+}
+
+// Key watches
+//
+// A watch registers its prefix by keeping a lease on `MdsCluster::pack_watcher_key` (the expiry
+// time, encoded like key expiry), renewed by its polls. Each renewal also extends the namespace
+// lease on `MdsCluster::pack_watched_key`, so that writes to a namespace nobody watches do a
+// single read before skipping the per-prefix lookups.
+//
+// Every write appends the changed key and its new value to the change queue
+// (`MdsCluster::pack_watch_key`) of each prefix containing the key that has a live lease; prefixes
+// nobody watches are not written to. Queue entries are keyed by the versionstamp of the write plus
+// the position of the key in the transaction, so they sort in commit order and repeated writes to
+// the same key are all kept. A watch reads the entries after the last one it has seen and reports
+// each of them in order. Versionstamped-key writes are not reported.
+//
+// Commit versions advance by about a million per second, so entries older than a lease are
+// trimmed by version when a watch renews its lease. A lease that has run out is cleared together
+// with its queue by the next write under the prefix, or by the sweep that a watch runs over the
+// registrations next to its own when renewing.
+
+/// Tuple type code of a 96-bit versionstamp: a 10-byte transaction versionstamp followed by a
+/// 2-byte user version.
+const VERSIONSTAMP_96_CODE: u8 = 0x33;
+
+/// Range of the entries in the change queue at `watch_key`. Queues of longer prefixes are nested
+/// under the same key, but their next tuple element is a string and sorts outside of this range.
+fn watch_queue_range(watch_key: &[u8]) -> (Vec<u8>, Vec<u8>) {
+  let mut begin = watch_key.to_vec();
+  begin.push(VERSIONSTAMP_96_CODE);
+  let mut end = watch_key.to_vec();
+  end.push(VERSIONSTAMP_96_CODE + 1);
+  (begin, end)
+}
+
+fn encode_watch_entry(key: &str, value: Option<&[u8]>) -> Vec<u8> {
+  let mut out = Vec::with_capacity(5 + key.len() + value.map(|x| x.len()).unwrap_or(0));
+  out.extend_from_slice(&(key.len() as u32).to_be_bytes());
+  out.extend_from_slice(key.as_bytes());
+  if let Some(value) = value {
+    out.push(1);
+    out.extend_from_slice(value);
+  } else {
+    out.push(0);
+  }
+  out
+}
+
+fn decode_watch_entry(entry: &[u8]) -> Option<KvWatchChange> {
+  let key_len = u32::from_be_bytes(entry.get(..4)?.try_into().ok()?) as usize;
+  let key = std::str::from_utf8(entry.get(4..4 + key_len)?).ok()?;
+  let value = match entry.get(4 + key_len)? {
+    0 => None,
+    _ => Some(entry[5 + key_len..].to_vec()),
+  };
+  Some(KvWatchChange {
+    key: key.to_string(),
+    value,
+  })
+}
+
+/// Queues a change to `key` for the watches of the prefixes containing it. `seq` distinguishes
+/// the keys changed by the same transaction.
+async fn notify_watchers(
+  txn: &Transaction,
+  cluster: &MdsCluster,
+  ns_prefix: &str,
+  key: &str,
+  value: Option<&[u8]>,
+  seq: u16,
+) -> Result<()> {
+  // Snapshot reads, so that writes don't conflict with lease renewals.
+  let now = now_millis();
+  let watched = txn.get(&cluster.pack_watched_key(ns_prefix), true).await?;
+  if watched.is_none() || is_expired(watched.as_deref(), now) {
+    return Ok(());
+  }
+
+  let segs = key.split('/').filter(|x| !x.is_empty()).collect::<Vec<_>>();
+  let prefixes = (0..=segs.len())
+    .map(|i| segs[..i].join("/"))
+    .collect::<Vec<_>>();
+  let watcher_keys = prefixes
+    .iter()
+    .map(|x| cluster.pack_watcher_key(ns_prefix, x))
+    .collect::<Vec<_>>();
+  let leases = futures::future::try_join_all(watcher_keys.iter().map(|x| txn.get(x, true))).await?;
+  let entry = encode_watch_entry(&segs.join("/"), value);
+  for ((prefix, watcher_key), lease) in prefixes.iter().zip(&watcher_keys).zip(&leases) {
+    if lease.is_none() {
+      continue;
+    }
+    let watch_key = cluster.pack_watch_key(ns_prefix, prefix);
+    let (queue_begin, queue_end) = watch_queue_range(&watch_key);
+    if is_expired(lease.as_deref(), now) {
+      txn.clear(watcher_key);
+      txn.clear_range(&queue_begin, &queue_end);
+    } else {
+      let entry_key = queue_begin
+        .iter()
+        .copied()
+        .chain([0u8; 10])
+        .chain(seq.to_be_bytes())
+        .chain((queue_begin.len() as u32).to_le_bytes())
+        .collect::<Vec<u8>>();
+      txn.atomic_op(&entry_key, &entry, MutationType::SetVersionstampedKey);
+    }
+  }
+  Ok(())
+}
+
+/// Reads a key, treating it as absent if it has expired.
+async fn get_live_value(
+  txn: &Transaction,
+  cluster: &MdsCluster,
+  ns_prefix: &str,
+  key: &str,
+  now: u64,
+) -> Result<Option<Vec<u8>>> {
+  let value = txn
+    .get(&cluster.pack_user_key(ns_prefix, key), false)
+    .await?;
+  if value.is_none() {
+    return Ok(None);
+  }
+  let expiry = txn
+    .get(&cluster.pack_expiry_key(ns_prefix, key), false)
+    .await?;
+  if is_expired(expiry.as_deref(), now) {
+    return Ok(None);
+  }
+  Ok(value.map(|x| x.to_vec()))
+}
 
 #[derive(Debug, Copy, Clone, Serialize, Deserialize, Eq, PartialEq, Hash)]
 #[serde(rename_all = "camelCase")]
@@ -43,8 +218,19 @@ pub enum TriStateSet<T> {
 #[serde(rename_all = "camelCase")]
 pub struct PrefixListOptions {
   pub reverse: bool,
+
+  /// Return values alongside keys. When false, values are returned as empty arrays.
+  #[serde(alias = "includeValues")]
   pub want_value: bool,
-  pub limit: u32,
+
+  /// Maximum number of keys to return, at most `MAX_KEYS_PER_OP` with values. If absent, a listing
+  /// with values returns one page of `MAX_KEYS_PER_OP` keys, and a listing of keys only is
+  /// unbounded, which is deprecated.
+  #[serde(default)]
+  pub limit: Option<u32>,
+
+  /// Key (relative to the listed prefix) after which listing resumes. Pass back the `cursor`
+  /// returned by a previous call.
   pub cursor: Option<String>,
 }
 
@@ -80,17 +266,14 @@ impl RchReqBody for KvGetManyRequest {
       .get(&ns.shard)
       .ok_or_else(|| anyhow::anyhow!("shard not found"))?;
     let txn = cluster.db.create_trx()?;
+    let now = now_millis();
     let values = self
       .keys
       .iter()
-      .map(|key| txn.get(&cluster.pack_user_key(&ns.prefix, key), false))
+      .map(|key| get_live_value(&txn, cluster, &ns.prefix, key, now))
       .collect::<FuturesOrdered<_>>()
       .try_collect::<Vec<_>>()
       .await?;
-    let values = values
-      .iter()
-      .map(|x| x.as_ref().map(|x| x.to_vec()))
-      .collect::<Vec<_>>();
     Ok(Box::new(KvGetManyResponse { values }))
   }
 }
@@ -106,6 +289,10 @@ pub struct KvCompareAndSetManyRequestKey<T> {
   key: String,
   check: TriStateCheck<T>,
   set: TriStateSet<T>,
+
+  /// Time-to-live in seconds. Only valid together with `TriStateSet::Value`.
+  #[serde(default)]
+  ttl: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -131,10 +318,12 @@ impl RchReqBody for KvCompareAndSetManyRequest<Vec<u8>> {
 
     loop {
       let mut has_versionstamp = false;
-      for req in &self.keys {
+      let now = now_millis();
+      for (i, req) in self.keys.iter().enumerate() {
         let key = cluster.pack_user_key(&ns.prefix, &req.key);
+        let expiry_key = cluster.pack_expiry_key(&ns.prefix, &req.key);
         if req.check != TriStateCheck::Any {
-          let current_value = txn.get(&key, false).await?;
+          let current_value = get_live_value(&txn, cluster, &ns.prefix, &req.key, now).await?;
           match &req.check {
             TriStateCheck::Any => unreachable!(),
             TriStateCheck::Absent => {
@@ -159,23 +348,44 @@ impl RchReqBody for KvCompareAndSetManyRequest<Vec<u8>> {
           TriStateSet::Preserve => {}
           TriStateSet::Delete => {
             txn.clear(&key);
+            txn.clear(&expiry_key);
+            notify_watchers(&txn, cluster, &ns.prefix, &req.key, None, i as u16).await?;
           }
           TriStateSet::Value(x) => {
             txn.set(&key, x.as_slice());
+            notify_watchers(&txn, cluster, &ns.prefix, &req.key, Some(x), i as u16).await?;
+            match req.ttl {
+              Some(ttl) => {
+                let expiry = now.saturating_add(ttl.saturating_mul(1000));
+                txn.set(&expiry_key, &expiry.to_be_bytes());
+              }
+              None => {
+                txn.clear(&expiry_key);
+              }
+            }
           }
           TriStateSet::WithVersionstampedKey { value } => {
-            let target_key = key
-              .iter()
-              .copied()
-              .chain([0x32u8])
-              .chain([0u8; 10])
-              .chain((key.len() as u32 + 1).to_le_bytes())
-              .collect::<Vec<u8>>();
+            let versionstamped = |key: &[u8]| {
+              key
+                .iter()
+                .copied()
+                .chain([0x32u8])
+                .chain([0u8; 10])
+                .chain((key.len() as u32 + 1).to_le_bytes())
+                .collect::<Vec<u8>>()
+            };
             txn.atomic_op(
-              &target_key,
+              &versionstamped(&key),
               value.as_slice(),
               MutationType::SetVersionstampedKey,
             );
+            // The target key is only known at commit time, so it can't be cleared. An empty expiry
+            // record means "never expires" and overrides any record left at that key.
+            txn.atomic_op(
+              &versionstamped(&expiry_key),
+              &[],
+              MutationType::SetVersionstampedKey,
+            );
             has_versionstamp = true;
           }
         }
@@ -211,6 +421,84 @@ impl RchReqBody for KvCompareAndSetManyRequest<Vec<u8>> {
   }
 }
 
+#[derive(Error, Debug)]
+#[error("existing value is not a valid integer")]
+struct KvValueNotAnInteger;
+
+#[derive(Error, Debug)]
+#[error("integer overflow")]
+struct KvIntegerOverflow;
+
+/// Counters are stored as ASCII decimal strings so that they stay readable through `kv_get_many`.
+#[derive(Serialize, Deserialize)]
+struct KvIncrementRequest {
+  namespace: String,
+  key: String,
+  delta: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KvIncrementResponse {
+  value: i64,
+}
+
+#[async_trait::async_trait]
+#[typetag::serde]
+impl RchReqBody for KvIncrementRequest {
+  async fn handle(self: Box<Self>, md: Arc<Metadata>) -> Result<Box<dyn erased_serde::Serialize>> {
+    let mds = get_mds()?;
+    let ns = md
+      .kv_namespaces
+      .get(&self.namespace)
+      .ok_or_else(|| anyhow::anyhow!("namespace not found"))?;
+    let cluster = mds
+      .fdb
+      .get(&ns.shard)
+      .ok_or_else(|| anyhow::anyhow!("shard not found"))?;
+    let key = cluster.pack_user_key(&ns.prefix, &self.key);
+    let mut txn = cluster.db.create_trx()?;
+
+    loop {
+      // An expired counter restarts from zero and loses its TTL.
+      let current_value =
+        get_live_value(&txn, cluster, &ns.prefix, &self.key, now_millis()).await?;
+      if current_value.is_none() {
+        txn.clear(&cluster.pack_expiry_key(&ns.prefix, &self.key));
+      }
+      let value = match &current_value {
+        Some(x) => std::str::from_utf8(&x[..])
+          .ok()
+          .and_then(|x| x.parse::<i64>().ok())
+          .ok_or(KvValueNotAnInteger)?
+          .checked_add(self.delta)
+          .ok_or(KvIntegerOverflow)?,
+        None => self.delta,
+      };
+      let encoded = value.to_string();
+      txn.set(&key, encoded.as_bytes());
+      notify_watchers(
+        &txn,
+        cluster,
+        &ns.prefix,
+        &self.key,
+        Some(encoded.as_bytes()),
+        0,
+      )
+      .await?;
+
+      match txn.commit().await {
+        Ok(_) => return Ok(Box::new(KvIncrementResponse { value })),
+        Err(e) => {
+          txn = e
+            .on_error()
+            .await
+            .map_err(|e| anyhow::Error::from(e).context("KvIncrementRequest commit failed"))?;
+        }
+      }
+    }
+  }
+}
+
 impl<'s> KvCompareAndSetManyRequest<serde_v8::Value<'s>> {
   fn encode<'t>(
     &self,
@@ -233,6 +521,7 @@ impl<'s> KvCompareAndSetManyRequest<serde_v8::Value<'s>> {
               TriStateCheck::Absent => TriStateCheck::Absent,
               TriStateCheck::Any => TriStateCheck::Any,
             },
+            ttl: x.ttl,
             set: match &x.set {
               TriStateSet::Value(x) => TriStateSet::Value(uint8array_to_vec(x)?),
               TriStateSet::Delete => TriStateSet::Delete,
@@ -261,6 +550,9 @@ struct KvPrefixListRequest {
 #[serde(rename_all = "camelCase")]
 struct KvPrefixListResponse {
   key_value_pairs: Vec<(String, Vec<u8>)>,
+
+  /// Present if the listing stopped because it hit `limit`.
+  cursor: Option<String>,
 }
 
 #[async_trait::async_trait]
@@ -296,30 +588,288 @@ impl RchReqBody for KvPrefixListRequest {
       }
     }
 
-    let mut opt = RangeOption::from(range_start..range_end);
-    opt.reverse = self.opts.reverse;
-    opt.mode = StreamingMode::WantAll;
-    opt.limit = Some(self.opts.limit as usize);
-    let range = txn.get_range(&opt, 0, false).await?;
-    let key_value_pairs = range
-      .iter()
-      .filter_map(|x| {
-        cluster
+    let want_value = self.opts.want_value;
+    let limit = self.opts.limit.map(|x| x as usize);
+    let now = now_millis();
+    let mut key_value_pairs: Vec<(String, Vec<u8>)> = vec![];
+    let mut cursor = None;
+
+    // Expired entries don't count towards `limit`, so keep reading until enough live entries are
+    // found or the range is exhausted.
+    loop {
+      let remaining = limit.map(|x| x - key_value_pairs.len());
+      if remaining == Some(0) {
+        break;
+      }
+      let mut opt = RangeOption::from(range_start.clone()..range_end.clone());
+      opt.reverse = self.opts.reverse;
+      opt.mode = StreamingMode::WantAll;
+      opt.limit = remaining;
+      let range = txn.get_range(&opt, 0, false).await?;
+
+      let expiry_keys = range
+        .iter()
+        .map(|x| {
+          cluster
+            .user_key_to_expiry_key(x.key())
+            .ok_or_else(|| anyhow::anyhow!("key outside of the user key space"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+      let expiries = expiry_keys
+        .iter()
+        .map(|x| txn.get(x, false))
+        .collect::<FuturesOrdered<_>>()
+        .try_collect::<Vec<_>>()
+        .await?;
+      for (x, expiry) in range.iter().zip(expiries.iter()) {
+        if is_expired(expiry.as_deref(), now) {
+          continue;
+        }
+        let key = match cluster
           .unpack_user_key(&ns.prefix, x.key())
           .and_then(|key| {
-            let value = x.value();
-            if let Some(key) = key.strip_prefix(&self.prefix) {
-              Some((key.trim_start_matches('/').to_string(), value.to_vec()))
-            } else {
-              None
-            }
-          })
-      })
-      .collect::<Vec<_>>();
-    Ok(Box::new(KvPrefixListResponse { key_value_pairs }))
+            key
+              .strip_prefix(&self.prefix)
+              .map(|x| x.trim_start_matches('/').to_string())
+          }) {
+          Some(x) => x,
+          None => continue,
+        };
+        let value = if want_value {
+          x.value().to_vec()
+        } else {
+          vec![]
+        };
+        key_value_pairs.push((key, value));
+      }
+
+      let last = match (remaining, range.last()) {
+        (Some(remaining), Some(last)) if range.len() >= remaining => last.key().to_vec(),
+        _ => break,
+      };
+      if Some(key_value_pairs.len()) == limit {
+        cursor = key_value_pairs.last().map(|(k, _)| k.clone());
+        break;
+      }
+      if self.opts.reverse {
+        range_end = last;
+      } else {
+        range_start = last.into_iter().chain(std::iter::once(0u8)).collect();
+      }
+    }
+
+    let total_value_size: usize = key_value_pairs.iter().map(|(_, v)| v.len()).sum();
+    if total_value_size > MAX_PREFIX_LIST_TOTAL_VALUE_SIZE {
+      anyhow::bail!(
+        "total value size {} exceeds limit of {} bytes - use a smaller limit",
+        total_value_size,
+        MAX_PREFIX_LIST_TOTAL_VALUE_SIZE
+      );
+    }
+    Ok(Box::new(KvPrefixListResponse {
+      key_value_pairs,
+      cursor,
+    }))
   }
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct KvPrefixDeleteRequest {
+  namespace: String,
+  prefix: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KvPrefixDeleteResponse {}
+
+#[async_trait::async_trait]
+#[typetag::serde]
+impl RchReqBody for KvPrefixDeleteRequest {
+  async fn handle(self: Box<Self>, md: Arc<Metadata>) -> Result<Box<dyn erased_serde::Serialize>> {
+    let mds = get_mds()?;
+    let ns = md
+      .kv_namespaces
+      .get(&self.namespace)
+      .ok_or_else(|| anyhow::anyhow!("namespace not found"))?;
+    let cluster = mds
+      .fdb
+      .get(&ns.shard)
+      .ok_or_else(|| anyhow::anyhow!("shard not found"))?;
+    let mut txn = cluster.db.create_trx()?;
+    loop {
+      for (start, end) in cluster.pack_prefix_ranges(&ns.prefix, &self.prefix) {
+        txn.clear_range(&start, &end);
+      }
+      notify_watchers(&txn, cluster, &ns.prefix, &self.prefix, None, 0).await?;
+      match txn.commit().await {
+        Ok(_) => return Ok(Box::new(KvPrefixDeleteResponse {})),
+        Err(e) => {
+          txn = e
+            .on_error()
+            .await
+            .map_err(|e| anyhow::Error::from(e).context("KvPrefixDeleteRequest commit failed"))?;
+        }
+      }
+    }
+  }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct KvWatchPollRequest {
+  namespace: String,
+  prefix: String,
+
+  /// Versionstamp of the last queue entry seen by the watch. Absent in the first poll, which only
+  /// returns the current end of the queue.
+  #[serde(default)]
+  cursor: Option<Vec<u8>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KvWatchPollResponse {
+  cursor: Vec<u8>,
+  changes: Vec<KvWatchChange>,
+
+  /// More changes are queued after `cursor`.
+  more: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KvWatchChange {
+  key: String,
+  value: Option<Vec<u8>>,
+}
+
+#[async_trait::async_trait]
+#[typetag::serde]
+impl RchReqBody for KvWatchPollRequest {
+  async fn handle(self: Box<Self>, md: Arc<Metadata>) -> Result<Box<dyn erased_serde::Serialize>> {
+    let mds = get_mds()?;
+    let ns = md
+      .kv_namespaces
+      .get(&self.namespace)
+      .ok_or_else(|| anyhow::anyhow!("namespace not found"))?;
+    let cluster = mds
+      .fdb
+      .get(&ns.shard)
+      .ok_or_else(|| anyhow::anyhow!("shard not found"))?;
+    let watcher_key = cluster.pack_watcher_key(&ns.prefix, &self.prefix);
+    let (queue_begin, queue_end) =
+      watch_queue_range(&cluster.pack_watch_key(&ns.prefix, &self.prefix));
+    let mut txn = cluster.db.create_trx()?;
+    loop {
+      let now = now_millis();
+
+      // The lease is renewed once less than half of it is left, so most polls are read-only.
+      let lease = txn.get(&watcher_key, false).await?;
+      let renew = lease.is_none() || is_expired(lease.as_deref(), now + KV_WATCH_LEASE_MS / 2);
+      if renew {
+        let expiry = (now + KV_WATCH_LEASE_MS).to_be_bytes();
+        txn.set(&watcher_key, &expiry);
+        txn.set(&cluster.pack_watched_key(&ns.prefix), &expiry);
+        sweep_watchers(&txn, cluster, &ns.prefix, &watcher_key, now).await?;
+
+        // Entries older than a lease have been read by every live watch of the prefix.
+        let version = txn.get_read_version().await?;
+        let cutoff = queue_begin
+          .iter()
+          .copied()
+          .chain(
+            (version.max(0) as u64)
+              .saturating_sub(KV_WATCH_LEASE_MS * 1000)
+              .to_be_bytes(),
+          )
+          .collect::<Vec<u8>>();
+        txn.clear_range(&queue_begin, &cutoff);
+      }
+
+      // Snapshot reads, so that renewals don't conflict with writes appending to the queue.
+      let rsp = match &self.cursor {
+        None => {
+          let mut opt = RangeOption::from(queue_begin.clone()..queue_end.clone());
+          opt.reverse = true;
+          opt.limit = Some(1);
+          let last = txn.get_range(&opt, 0, true).await?;
+          KvWatchPollResponse {
+            cursor: last
+              .iter()
+              .next()
+              .map(|x| x.key()[queue_begin.len()..].to_vec())
+              .unwrap_or_default(),
+            changes: vec![],
+            more: false,
+          }
+        }
+        Some(cursor) => {
+          let begin = queue_begin
+            .iter()
+            .chain(cursor)
+            .copied()
+            .chain(std::iter::once(0u8))
+            .collect::<Vec<u8>>();
+          let mut opt = RangeOption::from(begin..queue_end.clone());
+          opt.mode = StreamingMode::WantAll;
+          opt.limit = Some(KV_WATCH_BATCH_SIZE);
+          let entries = txn.get_range(&opt, 0, true).await?;
+          KvWatchPollResponse {
+            cursor: entries
+              .iter()
+              .last()
+              .map(|x| x.key()[queue_begin.len()..].to_vec())
+              .unwrap_or_else(|| cursor.clone()),
+            changes: entries
+              .iter()
+              .filter_map(|x| decode_watch_entry(x.value()))
+              .collect(),
+            more: entries.more(),
+          }
+        }
+      };
+      if !renew {
+        return Ok(Box::new(rsp));
+      }
+      match txn.commit().await {
+        Ok(_) => return Ok(Box::new(rsp)),
+        Err(e) => {
+          txn = e
+            .on_error()
+            .await
+            .map_err(|e| anyhow::Error::from(e).context("KvWatchPollRequest commit failed"))?;
+        }
+      }
+    }
+  }
+}
+
+/// Clears expired watch leases of the namespace that follow `from`, with their queues.
+async fn sweep_watchers(
+  txn: &Transaction,
+  cluster: &MdsCluster,
+  ns_prefix: &str,
+  from: &[u8],
+  now: u64,
+) -> Result<()> {
+  let end = cluster
+    .pack_watcher_key(ns_prefix, "")
+    .into_iter()
+    .chain(std::iter::once(0xffu8))
+    .collect::<Vec<u8>>();
+  let mut opt = RangeOption::from(from.to_vec()..end);
+  opt.mode = StreamingMode::WantAll;
+  opt.limit = Some(KV_WATCH_SWEEP_LIMIT);
+  for kv in txn.get_range(&opt, 0, true).await?.iter() {
+    if kv.key() != from && is_expired(Some(kv.value()), now) {
+      txn.clear(kv.key());
+      if let Some(watch_key) = cluster.watcher_key_to_watch_key(kv.key()) {
+        let (queue_begin, queue_end) = watch_queue_range(&watch_key);
+        txn.clear_range(&queue_begin, &queue_end);
+      }
+    }
+  }
+  Ok(())
+}
+
 fn api_kv_generic<
   'a,
   'b,
@@ -431,6 +981,14 @@ pub fn api_kv_compare_and_set_many<'a, 'b, 'c>(
             anyhow::bail!("TriStateSet: value too large");
           }
         }
+        if let Some(ttl) = k.ttl {
+          if ttl == 0 {
+            anyhow::bail!("ttl must be positive");
+          }
+          if !matches!(k.set, TriStateSet::Value(_)) {
+            anyhow::bail!("ttl is only supported when setting a value");
+          }
+        }
       }
       Ok(req)
     },
@@ -472,12 +1030,86 @@ pub fn api_kv_compare_and_set_many_1<'a, 'b, 'c>(
             anyhow::bail!("TriStateSet: value too large");
           }
         }
+        if let Some(ttl) = k.ttl {
+          if ttl == 0 {
+            anyhow::bail!("ttl must be positive");
+          }
+          if !matches!(k.set, TriStateSet::Value(_)) {
+            anyhow::bail!("ttl is only supported when setting a value");
+          }
+        }
+      }
+      Ok(req)
+    },
+  )
+}
+
+/// Counters are returned as BigInts, since a `Number` is exact only up to 2^53.
+fn counter_to_v8<'s>(scope: &mut v8::HandleScope<'s>, value: i64) -> v8::Local<'s, v8::Value> {
+  v8::BigInt::new_from_i64(scope, value).into()
+}
+
+pub fn api_kv_increment<'a, 'b, 'c>(
+  scope: &mut v8::HandleScope<'a>,
+  args: v8::FunctionCallbackArguments<'b>,
+  _retval: v8::ReturnValue<'c>,
+) -> Result<()> {
+  api_kv_generic::<KvIncrementRequest, _, KvIncrementResponse, _, _>(
+    scope,
+    args,
+    "kv_increment",
+    |scope, rsp| Ok(counter_to_v8(scope, rsp.value)),
+    |_, req| {
+      if req.key.as_bytes().len() > MAX_KEY_SIZE {
+        anyhow::bail!("key too large");
       }
       Ok(req)
     },
   )
 }
 
+fn key_value_pairs_to_v8<'s>(
+  scope: &mut v8::HandleScope<'s>,
+  kvp: Vec<(String, Vec<u8>)>,
+) -> Result<v8::Local<'s, v8::Array>> {
+  let out = v8::Array::new(scope, kvp.len() as _);
+  for (i, (k, v)) in kvp.into_iter().enumerate() {
+    let k = v8::Local::<v8::Value>::from(
+      v8::String::new(scope, &k).ok_or_else(|| anyhow::anyhow!("failed to create v8 string"))?,
+    );
+    let v = v8::Local::<v8::Value>::from(create_uint8array_from_bytes(scope, &v));
+    let pair = v8::Array::new(scope, 2);
+    pair.set_index(scope, 0, k);
+    pair.set_index(scope, 1, v);
+    out.set_index(scope, i as u32, pair.into());
+  }
+  Ok(out)
+}
+
+fn check_prefix_list_request(
+  scope: &mut v8::HandleScope,
+  mut req: KvPrefixListRequest,
+) -> Result<KvPrefixListRequest> {
+  if req.prefix.as_bytes().len() > MAX_KEY_SIZE {
+    anyhow::bail!("prefix too large");
+  }
+  if req.opts.want_value {
+    match req.opts.limit {
+      Some(x) if x as usize > MAX_KEYS_PER_OP => anyhow::bail!("too many keys"),
+      Some(_) => {}
+      None => req.opts.limit = Some(MAX_KEYS_PER_OP as u32),
+    }
+  }
+  if req.opts.limit.is_none() {
+    write_applog(
+      scope,
+      AppLogLevel::Warn,
+      "warning: kv_prefix_list called without a limit, loading the entire prefix".into(),
+    );
+  }
+  Ok(req)
+}
+
 pub fn api_kv_prefix_list<'a, 'b, 'c>(
   scope: &mut v8::HandleScope<'a>,
   args: v8::FunctionCallbackArguments<'b>,
@@ -487,22 +1119,46 @@ pub fn api_kv_prefix_list<'a, 'b, 'c>(
     scope,
     args,
     "kv_prefix_list",
+    |scope, rsp| Ok(key_value_pairs_to_v8(scope, rsp.key_value_pairs)?.into()),
+    check_prefix_list_request,
+  )
+}
+
+/// Like `kv_prefix_list`, but returns `{ keyValuePairs, cursor }` so that the caller can page.
+pub fn api_kv_prefix_list_1<'a, 'b, 'c>(
+  scope: &mut v8::HandleScope<'a>,
+  args: v8::FunctionCallbackArguments<'b>,
+  _retval: v8::ReturnValue<'c>,
+) -> Result<()> {
+  api_kv_generic::<KvPrefixListRequest, _, KvPrefixListResponse, _, _>(
+    scope,
+    args,
+    "kv_prefix_list_1",
     |scope, rsp| {
-      let kvp = rsp.key_value_pairs;
-      let out = v8::Array::new(scope, kvp.len() as _);
-      for (i, (k, v)) in kvp.into_iter().enumerate() {
-        let k = v8::Local::<v8::Value>::from(
-          v8::String::new(scope, &k)
-            .ok_or_else(|| anyhow::anyhow!("failed to create v8 string"))?,
-        );
-        let v = v8::Local::<v8::Value>::from(create_uint8array_from_bytes(scope, &v));
-        let pair = v8::Array::new(scope, 2);
-        pair.set_index(scope, 0, k);
-        pair.set_index(scope, 1, v);
-        out.set_index(scope, i as u32, pair.into());
-      }
+      let pairs = key_value_pairs_to_v8(scope, rsp.key_value_pairs)?;
+      let cursor = match &rsp.cursor {
+        Some(x) => v8::Local::<v8::Value>::from(mk_v8_string(scope, x)?),
+        None => v8::null(scope).into(),
+      };
+      let out = v8::Object::new(scope);
+      out.set_ext(scope, "keyValuePairs", pairs.into());
+      out.set_ext(scope, "cursor", cursor);
       Ok(out.into())
     },
+    check_prefix_list_request,
+  )
+}
+
+pub fn api_kv_prefix_delete<'a, 'b, 'c>(
+  scope: &mut v8::HandleScope<'a>,
+  args: v8::FunctionCallbackArguments<'b>,
+  _retval: v8::ReturnValue<'c>,
+) -> Result<()> {
+  api_kv_generic::<KvPrefixDeleteRequest, _, KvPrefixDeleteResponse, _, _>(
+    scope,
+    args,
+    "kv_prefix_delete",
+    |scope, _| Ok(v8::undefined(scope).into()),
     |_, req| {
       if req.prefix.as_bytes().len() > MAX_KEY_SIZE {
         anyhow::bail!("prefix too large");
@@ -511,3 +1167,122 @@ pub fn api_kv_prefix_list<'a, 'b, 'c>(
     },
   )
 }
+
+pub fn api_kv_watch(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  #[derive(Error, Debug)]
+  #[error("too many kv watches")]
+  struct TooManyWatches;
+
+  let mut req: KvWatchPollRequest = v8_deserialize(scope, args.get(1))?;
+  if req.prefix.as_bytes().len() > MAX_KEY_SIZE {
+    anyhow::bail!("prefix too large");
+  }
+  req.prefix = req
+    .prefix
+    .split('/')
+    .filter(|x| !x.is_empty())
+    .collect::<Vec<_>>()
+    .join("/");
+  let callback = v8::Global::new(scope, args.load_function_at(2)?);
+  let exec = Executor::try_current_result()?;
+  let (handle, mut stop) = {
+    let e = exec.upgrade().unwrap();
+    let mut watches = e.kv_watches.borrow_mut();
+    if watches.len() >= MAX_KV_WATCHES {
+      return Err(TooManyWatches.into());
+    }
+    let (stop_tx, stop_rx) = oneshot::channel();
+    let handle = e.allocate_handle();
+    watches.insert(handle, stop_tx);
+    (handle, stop_rx)
+  };
+  let ctx = exec.upgrade().unwrap().ctx;
+
+  // The task is also killed together with the executor, so the watch never outlives the request.
+  Executor::spawn(&exec.clone(), async move {
+    loop {
+      let rsp: KvWatchPollResponse = match ctx.rch.call(req.clone()).await {
+        Ok(x) => x,
+        Err(e) => {
+          Executor::enter(&exec, |scope| {
+            v8_invoke_callback("kv_watch", scope, Err(e), &callback);
+          });
+          break;
+        }
+      };
+
+      // The first poll only returns the current position, so the state at registration time is
+      // not reported.
+      for change in &rsp.changes {
+        let key = change
+          .key
+          .strip_prefix(&req.prefix)
+          .unwrap_or(&change.key)
+          .trim_start_matches('/');
+        Executor::enter(&exec, |scope| {
+          let res = (|| -> Result<v8::Local<v8::Value>> {
+            let out = v8::Object::new(scope);
+            let key = mk_v8_string(scope, key)?;
+            out.set_ext(scope, "key", key.into());
+            let value = match &change.value {
+              Some(x) => v8::Local::<v8::Value>::from(create_uint8array_from_bytes(scope, x)),
+              None => v8::null(scope).into(),
+            };
+            out.set_ext(scope, "value", value);
+            Ok(out.into())
+          })();
+          v8_invoke_callback("kv_watch", scope, res, &callback);
+        });
+      }
+      req.cursor = Some(rsp.cursor);
+
+      let delay = if rsp.more {
+        Duration::ZERO
+      } else {
+        KV_WATCH_POLL_INTERVAL
+      };
+      tokio::select! {
+        _ = tokio::time::sleep(delay) => {}
+        _ = &mut stop => break,
+      }
+    }
+    if let Some(e) = exec.upgrade() {
+      e.kv_watches.borrow_mut().remove(&handle);
+    }
+  });
+
+  retval.set(v8::Integer::new_from_unsigned(scope, handle).into());
+  Ok(())
+}
+
+pub fn api_kv_unwatch(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  _retval: v8::ReturnValue,
+) -> Result<()> {
+  let handle: u32 = v8_deserialize(scope, args.get(1))?;
+  let e = Executor::try_current_result()?.upgrade().unwrap();
+  e.kv_watches.borrow_mut().remove(&handle);
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::counter_to_v8;
+  use crate::api::testutil::ApiTester;
+
+  #[test]
+  fn counter_keeps_precision_above_2_53() {
+    let mut t = ApiTester::new();
+    let out = t.run(|scope| {
+      let value = counter_to_v8(scope, (1i64 << 53) + 1);
+      assert!(value.is_big_int());
+      value.to_rust_string_lossy(scope)
+    });
+    assert_eq!(out, "9007199254740993");
+  }
+}
@@ -0,0 +1,59 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use v8;
+
+use crate::{
+  api::util::v8_deserialize,
+  exec::Executor,
+  lpch::{LowPriorityMsg, MetricEntry},
+  metrics::{validate_metric, MetricOp},
+};
+
+fn record(
+  scope: &mut v8::HandleScope,
+  args: &v8::FunctionCallbackArguments,
+  op: impl FnOnce(f64) -> MetricOp,
+  default_value: Option<f64>,
+) -> Result<()> {
+  let name: String = v8_deserialize(scope, args.get(1))?;
+  let value: f64 = match default_value {
+    Some(x) if args.get(2).is_null_or_undefined() => x,
+    _ => v8_deserialize(scope, args.get(2))?,
+  };
+  let labels: BTreeMap<String, String> = if args.get(3).is_null_or_undefined() {
+    Default::default()
+  } else {
+    v8_deserialize(scope, args.get(3))?
+  };
+  let op = op(value);
+  validate_metric(&name, &labels, op)?;
+
+  let exec = Executor::try_current_result()?;
+  let exec = exec.upgrade().unwrap();
+  let _ = exec.ctx.lp_tx.send(LowPriorityMsg::Metric(MetricEntry {
+    app: exec.ctx.key.clone(),
+    name,
+    labels,
+    op,
+  }));
+  Ok(())
+}
+
+/// Adds to a counter of the calling app. The increment defaults to 1.
+pub fn api_metric_incr(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  _retval: v8::ReturnValue,
+) -> Result<()> {
+  record(scope, &args, MetricOp::Incr, Some(1.0))
+}
+
+/// Records a value into a histogram of the calling app.
+pub fn api_metric_observe(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  _retval: v8::ReturnValue,
+) -> Result<()> {
+  record(scope, &args, MetricOp::Observe, None)
+}
@@ -1,14 +1,17 @@
 pub mod apns;
+pub mod applog;
 pub mod codec;
 pub mod compress;
-mod crypto;
+pub mod crypto;
 pub mod dataset;
 pub mod external;
-mod fetch;
+pub mod fcm;
+pub mod fetch;
 pub mod graphics;
 pub mod host_object;
 pub mod kv;
-mod mysql;
+pub mod metric;
+pub mod mysql;
 pub mod pubsub;
 pub mod task;
 pub mod tera;
@@ -17,6 +20,9 @@ pub mod text;
 pub mod text_codec;
 pub mod util;
 pub mod validation;
+pub mod wasm;
+pub mod webpush;
+pub mod websocket;
 
 use std::time::Duration;
 
@@ -32,12 +38,12 @@ use crate::{
   exec::Executor,
   headers::HDR_RES_BUSY_DURATION,
   ipc::{BlueboatIpcRes, BlueboatResponse},
-  lpch::{BackgroundEntry, LowPriorityMsg},
+  lpch::{AppLogLevel, BackgroundEntry, LowPriorityMsg},
   objserde::serialize_v8_value,
   v8util::FunctionCallbackArgumentsExt,
 };
 
-use self::util::{v8_deserialize, write_applog};
+use self::util::{v8_deserialize, write_applog, write_applog_structured};
 
 pub type ApiHandler = fn(
   scope: &mut v8::HandleScope,
@@ -52,11 +58,30 @@ pub static API: phf::Map<&'static str, ApiHandler> = phf_map! {
   "schedule_at_most_once" => api_schedule_at_most_once,
   "schedule_at_least_once" => task::api_schedule_at_least_once,
   "schedule_delayed" => task::api_schedule_delayed,
+  "schedule_cron" => task::api_schedule_cron,
+  "task_cron_remove" => task::api_task_cron_remove,
+  "task_cancel" => task::api_task_cancel,
+  "task_dlq_list" => task::api_task_dlq_list,
+  "task_dlq_redrive" => task::api_task_dlq_redrive,
   "encode" => text_codec::api_encode,
   "decode" => text_codec::api_decode,
   "fetch" => fetch::api_fetch,
-  "log" => api_log,
+  "fetch_read_chunk" => fetch::api_fetch_read_chunk,
+  "fetch_close_stream" => fetch::api_fetch_close_stream,
+  "websocket_connect" => websocket::api_websocket_connect,
+  "websocket_send" => websocket::api_websocket_send,
+  "websocket_on_message" => websocket::api_websocket_on_message,
+  "websocket_close" => websocket::api_websocket_close,
+  "log" => api_log_info,
+  "log_debug" => api_log_debug,
+  "log_info" => api_log_info,
+  "log_warn" => api_log_warn,
+  "log_error" => api_log_error,
+  "log_structured" => api_log_structured,
   "crypto_digest" => crypto::api_crypto_digest,
+  "crypto_digest_init" => crypto::stream::api_crypto_digest_init,
+  "crypto_digest_update" => crypto::stream::api_crypto_digest_update,
+  "crypto_digest_final" => crypto::stream::api_crypto_digest_final,
   "crypto_getrandom" => crypto::api_crypto_getrandom,
   "crypto_random_uuid" => crypto::api_crypto_random_uuid,
   "crypto_x25519_derive_public" => crypto::curve25519::api_crypto_x25519_derive_public,
@@ -66,16 +91,44 @@ pub static API: phf::Map<&'static str, ApiHandler> = phf_map! {
   "crypto_ed25519_verify" => crypto::curve25519::api_crypto_ed25519_verify,
   "crypto_ed25519_pubkey_to_x25519" => crypto::curve25519::api_crypto_ed25519_pubkey_to_x25519,
   "crypto_x25519_pubkey_to_ed25519" => crypto::curve25519::api_crypto_x25519_pubkey_to_ed25519,
+  "crypto_p256_derive_public" => crypto::p256::api_crypto_p256_derive_public,
+  "crypto_p256_sign" => crypto::p256::api_crypto_p256_sign,
+  "crypto_p256_verify" => crypto::p256::api_crypto_p256_verify,
+  "crypto_secp256k1_sign" => crypto::secp256k1::api_crypto_secp256k1_sign,
+  "crypto_secp256k1_verify" => crypto::secp256k1::api_crypto_secp256k1_verify,
+  "crypto_secp256k1_recover" => crypto::secp256k1::api_crypto_secp256k1_recover,
+  "crypto_rsa_oaep_encrypt" => crypto::rsa::api_crypto_rsa_oaep_encrypt,
+  "crypto_rsa_oaep_decrypt" => crypto::rsa::api_crypto_rsa_oaep_decrypt,
+  "crypto_rsa_sign" => crypto::rsa::api_crypto_rsa_sign,
+  "crypto_rsa_verify" => crypto::rsa::api_crypto_rsa_verify,
   "crypto_jwt_encode" => crypto::jwt::api_crypto_jwt_encode,
   "crypto_jwt_decode" => crypto::jwt::api_crypto_jwt_decode,
+  "crypto_jwe_encrypt" => crypto::jwe::api_crypto_jwe_encrypt,
+  "crypto_jwe_decrypt" => crypto::jwe::api_crypto_jwe_decrypt,
   "crypto_aead_aes128_gcm_siv_encrypt" => crypto::aead::api_crypto_aead_aes128_gcm_siv_encrypt,
   "crypto_aead_aes128_gcm_siv_decrypt" => crypto::aead::api_crypto_aead_aes128_gcm_siv_decrypt,
+  "crypto_aes_gcm_encrypt" => crypto::aead::api_crypto_aes_gcm_encrypt,
+  "crypto_aes_gcm_decrypt" => crypto::aead::api_crypto_aes_gcm_decrypt,
+  "crypto_chacha20poly1305_encrypt" => crypto::aead::api_crypto_chacha20poly1305_encrypt,
+  "crypto_chacha20poly1305_decrypt" => crypto::aead::api_crypto_chacha20poly1305_decrypt,
   "crypto_hmac_sha256" => crypto::hmac::api_crypto_hmac_sha256,
+  "crypto_hmac_sign" => crypto::hmac::api_crypto_hmac_sign,
+  "crypto_hmac_verify" => crypto::hmac::api_crypto_hmac_verify,
+  "crypto_pbkdf2" => crypto::pbkdf2::api_crypto_pbkdf2,
+  "crypto_hkdf" => crypto::hkdf::api_crypto_hkdf,
+  "crypto_argon2_hash" => crypto::argon2::api_crypto_argon2_hash,
+  "crypto_argon2_verify" => crypto::argon2::api_crypto_argon2_verify,
   "crypto_constant_time_eq" => crypto::api_crypto_constant_time_eq,
   "mysql_exec" => mysql::api_mysql_exec,
+  "mysql_exec_batch" => mysql::api_mysql_exec_batch,
+  "mysql_query_stream" => mysql::api_mysql_query_stream,
+  "mysql_query_fetch" => mysql::api_mysql_query_fetch,
+  "mysql_query_close" => mysql::api_mysql_query_close,
   "mysql_start_transaction" => mysql::api_mysql_start_transaction,
   "mysql_end_transaction" => mysql::api_mysql_end_transaction,
   "apns_send" => apns::api_apns_send,
+  "fcm_send" => fcm::api_fcm_send,
+  "webpush_send" => webpush::api_webpush_send,
   "codec_hexencode" => codec::api_codec_hexencode,
   "codec_hexencode_to_uint8array" => codec::api_codec_hexencode_to_uint8array,
   "codec_hexdecode" => codec::api_codec_hexdecode,
@@ -83,28 +136,85 @@ pub static API: phf::Map<&'static str, ApiHandler> = phf_map! {
   "codec_b64encode_to_uint8array" => codec::api_codec_b64encode_to_uint8array,
   "codec_b64decode" => codec::api_codec_b64decode,
   "codec_multipart_decode" => codec::multipart::api_codec_multipart_decode,
+  "codec_multipart_encode" => codec::multipart::api_codec_multipart_encode,
+  "codec_compress" => codec::compression::api_codec_compress,
+  "codec_decompress" => codec::compression::api_codec_decompress,
+  "codec_cbor_encode" => codec::cbor::api_codec_cbor_encode,
+  "codec_cbor_decode" => codec::cbor::api_codec_cbor_decode,
+  "codec_structured_clone_encode" => codec::structured_clone::api_codec_structured_clone_encode,
+  "codec_structured_clone_decode" => codec::structured_clone::api_codec_structured_clone_decode,
+  "codec_msgpack_encode" => codec::msgpack::api_codec_msgpack_encode,
+  "codec_msgpack_decode" => codec::msgpack::api_codec_msgpack_decode,
+  "codec_url_encode" => codec::percent::api_codec_url_encode,
+  "codec_url_decode" => codec::percent::api_codec_url_decode,
   "graphics_canvas_commit" => graphics::api_graphics_canvas_commit,
   "graphics_canvas_render_svg" => graphics::svg::api_graphics_canvas_render_svg,
   "graphics_canvas_encode" => graphics::codec::api_graphics_canvas_encode,
+  "graphics_canvas_render_pdf" => graphics::pdf::api_graphics_canvas_render_pdf,
   "graphics_canvas_draw" => graphics::draw::api_graphics_canvas_draw,
+  "graphics_animation_encode" => graphics::animation::api_graphics_animation_encode,
+  "graphics_barcode_encode" => graphics::barcode::api_graphics_barcode_encode,
+  "graphics_canvas_decode_image" => graphics::raster::api_graphics_canvas_decode_image,
+  "graphics_image_resize" => graphics::raster::api_graphics_image_resize,
+  "graphics_font_load" => graphics::fonts::api_graphics_font_load,
+  "graphics_image_filter" => graphics::filter::api_graphics_image_filter,
+  "graphics_image_crop" => graphics::raster::api_graphics_image_crop,
+  "graphics_qr_encode" => graphics::qr::api_graphics_qr_encode,
   "graphics_layout_solve" => graphics::layout::api_graphics_layout_solve,
   "graphics_text_measure" => graphics::text::api_graphics_text_measure,
+  "graphics_text_layout" => graphics::text::api_graphics_text_layout,
+  "tera_compile" => tera::api_tera_compile,
+  "tera_compile_set" => tera::api_tera_compile_set,
   "tera_render" => tera::api_tera_render,
   "jtd_load_schema" => validation::jtd::api_jtd_load_schema,
   "jtd_validate" => validation::jtd::api_jtd_validate,
+  "jsonschema_load" => validation::jsonschema::api_jsonschema_load,
+  "jsonschema_validate" => validation::jsonschema::api_jsonschema_validate,
+  "applog_tail" => applog::api_applog_tail,
+  "metric_incr" => metric::api_metric_incr,
+  "metric_observe" => metric::api_metric_observe,
   "dataset_mime_guess_by_ext" => dataset::mime::api_dataset_mime_guess_by_ext,
+  "dataset_mime_guess_by_content" => dataset::mime::api_dataset_mime_guess_by_content,
   "text_markdown_render" => text::markdown::api_text_markdown_render,
   "text_yaml_parse" => text::yaml::api_text_yaml_parse,
   "text_yaml_stringify" => text::yaml::api_text_yaml_stringify,
+  "text_toml_parse" => text::toml::api_text_toml_parse,
+  "text_toml_stringify" => text::toml::api_text_toml_stringify,
+  "text_csv_parse" => text::csv::api_text_csv_parse,
+  "text_csv_stringify" => text::csv::api_text_csv_stringify,
+  "text_xml_parse" => text::xml::api_text_xml_parse,
+  "text_xml_stringify" => text::xml::api_text_xml_stringify,
+  "text_html_sanitize" => text::html::api_text_html_sanitize,
   "text_json_parse" => text::json::api_text_json_parse,
+  "text_json5_parse" => text::json::api_text_json5_parse,
   "text_json_to_uint8array" => text::json::api_text_json_to_uint8array,
   "external_s3_sign" => external::s3::api_external_s3_sign,
+  "external_s3_presign" => external::s3::api_external_s3_presign,
   "external_s3_list_objects_v2" => external::s3::api_external_s3_list_objects_v2,
+  "external_s3_create_multipart_upload" => external::s3::api_external_s3_create_multipart_upload,
+  "external_s3_upload_part" => external::s3::api_external_s3_upload_part,
+  "external_s3_complete_multipart_upload" => external::s3::api_external_s3_complete_multipart_upload,
+  "external_s3_abort_multipart_upload" => external::s3::api_external_s3_abort_multipart_upload,
+  "external_s3_get_object" => external::s3::api_external_s3_get_object,
+  "external_s3_put_object" => external::s3::api_external_s3_put_object,
+  "external_s3_delete_object" => external::s3::api_external_s3_delete_object,
+  "external_azure_sign" => external::azure::api_external_azure_sign,
+  "external_gcs_load_key" => external::gcs::api_external_gcs_load_key,
+  "external_gcs_sign" => external::gcs::api_external_gcs_sign,
+  "external_gcs_get_object" => external::gcs::api_external_gcs_get_object,
+  "external_gcs_put_object" => external::gcs::api_external_gcs_put_object,
+  "external_gcs_delete_object" => external::gcs::api_external_gcs_delete_object,
   "external_aws_sign" => external::aws::api_external_aws_sign,
+  "external_aws_sigv4_sign" => external::aws::api_external_aws_sigv4_sign,
   "kv_get_many" => kv::api_kv_get_many,
   "kv_compare_and_set_many" => kv::api_kv_compare_and_set_many,
   "kv_compare_and_set_many_1" => kv::api_kv_compare_and_set_many_1,
   "kv_prefix_list" => kv::api_kv_prefix_list,
+  "kv_prefix_list_1" => kv::api_kv_prefix_list_1,
+  "kv_prefix_delete" => kv::api_kv_prefix_delete,
+  "kv_watch" => kv::api_kv_watch,
+  "kv_unwatch" => kv::api_kv_unwatch,
+  "kv_increment" => kv::api_kv_increment,
   "host_object_remove" => host_object::api_host_object_remove,
   "compress_zstd_block_compress" => compress::zstd::api_compress_zstd_block_compress,
   "compress_zstd_block_decompress" => compress::zstd::api_compress_zstd_block_decompress,
@@ -117,6 +227,8 @@ pub static API: phf::Map<&'static str, ApiHandler> = phf_map! {
   "text_dom_update" => text::dom::repr::api_dom_update,
   "text_dom_remove" => text::dom::repr::api_dom_remove,
   "pubsub_publish" => pubsub::api_pubsub_publish,
+  "wasm_prepare" => wasm::api_wasm_prepare,
+  "wasm_track_memories" => wasm::api_wasm_track_memories,
 };
 
 #[derive(Error, Debug)]
@@ -163,6 +275,11 @@ fn api_schedule_at_most_once(
   _retval: v8::ReturnValue,
 ) -> Result<()> {
   let wire_bytes = serialize_v8_value(scope, args.get(1))?;
+  let opts: task::ScheduleAtMostOnceOpts = if args.get(2).is_null_or_undefined() {
+    Default::default()
+  } else {
+    v8_deserialize(scope, args.get(2))?
+  };
   let e = Executor::try_current_result()?.upgrade().unwrap();
   e.ctx
     .lp_tx
@@ -170,7 +287,10 @@ fn api_schedule_at_most_once(
       app: e.ctx.key.clone(),
       request_id: e.request_id.clone(),
       wire_bytes,
-      same_version: true,
+      same_version: opts.same_version,
+      retry: None,
+      attempts: 0,
+      dedup: None,
     }))?;
   Ok(())
 }
@@ -207,8 +327,8 @@ fn api_complete(
       Executor::try_current_result()?
         .upgrade()
         .unwrap()
-        .busy_duration
-        .get()
+        .cpu
+        .used()
         .as_secs_f64()
         * 1000.0
     )],
@@ -224,10 +344,10 @@ fn api_complete(
   Ok(())
 }
 
-fn api_log(
+fn api_log_at(
+  level: AppLogLevel,
   scope: &mut v8::HandleScope,
   args: v8::FunctionCallbackArguments,
-  _retval: v8::ReturnValue,
 ) -> Result<()> {
   let message = (1..args.length())
     .map(|i| {
@@ -243,6 +363,75 @@ fn api_log(
       arg.to_rust_string_lossy(scope)
     })
     .join(" ");
-  write_applog(scope, message);
+  write_applog(scope, level, message);
+  Ok(())
+}
+
+fn api_log_debug(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  _retval: v8::ReturnValue,
+) -> Result<()> {
+  api_log_at(AppLogLevel::Debug, scope, args)
+}
+
+fn api_log_info(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  _retval: v8::ReturnValue,
+) -> Result<()> {
+  api_log_at(AppLogLevel::Info, scope, args)
+}
+
+fn api_log_warn(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  _retval: v8::ReturnValue,
+) -> Result<()> {
+  api_log_at(AppLogLevel::Warn, scope, args)
+}
+
+fn api_log_error(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  _retval: v8::ReturnValue,
+) -> Result<()> {
+  api_log_at(AppLogLevel::Error, scope, args)
+}
+
+fn api_log_structured(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  _retval: v8::ReturnValue,
+) -> Result<()> {
+  #[derive(Error, Debug)]
+  #[error("structured log fields must be an object")]
+  struct NotAnObject;
+
+  #[derive(Error, Debug)]
+  #[error("structured log fields too large")]
+  struct FieldsTooLarge;
+
+  const MAX_FIELDS_SIZE: usize = 65536;
+
+  let level: AppLogLevel = v8_deserialize(scope, args.get(1))?;
+  let fields = args.get(2);
+  if !fields.is_object() || fields.is_array() {
+    return Err(NotAnObject.into());
+  }
+  let fields = v8::json::stringify(scope, fields)
+    .ok_or(NotAnObject)?
+    .to_rust_string_lossy(scope);
+  if fields.len() > MAX_FIELDS_SIZE {
+    return Err(FieldsTooLarge.into());
+  }
+
+  // Plain-text consumers see the fields if there's no message.
+  let message = if args.get(3).is_null_or_undefined() {
+    fields.clone()
+  } else {
+    args.get(3).to_rust_string_lossy(scope)
+  };
+  write_applog_structured(scope, level, message, Some(fields));
   Ok(())
 }
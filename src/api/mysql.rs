@@ -1,16 +1,18 @@
-use std::{collections::HashMap, rc::Weak, sync::Arc};
+use std::{collections::HashMap, future::Future, rc::Weak, sync::Arc, time::Duration};
 
 use anyhow::Result;
 use mysql_async::{prelude::Queryable, TxOpts};
 use num_traits::FromPrimitive;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
 use thiserror::Error;
-use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+use tokio::sync::{mpsc, oneshot, Mutex as AsyncMutex, OwnedMutexGuard};
 use v8;
 
 use crate::{
-  api::util::v8_invoke_callback,
-  app_mysql::{AppMysql, ValueSpec},
+  api::util::{v8_deserialize, v8_invoke_callback, v8_serialize},
+  app_mysql::{AppMysql, StmtCache, ValueSpec},
   exec::{Executor, ExecutorMysqlState},
   v8util::FunctionCallbackArgumentsExt,
 };
@@ -19,11 +21,294 @@ use crate::{
 #[error("bad spec")]
 struct BadSpec;
 
-pub fn api_mysql_exec(
+#[derive(Serialize, Deserialize, JsonSchema, Default)]
+pub struct MysqlExecOptions {
+  /// Close the prepared statement on its connection right after the query, instead of keeping it
+  /// in the connection's statement cache. Useful for one-off queries that would otherwise pile up
+  /// as open statements on the server.
+  #[serde(default)]
+  pub bypass_stmt_cache: bool,
+
+  /// Abort the query if it runs for longer than this. The statement is killed on the server and
+  /// its connection is not reused. Inside a transaction, the transaction is rolled back and a
+  /// later commit fails.
+  #[serde(default)]
+  pub timeout_ms: Option<u64>,
+
+  /// Run the query on the read replica, if one is configured. Ignored inside transactions, which
+  /// always run on the primary. The replica may lag behind the primary.
+  #[serde(default)]
+  pub readonly: bool,
+}
+
+/// How long to wait for a spare connection to kill the connection of a timed-out query.
+const KILL_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Error, Debug)]
+#[error("mysql query timed out after {0} ms")]
+pub struct MysqlQueryTimeout(u64);
+
+#[derive(Error, Debug)]
+#[error("transaction was rolled back because a query timed out")]
+pub struct MysqlTransactionAborted;
+
+/// Maximum number of parameter sets in one `mysql_exec_batch` call.
+pub const MAX_BATCH_ROWS: usize = 10000;
+
+/// Savepoint used to roll back a batch that runs inside an app-managed transaction.
+const BATCH_SAVEPOINT: &str = "blueboat_batch";
+
+#[derive(Serialize, Deserialize, JsonSchema, Default)]
+pub struct MysqlBatchOptions {
+  /// Keep executing after a failed row and commit the rows that succeeded. By default the whole
+  /// batch is rolled back on the first failure. The batch still fails if the server rolls back
+  /// the transaction, like on a deadlock.
+  #[serde(default)]
+  pub continue_on_error: bool,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct MysqlBatchRowResult {
+  pub affected_rows: u64,
+  pub last_insert_id: Option<u64>,
+
+  /// Set when the row failed and `continue_on_error` is enabled.
+  pub error: Option<String>,
+}
+
+#[derive(Error, Debug)]
+#[error("batch row {0} failed: {1}")]
+struct BatchRowError(usize, anyhow::Error);
+
+#[derive(Error, Debug)]
+#[error("batch row {0} failed and the server rolled back the transaction: {1}")]
+struct BatchRolledBack(usize, anyhow::Error);
+
+#[derive(Error, Debug)]
+#[error("batch has {0} rows, more than the maximum of {1}")]
+struct BatchTooLarge(u32, usize);
+
+#[derive(Error, Debug)]
+#[error("statements that commit the transaction implicitly cannot run in a batch")]
+struct ImplicitCommitInBatch;
+
+/// Server error for a deadlock, after which the whole transaction has been rolled back.
+const ER_LOCK_DEADLOCK: u16 = 1213;
+
+/// Whether the server rolled back the whole transaction, not just the failed statement.
+fn rolls_back_transaction(e: &anyhow::Error) -> bool {
+  matches!(
+    e.downcast_ref::<mysql_async::Error>(),
+    Some(mysql_async::Error::Server(x)) if x.code == ER_LOCK_DEADLOCK
+  )
+}
+
+/// Whether `sql` is a statement that commits the current transaction before it runs.
+fn commits_implicitly(sql: &str) -> bool {
+  const KEYWORDS: &[&str] = &[
+    "alter",
+    "analyze",
+    "begin",
+    "cache",
+    "check",
+    "commit",
+    "create",
+    "drop",
+    "flush",
+    "grant",
+    "install",
+    "lock",
+    "optimize",
+    "rename",
+    "repair",
+    "reset",
+    "revoke",
+    "rollback",
+    "start",
+    "truncate",
+    "uninstall",
+    "unlock",
+  ];
+  let sql = sql.trim_start().to_ascii_lowercase();
+  let first = sql
+    .split(|c: char| !c.is_ascii_alphabetic())
+    .next()
+    .unwrap_or_default();
+  if first == "create" && sql.starts_with("create temporary") {
+    return false;
+  }
+  KEYWORDS.contains(&first) || (first == "set" && sql.contains("autocommit"))
+}
+
+/// Maximum number of open streaming cursors in a request.
+const MAX_MYSQL_CURSORS: usize = 16;
+
+/// Maximum number of rows returned by one `mysql_query_fetch` call.
+pub const MAX_FETCH_ROWS: u32 = 10000;
+
+#[derive(Serialize, Deserialize, JsonSchema, Default)]
+pub struct MysqlStreamOptions {
+  /// Run the query on the read replica, if one is configured.
+  #[serde(default)]
+  pub readonly: bool,
+}
+
+/// A streaming query owned by the executor. The result set is read by a background task that
+/// holds its own connection. Dropping the cursor stops the task, and the connection goes back to
+/// the pool, which discards any unread rows.
+pub struct MysqlCursor {
+  tx: mpsc::Sender<CursorFetch>,
+  spec: Vec<ValueSpec>,
+}
+
+struct CursorFetch {
+  max_rows: usize,
+  reply: oneshot::Sender<Result<Vec<Vec<mysql_async::Value>>>>,
+}
+
+#[derive(Error, Debug)]
+#[error("too many open mysql cursors")]
+struct TooManyCursors;
+
+#[derive(Error, Debug)]
+#[error("mysql cursor not found")]
+struct CursorNotFound;
+
+#[derive(Error, Debug)]
+#[error("streaming queries are not supported inside transactions")]
+struct StreamInTransaction;
+
+#[derive(Error, Debug)]
+#[error("fetch size must be between 1 and {0}")]
+struct InvalidFetchSize(u32);
+
+fn read_spec(scope: &mut v8::HandleScope, spec: v8::Local<v8::Value>) -> Result<Vec<ValueSpec>> {
+  Ok(
+    spec
+      .to_rust_string_lossy(scope)
+      .as_bytes()
+      .iter()
+      .copied()
+      .map(ValueSpec::from_u8)
+      .collect::<Option<Vec<ValueSpec>>>()
+      .ok_or(BadSpec)?,
+  )
+}
+
+#[derive(Error, Debug)]
+#[error("missing value for query parameter :{0}")]
+struct MissingQueryParam(String);
+
+#[derive(Error, Debug)]
+#[error("positional `?` placeholders are not supported, use `:name` parameters")]
+struct PositionalQueryParam;
+
+/// A statement with its `:name` parameters rewritten to positional `?` placeholders.
+#[derive(Debug)]
+struct NamedStatement {
+  sql: String,
+
+  /// Parameter name for each placeholder in `sql`. A name used several times appears once per use.
+  names: Vec<String>,
+}
+
+impl NamedStatement {
+  /// Rewrites `:name` parameters outside of string literals, quoted identifiers and comments.
+  fn parse(stmt: &str) -> Result<Self> {
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut sql = String::with_capacity(stmt.len());
+    let mut names = vec![];
+    let mut chars = stmt.chars().peekable();
+    while let Some(c) = chars.next() {
+      match c {
+        '\'' | '"' | '`' => {
+          sql.push(c);
+          while let Some(x) = chars.next() {
+            sql.push(x);
+            if x == '\\' && c != '`' {
+              if let Some(x) = chars.next() {
+                sql.push(x);
+              }
+            } else if x == c {
+              // A doubled quote is an escaped quote, which the next iteration reopens.
+              break;
+            }
+          }
+        }
+        '#' => {
+          sql.push(c);
+          while let Some(x) = chars.next() {
+            sql.push(x);
+            if x == '\n' {
+              break;
+            }
+          }
+        }
+        '-' if chars.peek() == Some(&'-') => {
+          sql.push(c);
+          sql.push(chars.next().unwrap());
+          // `--` only starts a comment when followed by whitespace.
+          if chars.peek().map(|x| x.is_whitespace()).unwrap_or(true) {
+            while let Some(x) = chars.next() {
+              sql.push(x);
+              if x == '\n' {
+                break;
+              }
+            }
+          }
+        }
+        '/' if chars.peek() == Some(&'*') => {
+          sql.push(c);
+          sql.push(chars.next().unwrap());
+          let mut prev = '\0';
+          while let Some(x) = chars.next() {
+            sql.push(x);
+            if prev == '*' && x == '/' {
+              break;
+            }
+            prev = x;
+          }
+        }
+        '?' => return Err(PositionalQueryParam.into()),
+        ':' if chars.peek().map(|x| is_name_char(*x)).unwrap_or(false) => {
+          let mut name = String::new();
+          while let Some(x) = chars.peek().copied().filter(|x| is_name_char(*x)) {
+            name.push(x);
+            chars.next();
+          }
+          sql.push('?');
+          names.push(name);
+        }
+        _ => sql.push(c),
+      }
+    }
+    Ok(Self { sql, names })
+  }
+
+  /// Binds values in placeholder order. Values without a matching parameter are ignored.
+  fn bind(&self, args: &HashMap<String, mysql_async::Value>) -> Result<mysql_async::Params> {
+    if self.names.is_empty() {
+      return Ok(mysql_async::Params::Empty);
+    }
+    let values = self
+      .names
+      .iter()
+      .map(|x| {
+        args
+          .get(x)
+          .cloned()
+          .ok_or_else(|| MissingQueryParam(x.clone()))
+      })
+      .collect::<Result<Vec<_>, _>>()?;
+    Ok(mysql_async::Params::Positional(values))
+  }
+}
+
+/// Reads query parameters given as `{ name: [spec, value] }`.
+fn read_named_params(
   scope: &mut v8::HandleScope,
-  args: v8::FunctionCallbackArguments,
-  _retval: v8::ReturnValue,
-) -> Result<()> {
+  sql_args: v8::Local<v8::Object>,
+) -> Result<HashMap<String, mysql_async::Value>> {
   #[derive(Error, Debug)]
   #[error("unknown error in mysql_exec")]
   struct Unknown;
@@ -32,20 +317,7 @@ pub fn api_mysql_exec(
   #[error("bad query parameter")]
   struct BadQueryParam;
 
-  let key = v8::Local::<v8::String>::try_from(args.get(1))?.to_rust_string_lossy(scope);
-  let stmt = v8::Local::<v8::String>::try_from(args.get(2))?.to_rust_string_lossy(scope);
-  let sql_args = v8::Local::<v8::Object>::try_from(args.get(3))?;
-  let spec = args.get(4);
-  let spec = spec
-    .to_rust_string_lossy(scope)
-    .as_bytes()
-    .iter()
-    .copied()
-    .map(ValueSpec::from_u8)
-    .collect::<Option<Vec<ValueSpec>>>()
-    .ok_or(BadSpec)?;
-  let callback = v8::Global::new(scope, args.load_function_at(5)?);
-  let mut arg_map: HashMap<Vec<u8>, mysql_async::Value> = HashMap::new();
+  let mut arg_map: HashMap<String, mysql_async::Value> = HashMap::new();
   let prop_names = sql_args.get_own_property_names(scope).ok_or(Unknown)?;
   let prop_count = prop_names.length();
   for i in 0..prop_count {
@@ -64,12 +336,32 @@ pub fn api_mysql_exec(
     let v = v.get_index(scope, 1).ok_or(BadQueryParam)?;
     let k = k.to_rust_string_lossy(scope);
     let v = AppMysql::cast_value_from_js(scope, v, spec)?;
-    arg_map.insert(Vec::from(k), v);
+    arg_map.insert(k, v);
   }
+  Ok(arg_map)
+}
+
+pub fn api_mysql_exec(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  _retval: v8::ReturnValue,
+) -> Result<()> {
+  let key = v8::Local::<v8::String>::try_from(args.get(1))?.to_rust_string_lossy(scope);
+  let stmt = v8::Local::<v8::String>::try_from(args.get(2))?.to_rust_string_lossy(scope);
+  let sql_args = v8::Local::<v8::Object>::try_from(args.get(3))?;
+  let spec = read_spec(scope, args.get(4))?;
+  let callback = v8::Global::new(scope, args.load_function_at(5)?);
+  let opts: MysqlExecOptions = if args.get(6).is_null_or_undefined() {
+    Default::default()
+  } else {
+    v8_deserialize(scope, args.get(6))?
+  };
+  let stmt = NamedStatement::parse(&stmt)?;
+  let params = stmt.bind(&read_named_params(scope, sql_args)?)?;
   let exec = Executor::try_current_result()?;
   let exec_2 = exec.clone();
   Executor::spawn(&exec_2, async move {
-    let res = run_mysql(&exec, key, stmt, arg_map).await;
+    let res = run_mysql(&exec, key, stmt.sql, params, &opts).await;
     Executor::enter(&exec, move |scope| {
       let res = decode_mysql(scope, res, spec);
       v8_invoke_callback("mysql_exec", scope, res, &callback);
@@ -78,6 +370,224 @@ pub fn api_mysql_exec(
   Ok(())
 }
 
+/// Executes one statement for each parameter set in `rows`, within a single transaction.
+/// Returns a `MysqlBatchRowResult` for each row.
+pub fn api_mysql_exec_batch(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  _retval: v8::ReturnValue,
+) -> Result<()> {
+  let key = v8::Local::<v8::String>::try_from(args.get(1))?.to_rust_string_lossy(scope);
+  let stmt = v8::Local::<v8::String>::try_from(args.get(2))?.to_rust_string_lossy(scope);
+  let rows = v8::Local::<v8::Array>::try_from(args.get(3))?;
+  let callback = v8::Global::new(scope, args.load_function_at(4)?);
+  let opts: MysqlBatchOptions = if args.get(5).is_null_or_undefined() {
+    Default::default()
+  } else {
+    v8_deserialize(scope, args.get(5))?
+  };
+  if rows.length() as usize > MAX_BATCH_ROWS {
+    return Err(BatchTooLarge(rows.length(), MAX_BATCH_ROWS).into());
+  }
+  let stmt = NamedStatement::parse(&stmt)?;
+  if commits_implicitly(&stmt.sql) {
+    return Err(ImplicitCommitInBatch.into());
+  }
+  let mut params = Vec::with_capacity(rows.length() as usize);
+  for i in 0..rows.length() {
+    let row = rows.get_index(scope, i).ok_or(BadSpec)?;
+    let row = v8::Local::<v8::Object>::try_from(row)?;
+    params.push(stmt.bind(&read_named_params(scope, row)?)?);
+  }
+  let exec = Executor::try_current_result()?;
+  let exec_2 = exec.clone();
+  Executor::spawn(&exec_2, async move {
+    let res = run_mysql_batch(&exec, key, stmt.sql, params, &opts).await;
+    Executor::enter(&exec, move |scope| {
+      let res = res.and_then(|x| v8_serialize(scope, &x));
+      v8_invoke_callback("mysql_exec_batch", scope, res, &callback);
+    });
+  });
+  Ok(())
+}
+
+/// Starts a streaming query and returns a cursor handle. Rows are read with `mysql_query_fetch`.
+pub fn api_mysql_query_stream(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  _retval: v8::ReturnValue,
+) -> Result<()> {
+  let key = v8::Local::<v8::String>::try_from(args.get(1))?.to_rust_string_lossy(scope);
+  let stmt = v8::Local::<v8::String>::try_from(args.get(2))?.to_rust_string_lossy(scope);
+  let sql_args = v8::Local::<v8::Object>::try_from(args.get(3))?;
+  let spec = read_spec(scope, args.get(4))?;
+  let callback = v8::Global::new(scope, args.load_function_at(5)?);
+  let opts: MysqlStreamOptions = if args.get(6).is_null_or_undefined() {
+    Default::default()
+  } else {
+    v8_deserialize(scope, args.get(6))?
+  };
+  let stmt = NamedStatement::parse(&stmt)?;
+  let params = stmt.bind(&read_named_params(scope, sql_args)?)?;
+  let exec = Executor::try_current_result()?;
+  if exec.upgrade().unwrap().mysql_cursors.borrow().len() >= MAX_MYSQL_CURSORS {
+    return Err(TooManyCursors.into());
+  }
+  let exec_2 = exec.clone();
+  Executor::spawn(&exec_2, async move {
+    let res = open_cursor(&exec, key, stmt.sql, params, &opts).await;
+    Executor::enter(&exec, move |scope| {
+      let res = res.and_then(|tx| {
+        let e = exec.upgrade().unwrap();
+        let mut cursors = e.mysql_cursors.borrow_mut();
+        if cursors.len() >= MAX_MYSQL_CURSORS {
+          return Err(TooManyCursors.into());
+        }
+        let handle = e.allocate_handle();
+        cursors.insert(handle, MysqlCursor { tx, spec });
+        v8_serialize(scope, &handle)
+      });
+      v8_invoke_callback("mysql_query_stream", scope, res, &callback);
+    });
+  });
+  Ok(())
+}
+
+/// Reads up to `max_rows` rows from a cursor. Fewer rows mean that the result set is exhausted
+/// and the cursor has been closed.
+pub fn api_mysql_query_fetch(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  _retval: v8::ReturnValue,
+) -> Result<()> {
+  let handle: u32 = v8_deserialize(scope, args.get(1))?;
+  let max_rows: u32 = v8_deserialize(scope, args.get(2))?;
+  if max_rows == 0 || max_rows > MAX_FETCH_ROWS {
+    return Err(InvalidFetchSize(MAX_FETCH_ROWS).into());
+  }
+  let callback = v8::Global::new(scope, args.load_function_at(3)?);
+  let exec = Executor::try_current_result()?;
+  let (tx, spec) = {
+    let e = exec.upgrade().unwrap();
+    let cursors = e.mysql_cursors.borrow();
+    let cursor = cursors.get(&handle).ok_or(CursorNotFound)?;
+    (cursor.tx.clone(), cursor.spec.clone())
+  };
+  let exec_2 = exec.clone();
+  Executor::spawn(&exec_2, async move {
+    let (reply, rx) = oneshot::channel();
+    let max_rows = max_rows as usize;
+    let res = match tx.send(CursorFetch { max_rows, reply }).await {
+      Ok(()) => rx.await.unwrap_or_else(|_| Err(CursorNotFound.into())),
+      Err(_) => Err(CursorNotFound.into()),
+    };
+    if !matches!(&res, Ok(rows) if rows.len() == max_rows) {
+      if let Some(e) = exec.upgrade() {
+        e.mysql_cursors.borrow_mut().remove(&handle);
+      }
+    }
+    Executor::enter(&exec, move |scope| {
+      let res = decode_mysql(scope, res, spec);
+      v8_invoke_callback("mysql_query_fetch", scope, res, &callback);
+    });
+  });
+  Ok(())
+}
+
+/// Closes a cursor before its result set is exhausted.
+pub fn api_mysql_query_close(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  _retval: v8::ReturnValue,
+) -> Result<()> {
+  let handle: u32 = v8_deserialize(scope, args.get(1))?;
+  let e = Executor::try_current_result()?.upgrade().unwrap();
+  e.mysql_cursors.borrow_mut().remove(&handle);
+  Ok(())
+}
+
+async fn open_cursor(
+  e: &Weak<Executor>,
+  key: String,
+  stmt: String,
+  params: mysql_async::Params,
+  opts: &MysqlStreamOptions,
+) -> Result<mpsc::Sender<CursorFetch>> {
+  let pool = {
+    let state = get_mysql_state(e, &key).await?;
+    if state.txn.is_some() {
+      return Err(StreamInTransaction.into());
+    }
+    if opts.readonly {
+      state.replica_pool
+    } else {
+      state.pool
+    }
+  };
+  let conn = pool.get_conn().await?;
+  let (ready_tx, ready_rx) = oneshot::channel();
+  let (tx, rx) = mpsc::channel(1);
+
+  // Not spawned on the executor, so that an open cursor doesn't keep the request alive. The task
+  // exits when the executor drops the cursor.
+  tokio::task::spawn_local(run_cursor(conn, stmt, params, ready_tx, rx));
+  ready_rx.await??;
+  Ok(tx)
+}
+
+/// Executes the query and serves fetch requests until the result set is exhausted or `rx` is
+/// closed. `ready` receives the outcome of executing the query.
+async fn run_cursor(
+  mut conn: mysql_async::Conn,
+  stmt: String,
+  params: mysql_async::Params,
+  ready: oneshot::Sender<Result<()>>,
+  mut rx: mpsc::Receiver<CursorFetch>,
+) {
+  let stmt = match conn.prep(&stmt).await {
+    Ok(x) => x,
+    Err(e) => {
+      let _ = ready.send(Err(e.into()));
+      return;
+    }
+  };
+  let mut result = match conn.exec_iter(stmt, params).await {
+    Ok(x) => x,
+    Err(e) => {
+      let _ = ready.send(Err(e.into()));
+      return;
+    }
+  };
+  if ready.send(Ok(())).is_err() {
+    return;
+  }
+  while let Some(req) = rx.recv().await {
+    let mut rows = Vec::with_capacity(req.max_rows);
+    let res = loop {
+      if rows.len() == req.max_rows {
+        break Ok(false);
+      }
+      match result.next().await {
+        Ok(Some(row)) => rows.push(row.unwrap()),
+        Ok(None) => break Ok(true),
+        Err(e) => break Err(e),
+      }
+    };
+    match res {
+      Ok(exhausted) => {
+        let _ = req.reply.send(Ok(rows));
+        if exhausted {
+          return;
+        }
+      }
+      Err(e) => {
+        let _ = req.reply.send(Err(e.into()));
+        return;
+      }
+    }
+  }
+}
+
 pub fn api_mysql_start_transaction(
   scope: &mut v8::HandleScope,
   args: v8::FunctionCallbackArguments,
@@ -94,7 +604,9 @@ pub fn api_mysql_start_transaction(
         let txn = x.pool.start_transaction(TxOpts::new()).await;
         match txn {
           Ok(txn) => {
+            x.stmts.checkout(txn.id());
             x.txn = Some(txn);
+            x.txn_aborted = false;
             Ok(())
           }
           Err(e) => Err(anyhow::Error::from(e)),
@@ -130,6 +642,8 @@ pub fn api_mysql_end_transaction(
           } else {
             txn.rollback().await.map_err(anyhow::Error::from)
           }
+        } else if std::mem::replace(&mut x.txn_aborted, false) && commit {
+          Err(MysqlTransactionAborted.into())
         } else {
           Ok(())
         }
@@ -185,27 +699,224 @@ async fn run_mysql(
   e: &Weak<Executor>,
   key: String,
   stmt: String,
-  args: HashMap<Vec<u8>, mysql_async::Value>,
+  params: mysql_async::Params,
+  opts: &MysqlExecOptions,
 ) -> Result<Vec<Vec<mysql_async::Value>>> {
   let mut state = get_mysql_state(e, &key).await?;
-  let params = if args.is_empty() {
-    mysql_async::Params::Empty
-  } else {
-    mysql_async::Params::Named(args)
-  };
-  let res = if let Some(txn) = &mut state.txn {
-    txn.exec_iter(&stmt, params)
+  let timeout_ms = opts.timeout_ms;
+  let timeout = timeout_ms.map(Duration::from_millis);
+  let (stmts, replica_stmts) = (state.stmts, state.replica_stmts);
+  let (res, conn_id, pool) = if let Some(txn) = &mut state.txn {
+    let conn_id = txn.id();
+    let res = with_timeout(
+      timeout,
+      query_rows(txn, stmts, conn_id, &stmt, params, opts),
+    )
+    .await;
+    (res, conn_id, state.pool)
+  } else if opts.readonly {
+    let conn = state.ensure_replica_conn().await?;
+    let conn_id = conn.id();
+    let res = with_timeout(
+      timeout,
+      query_rows(conn, replica_stmts, conn_id, &stmt, params, opts),
+    )
+    .await;
+    (res, conn_id, state.replica_pool)
   } else {
     let conn = state.ensure_conn().await?;
-    conn.exec_iter(&stmt, params)
+    let conn_id = conn.id();
+    let res = with_timeout(
+      timeout,
+      query_rows(conn, stmts, conn_id, &stmt, params, opts),
+    )
+    .await;
+    (res, conn_id, state.pool)
+  };
+  match res {
+    Some(x) => x,
+    None => {
+      // The connection may be in the middle of a result set, so it can't be reused. Once the
+      // server has closed it, the pool discards it instead of draining it when it is dropped.
+      kill_connection(pool, conn_id).await;
+      if state.txn.take().is_some() {
+        state.txn_aborted = true;
+      } else if opts.readonly {
+        state.replica_conn = None;
+      } else {
+        state.conn = None;
+      }
+      Err(MysqlQueryTimeout(timeout_ms.unwrap_or_default()).into())
+    }
   }
-  .await?;
+}
+
+async fn with_timeout<T>(timeout: Option<Duration>, fut: impl Future<Output = T>) -> Option<T> {
+  match timeout {
+    Some(x) => tokio::time::timeout(x, fut).await.ok(),
+    None => Some(fut.await),
+  }
+}
+
+/// Aborts the statement running on connection `conn_id` and closes the connection, using another
+/// connection from the pool.
+async fn kill_connection(pool: &mysql_async::Pool, conn_id: u32) {
+  let res = tokio::time::timeout(KILL_TIMEOUT, async {
+    let mut conn = pool.get_conn().await?;
+    conn
+      .query_drop(format!("KILL CONNECTION {}", conn_id))
+      .await?;
+    Ok::<_, mysql_async::Error>(())
+  })
+  .await;
+  match res {
+    Ok(Ok(())) => {}
+    Ok(Err(e)) => log::warn!("failed to kill mysql connection {}: {}", conn_id, e),
+    Err(_) => log::warn!("timed out killing mysql connection {}", conn_id),
+  }
+}
+
+/// Prepares `sql` on connection `conn_id`, reusing the statement of an earlier query in the same
+/// checkout if `stmts` has one. Returns whether the statement is kept in `stmts`. If it is not,
+/// the caller closes it after use.
+async fn prepare<Q: Queryable>(
+  q: &mut Q,
+  stmts: &StmtCache,
+  conn_id: u32,
+  sql: &str,
+  bypass_cache: bool,
+) -> Result<(mysql_async::Statement, bool)> {
+  if bypass_cache {
+    return Ok((q.prep(sql).await?, false));
+  }
+  if let Some(x) = stmts.get(conn_id, sql) {
+    return Ok((x, true));
+  }
+  let stmt = q.prep(sql).await?;
+  match stmts.insert(conn_id, sql, stmt.clone()) {
+    None => Ok((stmt, true)),
+    Some(x) if x.id() == stmt.id() => Ok((stmt, false)),
+    Some(evicted) => {
+      q.close(evicted).await?;
+      Ok((stmt, true))
+    }
+  }
+}
+
+/// Prepares `stmt` through `stmts` and collects the result rows. A statement that isn't cached,
+/// like with `bypass_stmt_cache`, is closed on the same connection afterwards.
+async fn query_rows<Q: Queryable>(
+  q: &mut Q,
+  stmts: &StmtCache,
+  conn_id: u32,
+  stmt: &str,
+  params: mysql_async::Params,
+  opts: &MysqlExecOptions,
+) -> Result<Vec<Vec<mysql_async::Value>>> {
+  let (stmt, cached) = prepare(q, stmts, conn_id, stmt, opts.bypass_stmt_cache).await?;
   let mut out: Vec<Vec<mysql_async::Value>> = vec![];
-  res
+  q.exec_iter(&stmt, params)
+    .await?
     .for_each_and_drop(|x| {
       out.push(x.unwrap());
     })
     .await?;
+  if !cached {
+    q.close(stmt).await?;
+  }
+  Ok(out)
+}
+
+async fn run_mysql_batch(
+  e: &Weak<Executor>,
+  key: String,
+  stmt: String,
+  rows: Vec<mysql_async::Params>,
+  opts: &MysqlBatchOptions,
+) -> Result<Vec<MysqlBatchRowResult>> {
+  let mut state = get_mysql_state(e, &key).await?;
+  let stmts = state.stmts;
+  if let Some(txn) = &mut state.txn {
+    // The app controls this transaction, so a failed batch only rolls back to where it started.
+    txn
+      .query_drop(format!("SAVEPOINT {}", BATCH_SAVEPOINT))
+      .await?;
+    let conn_id = txn.id();
+    match exec_batch(txn, stmts, conn_id, &stmt, rows, opts).await {
+      Ok(x) => {
+        txn
+          .query_drop(format!("RELEASE SAVEPOINT {}", BATCH_SAVEPOINT))
+          .await?;
+        Ok(x)
+      }
+      Err(e) if e.is::<BatchRolledBack>() => {
+        // The savepoint is gone with the transaction, so the app's commit has to fail too.
+        if let Some(txn) = state.txn.take() {
+          let _ = txn.rollback().await;
+        }
+        state.txn_aborted = true;
+        Err(e)
+      }
+      Err(e) => {
+        txn
+          .query_drop(format!("ROLLBACK TO SAVEPOINT {}", BATCH_SAVEPOINT))
+          .await?;
+        Err(e)
+      }
+    }
+  } else {
+    let conn = state.ensure_conn().await?;
+    let conn_id = conn.id();
+    let mut txn = conn.start_transaction(TxOpts::new()).await?;
+    match exec_batch(&mut txn, stmts, conn_id, &stmt, rows, opts).await {
+      Ok(x) => {
+        txn.commit().await?;
+        Ok(x)
+      }
+      Err(e) => {
+        txn.rollback().await?;
+        Err(e)
+      }
+    }
+  }
+}
+
+async fn exec_batch<Q: Queryable>(
+  q: &mut Q,
+  stmts: &StmtCache,
+  conn_id: u32,
+  stmt: &str,
+  rows: Vec<mysql_async::Params>,
+  opts: &MysqlBatchOptions,
+) -> Result<Vec<MysqlBatchRowResult>> {
+  let (stmt, cached) = prepare(q, stmts, conn_id, stmt, false).await?;
+  let mut out = Vec::with_capacity(rows.len());
+  for (i, params) in rows.into_iter().enumerate() {
+    let res: Result<_> = async {
+      let mut res = q.exec_iter(&stmt, params).await?;
+      let summary = (res.affected_rows(), res.last_insert_id());
+      res.drop_result().await?;
+      Ok(summary)
+    }
+    .await;
+    match res {
+      Ok((affected_rows, last_insert_id)) => out.push(MysqlBatchRowResult {
+        affected_rows,
+        last_insert_id,
+        error: None,
+      }),
+      Err(e) if rolls_back_transaction(&e) => return Err(BatchRolledBack(i, e).into()),
+      Err(e) if opts.continue_on_error => out.push(MysqlBatchRowResult {
+        affected_rows: 0,
+        last_insert_id: None,
+        error: Some(e.to_string()),
+      }),
+      Err(e) => return Err(BatchRowError(i, e).into()),
+    }
+  }
+  if !cached {
+    q.close(stmt).await?;
+  }
   Ok(out)
 }
 
@@ -238,7 +949,12 @@ async fn get_mysql_state(
   let state = ExecutorMysqlState {
     conn: None,
     txn: None,
+    txn_aborted: false,
     pool: v.pool(),
+    stmts: v.stmt_cache(),
+    replica_conn: None,
+    replica_pool: v.replica_pool(),
+    replica_stmts: v.replica_stmt_cache(),
   };
   let state = Arc::new(AsyncMutex::new(state));
   let g = state.clone().try_lock_owned().unwrap();
@@ -252,8 +968,366 @@ impl ExecutorMysqlState {
   async fn ensure_conn(&mut self) -> Result<&mut mysql_async::Conn> {
     if self.conn.is_none() {
       let conn = self.pool.get_conn().await?;
+      self.stmts.checkout(conn.id());
       self.conn = Some(conn);
     }
     Ok(self.conn.as_mut().unwrap())
   }
+
+  async fn ensure_replica_conn(&mut self) -> Result<&mut mysql_async::Conn> {
+    if self.replica_conn.is_none() {
+      let conn = self.replica_pool.get_conn().await?;
+      self.replica_stmts.checkout(conn.id());
+      self.replica_conn = Some(conn);
+    }
+    Ok(self.replica_conn.as_mut().unwrap())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use mysql_async::{Params, Value};
+  use tokio::sync::{mpsc, oneshot};
+
+  use mysql_async::{prelude::Queryable, ServerError, TxOpts};
+
+  use crate::app_mysql::StmtCache;
+
+  use super::{
+    commits_implicitly, exec_batch, kill_connection, query_rows, rolls_back_transaction,
+    run_cursor, with_timeout, BatchRowError, CursorFetch, MissingQueryParam, MysqlBatchOptions,
+    MysqlExecOptions, NamedStatement, PositionalQueryParam,
+  };
+
+  #[test]
+  fn named_params_are_rewritten() {
+    let stmt =
+      NamedStatement::parse("select * from t where a = :a and (b = :b_2 or b = :a) and c = @x:=1")
+        .unwrap();
+    assert_eq!(
+      stmt.sql,
+      "select * from t where a = ? and (b = ? or b = ?) and c = @x:=1"
+    );
+    assert_eq!(stmt.names, ["a", "b_2", "a"]);
+
+    let args: HashMap<String, Value> = [
+      ("a".to_string(), Value::Int(1)),
+      ("b_2".to_string(), Value::Int(2)),
+      ("unused".to_string(), Value::Int(3)),
+    ]
+    .into_iter()
+    .collect();
+    match stmt.bind(&args).unwrap() {
+      Params::Positional(x) => assert_eq!(x, [Value::Int(1), Value::Int(2), Value::Int(1)]),
+      _ => panic!("expected positional params"),
+    }
+  }
+
+  #[test]
+  fn named_params_skip_literals_and_comments() {
+    let sql = "select ':a', \"it\\\":s :b\", `:c`, 'x''y :d' -- :e ?\n# :f ?\n/* :g ? */ :h, 1--:i";
+    let stmt = NamedStatement::parse(sql).unwrap();
+    assert_eq!(stmt.names, ["h", "i"]);
+    assert_eq!(stmt.sql, sql.replace(":h", "?").replace(":i", "?"));
+    assert!(matches!(
+      NamedStatement::parse("select 1")
+        .unwrap()
+        .bind(&HashMap::new())
+        .unwrap(),
+      Params::Empty
+    ));
+  }
+
+  #[test]
+  fn named_param_errors() {
+    let stmt = NamedStatement::parse("select :a, :b").unwrap();
+    let args: HashMap<String, Value> = [("a".to_string(), Value::NULL)].into_iter().collect();
+    let e = stmt.bind(&args).unwrap_err();
+    assert_eq!(e.downcast_ref::<MissingQueryParam>().unwrap().0, "b");
+
+    assert!(NamedStatement::parse("select ?")
+      .unwrap_err()
+      .is::<PositionalQueryParam>());
+  }
+
+  /// Needs a MySQL server. Set `BLUEBOAT_TEST_MYSQL_URL` to run.
+  #[tokio::test]
+  async fn batch_continues_only_when_asked() {
+    let url = match std::env::var("BLUEBOAT_TEST_MYSQL_URL") {
+      Ok(x) => x,
+      Err(_) => return,
+    };
+    let pool = mysql_async::Pool::new(mysql_async::Opts::from_url(&url).unwrap());
+    let mut conn = pool.get_conn().await.unwrap();
+    conn
+      .query_drop("create temporary table batch_test (id int primary key)")
+      .await
+      .unwrap();
+    let conn_id = conn.id();
+    let stmts = StmtCache::new(8, 1);
+    stmts.checkout(conn_id);
+    let stmt = "insert into batch_test (id) values (?)";
+    let rows = |ids: &[i64]| {
+      ids
+        .iter()
+        .map(|x| Params::Positional(vec![Value::Int(*x)]))
+        .collect::<Vec<_>>()
+    };
+
+    let mut txn = conn.start_transaction(TxOpts::new()).await.unwrap();
+    let out = exec_batch(
+      &mut txn,
+      &stmts,
+      conn_id,
+      stmt,
+      rows(&[1, 1, 2]),
+      &MysqlBatchOptions {
+        continue_on_error: true,
+      },
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+      out.iter().map(|x| x.affected_rows).collect::<Vec<_>>(),
+      [1, 0, 1]
+    );
+    assert!(out[0].error.is_none() && out[1].error.is_some() && out[2].error.is_none());
+    txn.commit().await.unwrap();
+
+    let mut txn = conn.start_transaction(TxOpts::new()).await.unwrap();
+    let e = exec_batch(
+      &mut txn,
+      &stmts,
+      conn_id,
+      stmt,
+      rows(&[3, 1]),
+      &Default::default(),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(e.downcast_ref::<BatchRowError>().unwrap().0, 1);
+    txn.rollback().await.unwrap();
+
+    let ids: Vec<i64> = conn
+      .query("select id from batch_test order by id")
+      .await
+      .unwrap();
+    assert_eq!(ids, [1, 2]);
+    drop(conn);
+    pool.disconnect().await.unwrap();
+  }
+
+  /// Needs a MySQL server. Set `BLUEBOAT_TEST_MYSQL_URL` to run.
+  #[tokio::test]
+  async fn cursor_fetches_in_batches() {
+    let url = match std::env::var("BLUEBOAT_TEST_MYSQL_URL") {
+      Ok(x) => x,
+      Err(_) => return,
+    };
+    let pool = mysql_async::Pool::new(mysql_async::Opts::from_url(&url).unwrap());
+    let conn = pool.get_conn().await.unwrap();
+    let stmt = "with recursive seq (n) as (select 1 union all select n + 1 from seq where n < 250) select n from seq";
+    let (ready_tx, ready_rx) = oneshot::channel();
+    let (tx, rx) = mpsc::channel(1);
+    tokio::spawn(run_cursor(
+      conn,
+      stmt.into(),
+      mysql_async::Params::Empty,
+      ready_tx,
+      rx,
+    ));
+    ready_rx.await.unwrap().unwrap();
+
+    let mut seen = vec![];
+    let mut batch_sizes = vec![];
+    loop {
+      let (reply, reply_rx) = oneshot::channel();
+      if tx
+        .send(CursorFetch {
+          max_rows: 100,
+          reply,
+        })
+        .await
+        .is_err()
+      {
+        break;
+      }
+      let rows = reply_rx.await.unwrap().unwrap();
+      batch_sizes.push(rows.len());
+      for row in &rows {
+        seen.push(mysql_async::from_value::<u64>(row[0].clone()));
+      }
+      if rows.len() < 100 {
+        break;
+      }
+    }
+    assert_eq!(batch_sizes, [100, 100, 50]);
+    assert_eq!(seen, (1..=250).collect::<Vec<u64>>());
+    drop(tx);
+    pool.disconnect().await.unwrap();
+  }
+
+  /// Needs a MySQL server. Set `BLUEBOAT_TEST_MYSQL_URL` to run.
+  #[tokio::test]
+  async fn timed_out_connection_is_not_reused() {
+    let url = match std::env::var("BLUEBOAT_TEST_MYSQL_URL") {
+      Ok(x) => x,
+      Err(_) => return,
+    };
+    let pool = mysql_async::Pool::new(mysql_async::Opts::from_url(&url).unwrap());
+    let stmts = StmtCache::new(8, 2);
+    let mut conn = pool.get_conn().await.unwrap();
+    let conn_id = conn.id();
+    stmts.checkout(conn_id);
+    let res = with_timeout(
+      Some(std::time::Duration::from_millis(100)),
+      query_rows(
+        &mut conn,
+        &stmts,
+        conn_id,
+        "select sleep(5)",
+        Params::Empty,
+        &MysqlExecOptions::default(),
+      ),
+    )
+    .await;
+    assert!(res.is_none());
+    kill_connection(&pool, conn_id).await;
+    drop(conn);
+
+    let mut conn = pool.get_conn().await.unwrap();
+    assert_ne!(conn.id(), conn_id);
+    stmts.checkout(conn.id());
+    let rows = query_rows(
+      &mut conn,
+      &stmts,
+      conn.id(),
+      "select 1",
+      Params::Empty,
+      &MysqlExecOptions::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(rows, [[Value::Int(1)]]);
+    drop(conn);
+    pool.disconnect().await.unwrap();
+  }
+
+  /// Needs a MySQL server. Set `BLUEBOAT_TEST_MYSQL_URL` to run.
+  #[tokio::test]
+  async fn bypassed_statement_is_closed() {
+    let url = match std::env::var("BLUEBOAT_TEST_MYSQL_URL") {
+      Ok(x) => x,
+      Err(_) => return,
+    };
+    let pool = mysql_async::Pool::new(mysql_async::Opts::from_url(&url).unwrap());
+    let mut conn = pool.get_conn().await.unwrap();
+    let closed = |rows: Vec<(String, u64)>| rows[0].1;
+    let before = closed(
+      conn
+        .query("show session status like 'Com_stmt_close'")
+        .await
+        .unwrap(),
+    );
+    let opts = MysqlExecOptions {
+      bypass_stmt_cache: true,
+      ..Default::default()
+    };
+    let stmts = StmtCache::new(8, 1);
+    stmts.checkout(conn.id());
+    let rows = query_rows(
+      &mut conn,
+      &stmts,
+      conn.id(),
+      "select ? + 1",
+      Params::Positional(vec![Value::Int(1)]),
+      &opts,
+    )
+    .await
+    .unwrap();
+    // Still the binary protocol, so the result keeps its type.
+    assert_eq!(rows, [[Value::Int(2)]]);
+    let after = closed(
+      conn
+        .query("show session status like 'Com_stmt_close'")
+        .await
+        .unwrap(),
+    );
+    assert_eq!(after, before + 1);
+    assert!(stmts.get(conn.id(), "select ? + 1").is_none());
+    drop(conn);
+    pool.disconnect().await.unwrap();
+  }
+
+  /// Needs a MySQL server. Set `BLUEBOAT_TEST_MYSQL_URL` to run.
+  #[tokio::test]
+  async fn statements_are_cached_until_the_connection_is_recycled() {
+    let url = match std::env::var("BLUEBOAT_TEST_MYSQL_URL") {
+      Ok(x) => x,
+      Err(_) => return,
+    };
+    let opts = mysql_async::OptsBuilder::from_opts(mysql_async::Opts::from_url(&url).unwrap())
+      .stmt_cache_size(0)
+      .pool_opts(
+        mysql_async::PoolOpts::default()
+          .with_constraints(mysql_async::PoolConstraints::new(1, 1).unwrap()),
+      );
+    let pool = mysql_async::Pool::new(opts);
+    let stmts = StmtCache::new(8, 1);
+    let sql = "select ? + 1";
+    let params = || Params::Positional(vec![Value::Int(1)]);
+    let prepared = |rows: Vec<(String, u64)>| rows[0].1;
+
+    let mut conn = pool.get_conn().await.unwrap();
+    let conn_id = conn.id();
+    stmts.checkout(conn_id);
+    let before = prepared(
+      conn
+        .query("show session status like 'Com_stmt_prepare'")
+        .await
+        .unwrap(),
+    );
+    for _ in 0..3 {
+      let rows = query_rows(
+        &mut conn,
+        &stmts,
+        conn_id,
+        sql,
+        params(),
+        &Default::default(),
+      )
+      .await
+      .unwrap();
+      assert_eq!(rows, [[Value::Int(2)]]);
+    }
+    let after = prepared(
+      conn
+        .query("show session status like 'Com_stmt_prepare'")
+        .await
+        .unwrap(),
+    );
+    assert_eq!(after, before + 1);
+    drop(conn);
+
+    // The pool resets the connection when it is returned, which deallocates the statement.
+    let mut conn = pool.get_conn().await.unwrap();
+    assert_eq!(conn.id(), conn_id);
+    stmts.checkout(conn_id);
+    assert!(stmts.get(conn_id, sql).is_none());
+    let rows = query_rows(
+      &mut conn,
+      &stmts,
+      conn_id,
+      sql,
+      params(),
+      &Default::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(rows, [[Value::Int(2)]]);
+    drop(conn);
+    pool.disconnect().await.unwrap();
+  }
 }
@@ -1,38 +1,150 @@
-use std::{sync::Arc, time::Duration};
+use std::{convert::TryFrom, sync::Arc, time::Duration};
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
 use v8;
 
 use crate::{
-  exec::Executor, metadata::Metadata, objserde::serialize_v8_value, reliable_channel::RchReqBody,
+  bgstore::BackgroundStore,
+  cron::CronSchedule,
+  exec::Executor,
+  lpch::{BackgroundEntry, CronEntry, DedupKey, RetryPolicy},
+  metadata::Metadata,
+  objserde::serialize_v8_value,
+  package::PackageKey,
+  reliable_channel::RchReqBody,
   v8util::FunctionCallbackArgumentsExt,
 };
 
-use super::util::{v8_deserialize, v8_error, v8_serialize};
+use super::util::{v8_deserialize, v8_error, v8_invoke_callback, v8_serialize};
+
+/// Defaults of the retry policy. The base delay doubles on each retry up to the max delay.
+const DEFAULT_MAX_RETRIES: u32 = 5;
+const DEFAULT_BASE_DELAY_MS: u64 = 1000;
+const DEFAULT_MAX_DELAY_MS: u64 = 60000;
+const DEFAULT_JITTER: f64 = 0.2;
+
+/// Upper bounds of the retry policy, so that a failing task leaves the queue within a day.
+const MAX_RETRIES: u32 = 20;
+const MAX_RETRY_DELAY_MS: u64 = 3600 * 1000;
+
+/// Upper bound of how far in the future a delayed task may be scheduled.
+const MAX_DELAY_SECS: i64 = 30 * 86400;
+
+const DEFAULT_DEDUP_WINDOW_SECS: u64 = 3600;
+const MAX_DEDUP_WINDOW_SECS: u64 = 86400;
 
 #[derive(Serialize, Deserialize)]
 struct ScheduleAtLeastOnceRequest {
   wire_bytes: Vec<u8>,
   request_id: String,
   same_version: bool,
+  retry: RetryPolicy,
+  dedup: Option<DedupKey>,
 }
 
 #[derive(Serialize, Deserialize)]
 struct ScheduleAtLeastOnceResponse {}
 
+/// Deferred runs are pinned to the version of the app that scheduled them by
+/// default. A pinned run fails once a new version is deployed, while an unpinned
+/// run always uses the latest version.
+fn default_same_version() -> bool {
+  true
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduleAtMostOnceOpts {
+  #[serde(default = "default_same_version")]
+  pub same_version: bool,
+}
+
+impl Default for ScheduleAtMostOnceOpts {
+  fn default() -> Self {
+    Self {
+      same_version: default_same_version(),
+    }
+  }
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct ScheduleAtLeastOnceOpts {
-  #[serde(default)]
+  #[serde(default = "default_same_version")]
   same_version: bool,
+  #[serde(default)]
+  max_retries: Option<u32>,
+  #[serde(default)]
+  base_delay_ms: Option<u64>,
+  #[serde(default)]
+  max_delay_ms: Option<u64>,
+  #[serde(default)]
+  jitter: Option<f64>,
+  #[serde(default)]
+  dedup_key: Option<String>,
+  #[serde(default)]
+  dedup_window_secs: Option<u64>,
+}
+
+impl ScheduleAtLeastOnceOpts {
+  fn retry_policy(&self) -> RetryPolicy {
+    let max_delay_ms = self
+      .max_delay_ms
+      .unwrap_or(DEFAULT_MAX_DELAY_MS)
+      .min(MAX_RETRY_DELAY_MS);
+    RetryPolicy {
+      max_retries: self
+        .max_retries
+        .unwrap_or(DEFAULT_MAX_RETRIES)
+        .min(MAX_RETRIES),
+      base_delay_ms: self
+        .base_delay_ms
+        .unwrap_or(DEFAULT_BASE_DELAY_MS)
+        .min(max_delay_ms),
+      max_delay_ms,
+      jitter: self.jitter.unwrap_or(DEFAULT_JITTER).clamp(0.0, 1.0),
+    }
+  }
+
+  fn dedup(&self) -> Option<DedupKey> {
+    self.dedup_key.as_ref().map(|key| DedupKey {
+      key: key.clone(),
+      window_secs: self
+        .dedup_window_secs
+        .unwrap_or(DEFAULT_DEDUP_WINDOW_SECS)
+        .min(MAX_DEDUP_WINDOW_SECS),
+    })
+  }
 }
 
 #[async_trait::async_trait]
 #[typetag::serde]
 impl RchReqBody for ScheduleAtLeastOnceRequest {
   async fn handle(self: Box<Self>, md: Arc<Metadata>) -> Result<Box<dyn erased_serde::Serialize>> {
-    anyhow::bail!("at-least-once tasks not implemented");
+    // Acknowledged only once committed to the background store.
+    let store = BackgroundStore::get()?;
+    let dedup_key = self.dedup.as_ref().map(|x| x.key.clone());
+    let accepted = store
+      .enqueue(&BackgroundEntry {
+        app: PackageKey {
+          path: md.path.clone(),
+          version: md.version.clone(),
+        },
+        request_id: self.request_id,
+        wire_bytes: self.wire_bytes,
+        same_version: self.same_version,
+        retry: Some(self.retry),
+        attempts: 0,
+        dedup: self.dedup,
+      })
+      .await?;
+    if !accepted {
+      tracing::info!(package_path = %md.path, dedup_key = ?dedup_key, "duplicate background task suppressed");
+    }
+    Ok(Box::new(ScheduleAtLeastOnceResponse {}))
   }
 }
 
@@ -52,6 +164,10 @@ struct ScheduleDelayedRequest {
   same_version: bool,
 }
 
+#[derive(Error, Debug)]
+#[error("delayed tasks can be scheduled at most {0} seconds ahead")]
+struct DelayTooLong(i64);
+
 #[derive(Serialize, Deserialize)]
 struct ScheduleDelayedResponse {
   id: String,
@@ -61,10 +177,310 @@ struct ScheduleDelayedResponse {
 #[typetag::serde]
 impl RchReqBody for ScheduleDelayedRequest {
   async fn handle(self: Box<Self>, md: Arc<Metadata>) -> Result<Box<dyn erased_serde::Serialize>> {
-    anyhow::bail!("delayed tasks not implemented");
+    let store = BackgroundStore::get()?;
+    let now = chrono::Utc::now().timestamp();
+    if self.ts_secs > now.saturating_add(MAX_DELAY_SECS) {
+      return Err(DelayTooLong(MAX_DELAY_SECS).into());
+    }
+    // A time in the past is due now.
+    let due_ms = self.ts_secs.max(now).saturating_mul(1000);
+    let id = Uuid::new_v4().to_string();
+    let entry = BackgroundEntry {
+      app: PackageKey {
+        path: md.path.clone(),
+        version: md.version.clone(),
+      },
+      request_id: self.request_id,
+      wire_bytes: self.wire_bytes,
+      same_version: self.same_version,
+      retry: None,
+      attempts: 0,
+      dedup: None,
+    };
+    store.enqueue_delayed(&entry, &id, due_ms).await?;
+    Ok(Box::new(ScheduleDelayedResponse { id }))
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ScheduleCronOpts {
+  name: String,
+  schedule: String,
+  #[serde(default)]
+  timezone: Option<String>,
+  #[serde(default)]
+  same_version: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ScheduleCronRequest {
+  wire_bytes: Vec<u8>,
+  request_id: String,
+  opts: ScheduleCronOpts,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ScheduleCronResponse {}
+
+#[async_trait::async_trait]
+#[typetag::serde]
+impl RchReqBody for ScheduleCronRequest {
+  async fn handle(self: Box<Self>, md: Arc<Metadata>) -> Result<Box<dyn erased_serde::Serialize>> {
+    BackgroundStore::get()?
+      .put_cron(&CronEntry {
+        app: PackageKey {
+          path: md.path.clone(),
+          version: md.version.clone(),
+        },
+        request_id: self.request_id,
+        name: self.opts.name,
+        schedule: self.opts.schedule,
+        timezone: self.opts.timezone,
+        wire_bytes: self.wire_bytes,
+        same_version: self.opts.same_version,
+      })
+      .await?;
+    Ok(Box::new(ScheduleCronResponse {}))
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+struct TaskCronRemoveRequest {
+  name: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TaskCronRemoveResponse {
+  removed: bool,
+}
+
+#[async_trait::async_trait]
+#[typetag::serde]
+impl RchReqBody for TaskCronRemoveRequest {
+  async fn handle(self: Box<Self>, md: Arc<Metadata>) -> Result<Box<dyn erased_serde::Serialize>> {
+    let removed = BackgroundStore::get()?
+      .remove_cron(&md.path, &self.name)
+      .await?;
+    Ok(Box::new(TaskCronRemoveResponse { removed }))
+  }
+}
+
+/// Upper bound of `limit` in `task_dlq_list`.
+const MAX_DLQ_LIST_LIMIT: usize = 1000;
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct TaskDlqListOpts {
+  /// Only list entries scheduled by this version of the app.
+  #[serde(default)]
+  version: Option<String>,
+  #[serde(default)]
+  limit: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TaskDlqListRequest {
+  version: Option<String>,
+  limit: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TaskDlqEntry {
+  id: String,
+  version: String,
+  attempts: u32,
+  error: String,
+  dead_lettered_at_ms: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TaskDlqListResponse {
+  entries: Vec<TaskDlqEntry>,
+}
+
+#[async_trait::async_trait]
+#[typetag::serde]
+impl RchReqBody for TaskDlqListRequest {
+  async fn handle(self: Box<Self>, md: Arc<Metadata>) -> Result<Box<dyn erased_serde::Serialize>> {
+    let entries = BackgroundStore::get()?
+      .list_dead_letters(&md.path, self.version.as_deref(), self.limit)
+      .await?
+      .into_iter()
+      .map(|x| TaskDlqEntry {
+        id: x.id,
+        version: x.entry.app.version,
+        attempts: x.entry.attempts,
+        error: x.error,
+        dead_lettered_at_ms: x.dead_lettered_at_ms,
+      })
+      .collect();
+    Ok(Box::new(TaskDlqListResponse { entries }))
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+struct TaskDlqRedriveRequest {
+  ids: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TaskDlqRedriveResponse {
+  /// Ids that were redriven by this call. Ids already redriven are left out.
+  redriven: Vec<String>,
+}
+
+#[async_trait::async_trait]
+#[typetag::serde]
+impl RchReqBody for TaskDlqRedriveRequest {
+  async fn handle(self: Box<Self>, md: Arc<Metadata>) -> Result<Box<dyn erased_serde::Serialize>> {
+    let store = BackgroundStore::get()?;
+    let mut redriven = vec![];
+    for id in self.ids {
+      if store.redrive(&md.path, &id).await? {
+        redriven.push(id);
+      }
+    }
+    Ok(Box::new(TaskDlqRedriveResponse { redriven }))
   }
 }
 
+pub fn api_task_dlq_list(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  _retval: v8::ReturnValue,
+) -> Result<()> {
+  let opts: TaskDlqListOpts = if args.get(1).is_null_or_undefined() {
+    Default::default()
+  } else {
+    v8_deserialize(scope, args.get(1))?
+  };
+  let callback = v8::Global::new(scope, args.load_function_at(2)?);
+  let exec = Executor::try_current_result()?;
+  let ctx = exec.upgrade().unwrap().ctx;
+  let req = TaskDlqListRequest {
+    version: opts.version,
+    limit: opts
+      .limit
+      .unwrap_or(MAX_DLQ_LIST_LIMIT)
+      .min(MAX_DLQ_LIST_LIMIT),
+  };
+  Executor::spawn(&exec.clone(), async move {
+    let out: Result<TaskDlqListResponse> = ctx.rch.call(req).await;
+    Executor::enter(&exec, |scope| {
+      let res = out.and_then(|x| v8_serialize(scope, &x.entries));
+      v8_invoke_callback("task_dlq_list", scope, res, &callback);
+    });
+  });
+  Ok(())
+}
+
+pub fn api_task_dlq_redrive(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  _retval: v8::ReturnValue,
+) -> Result<()> {
+  let ids: Vec<String> = v8_deserialize(scope, args.get(1))?;
+  let callback = v8::Global::new(scope, args.load_function_at(2)?);
+  let exec = Executor::try_current_result()?;
+  let ctx = exec.upgrade().unwrap().ctx;
+  Executor::spawn(&exec.clone(), async move {
+    let out: Result<TaskDlqRedriveResponse> = ctx.rch.call(TaskDlqRedriveRequest { ids }).await;
+    Executor::enter(&exec, |scope| {
+      let res = out.and_then(|x| v8_serialize(scope, &x.redriven));
+      v8_invoke_callback("task_dlq_redrive", scope, res, &callback);
+    });
+  });
+  Ok(())
+}
+
+pub fn api_schedule_cron(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  _retval: v8::ReturnValue,
+) -> Result<()> {
+  let wire_bytes = serialize_v8_value(scope, args.get(1))?;
+  let opts: ScheduleCronOpts = v8_deserialize(scope, args.get(2))?;
+
+  // Validate here so that the app gets the error, rather than the scheduler's log.
+  CronSchedule::parse(&opts.schedule, opts.timezone.as_deref())?;
+
+  let callback = v8::Global::new(scope, args.load_function_at(3)?);
+  let exec = Executor::try_current_result()?;
+  let ctx = exec.upgrade().unwrap().ctx;
+  let req = ScheduleCronRequest {
+    wire_bytes,
+    request_id: exec.upgrade().unwrap().request_id.clone(),
+    opts,
+  };
+  Executor::spawn(&exec.clone(), async move {
+    let out: Result<ScheduleCronResponse> = ctx.rch.call(req).await;
+    Executor::enter(&exec, |scope| {
+      let res = out.map(|_| v8::Local::<v8::Value>::from(v8::undefined(scope)));
+      v8_invoke_callback("schedule_cron", scope, res, &callback);
+    });
+  });
+  Ok(())
+}
+
+pub fn api_task_cron_remove(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  _retval: v8::ReturnValue,
+) -> Result<()> {
+  let name = v8::Local::<v8::String>::try_from(args.get(1))?.to_rust_string_lossy(scope);
+  let callback = v8::Global::new(scope, args.load_function_at(2)?);
+  let exec = Executor::try_current_result()?;
+  let ctx = exec.upgrade().unwrap().ctx;
+  Executor::spawn(&exec.clone(), async move {
+    let out: Result<TaskCronRemoveResponse> = ctx.rch.call(TaskCronRemoveRequest { name }).await;
+    Executor::enter(&exec, |scope| {
+      let res = out.map(|x| v8::Boolean::new(scope, x.removed).into());
+      v8_invoke_callback("task_cron_remove", scope, res, &callback);
+    });
+  });
+  Ok(())
+}
+
+#[derive(Serialize, Deserialize)]
+struct TaskCancelRequest {
+  id: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TaskCancelResponse {
+  cancelled: bool,
+}
+
+#[async_trait::async_trait]
+#[typetag::serde]
+impl RchReqBody for TaskCancelRequest {
+  async fn handle(self: Box<Self>, md: Arc<Metadata>) -> Result<Box<dyn erased_serde::Serialize>> {
+    let cancelled = BackgroundStore::get()?.cancel(&md.path, &self.id).await?;
+    Ok(Box::new(TaskCancelResponse { cancelled }))
+  }
+}
+
+pub fn api_task_cancel(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  _retval: v8::ReturnValue,
+) -> Result<()> {
+  let id = v8::Local::<v8::String>::try_from(args.get(1))?.to_rust_string_lossy(scope);
+  let callback = v8::Global::new(scope, args.load_function_at(2)?);
+  let exec = Executor::try_current_result()?;
+  let ctx = exec.upgrade().unwrap().ctx;
+  Executor::spawn(&exec.clone(), async move {
+    let out: Result<TaskCancelResponse> = ctx.rch.call(TaskCancelRequest { id }).await;
+    Executor::enter(&exec, |scope| {
+      let res = out.map(|x| v8::Boolean::new(scope, x.cancelled).into());
+      v8_invoke_callback("task_cancel", scope, res, &callback);
+    });
+  });
+  Ok(())
+}
+
 pub fn api_schedule_at_least_once(
   scope: &mut v8::HandleScope,
   args: v8::FunctionCallbackArguments,
@@ -79,6 +495,8 @@ pub fn api_schedule_at_least_once(
     request_id: exec.upgrade().unwrap().request_id.clone(),
     wire_bytes,
     same_version: opts.same_version,
+    retry: opts.retry_policy(),
+    dedup: opts.dedup(),
   };
   Executor::spawn(&exec.clone(), async move {
     let out: Result<ScheduleAtLeastOnceResponse> = ctx.rch.call(req).await;
@@ -134,3 +552,25 @@ pub fn api_schedule_delayed(
   });
   Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+  use super::{ScheduleAtLeastOnceOpts, MAX_RETRIES, MAX_RETRY_DELAY_MS};
+
+  #[test]
+  fn clamps_retry_policy() {
+    let opts: ScheduleAtLeastOnceOpts = serde_json::from_str(
+      r#"{"maxRetries": 1000000, "baseDelayMs": 1000000000000000, "maxDelayMs": 1000000000000000, "jitter": 2}"#,
+    )
+    .unwrap();
+    let policy = opts.retry_policy();
+    assert_eq!(policy.max_retries, MAX_RETRIES);
+    assert_eq!(policy.max_delay_ms, MAX_RETRY_DELAY_MS);
+    assert_eq!(policy.base_delay_ms, MAX_RETRY_DELAY_MS);
+    assert_eq!(policy.jitter, 1.0);
+
+    let opts: ScheduleAtLeastOnceOpts =
+      serde_json::from_str(r#"{"baseDelayMs": 5000, "maxDelayMs": 2000}"#).unwrap();
+    assert_eq!(opts.retry_policy().base_delay_ms, 2000);
+  }
+}
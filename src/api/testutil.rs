@@ -31,14 +31,7 @@ impl ApiTester {
     let global_ctx: v8::Global<v8::Context>;
     {
       let scope = &mut v8::HandleScope::new(&mut isolate);
-      let obj_t = v8::ObjectTemplate::new(scope);
-
-      let blueboat_host_invoke = v8::FunctionTemplate::new(scope, native_invoke_entry);
-      obj_t.set(
-        v8::String::new(scope, NI_ENTRY_KEY).unwrap().into(),
-        blueboat_host_invoke.into(),
-      );
-
+      let obj_t = global_template(scope);
       let ctx = v8::Context::new_from_template(scope, obj_t);
       global_ctx = v8::Global::new(scope, ctx);
     }
@@ -60,9 +53,43 @@ impl ApiTester {
     let scope = &mut v8::HandleScope::new(&mut self.isolate);
     let local_ctx = v8::Local::new(scope, &self.global_ctx);
     let scope = &mut v8::ContextScope::new(scope, local_ctx);
-    let text = v8::String::new(scope, text).expect("string construction failed");
-    let script = v8::Script::compile(scope, text, None).expect("compile failed");
-    let out = script.run(scope).expect("run failed");
-    v8_deserialize(scope, out).unwrap()
+    eval(scope, text)
+  }
+
+  /// Hands the isolate over to an app with `metadata`, so that scripts can run in an `Executor`.
+  #[cfg(test)]
+  pub fn into_ctx(
+    mut self,
+    metadata: crate::metadata::Metadata,
+  ) -> &'static crate::ctx::BlueboatCtx {
+    // Lets the app's context be rebuilt, as a worker's is after a termination.
+    let context_template = {
+      let scope = &mut v8::HandleScope::new(&mut self.isolate);
+      let obj_t = global_template(scope);
+      v8::Global::new(scope, obj_t)
+    };
+    self.isolate.set_slot(crate::pm::CachedBootstrapData {
+      context_template,
+      prebuilt_context: None,
+    });
+    crate::ctx::BlueboatCtx::for_test(metadata, self.isolate, self.global_ctx)
   }
 }
+
+fn global_template<'s>(scope: &mut v8::HandleScope<'s, ()>) -> v8::Local<'s, v8::ObjectTemplate> {
+  let obj_t = v8::ObjectTemplate::new(scope);
+  let blueboat_host_invoke = v8::FunctionTemplate::new(scope, native_invoke_entry);
+  obj_t.set(
+    v8::String::new(scope, NI_ENTRY_KEY).unwrap().into(),
+    blueboat_host_invoke.into(),
+  );
+  obj_t
+}
+
+/// Runs `text` in the current context of `scope`.
+pub fn eval<T: for<'a> Deserialize<'a>>(scope: &mut v8::HandleScope, text: &str) -> T {
+  let text = v8::String::new(scope, text).expect("string construction failed");
+  let script = v8::Script::compile(scope, text, None).expect("compile failed");
+  let out = script.run(scope).expect("run failed");
+  v8_deserialize(scope, out).unwrap()
+}
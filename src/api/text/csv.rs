@@ -0,0 +1,317 @@
+use std::convert::TryFrom;
+
+use anyhow::Result;
+use csv::{ReaderBuilder, Terminator, WriterBuilder};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use v8;
+
+use crate::{
+  api::util::{mk_v8_string, v8_deserialize},
+  v8util::LocalValueExt,
+};
+
+#[derive(Serialize, Deserialize, JsonSchema, Default)]
+pub struct TextCsvOptions {
+  /// Field delimiter. Defaults to `,`.
+  #[serde(default)]
+  pub delimiter: Option<String>,
+
+  /// Quote character. Defaults to `"`.
+  #[serde(default)]
+  pub quote: Option<String>,
+
+  /// Parse: the first row holds column names, and each following row becomes an object keyed by
+  /// them. Stringify: write a header row with the keys of the input objects.
+  #[serde(default)]
+  pub header: bool,
+}
+
+#[derive(Error, Debug)]
+#[error("csv {0} must be a single ASCII character")]
+struct InvalidCsvChar(&'static str);
+
+#[derive(Error, Debug)]
+#[error("csv row {0} must be an array or an object")]
+struct InvalidCsvRow(u32);
+
+impl TextCsvOptions {
+  fn byte(x: &Option<String>, default: u8, what: &'static str) -> Result<u8> {
+    match x.as_deref() {
+      None => Ok(default),
+      Some(x) if x.len() == 1 && x.is_ascii() => Ok(x.as_bytes()[0]),
+      Some(_) => Err(InvalidCsvChar(what).into()),
+    }
+  }
+
+  fn delimiter(&self) -> Result<u8> {
+    Self::byte(&self.delimiter, b',', "delimiter")
+  }
+
+  fn quote(&self) -> Result<u8> {
+    Self::byte(&self.quote, b'"', "quote")
+  }
+}
+
+/// Parses CSV into rows of fields. Quoted fields may contain delimiters, doubled quotes and line
+/// breaks. Rows may have different lengths.
+pub fn parse_csv(input: &[u8], opts: &TextCsvOptions) -> Result<Vec<Vec<String>>> {
+  let mut reader = ReaderBuilder::new()
+    .has_headers(false)
+    .flexible(true)
+    .delimiter(opts.delimiter()?)
+    .quote(opts.quote()?)
+    .from_reader(input);
+  let mut rows = vec![];
+  // Records are read one at a time into a reused buffer, so memory use is bounded by the output.
+  let mut record = csv::StringRecord::new();
+  while reader.read_record(&mut record)? {
+    rows.push(record.iter().map(String::from).collect());
+  }
+  Ok(rows)
+}
+
+/// Writes rows as CSV with CRLF line endings, quoting only the fields that need it.
+pub fn stringify_csv(rows: &[Vec<String>], opts: &TextCsvOptions) -> Result<String> {
+  let mut writer = WriterBuilder::new()
+    .flexible(true)
+    .delimiter(opts.delimiter()?)
+    .quote(opts.quote()?)
+    .terminator(Terminator::CRLF)
+    .from_writer(vec![]);
+  for row in rows {
+    writer.write_record(row)?;
+  }
+  Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+fn field_to_string(scope: &mut v8::HandleScope, value: v8::Local<v8::Value>) -> String {
+  if value.is_null_or_undefined() {
+    String::new()
+  } else {
+    value.to_rust_string_lossy(scope)
+  }
+}
+
+/// Parses CSV text. Returns an array of string arrays, or with `header` an array of objects keyed
+/// by the names in the first row. Missing trailing fields are left out of the objects.
+pub fn api_text_csv_parse(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let input = unsafe { args.get(1).read_string_assume_noalias(scope)? };
+  let opts: TextCsvOptions = if args.get(2).is_null_or_undefined() {
+    Default::default()
+  } else {
+    v8_deserialize(scope, args.get(2))?
+  };
+  let mut rows = parse_csv(input.as_bytes(), &opts)?.into_iter();
+
+  let header = if opts.header {
+    rows
+      .next()
+      .unwrap_or_default()
+      .iter()
+      .map(|x| mk_v8_string(scope, x))
+      .collect::<Result<Vec<_>>>()?
+  } else {
+    vec![]
+  };
+  let mut out: Vec<v8::Local<v8::Value>> = Vec::with_capacity(rows.len());
+  for row in rows {
+    let fields = row
+      .iter()
+      .map(|x| mk_v8_string(scope, x).map(|x| x.into()))
+      .collect::<Result<Vec<v8::Local<v8::Value>>>>()?;
+    if opts.header {
+      let obj = v8::Object::new(scope);
+      for (k, v) in header.iter().zip(fields) {
+        obj.set(scope, (*k).into(), v);
+      }
+      out.push(obj.into());
+    } else {
+      out.push(v8::Array::new_with_elements(scope, &fields).into());
+    }
+  }
+  retval.set(v8::Array::new_with_elements(scope, &out).into());
+  Ok(())
+}
+
+/// Stringifies an array of rows. A row is an array of fields or an object. For objects, columns
+/// are the keys in order of first appearance, and missing keys give empty fields. `null` and
+/// `undefined` become empty fields and other values are converted to strings.
+pub fn api_text_csv_stringify(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let input = v8::Local::<v8::Array>::try_from(args.get(1))?;
+  let opts: TextCsvOptions = if args.get(2).is_null_or_undefined() {
+    Default::default()
+  } else {
+    v8_deserialize(scope, args.get(2))?
+  };
+
+  let mut columns: Vec<String> = vec![];
+  let mut rows: Vec<Vec<String>> = Vec::with_capacity(input.length() as usize + 1);
+  for i in 0..input.length() {
+    let row = input.get_index(scope, i).ok_or(InvalidCsvRow(i))?;
+    if let Ok(row) = v8::Local::<v8::Array>::try_from(row) {
+      let fields = (0..row.length())
+        .map(|j| {
+          let v = row.get_index(scope, j).ok_or(InvalidCsvRow(i))?;
+          Ok(field_to_string(scope, v))
+        })
+        .collect::<Result<_>>()?;
+      rows.push(fields);
+    } else if row.is_object() {
+      let row = v8::Local::<v8::Object>::try_from(row)?;
+      let names = row.get_own_property_names(scope).ok_or(InvalidCsvRow(i))?;
+      let mut fields = vec![String::new(); columns.len()];
+      for j in 0..names.length() {
+        let k = names.get_index(scope, j).ok_or(InvalidCsvRow(i))?;
+        let v = row.get(scope, k).ok_or(InvalidCsvRow(i))?;
+        let k = k.to_rust_string_lossy(scope);
+        let index = match columns.iter().position(|x| *x == k) {
+          Some(x) => x,
+          None => {
+            columns.push(k);
+            fields.push(String::new());
+            columns.len() - 1
+          }
+        };
+        fields[index] = field_to_string(scope, v);
+      }
+      rows.push(fields);
+    } else {
+      return Err(InvalidCsvRow(i).into());
+    }
+  }
+
+  if !columns.is_empty() {
+    // Rows written before a column first appeared are padded to the full width.
+    for row in &mut rows {
+      if row.len() < columns.len() {
+        row.resize(columns.len(), String::new());
+      }
+    }
+    if opts.header {
+      rows.insert(0, columns);
+    }
+  }
+  let text = stringify_csv(&rows, &opts)?;
+  retval.set(mk_v8_string(scope, &text)?.into());
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{parse_csv, stringify_csv, InvalidCsvChar, TextCsvOptions};
+  use crate::api::testutil::ApiTester;
+
+  fn rows(x: &[&[&str]]) -> Vec<Vec<String>> {
+    x.iter()
+      .map(|x| x.iter().map(|x| x.to_string()).collect())
+      .collect()
+  }
+
+  #[test]
+  fn rfc4180_parse() {
+    let opts = TextCsvOptions::default();
+    // Quoted fields with delimiters, doubled quotes, CRLF and LF inside, and an empty last field.
+    let input = "a,\"b,c\",\"d \"\"e\"\"\"\r\n\"multi\r\nline\",\"x\ny\",\r\n,,\r\n";
+    assert_eq!(
+      parse_csv(input.as_bytes(), &opts).unwrap(),
+      rows(&[
+        &["a", "b,c", "d \"e\""],
+        &["multi\r\nline", "x\ny", ""],
+        &["", "", ""],
+      ])
+    );
+
+    // No trailing line break, and rows of different lengths.
+    assert_eq!(
+      parse_csv(b"1,2\n3", &opts).unwrap(),
+      rows(&[&["1", "2"], &["3"]])
+    );
+    assert!(parse_csv(b"", &opts).unwrap().is_empty());
+  }
+
+  #[test]
+  fn custom_delimiter_and_quote() {
+    let opts = TextCsvOptions {
+      delimiter: Some(";".into()),
+      quote: Some("'".into()),
+      header: false,
+    };
+    assert_eq!(
+      parse_csv(b"a;'b;c';'it''s'", &opts).unwrap(),
+      rows(&[&["a", "b;c", "it's"]])
+    );
+    assert_eq!(
+      stringify_csv(&rows(&[&["a", "b;c", "it's"]]), &opts).unwrap(),
+      "a;'b;c';'it''s'\r\n"
+    );
+
+    let opts = TextCsvOptions {
+      delimiter: Some("ab".into()),
+      ..Default::default()
+    };
+    assert!(parse_csv(b"", &opts).unwrap_err().is::<InvalidCsvChar>());
+  }
+
+  #[test]
+  fn rfc4180_round_trip() {
+    let opts = TextCsvOptions::default();
+    let data = rows(&[
+      &["plain", "with,comma", "with \"quote\""],
+      &["line\r\nbreak", "", " space "],
+    ]);
+    let text = stringify_csv(&data, &opts).unwrap();
+    assert_eq!(
+      text,
+      "plain,\"with,comma\",\"with \"\"quote\"\"\"\r\n\"line\r\nbreak\",, space \r\n"
+    );
+    assert_eq!(parse_csv(text.as_bytes(), &opts).unwrap(), data);
+  }
+
+  #[test]
+  fn header_mode() {
+    let mut tester = ApiTester::new();
+    let out: String = tester.run_script(
+      r#"
+      const rows = TextUtil.Csv.parse("name,age\r\nalice,30\r\nbob\r\n", { header: true });
+      JSON.stringify([
+        rows,
+        TextUtil.Csv.stringify([{ name: "a,b", age: 1 }, { age: null, extra: true }], { header: true }),
+      ])"#,
+    );
+    assert_eq!(
+      out,
+      r#"[[{"name":"alice","age":"30"},{"name":"bob"}],"name,age,extra\r\n\"a,b\",1,\r\n,,true\r\n"]"#
+    );
+  }
+
+  #[test]
+  fn throwing_getter() {
+    let mut tester = ApiTester::new();
+    let out: Vec<String> = tester.run_script(
+      r#"
+      const attempt = (rows) => {
+        try { TextUtil.Csv.stringify(rows); return "ok"; } catch (e) { return String(e); }
+      };
+      const throwing = { get() { throw 1; } };
+      [
+        attempt([new Proxy({}, throwing)]),
+        attempt([new Proxy([1, 2], throwing)]),
+        attempt([{ get x() { throw 1; } }]),
+      ]"#,
+    );
+    assert_eq!(out.len(), 3);
+    for x in out {
+      assert!(x.contains("csv row 0"), "{}", x);
+    }
+  }
+}
@@ -0,0 +1,239 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use v8;
+
+use crate::{
+  api::util::{mk_v8_string, v8_deserialize},
+  v8util::LocalValueExt,
+};
+
+/// Tags whose content is removed together with the tag. They can't be allowed.
+const CLEAN_CONTENT_TAGS: &[&str] = &["script", "style"];
+
+/// Allowlist for `text_html_sanitize`. Fields that are not set keep the defaults, which allow
+/// common formatting tags, `http`/`https`/`mailto` and a few other URL schemes, and add
+/// `rel="noopener noreferrer"` to links. Scripts, event handler attributes and `javascript:` URLs
+/// are never allowed.
+#[derive(Serialize, Deserialize, JsonSchema, Default, Clone)]
+pub struct TextHtmlSanitizeOptions {
+  /// Allowed tags. Other tags are removed but their content is kept.
+  #[serde(default)]
+  pub allowed_tags: Option<Vec<String>>,
+
+  /// Attributes allowed on every tag.
+  #[serde(default)]
+  pub allowed_attributes: Option<Vec<String>>,
+
+  /// Attributes allowed on specific tags, keyed by tag name.
+  #[serde(default)]
+  pub allowed_tag_attributes: Option<HashMap<String, Vec<String>>>,
+
+  /// Allowed schemes of absolute URLs, without the colon.
+  #[serde(default)]
+  pub allowed_url_schemes: Option<Vec<String>>,
+
+  /// Value of `rel` added to links. An empty string leaves links without `rel`.
+  #[serde(default)]
+  pub link_rel: Option<String>,
+}
+
+#[derive(Error, Debug)]
+#[error("html sanitizer cannot allow {0}")]
+pub struct DisallowedSanitizeOption(String);
+
+impl TextHtmlSanitizeOptions {
+  /// Rejects allowlists that would let scripts through or that the sanitizer can't apply.
+  fn validate(&self) -> Result<()> {
+    for tag in self.allowed_tags.iter().flatten() {
+      if CLEAN_CONTENT_TAGS.contains(&tag.to_ascii_lowercase().as_str()) {
+        return Err(DisallowedSanitizeOption(format!("tag `{}`", tag)).into());
+      }
+    }
+    let attributes = self.allowed_attributes.iter().flatten().chain(
+      self
+        .allowed_tag_attributes
+        .iter()
+        .flat_map(|x| x.values())
+        .flatten(),
+    );
+    for attr in attributes {
+      let lower = attr.to_ascii_lowercase();
+      if lower.starts_with("on") {
+        return Err(DisallowedSanitizeOption(format!("event handler `{}`", attr)).into());
+      }
+      // `rel` is set by the sanitizer itself.
+      if lower == "rel" && self.link_rel.as_deref() != Some("") {
+        return Err(
+          DisallowedSanitizeOption("attribute `rel` together with link_rel".into()).into(),
+        );
+      }
+    }
+    for scheme in self.allowed_url_schemes.iter().flatten() {
+      let lower = scheme.to_ascii_lowercase();
+      if lower == "javascript" || lower == "vbscript" {
+        return Err(DisallowedSanitizeOption(format!("url scheme `{}`", scheme)).into());
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Removes everything outside of the allowlist from an HTML fragment.
+pub fn sanitize_html(html: &str, opts: &TextHtmlSanitizeOptions) -> Result<String> {
+  Ok(sanitizer(opts)?.clean(html).to_string())
+}
+
+/// Builds the sanitizer for an allowlist, for callers that allow extra attributes on top of it.
+pub(crate) fn sanitizer(opts: &TextHtmlSanitizeOptions) -> Result<ammonia::Builder<'_>> {
+  opts.validate()?;
+  let mut builder = ammonia::Builder::default();
+  if let Some(x) = &opts.allowed_tags {
+    builder.tags(x.iter().map(|x| x.as_str()).collect());
+  }
+  if let Some(x) = &opts.allowed_attributes {
+    builder.generic_attributes(x.iter().map(|x| x.as_str()).collect());
+  }
+  if let Some(x) = &opts.allowed_tag_attributes {
+    builder.tag_attributes(
+      x.iter()
+        .map(|(k, v)| {
+          (
+            k.as_str(),
+            v.iter().map(|x| x.as_str()).collect::<HashSet<_>>(),
+          )
+        })
+        .collect(),
+    );
+  }
+  if let Some(x) = &opts.allowed_url_schemes {
+    builder.url_schemes(x.iter().map(|x| x.as_str()).collect());
+  }
+  if let Some(x) = &opts.link_rel {
+    builder.link_rel(if x.is_empty() { None } else { Some(x.as_str()) });
+  }
+  Ok(builder)
+}
+
+pub fn api_text_html_sanitize(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let html = unsafe { args.get(1).read_string_assume_noalias(scope)? };
+  let opts: TextHtmlSanitizeOptions = if args.get(2).is_null_or_undefined() {
+    Default::default()
+  } else {
+    v8_deserialize(scope, args.get(2))?
+  };
+  let output = sanitize_html(&html, &opts)?;
+  retval.set(mk_v8_string(scope, &output)?.into());
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{sanitize_html, DisallowedSanitizeOption, TextHtmlSanitizeOptions};
+
+  #[test]
+  fn xss_payloads() {
+    let opts = TextHtmlSanitizeOptions::default();
+    let payloads = [
+      r#"<script>alert(1)</script>"#,
+      r#"<img src=x onerror=alert(1)>"#,
+      r#"<a href="javascript:alert(1)">x</a>"#,
+      r#"<a href="JaVaScRiPt:alert(1)">x</a>"#,
+      r#"<a href=" &#106;avascript:alert(1)">x</a>"#,
+      r#"<a href="vbscript:msgbox(1)">x</a>"#,
+      r#"<svg onload=alert(1)><circle /></svg>"#,
+      r#"<iframe src="javascript:alert(1)"></iframe>"#,
+      r#"<body onload=alert(1)>"#,
+      r#"<div style="background:url(javascript:alert(1))">x</div>"#,
+      r#"<img src="data:text/html,<script>alert(1)</script>">"#,
+      r#"<form action="javascript:alert(1)"><input type=submit></form>"#,
+      r#"<scr<script>ipt>alert(1)</script>"#,
+      r#"<style>*{background:url(javascript:alert(1))}</style>"#,
+    ];
+    for payload in payloads {
+      let out = sanitize_html(payload, &opts).unwrap().to_ascii_lowercase();
+      for bad in [
+        "<script",
+        "javascript:",
+        "vbscript:",
+        "onerror",
+        "onload",
+        "<iframe",
+        "<svg",
+        "style=",
+        "data:",
+      ] {
+        assert!(!out.contains(bad), "{} -> {}", payload, out);
+      }
+    }
+  }
+
+  #[test]
+  fn keeps_safe_content() {
+    let opts = TextHtmlSanitizeOptions::default();
+    assert_eq!(
+      sanitize_html(
+        r#"<p>hi <b>there</b> <a href="https://example.com">link</a></p>"#,
+        &opts
+      )
+      .unwrap(),
+      r#"<p>hi <b>there</b> <a href="https://example.com" rel="noopener noreferrer">link</a></p>"#
+    );
+  }
+
+  #[test]
+  fn custom_allowlist() {
+    let opts = TextHtmlSanitizeOptions {
+      allowed_tags: Some(vec!["b".into(), "a".into()]),
+      allowed_tag_attributes: Some(
+        [("a".to_string(), vec!["href".to_string()])]
+          .into_iter()
+          .collect(),
+      ),
+      allowed_url_schemes: Some(vec!["https".into()]),
+      link_rel: Some("".into()),
+      ..Default::default()
+    };
+    assert_eq!(
+      sanitize_html(
+        r#"<i>x</i><b title="t">y</b><a href="https://a.example">a</a><a href="http://b.example">b</a>"#,
+        &opts
+      )
+      .unwrap(),
+      r#"x<b title="t">y</b><a href="https://a.example">a</a><a>b</a>"#
+    );
+  }
+
+  #[test]
+  fn rejects_unsafe_allowlists() {
+    for opts in [
+      TextHtmlSanitizeOptions {
+        allowed_tags: Some(vec!["SCRIPT".into()]),
+        ..Default::default()
+      },
+      TextHtmlSanitizeOptions {
+        allowed_attributes: Some(vec!["onclick".into()]),
+        ..Default::default()
+      },
+      TextHtmlSanitizeOptions {
+        allowed_url_schemes: Some(vec!["javascript".into()]),
+        ..Default::default()
+      },
+      TextHtmlSanitizeOptions {
+        allowed_attributes: Some(vec!["rel".into()]),
+        ..Default::default()
+      },
+    ] {
+      assert!(sanitize_html("", &opts)
+        .unwrap_err()
+        .is::<DisallowedSanitizeOption>());
+    }
+  }
+}
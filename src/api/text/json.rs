@@ -1,8 +1,13 @@
 use anyhow::Result;
+use serde::{
+  de::{MapAccess, SeqAccess, Visitor},
+  Deserialize, Deserializer,
+};
+use thiserror::Error;
 use v8;
 
 use crate::{
-  api::util::{v8_deserialize, v8_serialize},
+  api::util::{mk_v8_string, v8_deserialize, v8_serialize},
   v8util::{create_uint8array_from_bytes, LocalValueExt},
 };
 
@@ -34,3 +39,278 @@ pub fn api_text_json_to_uint8array(
   retval.set(buf.into());
   Ok(())
 }
+
+/// Maximum nesting depth of JSON5 input, the same as serde_json's limit for `text_json_parse`.
+const MAX_JSON5_DEPTH: usize = 128;
+
+#[derive(Error, Debug)]
+#[error("json5 parse error at line {line}, column {column}: {msg}")]
+pub struct Json5ParseError {
+  msg: String,
+  line: usize,
+  column: usize,
+}
+
+#[derive(Error, Debug)]
+#[error("json5 parse error: {0}")]
+pub struct Json5ParseErrorWithoutLocation(String);
+
+#[derive(Error, Debug)]
+#[error("json5 input is nested too deeply")]
+pub struct Json5TooDeep;
+
+/// A parsed JSON5 value. Unlike `serde_json::Value`, objects keep their key order and numbers
+/// can be `NaN` or infinite.
+#[derive(Debug, PartialEq)]
+enum Json5Value {
+  Null,
+  Bool(bool),
+  Number(f64),
+  String(String),
+  Array(Vec<Json5Value>),
+  Object(Vec<(String, Json5Value)>),
+}
+
+impl<'de> Deserialize<'de> for Json5Value {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    struct ValueVisitor;
+
+    impl<'de> Visitor<'de> for ValueVisitor {
+      type Value = Json5Value;
+
+      fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a json5 value")
+      }
+
+      fn visit_unit<E>(self) -> Result<Json5Value, E> {
+        Ok(Json5Value::Null)
+      }
+
+      fn visit_bool<E>(self, v: bool) -> Result<Json5Value, E> {
+        Ok(Json5Value::Bool(v))
+      }
+
+      fn visit_i64<E>(self, v: i64) -> Result<Json5Value, E> {
+        Ok(Json5Value::Number(v as f64))
+      }
+
+      fn visit_u64<E>(self, v: u64) -> Result<Json5Value, E> {
+        Ok(Json5Value::Number(v as f64))
+      }
+
+      fn visit_f64<E>(self, v: f64) -> Result<Json5Value, E> {
+        Ok(Json5Value::Number(v))
+      }
+
+      fn visit_str<E>(self, v: &str) -> Result<Json5Value, E> {
+        Ok(Json5Value::String(v.to_string()))
+      }
+
+      fn visit_string<E>(self, v: String) -> Result<Json5Value, E> {
+        Ok(Json5Value::String(v))
+      }
+
+      fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Json5Value, A::Error> {
+        let mut out = vec![];
+        while let Some(x) = seq.next_element()? {
+          out.push(x);
+        }
+        Ok(Json5Value::Array(out))
+      }
+
+      fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Json5Value, A::Error> {
+        let mut out = vec![];
+        while let Some(x) = map.next_entry()? {
+          out.push(x);
+        }
+        Ok(Json5Value::Object(out))
+      }
+    }
+
+    deserializer.deserialize_any(ValueVisitor)
+  }
+}
+
+/// Returns whether brackets nest deeper than `MAX_JSON5_DEPTH`, skipping strings and comments.
+/// The JSON5 parser is recursive, so this runs before it.
+fn json5_too_deep(text: &str) -> bool {
+  let mut depth = 0usize;
+  let mut chars = text.chars().peekable();
+  while let Some(c) = chars.next() {
+    match c {
+      '"' | '\'' => {
+        while let Some(x) = chars.next() {
+          if x == '\\' {
+            chars.next();
+          } else if x == c {
+            break;
+          }
+        }
+      }
+      '/' if chars.peek() == Some(&'/') => {
+        for x in chars.by_ref() {
+          if x == '\n' {
+            break;
+          }
+        }
+      }
+      '/' if chars.peek() == Some(&'*') => {
+        chars.next();
+        let mut prev = '\0';
+        for x in chars.by_ref() {
+          if prev == '*' && x == '/' {
+            break;
+          }
+          prev = x;
+        }
+      }
+      '[' | '{' => {
+        depth += 1;
+        if depth > MAX_JSON5_DEPTH {
+          return true;
+        }
+      }
+      ']' | '}' => depth = depth.saturating_sub(1),
+      _ => {}
+    }
+  }
+  false
+}
+
+fn parse_json5(text: &str) -> Result<Json5Value> {
+  if json5_too_deep(text) {
+    return Err(Json5TooDeep.into());
+  }
+  json5::from_str(text).map_err(|e| match e {
+    json5::Error::Message {
+      msg,
+      location: Some(location),
+    } => Json5ParseError {
+      msg,
+      line: location.line,
+      column: location.column,
+    }
+    .into(),
+    json5::Error::Message {
+      msg,
+      location: None,
+    } => Json5ParseErrorWithoutLocation(msg).into(),
+  })
+}
+
+fn json5_to_v8<'s>(
+  scope: &mut v8::HandleScope<'s>,
+  value: &Json5Value,
+) -> Result<v8::Local<'s, v8::Value>> {
+  Ok(match value {
+    Json5Value::Null => v8::null(scope).into(),
+    Json5Value::Bool(x) => v8::Boolean::new(scope, *x).into(),
+    Json5Value::Number(x) => v8::Number::new(scope, *x).into(),
+    Json5Value::String(x) => mk_v8_string(scope, x)?.into(),
+    Json5Value::Array(x) => {
+      let elements = x
+        .iter()
+        .map(|x| json5_to_v8(scope, x))
+        .collect::<Result<Vec<_>>>()?;
+      v8::Array::new_with_elements(scope, &elements).into()
+    }
+    Json5Value::Object(x) => {
+      let obj = v8::Object::new(scope);
+      for (k, v) in x {
+        let k = mk_v8_string(scope, k)?;
+        let v = json5_to_v8(scope, v)?;
+        // Like `JSON.parse`, a `__proto__` key is an own property and not the prototype.
+        obj.create_data_property(scope, k.into(), v);
+      }
+      obj.into()
+    }
+  })
+}
+
+/// Parses JSON5: JSON plus comments, trailing commas, unquoted keys, single-quoted strings, hex
+/// numbers, `Infinity` and `NaN`. Strict JSON parses to the same value as with `JSON.parse`.
+pub fn api_text_json5_parse(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let text = unsafe { args.get(1).read_string_assume_noalias(scope)? };
+  let value = parse_json5(&text)?;
+  retval.set(json5_to_v8(scope, &value)?);
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{parse_json5, Json5ParseError, Json5TooDeep, Json5Value};
+  use crate::api::testutil::ApiTester;
+
+  #[test]
+  fn test_json5_parse() {
+    let mut tester = ApiTester::new();
+    let out: String = tester.run_script(
+      r#"
+      const v = TextUtil.Json.parse5(`{
+        // comment
+        unquoted: 'single',
+        /* block */ "b": [1, 2, 3,],
+        hex: 0x10,
+        inf: Infinity,
+        nan: NaN,
+        z: 1, a: 2, 10: "x", 2: "y",
+        __proto__: { p: 1 },
+      }`);
+      JSON.stringify([v, Object.keys(v), Number.isNaN(v.nan), v.inf, Object.getPrototypeOf(v) === Object.prototype])"#,
+    );
+    assert_eq!(
+      out,
+      r#"[{"2":"y","10":"x","unquoted":"single","b":[1,2,3],"hex":16,"inf":null,"nan":null,"z":1,"a":2,"__proto__":{"p":1}},["2","10","unquoted","b","hex","inf","nan","z","a","__proto__"],true,null,true]"#
+    );
+  }
+
+  #[test]
+  fn test_json5_matches_json_parse() {
+    let mut tester = ApiTester::new();
+    let out: bool = tester.run_script(
+      r#"
+      const inputs = [
+        `{"a":[1,2.5,-3e10,true,false,null],"b":{"c":"\\u00e9\\n"},"1":0}`,
+        `[]`,
+        `"str"`,
+        `12345678901234567890`,
+        `{"a":1,"a":2}`,
+      ];
+      inputs.every(x => JSON.stringify(TextUtil.Json.parse5(x)) === JSON.stringify(JSON.parse(x)))"#,
+    );
+    assert!(out);
+  }
+
+  #[test]
+  fn test_json_bigint_as_string() {
+    let mut tester = ApiTester::new();
+    let out: String = tester.run_script(
+      r#"
+      const v = { a: 2n ** 64n + 1n, b: [-(2n ** 70n), 1] };
+      new TextDecoder().decode(TextUtil.Json.toUint8Array(v, { bigintAsString: true }))"#,
+    );
+    assert_eq!(
+      out,
+      r#"{"a":"18446744073709551617","b":["-1180591620717411303424",1]}"#
+    );
+  }
+
+  #[test]
+  fn test_json5_errors() {
+    let err = parse_json5("{\n  a: 1,\n  b: @\n}").unwrap_err();
+    let err = err.downcast::<Json5ParseError>().unwrap();
+    assert_eq!((err.line, err.column), (3, 6));
+
+    assert!(parse_json5(&"[".repeat(1000))
+      .unwrap_err()
+      .is::<Json5TooDeep>());
+    assert_eq!(
+      parse_json5(&format!("'{}'", "[".repeat(1000))).unwrap(),
+      Json5Value::String("[".repeat(1000))
+    );
+  }
+}
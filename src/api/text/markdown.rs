@@ -1,12 +1,18 @@
+use std::collections::HashMap;
+
 use anyhow::Result;
-use pulldown_cmark::{html, Options, Parser};
+use pulldown_cmark::{html, CowStr, Event, LinkType, Options, Parser, Tag};
 use schemars::JsonSchema;
 use serde::Deserialize;
 use v8;
 
 use crate::api::util::{mk_v8_string, v8_deserialize};
 
-#[derive(Deserialize, JsonSchema)]
+use super::html::{sanitizer, TextHtmlSanitizeOptions};
+
+const HEADING_TAGS: &[&str] = &["h1", "h2", "h3", "h4", "h5", "h6"];
+
+#[derive(Deserialize, JsonSchema, Default)]
 pub struct TextMarkdownRenderOpts {
   #[serde(default)]
   enable_tables: bool,
@@ -20,15 +26,184 @@ pub struct TextMarkdownRenderOpts {
   enable_smart_punctuation: bool,
   #[serde(default)]
   disable_sanitization: bool,
+
+  /// Turn bare `http://` and `https://` URLs in text into links.
+  #[serde(default)]
+  enable_autolinks: bool,
+
+  /// Give headings an `id` derived from their text, like `## Getting started` ->
+  /// `id="getting-started"`. Repeated ids get a `-1`, `-2`, ... suffix.
+  #[serde(default)]
+  enable_heading_anchors: bool,
+
+  /// Added to the level of every heading, so that `# Title` renders as `<h3>` with an offset of 2.
+  /// Levels stop at 6.
+  #[serde(default)]
+  heading_level_offset: u32,
+
+  /// Allowlist for sanitizing the output. Defaults to the `text_html_sanitize` defaults. Ignored
+  /// with `disable_sanitization`.
+  #[serde(default)]
+  sanitize: Option<TextHtmlSanitizeOptions>,
 }
 
-pub fn api_text_markdown_render(
-  scope: &mut v8::HandleScope,
-  args: v8::FunctionCallbackArguments,
-  mut retval: v8::ReturnValue,
-) -> Result<()> {
-  let markdown_input = args.get(1).to_rust_string_lossy(scope);
-  let jsopts: TextMarkdownRenderOpts = v8_deserialize(scope, args.get(2))?;
+/// Lowercases `text` and joins its words with `-`, dropping punctuation.
+fn slugify(text: &str) -> String {
+  let mut slug = String::with_capacity(text.len());
+  for c in text.chars() {
+    if c.is_alphanumeric() || c == '_' {
+      slug.extend(c.to_lowercase());
+    } else if (c.is_whitespace() || c == '-') && !slug.is_empty() && !slug.ends_with('-') {
+      slug.push('-');
+    }
+  }
+  while slug.ends_with('-') {
+    slug.pop();
+  }
+  slug
+}
+
+/// Splits `text` around bare URLs and pushes text and link events for it.
+fn push_autolinked<'a>(out: &mut Vec<Event<'a>>, text: String) {
+  let mut rest = text.as_str();
+  loop {
+    let start = match ["https://", "http://"]
+      .iter()
+      .filter_map(|x| rest.find(x))
+      .min()
+    {
+      Some(x) => x,
+      None => break,
+    };
+    let mut end = rest[start..]
+      .find(|c: char| c.is_whitespace() || c == '<' || c == '>' || c == '"')
+      .map(|x| start + x)
+      .unwrap_or(rest.len());
+
+    // Punctuation that ends a sentence is not part of the URL, and neither is an unbalanced `)`.
+    loop {
+      let url = &rest[start..end];
+      match url.chars().last() {
+        Some('.' | ',' | ':' | ';' | '!' | '?' | '\'') => end -= 1,
+        Some(')') if url.matches(')').count() > url.matches('(').count() => end -= 1,
+        _ => break,
+      }
+    }
+    let url = &rest[start..end];
+    if url.ends_with("://") {
+      // Just a scheme.
+      out.push(Event::Text(CowStr::from(rest[..end].to_string())));
+      rest = &rest[end..];
+      continue;
+    }
+    if start > 0 {
+      out.push(Event::Text(CowStr::from(rest[..start].to_string())));
+    }
+    let tag = Tag::Link(
+      LinkType::Autolink,
+      CowStr::from(url.to_string()),
+      CowStr::Borrowed(""),
+    );
+    out.push(Event::Start(tag.clone()));
+    out.push(Event::Text(CowStr::from(url.to_string())));
+    out.push(Event::End(tag));
+    rest = &rest[end..];
+  }
+  if !rest.is_empty() {
+    out.push(Event::Text(CowStr::from(rest.to_string())));
+  }
+}
+
+/// Applies the extensions that pulldown-cmark doesn't have to the event stream.
+fn apply_extensions<'a>(
+  events: impl Iterator<Item = Event<'a>>,
+  jsopts: &TextMarkdownRenderOpts,
+) -> Vec<Event<'a>> {
+  let mut out: Vec<Event<'a>> = vec![];
+
+  // Autolinks. Text inside links, images and code blocks is left alone, and adjacent text events
+  // are joined first because the parser may split a URL at characters like `_`.
+  let mut pending_text: Option<String> = None;
+  let mut no_autolink_depth = 0usize;
+  for ev in events {
+    if jsopts.enable_autolinks && no_autolink_depth == 0 {
+      if let Event::Text(x) = &ev {
+        pending_text.get_or_insert_with(String::new).push_str(x);
+        continue;
+      }
+    }
+    if let Some(text) = pending_text.take() {
+      push_autolinked(&mut out, text);
+    }
+    match &ev {
+      Event::Start(Tag::Link(..) | Tag::Image(..) | Tag::CodeBlock(_)) => no_autolink_depth += 1,
+      Event::End(Tag::Link(..) | Tag::Image(..) | Tag::CodeBlock(_)) => no_autolink_depth -= 1,
+      _ => {}
+    }
+    out.push(ev);
+  }
+  if let Some(text) = pending_text.take() {
+    push_autolinked(&mut out, text);
+  }
+
+  if !jsopts.enable_heading_anchors && jsopts.heading_level_offset == 0 {
+    return out;
+  }
+
+  // Headings. With anchors the heading tags are written as raw HTML, because pulldown-cmark
+  // doesn't render attributes on headings.
+  let shift = |level: u32| level.saturating_add(jsopts.heading_level_offset).min(6);
+  let mut seen_slugs: HashMap<String, usize> = HashMap::new();
+  let mut result = Vec::with_capacity(out.len());
+  let mut it = out.into_iter();
+  while let Some(ev) = it.next() {
+    let level = match ev {
+      Event::Start(Tag::Heading(level)) => level,
+      Event::End(Tag::Heading(level)) => {
+        result.push(Event::End(Tag::Heading(shift(level))));
+        continue;
+      }
+      ev => {
+        result.push(ev);
+        continue;
+      }
+    };
+    if !jsopts.enable_heading_anchors {
+      result.push(Event::Start(Tag::Heading(shift(level))));
+      continue;
+    }
+
+    let mut inner = vec![];
+    let mut text = String::new();
+    for ev in &mut it {
+      match &ev {
+        Event::End(Tag::Heading(_)) => break,
+        Event::Text(x) | Event::Code(x) => text.push_str(x),
+        _ => {}
+      }
+      inner.push(ev);
+    }
+    let mut slug = slugify(&text);
+    if !slug.is_empty() {
+      let count = seen_slugs.entry(slug.clone()).or_insert(0);
+      if *count > 0 {
+        slug = format!("{}-{}", slug, count);
+      }
+      *count += 1;
+    }
+    let level = shift(level);
+    result.push(Event::Html(CowStr::from(if slug.is_empty() {
+      format!("<h{}>", level)
+    } else {
+      format!("<h{} id=\"{}\">", level, slug)
+    })));
+    result.extend(inner);
+    result.push(Event::Html(CowStr::from(format!("</h{}>\n", level))));
+  }
+  result
+}
+
+pub fn render_markdown(markdown_input: &str, jsopts: &TextMarkdownRenderOpts) -> Result<String> {
   let mut opts = Options::empty();
   if jsopts.enable_footnotes {
     opts.insert(Options::ENABLE_FOOTNOTES);
@@ -45,13 +220,174 @@ pub fn api_text_markdown_render(
   if jsopts.enable_smart_punctuation {
     opts.insert(Options::ENABLE_SMART_PUNCTUATION);
   }
-  let parser = Parser::new_ext(&markdown_input, opts);
+  let parser = Parser::new_ext(markdown_input, opts);
   let mut html_output = String::new();
-  html::push_html(&mut html_output, parser);
+  html::push_html(
+    &mut html_output,
+    apply_extensions(parser, jsopts).into_iter(),
+  );
 
   if !jsopts.disable_sanitization {
-    html_output = ammonia::clean(&html_output);
+    let default_sanitize = TextHtmlSanitizeOptions::default();
+    let mut builder = sanitizer(jsopts.sanitize.as_ref().unwrap_or(&default_sanitize))?;
+    if jsopts.enable_heading_anchors {
+      for tag in HEADING_TAGS {
+        builder.add_tag_attributes(tag, &["id"]);
+      }
+    }
+    html_output = builder.clean(&html_output).to_string();
   }
+  Ok(html_output)
+}
+
+pub fn api_text_markdown_render(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let markdown_input = args.get(1).to_rust_string_lossy(scope);
+  let jsopts: TextMarkdownRenderOpts = v8_deserialize(scope, args.get(2))?;
+  let html_output = render_markdown(&markdown_input, &jsopts)?;
   retval.set(mk_v8_string(scope, &html_output)?.into());
   Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+  use super::{render_markdown, TextMarkdownRenderOpts};
+
+  fn render(input: &str, opts: TextMarkdownRenderOpts) -> String {
+    render_markdown(input, &opts).unwrap()
+  }
+
+  #[test]
+  fn tables() {
+    let input = "| a | b |\n|---|---|\n| 1 | 2 |\n";
+    assert!(!render(input, Default::default()).contains("<table>"));
+    let out = render(
+      input,
+      TextMarkdownRenderOpts {
+        enable_tables: true,
+        ..Default::default()
+      },
+    );
+    assert!(out.contains("<table>"), "{}", out);
+    assert!(out.contains("<td>1</td>"), "{}", out);
+  }
+
+  #[test]
+  fn strikethrough() {
+    assert_eq!(render("~~x~~", Default::default()), "<p>~~x~~</p>\n");
+    assert_eq!(
+      render(
+        "~~x~~",
+        TextMarkdownRenderOpts {
+          enable_strikethrough: true,
+          ..Default::default()
+        }
+      ),
+      "<p><del>x</del></p>\n"
+    );
+  }
+
+  #[test]
+  fn tasklists() {
+    let out = render(
+      "- [x] done\n- [ ] todo\n",
+      TextMarkdownRenderOpts {
+        enable_tasklists: true,
+        // The default allowlist removes `<input>`.
+        disable_sanitization: true,
+        ..Default::default()
+      },
+    );
+    assert!(out.contains("checked=\"\""), "{}", out);
+    assert!(out.contains("type=\"checkbox\""), "{}", out);
+    assert!(!out.contains("[x]"), "{}", out);
+  }
+
+  #[test]
+  fn footnotes() {
+    let input = "a[^1]\n\n[^1]: note\n";
+    assert!(!render(input, Default::default()).contains("footnote"));
+    let out = render(
+      input,
+      TextMarkdownRenderOpts {
+        enable_footnotes: true,
+        disable_sanitization: true,
+        ..Default::default()
+      },
+    );
+    assert!(out.contains("footnote-definition"), "{}", out);
+  }
+
+  #[test]
+  fn autolinks() {
+    let input = "see https://example.com/a_b_c. or (http://x.example/p) and `https://code.example`";
+    let opts = || TextMarkdownRenderOpts {
+      enable_autolinks: true,
+      ..Default::default()
+    };
+    assert_eq!(
+      render(input, opts()),
+      "<p>see <a href=\"https://example.com/a_b_c\" rel=\"noopener noreferrer\">https://example.com/a_b_c</a>. \
+       or (<a href=\"http://x.example/p\" rel=\"noopener noreferrer\">http://x.example/p</a>) \
+       and <code>https://code.example</code></p>\n"
+    );
+
+    // Existing links are not linked again.
+    assert_eq!(
+      render("[https://a.example](https://b.example)", opts()),
+      "<p><a href=\"https://b.example\" rel=\"noopener noreferrer\">https://a.example</a></p>\n"
+    );
+    assert!(!render("https://example.com", Default::default()).contains("<a"));
+  }
+
+  #[test]
+  fn heading_anchors() {
+    let out = render(
+      "# Getting Started!\n\n## Getting started\n\n### `code` & more\n",
+      TextMarkdownRenderOpts {
+        enable_heading_anchors: true,
+        ..Default::default()
+      },
+    );
+    assert!(
+      out.contains("<h1 id=\"getting-started\">Getting Started!</h1>"),
+      "{}",
+      out
+    );
+    assert!(
+      out.contains("<h2 id=\"getting-started-1\">Getting started</h2>"),
+      "{}",
+      out
+    );
+    assert!(
+      out.contains("<h3 id=\"code-more\"><code>code</code> &amp; more</h3>"),
+      "{}",
+      out
+    );
+  }
+
+  #[test]
+  fn heading_level_offset() {
+    let out = render(
+      "# a\n\n##### b\n",
+      TextMarkdownRenderOpts {
+        heading_level_offset: 2,
+        ..Default::default()
+      },
+    );
+    assert_eq!(out, "<h3>a</h3>\n<h6>b</h6>\n");
+
+    let out = render(
+      "# a\n",
+      TextMarkdownRenderOpts {
+        heading_level_offset: 1,
+        enable_heading_anchors: true,
+        ..Default::default()
+      },
+    );
+    assert!(out.contains("<h2 id=\"a\">a</h2>"), "{}", out);
+  }
+}
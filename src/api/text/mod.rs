@@ -1,4 +1,8 @@
+pub mod csv;
 pub mod dom;
+pub mod html;
 pub mod json;
 pub mod markdown;
+pub mod toml;
+pub mod xml;
 pub mod yaml;
@@ -0,0 +1,232 @@
+use std::convert::TryFrom;
+
+use anyhow::Result;
+use chrono::{SecondsFormat, TimeZone, Utc};
+use thiserror::Error;
+use toml::{value::Datetime, Value};
+use v8;
+
+use crate::{api::util::mk_v8_string, v8util::LocalValueExt};
+
+/// Maximum nesting depth of a value passed to `text_toml_stringify`. Also stops cycles.
+const MAX_STRINGIFY_DEPTH: usize = 128;
+
+#[derive(Error, Debug)]
+#[error("cannot represent {what} in toml at {path}")]
+pub struct TomlUnrepresentable {
+  what: &'static str,
+  path: String,
+}
+
+#[derive(Error, Debug)]
+#[error("toml stringify input must be an object")]
+struct TomlNotATable;
+
+#[derive(Error, Debug)]
+#[error("toml stringify input is nested too deeply")]
+struct TomlTooDeep;
+
+fn toml_to_v8<'s>(
+  scope: &mut v8::HandleScope<'s>,
+  value: &Value,
+) -> Result<v8::Local<'s, v8::Value>> {
+  Ok(match value {
+    Value::String(x) => mk_v8_string(scope, x)?.into(),
+    Value::Integer(x) => v8::Number::new(scope, *x as f64).into(),
+    Value::Float(x) => v8::Number::new(scope, *x).into(),
+    Value::Boolean(x) => v8::Boolean::new(scope, *x).into(),
+    // Offset datetimes are RFC 3339. Local dates and times keep their TOML form, which is also
+    // ISO 8601.
+    Value::Datetime(x) => mk_v8_string(scope, &x.to_string())?.into(),
+    Value::Array(x) => {
+      let elements = x
+        .iter()
+        .map(|x| toml_to_v8(scope, x))
+        .collect::<Result<Vec<_>>>()?;
+      v8::Array::new_with_elements(scope, &elements).into()
+    }
+    Value::Table(x) => {
+      let obj = v8::Object::new(scope);
+      for (k, v) in x {
+        let k = mk_v8_string(scope, k)?;
+        let v = toml_to_v8(scope, v)?;
+        obj.set(scope, k.into(), v);
+      }
+      obj.into()
+    }
+  })
+}
+
+fn v8_to_toml(
+  scope: &mut v8::HandleScope,
+  value: v8::Local<v8::Value>,
+  path: &mut Vec<String>,
+) -> Result<Value> {
+  let unrepresentable = |what: &'static str, path: &[String]| -> anyhow::Error {
+    TomlUnrepresentable {
+      what,
+      path: if path.is_empty() {
+        "the top level".into()
+      } else {
+        path.join(".")
+      },
+    }
+    .into()
+  };
+
+  if path.len() > MAX_STRINGIFY_DEPTH {
+    return Err(TomlTooDeep.into());
+  }
+  if value.is_undefined() {
+    return Err(unrepresentable("undefined", path));
+  }
+  if value.is_null() {
+    return Err(unrepresentable("null", path));
+  }
+  if value.is_boolean() {
+    return Ok(Value::Boolean(value.boolean_value(scope)));
+  }
+  if let Ok(x) = v8::Local::<v8::Number>::try_from(value) {
+    let x = x.value();
+    // Integral numbers in the safe integer range become TOML integers, like in `JSON.stringify`.
+    return Ok(if x.trunc() == x && x.abs() <= 9007199254740991.0 {
+      Value::Integer(x as i64)
+    } else {
+      Value::Float(x)
+    });
+  }
+  if value.is_string() {
+    return Ok(Value::String(value.to_rust_string_lossy(scope)));
+  }
+  if let Ok(x) = v8::Local::<v8::Date>::try_from(value) {
+    if !x.value_of().is_finite() {
+      return Err(unrepresentable("an invalid date", path));
+    }
+    let iso = Utc
+      .timestamp_millis_opt(x.value_of() as i64)
+      .single()
+      .ok_or_else(|| unrepresentable("an out-of-range date", path))?
+      .to_rfc3339_opts(SecondsFormat::Millis, true);
+    let dt: Datetime = iso
+      .parse()
+      .map_err(|_| unrepresentable("an out-of-range date", path))?;
+    return Ok(Value::Datetime(dt));
+  }
+  if let Ok(x) = v8::Local::<v8::Array>::try_from(value) {
+    let mut out = Vec::with_capacity(x.length() as usize);
+    for i in 0..x.length() {
+      let v = x.get_index(scope, i).unwrap();
+      path.push(i.to_string());
+      out.push(v8_to_toml(scope, v, path)?);
+      path.pop();
+    }
+    return Ok(Value::Array(out));
+  }
+  if value.is_function() || value.is_symbol() || value.is_big_int() {
+    return Err(unrepresentable(
+      if value.is_function() {
+        "a function"
+      } else if value.is_symbol() {
+        "a symbol"
+      } else {
+        "a bigint"
+      },
+      path,
+    ));
+  }
+  let obj = v8::Local::<v8::Object>::try_from(value)?;
+  let names = obj
+    .get_own_property_names(scope)
+    .ok_or_else(|| unrepresentable("an object without enumerable keys", path))?;
+  let mut table = toml::value::Table::new();
+  for i in 0..names.length() {
+    let k = names.get_index(scope, i).unwrap();
+    let v = obj.get(scope, k).unwrap();
+    let k = k.to_rust_string_lossy(scope);
+    path.push(k.clone());
+    table.insert(k, v8_to_toml(scope, v, path)?);
+    path.pop();
+  }
+  Ok(Value::Table(table))
+}
+
+/// Parses TOML into JS values. Tables keep their key order and datetimes become ISO 8601 strings.
+pub fn api_text_toml_parse(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let text = unsafe { args.get(1).read_string_assume_noalias(scope)? };
+  let value: Value = toml::from_str(&text)?;
+  retval.set(toml_to_v8(scope, &value)?);
+  Ok(())
+}
+
+/// Stringifies an object as a TOML document. Keys keep their order, except that TOML requires
+/// plain values to come before tables. Fails on values without a TOML representation, like
+/// `null` and `undefined`.
+pub fn api_text_toml_stringify(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let value = v8_to_toml(scope, args.get(1), &mut vec![])?;
+  if !value.is_table() {
+    return Err(TomlNotATable.into());
+  }
+  let text = toml::to_string(&value)?;
+  retval.set(mk_v8_string(scope, &text)?.into());
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::api::testutil::ApiTester;
+
+  #[test]
+  fn test_toml_parse() {
+    let mut tester = ApiTester::new();
+    let out: String = tester.run_script(
+      r#"
+      const v = TextUtil.Toml.parse(`
+        title = "x"
+        when = 1979-05-27T07:32:00Z
+        day = 1979-05-27
+
+        [server]
+        port = 8080
+        ratio = 0.5
+        tags = ["a", "b"]
+      `);
+      JSON.stringify(v)"#,
+    );
+    assert_eq!(
+      out,
+      r#"{"title":"x","when":"1979-05-27T07:32:00Z","day":"1979-05-27","server":{"port":8080,"ratio":0.5,"tags":["a","b"]}}"#
+    );
+  }
+
+  #[test]
+  fn test_toml_stringify() {
+    let mut tester = ApiTester::new();
+    let out: String = tester
+      .run_script(r#"TextUtil.Toml.stringify({ z: 1, server: { port: 8080 }, a: "x", f: 1.5 })"#);
+    assert_eq!(out, "z = 1\na = \"x\"\nf = 1.5\n\n[server]\nport = 8080\n");
+
+    let out: String = tester.run_script(
+      r#"
+      let msg = '';
+      try {
+        TextUtil.Toml.stringify({ a: { b: [1, undefined] } });
+      } catch (e) {
+        msg = e.message;
+      }
+      msg"#,
+    );
+    assert!(
+      out.contains("cannot represent undefined in toml at a.b.1"),
+      "{}",
+      out
+    );
+  }
+}
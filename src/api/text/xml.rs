@@ -0,0 +1,494 @@
+use std::convert::TryFrom;
+
+use anyhow::Result;
+use indexmap::IndexMap;
+use quick_xml::{
+  events::{BytesStart, Event},
+  Reader,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use v8;
+
+use crate::{
+  api::util::{mk_v8_string, v8_deserialize},
+  v8util::LocalValueExt,
+};
+
+/// Maximum element nesting depth for parse and stringify. Also stops cycles in stringify.
+const MAX_XML_DEPTH: usize = 128;
+
+/// Key of the attributes object of an element.
+const ATTRIBUTES_KEY: &str = "@";
+
+/// Key of the text content of an element that also has attributes or children.
+const TEXT_KEY: &str = "#text";
+
+#[derive(Serialize, Deserialize, JsonSchema, Default)]
+pub struct TextXmlParseOptions {
+  /// Always put child elements in arrays, even when there is only one with that name.
+  #[serde(default)]
+  pub always_array: bool,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Default)]
+pub struct TextXmlStringifyOptions {
+  /// Start the output with `<?xml version="1.0" encoding="UTF-8"?>`.
+  #[serde(default)]
+  pub declaration: bool,
+}
+
+#[derive(Error, Debug)]
+#[error("xml documents with a DOCTYPE are not allowed")]
+pub struct XmlDoctypeNotAllowed;
+
+#[derive(Error, Debug)]
+#[error("xml is nested too deeply")]
+pub struct XmlTooDeep;
+
+#[derive(Error, Debug)]
+#[error("malformed xml: {0}")]
+pub struct MalformedXml(&'static str);
+
+#[derive(Error, Debug)]
+#[error("invalid xml name `{0}`")]
+pub struct InvalidXmlName(String);
+
+#[derive(Error, Debug)]
+#[error("xml stringify input must be an object with a single key, the root element name")]
+struct XmlNotASingleRoot;
+
+#[derive(Default, Debug, PartialEq)]
+struct Element {
+  name: String,
+  attributes: Vec<(String, String)>,
+  text: String,
+  children: Vec<Element>,
+}
+
+fn read_element(reader: &Reader<&[u8]>, e: &BytesStart) -> Result<Element> {
+  let mut el = Element {
+    name: std::str::from_utf8(e.name())?.to_string(),
+    ..Default::default()
+  };
+  for attr in e.attributes() {
+    let attr = attr?;
+    el.attributes.push((
+      std::str::from_utf8(attr.key)?.to_string(),
+      attr.unescape_and_decode_value(reader)?,
+    ));
+  }
+  Ok(el)
+}
+
+fn close_element(stack: &mut Vec<Element>, root: &mut Option<Element>, el: Element) -> Result<()> {
+  if let Some(parent) = stack.last_mut() {
+    parent.children.push(el);
+  } else if root.is_some() {
+    return Err(MalformedXml("more than one root element").into());
+  } else {
+    *root = Some(el);
+  }
+  Ok(())
+}
+
+/// Parses an XML document into its root element.
+///
+/// Only the predefined entities and character references are expanded. Documents with a DOCTYPE
+/// are rejected, so entity definitions (and entity expansion attacks) never reach the parser.
+fn parse_xml(text: &str) -> Result<Element> {
+  let mut reader = Reader::from_str(text);
+  reader.check_end_names(true);
+  let mut buf = vec![];
+  let mut stack: Vec<Element> = vec![];
+  let mut root: Option<Element> = None;
+  loop {
+    match reader.read_event(&mut buf)? {
+      Event::Start(e) => {
+        if stack.len() >= MAX_XML_DEPTH {
+          return Err(XmlTooDeep.into());
+        }
+        if root.is_some() {
+          return Err(MalformedXml("more than one root element").into());
+        }
+        stack.push(read_element(&reader, &e)?);
+      }
+      Event::Empty(e) => {
+        let el = read_element(&reader, &e)?;
+        close_element(&mut stack, &mut root, el)?;
+      }
+      Event::End(_) => {
+        let mut el = stack.pop().ok_or(MalformedXml("unexpected closing tag"))?;
+        if el.text.trim().is_empty() {
+          el.text.clear();
+        } else {
+          el.text = el.text.trim().to_string();
+        }
+        close_element(&mut stack, &mut root, el)?;
+      }
+      Event::Text(e) => {
+        let text = e.unescape_and_decode(&reader)?;
+        match stack.last_mut() {
+          Some(el) => el.text.push_str(&text),
+          None if text.trim().is_empty() => {}
+          None => return Err(MalformedXml("text outside of the root element").into()),
+        }
+      }
+      Event::CData(e) => {
+        let text = std::str::from_utf8(&e)?;
+        stack
+          .last_mut()
+          .ok_or(MalformedXml("CDATA outside of the root element"))?
+          .text
+          .push_str(text);
+      }
+      Event::DocType(_) => return Err(XmlDoctypeNotAllowed.into()),
+      Event::Eof => break,
+      // Declarations, comments and processing instructions.
+      _ => {}
+    }
+    buf.clear();
+  }
+  if !stack.is_empty() {
+    return Err(MalformedXml("unclosed element").into());
+  }
+  root.ok_or_else(|| MalformedXml("no root element").into())
+}
+
+fn element_to_v8<'s>(
+  scope: &mut v8::HandleScope<'s>,
+  el: &Element,
+  always_array: bool,
+) -> Result<v8::Local<'s, v8::Value>> {
+  if el.attributes.is_empty() && el.children.is_empty() {
+    return Ok(mk_v8_string(scope, &el.text)?.into());
+  }
+  let obj = v8::Object::new(scope);
+  if !el.attributes.is_empty() {
+    let attrs = v8::Object::new(scope);
+    for (k, v) in &el.attributes {
+      let k = mk_v8_string(scope, k)?;
+      let v = mk_v8_string(scope, v)?;
+      attrs.set(scope, k.into(), v.into());
+    }
+    let k = mk_v8_string(scope, ATTRIBUTES_KEY)?;
+    obj.set(scope, k.into(), attrs.into());
+  }
+  if !el.text.is_empty() {
+    let k = mk_v8_string(scope, TEXT_KEY)?;
+    let v = mk_v8_string(scope, &el.text)?;
+    obj.set(scope, k.into(), v.into());
+  }
+  let mut groups: IndexMap<&str, Vec<v8::Local<v8::Value>>> = IndexMap::new();
+  for child in &el.children {
+    let v = element_to_v8(scope, child, always_array)?;
+    groups.entry(child.name.as_str()).or_default().push(v);
+  }
+  for (name, values) in groups {
+    let k = mk_v8_string(scope, name)?;
+    let v = if values.len() == 1 && !always_array {
+      values[0]
+    } else {
+      v8::Array::new_with_elements(scope, &values).into()
+    };
+    obj.set(scope, k.into(), v);
+  }
+  Ok(obj.into())
+}
+
+fn check_name(name: &str) -> Result<()> {
+  let valid = !name.is_empty()
+    && !name.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.')
+    && !name
+      .chars()
+      .any(|c| c.is_whitespace() || "<>&\"'/=!?".contains(c));
+  if valid {
+    Ok(())
+  } else {
+    Err(InvalidXmlName(name.to_string()).into())
+  }
+}
+
+fn escape_xml(out: &mut String, text: &str) {
+  for c in text.chars() {
+    match c {
+      '&' => out.push_str("&amp;"),
+      '<' => out.push_str("&lt;"),
+      '>' => out.push_str("&gt;"),
+      '"' => out.push_str("&quot;"),
+      '\'' => out.push_str("&apos;"),
+      c => out.push(c),
+    }
+  }
+}
+
+fn write_element(
+  scope: &mut v8::HandleScope,
+  name: &str,
+  value: v8::Local<v8::Value>,
+  out: &mut String,
+  depth: usize,
+) -> Result<()> {
+  if depth > MAX_XML_DEPTH {
+    return Err(XmlTooDeep.into());
+  }
+  check_name(name)?;
+
+  if let Ok(x) = v8::Local::<v8::Array>::try_from(value) {
+    // Repeated elements.
+    for i in 0..x.length() {
+      let v = x
+        .get_index(scope, i)
+        .ok_or(MalformedXml("failed to read a property"))?;
+      if v.is_array() {
+        return Err(MalformedXml("nested arrays in stringify input").into());
+      }
+      write_element(scope, name, v, out, depth + 1)?;
+    }
+    return Ok(());
+  }
+
+  out.push('<');
+  out.push_str(name);
+  if value.is_null_or_undefined() {
+    out.push_str("/>");
+    return Ok(());
+  }
+  if !value.is_object() || value.is_date() {
+    out.push('>');
+    escape_xml(out, &value.to_rust_string_lossy(scope));
+    out.push_str("</");
+    out.push_str(name);
+    out.push('>');
+    return Ok(());
+  }
+
+  let obj = v8::Local::<v8::Object>::try_from(value)?;
+  let names = obj
+    .get_own_property_names(scope)
+    .ok_or(MalformedXml("object without enumerable keys"))?;
+  let mut text = String::new();
+  let mut children: Vec<(String, v8::Local<v8::Value>)> = vec![];
+  for i in 0..names.length() {
+    let k = names
+      .get_index(scope, i)
+      .ok_or(MalformedXml("failed to read a property"))?;
+    let v = obj
+      .get(scope, k)
+      .ok_or(MalformedXml("failed to read a property"))?;
+    let k = k.to_rust_string_lossy(scope);
+    if k == ATTRIBUTES_KEY {
+      if v.is_null_or_undefined() {
+        continue;
+      }
+      let attrs = v8::Local::<v8::Object>::try_from(v)?;
+      let attr_names = attrs
+        .get_own_property_names(scope)
+        .ok_or(MalformedXml("object without enumerable keys"))?;
+      for j in 0..attr_names.length() {
+        let ak = attr_names
+          .get_index(scope, j)
+          .ok_or(MalformedXml("failed to read a property"))?;
+        let av = attrs
+          .get(scope, ak)
+          .ok_or(MalformedXml("failed to read a property"))?;
+        let ak = ak.to_rust_string_lossy(scope);
+        check_name(&ak)?;
+        if av.is_null_or_undefined() {
+          continue;
+        }
+        out.push(' ');
+        out.push_str(&ak);
+        out.push_str("=\"");
+        escape_xml(out, &av.to_rust_string_lossy(scope));
+        out.push('"');
+      }
+    } else if k == TEXT_KEY {
+      if !v.is_null_or_undefined() {
+        text = v.to_rust_string_lossy(scope);
+      }
+    } else {
+      children.push((k, v));
+    }
+  }
+  if text.is_empty() && children.is_empty() {
+    out.push_str("/>");
+    return Ok(());
+  }
+  out.push('>');
+  escape_xml(out, &text);
+  for (k, v) in children {
+    write_element(scope, &k, v, out, depth + 1)?;
+  }
+  out.push_str("</");
+  out.push_str(name);
+  out.push('>');
+  Ok(())
+}
+
+/// Parses an XML document into `{ rootName: element }`.
+///
+/// An element with only text becomes a string. Otherwise it becomes an object with its attributes
+/// under `@`, its trimmed text (including CDATA) under `#text`, and its child elements under their
+/// names. Names keep their namespace prefix, and `xmlns` declarations are kept as attributes. A
+/// name that appears more than once holds an array, and with `always_array` every child does.
+/// Comments and processing instructions are dropped.
+pub fn api_text_xml_parse(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let text = unsafe { args.get(1).read_string_assume_noalias(scope)? };
+  let opts: TextXmlParseOptions = if args.get(2).is_null_or_undefined() {
+    Default::default()
+  } else {
+    v8_deserialize(scope, args.get(2))?
+  };
+  let root = parse_xml(&text)?;
+  let value = element_to_v8(scope, &root, opts.always_array)?;
+  let obj = v8::Object::new(scope);
+  let k = mk_v8_string(scope, &root.name)?;
+  obj.set(scope, k.into(), value);
+  retval.set(obj.into());
+  Ok(())
+}
+
+/// Stringifies `{ rootName: element }` in the form returned by `text_xml_parse`. Arrays become
+/// repeated elements, `null` and `undefined` become empty elements, and other values are converted
+/// to strings.
+pub fn api_text_xml_stringify(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let input = v8::Local::<v8::Object>::try_from(args.get(1)).map_err(|_| XmlNotASingleRoot)?;
+  let opts: TextXmlStringifyOptions = if args.get(2).is_null_or_undefined() {
+    Default::default()
+  } else {
+    v8_deserialize(scope, args.get(2))?
+  };
+  let names = input
+    .get_own_property_names(scope)
+    .ok_or(XmlNotASingleRoot)?;
+  if names.length() != 1 {
+    return Err(XmlNotASingleRoot.into());
+  }
+  let k = names.get_index(scope, 0).ok_or(XmlNotASingleRoot)?;
+  let v = input.get(scope, k).ok_or(XmlNotASingleRoot)?;
+  if v.is_array() {
+    return Err(XmlNotASingleRoot.into());
+  }
+  let k = k.to_rust_string_lossy(scope);
+
+  let mut out = String::new();
+  if opts.declaration {
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+  }
+  write_element(scope, &k, v, &mut out, 0)?;
+  retval.set(mk_v8_string(scope, &out)?.into());
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{parse_xml, MalformedXml, XmlDoctypeNotAllowed};
+  use crate::api::testutil::ApiTester;
+
+  #[test]
+  fn test_xml_parse() {
+    let mut tester = ApiTester::new();
+    let out: String = tester.run_script(
+      r#"
+      const v = TextUtil.Xml.parse(`<?xml version="1.0"?>
+        <rss version="2.0" xmlns:content="http://purl.org/rss/1.0/modules/content/">
+          <!-- comment -->
+          <channel>
+            <title>A &amp; B</title>
+            <item><title>one</title><content:encoded><![CDATA[<p>hi</p>]]></content:encoded></item>
+            <item><title>two</title><guid isPermaLink="false">2</guid></item>
+            <empty/>
+          </channel>
+        </rss>`);
+      JSON.stringify(v)"#,
+    );
+    assert_eq!(
+      out,
+      r##"{"rss":{"@":{"version":"2.0","xmlns:content":"http://purl.org/rss/1.0/modules/content/"},"channel":{"title":"A & B","item":[{"title":"one","content:encoded":"<p>hi</p>"},{"title":"two","guid":{"@":{"isPermaLink":"false"},"#text":"2"}}],"empty":""}}}"##
+    );
+
+    let out: String = tester.run_script(
+      r#"JSON.stringify(TextUtil.Xml.parse("<a><b>x</b></a>", { always_array: true }))"#,
+    );
+    assert_eq!(out, r#"{"a":{"b":["x"]}}"#);
+  }
+
+  #[test]
+  fn test_xml_rejects_entities() {
+    let billion_laughs = r#"<?xml version="1.0"?>
+      <!DOCTYPE lolz [
+        <!ENTITY lol "lol">
+        <!ENTITY lol2 "&lol;&lol;&lol;&lol;&lol;&lol;&lol;&lol;&lol;&lol;">
+        <!ENTITY lol3 "&lol2;&lol2;&lol2;&lol2;&lol2;&lol2;&lol2;&lol2;&lol2;&lol2;">
+      ]>
+      <lolz>&lol3;</lolz>"#;
+    assert!(parse_xml(billion_laughs)
+      .unwrap_err()
+      .is::<XmlDoctypeNotAllowed>());
+
+    // Undefined entities are errors rather than being expanded or passed through.
+    assert!(parse_xml("<a>&lol;</a>").is_err());
+    assert_eq!(parse_xml("<a>&#x41;&lt;</a>").unwrap().text, "A<");
+
+    assert!(parse_xml("<a/><b/>").unwrap_err().is::<MalformedXml>());
+    assert!(parse_xml("<a>").unwrap_err().is::<MalformedXml>());
+  }
+
+  #[test]
+  fn test_xml_stringify() {
+    let mut tester = ApiTester::new();
+    let out: String = tester.run_script(
+      r##"
+      TextUtil.Xml.stringify({
+        "soap:Envelope": {
+          "@": { "xmlns:soap": "http://schemas.xmlsoap.org/soap/envelope/" },
+          "soap:Body": { item: [1, { "@": { id: "a\"b" }, "#text": "x < y" }], none: null },
+        },
+      }, { declaration: true })"##,
+    );
+    assert_eq!(
+      out,
+      r#"<?xml version="1.0" encoding="UTF-8"?><soap:Envelope xmlns:soap="http://schemas.xmlsoap.org/soap/envelope/"><soap:Body><item>1</item><item id="a&quot;b">x &lt; y</item><none/></soap:Body></soap:Envelope>"#
+    );
+
+    // Round trip.
+    let out: String = tester.run_script(
+      r#"
+      const x = `<a k="v"><b>1</b><b>2</b><c>t</c></a>`;
+      TextUtil.Xml.stringify(TextUtil.Xml.parse(x))"#,
+    );
+    assert_eq!(out, r#"<a k="v"><b>1</b><b>2</b><c>t</c></a>"#);
+  }
+
+  #[test]
+  fn test_xml_stringify_throwing_getter() {
+    let mut tester = ApiTester::new();
+    let out: Vec<String> = tester.run_script(
+      r#"
+      const attempt = (x) => {
+        try { TextUtil.Xml.stringify(x); return "ok"; } catch (e) { return String(e); }
+      };
+      const throwing = { get() { throw 1; } };
+      [
+        attempt(new Proxy({ a: 1 }, throwing)),
+        attempt({ a: new Proxy({ b: 1 }, throwing) }),
+        attempt({ a: { "@": { get k() { throw 1; } } } }),
+        attempt({ a: { b: new Proxy([1], throwing) } }),
+      ]"#,
+    );
+    assert!(out[0].contains("single key"), "{}", out[0]);
+    for x in &out[1..] {
+      assert!(x.contains("malformed xml"), "{}", x);
+    }
+  }
+}
@@ -1,4 +1,13 @@
+use std::cell::Cell;
+
 use anyhow::Result;
+use schemars::JsonSchema;
+use serde::{
+  de::{self, DeserializeSeed, MapAccess, SeqAccess, Visitor},
+  Deserialize, Deserializer, Serialize,
+};
+use serde_json::{Map, Number, Value};
+use thiserror::Error;
 use v8;
 
 use crate::{
@@ -6,13 +15,162 @@ use crate::{
   v8util::LocalValueExt,
 };
 
+/// Nodes allowed on top of one per input byte. Without aliases every node takes at least one
+/// byte, so only alias expansion can reach the limit.
+const YAML_EXTRA_NODES: usize = 10000;
+
+#[derive(Serialize, Deserialize, JsonSchema, Default)]
+pub struct TextYamlParseOptions {
+  /// Parse a stream of `---` separated documents into an array. Without this, a stream with more
+  /// than one document is an error.
+  #[serde(default)]
+  pub multi_document: bool,
+}
+
+#[derive(Error, Debug)]
+#[error("yaml expands to too many nodes")]
+pub struct YamlTooManyNodes;
+
+/// Deserializes a `serde_json::Value`, taking one node from the budget for every value. Aliases
+/// are expanded by serde_yaml, so each use of an anchor counts again.
+#[derive(Clone, Copy)]
+struct BudgetedValue<'a>(&'a Cell<usize>);
+
+impl<'a> BudgetedValue<'a> {
+  fn take<E: de::Error>(self) -> Result<(), E> {
+    match self.0.get() {
+      0 => Err(E::custom(YamlTooManyNodes)),
+      n => {
+        self.0.set(n - 1);
+        Ok(())
+      }
+    }
+  }
+}
+
+impl<'de, 'a> DeserializeSeed<'de> for BudgetedValue<'a> {
+  type Value = Value;
+
+  fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+    deserializer.deserialize_any(self)
+  }
+}
+
+impl<'de, 'a> Visitor<'de> for BudgetedValue<'a> {
+  type Value = Value;
+
+  fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    f.write_str("a yaml value")
+  }
+
+  fn visit_unit<E: de::Error>(self) -> Result<Value, E> {
+    self.take()?;
+    Ok(Value::Null)
+  }
+
+  fn visit_none<E: de::Error>(self) -> Result<Value, E> {
+    self.visit_unit()
+  }
+
+  fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+    self.deserialize(deserializer)
+  }
+
+  fn visit_bool<E: de::Error>(self, v: bool) -> Result<Value, E> {
+    self.take()?;
+    Ok(Value::Bool(v))
+  }
+
+  fn visit_i64<E: de::Error>(self, v: i64) -> Result<Value, E> {
+    self.take()?;
+    Ok(Value::Number(v.into()))
+  }
+
+  fn visit_u64<E: de::Error>(self, v: u64) -> Result<Value, E> {
+    self.take()?;
+    Ok(Value::Number(v.into()))
+  }
+
+  fn visit_f64<E: de::Error>(self, v: f64) -> Result<Value, E> {
+    self.take()?;
+    // Like serde_json, NaN and infinities become null.
+    Ok(
+      Number::from_f64(v)
+        .map(Value::Number)
+        .unwrap_or(Value::Null),
+    )
+  }
+
+  fn visit_str<E: de::Error>(self, v: &str) -> Result<Value, E> {
+    self.take()?;
+    Ok(Value::String(v.to_string()))
+  }
+
+  fn visit_string<E: de::Error>(self, v: String) -> Result<Value, E> {
+    self.take()?;
+    Ok(Value::String(v))
+  }
+
+  fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+    self.take()?;
+    let mut out = vec![];
+    while let Some(x) = seq.next_element_seed(self)? {
+      out.push(x);
+    }
+    Ok(Value::Array(out))
+  }
+
+  fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+    self.take()?;
+    let mut out = Map::new();
+    while let Some(k) = map.next_key::<String>()? {
+      let v = map.next_value_seed(self)?;
+      out.insert(k, v);
+    }
+    Ok(Value::Object(out))
+  }
+}
+
+/// Parses one YAML document, or with `multi_document` an array of all documents in the stream.
+/// Anchors and aliases are resolved by copying the anchored value into each alias.
+fn parse_yaml(text: &str, opts: &TextYamlParseOptions) -> Result<Value> {
+  let budget = Cell::new(text.len().saturating_add(YAML_EXTRA_NODES));
+  let too_many = |e: serde_yaml::Error, budget: &Cell<usize>| -> anyhow::Error {
+    if budget.get() == 0 {
+      YamlTooManyNodes.into()
+    } else {
+      e.into()
+    }
+  };
+  if opts.multi_document {
+    let mut docs = vec![];
+    for doc in serde_yaml::Deserializer::from_str(text) {
+      docs.push(
+        BudgetedValue(&budget)
+          .deserialize(doc)
+          .map_err(|e| too_many(e, &budget))?,
+      );
+    }
+    Ok(Value::Array(docs))
+  } else {
+    BudgetedValue(&budget)
+      .deserialize(serde_yaml::Deserializer::from_str(text))
+      .map_err(|e| too_many(e, &budget))
+  }
+}
+
 pub fn api_text_yaml_parse(
   scope: &mut v8::HandleScope,
   args: v8::FunctionCallbackArguments,
   mut retval: v8::ReturnValue,
 ) -> Result<()> {
   let text = unsafe { args.get(1).read_string_assume_noalias(scope)? };
-  let value: serde_json::Value = serde_yaml::from_str(&text)?;
+  let opts: TextYamlParseOptions = if args.get(2).is_null_or_undefined() {
+    Default::default()
+  } else {
+    v8_deserialize(scope, args.get(2))?
+  };
+  let value = parse_yaml(&text, &opts)?;
   retval.set(v8_serialize(scope, &value)?);
   Ok(())
 }
@@ -33,3 +191,66 @@ pub fn api_text_yaml_stringify(
   retval.set(mk_v8_string(scope, &text)?.into());
   Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::{parse_yaml, TextYamlParseOptions, YamlTooManyNodes};
+
+  #[test]
+  fn single_document() {
+    let opts = TextYamlParseOptions::default();
+    assert_eq!(
+      parse_yaml("a: 1\nb: [x, y]\n", &opts).unwrap(),
+      json!({"a": 1, "b": ["x", "y"]})
+    );
+    assert!(parse_yaml("a: 1\n---\nb: 2\n", &opts).is_err());
+  }
+
+  #[test]
+  fn multi_document() {
+    let opts = TextYamlParseOptions {
+      multi_document: true,
+    };
+    assert_eq!(
+      parse_yaml("---\na: 1\n---\n- 2\n---\nplain\n", &opts).unwrap(),
+      json!([{"a": 1}, [2], "plain"])
+    );
+  }
+
+  #[test]
+  fn aliases() {
+    let opts = TextYamlParseOptions::default();
+    assert_eq!(
+      parse_yaml(
+        "base: &base\n  host: localhost\n  port: 80\ndev: *base\nlist: [*base]\n",
+        &opts
+      )
+      .unwrap(),
+      json!({
+        "base": {"host": "localhost", "port": 80},
+        "dev": {"host": "localhost", "port": 80},
+        "list": [{"host": "localhost", "port": 80}],
+      })
+    );
+  }
+
+  #[test]
+  fn alias_bomb() {
+    let bomb = r#"
+a: &a ["lol","lol","lol","lol","lol","lol","lol","lol","lol"]
+b: &b [*a,*a,*a,*a,*a,*a,*a,*a,*a]
+c: &c [*b,*b,*b,*b,*b,*b,*b,*b,*b]
+d: &d [*c,*c,*c,*c,*c,*c,*c,*c,*c]
+e: &e [*d,*d,*d,*d,*d,*d,*d,*d,*d]
+f: &f [*e,*e,*e,*e,*e,*e,*e,*e,*e]
+g: &g [*f,*f,*f,*f,*f,*f,*f,*f,*f]
+"#;
+    for multi_document in [false, true] {
+      assert!(parse_yaml(bomb, &TextYamlParseOptions { multi_document })
+        .unwrap_err()
+        .is::<YamlTooManyNodes>());
+    }
+  }
+}
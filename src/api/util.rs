@@ -18,7 +18,7 @@ use v8;
 use crate::{
   ctx::BlueboatInitData,
   exec::Executor,
-  lpch::{AppLogEntry, LowPriorityMsg},
+  lpch::{AppLogEntry, AppLogLevel, LowPriorityMsg},
   package::PackageKey,
 };
 
@@ -253,23 +253,37 @@ impl<'s> ArrayBufferBuilder<'s> {
 }
 
 pub fn write_applog2(
+  level: AppLogLevel,
   message: String,
+  fields: Option<String>,
   key: &PackageKey,
   request_id: &str,
   logseq: i32,
   lp_tx: &IpcSender<LowPriorityMsg>,
 ) {
-  log::debug!("applog({})<{}>: {}", key, request_id, message);
+  log::debug!("applog({})<{}>[{:?}]: {}", key, request_id, level, message);
   let _ = lp_tx.send(LowPriorityMsg::Log(AppLogEntry {
     app: key.clone(),
     request_id: request_id.to_string(),
     message,
     logseq,
     time: yes_i_want_to_use_now(),
+    level,
+    fields,
   }));
 }
 
-pub fn write_applog(isolate: &mut v8::Isolate, message: String) {
+pub fn write_applog(isolate: &mut v8::Isolate, level: AppLogLevel, message: String) {
+  write_applog_structured(isolate, level, message, None)
+}
+
+/// Like `write_applog`, with `fields` holding a JSON object.
+pub fn write_applog_structured(
+  isolate: &mut v8::Isolate,
+  level: AppLogLevel,
+  message: String,
+  fields: Option<String>,
+) {
   static INIT_LOGSEQ: AtomicI32 = AtomicI32::new(0);
 
   lazy_static! {
@@ -279,7 +293,9 @@ pub fn write_applog(isolate: &mut v8::Isolate, message: String) {
   if let Some(e) = Executor::try_current() {
     let e = e.upgrade().unwrap();
     write_applog2(
+      level,
       message,
+      fields,
       e.ctx.key,
       &e.request_id,
       e.allocate_logseq(),
@@ -287,14 +303,16 @@ pub fn write_applog(isolate: &mut v8::Isolate, message: String) {
     );
   } else if let Some(&init_data) = isolate.get_slot::<&'static BlueboatInitData>() {
     write_applog2(
+      level,
       message,
+      fields,
       &init_data.key,
       &format!("s:init+{}", *INIT_UUID),
       INIT_LOGSEQ.fetch_add(1, Ordering::Relaxed),
       &init_data.lp_tx,
     )
   } else {
-    log::warn!("applog: {}", message);
+    log::warn!("applog[{:?}]: {}", level, message);
   }
 }
 
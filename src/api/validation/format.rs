@@ -0,0 +1,214 @@
+use std::{
+  cell::RefCell,
+  collections::{BTreeSet, HashMap},
+  net::{Ipv4Addr, Ipv6Addr},
+};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use v8;
+
+/// Formats checked by `check_builtin_format`.
+pub const BUILTIN_FORMATS: &[&str] = &["email", "uri", "uuid", "date-time", "ipv4", "ipv6"];
+
+/// Other formats that the JSON Schema validator checks itself.
+pub const LIBRARY_FORMATS: &[&str] = &[
+  "date",
+  "time",
+  "duration",
+  "hostname",
+  "idn-email",
+  "idn-hostname",
+  "iri",
+  "iri-reference",
+  "json-pointer",
+  "relative-json-pointer",
+  "regex",
+  "uri-reference",
+  "uri-template",
+];
+
+/// Keywords whose values are instances rather than schemas, so a `format` key inside them is data.
+const NON_SCHEMA_KEYWORDS: &[&str] = &["const", "default", "enum", "examples"];
+
+static EMAIL_RE: Lazy<Regex> = Lazy::new(|| {
+  Regex::new(r"^[A-Za-z0-9.!#$%&'*+/=?^_`{|}~-]+@[A-Za-z0-9](?:[A-Za-z0-9-]{0,61}[A-Za-z0-9])?(?:\.[A-Za-z0-9](?:[A-Za-z0-9-]{0,61}[A-Za-z0-9])?)*$").unwrap()
+});
+
+static UUID_RE: Lazy<Regex> = Lazy::new(|| {
+  Regex::new(r"^[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}$")
+    .unwrap()
+});
+
+/// Checks `value` against a builtin format. Returns `None` for other formats.
+pub fn check_builtin_format(name: &str, value: &str) -> Option<bool> {
+  Some(match name {
+    "email" => value.len() <= 254 && EMAIL_RE.is_match(value),
+    "uri" => url::Url::parse(value).is_ok(),
+    "uuid" => UUID_RE.is_match(value),
+    "date-time" => chrono::DateTime::parse_from_rfc3339(value).is_ok(),
+    "ipv4" => value.parse::<Ipv4Addr>().is_ok(),
+    "ipv6" => value.parse::<Ipv6Addr>().is_ok(),
+    _ => return None,
+  })
+}
+
+/// Collects the names used with the `format` keyword in a JSON Schema.
+pub fn referenced_formats(schema: &serde_json::Value) -> BTreeSet<String> {
+  fn walk(x: &serde_json::Value, out: &mut BTreeSet<String>) {
+    match x {
+      serde_json::Value::Object(x) => {
+        for (k, v) in x {
+          match (k.as_str(), v) {
+            ("format", serde_json::Value::String(name)) => {
+              out.insert(name.clone());
+            }
+            (k, _) if NON_SCHEMA_KEYWORDS.contains(&k) => {}
+            _ => walk(v, out),
+          }
+        }
+      }
+      serde_json::Value::Array(x) => x.iter().for_each(|x| walk(x, out)),
+      _ => {}
+    }
+  }
+  let mut out = BTreeSet::new();
+  walk(schema, &mut out);
+  out
+}
+
+struct FormatFrame {
+  scope: *mut v8::HandleScope<'static>,
+  formats: HashMap<String, v8::Global<v8::Function>>,
+  exception: Option<String>,
+}
+
+thread_local! {
+  /// Validations are synchronous, but a JS format may validate another value. The innermost one is
+  /// on top.
+  static FORMAT_STACK: RefCell<Vec<FormatFrame>> = RefCell::new(Vec::new());
+}
+
+/// Runs `f` with the JS formats available to `call_js_format`. Fails with the first exception
+/// thrown by a format.
+pub fn with_js_formats<R>(
+  scope: &mut v8::HandleScope,
+  formats: HashMap<String, v8::Global<v8::Function>>,
+  f: impl FnOnce() -> R,
+) -> Result<R, String> {
+  if formats.is_empty() {
+    return Ok(f());
+  }
+  FORMAT_STACK.with(|x| {
+    x.borrow_mut().push(FormatFrame {
+      scope: scope as *mut v8::HandleScope<'_> as *mut v8::HandleScope<'static>,
+      formats,
+      exception: None,
+    })
+  });
+  let output = f();
+  let frame = FORMAT_STACK.with(|x| x.borrow_mut().pop()).unwrap();
+  match frame.exception {
+    Some(e) => Err(e),
+    None => Ok(output),
+  }
+}
+
+/// Validation is synchronous and already runs inside the isolate, so formats are called on the
+/// scope of the validation instead of through `Executor::enter`. A format that throws fails the
+/// value, and the exception fails the whole validation.
+pub fn call_js_format(name: &str, value: &str) -> bool {
+  // Don't hold the borrow while calling into JS, which may validate another value.
+  let (scope, format) = match FORMAT_STACK.with(|x| {
+    let stack = x.borrow();
+    let frame = stack.last()?;
+    Some((frame.scope, frame.formats.get(name)?.clone()))
+  }) {
+    Some(x) => x,
+    None => return false,
+  };
+
+  // SAFETY: The frame is only on the stack while `with_js_formats` runs, and the scope isn't
+  // otherwise used until it returns.
+  let scope = unsafe { &mut *scope };
+  let scope = &mut v8::HandleScope::new(scope);
+  let format = v8::Local::new(scope, &format);
+  let value = match v8::String::new(scope, value) {
+    Some(x) => x,
+    None => return false,
+  };
+  let undef = v8::undefined(scope);
+  let scope = &mut v8::TryCatch::new(scope);
+  match format.call(scope, undef.into(), &[value.into()]) {
+    Some(x) => x.boolean_value(scope),
+    None => {
+      let msg = scope
+        .exception()
+        .map(|x| x.to_rust_string_lossy(scope))
+        .unwrap_or_else(|| "execution terminated".into());
+      FORMAT_STACK.with(|x| {
+        if let Some(frame) = x.borrow_mut().last_mut() {
+          frame
+            .exception
+            .get_or_insert_with(|| format!("format `{}` threw: {}", name, msg));
+        }
+      });
+      false
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::{check_builtin_format, referenced_formats};
+
+  #[test]
+  fn builtin_formats() {
+    let cases: &[(&str, &str, bool)] = &[
+      ("email", "a.b+c@example.com", true),
+      ("email", "a@b", true),
+      ("email", "a@@b", false),
+      ("email", "no-at.example.com", false),
+      ("uri", "https://example.com/x?y", true),
+      ("uri", "urn:isbn:0451450523", true),
+      ("uri", "/relative", false),
+      ("uuid", "123e4567-e89b-12d3-a456-426614174000", true),
+      ("uuid", "123e4567e89b12d3a456426614174000", false),
+      ("date-time", "2022-05-19T12:00:00Z", true),
+      ("date-time", "2022-05-19T12:00:00.5+08:00", true),
+      ("date-time", "2022-05-19", false),
+      ("ipv4", "192.168.0.1", true),
+      ("ipv4", "256.0.0.1", false),
+      ("ipv6", "::1", true),
+      ("ipv6", "2001:db8::8a2e:370:7334", true),
+      ("ipv6", "192.168.0.1", false),
+    ];
+    for (format, value, valid) in cases {
+      assert_eq!(
+        check_builtin_format(format, value),
+        Some(*valid),
+        "{} {}",
+        format,
+        value
+      );
+    }
+    assert_eq!(check_builtin_format("hostname", "x"), None);
+  }
+
+  #[test]
+  fn finds_referenced_formats() {
+    let schema = json!({
+      "properties": {
+        "format": { "type": "string", "format": "email" },
+        "b": { "items": { "format": "even" } },
+      },
+      "default": { "format": "not-a-format" },
+    });
+    assert_eq!(
+      referenced_formats(&schema).into_iter().collect::<Vec<_>>(),
+      vec!["email", "even"]
+    );
+  }
+}
@@ -0,0 +1,312 @@
+use std::{collections::HashMap, convert::TryFrom, rc::Rc};
+
+use anyhow::Result;
+use jsonschema::{Draft, JSONSchema};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use v8;
+
+use crate::{
+  api::util::{v8_deserialize, v8_serialize},
+  registry::SymbolRegistry,
+  v8util::LocalValueExt,
+};
+
+use super::{
+  format::{
+    call_js_format, check_builtin_format, referenced_formats, with_js_formats, BUILTIN_FORMATS,
+    LIBRARY_FORMATS,
+  },
+  ValidationError, ValidationOptions,
+};
+
+#[derive(Serialize, Deserialize, JsonSchema, Default)]
+pub struct JsonSchemaLoadOptions {
+  /// Accept formats that are neither builtin nor custom, and don't check them.
+  #[serde(default)]
+  pub lenient_formats: bool,
+}
+
+#[derive(Error, Debug)]
+#[error("invalid json schema: {0}")]
+pub struct InvalidJsonSchema(String);
+
+#[derive(Error, Debug)]
+#[error("json schema uses unknown format `{0}`")]
+pub struct UnknownFormat(String);
+
+#[derive(Error, Debug)]
+#[error("json schema validation failed: {0}")]
+pub struct JsonSchemaFormatError(String);
+
+pub struct CompiledJsonSchema {
+  schema: JSONSchema,
+  js_formats: HashMap<String, v8::Global<v8::Function>>,
+}
+
+/// Compiles a schema. Schemas without `$schema` are draft 2020-12. `$ref` can point into the same
+/// document; references to other documents are not fetched and fail compilation.
+///
+/// Formats are always checked. `custom_formats` are checked with `call_js_format`.
+fn compile_json_schema(
+  schema: &serde_json::Value,
+  custom_formats: &[String],
+  opts: &JsonSchemaLoadOptions,
+) -> Result<JSONSchema> {
+  if !opts.lenient_formats {
+    for name in referenced_formats(schema) {
+      if !BUILTIN_FORMATS.contains(&name.as_str())
+        && !LIBRARY_FORMATS.contains(&name.as_str())
+        && !custom_formats.contains(&name)
+      {
+        return Err(UnknownFormat(name).into());
+      }
+    }
+  }
+
+  let mut options = JSONSchema::options();
+  if schema.get("$schema").is_none() {
+    options.with_draft(Draft::Draft202012);
+  }
+  options
+    .should_validate_formats(true)
+    .should_ignore_unknown_formats(true);
+  for &name in BUILTIN_FORMATS {
+    options.with_format(name, move |x: &str| {
+      check_builtin_format(name, x).unwrap_or(true)
+    });
+  }
+  for name in custom_formats {
+    let name2 = name.clone();
+    options.with_format(name.clone(), move |x: &str| call_js_format(&name2, x));
+  }
+  options
+    .compile(schema)
+    .map_err(|e| InvalidJsonSchema(e.to_string()).into())
+}
+
+fn validate_json_schema(
+  schema: &JSONSchema,
+  value: &serde_json::Value,
+  opts: &ValidationOptions,
+) -> Vec<ValidationError> {
+  match schema.validate(value) {
+    Ok(()) => vec![],
+    Err(errors) => errors
+      .take(opts.max_errors())
+      .map(|e| ValidationError {
+        instance_path: e.instance_path.to_string(),
+        schema_path: e.schema_path.to_string(),
+        message: e.to_string(),
+      })
+      .collect(),
+  }
+}
+
+pub fn api_jsonschema_load(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let schema: serde_json::Value = v8_deserialize(scope, args.get(1))?;
+  let mut js_formats: HashMap<String, v8::Global<v8::Function>> = HashMap::new();
+  if !args.get(2).is_null_or_undefined() {
+    let formats = v8::Local::<v8::Object>::try_from(args.get(2))?;
+    let names = formats
+      .get_own_property_names(scope)
+      .ok_or(InvalidJsonSchema("bad custom formats".into()))?;
+    for i in 0..names.length() {
+      let k = names.get_index(scope, i).unwrap();
+      let v = formats.get(scope, k).unwrap();
+      let f = v8::Local::<v8::Function>::try_from(v)?;
+      js_formats.insert(k.to_rust_string_lossy(scope), v8::Global::new(scope, f));
+    }
+  }
+  let opts: JsonSchemaLoadOptions = if args.get(3).is_null_or_undefined() {
+    Default::default()
+  } else {
+    v8_deserialize(scope, args.get(3))?
+  };
+  let custom_formats = js_formats.keys().cloned().collect::<Vec<_>>();
+  let schema = Rc::new(CompiledJsonSchema {
+    schema: compile_json_schema(&schema, &custom_formats, &opts)?,
+    js_formats,
+  });
+  let sym = SymbolRegistry::current(scope).put_new(scope, schema);
+  retval.set(sym.into());
+  Ok(())
+}
+
+/// Returns the list of errors, empty if the value is valid. Fails if a custom format throws.
+pub fn api_jsonschema_validate(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let schema: Rc<CompiledJsonSchema> = SymbolRegistry::current(scope).cast_and_get(args.get(1))?;
+  let value: serde_json::Value = v8_deserialize(scope, args.get(2))?;
+  let opts: ValidationOptions = if args.get(3).is_null_or_undefined() {
+    Default::default()
+  } else {
+    v8_deserialize(scope, args.get(3))?
+  };
+  let errors = with_js_formats(scope, schema.js_formats.clone(), || {
+    validate_json_schema(&schema.schema, &value, &opts)
+  })
+  .map_err(JsonSchemaFormatError)?;
+  retval.set(v8_serialize(scope, &errors)?);
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::{
+    compile_json_schema, validate_json_schema, InvalidJsonSchema, JsonSchemaLoadOptions,
+    UnknownFormat,
+  };
+  use crate::api::{testutil::ApiTester, validation::ValidationOptions};
+
+  #[test]
+  fn test_validate() {
+    let schema = compile_json_schema(
+      &json!({
+        "type": "object",
+        "properties": {
+          "name": { "type": "string" },
+          "tags": { "type": "array", "items": { "$ref": "#/$defs/tag" } },
+        },
+        "required": ["name"],
+        "$defs": {
+          "tag": { "type": "string", "minLength": 1 },
+        },
+      }),
+      &[],
+      &Default::default(),
+    )
+    .unwrap();
+
+    assert!(validate_json_schema(
+      &schema,
+      &json!({"name": "a", "tags": ["x"]}),
+      &Default::default()
+    )
+    .is_empty());
+
+    let errors = validate_json_schema(&schema, &json!({"tags": ["x", ""]}), &Default::default());
+    let mut paths = errors
+      .iter()
+      .map(|e| (e.instance_path.as_str(), e.schema_path.as_str()))
+      .collect::<Vec<_>>();
+    paths.sort();
+    assert_eq!(
+      paths,
+      vec![
+        ("", "/required"),
+        ("/tags/1", "/properties/tags/items/$ref/minLength"),
+      ]
+    );
+
+    let opts = ValidationOptions {
+      first_error_only: true,
+    };
+    assert_eq!(
+      validate_json_schema(&schema, &json!({"tags": ["x", ""]}), &opts).len(),
+      1
+    );
+  }
+
+  #[test]
+  fn test_draft_2020_12() {
+    // `prefixItems` is new in 2020-12.
+    let schema = compile_json_schema(
+      &json!({
+        "prefixItems": [{ "type": "integer" }],
+        "items": false,
+      }),
+      &[],
+      &Default::default(),
+    )
+    .unwrap();
+    assert!(validate_json_schema(&schema, &json!([1]), &Default::default()).is_empty());
+    assert!(!validate_json_schema(&schema, &json!(["a"]), &Default::default()).is_empty());
+    assert!(!validate_json_schema(&schema, &json!([1, 2]), &Default::default()).is_empty());
+  }
+
+  #[test]
+  fn test_invalid_schema() {
+    assert!(
+      compile_json_schema(&json!({"type": 1}), &[], &Default::default())
+        .unwrap_err()
+        .is::<InvalidJsonSchema>()
+    );
+  }
+
+  #[test]
+  fn test_formats() {
+    let schema = json!({
+      "properties": {
+        "email": { "format": "email" },
+        "id": { "format": "uuid" },
+        "host": { "format": "hostname" },
+        "n": { "format": "even" },
+      },
+    });
+    assert!(compile_json_schema(&schema, &[], &Default::default())
+      .unwrap_err()
+      .is::<UnknownFormat>());
+
+    let lenient = JsonSchemaLoadOptions {
+      lenient_formats: true,
+    };
+    let compiled = compile_json_schema(&schema, &[], &lenient).unwrap();
+    assert!(validate_json_schema(
+      &compiled,
+      &json!({"email": "a@example.com", "id": "123e4567-e89b-12d3-a456-426614174000", "n": "1"}),
+      &Default::default()
+    )
+    .is_empty());
+    let errors = validate_json_schema(
+      &compiled,
+      &json!({"email": "nope", "id": "x"}),
+      &Default::default(),
+    );
+    let mut paths = errors
+      .iter()
+      .map(|e| e.instance_path.as_str())
+      .collect::<Vec<_>>();
+    paths.sort_unstable();
+    assert_eq!(paths, vec!["/email", "/id"]);
+  }
+
+  #[test]
+  fn test_js_formats() {
+    let mut tester = ApiTester::new();
+    let out: String = tester.run_script(
+      r#"
+      const schema = new Validation.JSONSchema.JSONSchema(
+        { items: { format: "even" } },
+        { formats: { even: (x) => Number(x) % 2 === 0 } },
+      );
+      const throwing = new Validation.JSONSchema.JSONSchema(
+        { format: "bad" },
+        { formats: { bad: () => { throw new Error("boom"); } } },
+      );
+      let msg = "";
+      try {
+        throwing.validate("x");
+      } catch (e) {
+        msg = e.message;
+      }
+      JSON.stringify([
+        schema.validate(["2", "4"]),
+        schema.validate(["2", "3"]),
+        schema.errors.map((e) => e.instance_path),
+        msg.includes("boom"),
+      ])"#,
+    );
+    assert_eq!(out, r#"[true,false,["/1"],true]"#);
+  }
+}
@@ -1,13 +1,16 @@
 use anyhow::Result;
-use jtd::{Schema, SerdeSchema, ValidateOptions};
+use jtd::{Schema, SerdeSchema, ValidateOptions, ValidationErrorIndicator};
 use std::rc::Rc;
 use v8;
 
 use crate::{
-  api::util::{mk_v8_string, v8_deserialize},
+  api::util::{v8_deserialize, v8_serialize},
   registry::SymbolRegistry,
+  v8util::LocalValueExt,
 };
 
+use super::{json_pointer, ValidationError, ValidationOptions};
+
 pub fn api_jtd_load_schema(
   scope: &mut v8::HandleScope,
   args: v8::FunctionCallbackArguments,
@@ -20,6 +23,60 @@ pub fn api_jtd_load_schema(
   Ok(())
 }
 
+/// Maximum number of `ref`s followed while validating a value.
+const MAX_DEPTH: usize = 128;
+
+/// Converts a JTD error indicator. The last token of the schema path is usually the failing
+/// keyword. For a missing property it is the property name, and for an unexpected property the
+/// schema path points at the properties form itself.
+fn convert_indicator(e: &ValidationErrorIndicator) -> ValidationError {
+  let instance_path = json_pointer(e.instance_path.iter().map(|x| &**x));
+  let n = e.schema_path.len();
+  let message = if n >= 2 && e.schema_path[n - 2] == "properties" {
+    format!(
+      "value at `{}` is missing property `{}`",
+      instance_path,
+      e.schema_path[n - 1]
+    )
+  } else if let Some(keyword) = e.schema_path.last() {
+    format!("value at `{}` failed `{}`", instance_path, keyword)
+  } else {
+    format!("unexpected property at `{}`", instance_path)
+  };
+  ValidationError {
+    schema_path: json_pointer(e.schema_path.iter().map(|x| &**x)),
+    instance_path,
+    message,
+  }
+}
+
+fn validate_jtd(
+  schema: &Schema,
+  value: &serde_json::Value,
+  opts: &ValidationOptions,
+) -> Result<Vec<ValidationError>> {
+  let errors = match jtd::validate(
+    schema,
+    value,
+    ValidateOptions::new()
+      .with_max_depth(MAX_DEPTH)
+      .with_max_errors(opts.max_errors()),
+  ) {
+    Ok(x) => x,
+    // Input that is too deep for the schema is invalid, not an error of the caller.
+    Err(e) => {
+      return Ok(vec![ValidationError {
+        schema_path: String::new(),
+        instance_path: String::new(),
+        message: e.to_string(),
+      }])
+    }
+  };
+  Ok(errors.iter().map(convert_indicator).collect())
+}
+
+/// Returns the list of errors as in the standardized JTD error indicators, empty if the value is
+/// valid.
 pub fn api_jtd_validate(
   scope: &mut v8::HandleScope,
   args: v8::FunctionCallbackArguments,
@@ -27,23 +84,98 @@ pub fn api_jtd_validate(
 ) -> Result<()> {
   let schema: Rc<Schema> = SymbolRegistry::current(scope).cast_and_get(args.get(1))?;
   let value: serde_json::Value = v8_deserialize(scope, args.get(2))?;
-  match jtd::validate(
-    &schema,
-    &value,
-    ValidateOptions::new()
-      .with_max_depth(128)
-      .with_max_errors(1),
-  ) {
-    Ok(errors) => {
-      if !errors.is_empty() {
-        let desc = mk_v8_string(scope, &format!("{:?}", errors[0]))?;
-        retval.set(desc.into());
-      }
-    }
-    Err(e) => {
-      let desc = mk_v8_string(scope, &format!("{}", e))?;
-      retval.set(desc.into());
-    }
-  }
+  let opts: ValidationOptions = if args.get(3).is_null_or_undefined() {
+    Default::default()
+  } else {
+    v8_deserialize(scope, args.get(3))?
+  };
+  let errors = validate_jtd(&schema, &value, &opts)?;
+  retval.set(v8_serialize(scope, &errors)?);
   Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+  use jtd::{Schema, SerdeSchema};
+  use serde_json::json;
+
+  use super::{validate_jtd, MAX_DEPTH};
+  use crate::api::validation::ValidationOptions;
+
+  fn schema() -> Schema {
+    let schema: SerdeSchema = serde_json::from_value(json!({
+      "properties": {
+        "name": { "type": "string" },
+        "tags": { "elements": { "type": "string" } },
+      },
+      "optionalProperties": {
+        "a/b": { "type": "uint8" },
+      },
+    }))
+    .unwrap();
+    Schema::from_serde_schema(schema).unwrap()
+  }
+
+  #[test]
+  fn test_error_paths() {
+    let schema = schema();
+    assert!(validate_jtd(
+      &schema,
+      &json!({"name": "x", "tags": []}),
+      &Default::default()
+    )
+    .unwrap()
+    .is_empty());
+
+    // Paths from the JTD spec's validation suite.
+    let errors = validate_jtd(
+      &schema,
+      &json!({"tags": ["x", 1], "a/b": 300, "extra": true}),
+      &Default::default(),
+    )
+    .unwrap();
+    let mut paths = errors
+      .iter()
+      .map(|e| (e.instance_path.as_str(), e.schema_path.as_str()))
+      .collect::<Vec<_>>();
+    paths.sort();
+    assert_eq!(
+      paths,
+      vec![
+        ("", "/properties/name"),
+        ("/a~1b", "/optionalProperties/a~1b/type"),
+        ("/extra", ""),
+        ("/tags/1", "/properties/tags/elements/type"),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_first_error_only() {
+    let opts = ValidationOptions {
+      first_error_only: true,
+    };
+    let errors = validate_jtd(&schema(), &json!({"tags": [1, 2, 3]}), &opts).unwrap();
+    assert_eq!(errors.len(), 1);
+  }
+
+  #[test]
+  fn test_too_deep_is_invalid() {
+    let schema: SerdeSchema = serde_json::from_value(json!({
+      "definitions": { "node": { "elements": { "ref": "node" } } },
+      "ref": "node",
+    }))
+    .unwrap();
+    let schema = Schema::from_serde_schema(schema).unwrap();
+    let nested = |depth| (0..depth).fold(json!([]), |x, _| json!([x]));
+
+    assert!(
+      validate_jtd(&schema, &nested(MAX_DEPTH / 2), &Default::default())
+        .unwrap()
+        .is_empty()
+    );
+    let errors = validate_jtd(&schema, &nested(MAX_DEPTH + 10), &Default::default()).unwrap();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].instance_path, "");
+  }
+}
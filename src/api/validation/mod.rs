@@ -1 +1,48 @@
+pub mod format;
+pub mod jsonschema;
 pub mod jtd;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of errors returned by one validation.
+const MAX_VALIDATION_ERRORS: usize = 100;
+
+/// One failed check from a schema validator.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+pub struct ValidationError {
+  /// JSON pointer to the failing part of the input, like `/items/0/name`.
+  pub instance_path: String,
+
+  /// JSON pointer to the keyword in the schema that failed, like `/properties/items/type`.
+  pub schema_path: String,
+
+  pub message: String,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Default)]
+pub struct ValidationOptions {
+  /// Stop at the first error. Use when only validity matters.
+  #[serde(default)]
+  pub first_error_only: bool,
+}
+
+impl ValidationOptions {
+  fn max_errors(&self) -> usize {
+    if self.first_error_only {
+      1
+    } else {
+      MAX_VALIDATION_ERRORS
+    }
+  }
+}
+
+/// Joins path tokens into a JSON pointer, escaping `~` and `/`.
+fn json_pointer<'a>(tokens: impl IntoIterator<Item = &'a str>) -> String {
+  let mut out = String::new();
+  for token in tokens {
+    out.push('/');
+    out.push_str(&token.replace('~', "~0").replace('/', "~1"));
+  }
+  out
+}
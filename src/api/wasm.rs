@@ -0,0 +1,470 @@
+use anyhow::Result;
+use std::{cell::RefCell, convert::TryFrom, rc::Rc};
+use thiserror::Error;
+use v8;
+
+use crate::{
+  api::util::{
+    mk_v8_string, v8_deref_typed_array_assuming_noalias, v8_deserialize, v8_serialize,
+    write_applog, ArrayBufferBuilder,
+  },
+  lpch::AppLogLevel,
+};
+
+const WASM_MAGIC: &[u8] = b"\0asm\x01\0\0\0";
+
+/// Upper bound of each wasm linear memory, in 64KiB pages (128MiB). V8 enforces it through
+/// `--wasm-max-mem-pages` on every memory. The total of all memories of an isolate is bounded
+/// separately by `WasmMemoryAccount`.
+pub const WASM_MAX_MEMORY_PAGES: u32 = 2048;
+
+const WASM_PAGE_SIZE: u64 = 65536;
+
+/// Prefix of the exports added for each memory of a module, so that `wasm.ts` can hand the
+/// memories of an instance to `wasm_track_memories`. They are removed from the exports seen by
+/// the app.
+pub const MEMORY_EXPORT_PREFIX: &str = "__blueboat_memory_";
+
+const SECTION_IMPORT: u8 = 2;
+const SECTION_MEMORY: u8 = 5;
+const SECTION_EXPORT: u8 = 7;
+
+/// Sections that come after the export section.
+const SECTIONS_AFTER_EXPORT: &[u8] = &[8, 9, 10, 11, 12];
+
+const EXPORT_MEMORY: u8 = 2;
+
+const IMPORT_FUNC: u8 = 0;
+const IMPORT_TABLE: u8 = 1;
+const IMPORT_MEMORY: u8 = 2;
+const IMPORT_GLOBAL: u8 = 3;
+
+const LIMITS_HAS_MAX: u8 = 0x01;
+const LIMITS_SHARED: u8 = 0x02;
+const LIMITS_MEMORY64: u8 = 0x04;
+
+#[derive(Error, Debug)]
+#[error("wasm memory of {0} bytes exceeds the isolate memory limit")]
+struct WasmMemoryLimitExceeded(u64);
+
+/// Linear memories of the isolate, each with its maximum size in pages. A memory is counted until
+/// it is garbage collected, so that the total stays within the heap limit however many memories
+/// an app holds.
+#[derive(Default)]
+pub struct WasmMemoryAccount {
+  memories: RefCell<Vec<(v8::Weak<v8::Object>, u32)>>,
+}
+
+impl WasmMemoryAccount {
+  fn current(isolate: &mut v8::Isolate) -> Rc<Self> {
+    if isolate.get_slot::<Rc<Self>>().is_none() {
+      isolate.set_slot(Rc::new(Self::default()));
+    }
+    isolate.get_slot::<Rc<Self>>().unwrap().clone()
+  }
+
+  /// Bytes that the live memories can grow to. Forgets memories that were collected.
+  fn reserved_bytes(&self, scope: &mut v8::HandleScope) -> u64 {
+    let mut memories = self.memories.borrow_mut();
+    memories.retain(|(x, _)| x.to_local(scope).is_some());
+    memories.iter().map(|(_, x)| *x as u64).sum::<u64>() * WASM_PAGE_SIZE
+  }
+
+  /// Whether the live memories, together with the V8 heap, fit in the heap limit.
+  fn within_limit(&self, scope: &mut v8::HandleScope) -> Result<(), WasmMemoryLimitExceeded> {
+    let reserved = self.reserved_bytes(scope);
+    let mut stats = v8::HeapStatistics::default();
+    scope.get_heap_statistics(&mut stats);
+    if reserved + stats.used_heap_size() as u64 > stats.heap_size_limit() as u64 {
+      Err(WasmMemoryLimitExceeded(reserved))
+    } else {
+      Ok(())
+    }
+  }
+}
+
+/// Checks a WebAssembly module before it is handed to V8. Returns the rewritten module, and the
+/// maximum size in pages of each of its memories.
+pub fn api_wasm_prepare(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut retval: v8::ReturnValue,
+) -> Result<()> {
+  let module = v8::Local::<v8::TypedArray>::try_from(args.get(1))?;
+  let module = unsafe { v8_deref_typed_array_assuming_noalias(scope, module) };
+  let out = prepare_module(&module[..], WASM_MAX_MEMORY_PAGES)?;
+  let mut builder = ArrayBufferBuilder::new(scope, out.bytes.len());
+  builder.copy_from_slice(&out.bytes);
+  let bytes = builder.build_uint8array(scope, None);
+  let memory_pages = v8_serialize(scope, &out.memory_pages)?;
+  retval.set(v8::Array::new_with_elements(scope, &[bytes.into(), memory_pages]).into());
+  Ok(())
+}
+
+/// Counts the memories of a new instance, given its raw exports and the maximum size in pages of
+/// each memory of its module, against the isolate memory limit. If the memories of the isolate no
+/// longer fit even after a full GC, execution is terminated.
+pub fn api_wasm_track_memories(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  _retval: v8::ReturnValue,
+) -> Result<()> {
+  let exports = v8::Local::<v8::Object>::try_from(args.get(1))?;
+  let pages: Vec<u32> = v8_deserialize(scope, args.get(2))?;
+  let account = WasmMemoryAccount::current(scope);
+  for (i, pages) in pages.into_iter().enumerate() {
+    let name = mk_v8_string(scope, &format!("{}{}", MEMORY_EXPORT_PREFIX, i))?;
+    let memory = exports
+      .get(scope, name.into())
+      .ok_or_else(|| anyhow::anyhow!("cannot read memory {}", i))?;
+    let memory = v8::Local::<v8::Object>::try_from(memory)?;
+    let memory = v8::Weak::new(scope, memory);
+    account.memories.borrow_mut().push((memory, pages));
+  }
+
+  if account.within_limit(scope).is_ok() {
+    return Ok(());
+  }
+  // Memories that are no longer reachable are only forgotten once collected.
+  scope.low_memory_notification();
+  if let Err(e) = account.within_limit(scope) {
+    write_applog(scope, AppLogLevel::Error, e.to_string());
+    scope.terminate_execution();
+  }
+  Ok(())
+}
+
+pub struct PreparedModule {
+  pub bytes: Vec<u8>,
+
+  /// Maximum size of each memory, after clamping, in pages.
+  pub memory_pages: Vec<u32>,
+}
+
+/// Rewrites the memory section so that the declared maximum of each linear memory is within
+/// `max_pages`, and exports each memory under `MEMORY_EXPORT_PREFIX`.
+///
+/// Only function and global imports are allowed: memories and tables must be defined by the
+/// module itself so that their limits are visible here.
+pub fn prepare_module(module: &[u8], max_pages: u32) -> Result<PreparedModule> {
+  if !module.starts_with(WASM_MAGIC) {
+    anyhow::bail!("not a WebAssembly module");
+  }
+  let mut out = Vec::with_capacity(module.len() + 64);
+  out.extend_from_slice(WASM_MAGIC);
+
+  let mut r = Reader {
+    buf: module,
+    pos: WASM_MAGIC.len(),
+  };
+  let mut memory_pages = vec![];
+  let mut exported = false;
+  while !r.is_empty() {
+    let id = r.byte()?;
+    let size = r.u32()? as usize;
+    let payload = r.take(size)?;
+    if !exported && !memory_pages.is_empty() && SECTIONS_AFTER_EXPORT.contains(&id) {
+      write_section(
+        &mut out,
+        SECTION_EXPORT,
+        &export_memories(&[0], &memory_pages)?,
+      );
+      exported = true;
+    }
+    match id {
+      SECTION_IMPORT => {
+        check_imports(payload)?;
+        write_section(&mut out, id, payload);
+      }
+      SECTION_MEMORY => {
+        let (payload, pages) = clamp_memories(payload, max_pages)?;
+        memory_pages = pages;
+        write_section(&mut out, id, &payload);
+      }
+      SECTION_EXPORT => {
+        write_section(&mut out, id, &export_memories(payload, &memory_pages)?);
+        exported = true;
+      }
+      _ => write_section(&mut out, id, payload),
+    }
+  }
+  if !exported && !memory_pages.is_empty() {
+    write_section(
+      &mut out,
+      SECTION_EXPORT,
+      &export_memories(&[0], &memory_pages)?,
+    );
+  }
+  Ok(PreparedModule {
+    bytes: out,
+    memory_pages,
+  })
+}
+
+/// Appends an export for each memory to the export section `payload`.
+fn export_memories(payload: &[u8], memory_pages: &[u32]) -> Result<Vec<u8>> {
+  let mut r = Reader {
+    buf: payload,
+    pos: 0,
+  };
+  let count = r.u32()?;
+  let mut out = vec![];
+  write_u32(&mut out, count + memory_pages.len() as u32);
+  out.extend_from_slice(&payload[r.pos..]);
+  for i in 0..memory_pages.len() {
+    let name = format!("{}{}", MEMORY_EXPORT_PREFIX, i);
+    write_u32(&mut out, name.len() as u32);
+    out.extend_from_slice(name.as_bytes());
+    out.push(EXPORT_MEMORY);
+    write_u32(&mut out, i as u32);
+  }
+  Ok(out)
+}
+
+fn check_imports(payload: &[u8]) -> Result<()> {
+  let mut r = Reader {
+    buf: payload,
+    pos: 0,
+  };
+  for _ in 0..r.u32()? {
+    let module_len = r.u32()? as usize;
+    let module = String::from_utf8_lossy(r.take(module_len)?).into_owned();
+    let field_len = r.u32()? as usize;
+    let field = String::from_utf8_lossy(r.take(field_len)?).into_owned();
+    match r.byte()? {
+      IMPORT_FUNC => {
+        r.u32()?;
+      }
+      IMPORT_GLOBAL => {
+        r.take(2)?;
+      }
+      IMPORT_TABLE | IMPORT_MEMORY => {
+        anyhow::bail!(
+          "import {}.{}: only function and global imports are allowed",
+          module,
+          field
+        );
+      }
+      x => anyhow::bail!("import {}.{}: unknown import kind {}", module, field, x),
+    }
+  }
+  Ok(())
+}
+
+fn clamp_memories(payload: &[u8], max_pages: u32) -> Result<(Vec<u8>, Vec<u32>)> {
+  let mut r = Reader {
+    buf: payload,
+    pos: 0,
+  };
+  let count = r.u32()?;
+  let mut out = vec![];
+  let mut pages = vec![];
+  write_u32(&mut out, count);
+  for i in 0..count {
+    let flags = r.byte()?;
+    if flags & LIMITS_MEMORY64 != 0 {
+      anyhow::bail!("memory {}: 64-bit memories are not supported", i);
+    }
+    let min = r.u32()?;
+    let max = if flags & LIMITS_HAS_MAX != 0 {
+      r.u32()?.min(max_pages)
+    } else {
+      max_pages
+    };
+    if min > max {
+      anyhow::bail!(
+        "memory {}: initial size of {} pages exceeds the limit of {} pages",
+        i,
+        min,
+        max
+      );
+    }
+    out.push((flags & LIMITS_SHARED) | LIMITS_HAS_MAX);
+    write_u32(&mut out, min);
+    write_u32(&mut out, max);
+    pages.push(max);
+  }
+  if !r.is_empty() {
+    anyhow::bail!("trailing bytes in memory section");
+  }
+  Ok((out, pages))
+}
+
+fn write_section(out: &mut Vec<u8>, id: u8, payload: &[u8]) {
+  out.push(id);
+  write_u32(out, payload.len() as u32);
+  out.extend_from_slice(payload);
+}
+
+fn write_u32(out: &mut Vec<u8>, mut x: u32) {
+  loop {
+    let byte = (x & 0x7f) as u8;
+    x >>= 7;
+    if x == 0 {
+      out.push(byte);
+      break;
+    }
+    out.push(byte | 0x80);
+  }
+}
+
+struct Reader<'a> {
+  buf: &'a [u8],
+  pos: usize,
+}
+
+impl<'a> Reader<'a> {
+  fn is_empty(&self) -> bool {
+    self.pos == self.buf.len()
+  }
+
+  fn byte(&mut self) -> Result<u8> {
+    Ok(self.take(1)?[0])
+  }
+
+  fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+    let end = self
+      .pos
+      .checked_add(n)
+      .filter(|x| *x <= self.buf.len())
+      .ok_or_else(|| anyhow::anyhow!("unexpected end of WebAssembly module"))?;
+    let out = &self.buf[self.pos..end];
+    self.pos = end;
+    Ok(out)
+  }
+
+  fn u32(&mut self) -> Result<u32> {
+    let mut out = 0u32;
+    for i in 0..5 {
+      let byte = self.byte()?;
+      out |= ((byte & 0x7f) as u32) << (i * 7);
+      if byte & 0x80 == 0 {
+        return Ok(out);
+      }
+    }
+    anyhow::bail!("malformed LEB128 integer in WebAssembly module")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{prepare_module, WASM_MAX_MEMORY_PAGES};
+  use crate::api::testutil::ApiTester;
+
+  // (module (memory 1) (func (export "add") (param i32 i32) (result i32)
+  //   local.get 0 local.get 1 i32.add))
+  const ADD: &[u8] = &[
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x07, 0x01, 0x60, 0x02, 0x7f, 0x7f, 0x01,
+    0x7f, 0x03, 0x02, 0x01, 0x00, 0x05, 0x03, 0x01, 0x00, 0x01, 0x07, 0x07, 0x01, 0x03, 0x61, 0x64,
+    0x64, 0x00, 0x00, 0x0a, 0x09, 0x01, 0x07, 0x00, 0x20, 0x00, 0x20, 0x01, 0x6a, 0x0b,
+  ];
+
+  // (module (memory (export "memory") 1))
+  const MEMORY: &[u8] = &[
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x05, 0x03, 0x01, 0x00, 0x01, 0x07, 0x0a, 0x01,
+    0x06, 0x6d, 0x65, 0x6d, 0x6f, 0x72, 0x79, 0x02, 0x00,
+  ];
+
+  #[test]
+  fn clamps_and_exports_memories() {
+    let out = prepare_module(ADD, 16).unwrap();
+    assert_eq!(out.memory_pages, [16]);
+    let mut expected = ADD[..21].to_vec();
+    // memory section: one memory, flags=has_max, min=1, max=16
+    expected.extend_from_slice(&[0x05, 0x04, 0x01, 0x01, 0x01, 0x10]);
+    // export section: `add`, then memory 0 under its reserved name
+    expected.extend_from_slice(&[0x07, 0x1d, 0x02]);
+    expected.extend_from_slice(&ADD[29..35]);
+    expected.push(0x13);
+    expected.extend_from_slice(b"__blueboat_memory_0");
+    expected.extend_from_slice(&[0x02, 0x00]);
+    expected.extend_from_slice(&ADD[35..]);
+    assert_eq!(out.bytes, expected);
+
+    // A module without an export section gets one.
+    let out = prepare_module(&MEMORY[..13], 16).unwrap();
+    assert_eq!(&out.bytes[14..17], &[0x07, 0x17, 0x01]);
+
+    assert!(prepare_module(ADD, 0).is_err());
+    assert!(prepare_module(b"not wasm", 16).is_err());
+    assert!(prepare_module(&ADD[..30], 16).is_err());
+  }
+
+  #[test]
+  fn rejects_memory_imports() {
+    // (module (import "env" "mem" (memory 1)))
+    let module: &[u8] = &[
+      0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x02, 0x0c, 0x01, 0x03, 0x65, 0x6e, 0x76,
+      0x03, 0x6d, 0x65, 0x6d, 0x02, 0x00, 0x01,
+    ];
+    let err = prepare_module(module, 16).unwrap_err();
+    assert!(err.to_string().contains("env.mem"));
+  }
+
+  #[test]
+  fn instantiates_and_calls_exports() {
+    let mut tester = ApiTester::new();
+    let out: String = tester.run_script(&format!(
+      r#"
+      const {{ exports }} = Wasm.instantiate(new Uint8Array({:?}));
+      JSON.stringify([exports.add(40, 2), Object.keys(exports), Object.isFrozen(exports)])"#,
+      ADD
+    ));
+    assert_eq!(out, r#"[42,["add"],true]"#);
+  }
+
+  #[test]
+  fn caps_memory_through_wasm_exports() {
+    let mut tester = ApiTester::new();
+    let out: String = tester.run_script(&format!(
+      r#"
+      const bytes = new Uint8Array({:?});
+      const instance = Wasm.instantiate(bytes);
+      const memory = instance.exports.memory;
+      const fails = (f) => {{ try {{ f(); return false; }} catch (e) {{ return e instanceof RangeError; }} }};
+      JSON.stringify([
+        typeof WebAssembly,
+        memory.constructor === Object,
+        Object.getPrototypeOf(instance) === Object.prototype,
+        Object.getOwnPropertyNames(Wasm.compile(bytes)).length,
+        fails(() => memory.grow({cap})),
+      ]);
+      "#,
+      MEMORY,
+      cap = WASM_MAX_MEMORY_PAGES
+    ));
+    assert_eq!(out, r#"["undefined",true,true,0,true]"#);
+  }
+
+  #[test]
+  fn counts_memories_against_isolate_limit() {
+    let mut tester = ApiTester::new();
+    let terminated = tester.run(|scope| {
+      let mut stats = v8::HeapStatistics::default();
+      scope.get_heap_statistics(&mut stats);
+      let per_instance = WASM_MAX_MEMORY_PAGES as u64 * 65536;
+      let count = stats.heap_size_limit() as u64 / per_instance + 2;
+      let source = format!(
+        r#"
+        (() => {{
+          const module = Wasm.compile(new Uint8Array({:?}));
+          const keep = [];
+          for (let i = 0; i < {}; i++) keep.push(Wasm.instantiate(module));
+        }})()"#,
+        MEMORY, count
+      );
+      let scope = &mut v8::TryCatch::new(scope);
+      let source = v8::String::new(scope, &source).unwrap();
+      let script = v8::Script::compile(scope, source, None).unwrap();
+      script.run(scope).is_none() && scope.has_terminated()
+    });
+    assert!(terminated);
+
+    // Memories that are no longer reachable stop counting once collected.
+    let out: u32 = tester.run_script(&format!(
+      "Wasm.instantiate(new Uint8Array({:?})).exports.memory.buffer.byteLength",
+      MEMORY
+    ));
+    assert_eq!(out, 65536);
+  }
+}
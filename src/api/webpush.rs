@@ -0,0 +1,480 @@
+//! Web Push with message encryption (RFC 8291) and VAPID (RFC 8292).
+
+use std::convert::TryFrom;
+
+use anyhow::Result;
+use p256::{
+  ecdsa::{
+    signature::{Signature as _, Signer},
+    Signature, SigningKey,
+  },
+  elliptic_curve::sec1::ToEncodedPoint,
+  PublicKey, SecretKey,
+};
+use rand::{rngs::OsRng, RngCore};
+use ring::aead;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use v8;
+
+use crate::{
+  api::{
+    fetch::{read_body_limited, send, FetchOptions, FetchRedirectMode},
+    util::{v8_invoke_callback, v8_serialize},
+  },
+  exec::Executor,
+  metadata::WebPushMetadata,
+  v8util::{FunctionCallbackArgumentsExt, LocalValueExt},
+};
+
+use super::util::v8_deserialize;
+
+/// Record size written in the content coding header. The whole message is a single record.
+const RECORD_SIZE: u32 = 4096;
+
+/// Salt, record size, key id length and the 65-byte key id.
+const HEADER_LEN: usize = 16 + 4 + 1 + 65;
+
+/// Push services must accept messages of 4096 bytes, which leaves room for this much plaintext
+/// after the header, the padding delimiter and the AEAD tag.
+pub const MAX_WEBPUSH_PAYLOAD: usize = 4096 - HEADER_LEN - 1 - 16;
+
+/// Default time for the push service to keep an undelivered message.
+const DEFAULT_TTL_SECS: u32 = 4 * 7 * 24 * 3600;
+
+/// Push services answer with a short status message at most; longer bodies are not read.
+const MAX_WEBPUSH_RESPONSE_SIZE: usize = 64 * 1024;
+
+/// Validity of the VAPID token. RFC 8292 allows at most 24 hours.
+const VAPID_TOKEN_LIFETIME_SECS: i64 = 12 * 3600;
+
+#[derive(Error, Debug)]
+#[error("invalid web push subscription endpoint: {0}")]
+struct InvalidWebPushEndpoint(String);
+
+#[derive(Error, Debug)]
+#[error("invalid web push subscription key `{0}`")]
+struct InvalidWebPushKey(&'static str);
+
+#[derive(Error, Debug)]
+#[error("invalid vapid key: {0}")]
+struct InvalidVapidKey(String);
+
+#[derive(Error, Debug)]
+#[error("web push payload must be at most 3993 bytes, got {0}")]
+struct WebPushPayloadTooLarge(usize);
+
+/// A VAPID key pair and the contact of the application server.
+pub struct WebPushVapid {
+  key: SigningKey,
+
+  /// Uncompressed public key, sent as `k` in the `Authorization` header.
+  public: Vec<u8>,
+  subject: String,
+}
+
+impl WebPushVapid {
+  pub fn from_metadata(md: &WebPushMetadata) -> Result<Self> {
+    let secret = decode_base64url(&md.vapid_private_key)
+      .ok_or_else(|| InvalidVapidKey("private key must be base64url".into()))?;
+    let key = SigningKey::from_bytes(&secret)
+      .map_err(|_| InvalidVapidKey("invalid p256 secret key".into()))?;
+    if !md.subject.starts_with("mailto:") && !md.subject.starts_with("https:") {
+      return Err(InvalidVapidKey("subject must be a mailto: or https: URL".into()).into());
+    }
+    Ok(Self {
+      public: key
+        .verifying_key()
+        .to_encoded_point(false)
+        .as_bytes()
+        .to_vec(),
+      key,
+      subject: md.subject.clone(),
+    })
+  }
+
+  /// Returns the `Authorization` header for a push service.
+  fn authorization(&self, endpoint: &url::Url, now: i64) -> String {
+    let header = base64::encode_config(r#"{"typ":"JWT","alg":"ES256"}"#, base64::URL_SAFE_NO_PAD);
+    let claims = serde_json::json!({
+      "aud": endpoint.origin().ascii_serialization(),
+      "exp": now + VAPID_TOKEN_LIFETIME_SECS,
+      "sub": self.subject,
+    });
+    let claims = base64::encode_config(claims.to_string(), base64::URL_SAFE_NO_PAD);
+    let signing_input = format!("{}.{}", header, claims);
+    let signature: Signature = self.key.sign(signing_input.as_bytes());
+    format!(
+      "vapid t={}.{}, k={}",
+      signing_input,
+      base64::encode_config(signature.as_bytes(), base64::URL_SAFE_NO_PAD),
+      base64::encode_config(&self.public, base64::URL_SAFE_NO_PAD)
+    )
+  }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct WebPushSubscription {
+  pub endpoint: String,
+  pub keys: WebPushSubscriptionKeys,
+}
+
+/// As returned by `PushSubscription.toJSON()` in the browser.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct WebPushSubscriptionKeys {
+  pub p256dh: String,
+  pub auth: String,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Default)]
+pub struct WebPushOptions {
+  /// How long the push service keeps an undelivered message. Defaults to 4 weeks.
+  #[serde(default)]
+  pub ttl_secs: Option<u32>,
+
+  #[serde(default)]
+  pub urgency: Option<WebPushUrgency>,
+
+  /// Replaces an undelivered message with the same topic.
+  #[serde(default)]
+  pub topic: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Copy, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub enum WebPushUrgency {
+  VeryLow,
+  Low,
+  Normal,
+  High,
+}
+
+impl WebPushUrgency {
+  fn as_str(self) -> &'static str {
+    match self {
+      Self::VeryLow => "very-low",
+      Self::Low => "low",
+      Self::Normal => "normal",
+      Self::High => "high",
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct WebPushResponse {
+  code: u16,
+
+  /// The subscription expired or was unsubscribed, and should be removed.
+  expired: bool,
+
+  /// Response body of the push service when it rejects the message.
+  error: Option<String>,
+}
+
+fn decode_base64url(s: &str) -> Option<Vec<u8>> {
+  base64::decode_config(s.trim_end_matches('='), base64::URL_SAFE_NO_PAD).ok()
+}
+
+/// Push services are reached over HTTPS only.
+fn parse_endpoint(endpoint: &str) -> Result<url::Url> {
+  let url = url::Url::parse(endpoint).map_err(|_| InvalidWebPushEndpoint(endpoint.to_string()))?;
+  if url.scheme() != "https" || url.host_str().is_none() {
+    return Err(InvalidWebPushEndpoint(endpoint.to_string()).into());
+  }
+  Ok(url)
+}
+
+struct OutputLength(usize);
+
+impl ring::hkdf::KeyType for OutputLength {
+  fn len(&self) -> usize {
+    self.0
+  }
+}
+
+fn hkdf_sha256(salt: &[u8], ikm: &[u8], info: &[u8], len: usize) -> Vec<u8> {
+  let prk = ring::hkdf::Salt::new(ring::hkdf::HKDF_SHA256, salt).extract(ikm);
+  let mut out = vec![0u8; len];
+  prk
+    .expand(&[info], OutputLength(len))
+    .and_then(|x| x.fill(&mut out))
+    .expect("hkdf output length is valid");
+  out
+}
+
+/// Encrypts `payload` for a subscription with the `aes128gcm` content coding. `as_secret` and
+/// `salt` must be fresh for every message.
+fn encrypt_payload(
+  keys: &WebPushSubscriptionKeys,
+  payload: &[u8],
+  as_secret: &SecretKey,
+  salt: &[u8; 16],
+) -> Result<Vec<u8>> {
+  if payload.len() > MAX_WEBPUSH_PAYLOAD {
+    return Err(WebPushPayloadTooLarge(payload.len()).into());
+  }
+  let ua_public = decode_base64url(&keys.p256dh).ok_or(InvalidWebPushKey("p256dh"))?;
+  let ua_key = PublicKey::from_sec1_bytes(&ua_public).map_err(|_| InvalidWebPushKey("p256dh"))?;
+  let ua_public = ua_key.to_encoded_point(false);
+  let auth = decode_base64url(&keys.auth)
+    .filter(|x| x.len() == 16)
+    .ok_or(InvalidWebPushKey("auth"))?;
+  let as_public = as_secret.public_key().to_encoded_point(false);
+
+  let shared = p256::ecdh::diffie_hellman(as_secret.to_nonzero_scalar(), ua_key.as_affine());
+  let mut key_info = b"WebPush: info\0".to_vec();
+  key_info.extend_from_slice(ua_public.as_bytes());
+  key_info.extend_from_slice(as_public.as_bytes());
+  let ikm = hkdf_sha256(&auth, shared.raw_secret_bytes(), &key_info, 32);
+  let cek = hkdf_sha256(salt, &ikm, b"Content-Encoding: aes128gcm\0", 16);
+  let nonce = hkdf_sha256(salt, &ikm, b"Content-Encoding: nonce\0", 12);
+
+  let key = aead::LessSafeKey::new(
+    aead::UnboundKey::new(&aead::AES_128_GCM, &cek).expect("cek has the key length"),
+  );
+  let mut record = payload.to_vec();
+  // Padding delimiter of the last record.
+  record.push(2);
+  key
+    .seal_in_place_append_tag(
+      aead::Nonce::try_assume_unique_for_key(&nonce).expect("nonce has the nonce length"),
+      aead::Aad::empty(),
+      &mut record,
+    )
+    .map_err(|_| anyhow::anyhow!("web push encryption failed"))?;
+
+  let mut out = Vec::with_capacity(HEADER_LEN + record.len());
+  out.extend_from_slice(salt);
+  out.extend_from_slice(&RECORD_SIZE.to_be_bytes());
+  out.push(as_public.as_bytes().len() as u8);
+  out.extend_from_slice(as_public.as_bytes());
+  out.extend_from_slice(&record);
+  Ok(out)
+}
+
+fn build_webpush_request(
+  vapid: &WebPushVapid,
+  subscription: &WebPushSubscription,
+  payload: &[u8],
+  opts: &WebPushOptions,
+) -> Result<reqwest::Request> {
+  let endpoint = parse_endpoint(&subscription.endpoint)?;
+  let mut salt = [0u8; 16];
+  OsRng.fill_bytes(&mut salt);
+  let body = encrypt_payload(
+    &subscription.keys,
+    payload,
+    &SecretKey::random(&mut OsRng),
+    &salt,
+  )?;
+
+  let mut req = reqwest::Request::new(reqwest::Method::POST, endpoint.clone());
+  let headers = req.headers_mut();
+  headers.insert(
+    "authorization",
+    vapid
+      .authorization(&endpoint, chrono::Utc::now().timestamp())
+      .parse()?,
+  );
+  headers.insert("content-encoding", "aes128gcm".parse()?);
+  headers.insert("content-type", "application/octet-stream".parse()?);
+  headers.insert(
+    "ttl",
+    opts
+      .ttl_secs
+      .unwrap_or(DEFAULT_TTL_SECS)
+      .to_string()
+      .parse()?,
+  );
+  if let Some(x) = opts.urgency {
+    headers.insert("urgency", x.as_str().parse()?);
+  }
+  if let Some(x) = &opts.topic {
+    headers.insert("topic", x.parse()?);
+  }
+  *req.body_mut() = Some(body.into());
+  Ok(req)
+}
+
+fn webpush_response(code: u16, body: &[u8]) -> WebPushResponse {
+  let ok = (200..300).contains(&code);
+  WebPushResponse {
+    code,
+    // 404 is used by some push services for unknown subscriptions, 410 by all for expired ones.
+    expired: code == 404 || code == 410,
+    error: if ok {
+      None
+    } else {
+      Some(String::from_utf8_lossy(body).into_owned())
+    },
+  }
+}
+
+pub fn api_webpush_send(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  _retval: v8::ReturnValue,
+) -> Result<()> {
+  #[derive(Error, Debug)]
+  #[error("webpush key not found: {0}")]
+  struct NotFound(String);
+
+  let key = v8::Local::<v8::String>::try_from(args.get(1))?.to_rust_string_lossy(scope);
+  let exec = Executor::try_current_result()?;
+  let ctx = exec.upgrade().unwrap().ctx;
+  let vapid = match ctx.webpush.get(&key) {
+    Some(x) => x,
+    None => return Err(NotFound(key).into()),
+  };
+
+  let subscription: WebPushSubscription = v8_deserialize(scope, args.get(2))?;
+  let payload = unsafe { args.get(3).read_bytes_assume_noalias(scope)? };
+  let opts: WebPushOptions = if args.get(4).is_null_or_undefined() {
+    Default::default()
+  } else {
+    v8_deserialize(scope, args.get(4))?
+  };
+  let req = build_webpush_request(vapid, &subscription, &payload, &opts)?;
+  let callback = v8::Global::new(scope, args.load_function_at(5)?);
+  // The endpoint comes from the end user's browser, so it gets the same egress checks as `fetch`.
+  let client = ctx.fetch_client(None)?;
+  let exec_2 = exec.clone();
+  Executor::spawn(&exec_2, async move {
+    let res = async {
+      let opts = FetchOptions {
+        redirect: FetchRedirectMode::Error,
+        via_proxy: ctx.metadata.fetch.proxy.is_some(),
+        ..Default::default()
+      };
+      let (res, _) = send(&client, &ctx.egress_policy, req, &opts).await?;
+      let code = res.status().as_u16();
+      let body = read_body_limited(res, MAX_WEBPUSH_RESPONSE_SIZE).await?;
+      Ok(webpush_response(code, &body))
+    }
+    .await;
+    Executor::enter(&exec, |scope| {
+      let res = res.and_then(|x| v8_serialize(scope, &x));
+      v8_invoke_callback("webpush_send", scope, res, &callback);
+    });
+  });
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use p256::{
+    ecdsa::{
+      signature::{Signature as _, Verifier},
+      Signature, VerifyingKey,
+    },
+    SecretKey,
+  };
+
+  use super::{
+    decode_base64url, encrypt_payload, parse_endpoint, webpush_response, WebPushSubscriptionKeys,
+    WebPushVapid, MAX_WEBPUSH_PAYLOAD,
+  };
+  use crate::metadata::WebPushMetadata;
+
+  // RFC 8291, appendix A.
+  fn rfc8291_keys() -> WebPushSubscriptionKeys {
+    WebPushSubscriptionKeys {
+      p256dh:
+        "BCVxsr7N_eNgVRqvHtD0zTZsEc6-VV-JvLexhqUzORcxaOzi6-AYWXvTBHm4bjyPjs7Vd8pZGH6SRpkNtoIAiw4"
+          .into(),
+      auth: "BTBZMqHH6r4Tts7J_aSIgg".into(),
+    }
+  }
+
+  fn rfc8291_as_secret() -> SecretKey {
+    SecretKey::from_be_bytes(
+      &decode_base64url("yfWPiYE-n46HLnH0KqZOF1fJJU3MYrct3AELtAQ-oRw").unwrap(),
+    )
+    .unwrap()
+  }
+
+  #[test]
+  fn rfc8291_vector() {
+    let salt = decode_base64url("DGv6ra1nlYgDCS1FRnbzlw").unwrap();
+    let out = encrypt_payload(
+      &rfc8291_keys(),
+      b"When I grow up, I want to be a watermelon",
+      &rfc8291_as_secret(),
+      salt.as_slice().try_into().unwrap(),
+    )
+    .unwrap();
+    assert_eq!(
+      base64::encode_config(&out, base64::URL_SAFE_NO_PAD),
+      "DGv6ra1nlYgDCS1FRnbzlwAAEABBBP4z9KsN6nGRTbVYI_c7VJSPQTBtkgcy27mlmlMoZIIgDll6e3vCYLocInmYWAmS\
+       6TlzAC8wEqKK6PBru3jl7A_yl95bQpu6cVPTpK4Mqgkf1CXztLVBSt2Ks3oZwbuwXPXLWyouBWLVWGNWQexSgSxsj_Q\
+       ulcy4a-fN"
+    );
+  }
+
+  #[test]
+  fn rejects_bad_input() {
+    let salt = [0u8; 16];
+    let secret = rfc8291_as_secret();
+    assert!(encrypt_payload(&rfc8291_keys(), &[0u8; MAX_WEBPUSH_PAYLOAD], &secret, &salt).is_ok());
+    assert!(encrypt_payload(
+      &rfc8291_keys(),
+      &[0u8; MAX_WEBPUSH_PAYLOAD + 1],
+      &secret,
+      &salt
+    )
+    .is_err());
+
+    let mut keys = rfc8291_keys();
+    keys.auth = "AAAA".into();
+    assert!(encrypt_payload(&keys, b"x", &secret, &salt).is_err());
+
+    assert!(parse_endpoint("https://fcm.googleapis.com/fcm/send/abc").is_ok());
+    assert!(parse_endpoint("http://fcm.googleapis.com/fcm/send/abc").is_err());
+    assert!(parse_endpoint("not a url").is_err());
+  }
+
+  #[test]
+  fn vapid_authorization() {
+    let vapid = WebPushVapid::from_metadata(&WebPushMetadata {
+      vapid_private_key: "yfWPiYE-n46HLnH0KqZOF1fJJU3MYrct3AELtAQ-oRw".into(),
+      subject: "mailto:admin@example.com".into(),
+    })
+    .unwrap();
+    let endpoint =
+      url::Url::parse("https://push.example.net/push/JzLQ3raZJfFBR0aqvOMsLrt54w4rJUsV").unwrap();
+    let auth = vapid.authorization(&endpoint, 1_500_000_000);
+    let (token, k) = auth
+      .strip_prefix("vapid t=")
+      .unwrap()
+      .split_once(", k=")
+      .unwrap();
+    assert_eq!(
+      k,
+      "BP4z9KsN6nGRTbVYI_c7VJSPQTBtkgcy27mlmlMoZIIgDll6e3vCYLocInmYWAmS6TlzAC8wEqKK6PBru3jl7A8"
+    );
+
+    let (signing_input, signature) = token.rsplit_once('.').unwrap();
+    let claims: serde_json::Value =
+      serde_json::from_slice(&decode_base64url(signing_input.split_once('.').unwrap().1).unwrap())
+        .unwrap();
+    assert_eq!(
+      claims,
+      serde_json::json!({
+        "aud": "https://push.example.net",
+        "exp": 1_500_043_200,
+        "sub": "mailto:admin@example.com",
+      })
+    );
+    let public = VerifyingKey::from_sec1_bytes(&decode_base64url(k).unwrap()).unwrap();
+    let signature = Signature::from_bytes(&decode_base64url(signature).unwrap()).unwrap();
+    assert!(public.verify(signing_input.as_bytes(), &signature).is_ok());
+  }
+
+  #[test]
+  fn expired_subscription() {
+    assert!(!webpush_response(201, b"").expired);
+    assert!(webpush_response(410, b"").expired);
+    assert_eq!(webpush_response(400, b"bad").error.as_deref(), Some("bad"));
+  }
+}
@@ -0,0 +1,604 @@
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+
+use anyhow::Result;
+use futures::{
+  stream::{SplitSink, SplitStream},
+  SinkExt, StreamExt,
+};
+use percent_encoding::percent_decode_str;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::{
+  io::{AsyncReadExt, AsyncWriteExt},
+  net::TcpStream,
+  sync::mpsc::{self, error::TrySendError},
+};
+use tokio_tungstenite::{
+  client_async_tls,
+  tungstenite::{
+    client::IntoClientRequest,
+    http::{
+      header::{HeaderName, CONNECTION, HOST, SEC_WEBSOCKET_PROTOCOL, UPGRADE},
+      HeaderValue,
+    },
+    protocol::{frame::coding::CloseCode, CloseFrame},
+    Message,
+  },
+  MaybeTlsStream, WebSocketStream,
+};
+use url::Host;
+use v8;
+
+use crate::{
+  api::{
+    fetch::{display_proxy, parse_proxy_url, InvalidProxy},
+    util::{mk_v8_string, v8_deserialize, v8_invoke_callback, v8_serialize},
+  },
+  egress::{BlockedDestination, EgressPolicy},
+  exec::Executor,
+  v8util::{create_uint8array_from_bytes, FunctionCallbackArgumentsExt, LocalValueExt},
+};
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Maximum number of open WebSocket connections in a request.
+const MAX_WEBSOCKETS: usize = 16;
+
+/// Maximum number of outgoing messages queued on a connection.
+const SEND_QUEUE_SIZE: usize = 64;
+
+/// Maximum size of the response headers of a proxy to a `CONNECT` request.
+const MAX_PROXY_RESPONSE_SIZE: usize = 8192;
+
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An open connection owned by the executor. Dropping it closes the connection.
+pub struct WebSocketConn {
+  tx: mpsc::Sender<Message>,
+
+  /// Taken by `websocket_on_message`.
+  rx: Option<SplitStream<WsStream>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct WebSocketConnectOptions {
+  #[serde(default)]
+  pub headers: HashMap<String, String>,
+
+  /// Sent as `Sec-WebSocket-Protocol`.
+  #[serde(default)]
+  pub protocols: Vec<String>,
+
+  /// Deadline for connecting and completing the handshake. Defaults to 10 seconds.
+  #[serde(default)]
+  pub timeout_ms: Option<u64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WebSocketConnectResponse {
+  handle: u32,
+
+  /// Subprotocol selected by the server.
+  protocol: Option<String>,
+}
+
+#[derive(Error, Debug)]
+#[error("invalid websocket url")]
+struct InvalidWebSocketUrl;
+
+#[derive(Error, Debug)]
+#[error("header `{0}` cannot be set on a websocket request")]
+struct DisallowedHeader(String);
+
+#[derive(Error, Debug)]
+#[error("proxy {0} refused to tunnel the websocket: {1}")]
+pub struct WebSocketProxyRefused(pub String, pub String);
+
+#[derive(Error, Debug)]
+#[error("websocket connect timed out after {0} ms")]
+pub struct WebSocketConnectTimeout(pub u64);
+
+#[derive(Error, Debug)]
+#[error("too many open websockets")]
+struct TooManyWebSockets;
+
+#[derive(Error, Debug)]
+#[error("websocket does not exist or is closed")]
+struct WebSocketNotFound;
+
+#[derive(Error, Debug)]
+#[error("websocket already has a message handler")]
+struct WebSocketHandlerExists;
+
+#[derive(Error, Debug)]
+#[error("websocket send queue is full")]
+struct WebSocketSendQueueFull;
+
+#[derive(Error, Debug)]
+#[error("invalid websocket close code: {0}")]
+struct InvalidCloseCode(u16);
+
+/// Connects to the first address of `url` allowed by `policy`.
+async fn connect_tcp(url: &Url, policy: &EgressPolicy) -> Result<TcpStream> {
+  let port = url.port_or_known_default().ok_or(InvalidWebSocketUrl)?;
+  let addrs: Vec<SocketAddr> = match url.host() {
+    Some(Host::Domain(x)) => tokio::net::lookup_host((x, port)).await?.collect(),
+    Some(Host::Ipv4(x)) => vec![SocketAddr::new(x.into(), port)],
+    Some(Host::Ipv6(x)) => vec![SocketAddr::new(x.into(), port)],
+    None => return Err(InvalidWebSocketUrl.into()),
+  };
+  let addrs: Vec<SocketAddr> = addrs
+    .into_iter()
+    .filter(|x| !policy.is_blocked(x.ip()))
+    .collect();
+  if addrs.is_empty() {
+    return Err(BlockedDestination(url.host_str().unwrap_or_default().to_string()).into());
+  }
+  let mut last_error = None;
+  for addr in addrs {
+    match TcpStream::connect(addr).await {
+      Ok(x) => return Ok(x),
+      Err(e) => last_error = Some(e),
+    }
+  }
+  Err(last_error.unwrap().into())
+}
+
+/// Opens a tunnel to `url` through an HTTP proxy with `CONNECT`. As with `fetch`, `policy` applies
+/// to the proxy address and the proxy resolves the destination by itself.
+async fn connect_via_proxy(url: &Url, proxy: &str, policy: &EgressPolicy) -> Result<TcpStream> {
+  let proxy_url = parse_proxy_url(proxy)?;
+  if proxy_url.scheme() != "http" {
+    return Err(
+      InvalidProxy(format!(
+        "websockets only support `http` proxies, got `{}`",
+        proxy_url.scheme()
+      ))
+      .into(),
+    );
+  }
+  let mut stream = connect_tcp(&proxy_url, policy).await?;
+
+  let authority = format!(
+    "{}:{}",
+    url.host_str().ok_or(InvalidWebSocketUrl)?,
+    url.port_or_known_default().ok_or(InvalidWebSocketUrl)?
+  );
+  let mut req = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", authority);
+  if !proxy_url.username().is_empty() {
+    let credentials = format!(
+      "{}:{}",
+      percent_decode_str(proxy_url.username()).decode_utf8_lossy(),
+      percent_decode_str(proxy_url.password().unwrap_or_default()).decode_utf8_lossy()
+    );
+    req.push_str(&format!(
+      "Proxy-Authorization: Basic {}\r\n",
+      base64::encode(credentials)
+    ));
+  }
+  req.push_str("\r\n");
+  stream.write_all(req.as_bytes()).await?;
+
+  // Read byte by byte, so that nothing after the response headers is consumed.
+  let mut res = Vec::new();
+  while !res.ends_with(b"\r\n\r\n") {
+    if res.len() >= MAX_PROXY_RESPONSE_SIZE {
+      return Err(WebSocketProxyRefused(display_proxy(proxy), "response too large".into()).into());
+    }
+    let b = stream.read_u8().await?;
+    res.push(b);
+  }
+  let status_line = String::from_utf8_lossy(&res);
+  let status_line = status_line.lines().next().unwrap_or_default();
+  match status_line.split_whitespace().nth(1) {
+    Some(x) if x.starts_with('2') => Ok(stream),
+    _ => Err(WebSocketProxyRefused(display_proxy(proxy), status_line.to_string()).into()),
+  }
+}
+
+/// Opens a WebSocket connection, through `proxy` if set. Destinations are checked against the
+/// egress policy in the same way as `fetch`.
+pub async fn connect(
+  url: &str,
+  opts: &WebSocketConnectOptions,
+  proxy: Option<&str>,
+  policy: &EgressPolicy,
+) -> Result<(WsStream, Option<String>)> {
+  let url = Url::parse(url).map_err(|_| InvalidWebSocketUrl)?;
+  if !matches!(url.scheme(), "ws" | "wss") {
+    return Err(InvalidWebSocketUrl.into());
+  }
+
+  let mut req = url.as_str().into_client_request()?;
+  for (k, v) in &opts.headers {
+    let name = HeaderName::from_bytes(k.as_bytes())?;
+    if name == HOST
+      || name == UPGRADE
+      || name == CONNECTION
+      || name.as_str().starts_with("sec-websocket-")
+    {
+      return Err(DisallowedHeader(name.to_string()).into());
+    }
+    req.headers_mut().insert(name, HeaderValue::from_str(v)?);
+  }
+  if !opts.protocols.is_empty() {
+    req.headers_mut().insert(
+      SEC_WEBSOCKET_PROTOCOL,
+      HeaderValue::from_str(&opts.protocols.join(", "))?,
+    );
+  }
+
+  let timeout = opts
+    .timeout_ms
+    .map(Duration::from_millis)
+    .unwrap_or(DEFAULT_CONNECT_TIMEOUT);
+  let (ws, res) = tokio::time::timeout(timeout, async {
+    let stream = match proxy {
+      Some(proxy) => connect_via_proxy(&url, proxy, policy).await?,
+      None => connect_tcp(&url, policy).await?,
+    };
+    Ok::<_, anyhow::Error>(client_async_tls(req, stream).await?)
+  })
+  .await
+  .map_err(|_| WebSocketConnectTimeout(timeout.as_millis() as u64))??;
+  let protocol = res
+    .headers()
+    .get(SEC_WEBSOCKET_PROTOCOL)
+    .and_then(|x| x.to_str().ok())
+    .map(|x| x.to_string());
+  Ok((ws, protocol))
+}
+
+/// Forwards queued messages to the connection. When the sender is dropped, either by
+/// `websocket_close` or because the request completed, the connection is closed with a close
+/// frame.
+async fn run_writer(mut sink: SplitSink<WsStream, Message>, mut rx: mpsc::Receiver<Message>) {
+  while let Some(msg) = rx.recv().await {
+    let closing = matches!(msg, Message::Close(_));
+    if sink.send(msg).await.is_err() || closing {
+      break;
+    }
+  }
+  let _ = tokio::time::timeout(CLOSE_TIMEOUT, sink.close()).await;
+}
+
+pub fn api_websocket_connect(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  _retval: v8::ReturnValue,
+) -> Result<()> {
+  let url = args.get(1).read_string(scope)?;
+  let opts = args.get(2);
+  let opts: WebSocketConnectOptions = if opts.is_null_or_undefined() {
+    Default::default()
+  } else {
+    v8_deserialize(scope, opts)?
+  };
+  let callback = v8::Global::new(scope, args.load_function_at(3)?);
+  let exec = Executor::try_current_result()?;
+  let ctx = {
+    let e = exec.upgrade().unwrap();
+    if e.websockets.borrow().len() >= MAX_WEBSOCKETS {
+      return Err(TooManyWebSockets.into());
+    }
+    e.ctx
+  };
+
+  Executor::spawn(&exec.clone(), async move {
+    let res = connect(
+      &url,
+      &opts,
+      ctx.metadata.fetch.proxy.as_deref(),
+      &ctx.egress_policy,
+    )
+    .await;
+    Executor::enter(&exec, |scope| {
+      let res = res.and_then(|(ws, protocol)| {
+        let e = exec.upgrade().unwrap();
+        let mut conns = e.websockets.borrow_mut();
+        if conns.len() >= MAX_WEBSOCKETS {
+          return Err(TooManyWebSockets.into());
+        }
+        let (sink, stream) = ws.split();
+        let (tx, rx) = mpsc::channel(SEND_QUEUE_SIZE);
+
+        // Not spawned on the executor, so that the close frame is still sent after the request
+        // completes.
+        tokio::task::spawn_local(run_writer(sink, rx));
+
+        let handle = e.allocate_handle();
+        conns.insert(
+          handle,
+          WebSocketConn {
+            tx,
+            rx: Some(stream),
+          },
+        );
+        v8_serialize(scope, &WebSocketConnectResponse { handle, protocol })
+      });
+      v8_invoke_callback("websocket_connect", scope, res, &callback);
+    });
+  });
+  Ok(())
+}
+
+/// Queues a text frame for strings, and a binary frame for everything else.
+pub fn api_websocket_send(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  _retval: v8::ReturnValue,
+) -> Result<()> {
+  let handle: u32 = v8_deserialize(scope, args.get(1))?;
+  let data = args.get(2);
+  let msg = if data.is_string() {
+    Message::Text(data.read_string(scope)?)
+  } else {
+    Message::Binary(unsafe { data.read_bytes_assume_noalias(scope)? }.to_vec())
+  };
+
+  let e = Executor::try_current_result()?.upgrade().unwrap();
+  let conns = e.websockets.borrow();
+  let conn = conns.get(&handle).ok_or(WebSocketNotFound)?;
+  conn.tx.try_send(msg).map_err(|e| -> anyhow::Error {
+    match e {
+      TrySendError::Full(_) => WebSocketSendQueueFull.into(),
+      TrySendError::Closed(_) => WebSocketNotFound.into(),
+    }
+  })?;
+  Ok(())
+}
+
+/// Delivers incoming messages to the callback: strings for text frames, `Uint8Array`s for binary
+/// frames, and `null` once the connection is closed.
+pub fn api_websocket_on_message(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  _retval: v8::ReturnValue,
+) -> Result<()> {
+  let handle: u32 = v8_deserialize(scope, args.get(1))?;
+  let callback = v8::Global::new(scope, args.load_function_at(2)?);
+  let exec = Executor::try_current_result()?;
+  let mut stream = {
+    let e = exec.upgrade().unwrap();
+    let mut conns = e.websockets.borrow_mut();
+    let conn = conns.get_mut(&handle).ok_or(WebSocketNotFound)?;
+    conn.rx.take().ok_or(WebSocketHandlerExists)?
+  };
+
+  Executor::spawn(&exec.clone(), async move {
+    let end: Result<()> = loop {
+      let msg = match stream.next().await {
+        Some(Ok(x)) => x,
+        Some(Err(e)) => break Err(e.into()),
+        None => break Ok(()),
+      };
+      match msg {
+        Message::Text(x) => {
+          Executor::enter(&exec, |scope| {
+            let res = mk_v8_string(scope, &x).map(|x| x.into());
+            v8_invoke_callback("websocket_on_message", scope, res, &callback);
+          });
+        }
+        Message::Binary(x) => {
+          Executor::enter(&exec, |scope| {
+            let x = create_uint8array_from_bytes(scope, &x);
+            v8_invoke_callback("websocket_on_message", scope, Ok(x.into()), &callback);
+          });
+        }
+        Message::Close(_) => break Ok(()),
+        _ => {}
+      }
+    };
+    if let Some(e) = exec.upgrade() {
+      e.websockets.borrow_mut().remove(&handle);
+    }
+    Executor::enter(&exec, |scope| {
+      let res = end.map(|()| v8::null(scope).into());
+      v8_invoke_callback("websocket_on_message", scope, res, &callback);
+    });
+  });
+  Ok(())
+}
+
+/// Closes the connection. `code` must be 1000 or in the application range 3000-4999.
+pub fn api_websocket_close(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  _retval: v8::ReturnValue,
+) -> Result<()> {
+  let handle: u32 = v8_deserialize(scope, args.get(1))?;
+  let code = args.get(2);
+  let frame = if code.is_null_or_undefined() {
+    None
+  } else {
+    let code: u16 = v8_deserialize(scope, code)?;
+    if code != 1000 && !(3000..5000).contains(&code) {
+      return Err(InvalidCloseCode(code).into());
+    }
+    let reason = args.get(3);
+    let reason = if reason.is_null_or_undefined() {
+      String::new()
+    } else {
+      reason.read_string(scope)?
+    };
+    Some(CloseFrame {
+      code: CloseCode::from(code),
+      reason: reason.into(),
+    })
+  };
+
+  let e = Executor::try_current_result()?.upgrade().unwrap();
+  let conn = e
+    .websockets
+    .borrow_mut()
+    .remove(&handle)
+    .ok_or(WebSocketNotFound)?;
+  // If the queue is full, the writer still sends a default close frame once the sender is dropped.
+  let _ = conn.tx.try_send(Message::Close(frame));
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::net::SocketAddr;
+
+  use futures::{SinkExt, StreamExt};
+  use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+  };
+  use tokio_tungstenite::tungstenite::Message;
+
+  use super::{connect, run_writer, WebSocketConnectOptions};
+  use crate::{
+    api::fetch::InvalidProxy,
+    egress::{BlockedDestination, EgressPolicy},
+    metadata::FetchMetadata,
+  };
+
+  /// Echoes messages back, and reports the frames it received on `seen`.
+  async fn spawn_echo_server(seen: mpsc::UnboundedSender<Message>) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+      let (stream, _) = listener.accept().await.unwrap();
+      let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+      while let Some(Ok(msg)) = ws.next().await {
+        let _ = seen.send(msg.clone());
+        if msg.is_text() || msg.is_binary() {
+          ws.send(msg).await.unwrap();
+        }
+      }
+    });
+    addr
+  }
+
+  #[tokio::test]
+  async fn echo_and_close() {
+    let (seen_tx, mut seen_rx) = mpsc::unbounded_channel();
+    let addr = spawn_echo_server(seen_tx).await;
+    let (ws, _) = connect(
+      &format!("ws://{}/", addr),
+      &WebSocketConnectOptions::default(),
+      None,
+      &EgressPolicy::unrestricted(),
+    )
+    .await
+    .unwrap();
+    let (sink, mut stream) = ws.split();
+    let (tx, rx) = mpsc::channel(4);
+    tokio::spawn(run_writer(sink, rx));
+
+    tx.send(Message::Text("hello".into())).await.unwrap();
+    assert_eq!(
+      stream.next().await.unwrap().unwrap(),
+      Message::Text("hello".into())
+    );
+    tx.send(Message::Binary(vec![1, 2, 3])).await.unwrap();
+    assert_eq!(
+      stream.next().await.unwrap().unwrap(),
+      Message::Binary(vec![1, 2, 3])
+    );
+
+    // Dropping the sender closes the connection cleanly.
+    drop(tx);
+    while !matches!(seen_rx.recv().await.unwrap(), Message::Close(_)) {}
+  }
+
+  /// Tunnels a single `CONNECT` request, and reports the request line on `seen`.
+  async fn spawn_connect_proxy(seen: mpsc::UnboundedSender<String>) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+      let (mut client, _) = listener.accept().await.unwrap();
+      let mut req = Vec::new();
+      while !req.ends_with(b"\r\n\r\n") {
+        req.push(client.read_u8().await.unwrap());
+      }
+      let req = String::from_utf8(req).unwrap();
+      let _ = seen.send(req.clone());
+      let target = req.split_whitespace().nth(1).unwrap().to_string();
+      let mut upstream = TcpStream::connect(target).await.unwrap();
+      client
+        .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+        .await
+        .unwrap();
+      let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+    });
+    addr
+  }
+
+  #[tokio::test]
+  async fn connect_through_proxy() {
+    let (seen_tx, _seen_rx) = mpsc::unbounded_channel();
+    let addr = spawn_echo_server(seen_tx).await;
+    let (proxy_tx, mut proxy_rx) = mpsc::unbounded_channel();
+    let proxy = spawn_connect_proxy(proxy_tx).await;
+    let (mut ws, _) = connect(
+      &format!("ws://{}/", addr),
+      &WebSocketConnectOptions::default(),
+      Some(&format!("http://user:pass@{}", proxy)),
+      &EgressPolicy::unrestricted(),
+    )
+    .await
+    .unwrap();
+    let req = proxy_rx.recv().await.unwrap();
+    assert!(req.starts_with(&format!("CONNECT {} HTTP/1.1\r\n", addr)));
+    assert!(req.contains("Proxy-Authorization: Basic dXNlcjpwYXNz\r\n"));
+
+    ws.send(Message::Text("hello".into())).await.unwrap();
+    assert_eq!(
+      ws.next().await.unwrap().unwrap(),
+      Message::Text("hello".into())
+    );
+  }
+
+  #[tokio::test]
+  async fn rejects_unsupported_proxy() {
+    let err = connect(
+      "ws://127.0.0.1:1/",
+      &WebSocketConnectOptions::default(),
+      Some("socks5://127.0.0.1:1080"),
+      &EgressPolicy::unrestricted(),
+    )
+    .await
+    .unwrap_err();
+    assert!(err.is::<InvalidProxy>());
+  }
+
+  #[tokio::test]
+  async fn egress_policy() {
+    let (seen_tx, _seen_rx) = mpsc::unbounded_channel();
+    let addr = spawn_echo_server(seen_tx).await;
+    let policy = EgressPolicy::from_metadata(&FetchMetadata::default()).unwrap();
+    let err = connect(
+      &format!("ws://{}/", addr),
+      &WebSocketConnectOptions::default(),
+      None,
+      &policy,
+    )
+    .await
+    .unwrap_err();
+    assert!(err.is::<BlockedDestination>());
+  }
+
+  #[tokio::test]
+  async fn rejects_bad_requests() {
+    let policy = EgressPolicy::unrestricted();
+    let opts = WebSocketConnectOptions::default();
+    assert!(connect("http://example.com/", &opts, None, &policy)
+      .await
+      .is_err());
+
+    let mut opts = WebSocketConnectOptions::default();
+    opts.headers.insert("Sec-WebSocket-Key".into(), "x".into());
+    assert!(connect("ws://127.0.0.1:1/", &opts, None, &policy)
+      .await
+      .is_err());
+  }
+}
@@ -1,16 +1,106 @@
-use std::time::{Duration, SystemTime};
+use std::{
+  collections::HashMap,
+  time::{Duration, SystemTime},
+};
 
-use crate::v8util::create_uint8array_from_bytes;
+use crate::{generational_cache::GenerationalCache, v8util::create_uint8array_from_bytes};
 use anyhow::Result;
-use mysql_async::{Pool, Value};
+use mysql_async::{Pool, Statement, Value};
 use num_derive::FromPrimitive;
+use parking_lot::Mutex;
 use std::convert::TryFrom;
 use thiserror::Error;
 use time::{Date, PrimitiveDateTime, Time};
 use v8;
 
+/// Number of prepared statements cached per connection if the app doesn't set it.
+pub const DEFAULT_STMT_CACHE_SIZE: usize = 32;
+
 pub struct AppMysql {
   pool: Pool,
+  stmts: StmtCache,
+  replica: Option<(Pool, StmtCache)>,
+}
+
+/// Prepared statements of the connections of a pool, keyed by SQL text.
+///
+/// The pool resets a connection when it is returned, which deallocates its statements on the
+/// server, and statements prepared later on the connection may get the same ids. So every
+/// checkout of a connection starts a new generation of its cache.
+pub struct StmtCache {
+  capacity: usize,
+  max_conns: usize,
+  inner: Mutex<StmtCacheInner>,
+}
+
+#[derive(Default)]
+struct StmtCacheInner {
+  checkouts: u64,
+  conns: HashMap<u32, ConnStmts>,
+}
+
+struct ConnStmts {
+  last_checkout: u64,
+  stmts: GenerationalCache<String, Statement>,
+}
+
+impl StmtCache {
+  /// A cache of `capacity` statements for each of up to `max_conns` connections.
+  pub fn new(capacity: usize, max_conns: usize) -> Self {
+    Self {
+      capacity,
+      max_conns,
+      inner: Mutex::new(StmtCacheInner::default()),
+    }
+  }
+
+  /// Invalidates the statements of connection `conn_id`, which was just taken from the pool.
+  pub fn checkout(&self, conn_id: u32) {
+    if self.capacity == 0 {
+      return;
+    }
+    let mut inner = self.inner.lock();
+    inner.checkouts += 1;
+    let checkout = inner.checkouts;
+    let capacity = self.capacity;
+    let conn = inner.conns.entry(conn_id).or_insert_with(|| ConnStmts {
+      last_checkout: 0,
+      stmts: GenerationalCache::new(capacity),
+    });
+    conn.last_checkout = checkout;
+    conn.stmts.advance();
+
+    // Connections that the pool has closed are never checked out again.
+    if inner.conns.len() > self.max_conns {
+      let idle = inner
+        .conns
+        .iter()
+        .min_by_key(|(_, x)| x.last_checkout)
+        .map(|(k, _)| *k)
+        .unwrap();
+      inner.conns.remove(&idle);
+    }
+  }
+
+  pub fn get(&self, conn_id: u32, sql: &str) -> Option<Statement> {
+    self
+      .inner
+      .lock()
+      .conns
+      .get_mut(&conn_id)?
+      .stmts
+      .get(sql)
+      .cloned()
+  }
+
+  /// Caches `stmt`, prepared on connection `conn_id` in its current checkout. Returns a statement
+  /// that the caller should close: one that was evicted, or `stmt` itself if it is not cached.
+  pub fn insert(&self, conn_id: u32, sql: &str, stmt: Statement) -> Option<Statement> {
+    match self.inner.lock().conns.get_mut(&conn_id) {
+      Some(x) => x.stmts.insert(sql.to_string(), stmt),
+      None => Some(stmt),
+    }
+  }
 }
 
 #[derive(FromPrimitive, Debug, Copy, Clone, Eq, PartialEq)]
@@ -29,14 +119,32 @@ pub enum ValueSpec {
 struct CastError(&'static str);
 
 impl AppMysql {
-  pub fn new(pool: Pool) -> Self {
-    Self { pool }
+  pub fn new((pool, stmts): (Pool, StmtCache), replica: Option<(Pool, StmtCache)>) -> Self {
+    Self {
+      pool,
+      stmts,
+      replica,
+    }
   }
 
   pub fn pool(&self) -> &Pool {
     &self.pool
   }
 
+  pub fn stmt_cache(&self) -> &StmtCache {
+    &self.stmts
+  }
+
+  /// The pool for read-only queries. Falls back to the primary if no replica is configured.
+  pub fn replica_pool(&self) -> &Pool {
+    self.replica.as_ref().map(|x| &x.0).unwrap_or(&self.pool)
+  }
+
+  /// The statement cache of `replica_pool`.
+  pub fn replica_stmt_cache(&self) -> &StmtCache {
+    self.replica.as_ref().map(|x| &x.1).unwrap_or(&self.stmts)
+  }
+
   pub fn cast_value_to_js<'s>(
     scope: &mut v8::HandleScope<'s>,
     v: &Value,
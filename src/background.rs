@@ -0,0 +1,95 @@
+//! Retries and dead-lettering of at-least-once background entries.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::lpch::BackgroundEntry;
+
+/// An entry whose retries are exhausted, kept by `BackgroundStore`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DeadLetter {
+  pub id: String,
+  pub entry: BackgroundEntry,
+
+  /// Error of the last attempt.
+  pub error: String,
+  pub dead_lettered_at_ms: i64,
+}
+
+/// Counts a failed run of `entry`. Returns the delay before the next run as configured by its
+/// retry policy, or `None` once retries are exhausted and the entry should be dead-lettered.
+/// Entries without a policy are not retried.
+pub fn next_retry(entry: &mut BackgroundEntry, rand: f64) -> Option<Duration> {
+  let policy = entry.retry.clone()?;
+  entry.attempts += 1;
+  if entry.attempts > policy.max_retries {
+    return None;
+  }
+  Some(policy.delay(entry.attempts, rand))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::next_retry;
+  use crate::{
+    lpch::{BackgroundEntry, RetryPolicy},
+    package::PackageKey,
+  };
+
+  fn policy() -> RetryPolicy {
+    RetryPolicy {
+      max_retries: 3,
+      base_delay_ms: 1,
+      max_delay_ms: 4,
+      jitter: 0.0,
+    }
+  }
+
+  fn entry(retry: Option<RetryPolicy>) -> BackgroundEntry {
+    BackgroundEntry {
+      app: PackageKey {
+        path: "app".into(),
+        version: "1".into(),
+      },
+      request_id: "req".into(),
+      wire_bytes: vec![1, 2, 3],
+      same_version: false,
+      retry,
+      attempts: 0,
+      dedup: None,
+    }
+  }
+
+  #[test]
+  fn backoff_delays() {
+    let policy = RetryPolicy {
+      max_retries: 10,
+      base_delay_ms: 100,
+      max_delay_ms: 1000,
+      jitter: 0.5,
+    };
+    assert_eq!(policy.delay(1, 0.0), Duration::from_millis(100));
+    assert_eq!(policy.delay(3, 0.0), Duration::from_millis(400));
+    assert_eq!(policy.delay(5, 0.0), Duration::from_millis(1000));
+    assert_eq!(policy.delay(100, 0.0), Duration::from_millis(1000));
+    assert_eq!(policy.delay(3, 0.5), Duration::from_millis(300));
+  }
+
+  #[test]
+  fn dead_letters_after_repeated_failures() {
+    let mut e = entry(Some(policy()));
+    let mut delays = vec![];
+    while let Some(delay) = next_retry(&mut e, 0.0) {
+      delays.push(delay.as_millis());
+    }
+    assert_eq!(delays, vec![1, 2, 4]);
+    assert_eq!(e.attempts, 4);
+
+    let mut e = entry(None);
+    assert!(next_retry(&mut e, 0.0).is_none());
+    assert_eq!(e.attempts, 0);
+  }
+}
@@ -0,0 +1,712 @@
+//! Persistent queue of at-least-once background entries, stored in FoundationDB.
+//!
+//! Entries are keyed by the time they are due and an id. Instances that accept background tasks
+//! claim due entries by moving them to `now + lease`, so an entry whose runner dies is claimed
+//! again once the lease is over. An entry is removed only after it succeeds or is dead-lettered.
+//!
+//! Dedup keys of at-least-once entries are stored with their expiry time and checked in the
+//! transaction that enqueues the entry. Expired keys are ignored, and reclaimed when their app
+//! reaches its limit of keys. If none has expired then, the key closest to expiring is evicted.
+//!
+//! Entries whose retries are exhausted are moved to the dead-letter queue of their app, ordered by
+//! the time they were dead-lettered. Once an app's queue is full, its oldest entries are dropped.
+//!
+//! Cron jobs are kept here too, one record per app and job name. Each fire time is claimed by
+//! updating the record in a transaction, so that a job runs on one instance per fire time.
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use foundationdb::{
+  options::{MutationType, StreamingMode},
+  Database, RangeOption, Transaction,
+};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::{
+  background::DeadLetter,
+  cron::{same_job, CronJob, CronTick},
+  lpch::{BackgroundEntry, CronEntry, DedupKey},
+};
+
+pub static BG_STORE: OnceCell<Arc<BackgroundStore>> = OnceCell::new();
+
+/// Upper bound of the entries queued by one app, including the ones being retried.
+pub const MAX_QUEUED_PER_APP: i64 = 10000;
+
+/// Upper bound of the dedup keys kept for one app.
+pub const MAX_DEDUP_KEYS_PER_APP: i64 = 10000;
+
+/// Upper bound of the dead letters kept for one app.
+pub const MAX_DEAD_LETTERS_PER_APP: i64 = 1000;
+
+/// Upper bound of the cron jobs registered by one app.
+pub const MAX_CRON_JOBS_PER_APP: usize = 32;
+
+/// Cron records read per transaction when listing all jobs.
+const CRON_SCAN_BATCH: usize = 1000;
+
+#[derive(Error, Debug)]
+#[error("background store is not configured")]
+pub struct BackgroundStoreNotConfigured;
+
+#[derive(Error, Debug)]
+#[error("too many queued background tasks (limit {0})")]
+pub struct TooManyQueuedTasks(i64);
+
+#[derive(Error, Debug)]
+#[error("too many cron jobs (limit {0})")]
+pub struct TooManyCronJobs(usize);
+
+pub struct BackgroundStoreConfig {
+  pub fdb_cluster_file: String,
+  pub prefix: String,
+}
+
+pub struct BackgroundStore {
+  db: Database,
+  config: BackgroundStoreConfig,
+  enqueued: Notify,
+}
+
+#[derive(Serialize, Deserialize)]
+struct QueuedEntry {
+  id: String,
+  entry: BackgroundEntry,
+}
+
+/// An entry leased to this instance by `BackgroundStore::claim`.
+pub struct ClaimedEntry {
+  key: Vec<u8>,
+  pub entry: BackgroundEntry,
+  id: String,
+}
+
+fn now_millis() -> i64 {
+  chrono::Utc::now().timestamp_millis()
+}
+
+impl BackgroundStore {
+  pub fn open(config: BackgroundStoreConfig) -> Result<Arc<Self>> {
+    let db = Database::new(Some(config.fdb_cluster_file.as_str()))
+      .map_err(|e| anyhow::Error::from(e).context("background store: failed to open cluster"))?;
+    Ok(Arc::new(Self {
+      db,
+      config,
+      enqueued: Notify::new(),
+    }))
+  }
+
+  pub fn get() -> Result<Arc<Self>> {
+    Ok(BG_STORE.get().ok_or(BackgroundStoreNotConfigured)?.clone())
+  }
+
+  fn queue_key(&self, due_ms: i64, id: &str) -> Vec<u8> {
+    foundationdb::tuple::pack(&(self.config.prefix.as_str(), "q", due_ms, id))
+  }
+
+  /// Maps the id of a cancellable entry to its key, until the entry is claimed.
+  fn queue_index_key(&self, path: &str, id: &str) -> Vec<u8> {
+    foundationdb::tuple::pack(&(self.config.prefix.as_str(), "qi", path, id))
+  }
+
+  fn queue_count_key(&self, path: &str) -> Vec<u8> {
+    foundationdb::tuple::pack(&(self.config.prefix.as_str(), "qn", path))
+  }
+
+  fn dedup_key(&self, path: &str, key: &str) -> Vec<u8> {
+    foundationdb::tuple::pack(&(self.config.prefix.as_str(), "dd", path, key))
+  }
+
+  fn dedup_count_key(&self, path: &str) -> Vec<u8> {
+    foundationdb::tuple::pack(&(self.config.prefix.as_str(), "ddn", path))
+  }
+
+  fn dlq_key(&self, path: &str, at_ms: i64, id: &str) -> Vec<u8> {
+    foundationdb::tuple::pack(&(self.config.prefix.as_str(), "dlq", path, at_ms, id))
+  }
+
+  fn dlq_app_range(&self, path: &str) -> (Vec<u8>, Vec<u8>) {
+    let start = foundationdb::tuple::pack(&(self.config.prefix.as_str(), "dlq", path));
+    let end = start
+      .iter()
+      .copied()
+      .chain(std::iter::once(0xffu8))
+      .collect::<Vec<u8>>();
+    (start, end)
+  }
+
+  /// Maps the id of a dead letter to its key.
+  fn dlq_index_key(&self, path: &str, id: &str) -> Vec<u8> {
+    foundationdb::tuple::pack(&(self.config.prefix.as_str(), "dlqi", path, id))
+  }
+
+  fn dlq_count_key(&self, path: &str) -> Vec<u8> {
+    foundationdb::tuple::pack(&(self.config.prefix.as_str(), "dlqn", path))
+  }
+
+  fn cron_key(&self, path: &str, name: &str) -> Vec<u8> {
+    foundationdb::tuple::pack(&(self.config.prefix.as_str(), "cron", path, name))
+  }
+
+  /// Adds `entry` to the queue, due now. Returns once the entry is committed, or `false` if it
+  /// was suppressed by its dedup key.
+  pub async fn enqueue(&self, entry: &BackgroundEntry) -> Result<bool> {
+    self
+      .enqueue_at(entry, &Uuid::new_v4().to_string(), now_millis(), false)
+      .await
+  }
+
+  /// Adds `entry` to the queue, due at `due_ms`, under `id`. Returns once the entry is committed.
+  /// Until it is claimed, the entry can be removed with `cancel`.
+  pub async fn enqueue_delayed(
+    &self,
+    entry: &BackgroundEntry,
+    id: &str,
+    due_ms: i64,
+  ) -> Result<()> {
+    self.enqueue_at(entry, id, due_ms, true).await?;
+    Ok(())
+  }
+
+  async fn enqueue_at(
+    &self,
+    entry: &BackgroundEntry,
+    id: &str,
+    due_ms: i64,
+    cancellable: bool,
+  ) -> Result<bool> {
+    let id = id.to_string();
+    let value = bincode::serialize(&QueuedEntry {
+      id: id.clone(),
+      entry: entry.clone(),
+    })?;
+    let count_key = self.queue_count_key(&entry.app.path);
+    let mut txn = self.db.create_trx()?;
+    loop {
+      // A snapshot read, so that concurrent enqueues don't conflict. The limit may be exceeded
+      // by the number of concurrent enqueues.
+      let count = txn.get(&count_key, true).await?;
+      if decode_i64(count.as_deref()) >= MAX_QUEUED_PER_APP {
+        return Err(TooManyQueuedTasks(MAX_QUEUED_PER_APP).into());
+      }
+      if let Some(dedup) = &entry.dedup {
+        if !self.accept_dedup_key(&txn, &entry.app.path, dedup).await? {
+          return Ok(false);
+        }
+      }
+      let key = self.queue_key(due_ms, &id);
+      txn.set(&key, &value);
+      txn.atomic_op(&count_key, &1i64.to_le_bytes(), MutationType::Add);
+      if cancellable {
+        txn.set(&self.queue_index_key(&entry.app.path, &id), &key);
+      }
+      match txn.commit().await {
+        Ok(_) => break,
+        Err(e) => {
+          txn = e.on_error().await?;
+        }
+      }
+    }
+    self.enqueued.notify_one();
+    Ok(true)
+  }
+
+  /// Starts the window of `dedup` in `txn`, or returns `false` if it is within one. A suppressed
+  /// entry doesn't extend the window.
+  async fn accept_dedup_key(
+    &self,
+    txn: &Transaction,
+    path: &str,
+    dedup: &DedupKey,
+  ) -> Result<bool> {
+    let now = now_millis();
+    let key = self.dedup_key(path, &dedup.key);
+    let existing = txn.get(&key, false).await?;
+    if existing.is_some() && decode_i64(existing.as_deref()) > now {
+      return Ok(false);
+    }
+    let expiry = now.saturating_add(dedup.window_secs.saturating_mul(1000) as i64);
+    txn.set(&key, &expiry.to_le_bytes());
+    if existing.is_some() {
+      return Ok(true);
+    }
+
+    let count_key = self.dedup_count_key(path);
+    // A snapshot read, so that keys added concurrently don't conflict. The limit may be exceeded
+    // by the number of concurrent enqueues.
+    let count = decode_i64(txn.get(&count_key, true).await?.as_deref());
+    let mut added = 1i64;
+    if count >= MAX_DEDUP_KEYS_PER_APP {
+      let start = foundationdb::tuple::pack(&(self.config.prefix.as_str(), "dd", path));
+      let end = start
+        .iter()
+        .copied()
+        .chain(std::iter::once(0xffu8))
+        .collect::<Vec<u8>>();
+      let mut opt = RangeOption::from(start..end);
+      opt.mode = StreamingMode::WantAll;
+      opt.limit = Some(MAX_DEDUP_KEYS_PER_APP as usize);
+      let range = txn.get_range(&opt, 0, false).await?;
+      let expiries = range
+        .iter()
+        .map(|kv| decode_i64(Some(kv.value())))
+        .collect::<Vec<_>>();
+      for i in dedup_evictions(&expiries, now) {
+        if let Some(kv) = range.iter().nth(i) {
+          txn.clear(kv.key());
+          added -= 1;
+        }
+      }
+    }
+    txn.atomic_op(&count_key, &added.to_le_bytes(), MutationType::Add);
+    Ok(true)
+  }
+
+  /// Leases at most `limit` due entries to this instance for `lease`.
+  pub async fn claim(&self, limit: usize, lease: Duration) -> Result<Vec<ClaimedEntry>> {
+    let start = foundationdb::tuple::pack(&(self.config.prefix.as_str(), "q"));
+    let mut txn = self.db.create_trx()?;
+    loop {
+      let now = now_millis();
+      let end = foundationdb::tuple::pack(&(self.config.prefix.as_str(), "q", now + 1));
+      let mut opt = RangeOption::from(start.clone()..end);
+      opt.mode = StreamingMode::WantAll;
+      opt.limit = Some(limit);
+      let range = txn.get_range(&opt, 0, false).await?;
+      let leased_until = now + lease.as_millis() as i64;
+      let mut out = vec![];
+      for kv in range.iter() {
+        txn.clear(kv.key());
+        let queued: QueuedEntry = match bincode::deserialize(kv.value()) {
+          Ok(x) => x,
+          Err(e) => {
+            tracing::error!(error = %e, "dropping undecodable background entry");
+            continue;
+          }
+        };
+        let key = self.queue_key(leased_until, &queued.id);
+        txn.set(&key, kv.value());
+        txn.clear(&self.queue_index_key(&queued.entry.app.path, &queued.id));
+        out.push(ClaimedEntry {
+          key,
+          entry: queued.entry,
+          id: queued.id,
+        });
+      }
+      match txn.commit().await {
+        Ok(_) => return Ok(out),
+        Err(e) => {
+          txn = e.on_error().await?;
+        }
+      }
+    }
+  }
+
+  /// Removes a claimed entry after it succeeded or was dead-lettered. Does nothing if the lease
+  /// ran out and another instance claimed the entry again.
+  pub async fn complete(&self, claimed: &ClaimedEntry) -> Result<()> {
+    let count_key = self.queue_count_key(&claimed.entry.app.path);
+    let mut txn = self.db.create_trx()?;
+    loop {
+      if txn.get(&claimed.key, false).await?.is_none() {
+        return Ok(());
+      }
+      txn.clear(&claimed.key);
+      txn.atomic_op(&count_key, &(-1i64).to_le_bytes(), MutationType::Add);
+      match txn.commit().await {
+        Ok(_) => return Ok(()),
+        Err(e) => {
+          txn = e.on_error().await?;
+        }
+      }
+    }
+  }
+
+  /// Removes an entry added by `enqueue_delayed` for the app at `path`. Returns `false` if there
+  /// is no such entry or it was already claimed.
+  pub async fn cancel(&self, path: &str, id: &str) -> Result<bool> {
+    let index_key = self.queue_index_key(path, id);
+    let count_key = self.queue_count_key(path);
+    let mut txn = self.db.create_trx()?;
+    loop {
+      let key = match txn.get(&index_key, false).await? {
+        Some(x) => x.to_vec(),
+        None => return Ok(false),
+      };
+      txn.clear(&index_key);
+      let removed = txn.get(&key, false).await?.is_some();
+      if removed {
+        txn.clear(&key);
+        txn.atomic_op(&count_key, &(-1i64).to_le_bytes(), MutationType::Add);
+      }
+      match txn.commit().await {
+        Ok(_) => return Ok(removed),
+        Err(e) => {
+          txn = e.on_error().await?;
+        }
+      }
+    }
+  }
+
+  /// Puts a claimed entry back, due after `delay`, with the updated `entry`.
+  pub async fn retry_later(
+    &self,
+    claimed: &ClaimedEntry,
+    entry: &BackgroundEntry,
+    delay: Duration,
+  ) -> Result<()> {
+    let value = bincode::serialize(&QueuedEntry {
+      id: claimed.id.clone(),
+      entry: entry.clone(),
+    })?;
+    let mut txn = self.db.create_trx()?;
+    loop {
+      if txn.get(&claimed.key, false).await?.is_none() {
+        return Ok(());
+      }
+      txn.clear(&claimed.key);
+      txn.set(
+        &self.queue_key(now_millis() + delay.as_millis() as i64, &claimed.id),
+        &value,
+      );
+      match txn.commit().await {
+        Ok(_) => return Ok(()),
+        Err(e) => {
+          txn = e.on_error().await?;
+        }
+      }
+    }
+  }
+
+  /// Moves a claimed entry, with the updated `entry`, to the dead-letter queue of its app. Does
+  /// nothing if the lease ran out and another instance claimed the entry again.
+  pub async fn dead_letter(
+    &self,
+    claimed: &ClaimedEntry,
+    entry: &BackgroundEntry,
+    error: &str,
+  ) -> Result<()> {
+    let path = &entry.app.path;
+    let count_key = self.queue_count_key(path);
+    let dlq_count_key = self.dlq_count_key(path);
+    let (dlq_start, dlq_end) = self.dlq_app_range(path);
+    let mut txn = self.db.create_trx()?;
+    loop {
+      if txn.get(&claimed.key, false).await?.is_none() {
+        return Ok(());
+      }
+      txn.clear(&claimed.key);
+      txn.atomic_op(&count_key, &(-1i64).to_le_bytes(), MutationType::Add);
+
+      let letter = DeadLetter {
+        id: claimed.id.clone(),
+        entry: entry.clone(),
+        error: error.to_string(),
+        dead_lettered_at_ms: now_millis(),
+      };
+      let key = self.dlq_key(path, letter.dead_lettered_at_ms, &letter.id);
+      txn.set(&key, &bincode::serialize(&letter)?);
+      txn.set(&self.dlq_index_key(path, &letter.id), &key);
+
+      let count = decode_i64(txn.get(&dlq_count_key, false).await?.as_deref());
+      if count >= MAX_DEAD_LETTERS_PER_APP {
+        let mut opt = RangeOption::from(dlq_start.clone()..dlq_end.clone());
+        opt.mode = StreamingMode::WantAll;
+        opt.limit = Some((count - MAX_DEAD_LETTERS_PER_APP + 1) as usize);
+        for kv in txn.get_range(&opt, 0, false).await?.iter() {
+          txn.clear(kv.key());
+          if let Ok(old) = bincode::deserialize::<DeadLetter>(kv.value()) {
+            txn.clear(&self.dlq_index_key(path, &old.id));
+          }
+          txn.atomic_op(&dlq_count_key, &(-1i64).to_le_bytes(), MutationType::Add);
+        }
+      }
+      txn.atomic_op(&dlq_count_key, &1i64.to_le_bytes(), MutationType::Add);
+      match txn.commit().await {
+        Ok(_) => return Ok(()),
+        Err(e) => {
+          txn = e.on_error().await?;
+        }
+      }
+    }
+  }
+
+  /// Lists dead letters of the app at `path`, oldest first.
+  pub async fn list_dead_letters(
+    &self,
+    path: &str,
+    version: Option<&str>,
+    limit: usize,
+  ) -> Result<Vec<DeadLetter>> {
+    let (start, end) = self.dlq_app_range(path);
+    let txn = self.db.create_trx()?;
+    let mut opt = RangeOption::from(start..end);
+    opt.mode = StreamingMode::WantAll;
+    opt.limit = Some(MAX_DEAD_LETTERS_PER_APP as usize);
+    let range = txn.get_range(&opt, 0, true).await?;
+    let mut out = vec![];
+    for kv in range.iter() {
+      let letter: DeadLetter = bincode::deserialize(kv.value())?;
+      if version
+        .map(|v| letter.entry.app.version == v)
+        .unwrap_or(true)
+      {
+        out.push(letter);
+        if out.len() >= limit {
+          break;
+        }
+      }
+    }
+    Ok(out)
+  }
+
+  /// Moves a dead letter of the app at `path` back to the queue, due now and with its attempts
+  /// reset. Returns `false` if there is no such dead letter, e.g. because it was redriven before.
+  pub async fn redrive(&self, path: &str, id: &str) -> Result<bool> {
+    let index_key = self.dlq_index_key(path, id);
+    let count_key = self.queue_count_key(path);
+    let dlq_count_key = self.dlq_count_key(path);
+    let mut txn = self.db.create_trx()?;
+    loop {
+      let key = match txn.get(&index_key, false).await? {
+        Some(x) => x.to_vec(),
+        None => return Ok(false),
+      };
+      let mut letter: DeadLetter = match txn.get(&key, false).await? {
+        Some(x) => bincode::deserialize(&x)?,
+        None => return Ok(false),
+      };
+      let count = txn.get(&count_key, true).await?;
+      if decode_i64(count.as_deref()) >= MAX_QUEUED_PER_APP {
+        return Err(TooManyQueuedTasks(MAX_QUEUED_PER_APP).into());
+      }
+      letter.entry.attempts = 0;
+      // Redriving is explicit, so it isn't suppressed by the original enqueue.
+      letter.entry.dedup = None;
+      txn.clear(&index_key);
+      txn.clear(&key);
+      txn.atomic_op(&dlq_count_key, &(-1i64).to_le_bytes(), MutationType::Add);
+      txn.set(
+        &self.queue_key(now_millis(), &letter.id),
+        &bincode::serialize(&QueuedEntry {
+          id: letter.id.clone(),
+          entry: letter.entry,
+        })?,
+      );
+      txn.atomic_op(&count_key, &1i64.to_le_bytes(), MutationType::Add);
+      match txn.commit().await {
+        Ok(_) => break,
+        Err(e) => {
+          txn = e.on_error().await?;
+        }
+      }
+    }
+    self.enqueued.notify_one();
+    Ok(true)
+  }
+
+  /// Registers a cron job, replacing the job of the same app with the same name. Registering an
+  /// identical job again does nothing.
+  pub async fn put_cron(&self, entry: &CronEntry) -> Result<()> {
+    let key = self.cron_key(&entry.app.path, &entry.name);
+    let app_start =
+      foundationdb::tuple::pack(&(self.config.prefix.as_str(), "cron", &entry.app.path));
+    let app_end = app_start
+      .iter()
+      .copied()
+      .chain(std::iter::once(0xffu8))
+      .collect::<Vec<u8>>();
+    let mut txn = self.db.create_trx()?;
+    loop {
+      let existing = match txn.get(&key, false).await? {
+        Some(x) => Some(bincode::deserialize::<CronJob>(&x)?),
+        None => None,
+      };
+      let job = match existing {
+        Some(x) if same_job(&x.entry, entry) => return Ok(()),
+        // A run in progress still prevents the replaced job from overlapping with it.
+        Some(x) => CronJob {
+          running_until_ms: x.running_until_ms,
+          ..CronJob::new(entry.clone(), now_millis())
+        },
+        None => {
+          // A snapshot read, so that registrations don't conflict with fire times of other jobs.
+          let mut opt = RangeOption::from(app_start.clone()..app_end.clone());
+          opt.mode = StreamingMode::WantAll;
+          opt.limit = Some(MAX_CRON_JOBS_PER_APP);
+          if txn.get_range(&opt, 0, true).await?.iter().count() >= MAX_CRON_JOBS_PER_APP {
+            return Err(TooManyCronJobs(MAX_CRON_JOBS_PER_APP).into());
+          }
+          CronJob::new(entry.clone(), now_millis())
+        }
+      };
+      txn.set(&key, &bincode::serialize(&job)?);
+      match txn.commit().await {
+        Ok(_) => return Ok(()),
+        Err(e) => {
+          txn = e.on_error().await?;
+        }
+      }
+    }
+  }
+
+  /// Removes a cron job. Returns whether the job existed. A run in progress is not interrupted.
+  pub async fn remove_cron(&self, path: &str, name: &str) -> Result<bool> {
+    let key = self.cron_key(path, name);
+    let mut txn = self.db.create_trx()?;
+    loop {
+      if txn.get(&key, false).await?.is_none() {
+        return Ok(false);
+      }
+      txn.clear(&key);
+      match txn.commit().await {
+        Ok(_) => return Ok(true),
+        Err(e) => {
+          txn = e.on_error().await?;
+        }
+      }
+    }
+  }
+
+  /// Lists the cron jobs of all apps.
+  pub async fn cron_jobs(&self) -> Result<Vec<CronJob>> {
+    let mut start = foundationdb::tuple::pack(&(self.config.prefix.as_str(), "cron"));
+    let end = start
+      .iter()
+      .copied()
+      .chain(std::iter::once(0xffu8))
+      .collect::<Vec<u8>>();
+    let mut out = vec![];
+    loop {
+      let txn = self.db.create_trx()?;
+      let mut opt = RangeOption::from(start.clone()..end.clone());
+      opt.mode = StreamingMode::WantAll;
+      opt.limit = Some(CRON_SCAN_BATCH);
+      let range = txn.get_range(&opt, 0, true).await?;
+      for kv in range.iter() {
+        match bincode::deserialize(kv.value()) {
+          Ok(x) => out.push(x),
+          Err(e) => tracing::error!(error = %e, "skipping undecodable cron job"),
+        }
+      }
+      match range.iter().last() {
+        Some(kv) if range.iter().count() == CRON_SCAN_BATCH => {
+          start = kv
+            .key()
+            .iter()
+            .copied()
+            .chain(std::iter::once(0u8))
+            .collect();
+        }
+        _ => return Ok(out),
+      }
+    }
+  }
+
+  /// Claims the current fire time of a cron job. Returns the job if this instance should run it,
+  /// leased for `lease`; `end_cron_run` ends the lease early.
+  pub async fn fire_cron(
+    &self,
+    path: &str,
+    name: &str,
+    lease: Duration,
+  ) -> Result<Option<CronJob>> {
+    let key = self.cron_key(path, name);
+    let mut txn = self.db.create_trx()?;
+    loop {
+      let mut job: CronJob = match txn.get(&key, false).await? {
+        Some(x) => bincode::deserialize(&x)?,
+        None => return Ok(None),
+      };
+      let tick = job.tick(now_millis(), lease.as_millis() as i64)?;
+      if tick == CronTick::NotDue {
+        return Ok(None);
+      }
+      txn.set(&key, &bincode::serialize(&job)?);
+      match txn.commit().await {
+        Ok(_) => {
+          if tick == CronTick::Overlap {
+            tracing::warn!(package_path = %path, cron = %name, "previous cron run still in progress, skipping");
+            return Ok(None);
+          }
+          return Ok(Some(job));
+        }
+        Err(e) => {
+          txn = e.on_error().await?;
+        }
+      }
+    }
+  }
+
+  /// Ends the lease of a run returned by `fire_cron`.
+  pub async fn end_cron_run(&self, job: &CronJob) -> Result<()> {
+    let key = self.cron_key(&job.entry.app.path, &job.entry.name);
+    let mut txn = self.db.create_trx()?;
+    loop {
+      let mut current: CronJob = match txn.get(&key, false).await? {
+        Some(x) => bincode::deserialize(&x)?,
+        None => return Ok(()),
+      };
+      if current.running_until_ms != job.running_until_ms {
+        return Ok(());
+      }
+      current.running_until_ms = 0;
+      txn.set(&key, &bincode::serialize(&current)?);
+      match txn.commit().await {
+        Ok(_) => return Ok(()),
+        Err(e) => {
+          txn = e.on_error().await?;
+        }
+      }
+    }
+  }
+
+  /// Waits until an entry is enqueued by this instance, or for `timeout`. Entries enqueued by
+  /// other instances are picked up after the timeout.
+  pub async fn wait(&self, timeout: Duration) {
+    let _ = tokio::time::timeout(timeout, self.enqueued.notified()).await;
+  }
+}
+
+/// Picks the dedup keys to clear when an app has reached its limit, by their expiry times: all
+/// expired keys, or else the one closest to expiring.
+fn dedup_evictions(expiries: &[i64], now: i64) -> Vec<usize> {
+  let expired = (0..expiries.len())
+    .filter(|i| expiries[*i] <= now)
+    .collect::<Vec<_>>();
+  if !expired.is_empty() {
+    return expired;
+  }
+  (0..expiries.len())
+    .min_by_key(|i| expiries[*i])
+    .into_iter()
+    .collect()
+}
+
+fn decode_i64(x: Option<&[u8]>) -> i64 {
+  match x {
+    Some(x) if x.len() == 8 => {
+      let mut buf = [0u8; 8];
+      buf.copy_from_slice(x);
+      i64::from_le_bytes(buf)
+    }
+    _ => 0,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::dedup_evictions;
+
+  #[test]
+  fn evicts_expired_dedup_keys_first() {
+    assert_eq!(dedup_evictions(&[10, 50, 20, 5], 20), vec![0, 2, 3]);
+    assert_eq!(dedup_evictions(&[30, 25, 40], 20), vec![1]);
+    assert!(dedup_evictions(&[], 20).is_empty());
+  }
+}
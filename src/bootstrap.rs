@@ -7,6 +7,8 @@ use serde::Serialize;
 pub struct BlueboatBootstrapData {
   pub mysql: Vec<String>,
   pub apns: Vec<String>,
+  pub fcm: Vec<String>,
+  pub webpush: Vec<String>,
   pub env: HashMap<String, String>,
   pub pubsub: Vec<String>,
 }
@@ -0,0 +1,207 @@
+//! Cron-style scheduling of background entries.
+
+use std::str::FromStr;
+
+use anyhow::Result;
+use chrono::{DateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::lpch::CronEntry;
+
+#[derive(Error, Debug)]
+#[error("invalid cron expression: {0}")]
+struct InvalidCronExpression(String);
+
+#[derive(Error, Debug)]
+#[error("unknown timezone: {0}")]
+struct UnknownTimezone(String);
+
+#[derive(Error, Debug)]
+#[error(
+  "cron schedule fires more often than every {} seconds",
+  MIN_CRON_INTERVAL_SECS
+)]
+struct CronIntervalTooShort;
+
+/// Shortest allowed time between two fire times of a job.
+pub const MIN_CRON_INTERVAL_SECS: i64 = 60;
+
+pub struct CronSchedule {
+  schedule: cron::Schedule,
+  tz: Tz,
+}
+
+impl CronSchedule {
+  pub fn parse(expr: &str, timezone: Option<&str>) -> Result<Self> {
+    let schedule =
+      cron::Schedule::from_str(expr).map_err(|e| InvalidCronExpression(e.to_string()))?;
+    let tz = match timezone {
+      Some(x) => Tz::from_str(x).map_err(|_| UnknownTimezone(x.to_string()))?,
+      None => Tz::UTC,
+    };
+
+    // A schedule fires more than once a minute only if its seconds field has several values,
+    // which shows within the first few fire times.
+    let times = schedule.upcoming(Utc).take(4).collect::<Vec<_>>();
+    if times
+      .windows(2)
+      .any(|x| (x[1] - x[0]).num_seconds() < MIN_CRON_INTERVAL_SECS)
+    {
+      return Err(CronIntervalTooShort.into());
+    }
+    Ok(Self { schedule, tz })
+  }
+
+  /// Returns the first fire time strictly after `t`, or `None` if the schedule has ended.
+  pub fn next_after(&self, t: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    self
+      .schedule
+      .after(&t.with_timezone(&self.tz))
+      .next()
+      .map(|x| x.with_timezone(&Utc))
+  }
+}
+
+/// A registered job as kept in the background store.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CronJob {
+  pub entry: CronEntry,
+
+  /// When the job last fired, or when it was registered.
+  pub last_fired_ms: i64,
+
+  /// End of the lease of the run in progress, if any. A run whose instance went away is assumed
+  /// to be over once its lease ends.
+  pub running_until_ms: i64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CronTick {
+  NotDue,
+
+  /// Due, but the previous run is still in progress. The fire time is skipped.
+  Overlap,
+  Run,
+}
+
+impl CronJob {
+  pub fn new(entry: CronEntry, now_ms: i64) -> Self {
+    Self {
+      entry,
+      last_fired_ms: now_ms,
+      running_until_ms: 0,
+    }
+  }
+
+  /// Advances the job to `now_ms` if a fire time has passed since it last fired. Fire times
+  /// missed in between, e.g. while no instance was running, are coalesced into one run.
+  pub fn tick(&mut self, now_ms: i64, lease_ms: i64) -> Result<CronTick> {
+    let schedule = CronSchedule::parse(&self.entry.schedule, self.entry.timezone.as_deref())?;
+    let next = schedule.next_after(Utc.timestamp_millis(self.last_fired_ms));
+    match next {
+      Some(x) if x.timestamp_millis() <= now_ms => {}
+      _ => return Ok(CronTick::NotDue),
+    }
+    self.last_fired_ms = now_ms;
+    if self.running_until_ms > now_ms {
+      return Ok(CronTick::Overlap);
+    }
+    self.running_until_ms = now_ms + lease_ms;
+    Ok(CronTick::Run)
+  }
+}
+
+/// Apps may register their jobs on every request, so an identical registration keeps the state of
+/// the existing job.
+pub fn same_job(a: &CronEntry, b: &CronEntry) -> bool {
+  CronEntry {
+    request_id: String::new(),
+    ..a.clone()
+  } == CronEntry {
+    request_id: String::new(),
+    ..b.clone()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::{TimeZone, Utc};
+
+  use super::{same_job, CronJob, CronSchedule, CronTick};
+  use crate::{lpch::CronEntry, package::PackageKey};
+
+  #[test]
+  fn next_fire_time_in_timezone() {
+    let schedule = CronSchedule::parse("0 30 9 * * Mon-Fri", Some("America/New_York")).unwrap();
+    // Saturday 2022-06-11 12:00 UTC
+    let next = schedule
+      .next_after(Utc.ymd(2022, 6, 11).and_hms(12, 0, 0))
+      .unwrap();
+    // Monday 09:30 EDT
+    assert_eq!(next, Utc.ymd(2022, 6, 13).and_hms(13, 30, 0));
+
+    let schedule = CronSchedule::parse("0 0 * * * *", None).unwrap();
+    let next = schedule
+      .next_after(Utc.ymd(2022, 6, 11).and_hms(12, 0, 0))
+      .unwrap();
+    assert_eq!(next, Utc.ymd(2022, 6, 11).and_hms(13, 0, 0));
+  }
+
+  #[test]
+  fn rejects_invalid_input() {
+    assert!(CronSchedule::parse("every minute", None).is_err());
+    assert!(CronSchedule::parse("0 * * * * *", Some("Mars/Olympus_Mons")).is_err());
+    assert!(CronSchedule::parse("* * * * * *", None).is_err());
+    assert!(CronSchedule::parse("0,30 * * * * *", None).is_err());
+    assert!(CronSchedule::parse("0 * * * * *", None).is_ok());
+  }
+
+  fn entry(schedule: &str, request_id: &str) -> CronEntry {
+    CronEntry {
+      app: PackageKey {
+        path: "app".into(),
+        version: "1".into(),
+      },
+      request_id: request_id.into(),
+      name: "job".into(),
+      schedule: schedule.into(),
+      timezone: None,
+      wire_bytes: vec![],
+      same_version: false,
+    }
+  }
+
+  #[test]
+  fn ticks_once_per_fire_time() {
+    let t0 = Utc.ymd(2022, 6, 11).and_hms(12, 0, 30).timestamp_millis();
+    let minute = 60 * 1000;
+    let mut job = CronJob::new(entry("0 * * * * *", "a"), t0);
+    assert_eq!(job.tick(t0 + 1000, minute).unwrap(), CronTick::NotDue);
+
+    let t1 = t0 + 31 * 1000;
+    assert_eq!(job.tick(t1, 2 * minute).unwrap(), CronTick::Run);
+    assert_eq!(job.tick(t1, 2 * minute).unwrap(), CronTick::NotDue);
+
+    // The run from t1 is still within its lease.
+    assert_eq!(job.tick(t1 + minute, minute).unwrap(), CronTick::Overlap);
+    job.running_until_ms = 0;
+
+    // Missed fire times are coalesced.
+    assert_eq!(job.tick(t1 + 10 * minute, minute).unwrap(), CronTick::Run);
+    assert_eq!(job.last_fired_ms, t1 + 10 * minute);
+  }
+
+  #[test]
+  fn ignores_request_id_when_comparing() {
+    assert!(same_job(
+      &entry("0 * * * * *", "a"),
+      &entry("0 * * * * *", "b")
+    ));
+    assert!(!same_job(
+      &entry("0 * * * * *", "a"),
+      &entry("0 0 * * * *", "a")
+    ));
+  }
+}
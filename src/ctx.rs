@@ -2,27 +2,36 @@ use std::{
   borrow::Cow,
   cell::RefCell,
   collections::HashMap,
-  sync::Arc,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
   time::{Duration, Instant},
 };
 
 use crate::{
   api::{
+    apns::build_apns_client,
+    crypto::DeterministicRng,
+    fcm::FcmClient,
+    fetch::build_http_client,
     util::{mk_v8_string, v8_serialize, write_applog},
+    webpush::WebPushVapid,
     API,
   },
-  app_mysql::AppMysql,
+  app_mysql::{AppMysql, StmtCache, DEFAULT_STMT_CACHE_SIZE},
   bootstrap::BlueboatBootstrapData,
   consts::CACERT_PEM,
+  egress::EgressPolicy,
   exec::Executor,
-  lpch::LowPriorityMsg,
-  metadata::{ApnsEndpointMetadata, Metadata},
+  lpch::{AppLogLevel, LowPriorityMsg},
+  metadata::{Metadata, MysqlMetadata},
   package::{Package, PackageKey},
   package_loader::load_package,
-  pm::{take_isolate, CachedBootstrapData},
+  pm::{deterministic_rng_seed, take_isolate, CachedBootstrapData},
   registry::SymbolRegistry,
   reliable_channel::{RchReqBody, ReliableChannel, ReliableChannelSeed},
-  v8util::{IsolateInitDataExt, ObjectExt},
+  v8util::{describe_exception, IsolateInitDataExt, ObjectExt},
 };
 use anyhow::{bail, Result};
 use parking_lot::Mutex;
@@ -33,8 +42,28 @@ use thiserror::Error;
 use tokio::runtime::Handle;
 use v8;
 
+/// Maximum number of distinct per-request fetch proxies with a cached client.
+const MAX_PROXY_CLIENTS: usize = 16;
+
 pub const NI_ENTRY_KEY: &str = "__blueboat_host_invoke";
 
+/// The app's memory pressure handler is called once the used heap exceeds this fraction of the
+/// heap limit.
+const MEMORY_PRESSURE_RATIO: f64 = 0.8;
+
+/// Minimum interval between two calls to the memory pressure handler.
+const MEMORY_PRESSURE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The memory pressure handler is terminated if it runs longer than this.
+const MEMORY_PRESSURE_DEADLINE: Duration = Duration::from_millis(50);
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MemoryPressureInfo {
+  used_heap_size: usize,
+  heap_size_limit: usize,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct BlueboatInitData {
   pub key: PackageKey,
@@ -78,10 +107,15 @@ pub struct BlueboatCtx {
   pub isolate: Mutex<v8::OwnedIsolate>,
   pub v8_ctx: RefCell<v8::Global<v8::Context>>,
   pub http_client: reqwest::Client,
+  pub proxied_http_clients: RefCell<HashMap<String, reqwest::Client>>,
+  pub egress_policy: Arc<EgressPolicy>,
   pub mysql: HashMap<String, AppMysql>,
   pub apns: HashMap<String, a2::Client>,
+  pub fcm: HashMap<String, FcmClient>,
+  pub webpush: HashMap<String, WebPushVapid>,
   pub computation_watcher: Handle,
   pub last_invocation_time_after_full_gc: RefCell<Option<Instant>>,
+  pub last_memory_pressure_time: RefCell<Option<Instant>>,
 }
 
 impl BlueboatCtx {
@@ -91,15 +125,11 @@ impl BlueboatCtx {
     let mut isolate = take_isolate();
     isolate.set_slot(SymbolRegistry::new());
     isolate.set_slot(d);
+    if let Some(seed) = deterministic_rng_seed() {
+      isolate.set_slot(DeterministicRng::new(seed));
+    }
 
-    let computation_watcher_rt = tokio::runtime::Builder::new_current_thread()
-      .enable_time()
-      .build()
-      .unwrap();
-    let computation_watcher = computation_watcher_rt.handle().clone();
-    std::thread::spawn(move || {
-      computation_watcher_rt.block_on(futures::future::pending::<()>());
-    });
+    let computation_watcher = spawn_computation_watcher();
 
     let app_key = &d.key;
     let init_timeout_watcher = computation_watcher.spawn(async move {
@@ -115,7 +145,11 @@ impl BlueboatCtx {
           v8_ctx = x;
         }
         Err(e) => {
-          write_applog(scope, format!("failed to build v8 context: {}", e));
+          write_applog(
+            scope,
+            AppLogLevel::Error,
+            format!("failed to build v8 context: {}", e),
+          );
           log::debug!("app {}: failed to build v8 context: {:?}", app_key, e);
           std::process::exit(1);
         }
@@ -127,38 +161,108 @@ impl BlueboatCtx {
       .metadata
       .mysql
       .iter()
-      .filter_map(|(k, v)| match mysql_async::Opts::from_url(&v.url) {
-        Ok(mut opts) => {
-          if opts.socket().is_some() || opts.ssl_opts().and_then(|x| x.root_cert_path()).is_some() {
+      .filter_map(|(k, v)| {
+        let pools = build_mysql_pool(&v.url, v).and_then(|pool| {
+          let replica = v
+            .replica_url
+            .as_ref()
+            .map(|url| build_mysql_pool(url, v))
+            .transpose()?;
+          Ok((pool, replica))
+        });
+        match pools {
+          Ok((pool, replica)) => Some((k.clone(), AppMysql::new(pool, replica))),
+          Err(e) => {
             write_applog(
               &mut isolate,
-              format!("mysql configuration contains disallowed keys"),
+              AppLogLevel::Error,
+              format!("mysql initialization failed: {}", e),
             );
-            log::debug!(
-              "app {}: mysql configuration contains disallowed keys",
-              app_key
-            );
-            return None;
-          }
-          if let Some(cert) = &v.root_certificate {
-            let cert = cert.as_str();
-            let cert_data: Cow<[u8]> = if cert == "default" {
-              Cow::Borrowed(CACERT_PEM)
-            } else {
-              Cow::Owned(cert.as_bytes().to_vec())
-            };
-            let ssl = opts.ssl_opts().cloned().unwrap_or_default();
-            opts
-              .try_set_ssl_opts(ssl.with_root_cert_data(Some(cert_data)))
-              .ok()
-              .expect("failed to set ssl opts");
+            log::debug!("app {}: failed to initialize mysql: {:?}", app_key, e);
+            None
           }
-          let pool = mysql_async::Pool::new(opts);
-          Some((k.clone(), AppMysql::new(pool)))
         }
+      })
+      .collect();
+
+    let egress_policy = match EgressPolicy::from_metadata(&d.metadata.fetch) {
+      Ok(x) => Arc::new(x),
+      Err(e) => {
+        write_applog(
+          &mut isolate,
+          AppLogLevel::Error,
+          format!("egress policy initialization failed: {}", e),
+        );
+        log::debug!("app {}: failed to build egress policy: {:?}", app_key, e);
+        std::process::exit(1);
+      }
+    };
+    let http_client = match build_http_client(
+      d.metadata.fetch.proxy.as_deref(),
+      &d.metadata.fetch,
+      egress_policy.clone(),
+    ) {
+      Ok(x) => x,
+      Err(e) => {
+        write_applog(
+          &mut isolate,
+          AppLogLevel::Error,
+          format!("http client initialization failed: {}", e),
+        );
+        log::debug!("app {}: failed to build http client: {:?}", app_key, e);
+        std::process::exit(1);
+      }
+    };
+
+    let apns: HashMap<String, a2::Client> = d
+      .metadata
+      .apns
+      .iter()
+      .filter_map(|(k, v)| match build_apns_client(v) {
+        Ok(x) => Some((k.clone(), x)),
+        Err(e) => {
+          write_applog(
+            &mut isolate,
+            AppLogLevel::Error,
+            format!("apns initialization failed: {}", e),
+          );
+          log::debug!("app {}: failed to initialize apns: {:?}", app_key, e);
+          None
+        }
+      })
+      .collect();
+
+    let fcm: HashMap<String, FcmClient> = d
+      .metadata
+      .fcm
+      .iter()
+      .filter_map(|(k, v)| match FcmClient::from_metadata(v) {
+        Ok(x) => Some((k.clone(), x)),
+        Err(e) => {
+          write_applog(
+            &mut isolate,
+            AppLogLevel::Error,
+            format!("fcm initialization failed: {}", e),
+          );
+          log::debug!("app {}: failed to initialize fcm: {:?}", app_key, e);
+          None
+        }
+      })
+      .collect();
+
+    let webpush: HashMap<String, WebPushVapid> = d
+      .metadata
+      .webpush
+      .iter()
+      .filter_map(|(k, v)| match WebPushVapid::from_metadata(v) {
+        Ok(x) => Some((k.clone(), x)),
         Err(e) => {
-          write_applog(&mut isolate, format!("mysql initialization failed: {}", e));
-          log::debug!("app {}: failed to initialize mysql: {:?}", app_key, e);
+          write_applog(
+            &mut isolate,
+            AppLogLevel::Error,
+            format!("webpush initialization failed: {}", e),
+          );
+          log::debug!("app {}: failed to initialize webpush: {:?}", app_key, e);
           None
         }
       })
@@ -171,32 +275,168 @@ impl BlueboatCtx {
       rch,
       isolate: Mutex::new(isolate),
       v8_ctx: RefCell::new(v8_ctx),
-      http_client: reqwest::Client::new(),
+      http_client,
+      proxied_http_clients: RefCell::new(HashMap::new()),
+      egress_policy,
       mysql,
-      apns: d
-        .metadata
-        .apns
-        .iter()
-        .filter_map(|(k, v)| {
-          a2::Client::certificate(
-            &mut &v.cert[..],
-            "",
-            match v.endpoint {
-              ApnsEndpointMetadata::Production => a2::Endpoint::Production,
-              ApnsEndpointMetadata::Sandbox => a2::Endpoint::Sandbox,
-            },
-          )
-          .ok()
-          .map(|x| (k.clone(), x))
-        })
-        .collect(),
+      apns,
+      fcm,
+      webpush,
       computation_watcher,
       last_invocation_time_after_full_gc: RefCell::new(None),
+      last_memory_pressure_time: RefCell::new(None),
     };
     let me: &'static BlueboatCtx = Box::leak(Box::new(me));
     me
   }
 
+  /// Returns the client for `proxy`, or the app's default client if no override is given.
+  pub fn fetch_client(&self, proxy: Option<&str>) -> Result<reqwest::Client> {
+    let proxy = match proxy {
+      Some(x) => x,
+      None => return Ok(self.http_client.clone()),
+    };
+    let mut clients = self.proxied_http_clients.borrow_mut();
+    if let Some(x) = clients.get(proxy) {
+      return Ok(x.clone());
+    }
+    let client = build_http_client(
+      Some(proxy),
+      &self.metadata.fetch,
+      self.egress_policy.clone(),
+    )?;
+    if clients.len() >= MAX_PROXY_CLIENTS {
+      clients.clear();
+    }
+    clients.insert(proxy.to_string(), client.clone());
+    Ok(client)
+  }
+
+  /// If the heap is close to its limit, gives the app's `onMemoryPressure` handler a chance to
+  /// drop caches and then performs a full GC. Best-effort: a handler that runs past the deadline
+  /// is terminated, and the context is reset.
+  pub fn check_memory_pressure(&self, isolate: &mut v8::Isolate) {
+    let mut stats = v8::HeapStatistics::default();
+    isolate.get_heap_statistics(&mut stats);
+    self.relieve_memory_pressure(isolate, stats.used_heap_size(), stats.heap_size_limit());
+  }
+
+  fn relieve_memory_pressure(&self, isolate: &mut v8::Isolate, used: usize, limit: usize) {
+    if (used as f64) < limit as f64 * MEMORY_PRESSURE_RATIO {
+      return;
+    }
+    {
+      let mut last = self.last_memory_pressure_time.borrow_mut();
+      if let Some(t) = *last {
+        if t.elapsed() < MEMORY_PRESSURE_INTERVAL {
+          return;
+        }
+      }
+      *last = Some(Instant::now());
+    }
+    log::warn!(
+      "app {}: memory pressure ({} of {} bytes used)",
+      self.key,
+      used,
+      limit
+    );
+
+    let handle = isolate.thread_safe_handle();
+    let abort_fence = Arc::new(Mutex::new(false));
+    let abort_fence_2 = abort_fence.clone();
+    let terminate_report = Arc::new(AtomicBool::new(false));
+    let terminate_report_2 = terminate_report.clone();
+    let deadline_watcher = self.computation_watcher.spawn(async move {
+      tokio::time::sleep(MEMORY_PRESSURE_DEADLINE).await;
+      let abort_fence = abort_fence.lock();
+      if !*abort_fence {
+        handle.terminate_execution();
+        terminate_report.store(true, Ordering::Relaxed);
+      }
+    });
+    {
+      let scope = &mut v8::HandleScope::new(&mut *isolate);
+      let context = v8::Local::new(scope, self.grab_v8_context());
+      let scope = &mut v8::ContextScope::new(scope, context);
+      {
+        let scope = &mut v8::TryCatch::new(scope);
+        let handler = context
+          .global(scope)
+          .get_ext(scope, "__blueboat_app_memory_pressure");
+        if let (Ok(handler), Ok(info)) = (
+          v8::Local::<v8::Function>::try_from(handler),
+          v8_serialize(
+            scope,
+            &MemoryPressureInfo {
+              used_heap_size: used,
+              heap_size_limit: limit,
+            },
+          ),
+        ) {
+          let undef = v8::undefined(scope);
+          handler.call(scope, undef.into(), &[info]);
+        }
+      }
+      deadline_watcher.abort();
+      *abort_fence_2.lock() = true;
+      if terminate_report_2.load(Ordering::Relaxed) {
+        write_applog(
+          scope,
+          AppLogLevel::Warn,
+          "memory pressure handler exceeded its deadline".into(),
+        );
+        log::error!("Resetting V8 context of app {}.", self.key);
+        self.reset_v8_context(scope);
+      }
+    }
+    isolate.low_memory_notification();
+  }
+
+  /// A context for tests around an isolate and a context that are already set up. The package has
+  /// only an empty index module, and there are no clients other than the HTTP client.
+  #[cfg(test)]
+  pub fn for_test(
+    metadata: Metadata,
+    mut isolate: v8::OwnedIsolate,
+    v8_ctx: v8::Global<v8::Context>,
+  ) -> &'static Self {
+    crate::server::init_test_tenancy(&metadata);
+    let (lp_tx, lp_rx) = smr::ipc_channel::ipc::channel::<LowPriorityMsg>().unwrap();
+    Box::leak(Box::new(lp_rx));
+    let rch =
+      crate::reliable_channel::create_reliable_channel(Arc::new(metadata.clone())).run_forever();
+    let d: &'static BlueboatInitData = Box::leak(Box::new(BlueboatInitData {
+      key: PackageKey {
+        path: metadata.path.clone(),
+        version: metadata.version.clone(),
+      },
+      metadata,
+      lp_tx,
+      rch: None,
+    }));
+    isolate.set_slot(d);
+    let egress_policy = Arc::new(EgressPolicy::unrestricted());
+    let http_client = build_http_client(None, &d.metadata.fetch, egress_policy.clone()).unwrap();
+    Box::leak(Box::new(Self {
+      key: &d.key,
+      metadata: &d.metadata,
+      lp_tx: &d.lp_tx,
+      rch,
+      isolate: Mutex::new(isolate),
+      v8_ctx: RefCell::new(v8_ctx),
+      http_client,
+      proxied_http_clients: RefCell::new(HashMap::new()),
+      egress_policy,
+      mysql: HashMap::new(),
+      apns: HashMap::new(),
+      fcm: HashMap::new(),
+      webpush: HashMap::new(),
+      computation_watcher: spawn_computation_watcher(),
+      last_invocation_time_after_full_gc: RefCell::new(None),
+      last_memory_pressure_time: RefCell::new(None),
+    }))
+  }
+
   pub fn grab_v8_context<'s>(&self) -> v8::Global<v8::Context> {
     (*self.v8_ctx.borrow()).clone()
   }
@@ -254,6 +494,8 @@ impl BlueboatCtx {
       let bootstrap_data = BlueboatBootstrapData {
         mysql: md.mysql.keys().cloned().collect(),
         apns: md.apns.keys().cloned().collect(),
+        fcm: md.fcm.keys().cloned().collect(),
+        webpush: md.webpush.keys().cloned().collect(),
         env: md.env.clone(),
         pubsub: md.pubsub.keys().cloned().collect(),
       };
@@ -265,28 +507,81 @@ impl BlueboatCtx {
           let undef = v8::undefined(catch);
           f.call(catch, undef.into(), &[bootstrap_data]);
           if let Some(exc) = catch.exception() {
-            return Err(PackageInitError(exc.to_rust_string_lossy(catch)).into());
+            return Err(PackageInitError(describe_exception(catch, exc)).into());
           }
         }
       }
 
-      let index = package.load_module_with_dependencies(scope, "");
-      if let Some(index) = index {
-        let _ = index.evaluate(scope);
-        if matches!(index.get_status(), v8::ModuleStatus::Errored) {
-          let exc = index.get_exception();
-          let stack = v8::Local::<v8::Object>::try_from(exc)
-            .ok()
-            .map(|x| x.get_ext(scope, "stack").to_rust_string_lossy(scope))
-            .unwrap_or_default();
-          return Err(PackageInitError(stack).into());
+      // Compile and link errors are thrown while loading, evaluation errors are kept on the module.
+      let catch = &mut v8::TryCatch::new(scope);
+      let index = match package.load_module_with_dependencies(catch, "") {
+        Some(x) => x,
+        None => {
+          let message = match catch.exception() {
+            Some(exc) => describe_exception(catch, exc),
+            None => "failed to load modules".into(),
+          };
+          return Err(PackageInitError(message).into());
         }
+      };
+      let _ = index.evaluate(catch);
+      if matches!(index.get_status(), v8::ModuleStatus::Errored) {
+        let exc = index.get_exception();
+        return Err(PackageInitError(describe_exception(catch, exc)).into());
       }
     }
     Ok(v8::Global::new(scope, ctx))
   }
 }
 
+/// Starts the runtime that terminates executions that run for too long. It has its own thread, so
+/// that it isn't blocked by the execution it watches.
+fn spawn_computation_watcher() -> Handle {
+  let rt = tokio::runtime::Builder::new_current_thread()
+    .enable_time()
+    .build()
+    .unwrap();
+  let handle = rt.handle().clone();
+  std::thread::spawn(move || {
+    rt.block_on(futures::future::pending::<()>());
+  });
+  handle
+}
+
+#[derive(Error, Debug)]
+#[error("mysql configuration contains disallowed keys")]
+struct DisallowedMysqlConfig;
+
+fn build_mysql_pool(url: &str, md: &MysqlMetadata) -> Result<(mysql_async::Pool, StmtCache)> {
+  let mut opts = mysql_async::Opts::from_url(url)?;
+  if opts.socket().is_some() || opts.ssl_opts().and_then(|x| x.root_cert_path()).is_some() {
+    return Err(DisallowedMysqlConfig.into());
+  }
+  if let Some(cert) = &md.root_certificate {
+    let cert = cert.as_str();
+    let cert_data: Cow<[u8]> = if cert == "default" {
+      Cow::Borrowed(CACERT_PEM)
+    } else {
+      Cow::Owned(cert.as_bytes().to_vec())
+    };
+    let ssl = opts.ssl_opts().cloned().unwrap_or_default();
+    opts
+      .try_set_ssl_opts(ssl.with_root_cert_data(Some(cert_data)))
+      .ok()
+      .expect("failed to set ssl opts");
+  }
+  // Prepared statements are cached by `StmtCache` rather than by the connection, so that they are
+  // invalidated when the pool recycles the connection.
+  let stmts = StmtCache::new(
+    md.stmt_cache_size.unwrap_or(DEFAULT_STMT_CACHE_SIZE),
+    opts.pool_opts().constraints().max(),
+  );
+  opts = mysql_async::OptsBuilder::from_opts(opts)
+    .stmt_cache_size(0)
+    .into();
+  Ok((mysql_async::Pool::new(opts), stmts))
+}
+
 pub fn native_invoke_entry(
   scope: &mut v8::HandleScope,
   args: v8::FunctionCallbackArguments,
@@ -345,3 +640,118 @@ fn native_invoke_entry_impl<'s>(
     );
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use std::time::{Duration, Instant};
+
+  use serde::Deserialize;
+  use tokio::sync::watch;
+
+  use super::{BlueboatCtx, MEMORY_PRESSURE_DEADLINE, MEMORY_PRESSURE_INTERVAL};
+  use crate::{
+    api::testutil::{eval, ApiTester},
+    exec::Executor,
+    metadata::Metadata,
+  };
+
+  fn test_ctx() -> &'static BlueboatCtx {
+    let metadata: Metadata = serde_json::from_value(serde_json::json!({
+      "version": "1",
+      "package": "",
+      "env": {},
+    }))
+    .unwrap();
+    ApiTester::new().into_ctx(metadata)
+  }
+
+  fn eval_in_app<T: for<'a> Deserialize<'a>>(ctx: &BlueboatCtx, text: &str) -> T {
+    let mut isolate = ctx.isolate.lock();
+    let scope = &mut v8::HandleScope::new(&mut *isolate);
+    let context = v8::Local::new(scope, ctx.grab_v8_context());
+    let scope = &mut v8::ContextScope::new(scope, context);
+    eval(scope, text)
+  }
+
+  fn relieve(ctx: &BlueboatCtx, used: usize, limit: usize) {
+    let mut isolate = ctx.isolate.lock();
+    ctx.relieve_memory_pressure(&mut isolate, used, limit);
+  }
+
+  #[test]
+  fn handler_is_called_over_the_threshold() {
+    let ctx = test_ctx();
+    assert!(eval_in_app::<bool>(
+      ctx,
+      r#"
+        globalThis.calls = [];
+        App.onMemoryPressure((info) => { calls.push(info); });
+        true
+      "#,
+    ));
+
+    relieve(ctx, 79, 100);
+    assert_eq!(eval_in_app::<usize>(ctx, "calls.length"), 0);
+
+    relieve(ctx, 90, 100);
+    let calls: Vec<(usize, usize)> =
+      eval_in_app(ctx, "calls.map((x) => [x.usedHeapSize, x.heapSizeLimit])");
+    assert_eq!(calls, vec![(90, 100)]);
+  }
+
+  #[test]
+  fn handler_is_rate_limited() {
+    let ctx = test_ctx();
+    assert!(eval_in_app::<bool>(
+      ctx,
+      r#"
+        globalThis.calls = 0;
+        App.onMemoryPressure(() => { calls++; });
+        true
+      "#,
+    ));
+
+    relieve(ctx, 90, 100);
+    relieve(ctx, 95, 100);
+    assert_eq!(eval_in_app::<u32>(ctx, "calls"), 1);
+
+    // Once the interval has passed, the handler is called again.
+    *ctx.last_memory_pressure_time.borrow_mut() =
+      Some(Instant::now() - MEMORY_PRESSURE_INTERVAL - Duration::from_secs(1));
+    relieve(ctx, 95, 100);
+    assert_eq!(eval_in_app::<u32>(ctx, "calls"), 2);
+  }
+
+  #[test]
+  fn spinning_handler_is_terminated_and_context_is_reset() {
+    let ctx = test_ctx();
+    assert!(eval_in_app::<bool>(
+      ctx,
+      r#"
+        globalThis.spun = true;
+        App.onMemoryPressure(() => { for (;;) {} });
+        true
+      "#,
+    ));
+
+    let start = Instant::now();
+    relieve(ctx, 90, 100);
+    assert!(start.elapsed() >= MEMORY_PRESSURE_DEADLINE);
+
+    // The next request runs in a fresh context.
+    let rt = tokio::runtime::Builder::new_current_thread()
+      .enable_all()
+      .build()
+      .unwrap();
+    tokio::task::LocalSet::new().block_on(&rt, async {
+      let (_cancel_tx, cancel) = watch::channel(());
+      let (exec, _spawn_activity) = Executor::new(ctx, "test".into(), cancel).unwrap();
+      let weak = exec.downgrade();
+      let fresh: bool = Executor::enter(&weak, |scope| {
+        eval(scope, "typeof globalThis.spun === 'undefined'")
+      })
+      .unwrap();
+      assert!(fresh);
+    });
+  }
+}
@@ -0,0 +1,231 @@
+use std::{
+  net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs},
+  str::FromStr,
+  sync::Arc,
+};
+
+use anyhow::Result;
+use ipnet::IpNet;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use thiserror::Error;
+use url::Host;
+
+use crate::metadata::FetchMetadata;
+
+/// Private, loopback, link-local (including cloud metadata endpoints) and other special-purpose
+/// ranges that apps cannot reach unless explicitly allowed.
+const DEFAULT_DENY: &[&str] = &[
+  "0.0.0.0/8",
+  "10.0.0.0/8",
+  "100.64.0.0/10",
+  "127.0.0.0/8",
+  "169.254.0.0/16",
+  "172.16.0.0/12",
+  "192.0.0.0/24",
+  "192.168.0.0/16",
+  "198.18.0.0/15",
+  "224.0.0.0/4",
+  "240.0.0.0/4",
+  "::/128",
+  "::1/128",
+  "fc00::/7",
+  "fe80::/10",
+  "ff00::/8",
+];
+
+#[derive(Error, Debug, Clone)]
+#[error("destination `{0}` is blocked by the egress policy")]
+pub struct BlockedDestination(pub String);
+
+#[derive(Error, Debug)]
+#[error("invalid cidr in egress policy: {0}")]
+pub struct InvalidCidr(String);
+
+/// Decides which addresses outbound connections may go to.
+#[derive(Debug, Default)]
+pub struct EgressPolicy {
+  allow: Vec<IpNet>,
+  deny: Vec<IpNet>,
+}
+
+impl EgressPolicy {
+  pub fn from_metadata(md: &FetchMetadata) -> Result<Self> {
+    let parse = |x: &str| IpNet::from_str(x).map_err(|_| InvalidCidr(x.to_string()));
+    Ok(Self {
+      allow: md
+        .allow
+        .iter()
+        .map(|x| parse(x))
+        .collect::<Result<_, _>>()?,
+      deny: DEFAULT_DENY
+        .iter()
+        .copied()
+        .chain(md.deny.iter().map(|x| x.as_str()))
+        .map(parse)
+        .collect::<Result<_, _>>()?,
+    })
+  }
+
+  /// A policy that allows everything.
+  pub fn unrestricted() -> Self {
+    Self::default()
+  }
+
+  pub fn is_blocked(&self, ip: IpAddr) -> bool {
+    let ip = embedded_ipv4(ip);
+    self.deny.iter().any(|x| x.contains(&ip)) && !self.allow.iter().any(|x| x.contains(&ip))
+  }
+
+  pub fn check(&self, ip: IpAddr) -> Result<(), BlockedDestination> {
+    if self.is_blocked(ip) {
+      Err(BlockedDestination(ip.to_string()))
+    } else {
+      Ok(())
+    }
+  }
+
+  /// Checks every address of `host`, for connections that don't go through `PolicyResolver`:
+  /// the proxy itself, and destinations that a proxy resolves on its own.
+  pub async fn check_host(&self, host: Host<&str>, port: u16) -> Result<()> {
+    match host {
+      Host::Ipv4(x) => self.check(x.into())?,
+      Host::Ipv6(x) => self.check(x.into())?,
+      Host::Domain(x) => {
+        for addr in tokio::net::lookup_host((x, port)).await? {
+          self.check(addr.ip())?;
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// Like `check_host`, but resolves with the blocking system resolver.
+  pub fn check_host_blocking(&self, host: Host<&str>, port: u16) -> Result<()> {
+    match host {
+      Host::Ipv4(x) => self.check(x.into())?,
+      Host::Ipv6(x) => self.check(x.into())?,
+      Host::Domain(x) => {
+        for addr in (x, port).to_socket_addrs()? {
+          self.check(addr.ip())?;
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+/// IPv6 addresses that carry an IPv4 address are treated as that address, so that v4 ranges can't
+/// be bypassed through them: IPv4-mapped `::ffff:a.b.c.d`, IPv4-compatible `::a.b.c.d`, NAT64
+/// `64:ff9b::a.b.c.d` and 6to4 `2002:aabb:ccdd::`. The unspecified and loopback addresses, which
+/// fall in the IPv4-compatible range, are left alone.
+fn embedded_ipv4(ip: IpAddr) -> IpAddr {
+  let v6 = match ip {
+    IpAddr::V6(x) => x,
+    IpAddr::V4(_) => return ip,
+  };
+  let (hi, lo) = match v6.segments() {
+    [0, 0, 0, 0, 0, 0, 0, 0] | [0, 0, 0, 0, 0, 0, 0, 1] => return ip,
+    [0, 0, 0, 0, 0, 0xffff, hi, lo] => (hi, lo),
+    [0, 0, 0, 0, 0, 0, hi, lo] => (hi, lo),
+    [0x64, 0xff9b, 0, 0, 0, 0, hi, lo] => (hi, lo),
+    [0x2002, hi, lo, ..] => (hi, lo),
+    _ => return ip,
+  };
+  IpAddr::V4(Ipv4Addr::new(
+    (hi >> 8) as u8,
+    hi as u8,
+    (lo >> 8) as u8,
+    lo as u8,
+  ))
+}
+
+/// DNS resolver that drops addresses blocked by the policy. Checking the resolved addresses right
+/// before connecting, instead of the hostname, closes the DNS rebinding gap.
+pub struct PolicyResolver {
+  pub policy: Arc<EgressPolicy>,
+}
+
+impl Resolve for PolicyResolver {
+  fn resolve(&self, name: Name) -> Resolving {
+    let policy = self.policy.clone();
+    Box::pin(async move {
+      let host = name.as_str().to_string();
+      let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+        .await?
+        .filter(|x| !policy.is_blocked(x.ip()))
+        .collect();
+      if addrs.is_empty() {
+        return Err(Box::new(BlockedDestination(host)) as _);
+      }
+      Ok(Box::new(addrs.into_iter()) as Addrs)
+    })
+  }
+}
+
+/// Finds a `BlockedDestination` anywhere in the cause chain of `e`. Resolver errors reach the
+/// caller wrapped in several layers of reqwest and hyper errors.
+pub fn find_blocked_destination(e: &anyhow::Error) -> Option<BlockedDestination> {
+  e.chain()
+    .find_map(|x| x.downcast_ref::<BlockedDestination>())
+    .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::EgressPolicy;
+  use crate::metadata::FetchMetadata;
+
+  #[test]
+  fn default_policy() {
+    let policy = EgressPolicy::from_metadata(&FetchMetadata::default()).unwrap();
+    for x in [
+      "127.0.0.1",
+      "10.1.2.3",
+      "172.20.0.1",
+      "192.168.1.1",
+      "169.254.169.254",
+      "::1",
+      "::ffff:169.254.169.254",
+      "::169.254.169.254",
+      "::a9fe:a9fe",
+      "64:ff9b::a9fe:a9fe",
+      "64:ff9b::10.0.0.1",
+      "2002:a9fe:a9fe::1",
+      "2002:7f00:1::",
+      "fd00::1",
+    ] {
+      assert!(policy.is_blocked(x.parse().unwrap()), "{}", x);
+    }
+    for x in [
+      "1.1.1.1",
+      "93.184.216.34",
+      "2606:4700::1111",
+      "64:ff9b::101:101",
+      "2002:101:101::1",
+    ] {
+      assert!(!policy.is_blocked(x.parse().unwrap()), "{}", x);
+    }
+  }
+
+  #[test]
+  fn allow_and_deny() {
+    let policy = EgressPolicy::from_metadata(&FetchMetadata {
+      allow: vec!["10.1.0.0/16".into()],
+      deny: vec!["1.1.1.0/24".into()],
+      ..Default::default()
+    })
+    .unwrap();
+    assert!(!policy.is_blocked("10.1.2.3".parse().unwrap()));
+    assert!(policy.is_blocked("10.2.0.1".parse().unwrap()));
+    assert!(policy.is_blocked("1.1.1.1".parse().unwrap()));
+    // Embedded v4 addresses follow the v4 rules both ways.
+    assert!(!policy.is_blocked("64:ff9b::a01:203".parse().unwrap()));
+    assert!(policy.is_blocked("2002:101:101::".parse().unwrap()));
+
+    assert!(EgressPolicy::from_metadata(&FetchMetadata {
+      deny: vec!["not-a-cidr".into()],
+      ..Default::default()
+    })
+    .is_err());
+  }
+}
@@ -10,12 +10,21 @@ use std::{
   time::{Duration, Instant},
 };
 
-use crate::{ctx::BlueboatCtx, ipc::BlueboatIpcRes};
+use crate::{
+  api::{
+    crypto::stream::StreamingDigest, mysql::MysqlCursor, util::write_applog,
+    websocket::WebSocketConn,
+  },
+  app_mysql::StmtCache,
+  ctx::BlueboatCtx,
+  ipc::BlueboatIpcRes,
+  lpch::AppLogLevel,
+};
 use anyhow::Result;
 use parking_lot::Mutex;
 use thiserror::Error;
 use tokio::{
-  sync::{watch, Mutex as AsyncMutex, OwnedMutexGuard, OwnedRwLockWriteGuard, RwLock},
+  sync::{oneshot, watch, Mutex as AsyncMutex, OwnedMutexGuard, OwnedRwLockWriteGuard, RwLock},
   task::spawn_local,
 };
 use v8;
@@ -25,7 +34,7 @@ pub struct Executor {
   v8_ctx: v8::Global<v8::Context>,
 
   async_kill: AsyncKill,
-  _async_kill_owner: OwnedRwLockWriteGuard<()>,
+  async_kill_owner: RefCell<Option<OwnedRwLockWriteGuard<()>>>,
 
   async_completion: AsyncKill,
   async_completion_owner: RefCell<Option<OwnedRwLockWriteGuard<()>>>,
@@ -34,17 +43,79 @@ pub struct Executor {
   spawn_activity_maybe_owner: Weak<OwnedMutexGuard<()>>,
 
   completed_result: RefCell<Option<BlueboatIpcRes>>,
-  pub busy_duration: Cell<Duration>,
+  pub cpu: CpuBudget,
   pub request_id: String,
   logseq: Cell<i32>,
   cancel: watch::Receiver<()>,
   pub mysql: Rc<AsyncMutex<HashMap<&'static str, Arc<AsyncMutex<ExecutorMysqlState>>>>>,
+
+  /// In-flight streaming digests. Dropped together with the executor when the request completes.
+  pub digests: RefCell<HashMap<u32, StreamingDigest>>,
+
+  /// Active KV watches. Dropping the sender stops the watch.
+  pub kv_watches: RefCell<HashMap<u32, oneshot::Sender<()>>>,
+
+  /// Streaming fetch responses whose bodies are still being read.
+  pub fetch_streams: RefCell<HashMap<u32, reqwest::Response>>,
+
+  /// Open WebSocket connections. Closed when the executor is dropped.
+  pub websockets: RefCell<HashMap<u32, WebSocketConn>>,
+
+  /// Streaming MySQL queries. Their connections go back to the pool when the executor is dropped.
+  pub mysql_cursors: RefCell<HashMap<u32, MysqlCursor>>,
+
+  next_handle: Cell<u32>,
 }
 
 pub struct ExecutorMysqlState {
   pub conn: Option<mysql_async::Conn>,
   pub txn: Option<mysql_async::Transaction<'static>>,
+
+  /// Set when `txn` was rolled back because a query timed out, so that the app's commit fails.
+  pub txn_aborted: bool,
   pub pool: &'static mysql_async::Pool,
+  pub stmts: &'static StmtCache,
+
+  /// Connection for `readonly` queries outside of transactions.
+  pub replica_conn: Option<mysql_async::Conn>,
+  pub replica_pool: &'static mysql_async::Pool,
+  pub replica_stmts: &'static StmtCache,
+}
+
+/// CPU time of a request. Only time spent in `Executor::enter` is charged, so awaiting I/O
+/// doesn't use up the budget.
+pub struct CpuBudget {
+  limit: Option<Duration>,
+  used: Cell<Duration>,
+  exceeded: Cell<bool>,
+}
+
+impl CpuBudget {
+  pub fn new(limit: Option<Duration>) -> Self {
+    Self {
+      limit,
+      used: Cell::new(Duration::ZERO),
+      exceeded: Cell::new(false),
+    }
+  }
+
+  pub fn used(&self) -> Duration {
+    self.used.get()
+  }
+
+  /// Whether the request was terminated for running out of budget.
+  pub fn exceeded(&self) -> bool {
+    self.exceeded.get()
+  }
+
+  fn charge(&self, d: Duration) {
+    self.used.set(self.used.get() + d);
+  }
+
+  /// Time left before execution is terminated, or `None` if unlimited.
+  fn remaining(&self) -> Option<Duration> {
+    self.limit.map(|x| x.saturating_sub(self.used.get()))
+  }
 }
 
 #[derive(Clone)]
@@ -96,6 +167,13 @@ impl Executor {
     v
   }
 
+  /// Allocates a request-scoped handle for native resources (streaming digests, KV watches, etc.).
+  pub fn allocate_handle(&self) -> u32 {
+    let v = self.next_handle.get();
+    self.next_handle.set(v.wrapping_add(1));
+    v
+  }
+
   pub fn get_cancel(&self) -> watch::Receiver<()> {
     self.cancel.clone()
   }
@@ -107,7 +185,7 @@ impl Executor {
   ) -> Result<(Rc<Self>, SpawnActivityOwner)> {
     let v8_ctx = ctx.grab_v8_context();
     let async_kill = AsyncKill(Arc::new(RwLock::new(())));
-    let _async_kill_owner = async_kill.0.clone().try_write_owned().unwrap();
+    let async_kill_owner = RefCell::new(Some(async_kill.0.clone().try_write_owned().unwrap()));
     let async_completion = AsyncKill(Arc::new(RwLock::new(())));
     let async_completion_owner =
       RefCell::new(Some(async_completion.0.clone().try_write_owned().unwrap()));
@@ -120,17 +198,23 @@ impl Executor {
       ctx,
       v8_ctx,
       async_kill,
-      _async_kill_owner,
+      async_kill_owner,
       async_completion,
       async_completion_owner,
       spawn_activity,
       spawn_activity_maybe_owner,
       completed_result: RefCell::new(None),
-      busy_duration: Cell::new(Duration::ZERO),
+      cpu: CpuBudget::new(ctx.metadata.cpu_budget_ms.map(Duration::from_millis)),
       request_id,
       logseq: Cell::new(0),
       cancel,
       mysql: Rc::new(AsyncMutex::new(HashMap::new())),
+      digests: RefCell::new(HashMap::new()),
+      kv_watches: RefCell::new(HashMap::new()),
+      fetch_streams: RefCell::new(HashMap::new()),
+      websockets: RefCell::new(HashMap::new()),
+      mysql_cursors: RefCell::new(HashMap::new()),
+      next_handle: Cell::new(0),
     });
     Ok((me, spawn_activity_owner))
   }
@@ -192,6 +276,11 @@ impl Executor {
     let scope = &mut v8::HandleScope::new(scope);
 
     let mut cancel = me.get_cancel();
+    let remaining_budget = me.cpu.remaining();
+    if remaining_budget == Some(Duration::ZERO) {
+      me.fail_cpu_budget(scope);
+      return None;
+    }
 
     // A fence that prevents aborting the isolate after `enter()` returns.
     let abort_fence = Arc::new(Mutex::new(false));
@@ -199,12 +288,28 @@ impl Executor {
     let terminate_report = Arc::new(AtomicBool::new(false));
     let terminate_report_2 = terminate_report.clone();
 
-    let computation_watcher = me.ctx.computation_watcher.spawn(async move {
-      let _ = cancel.changed().await;
+    let budget_report = Arc::new(AtomicBool::new(false));
+    let budget_report_2 = budget_report.clone();
 
-      // Grace period before performing a synchronous termination. Restarting the context is an
-      // expensive operation.
-      tokio::time::sleep(Duration::from_millis(100)).await;
+    let computation_watcher = me.ctx.computation_watcher.spawn(async move {
+      let budget = async move {
+        match remaining_budget {
+          Some(x) => tokio::time::sleep(x).await,
+          None => futures::future::pending().await,
+        }
+      };
+      tokio::select! {
+        _ = async {
+          let _ = cancel.changed().await;
+
+          // Grace period before performing a synchronous termination. Restarting the context is
+          // an expensive operation.
+          tokio::time::sleep(Duration::from_millis(100)).await;
+        } => {}
+        _ = budget => {
+          budget_report.store(true, Ordering::Relaxed);
+        }
+      }
 
       let abort_fence = abort_fence.lock();
       if !*abort_fence {
@@ -212,13 +317,12 @@ impl Executor {
         terminate_report.store(true, Ordering::Relaxed);
       }
     });
-    let (ret, exc) = {
+    let (ret, exc, terminated) = {
       let mut catch = v8::TryCatch::new(scope);
       let ret = f(&mut catch);
-      (ret, catch.exception())
+      (ret, catch.exception(), catch.has_terminated())
     };
-    me.busy_duration
-      .set(me.busy_duration.get() + start.elapsed());
+    me.cpu.charge(start.elapsed());
 
     // Abort the watcher task.
     // XXX: Is this synchronous or asynchronous?
@@ -228,7 +332,12 @@ impl Executor {
     *abort_fence_2.lock() = true;
 
     // If this is an abnormal termination, don't reuse the context. And the exception is useless.
-    if terminate_report_2.load(Ordering::Relaxed) {
+    // Besides the watcher, native APIs may terminate execution, e.g. when wasm memory exceeds the
+    // isolate memory limit.
+    if terminate_report_2.load(Ordering::Relaxed) || terminated {
+      if budget_report_2.load(Ordering::Relaxed) {
+        me.fail_cpu_budget(scope);
+      }
       log::error!("Resetting V8 context of app {}.", me.ctx.key);
       me.ctx.reset_v8_context(scope);
       return None;
@@ -249,8 +358,124 @@ impl Executor {
   }
 }
 
+impl Executor {
+  /// Ends a request that ran out of CPU budget. Outstanding async work (timers, fetches, queries)
+  /// is stopped, so that the request completes with an error right away instead of waiting for
+  /// callbacks that could no longer run.
+  fn fail_cpu_budget(&self, isolate: &mut v8::Isolate) {
+    if !self.cpu.exceeded.replace(true) {
+      write_applog(
+        isolate,
+        AppLogLevel::Error,
+        format!(
+          "request exceeded its cpu budget of {} ms",
+          self.ctx.metadata.cpu_budget_ms.unwrap_or_default()
+        ),
+      );
+    }
+    *self.async_kill_owner.borrow_mut() = None;
+  }
+}
+
 impl AsyncKill {
   async fn wait(&self) {
     self.0.read().await;
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use tokio::sync::watch;
+
+  use super::Executor;
+  use crate::{
+    api::testutil::{eval, ApiTester},
+    metadata::Metadata,
+  };
+
+  #[test]
+  fn io_wait_is_not_charged() {
+    let metadata: Metadata = serde_json::from_value(serde_json::json!({
+      "version": "1",
+      "package": "",
+      "env": {},
+      "cpu_budget_ms": 100,
+    }))
+    .unwrap();
+    let ctx = ApiTester::new().into_ctx(metadata);
+    let rt = tokio::runtime::Builder::new_current_thread()
+      .enable_all()
+      .build()
+      .unwrap();
+    tokio::task::LocalSet::new().block_on(&rt, async {
+      let (_cancel_tx, cancel) = watch::channel(());
+      let (exec, spawn_activity) = Executor::new(ctx, "test".into(), cancel).unwrap();
+      let weak = exec.downgrade();
+
+      // Sleeping for longer than the budget doesn't use it up.
+      let started: bool = Executor::enter(&weak, |scope| {
+        eval(
+          scope,
+          r#"
+            globalThis.woke = false;
+            __blueboat_host_invoke("sleep", 300, () => { globalThis.woke = true; });
+            true
+          "#,
+        )
+      })
+      .unwrap();
+      assert!(started);
+      drop(spawn_activity);
+      assert!(exec.wait_for_completion().await.is_none());
+
+      let woke: bool = Executor::enter(&weak, |scope| eval(scope, "woke")).unwrap();
+      assert!(woke);
+      assert!(exec.cpu.used() < Duration::from_millis(100));
+    });
+  }
+
+  #[test]
+  fn exhausted_budget_ends_the_request() {
+    let metadata: Metadata = serde_json::from_value(serde_json::json!({
+      "version": "1",
+      "package": "",
+      "env": {},
+      "cpu_budget_ms": 100,
+    }))
+    .unwrap();
+    let ctx = ApiTester::new().into_ctx(metadata);
+    let rt = tokio::runtime::Builder::new_current_thread()
+      .enable_all()
+      .build()
+      .unwrap();
+    tokio::task::LocalSet::new().block_on(&rt, async {
+      let (_cancel_tx, cancel) = watch::channel(());
+      let (exec, spawn_activity) = Executor::new(ctx, "test".into(), cancel).unwrap();
+      let weak = exec.downgrade();
+
+      let started: bool = Executor::enter(&weak, |scope| {
+        eval(
+          scope,
+          r#"
+            globalThis.woke = false;
+            __blueboat_host_invoke("sleep", 10, () => { globalThis.woke = true; });
+            __blueboat_host_invoke("sleep", 60000, () => {});
+            true
+          "#,
+        )
+      })
+      .unwrap();
+      assert!(started);
+      exec.cpu.charge(Duration::from_millis(100));
+      drop(spawn_activity);
+
+      // The first callback finds the budget used up, and the long sleep is stopped with it.
+      let res = tokio::time::timeout(Duration::from_secs(10), exec.wait_for_completion()).await;
+      assert!(res.unwrap().is_none());
+      assert!(exec.cpu.exceeded());
+      assert!(Executor::enter(&weak, |scope| eval::<bool>(scope, "woke")).is_none());
+    });
+  }
+}
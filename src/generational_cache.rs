@@ -0,0 +1,134 @@
+use std::{borrow::Borrow, collections::HashMap, hash::Hash};
+
+/// A bounded cache whose entries can all be invalidated at once by starting a new generation.
+///
+/// Entries from earlier generations are never returned, and are the first to go when space is
+/// needed. Within the current generation, the least recently used entry is evicted.
+pub struct GenerationalCache<K, V> {
+  capacity: usize,
+  generation: u64,
+  clock: u64,
+  entries: HashMap<K, Entry<V>>,
+}
+
+struct Entry<V> {
+  generation: u64,
+  last_used: u64,
+  value: V,
+}
+
+impl<K: Eq + Hash + Clone, V> GenerationalCache<K, V> {
+  /// A cache that holds at most `capacity` entries. With a capacity of zero, nothing is cached.
+  pub fn new(capacity: usize) -> Self {
+    Self {
+      capacity,
+      generation: 0,
+      clock: 0,
+      entries: HashMap::new(),
+    }
+  }
+
+  /// Starts a new generation, invalidating all entries.
+  pub fn advance(&mut self) {
+    self.generation += 1;
+  }
+
+  pub fn get<Q>(&mut self, k: &Q) -> Option<&V>
+  where
+    K: Borrow<Q>,
+    Q: Eq + Hash + ?Sized,
+  {
+    self.clock += 1;
+    let entry = self.entries.get_mut(k)?;
+    if entry.generation != self.generation {
+      return None;
+    }
+    entry.last_used = self.clock;
+    Some(&entry.value)
+  }
+
+  /// Inserts `value` into the current generation. Returns a live value that had to go to make
+  /// room for it: the previous value for `k`, the evicted entry, or `value` itself if the cache
+  /// has no capacity. Values from earlier generations are dropped silently.
+  pub fn insert(&mut self, k: K, value: V) -> Option<V> {
+    if self.capacity == 0 {
+      return Some(value);
+    }
+    self.clock += 1;
+    let entry = Entry {
+      generation: self.generation,
+      last_used: self.clock,
+      value,
+    };
+    if let Some(prev) = self.entries.insert(k, entry) {
+      return self.live(prev);
+    }
+    if self.entries.len() <= self.capacity {
+      return None;
+    }
+
+    let generation = self.generation;
+    self.entries.retain(|_, x| x.generation == generation);
+    if self.entries.len() <= self.capacity {
+      return None;
+    }
+    let lru = self
+      .entries
+      .iter()
+      .min_by_key(|(_, x)| x.last_used)
+      .map(|(k, _)| k.clone())
+      .unwrap();
+    let evicted = self.entries.remove(&lru).unwrap();
+    self.live(evicted)
+  }
+
+  fn live(&self, entry: Entry<V>) -> Option<V> {
+    if entry.generation == self.generation {
+      Some(entry.value)
+    } else {
+      None
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::GenerationalCache;
+
+  #[test]
+  fn advance_invalidates_entries() {
+    let mut cache = GenerationalCache::new(2);
+    assert_eq!(cache.insert("a", 1), None);
+    assert_eq!(cache.get("a"), Some(&1));
+
+    cache.advance();
+    assert_eq!(cache.get("a"), None);
+
+    // Stale values are not handed back when they are replaced.
+    assert_eq!(cache.insert("a", 2), None);
+    assert_eq!(cache.get("a"), Some(&2));
+    assert_eq!(cache.insert("a", 3), Some(2));
+  }
+
+  #[test]
+  fn evicts_stale_then_least_recently_used() {
+    let mut cache = GenerationalCache::new(2);
+    cache.insert("old", 0);
+    cache.advance();
+    assert_eq!(cache.insert("a", 1), None);
+    assert_eq!(cache.insert("b", 2), None);
+
+    cache.get("a");
+    assert_eq!(cache.insert("c", 3), Some(2));
+    assert_eq!(cache.get("a"), Some(&1));
+    assert_eq!(cache.get("b"), None);
+    assert_eq!(cache.get("c"), Some(&3));
+  }
+
+  #[test]
+  fn zero_capacity_caches_nothing() {
+    let mut cache = GenerationalCache::new(0);
+    assert_eq!(cache.insert("a", 1), Some(1));
+    assert_eq!(cache.get("a"), None);
+  }
+}
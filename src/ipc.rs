@@ -7,6 +7,7 @@ use std::{
 use anyhow::Result;
 use bytes::Bytes;
 use hyper::{
+  body::HttpBody,
   header::{HeaderName, HeaderValue},
   Body, StatusCode,
 };
@@ -20,10 +21,11 @@ use tokio::sync::watch;
 use v8;
 
 use crate::{
-  api::util::v8_serialize,
+  api::{crypto::DeterministicRng, util::v8_serialize},
   ctx::{BlueboatCtx, BlueboatInitData},
   exec::Executor,
   objserde::deserialize_v8_value,
+  profiling::{take_heap_snapshot, CpuProfiler, MAX_CPU_PROFILE_DURATION_MS},
   v8util::{create_arraybuffer_from_bytes, ObjectExt},
 };
 
@@ -38,6 +40,15 @@ pub enum BlueboatIpcReqV {
   Http(BlueboatRequest),
   Background(Vec<u8>),
   SseAuth(BlueboatRequest),
+
+  /// Takes a heap snapshot of the worker instead of invoking the app.
+  HeapSnapshot,
+
+  /// Samples the worker's CPU usage for a while instead of invoking the app.
+  CpuProfile {
+    duration_ms: u64,
+    sampling_interval_us: u32,
+  },
 }
 
 impl BaseRequest for BlueboatIpcReq {
@@ -54,6 +65,7 @@ impl Request for BlueboatIpcReq {
       loop {
         tokio::time::sleep(Duration::from_secs(2)).await;
         let mut isolate = ctx.isolate.try_lock().expect("isolate is locked");
+        ctx.check_memory_pressure(&mut isolate);
 
         let mut last_invocation_time = ctx.last_invocation_time_after_full_gc.borrow_mut();
         if let Some(t) = *last_invocation_time {
@@ -88,7 +100,46 @@ impl Request for BlueboatIpcReq {
     #[error("completion error")]
     struct CompletionError;
 
+    #[derive(Error, Debug)]
+    #[error("request exceeded its cpu budget")]
+    struct CpuBudgetExceeded;
+
+    match &self.v {
+      BlueboatIpcReqV::HeapSnapshot => {
+        let mut isolate = ctx.isolate.try_lock().expect("isolate is locked");
+        let body = take_heap_snapshot(&mut isolate)?;
+        return Ok(BlueboatIpcRes::json(body));
+      }
+      BlueboatIpcReqV::CpuProfile {
+        duration_ms,
+        sampling_interval_us,
+      } => {
+        let profiler = {
+          let mut isolate = ctx.isolate.try_lock().expect("isolate is locked");
+          let scope = &mut v8::HandleScope::new(&mut *isolate);
+          let context = v8::Local::new(scope, ctx.grab_v8_context());
+          CpuProfiler::start(scope, context, *sampling_interval_us)?
+        };
+        tokio::time::sleep(Duration::from_millis(
+          (*duration_ms).min(MAX_CPU_PROFILE_DURATION_MS),
+        ))
+        .await;
+        let _isolate = ctx.isolate.try_lock().expect("isolate is locked");
+        let body = profiler.stop()?;
+        return Ok(BlueboatIpcRes::json(body));
+      }
+      _ => {}
+    }
+
     *ctx.last_invocation_time_after_full_gc.borrow_mut() = Some(Instant::now());
+    if let Some(rng) = ctx
+      .isolate
+      .try_lock()
+      .expect("isolate is locked")
+      .get_slot::<DeterministicRng>()
+    {
+      rng.reset();
+    }
     let (exec, spawn_activity_owner) = Executor::new(ctx, self.id.clone(), cancel)?;
     let v = self.v;
     Executor::enter(&exec.downgrade(), move |scope| {
@@ -106,14 +157,18 @@ impl Request for BlueboatIpcReq {
     .unwrap()?;
     drop(spawn_activity_owner);
 
-    let res = exec
-      .wait_for_completion()
-      .await
-      .ok_or_else(|| CompletionError)?;
-    Ok(res)
+    match exec.wait_for_completion().await {
+      Some(x) => Ok(x),
+      None if exec.cpu.exceeded() => Err(CpuBudgetExceeded.into()),
+      None => Err(CompletionError.into()),
+    }
   }
 }
 
+#[derive(Error, Debug)]
+#[error("request is not an app invocation")]
+struct NotAnInvocation;
+
 impl BlueboatIpcReqV {
   fn build_invocation<'s>(
     self,
@@ -134,6 +189,7 @@ impl BlueboatIpcReqV {
         let req = v8_serialize(scope, &req)?;
         Ok(("__blueboat_app_sse_auth_entry", vec![req]))
       }
+      Self::HeapSnapshot | Self::CpuProfile { .. } => Err(NotAnInvocation.into()),
     }
   }
 }
@@ -144,8 +200,29 @@ pub struct BlueboatIpcRes {
   pub body: Bytes,
 }
 
+impl BlueboatIpcRes {
+  fn json(body: Vec<u8>) -> Self {
+    Self {
+      response: BlueboatResponse {
+        status: 200,
+        headers: [(
+          "content-type".to_string(),
+          vec!["application/json".to_string()],
+        )]
+        .into_iter()
+        .collect(),
+      },
+      body: body.into(),
+    }
+  }
+}
+
 impl Response for BlueboatIpcRes {}
 
+#[derive(Error, Debug)]
+#[error("request body exceeds limit of {0} bytes")]
+pub struct RequestBodyTooLarge(pub u64);
+
 #[derive(Serialize, Deserialize, JsonSchema)]
 pub struct BlueboatRequest {
   pub method: String,
@@ -155,13 +232,35 @@ pub struct BlueboatRequest {
 }
 
 impl BlueboatRequest {
-  pub async fn from_hyper(that: hyper::Request<Body>) -> Result<Self> {
+  /// Reads the request, failing with `RequestBodyTooLarge` as soon as the body is known to be
+  /// larger than `body_limit` bytes.
+  pub async fn from_hyper(that: hyper::Request<Body>, body_limit: u64) -> Result<Self> {
+    let declared_len = that
+      .headers()
+      .get(hyper::header::CONTENT_LENGTH)
+      .and_then(|x| x.to_str().ok())
+      .and_then(|x| x.parse::<u64>().ok());
+    if declared_len.map(|x| x > body_limit).unwrap_or(false) {
+      return Err(RequestBodyTooLarge(body_limit).into());
+    }
+
     let headers = decode_hyper_header_map(that.headers());
+    let method = that.method().to_string();
+    let uri = that.uri().to_string();
+    let mut body_stream = that.into_body();
+    let mut body = vec![];
+    while let Some(chunk) = body_stream.data().await {
+      let chunk = chunk?;
+      if (body.len() + chunk.len()) as u64 > body_limit {
+        return Err(RequestBodyTooLarge(body_limit).into());
+      }
+      body.extend_from_slice(&chunk);
+    }
     Ok(Self {
-      method: that.method().to_string(),
-      uri: that.uri().to_string(),
+      method,
+      uri,
       headers,
-      body: hyper::body::to_bytes(that.into_body()).await?.to_vec(),
+      body,
     })
   }
   pub fn from_hyper_no_body<T>(that: &hyper::Request<T>) -> Result<Self> {
@@ -193,14 +292,15 @@ pub struct BlueboatResponse {
 
 impl BlueboatResponse {
   pub async fn from_reqwest(that: reqwest::Response) -> Result<(Self, Bytes)> {
-    let headers = decode_hyper_header_map(that.headers());
-    Ok((
-      Self {
-        status: that.status().as_u16(),
-        headers,
-      },
-      that.bytes().await?,
-    ))
+    Ok((Self::from_reqwest_head(&that), that.bytes().await?))
+  }
+
+  /// Status and headers only, leaving the body unread.
+  pub fn from_reqwest_head(that: &reqwest::Response) -> Self {
+    Self {
+      status: that.status().as_u16(),
+      headers: decode_hyper_header_map(that.headers()),
+    }
   }
 
   pub fn into_hyper(self, body: Bytes) -> Result<hyper::Response<Body>> {
@@ -247,3 +347,38 @@ fn encode_hyper_header_map(m: &mut hyper::HeaderMap, source: &HashMap<String, Ve
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use hyper::Body;
+
+  use super::{BlueboatRequest, RequestBodyTooLarge};
+
+  fn mk_req(body: Body, content_length: Option<usize>) -> hyper::Request<Body> {
+    let mut req = hyper::Request::builder().method("POST").uri("/");
+    if let Some(x) = content_length {
+      req = req.header("content-length", x);
+    }
+    req.body(body).unwrap()
+  }
+
+  #[tokio::test]
+  async fn enforces_body_limit() {
+    let req = BlueboatRequest::from_hyper(mk_req(Body::from(vec![0u8; 16]), Some(16)), 16)
+      .await
+      .unwrap();
+    assert_eq!(req.body.len(), 16);
+
+    let err = BlueboatRequest::from_hyper(mk_req(Body::empty(), Some(17)), 16)
+      .await
+      .unwrap_err();
+    assert!(err.is::<RequestBodyTooLarge>());
+
+    // Chunked bodies are checked while reading.
+    let chunks = futures::stream::iter(vec![Ok::<_, std::io::Error>(vec![0u8; 10]); 2]);
+    let err = BlueboatRequest::from_hyper(mk_req(Body::wrap_stream(chunks), None), 16)
+      .await
+      .unwrap_err();
+    assert!(err.is::<RequestBodyTooLarge>());
+  }
+}
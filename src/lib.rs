@@ -1,9 +1,14 @@
 pub mod api;
 pub mod app_mysql;
+pub mod background;
+pub mod bgstore;
 pub mod bootstrap;
 pub mod consts;
+pub mod cron;
 pub mod ctx;
+pub mod egress;
 pub mod exec;
+pub mod generational_cache;
 pub mod gres;
 pub mod headers;
 pub mod ipc;
@@ -11,12 +16,14 @@ pub mod kvutil;
 pub mod logsvc;
 pub mod lpch;
 pub mod mds;
+pub mod metrics;
 pub mod metadata;
 pub mod mkimage;
 pub mod objserde;
 pub mod package;
 pub mod package_loader;
 pub mod pm;
+pub mod profiling;
 pub mod pubsub;
 pub mod registry;
 pub mod reliable_channel;
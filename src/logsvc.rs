@@ -1,5 +1,5 @@
 use std::{
-  collections::BTreeMap,
+  collections::{BTreeMap, VecDeque},
   sync::{
     atomic::{AtomicU64, Ordering},
     Arc,
@@ -9,16 +9,18 @@ use std::{
 
 use anyhow::Result;
 use lazy_static::lazy_static;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
 use rdkafka::{
   producer::{FutureProducer, FutureRecord, Producer},
   ClientConfig,
 };
 use regex::Regex;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use tracing::field::Field;
 use tracing_subscriber::Layer;
 
-use crate::lpch::AppLogEntry;
+use crate::lpch::{AppLogEntry, AppLogLevel};
 
 lazy_static! {
   static ref KAFKA_CONFIG_MATCHER: Regex = Regex::new("^([a-zA-Z0-9._-]+):([0-9]+)@(.+)$").unwrap();
@@ -30,6 +32,132 @@ thread_local! {
   static UNIQUE_TID: u64 = NEXT_TID.fetch_add(1, Ordering::Relaxed);
 }
 
+/// Number of apps whose recent lines are kept for tailing.
+const APPLOG_TAIL_MAX_APPS: u64 = 1000;
+
+/// Number of recent lines kept per app.
+const APPLOG_TAIL_LINES: usize = 1000;
+
+pub static APPLOG_TAIL: Lazy<AppLogTail> =
+  Lazy::new(|| AppLogTail::new(APPLOG_TAIL_MAX_APPS, APPLOG_TAIL_LINES));
+
+/// Recent applog lines of each app, kept by the server process in a ring buffer.
+pub struct AppLogTail {
+  lines_per_app: usize,
+  apps: moka::sync::Cache<String, Arc<Mutex<AppLogRing>>>,
+}
+
+#[derive(Default)]
+struct AppLogRing {
+  next_seq: u64,
+  entries: VecDeque<AppLogTailEntry>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AppLogTailEntry {
+  /// Position of the line in the app's tail, increasing by one per line.
+  pub seq: u64,
+  pub ts_ms: i64,
+  pub version: String,
+  pub request_id: String,
+  pub logseq: i32,
+  pub level: AppLogLevel,
+  pub message: String,
+
+  /// JSON object of a structured entry.
+  pub fields: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppLogTailPage {
+  pub entries: Vec<AppLogTailEntry>,
+
+  /// Pass as `after` to read the lines following this page.
+  pub cursor: u64,
+
+  /// Lines after the requested cursor were evicted before they were read.
+  pub missed: bool,
+}
+
+impl AppLogTail {
+  pub fn new(max_apps: u64, lines_per_app: usize) -> Self {
+    Self {
+      lines_per_app,
+      apps: moka::sync::Cache::new(max_apps),
+    }
+  }
+
+  pub fn push(&self, entry: &AppLogEntry) {
+    let ring = self.apps.get_with(entry.app.path.clone(), Default::default);
+    let mut ring = ring.lock();
+    let seq = ring.next_seq;
+    ring.next_seq += 1;
+    if ring.entries.len() >= self.lines_per_app {
+      ring.entries.pop_front();
+    }
+    ring.entries.push_back(AppLogTailEntry {
+      seq,
+      ts_ms: (entry.time.assume_utc() - time::OffsetDateTime::unix_epoch()).whole_milliseconds()
+        as i64,
+      version: entry.app.version.clone(),
+      request_id: entry.request_id.clone(),
+      logseq: entry.logseq,
+      level: entry.level,
+      message: entry.message.clone(),
+      fields: entry.fields.clone(),
+    });
+  }
+
+  /// Reads up to `limit` lines of the app at `path` following the cursor `after`,
+  /// or the last `limit` lines if `after` is `None`. Lines below `min_level` are
+  /// skipped but still advance the cursor.
+  pub fn read(
+    &self,
+    path: &str,
+    after: Option<u64>,
+    limit: usize,
+    min_level: AppLogLevel,
+  ) -> AppLogTailPage {
+    let ring = match self.apps.get(&path.to_string()) {
+      Some(x) => x,
+      None => {
+        return AppLogTailPage {
+          entries: vec![],
+          cursor: after.unwrap_or(0),
+          missed: false,
+        }
+      }
+    };
+    let ring = ring.lock();
+    let first_seq = ring.entries.front().map(|x| x.seq).unwrap_or(ring.next_seq);
+    let (start, missed) = match after {
+      Some(x) => (x.max(first_seq), x < first_seq),
+      None => (
+        ring.next_seq.saturating_sub(limit as u64).max(first_seq),
+        false,
+      ),
+    };
+    let mut cursor = start;
+    let mut entries = vec![];
+    for x in ring.entries.iter().skip((start - first_seq) as usize) {
+      if entries.len() == limit {
+        break;
+      }
+      cursor = x.seq + 1;
+      if x.level >= min_level {
+        entries.push(x.clone());
+      }
+    }
+    AppLogTailPage {
+      entries,
+      cursor,
+      missed,
+    }
+  }
+}
+
 fn unique_tid() -> String {
   format!("{}", UNIQUE_TID.with(|tid| *tid))
 }
@@ -116,7 +244,7 @@ impl LogService {
   }
 
   pub fn write_applog(&self, entry: AppLogEntry) {
-    let entry = match serde_json::to_string(&entry) {
+    let entry = match applog_json(&entry) {
       Ok(x) => x,
       Err(e) => {
         eprintln!("[applog] failed to serialize log entry: {}", e);
@@ -252,6 +380,16 @@ where
   }
 }
 
+/// Serializes `entry`, with structured fields embedded as a JSON object so that
+/// log processors can index them.
+fn applog_json(entry: &AppLogEntry) -> serde_json::Result<String> {
+  let mut value = serde_json::to_value(entry)?;
+  if let Some(fields) = &entry.fields {
+    value["fields"] = serde_json::from_str(fields)?;
+  }
+  serde_json::to_string(&value)
+}
+
 fn now_ts_secs_f64() -> f64 {
   SystemTime::now()
     .duration_since(SystemTime::UNIX_EPOCH)
@@ -317,3 +455,116 @@ impl<'a> tracing::field::Visit for JsonVisitor<'a> {
     };
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use time::{Date, PrimitiveDateTime, Time};
+
+  use super::{applog_json, AppLogTail};
+  use crate::{
+    lpch::{AppLogEntry, AppLogLevel},
+    package::PackageKey,
+  };
+
+  fn push(tail: &AppLogTail, path: &str, message: &str) {
+    push_at(tail, path, AppLogLevel::Info, message);
+  }
+
+  fn push_at(tail: &AppLogTail, path: &str, level: AppLogLevel, message: &str) {
+    tail.push(&AppLogEntry {
+      app: PackageKey {
+        path: path.into(),
+        version: "1".into(),
+      },
+      request_id: "req".into(),
+      message: message.into(),
+      logseq: 0,
+      time: PrimitiveDateTime::new(Date::try_from_ymd(1970, 1, 1).unwrap(), Time::midnight()),
+      level,
+      fields: None,
+    });
+  }
+
+  fn messages(page: &super::AppLogTailPage) -> Vec<&str> {
+    page.entries.iter().map(|x| x.message.as_str()).collect()
+  }
+
+  #[test]
+  fn tails_per_app() {
+    let tail = AppLogTail::new(10, 3);
+    for x in ["a", "b", "c", "d"] {
+      push(&tail, "app", x);
+    }
+    push(&tail, "other", "x");
+
+    let page = tail.read("app", None, 2, AppLogLevel::Debug);
+    assert_eq!(messages(&page), vec!["c", "d"]);
+    assert_eq!(page.cursor, 4);
+    assert!(!page.missed);
+
+    let page = tail.read("app", Some(page.cursor), 10, AppLogLevel::Debug);
+    assert!(page.entries.is_empty());
+    assert_eq!(page.cursor, 4);
+
+    push(&tail, "app", "e");
+    let page = tail.read("app", Some(4), 10, AppLogLevel::Debug);
+    assert_eq!(messages(&page), vec!["e"]);
+    assert_eq!(page.cursor, 5);
+
+    assert!(tail
+      .read("missing", None, 10, AppLogLevel::Debug)
+      .entries
+      .is_empty());
+  }
+
+  #[test]
+  fn reports_missed_lines() {
+    let tail = AppLogTail::new(10, 2);
+    for x in ["a", "b", "c", "d"] {
+      push(&tail, "app", x);
+    }
+    let page = tail.read("app", Some(1), 10, AppLogLevel::Debug);
+    assert_eq!(messages(&page), vec!["c", "d"]);
+    assert!(page.missed);
+  }
+
+  #[test]
+  fn filters_by_level() {
+    let tail = AppLogTail::new(10, 10);
+    push_at(&tail, "app", AppLogLevel::Debug, "a");
+    push_at(&tail, "app", AppLogLevel::Error, "b");
+    push_at(&tail, "app", AppLogLevel::Info, "c");
+    push_at(&tail, "app", AppLogLevel::Warn, "d");
+
+    let page = tail.read("app", Some(0), 10, AppLogLevel::Warn);
+    assert_eq!(messages(&page), vec!["b", "d"]);
+    assert_eq!(page.cursor, 4);
+  }
+
+  #[test]
+  fn embeds_structured_fields() {
+    let mut entry = AppLogEntry {
+      app: PackageKey {
+        path: "app".into(),
+        version: "1".into(),
+      },
+      request_id: "req".into(),
+      message: "order placed".into(),
+      logseq: 3,
+      time: PrimitiveDateTime::new(Date::try_from_ymd(1970, 1, 1).unwrap(), Time::midnight()),
+      level: AppLogLevel::Warn,
+      fields: Some(r#"{"order_id":42,"tags":["a"]}"#.into()),
+    };
+    let value: serde_json::Value = serde_json::from_str(&applog_json(&entry).unwrap()).unwrap();
+    assert_eq!(value["level"], "warn");
+    assert_eq!(value["message"], "order placed");
+    assert_eq!(
+      value["fields"],
+      serde_json::json!({ "order_id": 42, "tags": ["a"] })
+    );
+
+    entry.fields = None;
+    let value: serde_json::Value = serde_json::from_str(&applog_json(&entry).unwrap()).unwrap();
+    assert_eq!(value["fields"], serde_json::Value::Null);
+  }
+}
@@ -1,12 +1,23 @@
+use std::{collections::BTreeMap, time::Duration};
+
 use serde::{Deserialize, Serialize};
 use time::PrimitiveDateTime;
 
-use crate::package::PackageKey;
+use crate::{metrics::MetricOp, package::PackageKey};
 
 #[derive(Serialize, Deserialize)]
 pub enum LowPriorityMsg {
   Log(AppLogEntry),
   Background(BackgroundEntry),
+  Metric(MetricEntry),
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MetricEntry {
+  pub app: PackageKey,
+  pub name: String,
+  pub labels: BTreeMap<String, String>,
+  pub op: MetricOp,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -16,9 +27,32 @@ pub struct AppLogEntry {
   pub message: String,
   pub logseq: i32,
   pub time: PrimitiveDateTime,
+
+  #[serde(default)]
+  pub level: AppLogLevel,
+
+  /// Key/value fields of a structured entry, as a JSON object. Kept as a string
+  /// because IPC messages are not self-describing.
+  #[serde(default)]
+  pub fields: Option<String>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum AppLogLevel {
+  Debug,
+  Info,
+  Warn,
+  Error,
+}
+
+impl Default for AppLogLevel {
+  fn default() -> Self {
+    Self::Info
+  }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct BackgroundEntry {
   pub app: PackageKey,
   pub request_id: String,
@@ -26,4 +60,72 @@ pub struct BackgroundEntry {
 
   #[serde(default)]
   pub same_version: bool,
+
+  /// Set for at-least-once entries. Failed runs are retried, and the entry is
+  /// dead-lettered once retries are exhausted.
+  #[serde(default)]
+  pub retry: Option<RetryPolicy>,
+
+  /// Number of failed runs so far.
+  #[serde(default)]
+  pub attempts: u32,
+
+  #[serde(default)]
+  pub dedup: Option<DedupKey>,
+}
+
+/// Suppresses entries enqueued with the same key by the same app within `window_secs`
+/// of the first one that was accepted.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DedupKey {
+  pub key: String,
+  pub window_secs: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RetryPolicy {
+  pub max_retries: u32,
+  pub base_delay_ms: u64,
+  pub max_delay_ms: u64,
+
+  /// Fraction of each delay, between 0 and 1, that is randomized away.
+  pub jitter: f64,
+}
+
+impl RetryPolicy {
+  /// Delay before retry number `attempt`, starting from 1. `rand` is uniform in `[0, 1)`.
+  pub fn delay(&self, attempt: u32, rand: f64) -> Duration {
+    let exp = self
+      .base_delay_ms
+      .saturating_mul(
+        1u64
+          .checked_shl(attempt.saturating_sub(1))
+          .unwrap_or(u64::MAX),
+      )
+      .min(self.max_delay_ms);
+    let jitter = self.jitter.clamp(0.0, 1.0) * rand;
+    Duration::from_millis((exp as f64 * (1.0 - jitter)) as u64)
+  }
+}
+
+/// A background entry invoked repeatedly on a cron schedule. Registered in the background store.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct CronEntry {
+  pub app: PackageKey,
+  pub request_id: String,
+
+  /// Identifies the job within the app. Registering the same name again replaces it.
+  pub name: String,
+
+  /// Cron expression with a seconds field, like `0 30 9 * * Mon-Fri`.
+  pub schedule: String,
+
+  /// IANA timezone name. Defaults to UTC.
+  #[serde(default)]
+  pub timezone: Option<String>,
+
+  pub wire_bytes: Vec<u8>,
+
+  #[serde(default)]
+  pub same_version: bool,
 }
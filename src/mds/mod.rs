@@ -22,13 +22,80 @@ pub struct MdsCluster {
 }
 
 impl MdsCluster {
-  pub fn pack_user_key(&self, ns_prefix: &str, path: &str) -> Vec<u8> {
-    let path = std::iter::once(self.config.prefix.as_str())
+  fn pack_key_under(root: &str, ns_prefix: &str, path: &str) -> Vec<u8> {
+    let path = std::iter::once(root)
       .chain(ns_prefix.split('/').filter(|x| !x.is_empty()))
       .chain(path.split('/').filter(|x| !x.is_empty()))
       .collect::<Vec<_>>();
     foundationdb::tuple::pack(&path)
   }
+
+  pub fn pack_user_key(&self, ns_prefix: &str, path: &str) -> Vec<u8> {
+    Self::pack_key_under(&self.config.prefix, ns_prefix, path)
+  }
+
+  /// Expiry timestamps for user keys live in a separate root (`<prefix>.expiry`) so that they never
+  /// show up in range reads over the user key space.
+  pub fn pack_expiry_key(&self, ns_prefix: &str, path: &str) -> Vec<u8> {
+    Self::pack_key_under(&format!("{}.expiry", self.config.prefix), ns_prefix, path)
+  }
+
+  /// Key ranges of the user keys under `path`, including `path` itself, and of their expiry
+  /// records. Clearing both removes the entries whether or not they have expired.
+  pub fn pack_prefix_ranges(&self, ns_prefix: &str, path: &str) -> [(Vec<u8>, Vec<u8>); 2] {
+    Self::pack_prefix_ranges_under(&self.config.prefix, ns_prefix, path)
+  }
+
+  fn pack_prefix_ranges_under(root: &str, ns_prefix: &str, path: &str) -> [(Vec<u8>, Vec<u8>); 2] {
+    let range = |start: Vec<u8>| {
+      let mut end = start.clone();
+      end.push(0xff);
+      (start, end)
+    };
+    [
+      range(Self::pack_key_under(root, ns_prefix, path)),
+      range(Self::pack_key_under(
+        &format!("{}.expiry", root),
+        ns_prefix,
+        path,
+      )),
+    ]
+  }
+
+  /// Maps a key returned by `pack_user_key` to its expiry key. Works on raw bytes so that keys with
+  /// non-string segments (versionstamps) map too.
+  pub fn user_key_to_expiry_key(&self, key: &[u8]) -> Option<Vec<u8>> {
+    let root = foundationdb::tuple::pack(&vec![self.config.prefix.as_str()]);
+    let rest = key.strip_prefix(root.as_slice())?;
+    let mut out = foundationdb::tuple::pack(&vec![format!("{}.expiry", self.config.prefix)]);
+    out.extend_from_slice(rest);
+    Some(out)
+  }
+
+  /// Change queues for KV watches, one per path prefix, under `<prefix>.watch`. Queue entries are
+  /// keyed by a versionstamp appended to this key.
+  pub fn pack_watch_key(&self, ns_prefix: &str, path: &str) -> Vec<u8> {
+    Self::pack_key_under(&format!("{}.watch", self.config.prefix), ns_prefix, path)
+  }
+
+  /// Lease expiry of the watchers of a path prefix, under `<prefix>.watchers`.
+  pub fn pack_watcher_key(&self, ns_prefix: &str, path: &str) -> Vec<u8> {
+    Self::pack_key_under(&format!("{}.watchers", self.config.prefix), ns_prefix, path)
+  }
+
+  /// Lease expiry of the most recently renewed watcher of a namespace, under `<prefix>.watched`.
+  pub fn pack_watched_key(&self, ns_prefix: &str) -> Vec<u8> {
+    Self::pack_key_under(&format!("{}.watched", self.config.prefix), ns_prefix, "")
+  }
+
+  /// Maps a key returned by `pack_watcher_key` to the change queue of the same prefix.
+  pub fn watcher_key_to_watch_key(&self, key: &[u8]) -> Option<Vec<u8>> {
+    let root = foundationdb::tuple::pack(&vec![format!("{}.watchers", self.config.prefix)]);
+    let rest = key.strip_prefix(root.as_slice())?;
+    let mut out = foundationdb::tuple::pack(&vec![format!("{}.watch", self.config.prefix)]);
+    out.extend_from_slice(rest);
+    Some(out)
+  }
   pub fn unpack_user_key(&self, ns_prefix: &str, key: &[u8]) -> Option<String> {
     let prefix_to_strip = foundationdb::tuple::pack(
       &std::iter::once(self.config.prefix.as_str())
@@ -66,3 +133,40 @@ pub fn get_mds() -> Result<MdsService> {
     .clone()
     .ok_or_else(|| anyhow::anyhow!("mds not available"))
 }
+
+#[cfg(test)]
+mod tests {
+  use super::MdsCluster;
+
+  #[test]
+  fn prefix_ranges_cover_children_and_their_expiry_records() {
+    let [(start, end), (expiry_start, expiry_end)] =
+      MdsCluster::pack_prefix_ranges_under("kv", "app", "a/b");
+    let in_range = |key: &[u8]| start.as_slice() <= key && key < end.as_slice();
+    let in_expiry_range =
+      |key: &[u8]| expiry_start.as_slice() <= key && key < expiry_end.as_slice();
+
+    for path in ["a/b", "a/b/c", "a/b/c/d"] {
+      assert!(in_range(&MdsCluster::pack_key_under("kv", "app", path)));
+      assert!(in_expiry_range(&MdsCluster::pack_key_under(
+        "kv.expiry",
+        "app",
+        path
+      )));
+    }
+    for path in ["a", "a/bc", "a/c", "b"] {
+      assert!(!in_range(&MdsCluster::pack_key_under("kv", "app", path)));
+      assert!(!in_expiry_range(&MdsCluster::pack_key_under(
+        "kv.expiry",
+        "app",
+        path
+      )));
+    }
+    assert!(!in_range(&MdsCluster::pack_key_under("kv", "app2", "a/b")));
+    assert!(!in_range(&MdsCluster::pack_key_under(
+      "kv.expiry",
+      "app",
+      "a/b"
+    )));
+  }
+}
@@ -20,11 +20,60 @@ pub struct Metadata {
   #[serde(default)]
   pub apns: HashMap<String, ApnsMetadata>,
 
+  #[serde(default)]
+  pub fcm: HashMap<String, FcmMetadata>,
+
+  #[serde(default)]
+  pub webpush: HashMap<String, WebPushMetadata>,
+
   #[serde(default)]
   pub kv_namespaces: HashMap<String, KvNamespaceMetadata>,
 
   #[serde(default)]
   pub pubsub: HashMap<String, PubsubMetadata>,
+
+  #[serde(default)]
+  pub fetch: FetchMetadata,
+
+  /// CPU time in milliseconds a request may spend executing JavaScript before it's terminated.
+  /// Time spent awaiting I/O doesn't count. Unlimited if not set.
+  #[serde(default)]
+  pub cpu_budget_ms: Option<u64>,
+
+  /// Maximum size of request bodies in bytes. Larger requests are rejected with 413 before
+  /// reaching a worker. Capped by the server's `--max-request-body-size`.
+  #[serde(default)]
+  pub max_request_body_size: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct FetchMetadata {
+  /// Outbound proxy for all `fetch` requests of this app. Supported schemes are `http`, `https`,
+  /// `socks5` and `socks5h`. WebSocket connections are tunneled with `CONNECT` and only support
+  /// `http`.
+  #[serde(default)]
+  pub proxy: Option<String>,
+
+  /// CIDR ranges that may be reached even if they are in `deny` or in the built-in list of
+  /// private and special-purpose ranges.
+  #[serde(default)]
+  pub allow: Vec<String>,
+
+  /// Additional CIDR ranges that must not be reached.
+  #[serde(default)]
+  pub deny: Vec<String>,
+
+  /// Idle connections kept per origin. Defaults to 32.
+  #[serde(default)]
+  pub pool_max_idle_per_host: Option<usize>,
+
+  /// How long an idle connection is kept before it's closed. Defaults to 90 seconds.
+  #[serde(default)]
+  pub pool_idle_timeout_ms: Option<u64>,
+
+  /// Disables HTTP/2, which is otherwise negotiated with TLS upstreams.
+  #[serde(default)]
+  pub http1_only: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -49,14 +98,41 @@ pub struct KvNamespaceMetadata {
 pub struct MysqlMetadata {
   pub url: String,
   pub root_certificate: Option<String>,
+
+  /// Number of prepared statements cached per connection, keyed by SQL text. Defaults to 32, and
+  /// 0 disables the cache. Pooled connections are reset when they are returned to the pool, which
+  /// deallocates their statements, so a cached statement is reused only within one checkout of a
+  /// connection.
+  #[serde(default)]
+  pub stmt_cache_size: Option<usize>,
+
+  /// Read replica for queries marked `readonly`. Uses the same certificate and statement cache
+  /// settings as `url`. Replication is asynchronous, so a read routed here may not see writes
+  /// that were just committed on the primary, including the app's own.
+  #[serde(default)]
+  pub replica_url: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct ApnsMetadata {
   pub endpoint: ApnsEndpointMetadata,
 
-  #[serde(with = "Base64Standard")]
+  /// PKCS#12 client certificate, for certificate-based authentication.
+  #[serde(default, with = "Base64Standard")]
   pub cert: Vec<u8>,
+
+  /// Signing key, for token-based authentication. Exactly one of `cert` and `token` is set.
+  #[serde(default)]
+  pub token: Option<ApnsTokenMetadata>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ApnsTokenMetadata {
+  pub key_id: String,
+  pub team_id: String,
+
+  /// Contents of the `.p8` file downloaded from the developer account.
+  pub private_key: String,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -67,3 +143,23 @@ pub enum ApnsEndpointMetadata {
   #[serde(rename = "sandbox")]
   Sandbox,
 }
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FcmMetadata {
+  /// Contents of the JSON key file of a service account with the Firebase Messaging role.
+  pub service_account: String,
+
+  /// Defaults to the project of the service account.
+  #[serde(default)]
+  pub project_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WebPushMetadata {
+  /// Raw P-256 private key, base64url-encoded. Browsers get the public key as
+  /// `applicationServerKey`.
+  pub vapid_private_key: String,
+
+  /// Contact for the push service, a `mailto:` or `https:` URL.
+  pub subject: String,
+}
@@ -0,0 +1,386 @@
+//! Counters and histograms recorded by apps, aggregated by the server process and
+//! exposed in the Prometheus text format.
+
+use std::{
+  collections::{BTreeMap, HashMap},
+  fmt::Write,
+};
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Series per app. Samples creating more series are dropped.
+const MAX_SERIES_PER_APP: usize = 1000;
+
+const MAX_LABELS: usize = 8;
+const MAX_LABEL_VALUE_LEN: usize = 128;
+
+/// Upper bounds of histogram buckets, in seconds for latencies.
+const HISTOGRAM_BUCKETS: &[f64] = &[
+  0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+pub static METRICS: Lazy<MetricsRegistry> = Lazy::new(|| MetricsRegistry::new(MAX_SERIES_PER_APP));
+
+static METRIC_NAME: Lazy<Regex> = Lazy::new(|| Regex::new("^[a-zA-Z_:][a-zA-Z0-9_:]*$").unwrap());
+static LABEL_NAME: Lazy<Regex> = Lazy::new(|| Regex::new("^[a-zA-Z_][a-zA-Z0-9_]*$").unwrap());
+
+#[derive(Error, Debug)]
+#[error("invalid metric: {0}")]
+pub struct InvalidMetric(&'static str);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub enum MetricOp {
+  Incr(f64),
+  Observe(f64),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+enum MetricKind {
+  Counter,
+  Histogram,
+}
+
+impl MetricOp {
+  fn kind(&self) -> MetricKind {
+    match self {
+      MetricOp::Incr(_) => MetricKind::Counter,
+      MetricOp::Observe(_) => MetricKind::Histogram,
+    }
+  }
+}
+
+/// Checks a sample before it leaves the worker, so that the app gets the error.
+pub fn validate_metric(name: &str, labels: &BTreeMap<String, String>, op: MetricOp) -> Result<()> {
+  if !METRIC_NAME.is_match(name) || name.starts_with("__") {
+    return Err(InvalidMetric("bad name").into());
+  }
+  if labels.len() > MAX_LABELS {
+    return Err(InvalidMetric("too many labels").into());
+  }
+  for (k, v) in labels {
+    // `app` is set by the runtime, and `le` is used by histogram buckets.
+    if !LABEL_NAME.is_match(k) || k.starts_with("__") || k == "app" || k == "le" {
+      return Err(InvalidMetric("bad label name").into());
+    }
+    if v.len() > MAX_LABEL_VALUE_LEN {
+      return Err(InvalidMetric("label value too long").into());
+    }
+  }
+  match op {
+    MetricOp::Incr(x) if !(x.is_finite() && x >= 0.0) => {
+      Err(InvalidMetric("counter increment must be finite and non-negative").into())
+    }
+    MetricOp::Observe(x) if !x.is_finite() => {
+      Err(InvalidMetric("observed value must be finite").into())
+    }
+    _ => Ok(()),
+  }
+}
+
+enum MetricValue {
+  Counter(f64),
+  Histogram {
+    /// Non-cumulative counts per bucket, plus one for `+Inf`.
+    counts: Vec<u64>,
+    sum: f64,
+  },
+}
+
+impl MetricValue {
+  fn kind(&self) -> MetricKind {
+    match self {
+      MetricValue::Counter(_) => MetricKind::Counter,
+      MetricValue::Histogram { .. } => MetricKind::Histogram,
+    }
+  }
+}
+
+#[derive(Default)]
+struct AppSeries {
+  series: HashMap<(String, BTreeMap<String, String>), MetricValue>,
+
+  /// A name keeps the kind it was first used with by the app.
+  kinds: HashMap<String, MetricKind>,
+  dropped: u64,
+}
+
+pub struct MetricsRegistry {
+  max_series_per_app: usize,
+  inner: Mutex<MetricsInner>,
+}
+
+#[derive(Default)]
+struct MetricsInner {
+  apps: BTreeMap<String, AppSeries>,
+}
+
+impl MetricsRegistry {
+  pub fn new(max_series_per_app: usize) -> Self {
+    Self {
+      max_series_per_app,
+      inner: Mutex::new(MetricsInner::default()),
+    }
+  }
+
+  pub fn record(&self, app: &str, name: &str, labels: BTreeMap<String, String>, op: MetricOp) {
+    let mut inner = self.inner.lock();
+    let app = inner.apps.entry(app.to_string()).or_default();
+    let kind = app.kinds.get(name).copied().unwrap_or_else(|| op.kind());
+    let key = (name.to_string(), labels);
+    if kind != op.kind()
+      || (!app.series.contains_key(&key) && app.series.len() >= self.max_series_per_app)
+    {
+      app.dropped += 1;
+      return;
+    }
+    app.kinds.entry(name.to_string()).or_insert(kind);
+    let value = app.series.entry(key).or_insert_with(|| match kind {
+      MetricKind::Counter => MetricValue::Counter(0.0),
+      MetricKind::Histogram => MetricValue::Histogram {
+        counts: vec![0; HISTOGRAM_BUCKETS.len() + 1],
+        sum: 0.0,
+      },
+    });
+    match (value, op) {
+      (MetricValue::Counter(x), MetricOp::Incr(d)) => *x += d,
+      (MetricValue::Histogram { counts, sum }, MetricOp::Observe(v)) => {
+        let i = HISTOGRAM_BUCKETS
+          .iter()
+          .position(|b| v <= *b)
+          .unwrap_or(HISTOGRAM_BUCKETS.len());
+        counts[i] += 1;
+        *sum += v;
+      }
+      _ => unreachable!(),
+    }
+  }
+
+  /// Renders all series in the Prometheus text exposition format.
+  pub fn render(&self) -> String {
+    let inner = self.inner.lock();
+    let mut by_name: BTreeMap<&str, Vec<(&str, &BTreeMap<String, String>, &MetricValue)>> =
+      BTreeMap::new();
+    for (app, series) in &inner.apps {
+      for ((name, labels), value) in &series.series {
+        by_name
+          .entry(name.as_str())
+          .or_default()
+          .push((app.as_str(), labels, value));
+      }
+    }
+
+    let mut out = String::new();
+    for (name, mut series) in by_name {
+      series.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+
+      // Apps may use a name with different kinds, which a single family can't declare.
+      let kind = if series.iter().all(|x| x.2.kind() == MetricKind::Counter) {
+        "counter"
+      } else if series.iter().all(|x| x.2.kind() == MetricKind::Histogram) {
+        "histogram"
+      } else {
+        "untyped"
+      };
+      writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+      for (app, labels, value) in series {
+        match value {
+          MetricValue::Counter(x) => {
+            writeln!(out, "{}{} {}", name, render_labels(app, labels, None), x).unwrap();
+          }
+          MetricValue::Histogram { counts, sum } => {
+            let mut cumulative = 0;
+            for (i, count) in counts.iter().enumerate() {
+              cumulative += count;
+              let le = HISTOGRAM_BUCKETS
+                .get(i)
+                .map(|x| x.to_string())
+                .unwrap_or_else(|| "+Inf".into());
+              writeln!(
+                out,
+                "{}_bucket{} {}",
+                name,
+                render_labels(app, labels, Some(&le)),
+                cumulative
+              )
+              .unwrap();
+            }
+            let labels = render_labels(app, labels, None);
+            writeln!(out, "{}_sum{} {}", name, labels, sum).unwrap();
+            writeln!(out, "{}_count{} {}", name, labels, cumulative).unwrap();
+          }
+        }
+      }
+    }
+
+    out.push_str("# TYPE blueboat_metrics_dropped_total counter\n");
+    for (app, series) in &inner.apps {
+      writeln!(
+        out,
+        "blueboat_metrics_dropped_total{} {}",
+        render_labels(app, &BTreeMap::new(), None),
+        series.dropped
+      )
+      .unwrap();
+    }
+    out
+  }
+}
+
+fn render_labels(app: &str, labels: &BTreeMap<String, String>, le: Option<&str>) -> String {
+  let mut out = format!("{{app=\"{}\"", escape_label_value(app));
+  for (k, v) in labels {
+    write!(out, ",{}=\"{}\"", k, escape_label_value(v)).unwrap();
+  }
+  if let Some(le) = le {
+    write!(out, ",le=\"{}\"", le).unwrap();
+  }
+  out.push('}');
+  out
+}
+
+fn escape_label_value(x: &str) -> String {
+  x.replace('\\', "\\\\")
+    .replace('"', "\\\"")
+    .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::BTreeMap;
+
+  use super::{validate_metric, MetricOp, MetricsRegistry};
+
+  fn labels(x: &[(&str, &str)]) -> BTreeMap<String, String> {
+    x.iter()
+      .map(|(k, v)| (k.to_string(), v.to_string()))
+      .collect()
+  }
+
+  #[test]
+  fn renders_counters_and_histograms() {
+    let metrics = MetricsRegistry::new(10);
+    metrics.record(
+      "app",
+      "orders_total",
+      labels(&[("kind", "a")]),
+      MetricOp::Incr(1.0),
+    );
+    metrics.record(
+      "app",
+      "orders_total",
+      labels(&[("kind", "a")]),
+      MetricOp::Incr(2.0),
+    );
+    metrics.record(
+      "app",
+      "latency_seconds",
+      labels(&[]),
+      MetricOp::Observe(0.007),
+    );
+    metrics.record(
+      "app",
+      "latency_seconds",
+      labels(&[]),
+      MetricOp::Observe(20.0),
+    );
+
+    let out = metrics.render();
+    assert!(out.contains("# TYPE orders_total counter\norders_total{app=\"app\",kind=\"a\"} 3\n"));
+    assert!(out.contains("# TYPE latency_seconds histogram\n"));
+    assert!(out.contains("latency_seconds_bucket{app=\"app\",le=\"0.005\"} 0\n"));
+    assert!(out.contains("latency_seconds_bucket{app=\"app\",le=\"0.01\"} 1\n"));
+    assert!(out.contains("latency_seconds_bucket{app=\"app\",le=\"10\"} 1\n"));
+    assert!(out.contains("latency_seconds_bucket{app=\"app\",le=\"+Inf\"} 2\n"));
+    assert!(out.contains("latency_seconds_sum{app=\"app\"} 20.007\n"));
+    assert!(out.contains("latency_seconds_count{app=\"app\"} 2\n"));
+    assert!(out.contains("blueboat_metrics_dropped_total{app=\"app\"} 0\n"));
+  }
+
+  #[test]
+  fn limits_series_per_app() {
+    let metrics = MetricsRegistry::new(2);
+    for user in ["a", "b", "c"] {
+      metrics.record(
+        "app",
+        "hits",
+        labels(&[("user", user)]),
+        MetricOp::Incr(1.0),
+      );
+    }
+    metrics.record("app", "hits", labels(&[("user", "a")]), MetricOp::Incr(1.0));
+    metrics.record(
+      "other",
+      "hits",
+      labels(&[("user", "c")]),
+      MetricOp::Incr(1.0),
+    );
+
+    // A name keeps its first kind within an app.
+    metrics.record("other", "hits", labels(&[]), MetricOp::Observe(1.0));
+
+    let out = metrics.render();
+    assert!(out.contains("# TYPE hits counter\n"));
+    assert!(out.contains("hits{app=\"app\",user=\"a\"} 2\n"));
+    assert!(!out.contains("hits{app=\"app\",user=\"c\"}"));
+    assert!(out.contains("hits{app=\"other\",user=\"c\"} 1\n"));
+    assert!(out.contains("blueboat_metrics_dropped_total{app=\"app\"} 1\n"));
+    assert!(out.contains("blueboat_metrics_dropped_total{app=\"other\"} 1\n"));
+  }
+
+  #[test]
+  fn kinds_are_per_app() {
+    let metrics = MetricsRegistry::new(1);
+    metrics.record("a", "x", labels(&[]), MetricOp::Observe(1.0));
+    metrics.record("b", "x", labels(&[]), MetricOp::Incr(1.0));
+
+    // A dropped sample doesn't claim its name.
+    metrics.record("a", "y", labels(&[]), MetricOp::Observe(1.0));
+    metrics.record("a", "x", labels(&[("k", "v")]), MetricOp::Incr(1.0));
+
+    let out = metrics.render();
+    assert!(out.contains("# TYPE x untyped\n"));
+    assert!(out.contains("x_count{app=\"a\"} 1\n"));
+    assert!(out.contains("x{app=\"b\"} 1\n"));
+    assert!(!out.contains("y_count"));
+    assert!(out.contains("blueboat_metrics_dropped_total{app=\"a\"} 2\n"));
+    assert_eq!(metrics.inner.lock().apps["a"].kinds.len(), 1);
+  }
+
+  #[test]
+  fn escapes_label_values() {
+    let metrics = MetricsRegistry::new(10);
+    metrics.record(
+      "app",
+      "x",
+      labels(&[("path", "a\"b\\c\nd")]),
+      MetricOp::Incr(1.0),
+    );
+    assert!(metrics
+      .render()
+      .contains(r#"x{app="app",path="a\"b\\c\nd"} 1"#));
+  }
+
+  #[test]
+  fn validates_samples() {
+    let ok = labels(&[("kind", "a")]);
+    assert!(validate_metric("orders_total", &ok, MetricOp::Incr(1.0)).is_ok());
+    assert!(validate_metric("orders-total", &ok, MetricOp::Incr(1.0)).is_err());
+    assert!(validate_metric("__x", &ok, MetricOp::Incr(1.0)).is_err());
+    assert!(validate_metric("x", &labels(&[("app", "a")]), MetricOp::Incr(1.0)).is_err());
+    assert!(validate_metric("x", &labels(&[("le", "a")]), MetricOp::Observe(1.0)).is_err());
+    assert!(validate_metric(
+      "x",
+      &labels(&[("k", "v".repeat(200).as_str())]),
+      MetricOp::Incr(1.0)
+    )
+    .is_err());
+    assert!(validate_metric("x", &ok, MetricOp::Incr(-1.0)).is_err());
+    assert!(validate_metric("x", &ok, MetricOp::Observe(f64::NAN)).is_err());
+    assert!(validate_metric("x", &ok, MetricOp::Observe(-1.0)).is_ok());
+  }
+}
@@ -7,20 +7,45 @@ use v8;
 use crate::{
   api::{
     apns::{ApnsRequest, ApnsResponse},
-    codec::CodecBase64Mode,
+    codec::{compression::CodecCompressAlgorithm, percent::CodecUrlEncodeMode, CodecBase64Mode},
+    dataset::mime::DatasetMimeSniffResult,
+    external::aws::AwsSigV4SignRequest,
+    external::azure::AzureSharedKeyRequest,
+    external::gcs::{GcsObjectRequest, GcsSignedUrlRequest},
     external::s3::{
-      S3Credentials, S3DeleteObjectRequest, S3GetObjectRequest, S3ListObjectsV2Output,
-      S3ListObjectsV2Request, S3PresignInfo, S3PresignOptions, S3PutObjectRequest, S3Region,
-      S3UploadPartRequest,
+      S3AbortMultipartUploadRequest, S3CompleteMultipartUploadOutput,
+      S3CompleteMultipartUploadRequest, S3CreateMultipartUploadRequest, S3Credentials,
+      S3DeleteObjectRequest, S3GetObjectRequest, S3ListObjectsV2Output, S3ListObjectsV2Request,
+      S3ObjectRequest, S3PresignInfo, S3PresignOptions, S3PresignUrlRequest, S3PutObjectRequest,
+      S3Region, S3UploadPartRequest,
     },
+    fcm::{FcmRequest, FcmResponse},
     graphics::{
+      animation::GraphicsAnimationEncodeConfig,
+      barcode::GraphicsBarcodeEncodeConfig,
       codec::CanvasEncodeConfig,
       draw::CanvasDrawConfig,
+      filter::GraphicsImageFilter,
+      pdf::GraphicsPdfRenderConfig,
+      qr::GraphicsQrEncodeConfig,
+      raster::{GraphicsDecodedImageInfo, GraphicsImageCropConfig, GraphicsImageResizeConfig},
       svg::CanvasRenderSvgConfig,
-      text::{GraphicsTextMeasureOutput, GraphicsTextMeasureSettings},
+      text::{
+        GraphicsTextLayoutOutput, GraphicsTextLayoutSettings, GraphicsTextMeasureOutput,
+        GraphicsTextMeasureSettings,
+      },
       CanvasConfig, CanvasOp,
     },
-    text::markdown::TextMarkdownRenderOpts,
+    mysql::{MysqlBatchOptions, MysqlBatchRowResult, MysqlExecOptions, MysqlStreamOptions},
+    text::{
+      csv::TextCsvOptions,
+      html::TextHtmlSanitizeOptions,
+      markdown::TextMarkdownRenderOpts,
+      xml::{TextXmlParseOptions, TextXmlStringifyOptions},
+      yaml::TextYamlParseOptions,
+    },
+    validation::{jsonschema::JsonSchemaLoadOptions, ValidationError, ValidationOptions},
+    webpush::{WebPushOptions, WebPushResponse, WebPushSubscription},
   },
   bootstrap::BlueboatBootstrapData,
   ctx::{native_invoke_entry, NI_ENTRY_KEY},
@@ -148,14 +173,30 @@ fn print_schema() {
     blueboat_response: BlueboatResponse,
     apns_request: ApnsRequest,
     apns_response: ApnsResponse,
+    fcm_request: FcmRequest,
+    fcm_response: FcmResponse,
+    webpush_subscription: WebPushSubscription,
+    webpush_options: WebPushOptions,
+    webpush_response: WebPushResponse,
     blueboat_bootstrap_data: BlueboatBootstrapData,
     canvas_config: CanvasConfig,
     canvas_encode_config: CanvasEncodeConfig,
     canvas_draw_config: CanvasDrawConfig,
     canvas_render_svg_config: CanvasRenderSvgConfig,
     codec_base64_mode: CodecBase64Mode,
+    codec_compress_algorithm: CodecCompressAlgorithm,
+    codec_url_encode_mode: CodecUrlEncodeMode,
+    dataset_mime_sniff_result: DatasetMimeSniffResult,
     canvas_op: CanvasOp,
     text_markdown_render_opts: TextMarkdownRenderOpts,
+    text_csv_options: TextCsvOptions,
+    text_html_sanitize_options: TextHtmlSanitizeOptions,
+    text_xml_parse_options: TextXmlParseOptions,
+    text_xml_stringify_options: TextXmlStringifyOptions,
+    text_yaml_parse_options: TextYamlParseOptions,
+    validation_error: ValidationError,
+    validation_options: ValidationOptions,
+    json_schema_load_options: JsonSchemaLoadOptions,
     s3_put_object_request: S3PutObjectRequest,
     s3_get_object_request: S3GetObjectRequest,
     s3_delete_object_request: S3DeleteObjectRequest,
@@ -166,8 +207,32 @@ fn print_schema() {
     s3_list_objects_v2_request: S3ListObjectsV2Request,
     s3_list_objects_v2_output: S3ListObjectsV2Output,
     s3_presign_options: S3PresignOptions,
+    s3_presign_url_request: S3PresignUrlRequest,
+    s3_object_request: S3ObjectRequest,
+    s3_create_multipart_upload_request: S3CreateMultipartUploadRequest,
+    s3_complete_multipart_upload_request: S3CompleteMultipartUploadRequest,
+    s3_complete_multipart_upload_output: S3CompleteMultipartUploadOutput,
+    s3_abort_multipart_upload_request: S3AbortMultipartUploadRequest,
+    aws_sig_v4_sign_request: AwsSigV4SignRequest,
+    azure_shared_key_request: AzureSharedKeyRequest,
+    gcs_signed_url_request: GcsSignedUrlRequest,
+    gcs_object_request: GcsObjectRequest,
     graphics_text_measure_settings: GraphicsTextMeasureSettings,
     graphics_text_measure_output: GraphicsTextMeasureOutput,
+    graphics_text_layout_settings: GraphicsTextLayoutSettings,
+    graphics_text_layout_output: GraphicsTextLayoutOutput,
+    graphics_image_resize_config: GraphicsImageResizeConfig,
+    graphics_image_crop_config: GraphicsImageCropConfig,
+    graphics_decoded_image_info: GraphicsDecodedImageInfo,
+    graphics_qr_encode_config: GraphicsQrEncodeConfig,
+    graphics_barcode_encode_config: GraphicsBarcodeEncodeConfig,
+    graphics_animation_encode_config: GraphicsAnimationEncodeConfig,
+    graphics_image_filter: GraphicsImageFilter,
+    graphics_pdf_render_config: GraphicsPdfRenderConfig,
+    mysql_exec_options: MysqlExecOptions,
+    mysql_batch_options: MysqlBatchOptions,
+    mysql_stream_options: MysqlStreamOptions,
+    mysql_batch_row_result: MysqlBatchRowResult,
   }
 
   let schema = schema_for!(Root);
@@ -4,6 +4,8 @@ use anyhow::Result;
 use thiserror::Error;
 use v8;
 
+/// Encodes `value` in V8's structured clone format. Dates, Maps, Sets, typed arrays and shared
+/// references survive a round trip through `deserialize_v8_value`.
 pub fn serialize_v8_value<'s>(
   scope: &mut v8::HandleScope<'s>,
   value: v8::Local<v8::Value>,
@@ -56,3 +58,72 @@ pub fn deserialize_v8_value<'s>(
   let out = vds.read_value(ctx).ok_or(Generic)?;
   Ok(out)
 }
+
+#[cfg(test)]
+mod tests {
+  use super::{deserialize_v8_value, serialize_v8_value};
+  use crate::api::testutil::ApiTester;
+  use std::convert::TryFrom;
+
+  fn eval<'s>(scope: &mut v8::HandleScope<'s>, source: &str) -> v8::Local<'s, v8::Value> {
+    let source = v8::String::new(scope, source).unwrap();
+    let script = v8::Script::compile(scope, source, None).unwrap();
+    script.run(scope).unwrap()
+  }
+
+  /// Round-trips the value of `make` through the wire format used for background entries and
+  /// passes the result to `check`.
+  fn roundtrip(make: &str, check: &str) -> String {
+    let mut tester = ApiTester::new();
+    tester.run(|scope| {
+      let value = eval(scope, make);
+      let wire_bytes = serialize_v8_value(scope, value).unwrap();
+      let value = deserialize_v8_value(scope, &wire_bytes).unwrap();
+      let check = v8::Local::<v8::Function>::try_from(eval(scope, check)).unwrap();
+      let undef = v8::undefined(scope);
+      let out = check.call(scope, undef.into(), &[value]).unwrap();
+      out.to_rust_string_lossy(scope)
+    })
+  }
+
+  #[test]
+  fn preserves_dates_maps_and_sets() {
+    let out = roundtrip(
+      r#"({
+        at: new Date(1650000000123),
+        m: new Map([["a", new Map([[1, new Set(["x", "y"])]])], [2, new Date(-1)]]),
+        s: new Set([new Set([1]), new Map([["k", [1, 2]]])]),
+      })"#,
+      r#"(v) => JSON.stringify([
+        v.at instanceof Date, v.at.getTime(),
+        v.m instanceof Map, v.m.get("a") instanceof Map, [...v.m.get("a").get(1)],
+        v.m.get(2).getTime(),
+        [...v.s].map((x) => x.constructor.name), [...[...v.s][1].get("k")],
+      ])"#,
+    );
+    assert_eq!(
+      out,
+      r#"[true,1650000000123,true,true,["x","y"],-1,["Set","Map"],[1,2]]"#
+    );
+  }
+
+  #[test]
+  fn preserves_bigints() {
+    let out = roundtrip(
+      "[2n ** 64n + 1n, -(3n ** 90n), new Map([[2n ** 53n + 1n, 0n]])]",
+      r#"(v) => JSON.stringify([
+        v[0] === 2n ** 64n + 1n, v[1] === -(3n ** 90n), v[2].get(2n ** 53n + 1n) === 0n,
+      ])"#,
+    );
+    assert_eq!(out, "[true,true,true]");
+  }
+
+  #[test]
+  fn preserves_shared_references() {
+    let out = roundtrip(
+      "const d = new Date(5); const s = new Set([d]); ({ s, d, m: new Map([[d, s]]) })",
+      "(v) => String(v.s.has(v.d) && v.m.get(v.d) === v.s)",
+    );
+    assert_eq!(out, "true");
+  }
+}
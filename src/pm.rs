@@ -62,6 +62,12 @@ pub unsafe fn secure_init(
       std::env::vars().collect::<Vec<_>>()
     );
 
+    if let Some(seed) = deterministic_rng_seed() {
+      log::warn!("Deterministic RNG enabled with seed {}. This is for testing only.", seed);
+      // Truncated to the `i32` that V8 takes - see `deterministic_rng_seed`.
+      v8::V8::set_flags_from_string(&format!("--random-seed={}", seed as i32));
+    }
+
     set_up_v8_globally();
     ISOLATE_BUFFER.with(|buf| {
       let mut isolate =
@@ -216,6 +222,34 @@ pub fn is_seccomp_disabled() -> bool {
     .unwrap_or(false)
 }
 
+/// Seed for the deterministic test mode, in which `crypto.getRandomValues`, `crypto.randomUUID`
+/// and `Math.random` produce reproducible sequences.
+///
+/// `Math.random` is seeded through V8's `--random-seed`, which takes an `i32`, so only the low 32
+/// bits of the seed reach it, and seeds whose low 32 bits are zero leave it unseeded. Its sequence
+/// belongs to the V8 context and does not restart at every request like
+/// `api::crypto::DeterministicRng` does.
+///
+/// Threat model: everything generated from this seed, including keys and nonces created by apps,
+/// is predictable to anyone who knows it. The seed is therefore only taken from the worker
+/// process environment, which is controlled by the operator; nothing in app metadata or JS can
+/// turn it on. It is also ignored unless seccomp is disabled, so a production deployment always
+/// uses the OS source even if the variable leaks into its environment.
+pub fn deterministic_rng_seed() -> Option<u64> {
+  let seed = std::env::var("SMRAPP_BLUEBOAT_DANGEROUSLY_DETERMINISTIC_RNG_SEED").ok()?;
+  if !is_seccomp_disabled() {
+    log::error!("Ignoring deterministic RNG seed because seccomp is enabled.");
+    return None;
+  }
+  match seed.parse() {
+    Ok(x) => Some(x),
+    Err(_) => {
+      log::error!("Ignoring invalid deterministic RNG seed: {}", seed);
+      None
+    }
+  }
+}
+
 fn warm_up<'s, 't>(scope: &mut v8::HandleScope<'s, ()>, context: v8::Local<'t, v8::Context>) {
   let scope = &mut v8::ContextScope::new(scope, context);
   let text = v8::String::new(scope, r#"
@@ -0,0 +1,167 @@
+//! Diagnostics of app workers, requested by operators through the admin listener.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use serde_json::json;
+use thiserror::Error;
+use v8::{
+  self,
+  inspector::{
+    ChannelBase, ChannelImpl, StringBuffer, StringView, V8Inspector, V8InspectorClientBase,
+    V8InspectorClientImpl, V8InspectorSession,
+  },
+};
+
+/// Snapshots larger than this are aborted.
+const MAX_HEAP_SNAPSHOT_SIZE: usize = 512 * 1024 * 1024;
+
+/// Bounds of CPU profiling requests.
+pub const MAX_CPU_PROFILE_DURATION_MS: u64 = 30000;
+pub const MIN_CPU_PROFILE_SAMPLING_INTERVAL_US: u32 = 100;
+
+const CONTEXT_GROUP_ID: i32 = 1;
+
+#[derive(Error, Debug)]
+#[error("heap snapshot exceeds limit of {0} bytes")]
+struct HeapSnapshotTooLarge(usize);
+
+#[derive(Error, Debug)]
+#[error("inspector error: {0}")]
+struct InspectorError(String);
+
+/// Takes a heap snapshot of `isolate`, in the `.heapsnapshot` JSON format understood by Chrome
+/// DevTools.
+pub fn take_heap_snapshot(isolate: &mut v8::Isolate) -> Result<Vec<u8>> {
+  let mut out = vec![];
+  let mut too_large = false;
+  isolate.take_heap_snapshot(|chunk| {
+    if out.len() + chunk.len() > MAX_HEAP_SNAPSHOT_SIZE {
+      too_large = true;
+      return false;
+    }
+    out.extend_from_slice(chunk);
+    true
+  });
+  if too_large {
+    return Err(HeapSnapshotTooLarge(MAX_HEAP_SNAPSHOT_SIZE).into());
+  }
+  Ok(out)
+}
+
+struct ProfilerClient {
+  base: V8InspectorClientBase,
+}
+
+impl V8InspectorClientImpl for ProfilerClient {
+  fn base(&self) -> &V8InspectorClientBase {
+    &self.base
+  }
+
+  fn base_mut(&mut self) -> &mut V8InspectorClientBase {
+    &mut self.base
+  }
+}
+
+struct ProfilerChannel {
+  base: ChannelBase,
+  responses: HashMap<i32, String>,
+}
+
+impl ChannelImpl for ProfilerChannel {
+  fn base(&self) -> &ChannelBase {
+    &self.base
+  }
+
+  fn base_mut(&mut self) -> &mut ChannelBase {
+    &mut self.base
+  }
+
+  fn send_response(&mut self, call_id: i32, message: v8::UniquePtr<StringBuffer>) {
+    if let Some(x) = message.as_ref() {
+      self.responses.insert(call_id, x.string().to_string());
+    }
+  }
+
+  fn send_notification(&mut self, _message: v8::UniquePtr<StringBuffer>) {}
+
+  fn flush_protocol_notifications(&mut self) {}
+}
+
+/// A sampling CPU profile in progress, driven through an inspector session. Samples are taken
+/// across the whole isolate, so every request running on the worker while profiling shows up.
+pub struct CpuProfiler {
+  // Dropped in declaration order: the session before its channel, the inspector before its client.
+  session: v8::UniqueRef<V8InspectorSession>,
+  channel: Box<ProfilerChannel>,
+  _inspector: v8::UniqueRef<V8Inspector>,
+  _client: Box<ProfilerClient>,
+  next_call_id: i32,
+}
+
+impl CpuProfiler {
+  pub fn start(
+    scope: &mut v8::HandleScope,
+    context: v8::Local<v8::Context>,
+    sampling_interval_us: u32,
+  ) -> Result<Self> {
+    let mut client = Box::new(ProfilerClient {
+      base: V8InspectorClientBase::new::<ProfilerClient>(),
+    });
+    let mut inspector = V8Inspector::create(scope, &mut *client);
+    inspector.context_created(context, CONTEXT_GROUP_ID, StringView::from(&b"app"[..]));
+    let mut channel = Box::new(ProfilerChannel {
+      base: ChannelBase::new::<ProfilerChannel>(),
+      responses: HashMap::new(),
+    });
+    let session = inspector.connect(CONTEXT_GROUP_ID, &mut *channel, StringView::empty());
+    let mut me = Self {
+      session,
+      channel,
+      _inspector: inspector,
+      _client: client,
+      next_call_id: 0,
+    };
+    me.call("Profiler.enable", json!({}))?;
+    me.call(
+      "Profiler.setSamplingInterval",
+      json!({
+        "interval": sampling_interval_us.max(MIN_CPU_PROFILE_SAMPLING_INTERVAL_US)
+      }),
+    )?;
+    me.call("Profiler.start", json!({}))?;
+    Ok(me)
+  }
+
+  /// Stops profiling and returns the profile in the `.cpuprofile` JSON format, which Chrome
+  /// DevTools and flamegraph tools such as speedscope can read.
+  pub fn stop(mut self) -> Result<Vec<u8>> {
+    let mut res = self.call("Profiler.stop", json!({}))?;
+    Ok(serde_json::to_vec(&res["profile"].take())?)
+  }
+
+  fn call(&mut self, method: &str, params: serde_json::Value) -> Result<serde_json::Value> {
+    self.next_call_id += 1;
+    let id = self.next_call_id;
+    let msg = serde_json::to_string(&json!({
+      "id": id,
+      "method": method,
+      "params": params,
+    }))?;
+
+    // Responses to protocol commands are sent synchronously.
+    self
+      .session
+      .dispatch_protocol_message(StringView::from(msg.as_bytes()));
+    let res = self
+      .channel
+      .responses
+      .remove(&id)
+      .ok_or_else(|| InspectorError(format!("no response to {}", method)))?;
+    let mut res: serde_json::Value = serde_json::from_str(&res)?;
+    if let Some(e) = res.get("error") {
+      return Err(InspectorError(e.to_string()).into());
+    }
+    Ok(res["result"].take())
+  }
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::convert::Infallible;
 use std::net::IpAddr;
 use std::path::Path;
@@ -6,16 +7,20 @@ use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::time::Duration;
 use std::{net::SocketAddr, sync::Arc, time::Instant};
 
+use crate::background::next_retry;
+use crate::bgstore::{BackgroundStore, BackgroundStoreConfig, ClaimedEntry, BG_STORE};
+use crate::cron::CronTick;
 use crate::headers::{
   HDR_GLOBAL_PREFIX, HDR_REQ_CLIENT_CITY, HDR_REQ_CLIENT_COUNTRY, HDR_REQ_CLIENT_IP,
   HDR_REQ_CLIENT_SUBDIVISION_PREFIX, HDR_REQ_CLIENT_WPBL, HDR_REQ_METADATA, HDR_REQ_REQUEST_ID,
   HDR_RES_HANDLE_LATENCY, HDR_RES_REQUEST_ID, PROXY_HEADER_WHITELIST,
 };
-use crate::ipc::{BlueboatIpcReqV, BlueboatIpcRes};
-use crate::logsvc::LogService;
+use crate::ipc::{BlueboatIpcReqV, BlueboatIpcRes, RequestBodyTooLarge};
+use crate::logsvc::{LogService, APPLOG_TAIL};
 use crate::lpch::{BackgroundEntry, LowPriorityMsg};
 use crate::mds::config_v2::MdsConfig;
 use crate::mds::{MdsService, MDS};
+use crate::metrics::METRICS;
 use crate::pm::pm_handle;
 use crate::pubsub::mq::{MessageQueue, MessageQueueConfig};
 use crate::pubsub::MQ;
@@ -83,6 +88,11 @@ struct Opt {
   #[structopt(long, default_value = "131072")]
   mem_critical_watermark_kb: u64,
 
+  /// Maximum size of request bodies, unlimited if unset. Apps may set a lower limit with
+  /// `max_request_body_size`.
+  #[structopt(long)]
+  max_request_body_size: Option<u64>,
+
   /// Kafka cluster(s) for writing apps' logs. Looks like "com.example.blueboat.applog:0@kafka.core.svc.cluster.local:9092"
   #[structopt(long, default_value = "-")]
   log_kafka: String,
@@ -135,9 +145,60 @@ struct Opt {
   #[structopt(long, default_value = "-")]
   pubsub_prefix: String,
 
+  /// FoundationDB cluster file path for queued at-least-once background tasks.
+  #[structopt(long, default_value = "-")]
+  bg_cluster: String,
+
+  /// FoundationDB key prefix for queued at-least-once background tasks.
+  #[structopt(long, default_value = "-")]
+  bg_prefix: String,
+
   /// Run in single-tenant mode with the provided `metadata.json`.
   #[structopt(long, default_value = "-")]
   single_tenant: String,
+
+  /// Listen address for scraping app metrics in the Prometheus text format, at `/metrics`.
+  #[structopt(long)]
+  metrics_listen: Option<SocketAddr>,
+
+  /// Listen address for operator diagnostics such as heap snapshots. Requests are expensive and
+  /// must carry `Authorization: Bearer <admin token>`.
+  #[structopt(long)]
+  admin_listen: Option<SocketAddr>,
+
+  /// Token for the admin listener. Required with `--admin-listen`.
+  #[structopt(long, env = "BLUEBOAT_ADMIN_TOKEN", hide_env_values = true)]
+  admin_token: Option<String>,
+
+  /// `<metadata path>=<workers>`: an app to keep a pool of initialized workers for, so that
+  /// requests don't pay for initialization. The pool size defaults to 1 and is capped by the
+  /// per-app worker limit. Workers that exit or are evicted are replaced. May be repeated.
+  #[structopt(long)]
+  prewarm: Vec<PrewarmSpec>,
+}
+
+#[derive(Clone, Debug)]
+struct PrewarmSpec {
+  path: String,
+  workers: usize,
+}
+
+impl FromStr for PrewarmSpec {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    let (path, workers) = match s.rsplit_once('=') {
+      Some((path, workers)) => (path, workers.parse::<usize>()?),
+      None => (s, 1),
+    };
+    if path.is_empty() || workers == 0 {
+      anyhow::bail!("invalid prewarm spec: {}", s);
+    }
+    Ok(Self {
+      path: path.to_string(),
+      workers,
+    })
+  }
 }
 
 struct LpContext {
@@ -154,6 +215,8 @@ static CACHE: OnceCell<sqlite_cache::Topic> = OnceCell::const_new();
 static MD_CACHE: OnceCell<MdCacheType> = OnceCell::const_new();
 static MEM_HIGH_WATERMARK_KB: OnceCell<u64> = OnceCell::const_new();
 static MEM_CRITICAL_WATERMARK_KB: OnceCell<u64> = OnceCell::const_new();
+static MAX_REQUEST_BODY_SIZE: OnceCell<Option<u64>> = OnceCell::const_new();
+static ADMIN_TOKEN: OnceCell<String> = OnceCell::const_new();
 static LP_TX: OnceCell<Mutex<IpcSender<LowPriorityMsg>>> = OnceCell::const_new();
 static MMDB_CITY: OnceCell<Option<maxminddb::Reader<Mmap>>> = OnceCell::const_new();
 static WPBL_DB: OnceCell<Option<WpblDb>> = OnceCell::const_new();
@@ -164,6 +227,7 @@ static HTTP_FAST_PATH: AtomicBool = AtomicBool::new(false);
 
 const MIN_GAP_KB: u64 = 65536;
 const WORKER_IDLE_TTL_SECS: u64 = 400;
+const PREWARM_REFILL_INTERVAL_SECS: u64 = 5;
 
 pub fn main() {
   let network = unsafe { foundationdb::boot() };
@@ -175,6 +239,11 @@ pub fn main() {
   drop(network);
 }
 
+/// Sender for low-priority messages originating in the server process.
+fn lp_tx() -> IpcSender<LowPriorityMsg> {
+  LP_TX.get().unwrap().lock().clone()
+}
+
 pub fn global_scheduler() -> &'static Arc<RwLock<Scheduler<PackageKey, BlueboatIpcReq>>> {
   SCHEDULER.get().unwrap()
 }
@@ -193,6 +262,24 @@ pub fn tenancy() -> &'static Tenancy {
   TENANCY.get().unwrap()
 }
 
+/// Runs tests in single-tenant mode, with a package that only has an empty index module.
+#[cfg(test)]
+pub fn init_test_tenancy(metadata: &Metadata) {
+  let mut header = tar::Header::new_gnu();
+  header.set_entry_type(tar::EntryType::Regular);
+  header.set_mode(0o644);
+  header.set_size(0);
+  let mut package = tar::Builder::new(Vec::new());
+  package
+    .append_data(&mut header, "index.js", std::io::empty())
+    .unwrap();
+  let package = package.into_inner().unwrap();
+  let _ = TENANCY.set(Tenancy::SingleTenant {
+    metadata: metadata.clone(),
+    package,
+  });
+}
+
 pub fn cache() -> &'static sqlite_cache::Topic {
   CACHE.get().unwrap()
 }
@@ -378,6 +465,20 @@ async fn async_main() {
     );
   }
 
+  if opt.bg_cluster != "-" && opt.bg_prefix != "-" {
+    let store = BackgroundStore::open(BackgroundStoreConfig {
+      fdb_cluster_file: opt.bg_cluster.clone(),
+      prefix: opt.bg_prefix.clone(),
+    })
+    .expect("failed to open background store");
+    BG_STORE.set(store).unwrap_or_else(|_| unreachable!());
+    tracing::warn!(
+      cluster = opt.bg_cluster,
+      prefix = opt.bg_prefix,
+      "background store opened"
+    );
+  }
+
   // Tweak parameters
   SPRING_CLEANING_INTERVAL_MS.store(300);
   APP_INACTIVE_TIMEOUT_MS.store(WORKER_IDLE_TTL_SECS * 1000);
@@ -405,6 +506,9 @@ async fn async_main() {
   MEM_CRITICAL_WATERMARK_KB
     .set(opt.mem_critical_watermark_kb)
     .unwrap_or_else(|_| unreachable!());
+  MAX_REQUEST_BODY_SIZE
+    .set(opt.max_request_body_size)
+    .unwrap_or_else(|_| unreachable!());
 
   let (lp_tx, lp_rx) = smr::ipc_channel::ipc::channel::<LowPriorityMsg>().unwrap();
   LP_TX
@@ -510,10 +614,61 @@ async fn async_main() {
     bg_permit: Arc::new(Semaphore::new(500)),
   };
 
-  spawn_lp_handler(Arc::new(lp_ctx), lp_rx);
+  let lp_ctx = Arc::new(lp_ctx);
+  spawn_lp_handler(lp_ctx.clone(), lp_rx);
 
   if opt.accept_background_tasks {
-    log::warn!("Background tasks not implemented.");
+    match BG_STORE.get() {
+      Some(store) => {
+        log::warn!("Accepting at-least-once background tasks.");
+        tokio::spawn(run_background_queue(
+          store.clone(),
+          lp_ctx.bg_permit.clone(),
+        ));
+        tokio::spawn(run_cron_scheduler(store.clone(), lp_ctx.bg_permit.clone()));
+      }
+      None => {
+        log::error!("Accepting background tasks requires --bg-cluster and --bg-prefix.");
+        std::process::exit(1);
+      }
+    }
+  }
+
+  if let Some(addr) = opt.metrics_listen {
+    let make_svc =
+      make_service_fn(|_| async move { Ok::<_, hyper::Error>(service_fn(handle_metrics)) });
+    tracing::warn!(address = %addr, "start metrics listener");
+    let server = Server::bind(&addr).serve(make_svc);
+    tokio::spawn(async move {
+      if let Err(e) = server.await {
+        tracing::error!(error = %e, "metrics server error");
+      }
+    });
+  }
+
+  if let Some(addr) = opt.admin_listen {
+    match opt.admin_token.as_deref() {
+      Some(x) if !x.is_empty() => ADMIN_TOKEN.set(x.to_string()).unwrap(),
+      _ => {
+        log::error!(
+          "--admin-listen requires an admin token (--admin-token or BLUEBOAT_ADMIN_TOKEN)."
+        );
+        std::process::exit(1);
+      }
+    }
+    let make_svc =
+      make_service_fn(|_| async move { Ok::<_, hyper::Error>(service_fn(handle_admin)) });
+    tracing::warn!(address = %addr, "start admin listener");
+    let server = Server::bind(&addr).serve(make_svc);
+    tokio::spawn(async move {
+      if let Err(e) = server.await {
+        tracing::error!(error = %e, "admin server error");
+      }
+    });
+  }
+
+  if !opt.prewarm.is_empty() {
+    tokio::spawn(prewarm_apps(opt.prewarm.clone()));
   }
 
   let make_svc = make_service_fn(|_| async move { Ok::<_, hyper::Error>(service_fn(handle)) });
@@ -562,6 +717,93 @@ async fn shutdown_signal() {
   }
 }
 
+async fn handle_metrics(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+  if req.uri().path() != "/metrics" {
+    let mut res = Response::new(Body::from("not found"));
+    *res.status_mut() = StatusCode::NOT_FOUND;
+    return Ok(res);
+  }
+  let mut res = Response::new(Body::from(METRICS.render()));
+  res.headers_mut().insert(
+    "content-type",
+    HeaderValue::from_static("text/plain; version=0.0.4"),
+  );
+  Ok(res)
+}
+
+/// Diagnostics of a worker of the app at `app=<metadata path>`, spawning one if there's none:
+///
+/// - `GET /heap_snapshot` returns a `.heapsnapshot`.
+/// - `GET /cpu_profile` samples for `duration_ms` (default 5000, at most 30000) every
+///   `sampling_interval_us` (default 1000, at least 100) and returns a `.cpuprofile`.
+async fn handle_admin(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+  let token = req
+    .headers()
+    .get(hyper::header::AUTHORIZATION)
+    .and_then(|x| x.to_str().ok())
+    .and_then(|x| x.strip_prefix("Bearer "))
+    .unwrap_or_default();
+  if ring::constant_time::verify_slices_are_equal(
+    token.as_bytes(),
+    ADMIN_TOKEN.get().unwrap().as_bytes(),
+  )
+  .is_err()
+  {
+    let mut res = Response::new(Body::from("unauthorized"));
+    *res.status_mut() = StatusCode::UNAUTHORIZED;
+    return Ok(res);
+  }
+
+  let query = url::form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+    .into_owned()
+    .collect::<HashMap<String, String>>();
+  let v = match req.uri().path() {
+    "/heap_snapshot" => BlueboatIpcReqV::HeapSnapshot,
+    "/cpu_profile" => BlueboatIpcReqV::CpuProfile {
+      duration_ms: query
+        .get("duration_ms")
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(5000),
+      sampling_interval_us: query
+        .get("sampling_interval_us")
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(1000),
+    },
+    _ => {
+      let mut res = Response::new(Body::from("not found"));
+      *res.status_mut() = StatusCode::NOT_FOUND;
+      return Ok(res);
+    }
+  };
+  let app = match query.get("app") {
+    Some(x) => x.clone(),
+    None => {
+      let mut res = Response::new(Body::from("missing app"));
+      *res.status_mut() = StatusCode::BAD_REQUEST;
+      return Ok(res);
+    }
+  };
+  let res = async {
+    let md = load_md_with_cache(&app).await?;
+    let req = BlueboatIpcReq {
+      v,
+      id: format!("admin:{}", Uuid::new_v4()),
+    };
+    let res = generic_invoke(req, md, None).await?;
+    res.response.into_hyper(res.body)
+  }
+  .await;
+  match res {
+    Ok(x) => Ok(x),
+    Err(e) => {
+      tracing::error!(app = %app, error = %e, "admin request failed");
+      let mut res = Response::new(Body::from(e.to_string()));
+      *res.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+      Ok(res)
+    }
+  }
+}
+
 async fn handle(mut req: Request<Body>) -> Result<Response<Body>, Infallible> {
   if req.uri().path() == "/_blueboat/health" {
     return Ok(Response::new(Body::from("OK")));
@@ -743,21 +985,67 @@ pub async fn generic_invoke(
     path: md.path.to_string(),
     version: md.version.clone(),
   };
-  let pk2 = pk.clone();
-  let w = Scheduler::get_worker(global_scheduler(), &pk, move || {
+  let w = Scheduler::get_worker(global_scheduler(), &pk, worker_init(md, pk.clone())).await?;
+
+  let res = w.invoke(req).await?;
+
+  Ok(res)
+}
+
+fn worker_init(
+  md: Arc<Metadata>,
+  pk: PackageKey,
+) -> impl Fn() -> BlueboatInitData + Send + Sync + 'static {
+  move || {
     let rch = create_reliable_channel(md.clone());
     BlueboatInitData {
-      key: pk2.clone(),
+      key: pk.clone(),
       metadata: (*md).clone(),
-      lp_tx: LP_TX.get().unwrap().lock().clone(),
+      lp_tx: lp_tx(),
       rch: Some(rch),
     }
-  })
-  .await?;
+  }
+}
 
-  let res = w.invoke(req).await?;
+/// Keeps the worker pools of prewarmed apps filled. The scheduler has no hook for worker exit or
+/// eviction, so pools are checked periodically and workers that are gone are spawned again.
+async fn prewarm_apps(specs: Vec<PrewarmSpec>) {
+  let mut warm = vec![false; specs.len()];
+  loop {
+    for (spec, warm) in specs.iter().zip(warm.iter_mut()) {
+      let start = Instant::now();
+      match prewarm_app(spec).await {
+        Ok(()) => {
+          if !*warm {
+            tracing::info!(
+              app = %spec.path,
+              workers = spec.workers,
+              duration = ?start.elapsed(),
+              "prewarmed workers"
+            );
+          }
+          *warm = true;
+        }
+        Err(e) => tracing::error!(app = %spec.path, error = %e, "failed to prewarm workers"),
+      }
+    }
+    tokio::time::sleep(Duration::from_secs(PREWARM_REFILL_INTERVAL_SECS)).await;
+  }
+}
 
-  Ok(res)
+async fn prewarm_app(spec: &PrewarmSpec) -> Result<()> {
+  let md = load_md_with_cache(&spec.path).await?;
+  let pk = PackageKey {
+    path: md.path.to_string(),
+    version: md.version.clone(),
+  };
+  // Ask for the workers concurrently, like as many simultaneous requests would.
+  futures::future::try_join_all(
+    (0..spec.workers)
+      .map(|_| Scheduler::get_worker(global_scheduler(), &pk, worker_init(md.clone(), pk.clone()))),
+  )
+  .await?;
+  Ok(())
 }
 
 async fn raw_handle(mut req: Request<Body>, md_path: &String) -> Result<Response<Body>> {
@@ -785,7 +1073,19 @@ async fn raw_handle(mut req: Request<Body>, md_path: &String) -> Result<Response
       .await
       .map_err(|e| e.context("sse"));
   }
-  let request = BlueboatRequest::from_hyper(req).await?;
+  let body_limit = md
+    .max_request_body_size
+    .unwrap_or(u64::MAX)
+    .min(MAX_REQUEST_BODY_SIZE.get().unwrap().unwrap_or(u64::MAX));
+  let request = match BlueboatRequest::from_hyper(req, body_limit).await {
+    Ok(x) => x,
+    Err(e) if e.is::<RequestBodyTooLarge>() => {
+      let mut res = Response::new(Body::from("request body too large"));
+      *res.status_mut() = StatusCode::PAYLOAD_TOO_LARGE;
+      return Ok(res);
+    }
+    Err(e) => return Err(e),
+  };
   let request = BlueboatIpcReq {
     v: BlueboatIpcReqV::Http(request),
     id: request_id.clone(),
@@ -826,6 +1126,7 @@ fn spawn_lp_handler(ctx: Arc<LpContext>, rx: smr::ipc_channel::ipc::IpcReceiver<
 fn issue_lp(ctx: &Arc<LpContext>, msg: LowPriorityMsg) {
   match msg {
     LowPriorityMsg::Log(msg) => {
+      APPLOG_TAIL.push(&msg);
       if let Some(producer) = &ctx.log_kafka {
         producer.write_applog(msg);
       }
@@ -833,7 +1134,7 @@ fn issue_lp(ctx: &Arc<LpContext>, msg: LowPriorityMsg) {
     LowPriorityMsg::Background(entry) => match ctx.bg_permit.clone().try_acquire_owned() {
       Ok(permit) => {
         tokio::spawn(async move {
-          run_background_entry(entry).await;
+          let _ = run_background_entry(entry).await;
           drop(permit);
         });
       }
@@ -841,25 +1142,169 @@ fn issue_lp(ctx: &Arc<LpContext>, msg: LowPriorityMsg) {
         LP_BG_ISSUE_FAIL_COUNT.fetch_add(1, Ordering::Relaxed);
       }
     },
+    LowPriorityMsg::Metric(entry) => {
+      METRICS.record(&entry.app.path, &entry.name, entry.labels, entry.op);
+    }
+  }
+}
+
+/// Most entries claimed from the background store at once.
+const BG_CLAIM_BATCH: usize = 64;
+
+/// How long a claimed entry stays invisible to other instances. Longer than a run, so that an
+/// entry is only claimed again if its runner died.
+const BG_CLAIM_LEASE: Duration = Duration::from_secs(300);
+
+const BG_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Runs at-least-once entries from the background store. Entries are claimed only when a permit
+/// is free for each of them, so that waiting entries stay in the store instead of in memory.
+async fn run_background_queue(store: Arc<BackgroundStore>, bg_permit: Arc<Semaphore>) {
+  loop {
+    let mut permits = vec![];
+    while permits.len() < BG_CLAIM_BATCH {
+      match bg_permit.clone().try_acquire_owned() {
+        Ok(x) => permits.push(x),
+        Err(_) => break,
+      }
+    }
+    if permits.is_empty() {
+      tokio::time::sleep(BG_POLL_INTERVAL).await;
+      continue;
+    }
+    let claimed = match store.claim(permits.len(), BG_CLAIM_LEASE).await {
+      Ok(x) => x,
+      Err(e) => {
+        tracing::error!(error = %e, "failed to claim background tasks");
+        tokio::time::sleep(BG_POLL_INTERVAL).await;
+        continue;
+      }
+    };
+    let idle = claimed.len() < permits.len();
+    for (claimed, permit) in claimed.into_iter().zip(permits.drain(..)) {
+      let store = store.clone();
+      tokio::spawn(async move {
+        run_claimed_entry(&store, claimed).await;
+        drop(permit);
+      });
+    }
+    drop(permits);
+    if idle {
+      store.wait(BG_POLL_INTERVAL).await;
+    }
+  }
+}
+
+async fn run_claimed_entry(store: &BackgroundStore, claimed: ClaimedEntry) {
+  let error = match run_background_entry(claimed.entry.clone()).await {
+    Ok(()) => None,
+    Err(e) => Some(e),
+  };
+  let res = match error {
+    None => store.complete(&claimed).await,
+    // Delayed tasks have no retry policy, so a failed run is not retried.
+    Some(error) if claimed.entry.retry.is_none() => {
+      tracing::error!(package_path = %claimed.entry.app.path, error = %error, "delayed background task failed");
+      store.complete(&claimed).await
+    }
+    Some(error) => {
+      let mut entry = claimed.entry.clone();
+      match next_retry(&mut entry, rand::random()) {
+        Some(delay) => store.retry_later(&claimed, &entry, delay).await,
+        None => {
+          tracing::error!(package_path = %entry.app.path, attempts = entry.attempts, error = %error, "background task dead-lettered");
+          store
+            .dead_letter(&claimed, &entry, &error.to_string())
+            .await
+        }
+      }
+    }
+  };
+  if let Err(e) = res {
+    tracing::error!(package_path = %claimed.entry.app.path, error = %e, "failed to update background store");
+  }
+}
+
+/// How often cron jobs are checked for a passed fire time. Jobs run up to this late.
+const CRON_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Runs cron jobs from the background store. Each fire time is claimed in a transaction, so that
+/// a job runs on one of the instances that accept background tasks.
+async fn run_cron_scheduler(store: Arc<BackgroundStore>, bg_permit: Arc<Semaphore>) {
+  loop {
+    tokio::time::sleep(CRON_POLL_INTERVAL).await;
+    let jobs = match store.cron_jobs().await {
+      Ok(x) => x,
+      Err(e) => {
+        tracing::error!(error = %e, "failed to list cron jobs");
+        continue;
+      }
+    };
+    let now = chrono::Utc::now().timestamp_millis();
+    for mut job in jobs {
+      // Checked locally first, so that only due jobs are claimed.
+      if matches!(job.tick(now, 0), Ok(CronTick::NotDue) | Err(_)) {
+        continue;
+      }
+      let app = &job.entry.app;
+      let job = match store
+        .fire_cron(&app.path, &job.entry.name, BG_CLAIM_LEASE)
+        .await
+      {
+        Ok(Some(x)) => x,
+        Ok(None) => continue,
+        Err(e) => {
+          tracing::error!(package_path = %app.path, cron = %job.entry.name, error = %e, "failed to fire cron job");
+          continue;
+        }
+      };
+      let store = store.clone();
+      let bg_permit = bg_permit.clone();
+      tokio::spawn(async move {
+        if let Ok(_permit) = bg_permit.acquire_owned().await {
+          let _ = run_background_entry(BackgroundEntry {
+            app: job.entry.app.clone(),
+            request_id: job.entry.request_id.clone(),
+            wire_bytes: job.entry.wire_bytes.clone(),
+            same_version: job.entry.same_version,
+            retry: None,
+            attempts: 0,
+            dedup: None,
+          })
+          .await;
+        }
+        if let Err(e) = store.end_cron_run(&job).await {
+          tracing::error!(package_path = %job.entry.app.path, cron = %job.entry.name, error = %e, "failed to end cron run");
+        }
+      });
+    }
   }
 }
 
 static BACKGROUND_TASK_LOCK: RwLock<()> = RwLock::const_new(());
 
-async fn run_background_entry(entry: BackgroundEntry) {
+async fn run_background_entry(entry: BackgroundEntry) -> Result<()> {
   let _entry_g = BACKGROUND_TASK_LOCK.read().await;
   let request_id = format!(
     "{}+bg-{}",
     entry.request_id.split("+").next().unwrap(),
     Uuid::new_v4().to_string()
   );
-  let task_span = tracing::info_span!("background task", request_id = %request_id, package_path = %entry.app.path, package_version = %entry.app.version);
+  let task_span = tracing::info_span!("background task", request_id = %request_id, package_path = %entry.app.path, package_version = %entry.app.version, attempts = entry.attempts);
   do_run_background_entry(entry, request_id)
     .instrument(task_span)
-    .await;
+    .await
 }
 
-async fn do_run_background_entry(entry: BackgroundEntry, request_id: String) {
+async fn do_run_background_entry(entry: BackgroundEntry, request_id: String) -> Result<()> {
+  #[derive(Error, Debug)]
+  #[error("background task failed with status {0}")]
+  struct BackgroundTaskFailed(u16);
+
+  #[derive(Error, Debug)]
+  #[error("background task timed out")]
+  struct BackgroundTaskTimeout;
+
   tracing::info!("run background task");
 
   let req = BlueboatIpcReq {
@@ -871,7 +1316,7 @@ async fn do_run_background_entry(entry: BackgroundEntry, request_id: String) {
     Ok(x) => x,
     Err(e) => {
       tracing::error!(reason = "load_md", error = %e, "background task failed");
-      return;
+      return Err(e);
     }
   };
   let fut = async {
@@ -886,19 +1331,30 @@ async fn do_run_background_entry(entry: BackgroundEntry, request_id: String) {
     )
     .await
     {
-      Ok(_) => {}
+      Ok(x) if x.response.status >= 500 => {
+        tracing::error!(
+          reason = "status",
+          status = x.response.status,
+          "background task failed"
+        );
+        Err(BackgroundTaskFailed(x.response.status).into())
+      }
+      Ok(_) => Ok(()),
       Err(e) => {
         tracing::error!(reason = "invoke", error = %e, "background task failed");
+        Err(e)
       }
     }
   };
   tokio::select! {
-    _ = fut => {},
+    res = fut => res,
     _ = tokio::time::sleep(Duration::from_secs(30)) => {
-      tracing::error!(reason = "timeout", "background task failed")
+      tracing::error!(reason = "timeout", "background task failed");
+      Err(BackgroundTaskTimeout.into())
     }
   }
 }
+
 async fn print_status() {
   log::warn!("Requested to print system status.");
   eprintln!(
@@ -911,3 +1367,24 @@ async fn print_status() {
   );
   eprintln!("End of system status.");
 }
+
+#[cfg(test)]
+mod tests {
+  use super::PrewarmSpec;
+
+  fn parse(s: &str) -> Option<(String, usize)> {
+    s.parse::<PrewarmSpec>().ok().map(|x| (x.path, x.workers))
+  }
+
+  #[test]
+  fn prewarm_spec_parsing() {
+    assert_eq!(parse("apps/a.json=3"), Some(("apps/a.json".into(), 3)));
+    assert_eq!(parse("apps/a.json"), Some(("apps/a.json".into(), 1)));
+    assert_eq!(parse("apps/a.json=0"), None);
+    assert_eq!(parse("=2"), None);
+    assert_eq!(parse("apps/a.json=abc"), None);
+
+    // Only the last `=` separates the worker count.
+    assert_eq!(parse("apps/a=b.json=2"), Some(("apps/a=b.json".into(), 2)));
+  }
+}
@@ -10,6 +10,7 @@ use crate::{
   api::util::{
     v8_deref_arraybuffer_assuming_noalias, v8_deref_typed_array_assuming_noalias, TypedArrayView,
   },
+  api::wasm::WASM_MAX_MEMORY_PAGES,
   ctx::BlueboatInitData,
 };
 
@@ -213,11 +214,84 @@ impl IsolateInitDataExt for v8::Isolate {
   }
 }
 
+/// Formats a thrown value for the app's deployer: the stack of an `Error`, or otherwise the value
+/// and where it was thrown.
+pub fn describe_exception<'s>(
+  scope: &mut v8::HandleScope<'s>,
+  exc: v8::Local<'s, v8::Value>,
+) -> String {
+  if let Ok(obj) = v8::Local::<v8::Object>::try_from(exc) {
+    let stack = obj.get_ext(scope, "stack");
+    if stack.is_string() {
+      return stack.to_rust_string_lossy(scope);
+    }
+  }
+  let value = exc.to_rust_string_lossy(scope);
+  let msg = v8::Exception::create_message(scope, exc);
+  let resource = msg
+    .get_script_resource_name(scope)
+    .map(|x| x.to_rust_string_lossy(scope));
+  match (resource, msg.get_line_number(scope)) {
+    (Some(resource), Some(line)) => format!("Uncaught {}\n    at {}:{}", value, resource, line),
+    _ => format!("Uncaught {}", value),
+  }
+}
+
 pub fn set_up_v8_globally() {
-  let flags = concat!(" --turbo-fast-api-calls", " --single-threaded",);
-  v8::V8::set_flags_from_string(flags);
+  // The wasm memory cap applies to every memory V8 creates, not only to the ones declared in
+  // modules that went through `Wasm`.
+  let flags = format!(
+    " --turbo-fast-api-calls --single-threaded --wasm-max-mem-pages={}",
+    WASM_MAX_MEMORY_PAGES
+  );
+  v8::V8::set_flags_from_string(&flags);
 
   let platform = v8::new_single_threaded_default_platform(false).make_shared();
   v8::V8::initialize_platform(platform);
   v8::V8::initialize();
 }
+
+#[cfg(test)]
+mod tests {
+  use super::describe_exception;
+  use crate::api::testutil::ApiTester;
+
+  fn describe(source: &str) -> String {
+    let mut tester = ApiTester::new();
+    tester.run(|scope| {
+      let scope = &mut v8::TryCatch::new(scope);
+      let source = v8::String::new(scope, source).unwrap();
+      let name = v8::String::new(scope, "index.js").unwrap();
+      let undef = v8::undefined(scope);
+      let origin = v8::ScriptOrigin::new(
+        scope,
+        name.into(),
+        0,
+        0,
+        false,
+        0,
+        undef.into(),
+        false,
+        false,
+        false,
+      );
+      let script = v8::Script::compile(scope, source, Some(&origin)).unwrap();
+      assert!(script.run(scope).is_none());
+      let exc = scope.exception().unwrap();
+      describe_exception(scope, exc)
+    })
+  }
+
+  #[test]
+  fn describes_thrown_values() {
+    let out = describe("function init() { throw new Error(\"bad config\"); }\ninit();");
+    assert!(
+      out.starts_with("Error: bad config\n    at init (index.js:1:"),
+      "{}",
+      out
+    );
+
+    let out = describe("\nthrow \"plain string\";");
+    assert_eq!(out, "Uncaught plain string\n    at index.js:2");
+  }
+}